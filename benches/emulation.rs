@@ -0,0 +1,126 @@
+//! Throughput of the emulation on a few workloads, run with `cargo bench`
+//!
+//! Each workload is a small NROM program, timed a frame at a time.
+//! The throughput is in CPU instructions per second, so changes to the dispatch
+//! or the buses can be compared between runs. Pass a workload's name to only run that one.
+//! Stepping by instructions runs every cycle on its own, [`Nes::run_frame`] is also measured
+//! for both [`Accuracy`] settings.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nesty::{
+    cartridge::Cartridge,
+    nes::{Accuracy, Nes},
+};
+
+struct Workload {
+    name: &'static str,
+    program: &'static [u8],
+}
+
+#[rustfmt::skip]
+const WORKLOADS: [Workload; 4] = [
+    Workload {
+        name: "ram_loop",
+        // LDX #0, loop: INC $0200,X, LDA $00,X, ADC $01, STA $01, INX, BNE loop, JMP loop
+        program: &[
+            0xA2, 0x00,
+            0xFE, 0x00, 0x02, 0xB5, 0x00, 0x65, 0x01, 0x85, 0x01, 0xE8, 0xD0, 0xF4,
+            0x4C, 0x02, 0x80,
+        ],
+    },
+    Workload {
+        name: "ram_copy",
+        // only RAM accesses, through the mirrors too
+        // LDX #0, loop: LDA $0300,X, STA $0B00,X, LDA $1400,X, STA $0600,X, INX, BNE loop, JMP loop
+        program: &[
+            0xA2, 0x00,
+            0xBD, 0x00, 0x03, 0x9D, 0x00, 0x0B, 0xBD, 0x00, 0x14, 0x9D, 0x00, 0x06, 0xE8,
+            0xD0, 0xF1, 0x4C, 0x02, 0x80,
+        ],
+    },
+    Workload {
+        name: "rom_reads",
+        // LDY #0, loop: LDA $9000,Y, EOR ($10),Y, STA $00, INY, BNE loop, INC $11, JMP loop
+        program: &[
+            0xA0, 0x00,
+            0xB9, 0x00, 0x90, 0x51, 0x10, 0x85, 0x00, 0xC8, 0xD0, 0xF6, 0xE6, 0x11,
+            0x4C, 0x02, 0x80,
+        ],
+    },
+    Workload {
+        name: "rendering",
+        // turn on the background and sprites, then spin
+        // LDA #$1E, STA $2001, LDA #$80, STA $2000, loop: JMP loop
+        program: &[
+            0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x80, 0x8D, 0x00, 0x20,
+            0x4C, 0x0A, 0x80,
+        ],
+    },
+];
+
+/// Console running `program` from $8000, with $F0 bytes in the rest of the ROM and in CHR ROM
+fn nes(program: &[u8]) -> Nes {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0];
+    image.resize(16, 0);
+    let mut prg = vec![0xF0; 0x8000];
+    prg[..program.len()].copy_from_slice(program);
+    // NMI, reset and IRQ vectors, the NMI handler is an RTI right before them
+    prg[0x7FF9] = 0x40;
+    prg[0x7FFA..].copy_from_slice(&[0xF9, 0xFF, 0x00, 0x80, 0xF9, 0xFF]);
+    image.extend(prg);
+    image.resize(16 + 0x8000 + 0x2000, 0xF0);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    nes
+}
+
+/// Runs the console until the next frame starts, returns how many instructions that took
+fn run_frame(nes: &mut Nes) -> u64 {
+    let frame = nes.ppu().frame();
+    let mut instructions = 0;
+    while nes.ppu().frame() == frame {
+        nes.step_instruction();
+        instructions += 1;
+    }
+    instructions
+}
+
+fn emulation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("emulation");
+    for workload in &WORKLOADS {
+        let mut nes = nes(workload.program);
+        // the programs loop, so every frame runs about as many instructions as the first one
+        group.throughput(Throughput::Elements(run_frame(&mut nes)));
+        group.bench_function(workload.name, |b| {
+            b.iter(|| {
+                run_frame(&mut nes);
+                black_box(nes.ppu().framebuffer());
+            })
+        });
+    }
+    group.finish();
+}
+
+fn run_frame_accuracy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run_frame");
+    for workload in &WORKLOADS {
+        for accuracy in [Accuracy::Cycle, Accuracy::Instruction] {
+            let mut nes = nes(workload.program);
+            nes.set_accuracy(accuracy);
+            group.throughput(Throughput::Elements(1));
+            group.bench_function(format!("{}/{accuracy:?}", workload.name), |b| {
+                b.iter(|| {
+                    black_box(nes.run_frame().video);
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, emulation, run_frame_accuracy);
+criterion_main!(benches);
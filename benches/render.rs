@@ -0,0 +1,68 @@
+//! Time it takes to render a full frame, run with `cargo bench --bench render`
+//!
+//! Runs a program that keeps the background and sprites on, and measures
+//! the emulation of a frame with and without drawing it, and its conversion to RGBA.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use nesty::{
+    cartridge::Cartridge,
+    nes::{Frame, Nes},
+};
+
+/// Turns on the background and sprites, then spins
+/// LDA #$1E, STA $2001, LDA #$80, STA $2000, loop: JMP loop
+#[rustfmt::skip]
+const PROGRAM: [u8; 13] = [
+    0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x80, 0x8D, 0x00, 0x20,
+    0x4C, 0x0A, 0x80,
+];
+
+/// Console running the program, with CHR ROM full of patterns so every pixel has something to draw
+fn nes() -> Nes {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0];
+    image.resize(16, 0);
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    // NMI, reset and IRQ vectors, the NMI handler is an RTI right before them
+    prg[0x7FF9] = 0x40;
+    prg[0x7FFA..].copy_from_slice(&[0xF9, 0xFF, 0x00, 0x80, 0xF9, 0xFF]);
+    image.extend(prg);
+    image.extend((0..0x2000).map(|i| (i * 37) as u8));
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    nes
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    let mut nes = nes();
+    group.bench_function("drawn", |b| {
+        b.iter(|| {
+            black_box(nes.run_frame().video);
+        })
+    });
+    nes.set_frame_skip(u32::MAX);
+    group.bench_function("skipped", |b| {
+        b.iter(|| {
+            black_box(nes.run_frame().video);
+        })
+    });
+
+    nes.set_frame_skip(0);
+    let mut rgba = vec![0; Frame::WIDTH * Frame::HEIGHT * 4];
+    let frame = nes.run_frame();
+    group.bench_function("rgba", |b| {
+        b.iter(|| {
+            frame.write_rgba(&mut rgba);
+            black_box(&rgba);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
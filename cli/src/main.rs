@@ -0,0 +1,329 @@
+//! Runs a ROM without a window until a condition, and reports how it went in the exit code
+//!
+//! ```text
+//! nesty-cli --blargg instr_test-v5/rom_singles/01-basics.nes
+//! nesty-cli --frames 120 --print-hash game.nes
+//! nesty-cli --pc C66E --frames 600 nestest.nes
+//! ```
+//!
+//! The picture is hashed with the CRC32 of its RGB pixels, like the golden frames of the tests.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use nesty::{
+    cartridge::{self, database::crc32, patch, LoadError},
+    debug::{breakpoints::Breakpoint, StopReason},
+    nes::{Frame, Nes},
+    png, zip,
+};
+
+#[cfg(test)]
+mod tests;
+
+const USAGE: &str = "\
+Usage: nesty-cli [OPTIONS] ROM
+
+Runs an iNES, UNIF or NSF file, or a ZIP archive with one, without a window
+until one of the conditions holds, or for the number of frames if there are none.
+
+Options:
+  --frames N         Most frames to run [default: 3600]
+  --blargg           Wait for the result of a blargg test ROM and print its text
+  --pc ADDRESS       Wait for the CPU to reach ADDRESS, in hex
+  --hash CRC         Wait for a frame with the picture with the CRC32, in hex
+  --print-hash       Print the CRC32 of the picture of the last frame
+  --screenshot FILE  Save the picture of the last frame, as PNG or as PPM if FILE ends in .ppm
+  --patch FILE       Apply an IPS or BPS patch to the ROM
+  --soft-patch       Apply the .ips or .bps patch with the name of the ROM next to it, if any
+  -h, --help         Print this
+
+Exit codes:
+  0  a condition held, or the frames ran without any
+  1  the blargg test ROM failed
+  2  no condition held within the frames
+  3  the arguments or the ROM are invalid";
+
+const DEFAULT_FRAMES: u64 = 60 * 60;
+
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+const BLARGG_NEEDS_RESET: u8 = 0x81;
+/// Frames to wait before pressing reset, the ROMs ask for at least 100ms
+const BLARGG_RESET_DELAY: u32 = 10;
+
+/// What the command line asked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Options {
+    rom: PathBuf,
+    frames: u64,
+    blargg: bool,
+    pc: Option<u16>,
+    hash: Option<u32>,
+    print_hash: bool,
+    screenshot: Option<PathBuf>,
+    patch: Option<PathBuf>,
+    soft_patch: bool,
+}
+
+/// How a run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    /// All the frames ran and there was no condition
+    Finished,
+    /// The blargg ROM reported its result, 0 means it passed
+    Blargg {
+        code: u8,
+        text: String,
+    },
+    ReachedPc,
+    MatchedHash,
+    TimedOut,
+}
+
+impl Outcome {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Outcome::Blargg { code, .. } if *code != 0 => 1,
+            Outcome::TimedOut => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// A run, with the picture of the last frame
+#[derive(Debug)]
+struct Report {
+    outcome: Outcome,
+    frames: u64,
+    rgb: Vec<u8>,
+}
+
+fn parse_hex<T>(
+    value: &str,
+    parse: fn(&str, u32) -> Result<T, std::num::ParseIntError>,
+) -> Option<T> {
+    let digits = value.strip_prefix('$').unwrap_or(value);
+    let digits = digits.strip_prefix("0x").unwrap_or(digits);
+    parse(digits, 16).ok()
+}
+
+/// `None` when the usage should be printed
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut args = args.into_iter();
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: DEFAULT_FRAMES,
+        blargg: false,
+        pc: None,
+        hash: None,
+        print_hash: false,
+        screenshot: None,
+        patch: None,
+        soft_patch: false,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{name} needs a value"));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--frames" => {
+                let frames = value("--frames")?;
+                options.frames = frames
+                    .parse()
+                    .map_err(|_| format!("invalid number of frames `{frames}`"))?;
+            }
+            "--blargg" => options.blargg = true,
+            "--pc" => {
+                let pc = value("--pc")?;
+                let pc = parse_hex(&pc, u16::from_str_radix)
+                    .ok_or_else(|| format!("invalid address `{pc}`"))?;
+                options.pc = Some(pc);
+            }
+            "--hash" => {
+                let hash = value("--hash")?;
+                let hash = parse_hex(&hash, u32::from_str_radix)
+                    .ok_or_else(|| format!("invalid CRC32 `{hash}`"))?;
+                options.hash = Some(hash);
+            }
+            "--print-hash" => options.print_hash = true,
+            "--screenshot" => options.screenshot = Some(value("--screenshot")?.into()),
+            "--patch" => options.patch = Some(value("--patch")?.into()),
+            "--soft-patch" => options.soft_patch = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    options.rom = rom.ok_or("missing the ROM")?;
+    Ok(Some(options))
+}
+
+/// The zero terminated text a blargg ROM printed at $6004
+fn blargg_text(nes: &Nes) -> String {
+    (0x6004..0x8000)
+        .map(|address| nes.peek(address))
+        .take_while(|&byte| byte != 0)
+        .map(char::from)
+        .collect()
+}
+
+/// Run the console until a condition of `options` holds or the frames ran out
+fn run(rom: &[u8], options: &Options) -> Result<Report, LoadError> {
+    let mut nes = Nes::builder().load(rom)?;
+    if let Some(pc) = options.pc {
+        nes.breakpoints_mut().add(Breakpoint::new(pc));
+    }
+    let has_condition = options.blargg || options.pc.is_some() || options.hash.is_some();
+
+    let mut reset_in = None;
+    let mut rgb = Vec::new();
+    for frames in 1..=options.frames {
+        let frame = nes.run_frame();
+        let reached_pc = matches!(frame.stop, Some(StopReason::Breakpoint(_)));
+        rgb = frame.to_rgb();
+        let report = |outcome| Report {
+            outcome,
+            frames,
+            rgb: rgb.clone(),
+        };
+        if reached_pc {
+            return Ok(report(Outcome::ReachedPc));
+        }
+        if options.hash == Some(crc32(&rgb)) {
+            return Ok(report(Outcome::MatchedHash));
+        }
+        let signature = [0x6001, 0x6002, 0x6003].map(|address| nes.peek(address));
+        if !options.blargg || signature != BLARGG_SIGNATURE {
+            continue;
+        }
+        match (nes.peek(0x6000), reset_in) {
+            (BLARGG_RUNNING, _) => {}
+            (BLARGG_NEEDS_RESET, None) => reset_in = Some(BLARGG_RESET_DELAY),
+            (BLARGG_NEEDS_RESET, Some(0)) => {
+                nes.reset();
+                reset_in = None;
+            }
+            (BLARGG_NEEDS_RESET, Some(delay)) => reset_in = Some(delay - 1),
+            (code, _) => {
+                let text = blargg_text(&nes);
+                return Ok(report(Outcome::Blargg { code, text }));
+            }
+        }
+    }
+    let outcome = match has_condition {
+        true => Outcome::TimedOut,
+        false => Outcome::Finished,
+    };
+    Ok(Report {
+        outcome,
+        frames: options.frames,
+        rgb,
+    })
+}
+
+/// What's printed about a run
+fn describe(report: &Report, options: &Options) -> String {
+    let frames = report.frames;
+    let mut text = match &report.outcome {
+        Outcome::Finished => format!("ran {frames} frames\n"),
+        Outcome::Blargg { code: 0, text } => format!("frame {frames}: passed\n{text}"),
+        Outcome::Blargg { code, text } => {
+            format!("frame {frames}: failed with code {code}\n{text}")
+        }
+        Outcome::ReachedPc => format!("frame {frames}: reached ${:04X}\n", options.pc.unwrap_or(0)),
+        Outcome::MatchedHash => format!("frame {frames}: the picture matches\n"),
+        Outcome::TimedOut => format!("no condition held after {frames} frames\n"),
+    };
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    if options.print_hash {
+        let _ = writeln!(text, "hash {:08X}", crc32(&report.rgb));
+    }
+    text
+}
+
+/// Save an RGB picture as a PNG image, or a binary PPM image if the extension asks for it
+fn save_screenshot(path: &Path, rgb: &[u8]) -> std::io::Result<()> {
+    let image = match path.extension().is_some_and(|extension| extension == "ppm") {
+        true => {
+            let mut image = format!("P6\n{} {}\n255\n", Frame::WIDTH, Frame::HEIGHT).into_bytes();
+            image.extend_from_slice(rgb);
+            image
+        }
+        false => png::encode_rgb(Frame::WIDTH, Frame::HEIGHT, rgb),
+    };
+    std::fs::write(path, image)
+}
+
+fn apply_patch(rom: &[u8], path: &Path) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let patch = std::fs::read(path)?;
+    Ok(patch::apply(rom, &patch)?)
+}
+
+fn main() -> ExitCode {
+    const ERROR: u8 = 3;
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            return ExitCode::from(ERROR);
+        }
+    };
+    let rom = match std::fs::read(&options.rom) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("couldn't read {}: {error}", options.rom.display());
+            return ExitCode::from(ERROR);
+        }
+    };
+    // patches are made for the ROM, not for the archive
+    let rom = match zip::is_zip(&rom) {
+        true => match cartridge::unzip_rom(&rom) {
+            Ok(rom) => rom,
+            Err(error) => {
+                eprintln!("couldn't load {}: {error}", options.rom.display());
+                return ExitCode::from(ERROR);
+            }
+        },
+        false => rom,
+    };
+    let patch = match &options.patch {
+        Some(path) => Some(path.clone()),
+        None if options.soft_patch => patch::find_soft_patch(&options.rom),
+        None => None,
+    };
+    let rom = match &patch {
+        None => rom,
+        Some(path) => match apply_patch(&rom, path) {
+            Ok(patched) => patched,
+            Err(error) => {
+                eprintln!("couldn't apply {}: {error}", path.display());
+                return ExitCode::from(ERROR);
+            }
+        },
+    };
+    let report = match run(&rom, &options) {
+        Ok(report) => report,
+        Err(error) => {
+            eprintln!("couldn't load {}: {error}", options.rom.display());
+            return ExitCode::from(ERROR);
+        }
+    };
+    print!("{}", describe(&report, &options));
+    if let Some(path) = &options.screenshot {
+        if let Err(error) = save_screenshot(path, &report.rgb) {
+            eprintln!("couldn't save {}: {error}", path.display());
+            return ExitCode::from(ERROR);
+        }
+    }
+    ExitCode::from(report.outcome.exit_code())
+}
@@ -0,0 +1,122 @@
+use super::*;
+
+/// NROM with battery backed RAM that reports `code` like a blargg ROM, with the text "ok"
+fn blargg_rom(code: u8) -> Vec<u8> {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0xDE, 0x8D, 0x01, 0x60, 0xA9, 0xB0, 0x8D, 0x02, 0x60, 0xA9, 0x61, 0x8D, 0x03, 0x60,
+        0xA9, b'o', 0x8D, 0x04, 0x60, 0xA9, b'k', 0x8D, 0x05, 0x60, 0xA9, 0x00, 0x8D, 0x06, 0x60,
+        0xA9, code, 0x8D, 0x00, 0x60,
+        // $8023: JMP $8023
+        0x4C, 0x23, 0x80,
+    ];
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0b10];
+    image.resize(16, 0);
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..program.len()].copy_from_slice(&program);
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    image.extend(prg);
+    image.extend([0; 0x2000]);
+    image
+}
+
+fn options(args: &[&str]) -> Options {
+    parse_args(args.iter().map(|arg| arg.to_string()))
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn arguments() {
+    assert_eq!(
+        options(&[
+            "--frames",
+            "10",
+            "--pc",
+            "$C000",
+            "--hash",
+            "0xdeadBEEF",
+            "rom.nes"
+        ]),
+        Options {
+            rom: "rom.nes".into(),
+            frames: 10,
+            blargg: false,
+            pc: Some(0xC000),
+            hash: Some(0xDEAD_BEEF),
+            print_hash: false,
+            screenshot: None,
+            patch: None,
+            soft_patch: false,
+        }
+    );
+    let patched = options(&["--patch", "hack.bps", "--soft-patch", "rom.nes"]);
+    assert_eq!(patched.patch, Some("hack.bps".into()));
+    assert!(patched.soft_patch);
+    let parse = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+    assert_eq!(parse(&["--help"]), Ok(None));
+    assert!(parse(&[]).is_err());
+    assert!(parse(&["--pc", "nope", "rom.nes"]).is_err());
+    assert!(parse(&["--frames"]).is_err());
+    assert!(parse(&["--unknown", "rom.nes"]).is_err());
+    assert!(parse(&["a.nes", "b.nes"]).is_err());
+}
+
+#[test]
+fn blargg() {
+    let options = options(&["--blargg", "--frames", "10", "rom.nes"]);
+    let report = run(&blargg_rom(0), &options).unwrap();
+    assert_eq!(
+        report.outcome,
+        Outcome::Blargg {
+            code: 0,
+            text: "ok".to_string()
+        }
+    );
+    assert_eq!(report.outcome.exit_code(), 0);
+    assert_eq!(describe(&report, &options), "frame 1: passed\nok\n");
+
+    let report = run(&blargg_rom(3), &options).unwrap();
+    assert_eq!(report.outcome.exit_code(), 1);
+    assert_eq!(
+        describe(&report, &options),
+        "frame 1: failed with code 3\nok\n"
+    );
+
+    // still running
+    let report = run(&blargg_rom(0x80), &options).unwrap();
+    assert_eq!(report.outcome, Outcome::TimedOut);
+    assert_eq!(report.frames, 10);
+    assert_eq!(report.outcome.exit_code(), 2);
+}
+
+#[test]
+fn conditions() {
+    let rom = blargg_rom(0);
+    let report = run(&rom, &options(&["--pc", "8023", "rom.nes"])).unwrap();
+    assert_eq!((report.outcome, report.frames), (Outcome::ReachedPc, 1));
+    let report = run(
+        &rom,
+        &options(&["--pc", "9000", "--frames", "3", "rom.nes"]),
+    )
+    .unwrap();
+    assert_eq!(report.outcome, Outcome::TimedOut);
+
+    let options = options(&["--frames", "3", "--print-hash", "rom.nes"]);
+    let report = run(&rom, &options).unwrap();
+    assert_eq!((&report.outcome, report.frames), (&Outcome::Finished, 3));
+    assert_eq!(report.rgb.len(), Frame::WIDTH * Frame::HEIGHT * 3);
+    let hash = crc32(&report.rgb);
+    assert_eq!(
+        describe(&report, &options),
+        format!("ran 3 frames\nhash {hash:08X}\n")
+    );
+    let hash = format!("{hash:X}");
+    let report = run(&rom, &self::options(&["--hash", &hash, "rom.nes"])).unwrap();
+    assert_eq!(report.outcome, Outcome::MatchedHash);
+
+    assert_eq!(
+        run(&[0; 16], &options).unwrap_err(),
+        LoadError::InvalidHeader
+    );
+}
@@ -0,0 +1,406 @@
+//! A debugger built on the debugging tools of the crate, with egui
+//!
+//! Run it with `cargo run --release -- game.nes` in this directory.
+//! The game can be paused, stepped by instruction, over and out of subroutines, or by frame,
+//! and stops at the breakpoints added in the side panel. The panels show the registers,
+//! the call stack, the disassembly from the PC, the memory and the pattern tables.
+//! While the game runs, the controller is on the arrows, X, Z, Backspace and Enter.
+
+use std::path::Path;
+
+use eframe::egui::{self, Color32, ColorImage, Key, RichText, TextureHandle, TextureOptions, Ui};
+use nesty::{
+    cartridge::{Cartridge, LoadError},
+    cpu::StatusFlags,
+    debug::{
+        breakpoints::{Access, Breakpoint, Bus},
+        viewer::PATTERN_TABLE_SIZE,
+        StopReason,
+    },
+    input::ButtonState,
+    nes::{Frame, Nes},
+    ppu::palette,
+};
+
+/// Instructions shown from the PC on
+const DISASSEMBLY_LINES: usize = 24;
+/// Rows of 16 bytes in the memory view
+const MEMORY_ROWS: u16 = 16;
+const SCREEN_SCALE: f32 = 2.0;
+
+const KEYS: [(Key, ButtonState); 8] = [
+    (Key::X, ButtonState::A),
+    (Key::Z, ButtonState::B),
+    (Key::Backspace, ButtonState::SELECT),
+    (Key::Enter, ButtonState::START),
+    (Key::ArrowUp, ButtonState::UP),
+    (Key::ArrowDown, ButtonState::DOWN),
+    (Key::ArrowLeft, ButtonState::LEFT),
+    (Key::ArrowRight, ButtonState::RIGHT),
+];
+
+const FLAGS: [(StatusFlags, char); 7] = [
+    (StatusFlags::NEGATIVE, 'N'),
+    (StatusFlags::OVERFLOW, 'V'),
+    (StatusFlags::BREAK, 'B'),
+    (StatusFlags::DECIMAL, 'D'),
+    (StatusFlags::INTERRUPT_DISABLE, 'I'),
+    (StatusFlags::ZERO, 'Z'),
+    (StatusFlags::CARRY, 'C'),
+];
+
+/// The fields of the form that adds breakpoints
+struct NewBreakpoint {
+    start: String,
+    end: String,
+    bus: Bus,
+    access: Access,
+}
+
+struct Debugger {
+    nes: Nes,
+    running: bool,
+    /// Why the console last stopped by itself
+    stop: Option<StopReason>,
+    screen: TextureHandle,
+    pattern_tables: [TextureHandle; 2],
+    pattern_palette: usize,
+    memory_address: String,
+    new_breakpoint: NewBreakpoint,
+}
+
+fn load(path: &Path) -> Result<Nes, String> {
+    let rom = std::fs::read(path).map_err(|error| error.to_string())?;
+    let cartridge = [
+        Cartridge::from_ines,
+        Cartridge::from_unif,
+        Cartridge::from_nsf,
+    ]
+    .into_iter()
+    .map(|load| load(&rom))
+    .find(|cartridge| !matches!(cartridge, Err(LoadError::InvalidHeader)))
+    .unwrap_or(Err(LoadError::InvalidHeader))
+    .map_err(|error| error.to_string())?;
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    nes.power_cycle();
+    Ok(nes)
+}
+
+fn image(pixels: &[u16], width: usize, height: usize) -> ColorImage {
+    let mut rgba = vec![0; pixels.len() * 4];
+    palette::write_rgba_row(pixels, &mut rgba);
+    ColorImage::from_rgba_unmultiplied([width, height], &rgba)
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let digits = text.trim().trim_start_matches('$');
+    u16::from_str_radix(digits, 16).ok()
+}
+
+impl Debugger {
+    fn new(context: &egui::Context, nes: Nes) -> Self {
+        let blank = |width, height| ColorImage::new([width, height], Color32::BLACK);
+        let texture = |name, image| context.load_texture(name, image, TextureOptions::NEAREST);
+        let mut debugger = Self {
+            nes,
+            running: false,
+            stop: None,
+            screen: texture("screen", blank(Frame::WIDTH, Frame::HEIGHT)),
+            pattern_tables: [
+                texture(
+                    "pattern table 0",
+                    blank(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE),
+                ),
+                texture(
+                    "pattern table 1",
+                    blank(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE),
+                ),
+            ],
+            pattern_palette: 0,
+            memory_address: "0000".to_string(),
+            new_breakpoint: NewBreakpoint {
+                start: String::new(),
+                end: String::new(),
+                bus: Bus::Cpu,
+                access: Access::EXECUTE,
+            },
+        };
+        debugger.update_textures();
+        debugger
+    }
+
+    fn update_textures(&mut self) {
+        let screen = image(self.nes.ppu().framebuffer(), Frame::WIDTH, Frame::HEIGHT);
+        self.screen.set(screen, TextureOptions::NEAREST);
+        for (table, texture) in self.pattern_tables.iter_mut().enumerate() {
+            let pixels = self.nes.pattern_table(table, self.pattern_palette);
+            if !pixels.is_empty() {
+                let picture = image(&pixels, PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+                texture.set(picture, TextureOptions::NEAREST);
+            }
+        }
+    }
+
+    /// Run a step, stopping the game if it hit a breakpoint
+    fn step(&mut self, step: fn(&mut Nes) -> Option<StopReason>) {
+        self.stop = step(&mut self.nes);
+        self.running = false;
+        self.update_textures();
+    }
+
+    fn controls(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            let label = if self.running { "Pause" } else { "Run" };
+            if ui.button(label).clicked() {
+                self.running = !self.running;
+                self.stop = None;
+            }
+            ui.add_enabled_ui(!self.running, |ui| {
+                if ui.button("Step into").clicked() {
+                    self.step(Nes::step_into);
+                }
+                if ui.button("Step over").clicked() {
+                    self.step(Nes::step_over);
+                }
+                if ui.button("Step out").clicked() {
+                    self.step(Nes::step_out);
+                }
+                if ui.button("Next frame").clicked() {
+                    self.step(|nes| nes.run_frame().stop);
+                }
+            });
+            if ui.button("Reset").clicked() {
+                self.nes.reset();
+            }
+            match self.stop {
+                Some(StopReason::Breakpoint(id)) => {
+                    let hits = self.nes.breakpoints().hits(id).unwrap_or(0);
+                    ui.label(format!("Stopped at a breakpoint, hit {hits} times"));
+                }
+                Some(StopReason::Timeout) => {
+                    ui.label("The step didn't finish within two frames");
+                }
+                None => {}
+            }
+        });
+    }
+
+    fn registers(&self, ui: &mut Ui) {
+        let cpu = self.nes.cpu();
+        ui.heading("CPU");
+        ui.monospace(format!(
+            "A  ${:02X}\nX  ${:02X}\nY  ${:02X}\nS  ${:02X}\nPC ${:04X}",
+            cpu.accumulator, cpu.x_index, cpu.y_index, cpu.stack_ptr, cpu.program_counter
+        ));
+        let flags: String = FLAGS
+            .iter()
+            .map(|&(flag, name)| match cpu.flags.contains(flag) {
+                true => name,
+                false => '-',
+            })
+            .collect();
+        ui.monospace(format!("P  {flags}"));
+
+        let ppu = self.nes.ppu();
+        ui.heading("PPU");
+        ui.monospace(format!(
+            "Frame    {}\nScanline {}\nDot      {}\nVRAM     ${:04X}",
+            ppu.frame(),
+            ppu.scanline(),
+            ppu.dot(),
+            ppu.vram_address()
+        ));
+
+        ui.heading("Call stack");
+        ui.monospace(self.nes.backtrace());
+    }
+
+    fn disassembly(&self, ui: &mut Ui) {
+        ui.heading("Disassembly");
+        let mut address = self.nes.cpu().program_counter;
+        let breakpoints: Vec<_> = self.nes.breakpoints().iter().collect();
+        for line in 0..DISASSEMBLY_LINES {
+            let instruction = self.nes.disassemble(address);
+            let has_breakpoint = breakpoints.iter().any(|(_, breakpoint, enabled)| {
+                *enabled
+                    && breakpoint.bus == Bus::Cpu
+                    && breakpoint.access.contains(Access::EXECUTE)
+                    && (breakpoint.start..=breakpoint.end).contains(&address)
+            });
+            let marker = match (line, has_breakpoint) {
+                (0, _) => '>',
+                (_, true) => '*',
+                _ => ' ',
+            };
+            let text = format!(
+                "{marker} ${address:04X}  {}",
+                self.nes.disassemble_labeled(address)
+            );
+            let text = RichText::new(text).monospace();
+            ui.label(match line {
+                0 => text.color(Color32::YELLOW),
+                _ => text,
+            });
+            address = instruction.next_address();
+        }
+    }
+
+    fn memory(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Memory");
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.memory_address);
+        });
+        let start = parse_address(&self.memory_address).unwrap_or(0) & 0xFFF0;
+        for row in 0..MEMORY_ROWS {
+            let row_address = start.wrapping_add(row * 16);
+            let bytes: Vec<u8> = (0..16)
+                .map(|column| self.nes.peek(row_address.wrapping_add(column)))
+                .collect();
+            let hex: String = bytes.iter().map(|byte| format!("{byte:02X} ")).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7E => byte as char,
+                    _ => '.',
+                })
+                .collect();
+            ui.monospace(format!("${row_address:04X}  {hex} {ascii}"));
+        }
+    }
+
+    fn breakpoints(&mut self, ui: &mut Ui) {
+        ui.heading("Breakpoints");
+        let breakpoints: Vec<_> = self.nes.breakpoints().iter().collect();
+        for (id, breakpoint, mut enabled) in breakpoints {
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut enabled, "").changed() {
+                    self.nes.breakpoints_mut().set_enabled(id, enabled);
+                }
+                let hits = self.nes.breakpoints().hits(id).unwrap_or(0);
+                ui.monospace(format!(
+                    "{:?} ${:04X}-${:04X} {:?}, {hits} hits",
+                    breakpoint.bus, breakpoint.start, breakpoint.end, breakpoint.access
+                ));
+                if ui.small_button("Remove").clicked() {
+                    self.nes.breakpoints_mut().remove(id);
+                }
+            });
+        }
+
+        ui.separator();
+        let form = &mut self.new_breakpoint;
+        ui.horizontal(|ui| {
+            ui.label("From");
+            ui.add(egui::TextEdit::singleline(&mut form.start).desired_width(48.0));
+            ui.label("to");
+            ui.add(egui::TextEdit::singleline(&mut form.end).desired_width(48.0));
+        });
+        ui.horizontal(|ui| {
+            for (bus, name) in [(Bus::Cpu, "CPU"), (Bus::Vram, "VRAM"), (Bus::Oam, "OAM")] {
+                ui.radio_value(&mut form.bus, bus, name);
+            }
+        });
+        ui.horizontal(|ui| {
+            for (access, name) in [
+                (Access::READ, "Read"),
+                (Access::WRITE, "Write"),
+                (Access::EXECUTE, "Execute"),
+            ] {
+                let mut checked = form.access.contains(access);
+                if ui.checkbox(&mut checked, name).changed() {
+                    form.access.set(access, checked);
+                }
+            }
+        });
+        let start = parse_address(&form.start);
+        let end = match form.end.trim() {
+            "" => start,
+            end => parse_address(end),
+        };
+        let valid = matches!((start, end), (Some(start), Some(end)) if start <= end)
+            && !form.access.is_empty();
+        if ui.add_enabled(valid, egui::Button::new("Add")).clicked() {
+            if let (Some(start), Some(end)) = (start, end) {
+                let breakpoint = Breakpoint::on_access(form.bus, start..=end, form.access);
+                self.nes.breakpoints_mut().add(breakpoint);
+            }
+        }
+    }
+
+    fn pattern_tables(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Pattern tables");
+            ui.label("Palette");
+            let changed = ui
+                .add(egui::DragValue::new(&mut self.pattern_palette).range(0..=7))
+                .changed();
+            if changed {
+                self.update_textures();
+            }
+        });
+        ui.horizontal(|ui| {
+            let size = egui::vec2(PATTERN_TABLE_SIZE as f32, PATTERN_TABLE_SIZE as f32) * 2.0;
+            for texture in &self.pattern_tables {
+                ui.image((texture.id(), size));
+            }
+        });
+    }
+}
+
+impl eframe::App for Debugger {
+    fn update(&mut self, context: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.running {
+            let buttons = context.input(|input| {
+                KEYS.iter()
+                    .filter(|(key, _)| input.key_down(*key))
+                    .fold(ButtonState::empty(), |buttons, &(_, button)| {
+                        buttons | button
+                    })
+            });
+            self.nes.set_player_buttons(0, buttons);
+            if let Some(stop) = self.nes.run_frame().stop {
+                self.stop = Some(stop);
+                self.running = false;
+            }
+            self.update_textures();
+            context.request_repaint();
+        }
+
+        egui::TopBottomPanel::top("controls").show(context, |ui| self.controls(ui));
+        egui::SidePanel::left("registers").show(context, |ui| self.registers(ui));
+        egui::SidePanel::right("breakpoints").show(context, |ui| {
+            self.breakpoints(ui);
+            ui.separator();
+            self.pattern_tables(ui);
+        });
+        egui::TopBottomPanel::bottom("memory").show(context, |ui| self.memory(ui));
+        egui::CentralPanel::default().show(context, |ui| {
+            ui.horizontal_top(|ui| {
+                let size = egui::vec2(Frame::WIDTH as f32, Frame::HEIGHT as f32) * SCREEN_SCALE;
+                ui.image((self.screen.id(), size));
+                ui.vertical(|ui| self.disassembly(ui));
+            });
+        });
+    }
+}
+
+fn main() -> eframe::Result {
+    let Some(path) = std::env::args_os().nth(1) else {
+        eprintln!("Usage: nesty-debugger ROM");
+        std::process::exit(2);
+    };
+    let nes = match load(Path::new(&path)) {
+        Ok(nes) => nes,
+        Err(error) => {
+            eprintln!("couldn't load {}: {error}", Path::new(&path).display());
+            std::process::exit(1);
+        }
+    };
+    eframe::run_native(
+        "nesty debugger",
+        eframe::NativeOptions::default(),
+        Box::new(|creation| Ok(Box::new(Debugger::new(&creation.egui_ctx, nes)))),
+    )
+}
@@ -0,0 +1,142 @@
+#ifndef NESTY_H
+#define NESTY_H
+
+/* Generated by cbindgen from src/lib.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Width of the picture in pixels
+#define NESTY_WIDTH 256
+
+// Height of the picture in pixels
+#define NESTY_HEIGHT 240
+
+// Bits of the buttons for `nesty_set_buttons`
+#define NESTY_BUTTON_A 1
+
+#define NESTY_BUTTON_B 2
+
+#define NESTY_BUTTON_SELECT 4
+
+#define NESTY_BUTTON_START 8
+
+#define NESTY_BUTTON_UP 16
+
+#define NESTY_BUTTON_DOWN 32
+
+#define NESTY_BUTTON_LEFT 64
+
+#define NESTY_BUTTON_RIGHT 128
+
+// What the functions that can fail return
+typedef enum NestyStatus {
+  NESTY_STATUS_OK,
+  // A pointer that has to be valid was null
+  NESTY_STATUS_NULL_POINTER,
+  // The ROM isn't an iNES, UNIF or NSF file, or is damaged
+  NESTY_STATUS_INVALID_ROM,
+  // The ROM uses a board that isn't emulated
+  NESTY_STATUS_UNSUPPORTED_MAPPER,
+  // The save state is damaged, from another version, or for another game
+  NESTY_STATUS_INVALID_STATE,
+  // The buffer is too small for the save state
+  NESTY_STATUS_BUFFER_TOO_SMALL,
+} NestyStatus;
+
+// A console, only used through pointers
+typedef struct NestyConsole NestyConsole;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A console without a cartridge, free it with `nesty_destroy`
+NestyConsole *nesty_create(void);
+
+// # Safety
+// `console` has to come from `nesty_create` and can't be used afterwards, it can be null
+void nesty_destroy(NestyConsole *console);
+
+// Insert an iNES, UNIF or NSF file and power the console on, the data is copied
+//
+// # Safety
+// `console` has to be a live console and `data` has to point to `size` bytes
+NestyStatus nesty_load_rom(NestyConsole *console, const uint8_t *data, size_t size);
+
+// # Safety
+// `console` has to be a live console
+void nesty_unload_rom(NestyConsole *console);
+
+// Run until the end of the frame, then the picture and audio are ready
+//
+// # Safety
+// `console` has to be a live console
+void nesty_run_frame(NestyConsole *console);
+
+// The picture of the last frame, `NESTY_WIDTH * NESTY_HEIGHT` pixels in RGBA
+//
+// # Safety
+// `console` has to be a live console
+const uint8_t *nesty_framebuffer(const NestyConsole *console);
+
+// The mono samples of the last frame, -1.0-1.0, their number is written to `len`
+//
+// # Safety
+// `console` has to be a live console, `len` can be null
+const float *nesty_audio(const NestyConsole *console, size_t *len);
+
+// 44100 Hz by default
+//
+// # Safety
+// `console` has to be a live console
+void nesty_set_sample_rate(NestyConsole *console, uint32_t sample_rate);
+
+// Set the buttons a player holds, a combination of the `NESTY_BUTTON_` bits
+//
+// # Safety
+// `console` has to be a live console
+void nesty_set_buttons(NestyConsole *console, size_t player, uint8_t buttons);
+
+// Press the reset button
+//
+// # Safety
+// `console` has to be a live console
+void nesty_reset(NestyConsole *console);
+
+// Turn the console off and on again
+//
+// # Safety
+// `console` has to be a live console
+void nesty_power_cycle(NestyConsole *console);
+
+// Size of a save state of the console as it is now, 0 if it can't be saved
+//
+// # Safety
+// `console` has to be a live console
+size_t nesty_save_state_size(const NestyConsole *console);
+
+// Write a save state to `buffer`, and its size to `written`
+//
+// # Safety
+// `console` has to be a live console, `buffer` has to point to `size` writable bytes
+// and `written` can be null
+NestyStatus nesty_save_state(const NestyConsole *console,
+                             uint8_t *buffer,
+                             size_t size,
+                             size_t *written);
+
+// Load a save state, the console is unchanged if it fails
+//
+// # Safety
+// `console` has to be a live console and `data` has to point to `size` bytes
+NestyStatus nesty_load_state(NestyConsole *console, const uint8_t *data, size_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NESTY_H */
@@ -0,0 +1,298 @@
+//! C API of the emulator
+//!
+//! Build with `cargo build --release -p nesty-ffi`, which gives a shared and a static library,
+//! and include `include/nesty.h`. A frontend creates a console, loads a ROM,
+//! then runs a frame and reads its picture and audio in a loop:
+//!
+//! ```c
+//! NestyConsole *console = nesty_create();
+//! if (nesty_load_rom(console, rom, rom_size) != NESTY_STATUS_OK) { ... }
+//! while (running) {
+//!     nesty_set_buttons(console, 0, NESTY_BUTTON_A | NESTY_BUTTON_RIGHT);
+//!     nesty_run_frame(console);
+//!     draw(nesty_framebuffer(console));
+//!     size_t samples;
+//!     const float *audio = nesty_audio(console, &samples);
+//!     queue(audio, samples);
+//! }
+//! nesty_destroy(console);
+//! ```
+//!
+//! A console can't be used by several threads at once.
+//! The pointers to the picture and the audio stay valid until the next call that takes the console.
+//! The header is generated by cbindgen with `cbindgen.toml`.
+
+use std::ptr;
+
+use nesty::prelude::*;
+
+#[cfg(test)]
+mod tests;
+
+/// Width of the picture in pixels
+pub const NESTY_WIDTH: usize = Frame::WIDTH;
+/// Height of the picture in pixels
+pub const NESTY_HEIGHT: usize = Frame::HEIGHT;
+
+/// Bits of the buttons for `nesty_set_buttons`
+pub const NESTY_BUTTON_A: u8 = ButtonState::A.bits();
+pub const NESTY_BUTTON_B: u8 = ButtonState::B.bits();
+pub const NESTY_BUTTON_SELECT: u8 = ButtonState::SELECT.bits();
+pub const NESTY_BUTTON_START: u8 = ButtonState::START.bits();
+pub const NESTY_BUTTON_UP: u8 = ButtonState::UP.bits();
+pub const NESTY_BUTTON_DOWN: u8 = ButtonState::DOWN.bits();
+pub const NESTY_BUTTON_LEFT: u8 = ButtonState::LEFT.bits();
+pub const NESTY_BUTTON_RIGHT: u8 = ButtonState::RIGHT.bits();
+
+/// What the functions that can fail return
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NestyStatus {
+    Ok,
+    /// A pointer that has to be valid was null
+    NullPointer,
+    /// The ROM isn't an iNES, UNIF or NSF file, or is damaged
+    InvalidRom,
+    /// The ROM uses a board that isn't emulated
+    UnsupportedMapper,
+    /// The save state is damaged, from another version, or for another game
+    InvalidState,
+    /// The buffer is too small for the save state
+    BufferTooSmall,
+}
+
+/// A console, only used through pointers
+pub struct NestyConsole {
+    nes: Nes,
+    /// The picture of the last frame in RGBA
+    rgba: Vec<u8>,
+    /// The samples of the last frame, -1.0-1.0
+    audio: Vec<f32>,
+}
+
+/// Try every format, the first that recognizes the header decides
+fn load_cartridge(rom: &[u8]) -> Result<Cartridge, LoadError> {
+    [
+        Cartridge::from_ines,
+        Cartridge::from_unif,
+        Cartridge::from_nsf,
+    ]
+    .into_iter()
+    .map(|load| load(rom))
+    .find(|cartridge| !matches!(cartridge, Err(LoadError::InvalidHeader)))
+    .unwrap_or(Err(LoadError::InvalidHeader))
+}
+
+/// A console without a cartridge, free it with `nesty_destroy`
+#[no_mangle]
+pub extern "C" fn nesty_create() -> *mut NestyConsole {
+    let console = NestyConsole {
+        nes: Nes::new(),
+        rgba: vec![0; Frame::WIDTH * Frame::HEIGHT * 4],
+        audio: Vec::new(),
+    };
+    Box::into_raw(Box::new(console))
+}
+
+/// # Safety
+/// `console` has to come from `nesty_create` and can't be used afterwards, it can be null
+#[no_mangle]
+pub unsafe extern "C" fn nesty_destroy(console: *mut NestyConsole) {
+    if !console.is_null() {
+        drop(unsafe { Box::from_raw(console) });
+    }
+}
+
+/// Insert an iNES, UNIF or NSF file and power the console on, the data is copied
+///
+/// # Safety
+/// `console` has to be a live console and `data` has to point to `size` bytes
+#[no_mangle]
+pub unsafe extern "C" fn nesty_load_rom(
+    console: *mut NestyConsole,
+    data: *const u8,
+    size: usize,
+) -> NestyStatus {
+    let Some(console) = (unsafe { console.as_mut() }) else {
+        return NestyStatus::NullPointer;
+    };
+    if data.is_null() {
+        return NestyStatus::NullPointer;
+    }
+    let rom = unsafe { std::slice::from_raw_parts(data, size) };
+    match load_cartridge(rom) {
+        Ok(cartridge) => {
+            console.nes.insert_cartridge(cartridge);
+            console.nes.power_cycle();
+            NestyStatus::Ok
+        }
+        Err(LoadError::UnsupportedMapper(_) | LoadError::UnknownBoard(_)) => {
+            NestyStatus::UnsupportedMapper
+        }
+        Err(_) => NestyStatus::InvalidRom,
+    }
+}
+
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_unload_rom(console: *mut NestyConsole) {
+    if let Some(console) = unsafe { console.as_mut() } {
+        console.nes.remove_cartridge();
+    }
+}
+
+/// Run until the end of the frame, then the picture and audio are ready
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_run_frame(console: *mut NestyConsole) {
+    let Some(console) = (unsafe { console.as_mut() }) else {
+        return;
+    };
+    let frame = console.nes.run_frame();
+    frame.palette.write_rgba_row(frame.video, &mut console.rgba);
+    console.audio.clear();
+    console
+        .audio
+        .extend(frame.audio.iter().map(|&sample| sample * 2.0 - 1.0));
+}
+
+/// The picture of the last frame, `NESTY_WIDTH * NESTY_HEIGHT` pixels in RGBA
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_framebuffer(console: *const NestyConsole) -> *const u8 {
+    match unsafe { console.as_ref() } {
+        Some(console) => console.rgba.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The mono samples of the last frame, -1.0-1.0, their number is written to `len`
+///
+/// # Safety
+/// `console` has to be a live console, `len` can be null
+#[no_mangle]
+pub unsafe extern "C" fn nesty_audio(console: *const NestyConsole, len: *mut usize) -> *const f32 {
+    let (samples, count) = match unsafe { console.as_ref() } {
+        Some(console) => (console.audio.as_ptr(), console.audio.len()),
+        None => (ptr::null(), 0),
+    };
+    if !len.is_null() {
+        unsafe { len.write(count) };
+    }
+    samples
+}
+
+/// 44100 Hz by default
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_set_sample_rate(console: *mut NestyConsole, sample_rate: u32) {
+    if let Some(console) = unsafe { console.as_mut() } {
+        console.nes.set_sample_rate(sample_rate);
+    }
+}
+
+/// Set the buttons a player holds, a combination of the `NESTY_BUTTON_` bits
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_set_buttons(console: *mut NestyConsole, player: usize, buttons: u8) {
+    if let Some(console) = unsafe { console.as_mut() } {
+        let buttons = ButtonState::from_bits_truncate(buttons);
+        console.nes.set_player_buttons(player, buttons);
+    }
+}
+
+/// Press the reset button
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_reset(console: *mut NestyConsole) {
+    if let Some(console) = unsafe { console.as_mut() } {
+        console.nes.reset();
+    }
+}
+
+/// Turn the console off and on again
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_power_cycle(console: *mut NestyConsole) {
+    if let Some(console) = unsafe { console.as_mut() } {
+        console.nes.power_cycle();
+    }
+}
+
+/// Size of a save state of the console as it is now, 0 if it can't be saved
+///
+/// # Safety
+/// `console` has to be a live console
+#[no_mangle]
+pub unsafe extern "C" fn nesty_save_state_size(console: *const NestyConsole) -> usize {
+    unsafe { console.as_ref() }
+        .and_then(|console| console.nes.save_state().ok())
+        .map_or(0, |state| state.len())
+}
+
+/// Write a save state to `buffer`, and its size to `written`
+///
+/// # Safety
+/// `console` has to be a live console, `buffer` has to point to `size` writable bytes
+/// and `written` can be null
+#[no_mangle]
+pub unsafe extern "C" fn nesty_save_state(
+    console: *const NestyConsole,
+    buffer: *mut u8,
+    size: usize,
+    written: *mut usize,
+) -> NestyStatus {
+    let Some(console) = (unsafe { console.as_ref() }) else {
+        return NestyStatus::NullPointer;
+    };
+    if buffer.is_null() {
+        return NestyStatus::NullPointer;
+    }
+    let Ok(state) = console.nes.save_state() else {
+        return NestyStatus::InvalidState;
+    };
+    if state.len() > size {
+        return NestyStatus::BufferTooSmall;
+    }
+    unsafe { ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len()) };
+    if !written.is_null() {
+        unsafe { written.write(state.len()) };
+    }
+    NestyStatus::Ok
+}
+
+/// Load a save state, the console is unchanged if it fails
+///
+/// # Safety
+/// `console` has to be a live console and `data` has to point to `size` bytes
+#[no_mangle]
+pub unsafe extern "C" fn nesty_load_state(
+    console: *mut NestyConsole,
+    data: *const u8,
+    size: usize,
+) -> NestyStatus {
+    let Some(console) = (unsafe { console.as_mut() }) else {
+        return NestyStatus::NullPointer;
+    };
+    if data.is_null() {
+        return NestyStatus::NullPointer;
+    }
+    let state = unsafe { std::slice::from_raw_parts(data, size) };
+    match console.nes.load_state(state) {
+        Ok(()) => NestyStatus::Ok,
+        Err(_) => NestyStatus::InvalidState,
+    }
+}
@@ -0,0 +1,119 @@
+use super::*;
+
+const HEADER: &str = include_str!("../include/nesty.h");
+const SOURCE: &str = include_str!("lib.rs");
+
+/// NROM spinning at $8000
+fn rom() -> Vec<u8> {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0];
+    image.resize(16, 0);
+    let mut prg = vec![0xEA; 0x8000];
+    // JMP $8000
+    prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    image.extend(prg);
+    image.extend([0; 0x2000]);
+    image
+}
+
+#[test]
+fn console() {
+    let console = nesty_create();
+    let rom = rom();
+    unsafe {
+        assert_eq!(
+            nesty_load_rom(console, [0; 16].as_ptr(), 16),
+            NestyStatus::InvalidRom
+        );
+        assert_eq!(
+            nesty_load_rom(console, rom.as_ptr(), rom.len()),
+            NestyStatus::Ok
+        );
+        nesty_set_buttons(console, 0, NESTY_BUTTON_A | NESTY_BUTTON_START);
+        nesty_run_frame(console);
+
+        let pixels =
+            std::slice::from_raw_parts(nesty_framebuffer(console), NESTY_WIDTH * NESTY_HEIGHT * 4);
+        assert!(pixels.chunks(4).all(|pixel| pixel[3] == 0xFF));
+        let mut len = 0;
+        let audio = nesty_audio(console, &mut len);
+        assert!(len > 700);
+        let audio = std::slice::from_raw_parts(audio, len);
+        assert!(audio.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+
+        let size = nesty_save_state_size(console);
+        assert_ne!(size, 0);
+        let mut state = vec![0; size];
+        let mut written = 0;
+        assert_eq!(
+            nesty_save_state(console, state.as_mut_ptr(), 4, &mut written),
+            NestyStatus::BufferTooSmall
+        );
+        assert_eq!(
+            nesty_save_state(console, state.as_mut_ptr(), size, &mut written),
+            NestyStatus::Ok
+        );
+        assert_eq!(written, size);
+        nesty_run_frame(console);
+        assert_eq!(
+            nesty_load_state(console, state.as_ptr(), size),
+            NestyStatus::Ok
+        );
+        assert_eq!(
+            nesty_load_state(console, state.as_ptr(), 4),
+            NestyStatus::InvalidState
+        );
+
+        nesty_destroy(console);
+    }
+}
+
+#[test]
+fn null_console() {
+    unsafe {
+        assert_eq!(
+            nesty_load_rom(ptr::null_mut(), ptr::null(), 0),
+            NestyStatus::NullPointer
+        );
+        nesty_run_frame(ptr::null_mut());
+        assert!(nesty_framebuffer(ptr::null()).is_null());
+        let mut len = 1;
+        assert!(nesty_audio(ptr::null(), &mut len).is_null());
+        assert_eq!(len, 0);
+        assert_eq!(nesty_save_state_size(ptr::null()), 0);
+        nesty_destroy(ptr::null_mut());
+    }
+}
+
+/// The header has to be regenerated when the API changes
+#[test]
+fn header_is_up_to_date() {
+    let functions = SOURCE
+        .lines()
+        .filter_map(|line| line.split_once("extern \"C\" fn ").map(|(_, rest)| rest))
+        .filter_map(|rest| rest.split_once('('))
+        .map(|(name, _)| name);
+    for function in functions {
+        assert!(
+            HEADER.contains(&format!(" {function}(")) || HEADER.contains(&format!("*{function}(")),
+            "`{function}` isn't in the header"
+        );
+    }
+    for (name, value) in [
+        ("NESTY_WIDTH", NESTY_WIDTH),
+        ("NESTY_HEIGHT", NESTY_HEIGHT),
+        ("NESTY_BUTTON_A", NESTY_BUTTON_A as usize),
+        ("NESTY_BUTTON_B", NESTY_BUTTON_B as usize),
+        ("NESTY_BUTTON_SELECT", NESTY_BUTTON_SELECT as usize),
+        ("NESTY_BUTTON_START", NESTY_BUTTON_START as usize),
+        ("NESTY_BUTTON_UP", NESTY_BUTTON_UP as usize),
+        ("NESTY_BUTTON_DOWN", NESTY_BUTTON_DOWN as usize),
+        ("NESTY_BUTTON_LEFT", NESTY_BUTTON_LEFT as usize),
+        ("NESTY_BUTTON_RIGHT", NESTY_BUTTON_RIGHT as usize),
+    ] {
+        assert!(
+            HEADER.contains(&format!("#define {name} {value}\n")),
+            "`{name}` isn't {value} in the header"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Random code and register writes never make the console panic
+//!
+//! The input starts with a count of writes, then the address and value of each one.
+//! They're turned into `LDA #value, STA address` at $8000, followed by the rest
+//! of the input as random code, with random vectors at the end of the ROM.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nesty::{cartridge::Cartridge, nes::Nes};
+
+const PRG_SIZE: usize = 0x8000;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&count, data)) = data.split_first() else {
+        return;
+    };
+    let (writes, code) = data.split_at(data.len().min(count as usize * 3));
+    let mut prg = Vec::with_capacity(PRG_SIZE);
+    for write in writes.chunks_exact(3) {
+        prg.extend([0xA9, write[2], 0x8D, write[0], write[1]]);
+    }
+    prg.extend(code);
+    prg.resize(PRG_SIZE, 0);
+    // the reset vector has to point at the writes
+    prg[PRG_SIZE - 4] = 0x00;
+    prg[PRG_SIZE - 3] = 0x80;
+
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0];
+    image.resize(16, 0);
+    image.extend(prg);
+    image.resize(16 + PRG_SIZE + 0x2000, 0);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    for _ in 0..10 {
+        nes.run_frame();
+    }
+    let state = nes.save_state().unwrap();
+    nes.load_state(&state).unwrap();
+});
@@ -0,0 +1,30 @@
+//! Every file format has to be rejected with an error, never a panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nesty::{
+    cartridge::{fds::DiskImage, Cartridge},
+    movie::Movie,
+    nes::Nes,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let loaded = [
+        Cartridge::from_ines(data),
+        Cartridge::from_unif(data),
+        Cartridge::from_nsf(data),
+    ];
+    let _ = DiskImage::from_fds(data);
+    let _ = DiskImage::from_qd(data);
+    if let Ok(text) = std::str::from_utf8(data) {
+        let _ = Movie::from_fm2(text);
+    }
+    // whatever loads has to survive a frame, and a save state has to load or fail cleanly
+    for cartridge in loaded.into_iter().flatten() {
+        let mut nes = Nes::new();
+        nes.insert_cartridge(cartridge);
+        nes.power_cycle();
+        nes.run_frame();
+        let _ = nes.load_state(data);
+    }
+});
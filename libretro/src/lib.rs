@@ -0,0 +1,542 @@
+//! A [libretro](https://docs.libretro.com/development/cores/developing-cores/) core
+//!
+//! Build the `cdylib` with `cargo build --release -p nesty-libretro` and load
+//! `libnesty_libretro.so` (or `.dll`, `.dylib`) into RetroArch or another libretro frontend.
+//! The core plays iNES, UNIF and NSF files with a standard controller in each port.
+//! It tells the frontend where the RAM and the battery backed cartridge RAM are,
+//! so cheats, achievements and save files work, and supports save states and Game Genie codes.
+//!
+//! There's a single console, which lives on the thread the frontend calls the core from.
+//! Frontends make every call from the same thread, so that's the one it keeps running on.
+
+use std::{
+    cell::{Cell, RefCell},
+    ffi::{c_char, c_uint, c_void, CStr},
+};
+
+use nesty::{
+    apu::DEFAULT_SAMPLE_RATE,
+    cartridge::{game_genie::GameGenieCode, Cartridge},
+    clock::Region,
+    input::ButtonState,
+    nes::{Frame, Nes},
+    ppu::{palette, DOTS_PER_SCANLINE},
+};
+
+#[cfg(test)]
+mod tests;
+
+const API_VERSION: c_uint = 1;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const ENVIRONMENT_SET_MEMORY_MAPS: c_uint = 36 | 0x10000;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const DEVICE_JOYPAD: c_uint = 1;
+/// Joypad button IDs, with the buttons they're mapped to
+const JOYPAD_BUTTONS: [(c_uint, ButtonState, &CStr); 8] = [
+    (0, ButtonState::B, c"B"),
+    (2, ButtonState::SELECT, c"Select"),
+    (3, ButtonState::START, c"Start"),
+    (4, ButtonState::UP, c"Up"),
+    (5, ButtonState::DOWN, c"Down"),
+    (6, ButtonState::LEFT, c"Left"),
+    (7, ButtonState::RIGHT, c"Right"),
+    (8, ButtonState::A, c"A"),
+];
+const PORTS: c_uint = 2;
+
+const REGION_NTSC: c_uint = 0;
+const REGION_PAL: c_uint = 1;
+
+const MEMORY_SAVE_RAM: c_uint = 0;
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const MEMDESC_SYSTEM_RAM: u64 = 1 << 2;
+const MEMDESC_SAVE_RAM: u64 = 1 << 3;
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+struct InputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+#[repr(C)]
+struct MemoryDescriptor {
+    flags: u64,
+    ptr: *mut c_void,
+    offset: usize,
+    start: usize,
+    select: usize,
+    disconnect: usize,
+    len: usize,
+    addrspace: *const c_char,
+}
+
+#[repr(C)]
+struct MemoryMap {
+    descriptors: *const MemoryDescriptor,
+    num_descriptors: c_uint,
+}
+
+pub type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn =
+    extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = extern "C" fn();
+pub type InputStateFn =
+    extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// What the frontend gave the core to talk to it
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    nes: Nes,
+    /// The picture in XRGB8888
+    video: Vec<u32>,
+    /// Stereo samples
+    audio: Vec<i16>,
+}
+
+thread_local! {
+    static CALLBACKS: Cell<Callbacks> = const {
+        Cell::new(Callbacks {
+            environment: None,
+            video_refresh: None,
+            audio_sample_batch: None,
+            input_poll: None,
+            input_state: None,
+        })
+    };
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn callbacks() -> Callbacks {
+    CALLBACKS.get()
+}
+
+fn set_callbacks(f: impl FnOnce(&mut Callbacks)) {
+    let mut callbacks = CALLBACKS.get();
+    f(&mut callbacks);
+    CALLBACKS.set(callbacks);
+}
+
+/// Run `f` on the console, `None` if there's none between `retro_init` and `retro_deinit`
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> Option<T> {
+    CORE.with_borrow_mut(|core| core.as_mut().map(f))
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    callbacks()
+        .environment
+        .is_some_and(|environment| environment(cmd, data))
+}
+
+/// Frames per second of a region, the odd frames that are a dot shorter are ignored
+fn frame_rate(region: Region) -> f64 {
+    let dots = DOTS_PER_SCANLINE as f64 * region.scanlines_per_frame() as f64;
+    region.master_clock_rate() as f64 / (region.ppu_divider() as f64 * dots)
+}
+
+fn load_cartridge(rom: &[u8]) -> Option<Cartridge> {
+    let cartridge = match rom {
+        [b'N', b'E', b'S', 0x1A, ..] => Cartridge::from_ines(rom),
+        [b'U', b'N', b'I', b'F', ..] => Cartridge::from_unif(rom),
+        [b'N', b'E', b'S', b'M', 0x1A, ..] => Cartridge::from_nsf(rom),
+        _ => return None,
+    };
+    cartridge.ok()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    set_callbacks(|callbacks| callbacks.environment = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    set_callbacks(|callbacks| callbacks.video_refresh = Some(callback));
+}
+
+/// Samples are always sent in batches
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    set_callbacks(|callbacks| callbacks.audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    set_callbacks(|callbacks| callbacks.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    set_callbacks(|callbacks| callbacks.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    let nes = Nes::builder().sample_rate(DEFAULT_SAMPLE_RATE).build();
+    let core = Core {
+        nes,
+        video: vec![0; Frame::WIDTH * Frame::HEIGHT],
+        audio: Vec::new(),
+    };
+    CORE.set(Some(core));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.set(None);
+}
+
+/// # Safety
+/// `info` has to point to a `retro_system_info`
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    let system_info = SystemInfo {
+        library_name: c"nesty".as_ptr(),
+        library_version: c"0.1.0".as_ptr(),
+        valid_extensions: c"nes|unf|unif|nsf".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+    unsafe { info.write(system_info) };
+}
+
+/// # Safety
+/// `info` has to point to a `retro_system_av_info`
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let region = with_core(|core| core.nes.region()).unwrap_or_default();
+    let av_info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: Frame::WIDTH as c_uint,
+            base_height: Frame::HEIGHT as c_uint,
+            max_width: Frame::WIDTH as c_uint,
+            max_height: Frame::HEIGHT as c_uint,
+            // the pixels of the NTSC picture are a bit wider than tall
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: SystemTiming {
+            fps: frame_rate(region),
+            sample_rate: DEFAULT_SAMPLE_RATE as f64,
+        },
+    };
+    unsafe { info.write(av_info) };
+}
+
+/// Only the standard controller is supported
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| core.nes.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    with_core(|core| {
+        if let Some(input_poll) = callbacks.input_poll {
+            input_poll();
+        }
+        if let Some(input_state) = callbacks.input_state {
+            for port in 0..PORTS {
+                let buttons = JOYPAD_BUTTONS
+                    .iter()
+                    .filter(|&&(id, ..)| input_state(port, DEVICE_JOYPAD, 0, id) != 0)
+                    .fold(ButtonState::empty(), |buttons, &(_, button, _)| {
+                        buttons | button
+                    });
+                core.nes.set_player_buttons(port as usize, buttons);
+            }
+        }
+
+        let frame = core.nes.run_frame();
+        for (output, &pixel) in core.video.iter_mut().zip(frame.video) {
+            let [r, g, b, _] = palette::to_rgba(pixel);
+            *output = u32::from_be_bytes([0, r, g, b]);
+        }
+        core.audio.clear();
+        core.audio.extend(frame.audio.iter().flat_map(|&sample| {
+            // the APU's output is 0.0-1.0
+            let sample = ((sample * 2.0 - 1.0) * i16::MAX as f32) as i16;
+            [sample, sample]
+        }));
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            video_refresh(
+                core.video.as_ptr().cast(),
+                Frame::WIDTH as c_uint,
+                Frame::HEIGHT as c_uint,
+                Frame::WIDTH * 4,
+            );
+        }
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            audio_sample_batch(core.audio.as_ptr(), core.audio.len() / 2);
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.nes.save_state().ok())
+        .flatten()
+        .map_or(0, |state| state.len())
+}
+
+/// # Safety
+/// `data` has to point to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let Some(state) = with_core(|core| core.nes.save_state().ok()).flatten() else {
+        return false;
+    };
+    if state.len() > size {
+        return false;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(state.as_ptr(), data.cast(), state.len()) };
+    true
+}
+
+/// # Safety
+/// `data` has to point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), size) };
+    with_core(|core| core.nes.load_state(state).is_ok()).unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core(|core| {
+        if let Some(cartridge) = core.nes.cartridge_mut() {
+            let codes: Vec<_> = cartridge.game_genie_codes().map(|(code, _)| code).collect();
+            for code in codes {
+                cartridge.remove_game_genie_code(code);
+            }
+        }
+    });
+}
+
+/// Add Game Genie codes, several can be given separated by `+`
+///
+/// # Safety
+/// `code` has to be a null-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    let code = unsafe { CStr::from_ptr(code) }.to_string_lossy();
+    with_core(|core| {
+        let Some(cartridge) = core.nes.cartridge_mut() else {
+            return;
+        };
+        for code in code.split('+') {
+            if let Ok(code) = code.trim().parse::<GameGenieCode>() {
+                cartridge.add_game_genie_code(code);
+                cartridge.set_game_genie_code_enabled(code, enabled);
+            }
+        }
+    });
+}
+
+/// # Safety
+/// `game` has to point to a `retro_game_info` with the file's contents
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = (unsafe { game.as_ref() }) else {
+        return false;
+    };
+    if game.data.is_null() {
+        return false;
+    }
+    let rom = unsafe { std::slice::from_raw_parts(game.data.cast::<u8>(), game.size) };
+    let Some(cartridge) = load_cartridge(rom) else {
+        return false;
+    };
+
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, (&raw mut format).cast()) {
+        return false;
+    }
+    let descriptors: Vec<_> = (0..PORTS)
+        .flat_map(|port| {
+            JOYPAD_BUTTONS.map(|(id, _, name)| InputDescriptor {
+                port,
+                device: DEVICE_JOYPAD,
+                index: 0,
+                id,
+                description: name.as_ptr(),
+            })
+        })
+        .chain([InputDescriptor {
+            port: 0,
+            device: 0,
+            index: 0,
+            id: 0,
+            description: std::ptr::null(),
+        }])
+        .collect();
+    environment(
+        ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_ptr().cast_mut().cast(),
+    );
+
+    with_core(|core| {
+        core.nes.insert_cartridge(cartridge);
+        core.nes.power_cycle();
+        set_memory_maps(&mut core.nes);
+    })
+    .is_some()
+}
+
+/// Tell the frontend where the RAM and cartridge RAM are, in the CPU address space
+fn set_memory_maps(nes: &mut Nes) {
+    let ram = nes.ram_mut().as_mut_slice();
+    let mut descriptors = vec![MemoryDescriptor {
+        flags: MEMDESC_SYSTEM_RAM,
+        ptr: ram.as_mut_ptr().cast(),
+        offset: 0,
+        start: 0x0000,
+        // mirrored up to $1FFF
+        select: 0xE000,
+        disconnect: 0,
+        len: ram.len(),
+        addrspace: std::ptr::null(),
+    }];
+    if let Some(cartridge) = nes.cartridge_mut() {
+        let battery = cartridge.board().battery;
+        let prg_ram = cartridge.prg_ram_mut();
+        if !prg_ram.is_empty() {
+            descriptors.push(MemoryDescriptor {
+                flags: if battery { MEMDESC_SAVE_RAM } else { 0 },
+                ptr: prg_ram.as_mut_ptr().cast(),
+                offset: 0,
+                start: 0x6000,
+                select: 0xE000,
+                disconnect: 0,
+                len: prg_ram.len().min(0x2000),
+                addrspace: std::ptr::null(),
+            });
+        }
+    }
+    let mut map = MemoryMap {
+        descriptors: descriptors.as_ptr(),
+        num_descriptors: descriptors.len() as c_uint,
+    };
+    environment(ENVIRONMENT_SET_MEMORY_MAPS, (&raw mut map).cast());
+}
+
+/// Games that need several files aren't supported
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| core.nes.remove_cartridge());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    match with_core(|core| core.nes.region()) {
+        Some(Region::Pal | Region::Dendy) => REGION_PAL,
+        _ => REGION_NTSC,
+    }
+}
+
+/// The memory stays where it is until the game is unloaded or the console is powered off,
+/// which only happens when a game is loaded
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(|core| match id {
+        MEMORY_SYSTEM_RAM => core.nes.ram_mut().as_mut_slice().as_mut_ptr().cast(),
+        MEMORY_SAVE_RAM => match core.nes.cartridge_mut() {
+            Some(cartridge) if cartridge.board().battery => {
+                cartridge.prg_ram_mut().as_mut_ptr().cast()
+            }
+            _ => std::ptr::null_mut(),
+        },
+        _ => std::ptr::null_mut(),
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(|core| match id {
+        MEMORY_SYSTEM_RAM => core.nes.ram().as_slice().len(),
+        MEMORY_SAVE_RAM => match core.nes.cartridge() {
+            Some(cartridge) if cartridge.board().battery => cartridge.prg_ram().len(),
+            _ => 0,
+        },
+        _ => 0,
+    })
+    .unwrap_or(0)
+}
@@ -0,0 +1,141 @@
+use std::{
+    cell::RefCell,
+    ffi::{c_uint, c_void},
+};
+
+use super::*;
+
+/// Writes the buttons of player 1 to RAM and the battery backed RAM, then spins
+/// loop: LDA #$01, STA $4016, LDA #$00, STA $4016, LDX #$08
+/// read: LDA $4016, LSR A, ROL $10, DEX, BNE read, LDA $10, STA $11, STA $6000, JMP loop
+#[rustfmt::skip]
+const PROGRAM: [u8; 31] = [
+    0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, 0xA2, 0x08,
+    0xAD, 0x16, 0x40, 0x4A, 0x26, 0x10, 0xCA, 0xD0, 0xF7, 0xA5, 0x10, 0x85,
+    0x11, 0x8D, 0x00, 0x60, 0x4C, 0x00, 0x80,
+];
+
+fn rom() -> Vec<u8> {
+    // NROM with battery backed RAM
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0b10];
+    image.resize(16, 0);
+    let mut prg = vec![0xEA; 0x8000];
+    prg[..PROGRAM.len()].copy_from_slice(&PROGRAM);
+    // reset vector at $8000
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0x80]);
+    image.extend(prg);
+    image.extend([0; 0x2000]);
+    image
+}
+
+#[derive(Default)]
+struct Frontend {
+    commands: Vec<c_uint>,
+    memory_descriptors: usize,
+    frames: usize,
+    audio_frames: usize,
+}
+
+thread_local! {
+    static FRONTEND: RefCell<Frontend> = RefCell::default();
+}
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    FRONTEND.with_borrow_mut(|frontend| {
+        frontend.commands.push(cmd);
+        if cmd == ENVIRONMENT_SET_MEMORY_MAPS {
+            let map = unsafe { &*data.cast::<MemoryMap>() };
+            frontend.memory_descriptors = map.num_descriptors as usize;
+        }
+    });
+    true
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    assert!(!data.is_null());
+    assert_eq!((width, height, pitch), (256, 240, 1024));
+    FRONTEND.with_borrow_mut(|frontend| frontend.frames += 1);
+}
+
+extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    FRONTEND.with_borrow_mut(|frontend| frontend.audio_frames += frames);
+    frames
+}
+
+extern "C" fn input_poll() {}
+
+/// Start and right are held on the first controller
+extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    (port == 0 && device == DEVICE_JOYPAD && matches!(id, 3 | 7)) as i16
+}
+
+#[test]
+fn lifecycle() {
+    retro_set_environment(environment);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+    assert_eq!(retro_api_version(), 1);
+
+    let rom = rom();
+    let game = GameInfo {
+        path: std::ptr::null(),
+        data: rom.as_ptr().cast(),
+        size: rom.len(),
+        meta: std::ptr::null(),
+    };
+    assert!(unsafe { retro_load_game(&game) });
+    FRONTEND.with_borrow(|frontend| {
+        assert!(frontend.commands.contains(&ENVIRONMENT_SET_PIXEL_FORMAT));
+        assert!(frontend
+            .commands
+            .contains(&ENVIRONMENT_SET_INPUT_DESCRIPTORS));
+        assert_eq!(frontend.memory_descriptors, 2);
+    });
+    assert_eq!(retro_get_region(), REGION_NTSC);
+
+    let mut info = std::mem::MaybeUninit::<SystemAvInfo>::uninit();
+    unsafe { retro_get_system_av_info(info.as_mut_ptr()) };
+    let info = unsafe { info.assume_init() };
+    assert!((info.timing.fps - 60.1).abs() < 0.01);
+
+    for _ in 0..3 {
+        retro_run();
+    }
+    FRONTEND.with_borrow(|frontend| {
+        assert_eq!(frontend.frames, 3);
+        assert!(frontend.audio_frames > 2000);
+    });
+
+    // start and right, the first button read ends up in the highest bit
+    // $11 has the finished byte, the frame can end while $10 is being shifted
+    let ram = retro_get_memory_data(MEMORY_SYSTEM_RAM).cast::<u8>();
+    assert_eq!(retro_get_memory_size(MEMORY_SYSTEM_RAM), 0x800);
+    assert_eq!(unsafe { *ram.add(0x11) }, 0b0001_0001);
+    let save = retro_get_memory_data(MEMORY_SAVE_RAM).cast::<u8>();
+    assert_eq!(retro_get_memory_size(MEMORY_SAVE_RAM), 0x2000);
+    assert_eq!(unsafe { *save }, 0b0001_0001);
+
+    let mut state = vec![0; retro_serialize_size()];
+    assert!(unsafe { retro_serialize(state.as_mut_ptr().cast(), state.len()) });
+    let saved = unsafe { *ram.add(0x20) };
+    unsafe { *ram.add(0x20) = !saved };
+    assert!(unsafe { retro_unserialize(state.as_ptr().cast(), state.len()) });
+    let ram = retro_get_memory_data(MEMORY_SYSTEM_RAM).cast::<u8>();
+    assert_eq!(unsafe { *ram.add(0x20) }, saved);
+    assert!(!unsafe { retro_unserialize([0; 4].as_ptr().cast(), 4) });
+
+    unsafe { retro_cheat_set(0, true, c"SXIOPO".as_ptr()) };
+    let codes = with_core(|core| core.nes.cartridge().unwrap().game_genie_codes().count());
+    assert_eq!(codes, Some(1));
+    retro_cheat_reset();
+    let codes = with_core(|core| core.nes.cartridge().unwrap().game_genie_codes().count());
+    assert_eq!(codes, Some(0));
+
+    retro_unload_game();
+    assert_eq!(retro_get_memory_size(MEMORY_SAVE_RAM), 0);
+    retro_deinit();
+    assert_eq!(retro_get_memory_size(MEMORY_SYSTEM_RAM), 0);
+}
@@ -0,0 +1,180 @@
+//! The `nesty` Python module, through [PyO3](https://pyo3.rs)
+//!
+//! Build and install it into the current environment with `maturin develop --release`
+//! in this directory. The picture, audio and RAM come out as numpy arrays:
+//!
+//! ```python
+//! import nesty
+//!
+//! console = nesty.Console()
+//! console.load_rom(open("game.nes", "rb").read())
+//! console.set_buttons(0, nesty.Console.START)
+//! console.step(60)
+//! picture = console.framebuffer()  # (240, 256, 3) uint8
+//! lives = console.ram()[0x075A]
+//! ```
+//!
+//! A console stays on the Python thread that created it.
+
+use nesty_core::{
+    cartridge::{Cartridge, LoadError},
+    input::ButtonState,
+    nes::{Frame, Nes},
+    ppu::palette,
+};
+use numpy::{PyArray1, PyArray2, PyArray3, PyArrayMethods};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+/// An NES console
+#[pyclass(unsendable, module = "nesty")]
+struct Console {
+    nes: Nes,
+    /// The picture of the last frame, in palette indices
+    video: Vec<u16>,
+    /// The samples of the last frame
+    audio: Vec<f32>,
+}
+
+fn load_cartridge(rom: &[u8]) -> Result<Cartridge, LoadError> {
+    [
+        Cartridge::from_ines,
+        Cartridge::from_unif,
+        Cartridge::from_nsf,
+    ]
+    .into_iter()
+    .map(|load| load(rom))
+    .find(|cartridge| !matches!(cartridge, Err(LoadError::InvalidHeader)))
+    .unwrap_or(Err(LoadError::InvalidHeader))
+}
+
+fn value_error(error: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+#[pymethods]
+impl Console {
+    #[classattr]
+    const A: u8 = ButtonState::A.bits();
+    #[classattr]
+    const B: u8 = ButtonState::B.bits();
+    #[classattr]
+    const SELECT: u8 = ButtonState::SELECT.bits();
+    #[classattr]
+    const START: u8 = ButtonState::START.bits();
+    #[classattr]
+    const UP: u8 = ButtonState::UP.bits();
+    #[classattr]
+    const DOWN: u8 = ButtonState::DOWN.bits();
+    #[classattr]
+    const LEFT: u8 = ButtonState::LEFT.bits();
+    #[classattr]
+    const RIGHT: u8 = ButtonState::RIGHT.bits();
+
+    /// A console without a cartridge, making audio at `sample_rate`
+    #[new]
+    #[pyo3(signature = (sample_rate = 44100))]
+    fn new(sample_rate: u32) -> Self {
+        let mut nes = Nes::new();
+        nes.set_sample_rate(sample_rate);
+        Self {
+            nes,
+            video: vec![0; Frame::WIDTH * Frame::HEIGHT],
+            audio: Vec::new(),
+        }
+    }
+
+    /// Insert an iNES, UNIF or NSF file and power the console on
+    fn load_rom(&mut self, rom: &[u8]) -> PyResult<()> {
+        let cartridge = load_cartridge(rom).map_err(value_error)?;
+        self.nes.insert_cartridge(cartridge);
+        self.nes.power_cycle();
+        Ok(())
+    }
+
+    /// Run `frames` frames, the picture is the one of the last frame and the audio of all of them
+    #[pyo3(signature = (frames = 1))]
+    fn step(&mut self, frames: u32) {
+        self.audio.clear();
+        for _ in 0..frames {
+            let frame = self.nes.run_frame();
+            self.video.copy_from_slice(frame.video);
+            self.audio.extend(frame.audio);
+        }
+    }
+
+    /// Number of frames the console ran since it was powered on
+    #[getter]
+    fn frame(&self) -> u64 {
+        self.nes.ppu().frame()
+    }
+
+    /// Set the buttons a player holds, a combination of the button constants
+    fn set_buttons(&mut self, player: usize, buttons: u8) {
+        self.nes
+            .set_player_buttons(player, ButtonState::from_bits_truncate(buttons));
+    }
+
+    /// The picture of the last frame as a (240, 256, 3) array of RGB
+    fn framebuffer<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let rgb: Vec<u8> = self
+            .video
+            .iter()
+            .flat_map(|&pixel| palette::to_rgb(pixel))
+            .collect();
+        PyArray1::from_vec_bound(py, rgb).reshape([Frame::HEIGHT, Frame::WIDTH, 3])
+    }
+
+    /// The picture of the last frame as a (240, 256) array of palette indices with the emphasis bits
+    fn palette_indices<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<u16>>> {
+        PyArray1::from_slice_bound(py, &self.video).reshape([Frame::HEIGHT, Frame::WIDTH])
+    }
+
+    /// The mono samples of the last step, 0.0-1.0
+    fn audio<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_slice_bound(py, &self.audio)
+    }
+
+    /// A copy of the 2KB of RAM
+    fn ram<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<u8>> {
+        PyArray1::from_slice_bound(py, self.nes.ram().as_slice())
+    }
+
+    /// Change a byte of RAM
+    fn write_ram(&mut self, address: usize, value: u8) -> PyResult<()> {
+        let ram = self.nes.ram_mut().as_mut_slice();
+        let len = ram.len();
+        let byte = ram
+            .get_mut(address)
+            .ok_or_else(|| value_error(format!("RAM address {address} isn't below {len}")))?;
+        *byte = value;
+        Ok(())
+    }
+
+    /// Read a byte of the CPU address space without side effects
+    fn peek(&self, address: u16) -> u8 {
+        self.nes.peek(address)
+    }
+
+    fn reset(&mut self) {
+        self.nes.reset();
+    }
+
+    fn power_cycle(&mut self) {
+        self.nes.power_cycle();
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let state = self.nes.save_state().map_err(value_error)?;
+        Ok(PyBytes::new_bound(py, &state))
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.nes.load_state(state).map_err(value_error)
+    }
+}
+
+#[pymodule]
+fn nesty(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Console>()?;
+    Ok(())
+}
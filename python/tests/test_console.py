@@ -0,0 +1,48 @@
+"""Run with `pytest` after `maturin develop`"""
+
+import numpy as np
+import pytest
+
+import nesty
+
+
+def rom():
+    """NROM spinning at $8000"""
+    header = b"NES\x1a\x02\x01" + bytes(10)
+    prg = bytearray([0xEA] * 0x8000)
+    prg[0:3] = bytes([0x4C, 0x00, 0x80])
+    prg[0x7FFC:0x7FFE] = bytes([0x00, 0x80])
+    return header + bytes(prg) + bytes(0x2000)
+
+
+def test_step():
+    console = nesty.Console()
+    console.load_rom(rom())
+    console.set_buttons(0, nesty.Console.A | nesty.Console.START)
+    console.step(2)
+    assert console.frame >= 2
+    assert console.framebuffer().shape == (240, 256, 3)
+    assert console.framebuffer().dtype == np.uint8
+    assert console.palette_indices().shape == (240, 256)
+    assert len(console.audio()) > 1400
+
+
+def test_ram_and_states():
+    console = nesty.Console()
+    console.load_rom(rom())
+    console.write_ram(0x10, 0xAB)
+    assert console.ram()[0x10] == 0xAB
+    assert console.peek(0x0810) == 0xAB
+    state = console.save_state()
+    console.write_ram(0x10, 0)
+    console.load_state(state)
+    assert console.ram()[0x10] == 0xAB
+    with pytest.raises(ValueError):
+        console.write_ram(0x800, 0)
+    with pytest.raises(ValueError):
+        console.load_state(b"nope")
+
+
+def test_invalid_rom():
+    with pytest.raises(ValueError):
+        nesty.Console().load_rom(b"not a rom")
@@ -0,0 +1,282 @@
+//! A frontend to play games with, using SDL2 for the window, audio and input
+//!
+//! Run it with `cargo run --release -- game.nes` in this directory, or drop a ROM on the window.
+//! It needs the SDL2 development libraries, see
+//! [the sdl2 crate](https://github.com/Rust-SDL2/rust-sdl2#sdl20-development-libraries).
+//!
+//! | Key | Button |
+//! |---|---|
+//! | Arrows | D-pad |
+//! | X | A |
+//! | Z | B |
+//! | Right Shift | Select |
+//! | Enter | Start |
+//! | F2 | Reset |
+//! | F5 / F8 | Save / load a state |
+//! | Escape | Quit |
+//!
+//! Game controllers are given to the players in the order they're connected.
+//! The battery backed RAM is kept in a `.sav` file next to the ROM.
+//! The emulation is paced by the audio, a frame runs when less than two frames of samples are queued.
+
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use nesty::{
+    cartridge::{Cartridge, LoadError},
+    input::ButtonState,
+    nes::{Frame, Nes},
+    ppu::palette,
+};
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    controller::{Axis, Button, GameController},
+    event::Event,
+    keyboard::{KeyboardState, Keycode, Scancode},
+    pixels::PixelFormatEnum,
+};
+
+const SCALE: u32 = 3;
+const SAMPLE_RATE: i32 = 44100;
+/// Frames of audio that are kept queued, more adds latency and less makes it crackle
+const QUEUED_FRAMES: u32 = 2;
+/// How far a stick has to be pushed to count as the d-pad, out of 32767
+const STICK_DEADZONE: i16 = 16000;
+
+const KEYS: [(Scancode, ButtonState); 8] = [
+    (Scancode::X, ButtonState::A),
+    (Scancode::Z, ButtonState::B),
+    (Scancode::RShift, ButtonState::SELECT),
+    (Scancode::Return, ButtonState::START),
+    (Scancode::Up, ButtonState::UP),
+    (Scancode::Down, ButtonState::DOWN),
+    (Scancode::Left, ButtonState::LEFT),
+    (Scancode::Right, ButtonState::RIGHT),
+];
+
+const CONTROLLER_BUTTONS: [(Button, ButtonState); 8] = [
+    (Button::A, ButtonState::A),
+    (Button::B, ButtonState::B),
+    (Button::Back, ButtonState::SELECT),
+    (Button::Start, ButtonState::START),
+    (Button::DPadUp, ButtonState::UP),
+    (Button::DPadDown, ButtonState::DOWN),
+    (Button::DPadLeft, ButtonState::LEFT),
+    (Button::DPadRight, ButtonState::RIGHT),
+];
+
+/// The game that's running
+struct Game {
+    path: PathBuf,
+    nes: Nes,
+}
+
+impl Game {
+    fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let rom = std::fs::read(path)?;
+        let mut cartridge = [
+            Cartridge::from_ines,
+            Cartridge::from_unif,
+            Cartridge::from_nsf,
+        ]
+        .into_iter()
+        .map(|load| load(&rom))
+        .find(|cartridge| !matches!(cartridge, Err(LoadError::InvalidHeader)))
+        .unwrap_or(Err(LoadError::InvalidHeader))?;
+
+        if cartridge.board().battery {
+            if let Ok(save) = std::fs::read(path.with_extension("sav")) {
+                let prg_ram = cartridge.prg_ram_mut();
+                let len = prg_ram.len().min(save.len());
+                prg_ram[..len].copy_from_slice(&save[..len]);
+            }
+        }
+        let mut nes = Nes::new();
+        nes.set_sample_rate(SAMPLE_RATE as u32);
+        nes.insert_cartridge(cartridge);
+        nes.power_cycle();
+        Ok(Self {
+            path: path.to_path_buf(),
+            nes,
+        })
+    }
+
+    fn save(&self) {
+        let Some(cartridge) = self.nes.cartridge() else {
+            return;
+        };
+        if cartridge.board().battery {
+            let path = self.path.with_extension("sav");
+            if let Err(error) = std::fs::write(&path, cartridge.prg_ram()) {
+                eprintln!("couldn't save to {}: {error}", path.display());
+            }
+        }
+    }
+}
+
+fn buttons_of(controller: &GameController) -> ButtonState {
+    let mut buttons = CONTROLLER_BUTTONS
+        .iter()
+        .filter(|(button, _)| controller.button(*button))
+        .fold(ButtonState::empty(), |buttons, &(_, button)| {
+            buttons | button
+        });
+    let x = controller.axis(Axis::LeftX);
+    let y = controller.axis(Axis::LeftY);
+    buttons.set(ButtonState::LEFT, x < -STICK_DEADZONE);
+    buttons.set(ButtonState::RIGHT, x > STICK_DEADZONE);
+    buttons.set(ButtonState::UP, y < -STICK_DEADZONE);
+    buttons.set(ButtonState::DOWN, y > STICK_DEADZONE);
+    buttons
+}
+
+fn keyboard_buttons(keyboard: &KeyboardState) -> ButtonState {
+    KEYS.iter()
+        .filter(|(key, _)| keyboard.is_scancode_pressed(*key))
+        .fold(ButtonState::empty(), |buttons, &(_, button)| {
+            buttons | button
+        })
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let audio = sdl.audio()?;
+    let controller_subsystem = sdl.game_controller()?;
+    let mut events = sdl.event_pump()?;
+
+    let window = video
+        .window(
+            "nesty",
+            Frame::WIDTH as u32 * SCALE,
+            Frame::HEIGHT as u32 * SCALE,
+        )
+        .position_centered()
+        .resizable()
+        .build()?;
+    let mut canvas = window.into_canvas().build()?;
+    canvas.set_logical_size(Frame::WIDTH as u32, Frame::HEIGHT as u32)?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator.create_texture_streaming(
+        PixelFormatEnum::RGBA32,
+        Frame::WIDTH as u32,
+        Frame::HEIGHT as u32,
+    )?;
+
+    let spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let queue: AudioQueue<f32> = audio.open_queue(None, &spec)?;
+    queue.resume();
+    // about 60 frames a second, close enough for the size of the queue
+    let queued_bytes = QUEUED_FRAMES * SAMPLE_RATE as u32 / 60 * 4;
+
+    let mut game = match std::env::args_os().nth(1) {
+        Some(path) => Some(Game::load(Path::new(&path))?),
+        None => None,
+    };
+    let mut controllers: Vec<GameController> = Vec::new();
+    let mut state = None;
+    let mut rgba = vec![0; Frame::WIDTH * Frame::HEIGHT * 4];
+    let mut samples = Vec::new();
+
+    'running: loop {
+        for event in events.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::DropFile { filename, .. } => match Game::load(Path::new(&filename)) {
+                    Ok(loaded) => {
+                        if let Some(game) = &game {
+                            game.save();
+                        }
+                        game = Some(loaded);
+                        state = None;
+                        queue.clear();
+                    }
+                    Err(error) => eprintln!("couldn't load {filename}: {error}"),
+                },
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => controllers.push(controller),
+                        Err(error) => eprintln!("couldn't open controller {which}: {error}"),
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.retain(|controller| controller.instance_id() != which);
+                }
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => {
+                    let Some(game) = &mut game else {
+                        continue;
+                    };
+                    match key {
+                        Keycode::F2 => game.nes.reset(),
+                        Keycode::F5 => match game.nes.save_state() {
+                            Ok(saved) => state = Some(saved),
+                            Err(error) => eprintln!("couldn't save the state: {error}"),
+                        },
+                        Keycode::F8 => {
+                            if let Some(saved) = &state {
+                                if let Err(error) = game.nes.load_state(saved) {
+                                    eprintln!("couldn't load the state: {error}");
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let Some(game) = &mut game else {
+            canvas.clear();
+            canvas.present();
+            std::thread::sleep(Duration::from_millis(16));
+            continue;
+        };
+        if queue.size() > queued_bytes {
+            std::thread::sleep(Duration::from_millis(1));
+            continue;
+        }
+
+        let keyboard = keyboard_buttons(&events.keyboard_state());
+        for player in 0..2 {
+            let mut buttons = controllers
+                .get(player)
+                .map_or(ButtonState::empty(), buttons_of);
+            if player == 0 {
+                buttons |= keyboard;
+            }
+            game.nes.set_player_buttons(player, buttons);
+        }
+
+        let frame = game.nes.run_frame();
+        palette::write_rgba_row(frame.video, &mut rgba);
+        samples.clear();
+        samples.extend(frame.audio.iter().map(|&sample| sample * 2.0 - 1.0));
+        queue.queue_audio(&samples)?;
+
+        texture.update(None, &rgba, Frame::WIDTH * 4)?;
+        canvas.clear();
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+    }
+
+    if let Some(game) = &game {
+        game.save();
+    }
+    Ok(())
+}
@@ -0,0 +1,553 @@
+//! Audio Processing Unit
+//!
+//! The APU has 5 channels: 2 pulse waves, a triangle wave, noise and delta modulated samples (DMC).
+//! They're clocked by the CPU, sequenced by the frame counter,
+//! and their outputs are mixed and resampled to [`Apu::sample_rate`].
+//!
+//! Details at https://www.nesdev.org/wiki/APU
+
+mod dmc;
+mod filter;
+mod noise;
+mod pulse;
+pub mod recording;
+mod triangle;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::{boxed::Box, vec::Vec};
+
+use dmc::Dmc;
+use filter::OutputFilter;
+use noise::Noise;
+use pulse::Pulse;
+use recording::Recording;
+use triangle::Triangle;
+
+use crate::{clock::Region, state::impl_state};
+
+/// CPU clock rate of an NTSC console in Hz
+pub const CPU_CLOCK_RATE: u32 = 1_789_773;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// The sample buffer has room for a frame at this frame rate from the start, PAL runs at 50Hz
+const MIN_FRAME_RATE: u32 = 40;
+
+/// CPU cycles into the frame counter sequence at which each step happens
+///
+/// The 4-step sequence ends a cycle after its 4th step and raises the frame IRQ,
+/// the 5-step sequence a cycle after its 5th.
+const NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/// Values loaded into the length counters, indexed by the top 5 bits of the channels' 4th register
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Volume envelope of the pulse and noise channels
+#[derive(Debug, Clone, Copy, Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    /// Either the constant volume or the divider period
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Handle a write to the channel's first register
+    fn store(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant_volume = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    /// Clocked by the frame counter's quarter frames
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+/// Silences a channel after a given number of half frames
+///
+/// Writes to the counter and the halt flag take effect at the end of the cycle,
+/// so a half frame in the same cycle still sees the old halt flag,
+/// and a reload is lost if the half frame counts the counter down.
+///
+/// Details at https://www.nesdev.org/wiki/APU_Length_Counter
+#[derive(Debug, Clone, Copy, Default)]
+struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    value: u8,
+    /// Writes of this cycle, always applied by the end of it so they're not part of the state
+    pending_halt: Option<bool>,
+    pending_value: Option<u8>,
+}
+
+impl LengthCounter {
+    /// Load the counter with an entry from the length table, ignored if the channel is disabled
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.pending_value = Some(LENGTH_TABLE[index as usize & 0x1F]);
+        }
+    }
+
+    fn set_halt(&mut self, halt: bool) {
+        self.pending_halt = Some(halt);
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.value = 0;
+            self.pending_value = None;
+        }
+    }
+
+    /// Clocked by the frame counter's half frames
+    fn clock(&mut self) {
+        if !self.halt && self.value > 0 {
+            self.value -= 1;
+            self.pending_value = None;
+        }
+    }
+
+    /// Apply the writes made during the cycle, after the frame counter clocked it
+    fn apply_writes(&mut self) {
+        if let Some(value) = self.pending_value.take() {
+            self.value = value;
+        }
+        if let Some(halt) = self.pending_halt.take() {
+            self.halt = halt;
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.value > 0
+    }
+}
+
+/// One of the sound channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    Dmc,
+}
+
+/// Outputs of the channels during a cycle, before they're mixed
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelOutputs {
+    pulse1: u8,
+    pulse2: u8,
+    triangle: u8,
+    noise: u8,
+    dmc: u8,
+}
+
+impl ChannelOutputs {
+    /// Only the output of `channel`, the others silent
+    fn only(&self, channel: Channel) -> Self {
+        let mut outputs = Self::default();
+        match channel {
+            Channel::Pulse1 => outputs.pulse1 = self.pulse1,
+            Channel::Pulse2 => outputs.pulse2 = self.pulse2,
+            Channel::Triangle => outputs.triangle = self.triangle,
+            Channel::Noise => outputs.noise = self.noise,
+            Channel::Dmc => outputs.dmc = self.dmc,
+        }
+        outputs
+    }
+
+    /// Combine the outputs, approximating the nonlinear mixer of the console
+    ///
+    /// Details at https://www.nesdev.org/wiki/APU_Mixer
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse1 + self.pulse2) as f32;
+        let triangle = self.triangle as f32;
+        let noise = self.noise as f32;
+        let dmc = self.dmc as f32;
+
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles into the current frame counter sequence
+    frame_cycle: u32,
+    /// Pulse timers are clocked every other CPU cycle
+    odd_cycle: bool,
+
+    /// Not part of the state, the console sets it from its clock
+    region: Region,
+    sample_rate: u32,
+    /// Sum of the mixer outputs since the last sample, they're averaged into one sample
+    sample_sum: f32,
+    sample_cycles: u32,
+    /// Incremented by the sample rate every cycle, a sample is output every time it passes the CPU clock rate
+    sample_phase: u32,
+    samples: Vec<f32>,
+    /// Not part of the state either
+    recording: Option<Box<Recording>>,
+    /// Settings from the frontend, not part of the state, see [`Apu::set_output_filter`]
+    /// and [`Apu::set_reduce_dmc_popping`]
+    filter: Option<OutputFilter>,
+    reduce_dmc_popping: bool,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply the effects of the reset button, which silences all channels
+    pub fn reset(&mut self) {
+        self.store_register(0x4015, 0);
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+    }
+
+    /// Whether the APU is asserting the IRQ line
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq()
+    }
+
+    /// Samples generated since the last call to [`Apu::clear_samples`], in the 0.0-1.0 range
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn clear_samples(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switch to the timing and period tables of another region
+    ///
+    /// Takes effect the next time a channel's period is written
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn is_pal(&self) -> bool {
+        self.region == Region::Pal
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Set the rate at which the output is sampled, in Hz
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_phase = 0;
+        if self.filter.is_some() {
+            self.filter = Some(OutputFilter::new(sample_rate));
+        }
+        self.samples.reserve(frame_samples(sample_rate));
+    }
+
+    pub fn has_output_filter(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// Run the samples through the high-pass and low-pass filters of the console's audio output
+    ///
+    /// The filtered samples are centered on 0.5 instead of starting from 0.
+    /// Recordings always get the unfiltered output.
+    pub fn set_output_filter(&mut self, enabled: bool) {
+        if enabled != self.filter.is_some() {
+            self.filter = enabled.then(|| OutputFilter::new(self.sample_rate));
+        }
+    }
+
+    pub fn reduce_dmc_popping(&self) -> bool {
+        self.reduce_dmc_popping
+    }
+
+    /// Soften jumps of the DMC output level written to $4011
+    ///
+    /// Some games write the level once at startup and it makes a click,
+    /// with this on the jumps are halved, at the cost of quieter PCM in games playing it through $4011.
+    pub fn set_reduce_dmc_popping(&mut self, reduce: bool) {
+        self.reduce_dmc_popping = reduce;
+    }
+
+    /// Start capturing the output into `recording`, replacing the one that was going on
+    pub fn start_recording(&mut self, recording: Recording) {
+        self.recording = Some(Box::new(recording));
+    }
+
+    /// Stop capturing the output, returning what was recorded
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recording.take().map(|recording| *recording)
+    }
+
+    /// The recording going on, which can be finished but not stopped yet
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recording.as_deref()
+    }
+
+    /// Address the DMC wants to fetch its next sample byte from, if it needs one
+    ///
+    /// The byte has to be read from the CPU bus and given back with [`Apu::fill_dmc_buffer`]
+    pub fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc.dma_request()
+    }
+
+    pub fn fill_dmc_buffer(&mut self, value: u8) {
+        self.dmc.fill_buffer(value);
+    }
+
+    /// Read the status register ($4015), this acknowledges the frame IRQ
+    pub fn load_status(&mut self) -> u8 {
+        let status = self.pulse1.length_counter.is_active() as u8
+            | (self.pulse2.length_counter.is_active() as u8) << 1
+            | (self.triangle.length_counter.is_active() as u8) << 2
+            | (self.noise.length_counter.is_active() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq() as u8) << 7;
+        self.frame_irq = false;
+        status
+    }
+
+    /// Write to one of the registers at $4000-$4013, $4015 or $4017
+    pub fn store_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4000..=0x4003 => self.pulse1.store(address, value),
+            0x4004..=0x4007 => self.pulse2.store(address, value),
+            0x4008..=0x400B => self.triangle.store(address, value),
+            0x400C..=0x400F => self.noise.store(address, value, self.is_pal()),
+            0x4011 if self.reduce_dmc_popping => {
+                let value = self.dmc.reduce_pop(value);
+                self.dmc.store(address, value, self.is_pal());
+            }
+            0x4010..=0x4013 => self.dmc.store(address, value, self.is_pal()),
+            0x4015 => {
+                self.pulse1.length_counter.set_enabled(value & 0x01 != 0);
+                self.pulse2.length_counter.set_enabled(value & 0x02 != 0);
+                self.triangle.length_counter.set_enabled(value & 0x04 != 0);
+                self.noise.length_counter.set_enabled(value & 0x08 != 0);
+                self.dmc.set_enabled(value & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step_mode = value & 0x80 != 0;
+                self.irq_inhibit = value & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                // switching to the 5-step mode clocks everything immediately
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Advance the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.tick_frame_counter();
+        for length_counter in [
+            &mut self.pulse1.length_counter,
+            &mut self.pulse2.length_counter,
+            &mut self.triangle.length_counter,
+            &mut self.noise.length_counter,
+        ] {
+            length_counter.apply_writes();
+        }
+
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        let outputs = self.outputs();
+        self.sample_sum += outputs.mix();
+        self.sample_cycles += 1;
+        self.sample_phase += self.sample_rate;
+        let clock_rate = self.region.cpu_clock_rate();
+        if let Some(recording) = &mut self.recording {
+            recording.tick(&outputs, clock_rate);
+        }
+        if self.sample_phase >= clock_rate {
+            self.sample_phase -= clock_rate;
+            let mut sample = self.sample_sum / self.sample_cycles as f32;
+            if let Some(filter) = &mut self.filter {
+                sample = filter.apply(sample);
+            }
+            self.samples.push(sample);
+            self.sample_sum = 0.0;
+            self.sample_cycles = 0;
+        }
+    }
+
+    fn tick_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        let steps = if self.is_pal() {
+            PAL_FRAME_STEPS
+        } else {
+            NTSC_FRAME_STEPS
+        };
+        let last_step = if self.five_step_mode { 4 } else { 3 };
+        match steps.iter().position(|&step| step == self.frame_cycle) {
+            Some(0 | 2) => self.clock_quarter_frame(),
+            Some(step) if step == 1 || step == last_step => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            _ => {}
+        }
+
+        if self.frame_cycle == steps[last_step] + 1 {
+            self.frame_cycle = 0;
+            if !self.five_step_mode && !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.clock_linear_counter();
+        self.noise.envelope.clock();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_half_frame();
+        self.pulse2.clock_half_frame();
+        self.triangle.length_counter.clock();
+        self.noise.length_counter.clock();
+    }
+
+    fn outputs(&self) -> ChannelOutputs {
+        ChannelOutputs {
+            pulse1: self.pulse1.output(),
+            pulse2: self.pulse2.output(),
+            triangle: self.triangle.output(),
+            noise: self.noise.output(),
+            dmc: self.dmc.output(),
+        }
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            pulse1: Pulse::new(false),
+            pulse2: Pulse::new(true),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            region: Region::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_sum: 0.0,
+            sample_cycles: 0,
+            sample_phase: 0,
+            samples: Vec::with_capacity(frame_samples(DEFAULT_SAMPLE_RATE)),
+            recording: None,
+            filter: None,
+            reduce_dmc_popping: false,
+        }
+    }
+}
+
+/// Samples generated during a frame, or a bit more, so that running frames doesn't have to grow the buffer
+fn frame_samples(sample_rate: u32) -> usize {
+    (sample_rate / MIN_FRAME_RATE) as usize
+}
+
+impl_state!(Envelope {
+    start,
+    looping,
+    constant_volume,
+    volume,
+    divider,
+    decay,
+});
+impl_state!(LengthCounter {
+    enabled,
+    halt,
+    value
+});
+// the sample buffer and rate aren't part of the state, they depend on the frontend
+impl_state!(Apu {
+    pulse1,
+    pulse2,
+    triangle,
+    noise,
+    dmc,
+    five_step_mode,
+    irq_inhibit,
+    frame_irq,
+    frame_cycle,
+    odd_cycle,
+});
@@ -0,0 +1,191 @@
+//! Delta modulation channel at $4010-$4013
+//!
+//! Plays 1-bit delta encoded samples fetched from the CPU address space.
+//! The CPU stall caused by the sample fetches isn't emulated.
+//!
+//! Details at https://www.nesdev.org/wiki/APU_DMC
+
+use crate::state::impl_state;
+
+/// Timer periods in CPU cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATE_TABLE: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Largest jump of the output level written to $4011 left alone by [`Dmc::reduce_pop`]
+const MAX_OUTPUT_JUMP: i16 = 50;
+
+#[derive(Debug, Clone)]
+pub(super) struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    /// 7-bit output level, incremented or decremented by 2 for every bit of the sample
+    output_level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    /// Write to one of the channel's 4 registers, `address` is taken modulo 4
+    pub(super) fn store(&mut self, address: u16, value: u8, pal: bool) {
+        match address & 3 {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = value & 0x40 != 0;
+                let table = if pal { &PAL_RATE_TABLE } else { &RATE_TABLE };
+                self.timer_period = table[value as usize & 0x0F];
+            }
+            1 => self.output_level = value & 0x7F,
+            2 => self.sample_address = 0xC000 + value as u16 * 64,
+            _ => self.sample_length = value as u16 * 16 + 1,
+        }
+    }
+
+    /// Output level to write to $4011 instead of `value`, with a large jump from the current level halved
+    pub(super) fn reduce_pop(&self, value: u8) -> u8 {
+        let level = self.output_level as i16;
+        let difference = (value & 0x7F) as i16 - level;
+        if difference.abs() > MAX_OUTPUT_JUMP {
+            (level + difference / 2) as u8
+        } else {
+            value
+        }
+    }
+
+    /// Handle the DMC bit of $4015, which also acknowledges the DMC IRQ
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub(super) fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Whether there are still sample bytes left to fetch
+    pub(super) fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub(super) fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn fill_buffer(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        // the address wraps around to $8000
+        self.current_address = self.current_address.wrapping_add(1) | 0x8000;
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self {
+            irq_enabled: false,
+            looping: false,
+            timer_period: RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+}
+
+impl_state!(Dmc {
+    irq_enabled,
+    looping,
+    timer_period,
+    timer,
+    output_level,
+    sample_address,
+    sample_length,
+    current_address,
+    bytes_remaining,
+    sample_buffer,
+    shift_register,
+    bits_remaining,
+    silence,
+    irq,
+});
@@ -0,0 +1,83 @@
+//! Filters of the console's audio output
+//!
+//! After the mixer the signal goes through two high-pass filters, at 90 Hz and 440 Hz,
+//! which take out the DC offset, and a 14 kHz low-pass filter that softens the steps.
+//! They're emulated as first order filters running at the sample rate.
+//!
+//! Details at https://www.nesdev.org/wiki/APU_Mixer
+
+use core::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy)]
+struct HighPass {
+    factor: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        Self {
+            factor: rc / (rc + 1.0 / sample_rate),
+            previous_input: 0.0,
+            previous_output: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        self.previous_output = self.factor * (self.previous_output + input - self.previous_input);
+        self.previous_input = input;
+        self.previous_output
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LowPass {
+    factor: f32,
+    previous_output: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Self {
+            factor: dt / (rc + dt),
+            previous_output: 0.0,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        self.previous_output += self.factor * (input - self.previous_output);
+        self.previous_output
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct OutputFilter {
+    high_pass: [HighPass; 2],
+    low_pass: LowPass,
+}
+
+impl OutputFilter {
+    pub(super) fn new(sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+        Self {
+            high_pass: [
+                HighPass::new(90.0, sample_rate),
+                HighPass::new(440.0, sample_rate),
+            ],
+            low_pass: LowPass::new(14_000.0, sample_rate),
+        }
+    }
+
+    /// Filter a sample, the output is centered on 0.5 so it stays in the range of unfiltered samples
+    pub(super) fn apply(&mut self, sample: f32) -> f32 {
+        let sample = self
+            .high_pass
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.apply(sample));
+        (self.low_pass.apply(sample) + 0.5).clamp(0.0, 1.0)
+    }
+}
@@ -0,0 +1,95 @@
+//! Noise channel at $400C-$400F
+//!
+//! Details at https://www.nesdev.org/wiki/APU_Noise
+
+use super::{Envelope, LengthCounter};
+use crate::state::impl_state;
+
+/// Timer periods in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+#[derive(Debug, Clone)]
+pub(super) struct Noise {
+    /// Short mode takes the feedback from bit 6 instead of bit 1, producing a metallic tone
+    short_mode: bool,
+    timer_period: u16,
+    timer: u16,
+    /// 15-bit linear feedback shift register
+    shift_register: u16,
+    pub(super) envelope: Envelope,
+    pub(super) length_counter: LengthCounter,
+}
+
+impl Noise {
+    /// Write to one of the channel's 4 registers, `address` is taken modulo 4
+    pub(super) fn store(&mut self, address: u16, value: u8, pal: bool) {
+        match address & 3 {
+            0 => {
+                self.length_counter.set_halt(value & 0x20 != 0);
+                self.envelope.store(value);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                let table = if pal {
+                    &PAL_PERIOD_TABLE
+                } else {
+                    &PERIOD_TABLE
+                };
+                self.timer_period = table[value as usize & 0x0F];
+            }
+            _ => {
+                self.length_counter.load(value >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if self.shift_register & 1 == 0 && self.length_counter.is_active() {
+            self.envelope.output()
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            short_mode: false,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            // the register is 1 on power up
+            shift_register: 1,
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+}
+
+impl_state!(Noise {
+    short_mode,
+    timer_period,
+    timer,
+    shift_register,
+    envelope,
+    length_counter,
+});
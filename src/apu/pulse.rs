@@ -0,0 +1,147 @@
+//! Pulse (square wave) channels at $4000-$4007
+//!
+//! Details at https://www.nesdev.org/wiki/APU_Pulse
+
+use super::{Envelope, LengthCounter};
+use crate::state::impl_state;
+
+#[rustfmt::skip]
+const DUTY_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Pulse {
+    /// The second channel negates the sweep in two's complement, the first one in ones' complement
+    second_channel: bool,
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    sweep: Sweep,
+    pub(super) envelope: Envelope,
+    pub(super) length_counter: LengthCounter,
+}
+
+impl Pulse {
+    pub(super) fn new(second_channel: bool) -> Self {
+        Self {
+            second_channel,
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            sweep: Sweep::default(),
+            envelope: Envelope::default(),
+            length_counter: LengthCounter::default(),
+        }
+    }
+
+    /// Write to one of the channel's 4 registers, `address` is taken modulo 4
+    pub(super) fn store(&mut self, address: u16, value: u8) {
+        match address & 3 {
+            0 => {
+                self.duty = value >> 6;
+                self.length_counter.set_halt(value & 0x20 != 0);
+                self.envelope.store(value);
+            }
+            1 => {
+                self.sweep.enabled = value & 0x80 != 0;
+                self.sweep.period = (value >> 4) & 0b111;
+                self.sweep.negate = value & 0x08 != 0;
+                self.sweep.shift = value & 0b111;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0xFF) | (value as u16 & 0b111) << 8;
+                self.length_counter.load(value >> 3);
+                self.sequence_step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every other CPU cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clock the length counter and the sweep unit
+    pub(super) fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.is_muted()
+        {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    /// Period the sweep unit would change the timer to
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if self.sweep.negate {
+            let change = change + !self.second_channel as u16;
+            self.timer_period.saturating_sub(change)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    /// The sweep unit mutes the channel when the period is too low or the target overflows,
+    /// even if the sweep is disabled
+    fn is_muted(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        let high = DUTY_SEQUENCES[self.duty as usize][self.sequence_step as usize] != 0;
+        if high && self.length_counter.is_active() && !self.is_muted() {
+            self.envelope.output()
+        } else {
+            0
+        }
+    }
+}
+
+impl_state!(Sweep {
+    enabled,
+    period,
+    negate,
+    shift,
+    reload,
+    divider,
+});
+impl_state!(Pulse {
+    duty,
+    sequence_step,
+    timer_period,
+    timer,
+    sweep,
+    envelope,
+    length_counter,
+});
@@ -0,0 +1,129 @@
+//! Recording the audio into WAV files
+//!
+//! A [`Recording`] is armed with [`Nes::start_audio_recording`](crate::nes::Nes::start_audio_recording),
+//! then captures the output of the APU at its own sample rate as 16-bit samples,
+//! however the console is run, until it's stopped or has recorded for its duration.
+//! It can record a single channel, like a hardware recording made with the others muted.
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use super::{Channel, ChannelOutputs};
+
+/// What a recording captures
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Source {
+    /// The channels mixed together, like the audio of [`Apu::samples`](super::Apu::samples)
+    #[default]
+    Mixed,
+    /// A single channel going through the mixer alone
+    Channel(Channel),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    source: Source,
+    sample_rate: u32,
+    /// Samples to record before stopping by itself
+    max_samples: Option<usize>,
+    samples: Vec<i16>,
+    /// Sum of the outputs since the last sample, they're averaged into one sample
+    sum: f32,
+    cycles: u32,
+    /// Incremented by the sample rate every cycle, a sample is recorded every time it passes the CPU clock rate
+    phase: u32,
+}
+
+impl Recording {
+    /// Record `source` at `sample_rate` Hz, for `duration` or until it's stopped if there's none
+    pub fn new(source: Source, sample_rate: u32, duration: Option<Duration>) -> Self {
+        let max_samples =
+            duration.map(|duration| (duration.as_secs_f64() * sample_rate as f64 + 0.5) as usize);
+        Self {
+            source,
+            sample_rate,
+            max_samples,
+            samples: Vec::with_capacity(max_samples.unwrap_or(0)),
+            sum: 0.0,
+            cycles: 0,
+            phase: 0,
+        }
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Mono samples recorded so far
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    /// Length of the audio recorded so far
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / self.sample_rate as f64)
+    }
+
+    /// The recording has lasted for its duration and doesn't record anymore
+    pub fn is_finished(&self) -> bool {
+        self.max_samples
+            .is_some_and(|max_samples| self.samples.len() >= max_samples)
+    }
+
+    /// Add the outputs of the channels during a CPU cycle
+    pub(super) fn tick(&mut self, outputs: &ChannelOutputs, clock_rate: u32) {
+        if self.is_finished() {
+            return;
+        }
+        let output = match self.source {
+            Source::Mixed => outputs.mix(),
+            Source::Channel(channel) => outputs.only(channel).mix(),
+        };
+        self.sum += output;
+        self.cycles += 1;
+        self.phase += self.sample_rate;
+        if self.phase >= clock_rate {
+            self.phase -= clock_rate;
+            let sample = self.sum / self.cycles as f32;
+            // the 0.0-1.0 output of the mixer to the full range
+            self.samples
+                .push(((sample * 2.0 - 1.0) * i16::MAX as f32) as i16);
+            self.sum = 0.0;
+            self.cycles = 0;
+        }
+    }
+
+    /// Encode the samples as a 16-bit mono WAV file
+    pub fn to_wav_bytes(&self) -> Vec<u8> {
+        const HEADER_SIZE: usize = 44;
+        let data_size = (self.samples.len() * 2) as u32;
+        let mut wav = Vec::with_capacity(HEADER_SIZE + self.samples.len() * 2);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(HEADER_SIZE as u32 - 8 + data_size).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        // PCM, 1 channel, 2 bytes per sample
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        for sample in &self.samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+
+    /// Save the samples as a WAV file, see [`Recording::to_wav_bytes`]
+    #[cfg(feature = "std")]
+    pub fn save_wav(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_wav_bytes())
+    }
+}
@@ -0,0 +1,186 @@
+use core::time::Duration;
+
+use super::{
+    recording::{Recording, Source},
+    Apu, Channel, CPU_CLOCK_RATE, DEFAULT_SAMPLE_RATE,
+};
+
+#[test]
+fn frame_irq() {
+    let mut apu = Apu::new();
+    (0..29829).for_each(|_| apu.tick());
+    assert!(!apu.irq());
+    apu.tick();
+    assert!(apu.irq());
+    assert_eq!(apu.load_status() & 0x40, 0x40);
+    assert!(!apu.irq());
+
+    // 5-step mode never raises the IRQ
+    apu.store_register(0x4017, 0x80);
+    (0..100_000).for_each(|_| apu.tick());
+    assert!(!apu.irq());
+}
+
+#[test]
+fn length_counter() {
+    let mut apu = Apu::new();
+    // ignored while the channel is disabled
+    apu.store_register(0x4003, 0x08);
+    assert_eq!(apu.load_status() & 0x01, 0);
+
+    apu.store_register(0x4015, 0x01);
+    // length index 1, 254 half frames, loaded at the end of the cycle
+    apu.store_register(0x4003, 0x08);
+    apu.tick();
+    assert_eq!(apu.load_status() & 0x01, 0x01);
+
+    // 2 half frames per 4-step sequence, the last one on its second to last cycle
+    (0..127 * 29830 - 3).for_each(|_| apu.tick());
+    assert_eq!(apu.load_status() & 0x01, 0x01);
+    apu.tick();
+    assert_eq!(apu.load_status() & 0x01, 0);
+
+    apu.store_register(0x4003, 0x08);
+    apu.store_register(0x4015, 0x00);
+    assert_eq!(apu.load_status() & 0x01, 0);
+}
+
+#[test]
+fn length_counter_timing() {
+    let mut apu = Apu::new();
+    apu.store_register(0x4015, 0x01);
+    apu.store_register(0x4003, 0x08);
+    // the first half frame is on the 14913th cycle
+    let run_to_half_frame = |apu: &mut Apu| {
+        while apu.frame_cycle != 14912 {
+            apu.tick();
+        }
+    };
+    run_to_half_frame(&mut apu);
+    assert_eq!(apu.pulse1.length_counter.value, 254);
+
+    // a reload in the same cycle as the half frame is lost when the counter is counted down
+    apu.store_register(0x4003, 0x18);
+    apu.tick();
+    assert_eq!(apu.pulse1.length_counter.value, 253);
+
+    // but not when it's halted, or at 0
+    apu.store_register(0x4000, 0x20);
+    run_to_half_frame(&mut apu);
+    apu.store_register(0x4003, 0x18);
+    apu.tick();
+    assert_eq!(apu.pulse1.length_counter.value, 2);
+    apu.store_register(0x4015, 0x00);
+    apu.store_register(0x4015, 0x01);
+    run_to_half_frame(&mut apu);
+    apu.store_register(0x4003, 0x18);
+    apu.tick();
+    assert_eq!(apu.pulse1.length_counter.value, 2);
+
+    // the half frame sees the halt flag from before a write in the same cycle
+    run_to_half_frame(&mut apu);
+    apu.store_register(0x4000, 0x00);
+    apu.tick();
+    assert_eq!(apu.pulse1.length_counter.value, 2);
+    // counted down by the half frame ending the sequence
+    run_to_half_frame(&mut apu);
+    assert_eq!(apu.pulse1.length_counter.value, 1);
+    apu.store_register(0x4000, 0x20);
+    apu.tick();
+    assert_eq!(apu.pulse1.length_counter.value, 0);
+}
+
+#[test]
+fn samples() {
+    let mut apu = Apu::new();
+    apu.store_register(0x4015, 0x01);
+    // 50% duty, constant volume 15
+    apu.store_register(0x4000, 0xBF);
+    apu.store_register(0x4002, 0xFD);
+    apu.store_register(0x4003, 0x00);
+
+    (0..CPU_CLOCK_RATE).for_each(|_| apu.tick());
+    assert_eq!(apu.samples().len(), DEFAULT_SAMPLE_RATE as usize);
+    assert!(apu.samples().iter().any(|&sample| sample > 0.0));
+    assert!(apu
+        .samples()
+        .iter()
+        .all(|&sample| (0.0..=1.0).contains(&sample)));
+
+    apu.clear_samples();
+    assert!(apu.samples().is_empty());
+}
+
+#[test]
+fn output_filter() {
+    let mut apu = Apu::new();
+    apu.set_output_filter(true);
+    assert!(apu.has_output_filter());
+    // a constant DMC level is taken out by the high-pass filters
+    apu.store_register(0x4011, 0x7F);
+    (0..CPU_CLOCK_RATE / 10).for_each(|_| apu.tick());
+    let last = *apu.samples().last().unwrap();
+    assert!((last - 0.5).abs() < 0.01, "{last}");
+
+    apu.set_output_filter(false);
+    apu.clear_samples();
+    (0..CPU_CLOCK_RATE / 100).for_each(|_| apu.tick());
+    assert!(apu.samples().iter().all(|&sample| sample > 0.5));
+}
+
+#[test]
+fn dmc_popping() {
+    let mut apu = Apu::new();
+    apu.set_reduce_dmc_popping(true);
+    apu.store_register(0x4011, 0x7F);
+    assert_eq!(apu.dmc.output(), 63);
+    // small changes are left alone
+    apu.store_register(0x4011, 0x50);
+    assert_eq!(apu.dmc.output(), 0x50);
+
+    apu.set_reduce_dmc_popping(false);
+    apu.store_register(0x4011, 0x00);
+    assert_eq!(apu.dmc.output(), 0);
+}
+
+#[test]
+fn recording() {
+    let mut apu = Apu::new();
+    apu.store_register(0x4015, 0x05);
+    // pulse 1 at constant volume 15, the triangle stopped on the first step of its sequence
+    apu.store_register(0x4000, 0xBF);
+    apu.store_register(0x4002, 0xFD);
+    apu.store_register(0x4003, 0x00);
+
+    let duration = Duration::from_millis(100);
+    apu.start_recording(Recording::new(Source::Mixed, 8000, Some(duration)));
+    (0..CPU_CLOCK_RATE / 5).for_each(|_| apu.tick());
+    let mixed = apu.stop_recording().unwrap();
+    assert!(mixed.is_finished());
+    assert_eq!(mixed.samples().len(), 800);
+    assert_eq!(mixed.duration(), duration);
+    assert!(mixed.samples().iter().any(|&sample| sample > -i16::MAX));
+
+    apu.start_recording(Recording::new(
+        Source::Channel(Channel::Triangle),
+        8000,
+        None,
+    ));
+    (0..CPU_CLOCK_RATE / 5).for_each(|_| apu.tick());
+    let triangle = apu.recording().unwrap();
+    assert!(!triangle.is_finished());
+    let len = triangle.samples().len();
+    assert!(len.abs_diff(1600) <= 1);
+    let level = triangle.samples()[0];
+    assert!(level > -i16::MAX);
+    assert!(triangle.samples().iter().all(|&sample| sample == level));
+
+    let wav = triangle.to_wav_bytes();
+    assert_eq!(wav.len(), 44 + len * 2);
+    assert_eq!(&wav[..4], b"RIFF");
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(wav[24..28], 8000u32.to_le_bytes());
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(wav[40..44], (len as u32 * 2).to_le_bytes());
+    assert_eq!(wav[44..46], level.to_le_bytes());
+}
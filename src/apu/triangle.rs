@@ -0,0 +1,85 @@
+//! Triangle wave channel at $4008-$400B
+//!
+//! Details at https://www.nesdev.org/wiki/APU_Triangle
+
+use super::LengthCounter;
+use crate::state::impl_state;
+
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct Triangle {
+    /// Also halts the length counter
+    control: bool,
+    linear_counter_period: u8,
+    linear_counter: u8,
+    linear_counter_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    sequence_step: u8,
+    pub(super) length_counter: LengthCounter,
+}
+
+impl Triangle {
+    /// Write to one of the channel's 4 registers, `address` is taken modulo 4
+    pub(super) fn store(&mut self, address: u16, value: u8) {
+        match address & 3 {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.length_counter.set_halt(self.control);
+                self.linear_counter_period = value & 0x7F;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x700) | value as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0xFF) | (value as u16 & 0b111) << 8;
+                self.length_counter.load(value >> 3);
+                self.linear_counter_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length_counter.is_active() && self.linear_counter > 0 {
+                self.sequence_step = (self.sequence_step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocked by the frame counter's quarter frames
+    pub(super) fn clock_linear_counter(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    /// The triangle keeps outputting its last value when silenced
+    pub(super) fn output(&self) -> u8 {
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl_state!(Triangle {
+    control,
+    linear_counter_period,
+    linear_counter,
+    linear_counter_reload,
+    timer_period,
+    timer,
+    sequence_step,
+    length_counter,
+});
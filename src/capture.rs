@@ -0,0 +1,111 @@
+//! Lossless capture of the picture and audio as raw streams
+//!
+//! [`RawRecorder`] writes every frame as 8-bit RGB and its audio as 16-bit mono samples,
+//! to two writers, so they can be piped into an encoder like ffmpeg without a window.
+//! With named pipes, or files:
+//!
+//! ```text
+//! mkfifo video audio
+//! ffmpeg -f rawvideo -pixel_format rgb24 -video_size 256x240 -framerate 21477272/357368 -i video \
+//!        -f s16le -ar 44100 -ac 1 -i audio gameplay.mkv
+//! ```
+//!
+//! [`ffmpeg_input_args`] gives the arguments for the console's region and sample rate.
+//! The picture has a constant frame rate, the nominal one of [`Region::frame_rate`](crate::clock::Region::frame_rate),
+//! and the audio keeps in sync with it since the APU is sampled at exactly its sample rate.
+
+use std::io::{self, Write};
+
+use crate::nes::{Frame, Nes};
+
+#[cfg(test)]
+mod tests;
+
+pub struct RawRecorder<V, A> {
+    video: V,
+    audio: A,
+    /// Buffers reused by every frame
+    rgb: Vec<u8>,
+    samples: Vec<u8>,
+    frames: u64,
+}
+
+impl<V: Write, A: Write> RawRecorder<V, A> {
+    pub fn new(video: V, audio: A) -> Self {
+        Self {
+            video,
+            audio,
+            rgb: Vec::with_capacity(Frame::WIDTH * Frame::HEIGHT * 3),
+            samples: Vec::new(),
+            frames: 0,
+        }
+    }
+
+    /// Write the audio of a frame, and its picture unless it was cut short
+    ///
+    /// A frame stopped by a breakpoint only writes its audio, so every picture
+    /// is written once, when running again finishes it.
+    pub fn record(&mut self, frame: &Frame) -> io::Result<()> {
+        self.samples.clear();
+        for &sample in frame.audio {
+            let sample = ((sample * 2.0 - 1.0) * i16::MAX as f32) as i16;
+            self.samples.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.audio.write_all(&self.samples)?;
+
+        if frame.stop.is_none() {
+            self.rgb.clear();
+            self.rgb.extend(
+                frame
+                    .video
+                    .iter()
+                    .flat_map(|&pixel| frame.palette.to_rgb(pixel)),
+            );
+            self.video.write_all(&self.rgb)?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Pictures written so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flush both writers and give them back
+    pub fn finish(mut self) -> io::Result<(V, A)> {
+        self.video.flush()?;
+        self.audio.flush()?;
+        Ok((self.video, self.audio))
+    }
+}
+
+/// The arguments that describe the two streams to ffmpeg, each followed by `-i` and its path
+pub fn ffmpeg_input_args(nes: &Nes, video: &str, audio: &str) -> Vec<String> {
+    let (frames, seconds) = nes.region().frame_rate();
+    let size = format!("{}x{}", Frame::WIDTH, Frame::HEIGHT);
+    let frame_rate = format!("{frames}/{seconds}");
+    let sample_rate = nes.apu().sample_rate().to_string();
+    [
+        "-f",
+        "rawvideo",
+        "-pixel_format",
+        "rgb24",
+        "-video_size",
+        &size,
+        "-framerate",
+        &frame_rate,
+        "-i",
+        video,
+        "-f",
+        "s16le",
+        "-ar",
+        &sample_rate,
+        "-ac",
+        "1",
+        "-i",
+        audio,
+    ]
+    .map(String::from)
+    .to_vec()
+}
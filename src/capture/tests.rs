@@ -0,0 +1,54 @@
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    debug::breakpoints::Breakpoint,
+    nes::{Frame, Nes},
+};
+
+use super::{ffmpeg_input_args, RawRecorder};
+
+/// NROM cartridge looping on a `JMP $8000`
+fn cartridge() -> Cartridge {
+    Cartridge::from_ines(&nrom_image(&[0x4C, 0x00, 0x80], 1, 0)).unwrap()
+}
+
+#[test]
+fn record() {
+    const PICTURE: usize = Frame::WIDTH * Frame::HEIGHT * 3;
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge());
+    nes.power_cycle();
+    let mut recorder = RawRecorder::new(Vec::new(), Vec::new());
+    let mut samples = 0;
+    for _ in 0..2 {
+        let frame = nes.run_frame();
+        samples += frame.audio.len();
+        recorder.record(&frame).unwrap();
+    }
+
+    // cut short by a breakpoint, the picture waits for the end of the frame
+    nes.breakpoints_mut().add(Breakpoint::new(0x8000));
+    let frame = nes.run_frame();
+    assert!(frame.stop.is_some());
+    samples += frame.audio.len();
+    recorder.record(&frame).unwrap();
+    assert_eq!(recorder.frames(), 2);
+    nes.breakpoints_mut().clear();
+    let frame = nes.run_frame();
+    samples += frame.audio.len();
+    recorder.record(&frame).unwrap();
+    assert_eq!(recorder.frames(), 3);
+
+    let (video, audio) = recorder.finish().unwrap();
+    assert_eq!(video.len(), PICTURE * 3);
+    assert_eq!(audio.len(), samples * 2);
+}
+
+#[test]
+fn ffmpeg() {
+    let args = ffmpeg_input_args(&Nes::new(), "video", "audio").join(" ");
+    assert_eq!(
+        args,
+        "-f rawvideo -pixel_format rgb24 -video_size 256x240 -framerate 21477272/357368 -i video \
+         -f s16le -ar 44100 -ac 1 -i audio"
+    );
+}
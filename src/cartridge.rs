@@ -0,0 +1,672 @@
+//! Game cartridges and the ROM image formats they are loaded from
+//!
+//! The cartridge sits on both the CPU and the PPU buses, the CPU sees the PRG memory
+//! and the PPU sees the CHR (pattern table) memory.
+//! Only the NROM board (mapper 0), the Famicom Disk System, the VS System's mapper 99,
+//! and NSF music files are currently emulated.
+
+use alloc::{boxed::Box, string::String, vec};
+use core::fmt::{Debug, Display, Formatter};
+
+#[cfg(feature = "zip")]
+use crate::zip::{self, ZipError};
+use crate::{
+    clock::Region,
+    debug::cdl::{CdlError, ChrUsage, CodeDataLog, PrgUsage},
+    logging,
+    memory::ram::Ram,
+    ppu,
+    state::{State, StateError, StateReader, StateWriter},
+};
+use game_genie::{GameGenie, GameGenieCode};
+
+pub mod database;
+pub mod fds;
+pub mod game_genie;
+pub mod ines;
+pub mod nsf;
+pub mod patch;
+#[cfg(test)]
+pub(crate) mod tests;
+pub mod unif;
+pub mod vs_system;
+
+/// Size of a single CHR bank, and the amount of CHR RAM given to boards without CHR ROM
+const CHR_BANK_SIZE: usize = 0x2000;
+/// Size of the $6000-$7FFF area where PRG RAM is mapped
+const PRG_RAM_WINDOW_SIZE: usize = 0x2000;
+
+/// Nametable arrangement selected by the cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    /// The cartridge has an extra 2KB of VRAM for the second pair of nametables,
+    /// so all four nametables are unique
+    FourScreen,
+    /// All nametables map to the first 1KB of VRAM
+    SingleScreenLower,
+    /// All nametables map to the second 1KB of VRAM
+    SingleScreenUpper,
+}
+
+/// Pattern table memory on the cartridge
+#[derive(Clone)]
+enum ChrMemory {
+    Rom(Box<[u8]>),
+    /// Boards without CHR ROM have writable pattern memory that the game fills in itself
+    Ram(Box<[u8]>),
+}
+
+impl ChrMemory {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            ChrMemory::Rom(buf) | ChrMemory::Ram(buf) => buf,
+        }
+    }
+}
+
+/// Board specific logic
+#[derive(Clone)]
+enum Hardware {
+    Nrom,
+    Fds(Box<fds::FdsAdapter>),
+    Nsf(Box<nsf::NsfPlayer>),
+    Vs(Box<vs_system::VsSystem>),
+}
+
+/// Errors that can happen when loading a ROM image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file doesn't start with a recognized header
+    InvalidHeader,
+    /// The file is shorter than what its header claims
+    Truncated,
+    /// The board used by the cartridge isn't emulated
+    UnsupportedMapper(u16),
+    /// The UNIF board name doesn't correspond to any known mapper
+    UnknownBoard(String),
+    /// The FDS BIOS image doesn't have the right size
+    InvalidBios,
+    /// A Famicom Disk System image was loaded without the BIOS
+    MissingBios,
+    /// The file couldn't be read
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind),
+    /// The ZIP archive couldn't be read
+    #[cfg(feature = "zip")]
+    Archive(ZipError),
+    /// The ZIP archive has this many ROM images instead of one
+    #[cfg(feature = "zip")]
+    ArchiveRoms(usize),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoadError::InvalidHeader => write!(f, "invalid ROM header"),
+            LoadError::Truncated => write!(f, "ROM image is shorter than its header specifies"),
+            LoadError::UnsupportedMapper(mapper) => write!(f, "unsupported mapper {mapper}"),
+            LoadError::UnknownBoard(name) => write!(f, "unknown board \"{name}\""),
+            LoadError::InvalidBios => write!(f, "invalid FDS BIOS image"),
+            LoadError::MissingBios => write!(f, "FDS images need the FDS BIOS"),
+            #[cfg(feature = "std")]
+            LoadError::Io(kind) => write!(f, "couldn't read the ROM: {kind}"),
+            #[cfg(feature = "zip")]
+            LoadError::Archive(error) => write!(f, "{error}"),
+            #[cfg(feature = "zip")]
+            LoadError::ArchiveRoms(0) => write!(f, "no ROM in the ZIP archive"),
+            #[cfg(feature = "zip")]
+            LoadError::ArchiveRoms(count) => write!(f, "{count} ROMs in the ZIP archive"),
+        }
+    }
+}
+
+impl core::error::Error for LoadError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for LoadError {
+    fn from(error: std::io::Error) -> Self {
+        LoadError::Io(error.kind())
+    }
+}
+
+#[cfg(feature = "zip")]
+impl From<ZipError> for LoadError {
+    fn from(error: ZipError) -> Self {
+        LoadError::Archive(error)
+    }
+}
+
+/// Extensions of the files that are loaded from ZIP archives, in lowercase
+#[cfg(feature = "zip")]
+const ROM_EXTENSIONS: [&[u8]; 5] = [b".nes", b".unf", b".unif", b".nsf", b".fds"];
+
+/// The ROM image in a ZIP archive, which has to have exactly one
+///
+/// The other files, like readmes, are ignored.
+#[cfg(feature = "zip")]
+pub fn unzip_rom(archive: &[u8]) -> Result<alloc::vec::Vec<u8>, LoadError> {
+    let files = zip::files(archive)?;
+    let mut roms = files.iter().filter(|file| {
+        let name = file.name.to_ascii_lowercase();
+        ROM_EXTENSIONS
+            .iter()
+            .any(|extension| name.ends_with(extension))
+    });
+    match (roms.next(), roms.count()) {
+        (Some(rom), 0) => Ok(rom.read()?),
+        (None, _) => Err(LoadError::ArchiveRoms(0)),
+        (Some(_), others) => Err(LoadError::ArchiveRoms(others + 1)),
+    }
+}
+
+/// Description of the cartridge hardware, independent of the file format it was loaded from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoardInfo {
+    /// iNES mapper number
+    pub mapper: u16,
+    pub mirroring: Mirroring,
+    /// Size of the RAM mapped at $6000-$7FFF
+    pub prg_ram_size: usize,
+    /// Size of the CHR RAM, only used when there is no CHR ROM
+    pub chr_ram_size: usize,
+    /// The PRG RAM is battery backed and should be saved between sessions
+    pub battery: bool,
+    /// Console the game was made for, `None` if it runs on any or it's unknown
+    pub region: Option<Region>,
+    /// The game is for the VS System, which has this PPU
+    pub vs_ppu: Option<ppu::Variant>,
+}
+
+#[derive(Clone)]
+pub struct Cartridge {
+    prg_rom: Box<[u8]>,
+    prg_ram: Box<[u8]>,
+    chr: ChrMemory,
+    /// Extra nametable memory for four-screen boards
+    nametable_ram: Option<Ram>,
+    board: BoardInfo,
+    hardware: Hardware,
+    /// CRC32 of the ROM contents, identifies the game
+    crc32: u32,
+    game_genie: GameGenie,
+    code_data_log: Option<Box<CodeDataLog>>,
+}
+
+impl Cartridge {
+    /// Build a cartridge from its memory contents
+    ///
+    /// Empty `chr_rom` means the board has CHR RAM instead.
+    fn new(board: BoardInfo, prg_rom: Box<[u8]>, chr_rom: Box<[u8]>) -> Result<Self, LoadError> {
+        if board.mapper != 0 && board.mapper != vs_system::VS_MAPPER {
+            logging::warn!("unsupported mapper {}", board.mapper);
+            return Err(LoadError::UnsupportedMapper(board.mapper));
+        }
+        logging::debug!(
+            "mapper {}, {:?} mirroring, {}KB PRG ROM, {}KB CHR ROM",
+            board.mapper,
+            board.mirroring,
+            prg_rom.len() / 1024,
+            chr_rom.len() / 1024
+        );
+        if prg_rom.is_empty() {
+            return Err(LoadError::Truncated);
+        }
+
+        let crc32 = database::crc32_update(database::crc32(&prg_rom), &chr_rom);
+        let chr = if chr_rom.is_empty() {
+            // NES 2.0 headers can say there's no CHR RAM either, but every board has pattern tables
+            let size = match board.chr_ram_size {
+                0 => CHR_BANK_SIZE,
+                size => size,
+            };
+            ChrMemory::Ram(vec![0; size].into_boxed_slice())
+        } else {
+            ChrMemory::Rom(chr_rom)
+        };
+
+        let nametable_ram = (board.mirroring == Mirroring::FourScreen).then(Ram::new);
+        // the VS System's RAM is on the mainboard, in place of the cartridge's
+        let (hardware, prg_ram_size) = if board.vs_ppu.is_some() {
+            let vs = Box::new(vs_system::VsSystem::new());
+            (Hardware::Vs(vs), vs_system::RAM_SIZE)
+        } else {
+            (Hardware::Nrom, board.prg_ram_size)
+        };
+
+        Ok(Self {
+            prg_rom,
+            prg_ram: vec![0; prg_ram_size].into_boxed_slice(),
+            chr,
+            nametable_ram,
+            board,
+            hardware,
+            crc32,
+            game_genie: GameGenie::default(),
+            code_data_log: None,
+        })
+    }
+
+    /// Copy a 512 byte trainer to $7000-$71FF
+    ///
+    /// Trainers are usually patches that were needed for the game to run on various copier devices
+    fn load_trainer(&mut self, trainer: &[u8]) {
+        // boards with trainers always have at least 8KB of PRG RAM
+        if self.prg_ram.len() < PRG_RAM_WINDOW_SIZE {
+            let mut prg_ram = vec![0; PRG_RAM_WINDOW_SIZE];
+            prg_ram[..self.prg_ram.len()].copy_from_slice(&self.prg_ram);
+            self.prg_ram = prg_ram.into_boxed_slice();
+        }
+        self.prg_ram[0x1000..0x1000 + trainer.len()].copy_from_slice(trainer);
+    }
+
+    /// Load a ROM image in any of the supported formats, recognized by its header
+    ///
+    /// `fds_bios` is the BIOS for Famicom Disk System images, the other formats don't need it.
+    /// With the `zip` feature, the image can also be in a ZIP archive.
+    pub fn from_bytes(bytes: &[u8], fds_bios: Option<&[u8]>) -> Result<Self, LoadError> {
+        #[cfg(feature = "zip")]
+        if zip::is_zip(bytes) {
+            // an archive inside the archive isn't looked into
+            return Self::from_unzipped_bytes(&unzip_rom(bytes)?, fds_bios);
+        }
+        Self::from_unzipped_bytes(bytes, fds_bios)
+    }
+
+    fn from_unzipped_bytes(bytes: &[u8], fds_bios: Option<&[u8]>) -> Result<Self, LoadError> {
+        if fds::is_disk_image(bytes) {
+            let disk = fds::DiskImage::from_fds(bytes)?;
+            return Self::from_fds(fds_bios.ok_or(LoadError::MissingBios)?, disk);
+        }
+        [Self::from_ines, Self::from_unif, Self::from_nsf]
+            .into_iter()
+            .map(|load| load(bytes))
+            .find(|cartridge| !matches!(cartridge, Err(LoadError::InvalidHeader)))
+            .unwrap_or(Err(LoadError::InvalidHeader))
+    }
+
+    /// Read a ROM image in any of the supported formats, see [`Cartridge::from_bytes`]
+    #[cfg(feature = "std")]
+    pub fn from_reader(
+        mut reader: impl std::io::Read,
+        fds_bios: Option<&[u8]>,
+    ) -> Result<Self, LoadError> {
+        let mut bytes = alloc::vec::Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Self::from_bytes(&bytes, fds_bios)
+    }
+
+    pub fn board(&self) -> &BoardInfo {
+        &self.board
+    }
+
+    /// Current nametable arrangement, some boards can change it at runtime
+    pub fn mirroring(&self) -> Mirroring {
+        match &self.hardware {
+            Hardware::Fds(fds) => fds.mirroring(),
+            Hardware::Nrom | Hardware::Nsf(_) | Hardware::Vs(_) => self.board.mirroring,
+        }
+    }
+
+    /// Whether the cartridge is asserting the IRQ line
+    pub fn irq(&self) -> bool {
+        match &self.hardware {
+            Hardware::Fds(fds) => fds.irq(),
+            Hardware::Nsf(nsf) => nsf.irq(),
+            Hardware::Nrom | Hardware::Vs(_) => false,
+        }
+    }
+
+    /// Advance the cartridge hardware by one CPU cycle
+    pub fn tick(&mut self) {
+        match &mut self.hardware {
+            Hardware::Fds(fds) => fds.tick(),
+            Hardware::Nsf(nsf) => nsf.tick(),
+            Hardware::Vs(vs) => vs.tick(),
+            Hardware::Nrom => {}
+        }
+    }
+
+    /// CRC32 of the PRG and CHR ROM, used as the key in the [`database::RomDatabase`]
+    ///
+    /// For NSF files and disk images this is the CRC32 of the file contents instead
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// iNES mapper number of the board
+    pub fn mapper(&self) -> u16 {
+        self.board.mapper
+    }
+
+    /// The RAM mapped at $6000-$7FFF, which holds the saves when it's battery backed
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    /// See [`Cartridge::prg_ram`], for restoring saves
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    /// Whether the pattern tables are writable RAM rather than ROM
+    pub fn has_chr_ram(&self) -> bool {
+        matches!(self.chr, ChrMemory::Ram(_))
+    }
+
+    /// Add a Game Genie code, it starts out enabled
+    pub fn add_game_genie_code(&mut self, code: GameGenieCode) {
+        self.game_genie.add(code);
+    }
+
+    /// Returns `false` if the code wasn't added
+    pub fn remove_game_genie_code(&mut self, code: GameGenieCode) -> bool {
+        self.game_genie.remove(code)
+    }
+
+    /// Returns `false` if the code wasn't added
+    pub fn set_game_genie_code_enabled(&mut self, code: GameGenieCode, enabled: bool) -> bool {
+        self.game_genie.set_enabled(code, enabled)
+    }
+
+    /// All added Game Genie codes and whether they are enabled
+    pub fn game_genie_codes(&self) -> impl Iterator<Item = (GameGenieCode, bool)> + '_ {
+        self.game_genie.codes()
+    }
+
+    /// Start logging how the ROM is used, or continue an existing log
+    ///
+    /// Fails if the log was made for a different amount of PRG and CHR ROM
+    pub fn start_code_data_log(&mut self, log: Option<CodeDataLog>) -> Result<(), CdlError> {
+        let chr_size = self.chr_rom_size();
+        let log = log.unwrap_or_else(|| CodeDataLog::new(self.prg_rom.len(), chr_size));
+        let expected = self.prg_rom.len() + chr_size;
+        if log.prg_size() != self.prg_rom.len() || log.chr_size() != chr_size {
+            return Err(CdlError::WrongSize {
+                expected,
+                found: log.prg_size() + log.chr_size(),
+            });
+        }
+        self.code_data_log = Some(Box::new(log));
+        Ok(())
+    }
+
+    /// Stop logging, returning what was logged
+    pub fn stop_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.code_data_log.take().map(|log| *log)
+    }
+
+    pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+        self.code_data_log.as_deref()
+    }
+
+    /// CHR RAM isn't logged
+    fn chr_rom_size(&self) -> usize {
+        match &self.chr {
+            ChrMemory::Rom(rom) => rom.len(),
+            ChrMemory::Ram(_) => 0,
+        }
+    }
+
+    /// Offset in the PRG ROM that a CPU address reads, if it's mapped to ROM
+    pub fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        match &self.hardware {
+            Hardware::Fds(_) => (address >= 0xE000).then(|| address as usize - 0xE000),
+            // the vectors point to the driver
+            Hardware::Nsf(_) if address >= 0xFFFA => None,
+            Hardware::Nsf(nsf) => {
+                (address >= 0x8000).then(|| nsf.prg_offset(address) % self.prg_rom.len())
+            }
+            Hardware::Nrom => {
+                (address >= 0x8000).then(|| (address as usize - 0x8000) % self.prg_rom.len())
+            }
+            Hardware::Vs(vs) => {
+                (address >= 0x8000).then(|| vs.prg_offset(address, self.prg_rom.len()))
+            }
+        }
+    }
+
+    /// Note a use of the byte at a CPU address in the code/data log, if there is one
+    #[inline]
+    pub(crate) fn log_prg(&mut self, address: u16, usage: PrgUsage) {
+        if self.code_data_log.is_none() {
+            return;
+        }
+        if let Some(offset) = self.prg_rom_offset(address) {
+            if let Some(log) = &mut self.code_data_log {
+                log.log_prg(offset, address, usage);
+            }
+        }
+    }
+
+    /// Note a use of the byte at a pattern table address in the code/data log, if there is one
+    #[inline]
+    pub(crate) fn log_chr(&mut self, address: u16, usage: ChrUsage) {
+        let chr_size = self.chr_rom_size();
+        if let (Some(_), 0x0000..0x2000, 1..) = (&self.code_data_log, address, chr_size) {
+            let offset = self.chr_offset(address, chr_size);
+            if let Some(log) = &mut self.code_data_log {
+                log.log_chr(offset, usage);
+            }
+        }
+    }
+
+    /// Read a byte from the CPU address space ($4020-$FFFF)
+    ///
+    /// Returns `None` if the cartridge doesn't respond to the address (open bus)
+    #[must_use]
+    pub fn cpu_load(&mut self, address: u16) -> Option<u8> {
+        let value = self.load_unpatched(address);
+        if self.game_genie.is_empty() || address < 0x8000 {
+            return value;
+        }
+        value.map(|value| self.game_genie.patch(address, value))
+    }
+
+    /// Read a byte from the CPU address space without side effects, for debuggers
+    ///
+    /// Returns `None` for registers, even if reading them would normally return a value.
+    #[must_use]
+    pub fn cpu_peek(&self, address: u16) -> Option<u8> {
+        let value = self.peek_unpatched(address);
+        if self.game_genie.is_empty() || address < 0x8000 {
+            return value;
+        }
+        value.map(|value| self.game_genie.patch(address, value))
+    }
+
+    fn load_unpatched(&mut self, address: u16) -> Option<u8> {
+        match &mut self.hardware {
+            Hardware::Fds(fds) if (0x4020..0x6000).contains(&address) => fds.read_register(address),
+            Hardware::Nsf(nsf) if !(0x6000..0x8000).contains(&address) => {
+                nsf.load(&self.prg_rom, address)
+            }
+            _ => self.peek_unpatched(address),
+        }
+    }
+
+    fn peek_unpatched(&self, address: u16) -> Option<u8> {
+        match &self.hardware {
+            Hardware::Fds(_) => {
+                return match address {
+                    0x6000..0xE000 => Some(self.prg_ram[address as usize - 0x6000]),
+                    0xE000..=0xFFFF => Some(self.prg_rom[address as usize - 0xE000]),
+                    _ => None,
+                };
+            }
+            Hardware::Nsf(nsf) if !(0x6000..0x8000).contains(&address) => {
+                return nsf.peek(&self.prg_rom, address);
+            }
+            _ => {}
+        }
+
+        match address {
+            0x6000..0x8000 if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[(address as usize - 0x6000) % self.prg_ram.len()])
+            }
+            // NROM-128 mirrors its single 16KB bank into both halves
+            0x8000..=0xFFFF => self
+                .prg_rom_offset(address)
+                .map(|offset| self.prg_rom[offset]),
+            _ => None,
+        }
+    }
+
+    /// Write a byte to the CPU address space ($4020-$FFFF)
+    pub fn cpu_store(&mut self, address: u16, value: u8) {
+        match &mut self.hardware {
+            Hardware::Fds(fds) => {
+                match address {
+                    0x4020..0x6000 => fds.write_register(address, value),
+                    0x6000..0xE000 => self.prg_ram[address as usize - 0x6000] = value,
+                    _ => {}
+                }
+                return;
+            }
+            Hardware::Nsf(nsf) => nsf.store(address, value),
+            Hardware::Vs(vs) if address == 0x4020 => vs.write_coin_counter(value),
+            Hardware::Nrom | Hardware::Vs(_) => {}
+        }
+
+        match address {
+            0x6000..0x8000 if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(address as usize - 0x6000) % len] = value;
+            }
+            // NROM has no registers and writes to ROM do nothing
+            0x8000.. => {
+                logging::debug!("ignored write of ${value:02X} to ROM at ${address:04X}");
+            }
+            _ => {}
+        }
+    }
+
+    /// Select where a nametable access ($2000-$3EFF in the PPU address space) goes
+    ///
+    /// Returns the address within the console's 2KB of VRAM,
+    /// or `None` if the cartridge handles the access itself through [`Cartridge::ppu_load`] and [`Cartridge::ppu_store`]
+    #[must_use]
+    pub fn nametable_address(&self, address: u16) -> Option<u16> {
+        let offset = address & 0x3FF;
+        let nametable = (address >> 10) & 0b11;
+
+        match self.mirroring() {
+            Mirroring::Horizontal => Some((nametable >> 1) << 10 | offset),
+            Mirroring::Vertical => Some((nametable & 1) << 10 | offset),
+            // First 2 nametables are still in the console's VRAM
+            Mirroring::FourScreen => (nametable < 2).then_some(nametable << 10 | offset),
+            Mirroring::SingleScreenLower => Some(offset),
+            Mirroring::SingleScreenUpper => Some(0x400 | offset),
+        }
+    }
+
+    /// Read a byte from the PPU address space
+    ///
+    /// Covers the pattern tables ($0000-$1FFF) and the nametables
+    /// for which [`Cartridge::nametable_address`] returned `None`
+    #[must_use]
+    pub fn ppu_load(&self, address: u16) -> u8 {
+        match (address, &self.nametable_ram) {
+            (0x0000..0x2000, _) => {
+                let chr = self.chr.as_slice();
+                chr[self.chr_offset(address, chr.len())]
+            }
+            (_, Some(ram)) => ram.load(address & 0x7FF),
+            // nothing drives the bus
+            (_, None) => 0,
+        }
+    }
+
+    /// Offset in the CHR memory of a pattern table address
+    fn chr_offset(&self, address: u16, chr_size: usize) -> usize {
+        match &self.hardware {
+            Hardware::Vs(vs) => vs.chr_offset(address, chr_size),
+            _ => address as usize % chr_size,
+        }
+    }
+
+    /// Write a byte to the PPU address space
+    ///
+    /// Pattern table writes only have an effect on boards with CHR RAM
+    pub fn ppu_store(&mut self, address: u16, value: u8) {
+        match (address, &mut self.chr, &mut self.nametable_ram) {
+            (0x0000..0x2000, ChrMemory::Ram(buf), _) => {
+                let offset = match &self.hardware {
+                    Hardware::Vs(vs) => vs.chr_offset(address, buf.len()),
+                    _ => address as usize % buf.len(),
+                };
+                buf[offset] = value;
+            }
+            (0x0000..0x2000, ChrMemory::Rom(_), _) => {}
+            (_, _, Some(ram)) => ram.store(address & 0x7FF, value),
+            (_, _, None) => {}
+        }
+    }
+}
+
+impl Debug for Cartridge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Cartridge")
+            .field("prg_rom_size", &self.prg_rom.len())
+            .field("chr_size", &self.chr.as_slice().len())
+            .field("chr_ram", &self.has_chr_ram())
+            .field("board", &self.board)
+            .finish()
+    }
+}
+
+impl State for Mirroring {
+    fn save_state(&self, writer: &mut StateWriter) {
+        (*self as u8).save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut mirroring = 0u8;
+        mirroring.load_state(reader)?;
+        *self = match mirroring {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            _ => return Err(StateError::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+/// ROMs and Game Genie codes aren't part of the state
+impl State for Cartridge {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.prg_ram.save_state(writer);
+        if let ChrMemory::Ram(chr_ram) = &self.chr {
+            chr_ram.save_state(writer);
+        }
+        self.nametable_ram.save_state(writer);
+        match &self.hardware {
+            Hardware::Nrom => {}
+            Hardware::Fds(fds) => fds.save_state(writer),
+            Hardware::Nsf(nsf) => nsf.save_state(writer),
+            Hardware::Vs(vs) => vs.save_state(writer),
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.prg_ram.load_state(reader)?;
+        if let ChrMemory::Ram(chr_ram) = &mut self.chr {
+            chr_ram.load_state(reader)?;
+        }
+        let has_nametable_ram = self.nametable_ram.is_some();
+        self.nametable_ram.load_state(reader)?;
+        if self.nametable_ram.is_some() != has_nametable_ram {
+            return Err(StateError::InvalidData);
+        }
+        match &mut self.hardware {
+            Hardware::Nrom => Ok(()),
+            Hardware::Fds(fds) => fds.load_state(reader),
+            Hardware::Nsf(nsf) => nsf.load_state(reader),
+            Hardware::Vs(vs) => vs.load_state(reader),
+        }
+    }
+}
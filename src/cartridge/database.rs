@@ -0,0 +1,114 @@
+//! Database of known cartridges, used to fix up broken ROM headers
+//!
+//! Many dumps in circulation have iNES headers with the wrong mapper number,
+//! a missing battery flag or the wrong mirroring.
+//! Entries are keyed by the CRC32 of the PRG ROM followed by the CHR ROM (without the header),
+//! which is the same key other emulators' databases use.
+
+use alloc::collections::BTreeMap;
+
+use super::{BoardInfo, Mirroring};
+use crate::{clock::Region, ppu};
+
+/// Reflected CRC-32 polynomial, as used by zip and PNG
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continue a CRC32 calculation over more data, start with `crc = 0`
+#[must_use]
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!crc, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+#[must_use]
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// Corrections for a single game, `None` fields are taken from the header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatabaseEntry {
+    pub mapper: Option<u16>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub prg_ram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub region: Option<Region>,
+    /// PPU of a VS System game, which iNES 1.0 headers can't tell
+    pub vs_ppu: Option<ppu::Variant>,
+}
+
+impl DatabaseEntry {
+    pub fn apply(&self, board: &mut BoardInfo) {
+        board.mapper = self.mapper.unwrap_or(board.mapper);
+        board.mirroring = self.mirroring.unwrap_or(board.mirroring);
+        board.battery = self.battery.unwrap_or(board.battery);
+        board.prg_ram_size = self.prg_ram_size.unwrap_or(board.prg_ram_size);
+        board.chr_ram_size = self.chr_ram_size.unwrap_or(board.chr_ram_size);
+        board.region = self.region.or(board.region);
+        board.vs_ppu = self.vs_ppu.or(board.vs_ppu);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    entries: BTreeMap<u32, DatabaseEntry>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the corrections for a game with the given CRC32
+    pub fn insert(&mut self, crc32: u32, entry: DatabaseEntry) {
+        self.entries.insert(crc32, entry);
+    }
+
+    pub fn remove(&mut self, crc32: u32) -> Option<DatabaseEntry> {
+        self.entries.remove(&crc32)
+    }
+
+    #[must_use]
+    pub fn get(&self, crc32: u32) -> Option<&DatabaseEntry> {
+        self.entries.get(&crc32)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl FromIterator<(u32, DatabaseEntry)> for RomDatabase {
+    fn from_iter<T: IntoIterator<Item = (u32, DatabaseEntry)>>(iter: T) -> Self {
+        Self {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
@@ -0,0 +1,606 @@
+//! Famicom Disk System
+//!
+//! The FDS is a disk drive connected through a RAM adapter that plugs into the cartridge slot.
+//! The adapter has 32KB of PRG RAM, 8KB of CHR RAM, the BIOS ROM, an IRQ timer,
+//! and the registers used to talk to the drive.
+//!
+//! Disk sides are stored as a raw byte stream, the way the drive sees them, with gaps and CRCs between blocks.
+//! The drive itself is a state machine that advances one byte every ~150 CPU cycles while the motor is on.
+//!
+//! Details at https://www.nesdev.org/wiki/Family_Computer_Disk_System and https://www.nesdev.org/wiki/FDS_file_format
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt::{Debug, Formatter};
+
+use crate::{
+    clock::Region,
+    state::{impl_state, State, StateError, StateReader, StateWriter},
+};
+
+use super::{
+    database, BoardInfo, Cartridge, ChrMemory, GameGenie, Hardware, LoadError, Mirroring,
+    CHR_BANK_SIZE,
+};
+
+pub const BIOS_SIZE: usize = 0x2000;
+const PRG_RAM_SIZE: usize = 0x8000;
+
+const FDS_MAGIC: &[u8; 4] = b"FDS\x1A";
+const FDS_HEADER_SIZE: usize = 16;
+/// Start of the disk info block that starts every side
+const DISK_INFO_MAGIC: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+/// Size of a disk side in a .fds image
+const SIDE_SIZE: usize = 65500;
+/// Size of a disk side in a .qd image, these also contain the block CRCs
+const QD_SIDE_SIZE: usize = 0x10000;
+
+/// Gap at the start of the disk, 28300 bits
+const LEAD_IN_SIZE: usize = 28300 / 8;
+/// Gap between blocks, 976 bits
+const GAP_SIZE: usize = 976 / 8;
+/// Marks the end of a gap and the start of a block
+const BLOCK_START: u8 = 0x80;
+/// The raw disk is padded to this size, so there's space for blocks written by the game
+const RAW_SIDE_SIZE: usize = LEAD_IN_SIZE + SIDE_SIZE;
+
+/// Cycles for the head to move back to the start of the disk
+const HEAD_RESET_DELAY: u32 = 50000;
+/// Cycles between two bytes passing under the head
+const BYTE_DELAY: u32 = 150;
+
+/// Mapper number used by iNES for the FDS
+const FDS_MAPPER: u16 = 20;
+
+fn update_crc(crc: &mut u16, value: u8) {
+    for bit in 0..8 {
+        let carry = *crc & 1 != 0;
+        *crc >>= 1;
+        if carry {
+            *crc ^= 0x8408;
+        }
+        if value & (1 << bit) != 0 {
+            *crc ^= 0x8000;
+        }
+    }
+}
+
+/// Length of a block, including its type byte
+///
+/// `file_size` is the size from the last file header block
+fn block_length(block_type: u8, file_size: usize) -> Option<usize> {
+    match block_type {
+        // disk info
+        1 => Some(56),
+        // file amount
+        2 => Some(2),
+        // file header
+        3 => Some(16),
+        // file data
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+/// Convert a side from a disk image file to the raw form the drive sees
+fn side_to_raw(side: &[u8], has_crc: bool) -> Box<[u8]> {
+    let mut raw = vec![0; LEAD_IN_SIZE];
+    let mut position = 0;
+    let mut file_size = 0;
+
+    while let Some(length) = side
+        .get(position)
+        .and_then(|&block_type| block_length(block_type, file_size))
+    {
+        let Some(block) = side.get(position..position + length) else {
+            break;
+        };
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+
+        let mut crc = 0;
+        for &byte in [BLOCK_START].iter().chain(block) {
+            update_crc(&mut crc, byte);
+        }
+        update_crc(&mut crc, 0);
+        update_crc(&mut crc, 0);
+
+        raw.push(BLOCK_START);
+        raw.extend_from_slice(block);
+        raw.extend(crc.to_le_bytes());
+        raw.extend([0; GAP_SIZE]);
+
+        position += length;
+        if has_crc {
+            position += 2;
+        }
+    }
+
+    raw.resize(raw.len().max(RAW_SIDE_SIZE), 0);
+    raw.into_boxed_slice()
+}
+
+/// Convert a raw side back into the .fds form, dropping the gaps and CRCs
+fn raw_to_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut position = 0;
+    let mut file_size = 0;
+
+    while let Some(start) = raw[position..].iter().position(|&b| b == BLOCK_START) {
+        position += start + 1;
+
+        let Some(length) = raw
+            .get(position)
+            .and_then(|&block_type| block_length(block_type, file_size))
+        else {
+            break;
+        };
+        let Some(block) = raw.get(position..position + length) else {
+            break;
+        };
+        if block[0] == 3 {
+            file_size = u16::from_le_bytes([block[13], block[14]]) as usize;
+        }
+
+        side.extend_from_slice(block);
+        // skip the CRC
+        position = (position + length + 2).min(raw.len());
+    }
+
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+/// Whether the data looks like a .fds file, with or without the fwNES header
+pub(super) fn is_disk_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(FDS_MAGIC) || bytes.starts_with(DISK_INFO_MAGIC)
+}
+
+/// Contents of a floppy disk, possibly modified by the game
+#[derive(Clone)]
+pub struct DiskImage {
+    sides: Vec<Box<[u8]>>,
+    modified: bool,
+}
+
+impl DiskImage {
+    /// Load a disk image from a .fds file, with or without the fwNES header
+    pub fn from_fds(bytes: &[u8]) -> Result<Self, LoadError> {
+        let bytes = match bytes.strip_prefix(FDS_MAGIC) {
+            Some(_) if bytes.len() < FDS_HEADER_SIZE => return Err(LoadError::Truncated),
+            Some(_) => &bytes[FDS_HEADER_SIZE..],
+            None => bytes,
+        };
+        Self::from_sides(bytes, SIDE_SIZE, false)
+    }
+
+    /// Load a disk image from a .qd file
+    pub fn from_qd(bytes: &[u8]) -> Result<Self, LoadError> {
+        Self::from_sides(bytes, QD_SIDE_SIZE, true)
+    }
+
+    fn from_sides(bytes: &[u8], side_size: usize, has_crc: bool) -> Result<Self, LoadError> {
+        if bytes.len() < side_size {
+            return Err(LoadError::Truncated);
+        }
+        // every side starts with the disk info block
+        if bytes.chunks(side_size).any(|side| side[0] != 1) {
+            return Err(LoadError::InvalidHeader);
+        }
+
+        let sides = bytes
+            .chunks_exact(side_size)
+            .map(|side| side_to_raw(side, has_crc))
+            .collect();
+
+        Ok(Self {
+            sides,
+            modified: false,
+        })
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    /// Whether the game has written to the disk since it was loaded
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Serialize the disk into the .fds format (without a header), including any modifications
+    pub fn to_fds(&self) -> Vec<u8> {
+        self.sides
+            .iter()
+            .flat_map(|side| raw_to_side(side))
+            .collect()
+    }
+}
+
+impl Debug for DiskImage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DiskImage")
+            .field("side_count", &self.side_count())
+            .field("modified", &self.modified)
+            .finish()
+    }
+}
+
+/// RAM adapter registers and disk drive state
+#[derive(Clone)]
+pub(super) struct FdsAdapter {
+    disk: DiskImage,
+    /// Currently inserted side
+    side: Option<usize>,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+
+    disk_registers_enabled: bool,
+    mirroring: Mirroring,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+
+    /// The head has to move back to the start of the disk before scanning
+    end_of_head: bool,
+    scanning_disk: bool,
+    gap_ended: bool,
+    transfer_complete: bool,
+    position: usize,
+    delay: u32,
+    crc: u16,
+
+    read_data: u8,
+    write_data: u8,
+    external_output: u8,
+}
+
+impl FdsAdapter {
+    fn new(disk: DiskImage) -> Self {
+        Self {
+            disk,
+            side: Some(0),
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+            disk_registers_enabled: false,
+            mirroring: Mirroring::Horizontal,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            end_of_head: true,
+            scanning_disk: false,
+            gap_ended: false,
+            transfer_complete: false,
+            position: 0,
+            delay: 0,
+            crc: 0,
+            read_data: 0,
+            write_data: 0,
+            external_output: 0,
+        }
+    }
+
+    pub(super) fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    pub(super) fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    pub(super) fn read_register(&mut self, address: u16) -> Option<u8> {
+        match address {
+            0x4030 => {
+                let mut status = self.timer_irq as u8 | (self.transfer_complete as u8) << 1;
+                // bit 6 is set when the head reaches the end of the disk
+                status |= (self.end_of_head as u8) << 6;
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                Some(status)
+            }
+            0x4031 => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                Some(self.read_data)
+            }
+            0x4032 => {
+                let no_disk = self.side.is_none();
+                let status = no_disk as u8
+                    | ((no_disk || !self.scanning_disk) as u8) << 1
+                    // write protect
+                    | (no_disk as u8) << 2;
+                Some(status)
+            }
+            // battery is always good
+            0x4033 => Some(0x80 | (self.external_output & 0x7F)),
+            _ => None,
+        }
+    }
+
+    pub(super) fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4020 => self.timer_reload = self.timer_reload & 0xFF00 | value as u16,
+            0x4021 => self.timer_reload = self.timer_reload & 0x00FF | (value as u16) << 8,
+            0x4022 => {
+                self.timer_repeat = value & 1 != 0;
+                self.timer_enabled = value & 0b10 != 0 && self.disk_registers_enabled;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_registers_enabled = value & 1 != 0;
+                if !self.disk_registers_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_registers_enabled => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_registers_enabled => {
+                self.motor_on = value & 1 != 0;
+                self.reset_transfer = value & 0b10 != 0;
+                self.read_mode = value & 0b100 != 0;
+                self.mirroring = if value & 0b1000 != 0 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                };
+                self.crc_control = value & 0b1_0000 != 0;
+                self.disk_ready = value & 0b100_0000 != 0;
+                self.disk_irq_enabled = value & 0b1000_0000 != 0;
+                self.disk_irq = false;
+            }
+            0x4026 => self.external_output = value,
+            _ => {}
+        }
+    }
+
+    /// Advance the timer and the drive by one CPU cycle
+    pub(super) fn tick(&mut self) {
+        if self.timer_enabled {
+            if self.timer_counter == 0 {
+                self.timer_irq = true;
+                self.timer_counter = self.timer_reload;
+                if !self.timer_repeat {
+                    self.timer_enabled = false;
+                }
+            } else {
+                self.timer_counter -= 1;
+            }
+        }
+
+        self.tick_drive();
+    }
+
+    fn tick_drive(&mut self) {
+        let Some(side) = self.side.filter(|_| self.motor_on) else {
+            self.end_of_head = true;
+            self.scanning_disk = false;
+            return;
+        };
+        if self.reset_transfer && !self.scanning_disk {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = HEAD_RESET_DELAY;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning_disk = true;
+        let raw = &mut self.disk.sides[side];
+        let mut raise_irq = self.disk_irq_enabled;
+
+        if self.read_mode {
+            let value = raw[self.position];
+            if !self.previous_crc_control {
+                update_crc(&mut self.crc, value);
+            }
+
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if value != 0 && !self.gap_ended {
+                // the block start mark itself isn't transferred
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = value;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+        } else {
+            let mut value = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                value = self.write_data;
+                if raise_irq {
+                    self.disk_irq = true;
+                }
+            }
+            if !self.disk_ready {
+                value = 0;
+            }
+
+            if self.crc_control {
+                if !self.previous_crc_control {
+                    update_crc(&mut self.crc, 0);
+                    update_crc(&mut self.crc, 0);
+                }
+                value = self.crc as u8;
+                self.crc >>= 8;
+            } else {
+                update_crc(&mut self.crc, value);
+            }
+
+            raw[self.position] = value;
+            self.disk.modified = true;
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+        self.position += 1;
+        if self.position >= raw.len() {
+            self.motor_on = false;
+            // the head has to go back to the start even if the motor is turned on again right away
+            self.end_of_head = true;
+            if raise_irq {
+                self.disk_irq = true;
+            }
+        } else {
+            self.delay = BYTE_DELAY;
+        }
+    }
+}
+
+impl Cartridge {
+    /// Build a Famicom Disk System from the BIOS ROM and a disk image
+    ///
+    /// The first side of the disk starts out inserted
+    pub fn from_fds(bios: &[u8], disk: DiskImage) -> Result<Self, LoadError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(LoadError::InvalidBios);
+        }
+
+        let board = BoardInfo {
+            mapper: FDS_MAPPER,
+            mirroring: Mirroring::Horizontal,
+            prg_ram_size: PRG_RAM_SIZE,
+            chr_ram_size: CHR_BANK_SIZE,
+            battery: false,
+            // the Famicom was only sold in Japan
+            region: Some(Region::Ntsc),
+            vs_ppu: None,
+        };
+
+        let crc32 = database::crc32(&disk.to_fds());
+
+        Ok(Self {
+            prg_rom: bios.into(),
+            prg_ram: vec![0; PRG_RAM_SIZE].into_boxed_slice(),
+            chr: ChrMemory::Ram(vec![0; CHR_BANK_SIZE].into_boxed_slice()),
+            nametable_ram: None,
+            board,
+            hardware: Hardware::Fds(Box::new(FdsAdapter::new(disk))),
+            crc32,
+            game_genie: GameGenie::default(),
+            code_data_log: None,
+        })
+    }
+
+    /// The disk in the drive, if this is a Famicom Disk System
+    ///
+    /// Use [`DiskImage::to_fds`] to save the disk after the game has written to it
+    pub fn fds_disk(&self) -> Option<&DiskImage> {
+        match &self.hardware {
+            Hardware::Fds(fds) => Some(&fds.disk),
+            _ => None,
+        }
+    }
+
+    /// Flip or change the disk in the drive, `None` ejects it
+    ///
+    /// Does nothing if this isn't a Famicom Disk System, or if `side` is out of range
+    pub fn insert_disk_side(&mut self, side: Option<usize>) {
+        if let Hardware::Fds(fds) = &mut self.hardware {
+            if side.is_none_or(|side| side < fds.disk.side_count()) {
+                fds.side = side;
+            }
+        }
+    }
+
+    /// Currently inserted disk side, if this is a Famicom Disk System with a disk inserted
+    pub fn disk_side(&self) -> Option<usize> {
+        match &self.hardware {
+            Hardware::Fds(fds) => fds.side,
+            _ => None,
+        }
+    }
+}
+
+/// Disks are saved whole, since games write to them
+impl State for DiskImage {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.sides.len().save_state(writer);
+        self.sides.iter().for_each(|side| side.save_state(writer));
+        self.modified.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut side_count = 0usize;
+        side_count.load_state(reader)?;
+        if side_count != self.sides.len() {
+            return Err(StateError::InvalidData);
+        }
+        self.sides
+            .iter_mut()
+            .try_for_each(|side| side.load_state(reader))?;
+        self.modified.load_state(reader)
+    }
+}
+
+impl_state!(FdsAdapter {
+    disk,
+    side,
+    timer_reload,
+    timer_counter,
+    timer_repeat,
+    timer_enabled,
+    timer_irq,
+    disk_registers_enabled,
+    mirroring,
+    motor_on,
+    reset_transfer,
+    read_mode,
+    crc_control,
+    previous_crc_control,
+    disk_ready,
+    disk_irq_enabled,
+    disk_irq,
+    end_of_head,
+    scanning_disk,
+    gap_ended,
+    transfer_complete,
+    position,
+    delay,
+    crc,
+    read_data,
+    write_data,
+    external_output,
+});
@@ -0,0 +1,131 @@
+//! Game Genie cheat codes
+//!
+//! The Game Genie sits between the console and the cartridge and replaces
+//! the bytes read from specific PRG ROM addresses.
+//! 8 letter codes also have a compare value, the byte is only replaced if the ROM contains the compare value,
+//! which makes them work with bankswitched ROMs.
+//!
+//! Code format is described at https://tuxnes.sourceforge.net/gamegenie.html
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GameGenieCode {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameGenieError {
+    /// Codes are either 6 or 8 letters long
+    InvalidLength,
+    InvalidLetter(char),
+}
+
+impl Display for GameGenieError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            GameGenieError::InvalidLength => write!(f, "Game Genie codes must be 6 or 8 letters"),
+            GameGenieError::InvalidLetter(c) => write!(f, "invalid Game Genie letter '{c}'"),
+        }
+    }
+}
+
+impl core::error::Error for GameGenieError {}
+
+impl FromStr for GameGenieCode {
+    type Err = GameGenieError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let mut n = [0u8; 8];
+        let mut len = 0;
+        for c in code.chars() {
+            if len == n.len() {
+                return Err(GameGenieError::InvalidLength);
+            }
+            let letter = c.to_ascii_uppercase() as u8;
+            n[len] = LETTERS
+                .iter()
+                .position(|&l| l == letter)
+                .ok_or(GameGenieError::InvalidLetter(c))? as u8;
+            len += 1;
+        }
+
+        let address = 0x8000
+            | ((n[3] & 7) as u16) << 12
+            | ((n[5] & 7) as u16) << 8
+            | ((n[4] & 8) as u16) << 8
+            | ((n[2] & 7) as u16) << 4
+            | ((n[1] & 8) as u16) << 4
+            | (n[4] & 7) as u16
+            | (n[3] & 8) as u16;
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7);
+
+        match len {
+            6 => Ok(Self {
+                address,
+                value: value | (n[5] & 8),
+                compare: None,
+            }),
+            8 => Ok(Self {
+                address,
+                value: value | (n[7] & 8),
+                compare: Some((n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8)),
+            }),
+            _ => Err(GameGenieError::InvalidLength),
+        }
+    }
+}
+
+/// Set of codes applied to the cartridge
+#[derive(Debug, Clone, Default)]
+pub(super) struct GameGenie {
+    /// Codes and whether they are enabled
+    codes: Vec<(GameGenieCode, bool)>,
+}
+
+impl GameGenie {
+    pub(super) fn add(&mut self, code: GameGenieCode) {
+        if !self.codes.iter().any(|(c, _)| *c == code) {
+            self.codes.push((code, true));
+        }
+    }
+
+    pub(super) fn remove(&mut self, code: GameGenieCode) -> bool {
+        let len = self.codes.len();
+        self.codes.retain(|(c, _)| *c != code);
+        self.codes.len() != len
+    }
+
+    pub(super) fn set_enabled(&mut self, code: GameGenieCode, enabled: bool) -> bool {
+        self.codes
+            .iter_mut()
+            .find(|(c, _)| *c == code)
+            .map(|(_, e)| *e = enabled)
+            .is_some()
+    }
+
+    pub(super) fn codes(&self) -> impl Iterator<Item = (GameGenieCode, bool)> + '_ {
+        self.codes.iter().copied()
+    }
+
+    /// Apply the codes to a byte read from the cartridge
+    pub(super) fn patch(&self, address: u16, value: u8) -> u8 {
+        self.codes
+            .iter()
+            .filter(|&&(code, enabled)| enabled && code.address == address)
+            .find(|(code, _)| code.compare.is_none_or(|compare| compare == value))
+            .map_or(value, |(code, _)| code.value)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+}
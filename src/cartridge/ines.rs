@@ -0,0 +1,151 @@
+//! Parser for the iNES and NES 2.0 ROM formats
+//!
+//! The format is described at https://www.nesdev.org/wiki/INES and https://www.nesdev.org/wiki/NES_2.0
+
+use crate::{clock::Region, ppu};
+
+use super::{
+    database::{crc32, RomDatabase},
+    vs_system::{self, VS_MAPPER},
+    BoardInfo, Cartridge, LoadError, Mirroring, CHR_BANK_SIZE, PRG_RAM_WINDOW_SIZE,
+};
+
+const MAGIC: &[u8; 4] = b"NES\x1A";
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Information contained in the 16 byte header at the start of an iNES file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub prg_rom_size: usize,
+    /// 0 means that the board uses CHR RAM
+    pub chr_rom_size: usize,
+    /// There is a 512 byte trainer between the header and the PRG ROM
+    pub has_trainer: bool,
+    pub is_nes2: bool,
+    pub board: BoardInfo,
+}
+
+/// Decode the NES 2.0 RAM size shift count, 0 means there is no RAM
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+impl Header {
+    pub fn parse(bytes: &[u8]) -> Result<Self, LoadError> {
+        let Some(header) = bytes.get(..HEADER_SIZE) else {
+            return Err(LoadError::InvalidHeader);
+        };
+        if &header[..4] != MAGIC {
+            return Err(LoadError::InvalidHeader);
+        }
+
+        let flags6 = header[6];
+        let flags7 = header[7];
+        let is_nes2 = flags7 & 0x0C == 0x08;
+
+        let mut prg_banks = header[4] as usize;
+        let mut chr_banks = header[5] as usize;
+        let mut mapper = (flags6 >> 4) as u16 | (flags7 & 0xF0) as u16;
+        // iNES 1.0 didn't specify the RAM sizes, 8KB is what most boards have
+        let mut chr_ram_size = CHR_BANK_SIZE;
+        let mut prg_ram_size = PRG_RAM_WINDOW_SIZE;
+        // iNES 1.0 has a TV system bit that's almost never set, so it only tells PAL games apart
+        let mut region = (header[9] & 1 != 0).then_some(Region::Pal);
+        // iNES 1.0 doesn't say which PPU a VS game needs, the 2C03 has the colors most of them expect
+        let mut is_vs = flags7 & 1 != 0;
+        let mut vs_ppu = ppu::Variant::Ricoh2C03;
+
+        if is_nes2 {
+            mapper |= ((header[8] & 0x0F) as u16) << 8;
+            prg_banks |= ((header[9] & 0x0F) as usize) << 8;
+            chr_banks |= ((header[9] >> 4) as usize) << 8;
+            prg_ram_size = nes2_ram_size(header[10] & 0x0F) + nes2_ram_size(header[10] >> 4);
+            chr_ram_size = nes2_ram_size(header[11] & 0x0F) + nes2_ram_size(header[11] >> 4);
+            region = match header[12] & 3 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                // 2 means it works on both
+                2 => None,
+                _ => Some(Region::Dendy),
+            };
+            is_vs = flags7 & 3 == 1;
+            vs_ppu = vs_system::nes2_ppu(header[13] & 0x0F);
+        }
+
+        let mirroring = if flags6 & 0b1000 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 1 == 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+
+        Ok(Self {
+            prg_rom_size: prg_banks * PRG_BANK_SIZE,
+            chr_rom_size: chr_banks * CHR_BANK_SIZE,
+            has_trainer: flags6 & 0b100 != 0,
+            is_nes2,
+            board: BoardInfo {
+                mapper,
+                mirroring,
+                prg_ram_size,
+                chr_ram_size,
+                battery: flags6 & 0b10 != 0,
+                region,
+                vs_ppu: (is_vs || mapper == VS_MAPPER).then_some(vs_ppu),
+            },
+        })
+    }
+}
+
+impl Cartridge {
+    /// Load a cartridge from the contents of an iNES (.nes) file
+    pub fn from_ines(bytes: &[u8]) -> Result<Self, LoadError> {
+        Self::load_ines(bytes, None)
+    }
+
+    /// Load a cartridge from the contents of an iNES (.nes) file,
+    /// correcting the header with an entry from the database if there's one for this game
+    pub fn from_ines_with_database(
+        bytes: &[u8],
+        database: &RomDatabase,
+    ) -> Result<Self, LoadError> {
+        Self::load_ines(bytes, Some(database))
+    }
+
+    fn load_ines(bytes: &[u8], database: Option<&RomDatabase>) -> Result<Self, LoadError> {
+        let mut header = Header::parse(bytes)?;
+
+        let trainer_start = HEADER_SIZE;
+        let prg_start = if header.has_trainer {
+            trainer_start + TRAINER_SIZE
+        } else {
+            trainer_start
+        };
+        let chr_start = prg_start + header.prg_rom_size;
+        let chr_end = chr_start + header.chr_rom_size;
+        if bytes.len() < chr_end {
+            return Err(LoadError::Truncated);
+        }
+
+        if let Some(entry) = database.and_then(|db| db.get(crc32(&bytes[prg_start..chr_end]))) {
+            entry.apply(&mut header.board);
+        }
+
+        let prg_rom = bytes[prg_start..chr_start].into();
+        let chr_rom = bytes[chr_start..chr_end].into();
+
+        let mut cartridge = Cartridge::new(header.board, prg_rom, chr_rom)?;
+        if header.has_trainer {
+            cartridge.load_trainer(&bytes[trainer_start..prg_start]);
+        }
+
+        Ok(cartridge)
+    }
+}
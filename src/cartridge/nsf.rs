@@ -0,0 +1,457 @@
+//! NSF and NSFe music files
+//!
+//! An NSF file is the sound engine and music data ripped out of a game,
+//! played back by calling the `init` routine once per track and then the `play` routine at a fixed rate.
+//!
+//! This is emulated as a cartridge with a small built-in driver program that does the calling:
+//! it clears the RAM, silences the APU, calls `init` with the selected track,
+//! and then calls `play` from an IRQ handler raised by a timer running at the rate from the header.
+//!
+//! Formats are described at https://www.nesdev.org/wiki/NSF and https://www.nesdev.org/wiki/NSFe
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+use crate::{clock::Region, state::impl_state};
+
+use super::{
+    database, BoardInfo, Cartridge, ChrMemory, GameGenie, Hardware, LoadError, Mirroring,
+    CHR_BANK_SIZE,
+};
+
+const NSF_MAGIC: &[u8; 5] = b"NESM\x1A";
+const NSFE_MAGIC: &[u8; 4] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// Mapper 31 uses the same 4KB bankswitching scheme as NSF files
+const NSF_MAPPER: u16 = 31;
+
+const NTSC_CPU_CLOCK: u64 = 1_789_773;
+const PAL_CPU_CLOCK: u64 = 1_662_607;
+
+const DRIVER_ADDRESS: u16 = 0x4100;
+/// Reads the selected track
+const TRACK_REGISTER: u16 = 0x4180;
+/// Reads 0 for NTSC and 1 for PAL
+const REGION_REGISTER: u16 = 0x4181;
+/// Reading it acknowledges the play timer IRQ
+const ACKNOWLEDGE_REGISTER: u16 = 0x4182;
+
+#[rustfmt::skip]
+const DRIVER: [u8; 0x52] = [
+    // reset: $4100
+    0x78,               // SEI
+    0xD8,               // CLD
+    0xA2, 0xFF,         // LDX #$FF
+    0x9A,               // TXS
+    0xE8,               // INX
+    0x8A,               // TXA
+    // clear RAM: $4107
+    0x9D, 0x00, 0x00,   // STA $0000,X
+    0x9D, 0x00, 0x01,   // STA $0100,X
+    0x9D, 0x00, 0x02,   // STA $0200,X
+    0x9D, 0x00, 0x03,   // STA $0300,X
+    0x9D, 0x00, 0x04,   // STA $0400,X
+    0x9D, 0x00, 0x05,   // STA $0500,X
+    0x9D, 0x00, 0x06,   // STA $0600,X
+    0x9D, 0x00, 0x07,   // STA $0700,X
+    0xE8,               // INX
+    0xD0, 0xE5,         // BNE $4107
+    // silence the APU
+    0xA2, 0x13,         // LDX #$13
+    0x9D, 0x00, 0x40,   // STA $4000,X
+    0xCA,               // DEX
+    0x10, 0xFA,         // BPL $4124
+    0xA9, 0x0F,         // LDA #$0F
+    0x8D, 0x15, 0x40,   // STA $4015
+    0xA9, 0x40,         // LDA #$40
+    0x8D, 0x17, 0x40,   // STA $4017
+    // init the track
+    0xAD, 0x80, 0x41,   // LDA $4180
+    0xAE, 0x81, 0x41,   // LDX $4181
+    0x20, 0x00, 0x00,   // JSR init
+    0x58,               // CLI
+    0x4C, 0x3E, 0x41,   // JMP $413E
+    // irq: $4141
+    0x48,               // PHA
+    0x8A,               // TXA
+    0x48,               // PHA
+    0x98,               // TYA
+    0x48,               // PHA
+    0xAD, 0x82, 0x41,   // LDA $4182
+    0x20, 0x00, 0x00,   // JSR play
+    0x68,               // PLA
+    0xA8,               // TAY
+    0x68,               // PLA
+    0xAA,               // TAX
+    0x68,               // PLA
+    // nmi: $4151
+    0x40,               // RTI
+];
+/// Offsets of the JSR operands in the driver
+const DRIVER_INIT_OPERAND: usize = 0x3B;
+const DRIVER_PLAY_OPERAND: usize = 0x4A;
+const DRIVER_IRQ_ADDRESS: u16 = 0x4141;
+const DRIVER_NMI_ADDRESS: u16 = 0x4151;
+
+/// Video standard the music was written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NsfRegion {
+    Ntsc,
+    Pal,
+    /// Plays correctly on both
+    Dual,
+}
+
+/// Metadata of an NSF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NsfInfo {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub track_count: u8,
+    /// 0-based index of the track to play first
+    pub starting_track: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    /// Microseconds between `play` calls on NTSC
+    pub ntsc_play_period: u16,
+    /// Microseconds between `play` calls on PAL
+    pub pal_play_period: u16,
+    pub region: NsfRegion,
+    /// Bitmask of the expansion audio chips the music uses, these aren't emulated
+    pub expansion_audio: u8,
+    /// Track names, only present in NSFe files
+    pub track_labels: Vec<String>,
+    /// Track lengths in milliseconds, only present in NSFe files
+    pub track_durations: Vec<Option<u32>>,
+}
+
+/// State of the NSF driver hardware
+#[derive(Debug, Clone)]
+pub(super) struct NsfPlayer {
+    info: NsfInfo,
+    driver: [u8; DRIVER.len()],
+    initial_banks: [u8; 8],
+    banks: [u8; 8],
+    track: u8,
+    timer_period: u32,
+    timer_counter: u32,
+    irq: bool,
+}
+
+impl NsfPlayer {
+    pub(super) fn irq(&self) -> bool {
+        self.irq
+    }
+
+    pub(super) fn tick(&mut self) {
+        if self.timer_counter == 0 {
+            self.timer_counter = self.timer_period;
+            self.irq = true;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn is_pal(&self) -> bool {
+        self.info.region == NsfRegion::Pal
+    }
+
+    /// Offset in the bankswitched data of a $8000-$FFFF address
+    pub(super) fn prg_offset(&self, address: u16) -> usize {
+        let bank = self.banks[(address as usize - 0x8000) / BANK_SIZE] as usize;
+        bank * BANK_SIZE + address as usize % BANK_SIZE
+    }
+
+    /// Read from the driver and the bankswitched music data at $4100-$41FF and $8000-$FFFF
+    pub(super) fn load(&mut self, prg: &[u8], address: u16) -> Option<u8> {
+        if address == ACKNOWLEDGE_REGISTER {
+            self.irq = false;
+            return Some(0);
+        }
+        self.peek(prg, address)
+    }
+
+    /// Same as [`NsfPlayer::load`], but reading the acknowledge register doesn't do anything
+    pub(super) fn peek(&self, prg: &[u8], address: u16) -> Option<u8> {
+        let vector = |address: u16, vector: u16| vector.to_le_bytes()[address as usize & 1];
+
+        match address {
+            TRACK_REGISTER => Some(self.track),
+            REGION_REGISTER => Some(self.is_pal() as u8),
+            DRIVER_ADDRESS..TRACK_REGISTER => self
+                .driver
+                .get((address - DRIVER_ADDRESS) as usize)
+                .copied(),
+            // the music data can cover the vectors, they have to point to the driver instead
+            0xFFFA..=0xFFFB => Some(vector(address, DRIVER_NMI_ADDRESS)),
+            0xFFFC..=0xFFFD => Some(vector(address, DRIVER_ADDRESS)),
+            0xFFFE..=0xFFFF => Some(vector(address, DRIVER_IRQ_ADDRESS)),
+            0x8000..=0xFFFF => Some(prg[self.prg_offset(address) % prg.len()]),
+            _ => None,
+        }
+    }
+
+    /// Write to the bank registers at $5FF8-$5FFF
+    pub(super) fn store(&mut self, address: u16, value: u8) {
+        if let 0x5FF8..=0x5FFF = address {
+            self.banks[address as usize - 0x5FF8] = value;
+        }
+    }
+}
+
+/// Read a null-terminated string
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn region_from_flags(flags: u8) -> NsfRegion {
+    match flags & 0b11 {
+        0 => NsfRegion::Ntsc,
+        1 => NsfRegion::Pal,
+        _ => NsfRegion::Dual,
+    }
+}
+
+/// Parse an NSF file into its metadata, music data and initial banks
+fn parse_nsf(bytes: &[u8]) -> Result<(NsfInfo, &[u8], [u8; 8]), LoadError> {
+    if bytes.len() < NSF_HEADER_SIZE || !bytes.starts_with(NSF_MAGIC) {
+        return Err(LoadError::InvalidHeader);
+    }
+    let header = &bytes[..NSF_HEADER_SIZE];
+
+    let info = NsfInfo {
+        title: read_string(&header[0x0E..0x2E]),
+        artist: read_string(&header[0x2E..0x4E]),
+        copyright: read_string(&header[0x4E..0x6E]),
+        track_count: header[0x06],
+        starting_track: header[0x07].saturating_sub(1),
+        load_address: read_u16(header, 0x08),
+        init_address: read_u16(header, 0x0A),
+        play_address: read_u16(header, 0x0C),
+        ntsc_play_period: read_u16(header, 0x6E),
+        pal_play_period: read_u16(header, 0x78),
+        region: region_from_flags(header[0x7A]),
+        expansion_audio: header[0x7B],
+        track_labels: Vec::new(),
+        track_durations: Vec::new(),
+    };
+    let banks = header[0x70..0x78].try_into().unwrap();
+
+    // NSF2 files can specify the data length, anything after it is metadata
+    let data_length = u32::from_le_bytes([header[0x7D], header[0x7E], header[0x7F], 0]) as usize;
+    let data = &bytes[NSF_HEADER_SIZE..];
+    let data = if header[0x05] >= 2 && data_length != 0 {
+        data.get(..data_length).ok_or(LoadError::Truncated)?
+    } else {
+        data
+    };
+
+    Ok((info, data, banks))
+}
+
+/// Parse an NSFe file into its metadata, music data and initial banks
+fn parse_nsfe(bytes: &[u8]) -> Result<(NsfInfo, &[u8], [u8; 8]), LoadError> {
+    if !bytes.starts_with(NSFE_MAGIC) {
+        return Err(LoadError::InvalidHeader);
+    }
+
+    let mut info = None;
+    let mut data = None;
+    let mut banks = [0; 8];
+    let mut rate = None;
+    let mut strings = Vec::new();
+    let mut track_labels = Vec::new();
+    let mut track_durations = Vec::new();
+
+    let mut rest = &bytes[NSFE_MAGIC.len()..];
+    loop {
+        if rest.len() < 8 {
+            return Err(LoadError::Truncated);
+        }
+        let length = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let id = &rest[4..8];
+        let chunk = rest.get(8..8 + length).ok_or(LoadError::Truncated)?;
+        rest = &rest[8 + length..];
+
+        match id {
+            b"INFO" if chunk.len() >= 9 => info = Some(chunk),
+            b"INFO" => return Err(LoadError::InvalidHeader),
+            b"DATA" => data = Some(chunk),
+            b"BANK" => {
+                let len = chunk.len().min(8);
+                banks[..len].copy_from_slice(&chunk[..len]);
+            }
+            b"RATE" if chunk.len() >= 4 => rate = Some((read_u16(chunk, 0), read_u16(chunk, 2))),
+            b"auth" => strings = chunk.split(|&b| b == 0).map(read_string).collect(),
+            b"tlbl" => track_labels = chunk.split(|&b| b == 0).map(read_string).collect(),
+            b"time" => {
+                track_durations = chunk
+                    .chunks_exact(4)
+                    .map(|time| i32::from_le_bytes(time.try_into().unwrap()))
+                    .map(|time| u32::try_from(time).ok())
+                    .collect()
+            }
+            b"NEND" => break,
+            // lowercase first letter means the chunk can be skipped
+            [first, ..] if first.is_ascii_lowercase() => {}
+            _ => return Err(LoadError::InvalidHeader),
+        }
+    }
+
+    let (Some(info_chunk), Some(data)) = (info, data) else {
+        return Err(LoadError::InvalidHeader);
+    };
+    // labels list ends with an empty string after the last terminator
+    track_labels.truncate(info_chunk.get(8).copied().unwrap_or(1) as usize);
+    let (ntsc_play_period, pal_play_period) = rate.unwrap_or((16639, 19997));
+    let string = |i: usize| strings.get(i).cloned().unwrap_or_default();
+
+    let info = NsfInfo {
+        title: string(0),
+        artist: string(1),
+        copyright: string(2),
+        track_count: info_chunk.get(8).copied().unwrap_or(1),
+        starting_track: info_chunk.get(9).copied().unwrap_or(0),
+        load_address: read_u16(info_chunk, 0),
+        init_address: read_u16(info_chunk, 2),
+        play_address: read_u16(info_chunk, 4),
+        ntsc_play_period,
+        pal_play_period,
+        region: region_from_flags(info_chunk[6]),
+        expansion_audio: info_chunk[7],
+        track_labels,
+        track_durations,
+    };
+
+    Ok((info, data, banks))
+}
+
+impl Cartridge {
+    /// Load an NSF or NSFe music file
+    ///
+    /// The console will play the starting track after a reset,
+    /// use [`Cartridge::select_nsf_track`] and reset to change it
+    pub fn from_nsf(bytes: &[u8]) -> Result<Self, LoadError> {
+        let (info, data, mut banks) = if bytes.starts_with(NSFE_MAGIC) {
+            parse_nsfe(bytes)?
+        } else {
+            parse_nsf(bytes)?
+        };
+        if info.load_address < 0x8000 || info.track_count == 0 {
+            return Err(LoadError::InvalidHeader);
+        }
+
+        let bankswitched = banks.iter().any(|&bank| bank != 0);
+        let padding = if bankswitched {
+            info.load_address as usize % BANK_SIZE
+        } else {
+            // without bankswitching the data is just placed at the load address
+            banks = [0, 1, 2, 3, 4, 5, 6, 7];
+            info.load_address as usize - 0x8000
+        };
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(data);
+        prg.resize(prg.len().next_multiple_of(BANK_SIZE).max(8 * BANK_SIZE), 0);
+
+        let mut driver = DRIVER;
+        driver[DRIVER_INIT_OPERAND..][..2].copy_from_slice(&info.init_address.to_le_bytes());
+        driver[DRIVER_PLAY_OPERAND..][..2].copy_from_slice(&info.play_address.to_le_bytes());
+
+        let (period, clock) = if info.region == NsfRegion::Pal {
+            (info.pal_play_period, PAL_CPU_CLOCK)
+        } else {
+            (info.ntsc_play_period, NTSC_CPU_CLOCK)
+        };
+        // some files leave the rate at 0, assume the usual 60Hz
+        let period = match (period, info.region) {
+            (0, NsfRegion::Pal) => 19997,
+            (0, _) => 16639,
+            (period, _) => period,
+        };
+        let timer_period = (period as u64 * clock / 1_000_000) as u32;
+
+        let board = BoardInfo {
+            mapper: NSF_MAPPER,
+            mirroring: Mirroring::Horizontal,
+            prg_ram_size: PRG_RAM_SIZE,
+            chr_ram_size: CHR_BANK_SIZE,
+            battery: false,
+            region: match info.region {
+                NsfRegion::Ntsc => Some(Region::Ntsc),
+                NsfRegion::Pal => Some(Region::Pal),
+                NsfRegion::Dual => None,
+            },
+            vs_ppu: None,
+        };
+
+        Ok(Self {
+            prg_rom: prg.into_boxed_slice(),
+            prg_ram: vec![0; PRG_RAM_SIZE].into_boxed_slice(),
+            chr: ChrMemory::Ram(vec![0; CHR_BANK_SIZE].into_boxed_slice()),
+            nametable_ram: None,
+            board,
+            hardware: Hardware::Nsf(Box::new(NsfPlayer {
+                track: info.starting_track,
+                info,
+                driver,
+                initial_banks: banks,
+                banks,
+                timer_period,
+                timer_counter: timer_period,
+                irq: false,
+            })),
+            crc32: database::crc32(bytes),
+            game_genie: GameGenie::default(),
+            code_data_log: None,
+        })
+    }
+
+    /// Metadata of the music file, if this is an NSF
+    pub fn nsf_info(&self) -> Option<&NsfInfo> {
+        match &self.hardware {
+            Hardware::Nsf(nsf) => Some(&nsf.info),
+            _ => None,
+        }
+    }
+
+    /// The 0-based index of the track that is played after a reset, if this is an NSF
+    pub fn nsf_track(&self) -> Option<u8> {
+        match &self.hardware {
+            Hardware::Nsf(nsf) => Some(nsf.track),
+            _ => None,
+        }
+    }
+
+    /// Select the track to play, starting from 0, the console has to be reset for it to start
+    ///
+    /// Resets the banks and the PRG RAM to their initial state.
+    /// Does nothing if this isn't an NSF or the track is out of range.
+    pub fn select_nsf_track(&mut self, track: u8) {
+        if let Hardware::Nsf(nsf) = &mut self.hardware {
+            if track < nsf.info.track_count {
+                nsf.track = track;
+                nsf.banks = nsf.initial_banks;
+                nsf.timer_counter = nsf.timer_period;
+                nsf.irq = false;
+                self.prg_ram.fill(0);
+            }
+        }
+    }
+}
+
+impl_state!(NsfPlayer {
+    banks,
+    track,
+    timer_period,
+    timer_counter,
+    irq,
+});
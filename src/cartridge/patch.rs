@@ -0,0 +1,259 @@
+//! IPS and BPS patches, how translations and ROM hacks are distributed
+//!
+//! Patches are applied to the whole ROM file, with its header, before it's loaded.
+//! IPS patches are a list of bytes to write at offsets, BPS patches are made of copies
+//! from the original and the patched file, with CRC32s to check they're applied to the right ROM.
+//!
+//! Details at https://zerosoft.zophar.net/ips.php and https://www.romhacking.net/documents/746/
+
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
+use super::database::crc32;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_END: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+/// The CRC32s of the source, the target and the patch at the end of a BPS patch
+const BPS_FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with the magic of IPS or BPS
+    InvalidHeader,
+    /// The patch ends in the middle of a record, or copies from outside of the files
+    Truncated,
+    /// The patch isn't for this ROM, its CRC32 isn't the one the patch was made from
+    SourceMismatch { expected: u32, actual: u32 },
+    /// The patched ROM doesn't have the CRC32 the patch expects
+    TargetMismatch,
+    /// The patch itself doesn't have the CRC32 it ends with
+    Corrupt,
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            PatchError::InvalidHeader => write!(f, "not an IPS or BPS patch"),
+            PatchError::Truncated => write!(f, "the patch is truncated"),
+            PatchError::SourceMismatch { expected, actual } => write!(
+                f,
+                "the patch is for a ROM with CRC32 {expected:08X}, not {actual:08X}"
+            ),
+            PatchError::TargetMismatch => write!(f, "the patched ROM has the wrong CRC32"),
+            PatchError::Corrupt => write!(f, "the patch is corrupt"),
+        }
+    }
+}
+
+impl core::error::Error for PatchError {}
+
+/// Apply an IPS or BPS patch, depending on how it starts
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(PatchError::InvalidHeader)
+    }
+}
+
+/// Apply an IPS patch
+///
+/// Records past the end of the ROM make it longer, and the optional size after the end marker
+/// truncates it.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut records = patch
+        .strip_prefix(IPS_MAGIC)
+        .ok_or(PatchError::InvalidHeader)?;
+    let mut take = |len: usize| -> Result<&[u8], PatchError> {
+        if records.len() < len {
+            return Err(PatchError::Truncated);
+        }
+        let (taken, rest) = records.split_at(len);
+        records = rest;
+        Ok(taken)
+    };
+    let be = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize)
+    };
+
+    let mut output = rom.to_vec();
+    loop {
+        let offset = take(3)?;
+        if offset == IPS_END {
+            break;
+        }
+        let offset = be(offset);
+        let len = be(take(2)?);
+        if len == 0 {
+            // a run of the same byte
+            let len = be(take(2)?);
+            let value = take(1)?[0];
+            grow(&mut output, offset, len).fill(value);
+        } else {
+            grow(&mut output, offset, len).copy_from_slice(take(len)?);
+        }
+    }
+    if let Ok(size) = take(3) {
+        output.truncate(be(size));
+    }
+    Ok(output)
+}
+
+/// The bytes at `offset..offset + len`, extending the ROM if it's shorter
+fn grow(output: &mut Vec<u8>, offset: usize, len: usize) -> &mut [u8] {
+    if output.len() < offset + len {
+        output.resize(offset + len, 0);
+    }
+    &mut output[offset..offset + len]
+}
+
+/// Reader of the variable length numbers of BPS
+struct BpsReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl BpsReader<'_> {
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(PatchError::Truncated)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn number(&mut self) -> Result<usize, PatchError> {
+        // every byte but the last has its top bit clear, and adds one to the next
+        // so that a number only has one encoding
+        let mut number = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let byte = self.byte()?;
+            number = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|value| value.checked_add(number))
+                .ok_or(PatchError::Truncated)?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::Truncated)?;
+            number = number.checked_add(shift).ok_or(PatchError::Truncated)?;
+        }
+    }
+
+    /// A relative offset, its lowest bit is the sign
+    fn offset(&mut self) -> Result<isize, PatchError> {
+        let number = self.number()?;
+        let magnitude = (number >> 1) as isize;
+        Ok(if number & 1 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    }
+}
+
+/// Move a copy offset by `delta` and past the `len` bytes copied, returning where the copy starts
+fn relative(offset: &mut isize, delta: isize, len: usize) -> Result<usize, PatchError> {
+    let start = offset.checked_add(delta).ok_or(PatchError::Truncated)?;
+    *offset = start
+        .checked_add_unsigned(len)
+        .ok_or(PatchError::Truncated)?;
+    usize::try_from(start).map_err(|_| PatchError::Truncated)
+}
+
+/// Apply a BPS patch, checking the CRC32s of the ROM, the patched ROM and the patch
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let body = patch
+        .strip_prefix(BPS_MAGIC)
+        .ok_or(PatchError::InvalidHeader)?;
+    let footer_start = body
+        .len()
+        .checked_sub(BPS_FOOTER_SIZE)
+        .ok_or(PatchError::Truncated)?;
+    let (body, footer) = body.split_at(footer_start);
+    let crc =
+        |index: usize| u32::from_le_bytes(footer[index * 4..index * 4 + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != crc(2) {
+        return Err(PatchError::Corrupt);
+    }
+    let actual = crc32(rom);
+    if actual != crc(0) {
+        return Err(PatchError::SourceMismatch {
+            expected: crc(0),
+            actual,
+        });
+    }
+
+    let mut reader = BpsReader { bytes: body };
+    // the source size, which the CRC32 already checked
+    reader.number()?;
+    let target_size = reader.number()?;
+    // the metadata is usually XML about the author, it's skipped
+    let metadata_size = reader.number()?;
+    reader.bytes = reader
+        .bytes
+        .get(metadata_size..)
+        .ok_or(PatchError::Truncated)?;
+
+    // the output grows with the actions, the target size is only trusted once they wrote it all
+    let mut output = Vec::new();
+    let (mut source_offset, mut target_offset) = (0isize, 0isize);
+    while !reader.bytes.is_empty() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        let position = output.len();
+        let end = position
+            .checked_add(len)
+            .filter(|&end| end <= target_size)
+            .ok_or(PatchError::Truncated)?;
+        match action & 3 {
+            // the bytes at the same place in the ROM
+            0 => output.extend_from_slice(rom.get(position..end).ok_or(PatchError::Truncated)?),
+            // bytes from the patch
+            1 => {
+                let data = reader.bytes.get(..len).ok_or(PatchError::Truncated)?;
+                output.extend_from_slice(data);
+                reader.bytes = &reader.bytes[len..];
+            }
+            // bytes from elsewhere in the ROM
+            2 => {
+                let start = relative(&mut source_offset, reader.offset()?, len)?;
+                let data = rom.get(start..).and_then(|rest| rest.get(..len));
+                output.extend_from_slice(data.ok_or(PatchError::Truncated)?);
+            }
+            // bytes already written, the copy can overlap what it's writing
+            _ => {
+                let start = relative(&mut target_offset, reader.offset()?, len)?;
+                if start >= position {
+                    return Err(PatchError::Truncated);
+                }
+                for index in start..start + len {
+                    output.push(output[index]);
+                }
+            }
+        }
+    }
+    if output.len() != target_size {
+        return Err(PatchError::Truncated);
+    }
+
+    if crc32(&output) != crc(1) {
+        return Err(PatchError::TargetMismatch);
+    }
+    Ok(output)
+}
+
+/// The patch next to a ROM with the same name, like `game.ips` or `game.bps` for `game.nes`
+///
+/// That's how patches are soft-patched, applied when the ROM is loaded without changing
+/// the ROM file.
+#[cfg(feature = "std")]
+pub fn find_soft_patch(rom: &std::path::Path) -> Option<std::path::PathBuf> {
+    ["ips", "bps"]
+        .into_iter()
+        .map(|extension| rom.with_extension(extension))
+        .find(|patch| patch.is_file())
+}
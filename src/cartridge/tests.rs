@@ -0,0 +1,787 @@
+use super::{
+    database::{self, DatabaseEntry, RomDatabase},
+    fds::DiskImage,
+    game_genie::{GameGenieCode, GameGenieError},
+    patch::{self, PatchError},
+    unif,
+    vs_system::{self, CoinSlot},
+    Cartridge, LoadError, Mirroring,
+};
+use crate::{
+    clock::Region,
+    memory::{ram::Ram, PpuMemoryMapping},
+    ppu,
+};
+
+/// Build an iNES image of an NROM cartridge with `prg` at $8000, for tests that need a console to run
+///
+/// PRG ROM is padded to 32KB, and unless `prg` reaches the vectors, the reset vector points to $8000.
+/// CHR banks are empty, without any the cartridge gets CHR RAM.
+pub(crate) fn nrom_image(prg: &[u8], chr_banks: u8, flags6: u8) -> Vec<u8> {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 2, chr_banks, flags6];
+    image.resize(16, 0);
+    let mut prg_rom = vec![0; 0x8000];
+    prg_rom[..prg.len()].copy_from_slice(prg);
+    if prg.len() <= 0x7FFC {
+        prg_rom[0x7FFD] = 0x80;
+    }
+    image.extend(prg_rom);
+    image.resize(16 + 0x8000 + chr_banks as usize * 0x2000, 0);
+    image
+}
+
+/// Build an iNES image with the given amount of 16KB PRG and 8KB CHR banks
+fn ines_image(prg_banks: u8, chr_banks: u8, flags6: u8) -> Vec<u8> {
+    let mut image = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, flags6];
+    image.resize(16, 0);
+    image.extend((0..prg_banks as usize * 0x4000).map(|i| i as u8));
+    image.extend((0..chr_banks as usize * 0x2000).map(|i| (i >> 8) as u8));
+    image
+}
+
+#[test]
+fn nrom_prg_mirroring() {
+    let mut cartridge = Cartridge::from_ines(&ines_image(1, 1, 1)).unwrap();
+    assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+    assert_eq!(cartridge.cpu_load(0x8001), Some(0x01));
+    // 16KB of PRG is mirrored into $C000-$FFFF
+    assert_eq!(cartridge.cpu_load(0xC001), Some(0x01));
+    assert_eq!(cartridge.cpu_load(0xFFFF), Some(0xFF));
+    assert_eq!(cartridge.cpu_load(0x5000), None);
+}
+
+#[test]
+fn trainer() {
+    let mut image = ines_image(1, 1, 0b100);
+    // insert the trainer between the header and PRG ROM
+    image.splice(16..16, (0..512).map(|i| !(i as u8)));
+    let mut cartridge = Cartridge::from_ines(&image).unwrap();
+
+    assert_eq!(cartridge.cpu_load(0x7000), Some(0xFF));
+    assert_eq!(cartridge.cpu_load(0x71FF), Some(0x00));
+    assert_eq!(cartridge.cpu_load(0x7200), Some(0x00));
+    // PRG ROM is not offset by the trainer
+    assert_eq!(cartridge.cpu_load(0x8001), Some(0x01));
+
+    cartridge.cpu_store(0x6000, 0xAB);
+    assert_eq!(cartridge.cpu_load(0x6000), Some(0xAB));
+}
+
+#[test]
+fn chr_rom_is_read_only() {
+    let mut cartridge = Cartridge::from_ines(&ines_image(1, 1, 0)).unwrap();
+    assert!(!cartridge.has_chr_ram());
+    assert_eq!(cartridge.ppu_load(0x0345), 0x03);
+    cartridge.ppu_store(0x0345, 0xAA);
+    assert_eq!(cartridge.ppu_load(0x0345), 0x03);
+}
+
+#[test]
+fn chr_ram() {
+    let mut cartridge = Cartridge::from_ines(&ines_image(2, 0, 0)).unwrap();
+    assert!(cartridge.has_chr_ram());
+    assert_eq!(cartridge.ppu_load(0x1FFF), 0x00);
+    cartridge.ppu_store(0x1FFF, 0xAA);
+    cartridge.ppu_store(0x0000, 0x55);
+    assert_eq!(cartridge.ppu_load(0x1FFF), 0xAA);
+    assert_eq!(cartridge.ppu_load(0x0000), 0x55);
+
+    // a NES 2.0 header without CHR RAM either still gets pattern tables
+    let mut image = ines_image(2, 0, 0);
+    image[7] = 0x08;
+    let mut cartridge = Cartridge::from_ines(&image).unwrap();
+    assert!(cartridge.has_chr_ram());
+    cartridge.ppu_store(0x1FFF, 0xAA);
+    assert_eq!(cartridge.ppu_load(0x1FFF), 0xAA);
+}
+
+#[test]
+fn load_errors() {
+    assert_eq!(
+        Cartridge::from_ines(b"NES").unwrap_err(),
+        LoadError::InvalidHeader
+    );
+
+    let mut image = ines_image(1, 1, 0);
+    image.truncate(image.len() - 1);
+    assert_eq!(
+        Cartridge::from_ines(&image).unwrap_err(),
+        LoadError::Truncated
+    );
+
+    // mapper 1 (MMC1)
+    let image = ines_image(1, 1, 0x10);
+    assert_eq!(
+        Cartridge::from_ines(&image).unwrap_err(),
+        LoadError::UnsupportedMapper(1)
+    );
+}
+
+#[test]
+fn four_screen_mirroring() {
+    let mut cartridge = Cartridge::from_ines(&ines_image(1, 1, 0b1000)).unwrap();
+    let mut vram = Ram::new();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+
+    for (i, address) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+        memory.store(address, i as u8 + 1);
+    }
+    for (i, address) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+        assert_eq!(memory.load(address), i as u8 + 1);
+        // $3000-$3EFF mirrors the nametables
+        assert_eq!(memory.load(address + 0x1000), i as u8 + 1);
+    }
+    // and so does $3F00-$3FFF, under the palette
+    memory.store(0x3F00, 5);
+    assert_eq!(memory.load(0x2F00), 5);
+    // second half of the nametables lives on the cartridge
+    assert_eq!(vram.load(0x000), 1);
+    assert_eq!(vram.load(0x400), 2);
+    assert_eq!(cartridge.ppu_load(0x2800), 3);
+    assert_eq!(cartridge.ppu_load(0x2C00), 4);
+}
+
+#[test]
+fn nametable_mirroring() {
+    let horizontal = Cartridge::from_ines(&ines_image(1, 1, 0)).unwrap();
+    assert_eq!(horizontal.nametable_address(0x2400), Some(0x000));
+    assert_eq!(horizontal.nametable_address(0x2C05), Some(0x405));
+
+    let vertical = Cartridge::from_ines(&ines_image(1, 1, 1)).unwrap();
+    assert_eq!(vertical.nametable_address(0x2800), Some(0x000));
+    assert_eq!(vertical.nametable_address(0x2C05), Some(0x405));
+}
+
+/// Build a UNIF image out of (id, data) chunks
+fn unif_image(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut image = b"UNIF".to_vec();
+    image.extend(7u32.to_le_bytes());
+    image.resize(32, 0);
+    for (id, data) in chunks {
+        image.extend(*id);
+        image.extend((data.len() as u32).to_le_bytes());
+        image.extend(*data);
+    }
+    image
+}
+
+#[test]
+fn unif() {
+    let prg0 = [0x11; 0x4000];
+    let prg1 = [0x22; 0x4000];
+    let chr0 = [0x33; 0x2000];
+    // chunks don't have to be in order
+    let image = unif_image(&[
+        (b"MAPR", b"NES-NROM-256\0"),
+        (b"NAME", b"Test\0"),
+        (b"PRG1", &prg1),
+        (b"MIRR", &[1]),
+        (b"PRG0", &prg0),
+        (b"CHR0", &chr0),
+    ]);
+    let mut cartridge = Cartridge::from_unif(&image).unwrap();
+
+    assert_eq!(cartridge.mapper(), 0);
+    assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+    assert_eq!(cartridge.cpu_load(0x8000), Some(0x11));
+    assert_eq!(cartridge.cpu_load(0xC000), Some(0x22));
+    assert_eq!(cartridge.ppu_load(0x0000), 0x33);
+    assert!(!cartridge.has_chr_ram());
+}
+
+#[test]
+fn unif_board_names() {
+    assert_eq!(unif::resolve_board("NES-NROM-128"), Some(0));
+    assert_eq!(unif::resolve_board("HVC-SxROM"), Some(1));
+    assert_eq!(unif::resolve_board("UNROM"), Some(2));
+    assert_eq!(unif::resolve_board("UNL-Bogus"), None);
+
+    let image = unif_image(&[(b"MAPR", b"UNL-Bogus\0"), (b"PRG0", &[0; 0x4000])]);
+    assert_eq!(
+        Cartridge::from_unif(&image).unwrap_err(),
+        LoadError::UnknownBoard("UNL-Bogus".into())
+    );
+
+    let image = unif_image(&[(b"MAPR", b"NES-SNROM\0"), (b"PRG0", &[0; 0x4000])]);
+    assert_eq!(
+        Cartridge::from_unif(&image).unwrap_err(),
+        LoadError::UnsupportedMapper(1)
+    );
+}
+
+/// Build a single sided .fds image with one file
+fn fds_image() -> Vec<u8> {
+    let mut side = vec![0x01];
+    side.extend(b"*NINTENDO-HVC*");
+    side.resize(56, 0);
+    // file amount
+    side.extend([0x02, 0x01]);
+    // file header, 4 bytes of data
+    side.extend([
+        0x03, 0x00, 0x00, b'F', b'I', b'L', b'E', b' ', b' ', b' ', b' ',
+    ]);
+    side.extend([0x00, 0x60, 0x04, 0x00, 0x00]);
+    side.extend([0x04, 0xDE, 0xAD, 0xBE, 0xEF]);
+    side.resize(65500, 0);
+    side
+}
+
+#[test]
+fn fds_disk_image() {
+    let image = fds_image();
+    let disk = DiskImage::from_fds(&image).unwrap();
+    assert_eq!(disk.side_count(), 1);
+    assert_eq!(disk.to_fds(), image);
+
+    let mut with_header = b"FDS\x1A\x01".to_vec();
+    with_header.resize(16, 0);
+    with_header.extend(&image);
+    assert_eq!(DiskImage::from_fds(&with_header).unwrap().to_fds(), image);
+
+    assert_eq!(
+        DiskImage::from_fds(&image[..100]).unwrap_err(),
+        LoadError::Truncated
+    );
+}
+
+#[test]
+fn fds_drive() {
+    let bios = [0xEA; 0x2000];
+    let disk = DiskImage::from_fds(&fds_image()).unwrap();
+    let mut cartridge = Cartridge::from_fds(&bios, disk).unwrap();
+
+    assert_eq!(cartridge.cpu_load(0xFFFC), Some(0xEA));
+    cartridge.cpu_store(0x6000, 0x12);
+    cartridge.cpu_store(0xDFFF, 0x34);
+    assert_eq!(cartridge.cpu_load(0x6000), Some(0x12));
+    assert_eq!(cartridge.cpu_load(0xDFFF), Some(0x34));
+    // disk inserted and not write protected
+    assert_eq!(cartridge.cpu_load(0x4032).unwrap() & 0b101, 0);
+
+    // enable disk registers, start the motor in read mode with the disk IRQ enabled
+    cartridge.cpu_store(0x4023, 0x01);
+    cartridge.cpu_store(0x4025, 0b1100_0101);
+
+    let mut read = Vec::new();
+    // the lead-in gap alone takes over half a million cycles
+    for _ in 0..1_000_000 {
+        cartridge.tick();
+        if cartridge.irq() {
+            read.push(cartridge.cpu_load(0x4031).unwrap());
+            assert!(!cartridge.irq());
+            if read.len() == 15 {
+                break;
+            }
+        }
+    }
+    assert_eq!(&read[..15], b"\x01*NINTENDO-HVC*");
+
+    cartridge.insert_disk_side(None);
+    assert_eq!(cartridge.disk_side(), None);
+    assert_eq!(cartridge.cpu_load(0x4032).unwrap() & 1, 1);
+}
+
+#[test]
+fn fds_end_of_disk() {
+    let disk = DiskImage::from_fds(&fds_image()).unwrap();
+    let mut cartridge = Cartridge::from_fds(&[0; 0x2000], disk).unwrap();
+
+    cartridge.cpu_store(0x4023, 0x01);
+    cartridge.cpu_store(0x4025, 0b1100_0101);
+    // 65500 bytes of about 150 cycles each, after the head's way back to the start
+    let mut end_reached = false;
+    for _ in 0..11_000_000 {
+        cartridge.tick();
+        if cartridge.irq() && cartridge.cpu_load(0x4030).unwrap() & 0x40 != 0 {
+            end_reached = true;
+            break;
+        }
+    }
+    assert!(end_reached);
+
+    // turning the motor back on right away goes back to the start of the disk
+    cartridge.cpu_store(0x4025, 0b1100_0101);
+    for _ in 0..100_000 {
+        cartridge.tick();
+    }
+    assert_eq!(cartridge.cpu_load(0x4030).unwrap() & 0x40, 0);
+}
+
+#[test]
+fn fds_timer_irq() {
+    let disk = DiskImage::from_fds(&fds_image()).unwrap();
+    let mut cartridge = Cartridge::from_fds(&[0; 0x2000], disk).unwrap();
+
+    cartridge.cpu_store(0x4023, 0x01);
+    cartridge.cpu_store(0x4020, 10);
+    cartridge.cpu_store(0x4021, 0);
+    // enabled, no repeat
+    cartridge.cpu_store(0x4022, 0b10);
+
+    (0..10).for_each(|_| cartridge.tick());
+    assert!(!cartridge.irq());
+    cartridge.tick();
+    assert!(cartridge.irq());
+
+    // acknowledged by reading the status
+    assert_eq!(cartridge.cpu_load(0x4030).unwrap() & 1, 1);
+    assert!(!cartridge.irq());
+    (0..100).for_each(|_| cartridge.tick());
+    assert!(!cartridge.irq());
+}
+
+/// Build an NSF image with 3 tracks, playing at 100Hz
+fn nsf_image(load_address: u16, banks: [u8; 8], data: &[u8]) -> Vec<u8> {
+    let mut image = b"NESM\x1A\x01\x03\x02".to_vec();
+    image.extend(load_address.to_le_bytes());
+    // init and play
+    image.extend(0x8000u16.to_le_bytes());
+    image.extend(0x8003u16.to_le_bytes());
+    let mut title = b"Title".to_vec();
+    title.resize(32, 0);
+    image.extend(title);
+    image.resize(0x6E, 0);
+    image.extend(10_000u16.to_le_bytes());
+    image.extend(banks);
+    image.resize(0x80, 0);
+    image.extend(data);
+    image
+}
+
+#[test]
+fn nsf() {
+    let mut cartridge = Cartridge::from_nsf(&nsf_image(0x8100, [0; 8], &[0xAB; 0x10])).unwrap();
+
+    let info = cartridge.nsf_info().unwrap();
+    assert_eq!(info.title, "Title");
+    assert_eq!(info.track_count, 3);
+    assert_eq!(info.starting_track, 1);
+    assert_eq!(cartridge.nsf_track(), Some(1));
+
+    assert_eq!(cartridge.cpu_load(0x80FF), Some(0x00));
+    assert_eq!(cartridge.cpu_load(0x8100), Some(0xAB));
+    // reset vector points to the driver, which calls init
+    assert_eq!(cartridge.cpu_load(0xFFFC), Some(0x00));
+    assert_eq!(cartridge.cpu_load(0xFFFD), Some(0x41));
+    assert_eq!(cartridge.cpu_load(0x4100), Some(0x78));
+    assert_eq!(cartridge.cpu_load(0x4180), Some(1));
+
+    cartridge.select_nsf_track(2);
+    assert_eq!(cartridge.cpu_load(0x4180), Some(2));
+    cartridge.select_nsf_track(3);
+    assert_eq!(cartridge.nsf_track(), Some(2));
+
+    // 10ms at the NTSC clock rate
+    (0..17897).for_each(|_| cartridge.tick());
+    assert!(!cartridge.irq());
+    cartridge.tick();
+    assert!(cartridge.irq());
+    let _ = cartridge.cpu_load(0x4182);
+    assert!(!cartridge.irq());
+}
+
+#[test]
+fn nsf_bankswitching() {
+    let data: Vec<u8> = (0..3).flat_map(|bank| [bank; 0x1000]).collect();
+    let mut cartridge =
+        Cartridge::from_nsf(&nsf_image(0x8000, [0, 1, 2, 0, 0, 0, 0, 0], &data)).unwrap();
+
+    assert_eq!(cartridge.cpu_load(0x8000), Some(0));
+    assert_eq!(cartridge.cpu_load(0x9000), Some(1));
+    assert_eq!(cartridge.cpu_load(0xA000), Some(2));
+    cartridge.cpu_store(0x5FF8, 2);
+    assert_eq!(cartridge.cpu_load(0x8000), Some(2));
+
+    // selecting a track restores the initial banks
+    cartridge.select_nsf_track(0);
+    assert_eq!(cartridge.cpu_load(0x8000), Some(0));
+}
+
+#[test]
+fn nsfe() {
+    let chunk = |id: &[u8; 4], data: &[u8]| {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(id);
+        chunk.extend(data);
+        chunk
+    };
+    let mut image = b"NSFE".to_vec();
+    image.extend(chunk(
+        b"INFO",
+        &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0x00, 0x00, 2, 0],
+    ));
+    image.extend(chunk(b"DATA", &[0xAB; 0x10]));
+    image.extend(chunk(b"auth", b"Game\0Artist\0Copyright\0Ripper\0"));
+    image.extend(chunk(b"tlbl", b"First\0Second\0"));
+    image.extend(chunk(b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+    image.extend(chunk(b"NEND", &[]));
+
+    let mut cartridge = Cartridge::from_nsf(&image).unwrap();
+    let info = cartridge.nsf_info().unwrap();
+    assert_eq!(info.title, "Game");
+    assert_eq!(info.artist, "Artist");
+    assert_eq!(info.track_count, 2);
+    assert_eq!(info.track_labels, ["First", "Second"]);
+    assert_eq!(info.track_durations, [Some(10_000), None]);
+    assert_eq!(cartridge.cpu_load(0x8000), Some(0xAB));
+}
+
+#[test]
+fn crc32() {
+    assert_eq!(database::crc32(b""), 0);
+    assert_eq!(database::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(
+        database::crc32_update(database::crc32(b"1234"), b"56789"),
+        0xCBF4_3926
+    );
+}
+
+#[test]
+fn database_header_correction() {
+    // header claims mapper 1 and horizontal mirroring, without a battery
+    let image = ines_image(1, 1, 0x10);
+    let crc = database::crc32(&image[16..]);
+    let database: RomDatabase = [(
+        crc,
+        DatabaseEntry {
+            mapper: Some(0),
+            mirroring: Some(Mirroring::Vertical),
+            battery: Some(true),
+            ..Default::default()
+        },
+    )]
+    .into_iter()
+    .collect();
+
+    assert_eq!(
+        Cartridge::from_ines(&image).unwrap_err(),
+        LoadError::UnsupportedMapper(1)
+    );
+    let cartridge = Cartridge::from_ines_with_database(&image, &database).unwrap();
+    assert_eq!(cartridge.crc32(), crc);
+    assert_eq!(cartridge.mapper(), 0);
+    assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+    assert!(cartridge.board().battery);
+    // untouched fields come from the header
+    assert_eq!(cartridge.board().prg_ram_size, 0x2000);
+    assert_eq!(cartridge.board().region, None);
+}
+
+#[test]
+fn region_detection() {
+    let region = |image: &[u8]| Cartridge::from_ines(image).unwrap().board().region;
+    let mut image = ines_image(1, 1, 0);
+    assert_eq!(region(&image), None);
+    image[9] = 1;
+    assert_eq!(region(&image), Some(Region::Pal));
+
+    // NES 2.0
+    image[7] = 0x08;
+    image[9] = 0;
+    for (timing, expected) in [
+        (0, Some(Region::Ntsc)),
+        (1, Some(Region::Pal)),
+        (2, None),
+        (3, Some(Region::Dendy)),
+    ] {
+        image[12] = timing;
+        assert_eq!(region(&image), expected);
+    }
+
+    let database: RomDatabase = [(
+        database::crc32(&image[16..]),
+        DatabaseEntry {
+            region: Some(Region::Pal),
+            ..Default::default()
+        },
+    )]
+    .into_iter()
+    .collect();
+    let cartridge = Cartridge::from_ines_with_database(&image, &database).unwrap();
+    assert_eq!(cartridge.board().region, Some(Region::Pal));
+}
+
+#[test]
+fn vs_system() {
+    let mut image = ines_image(1, 1, 0);
+    assert_eq!(Cartridge::from_ines(&image).unwrap().board().vs_ppu, None);
+    // iNES 1.0 only has a flag
+    image[7] = 1;
+    let cartridge = Cartridge::from_ines(&image).unwrap();
+    assert_eq!(cartridge.board().vs_ppu, Some(ppu::Variant::Ricoh2C03));
+    assert!(cartridge.vs_system().is_some());
+
+    // mapper 99 with 48KB of PRG ROM and 16KB of CHR ROM, as NES 2.0 for an RC2C05-03
+    let mut image = ines_image(3, 2, 0x30);
+    image[7] = 0x69;
+    image[13] = 0x0A;
+    image[16 + 0x8000] = 0xEE;
+    let mut cartridge = Cartridge::from_ines(&image).unwrap();
+    assert_eq!(cartridge.mapper(), vs_system::VS_MAPPER);
+    assert_eq!(cartridge.board().vs_ppu, Some(ppu::Variant::Ricoh2C05C));
+
+    // the mainboard's 2KB of RAM is mirrored through $6000-$7FFF
+    assert_eq!(cartridge.prg_ram().len(), vs_system::RAM_SIZE);
+    cartridge.cpu_store(0x6001, 0xAB);
+    assert_eq!(cartridge.cpu_load(0x7801), Some(0xAB));
+
+    // bit 2 of $4016 switches the CHR bank and the PRG bank at $8000
+    assert_eq!(
+        (cartridge.ppu_load(0x0000), cartridge.cpu_load(0x8000)),
+        (0x00, Some(0x00))
+    );
+    cartridge.write_outputs(0b101);
+    assert_eq!(
+        (cartridge.ppu_load(0x0000), cartridge.cpu_load(0x8000)),
+        (0x20, Some(0xEE))
+    );
+    assert_eq!(cartridge.cpu_load(0xA001), Some(0x01));
+    cartridge.write_outputs(0b001);
+    assert_eq!(cartridge.ppu_load(0x1000), 0x10);
+
+    let vs = cartridge.vs_system_mut().unwrap();
+    vs.set_dip_switches(0b1000_0110);
+    vs.set_service_button(true);
+    vs.insert_coin(CoinSlot::Two);
+    assert_eq!(cartridge.read_inputs(0), Some(0b0101_0100));
+    assert_eq!(cartridge.read_inputs(1), Some(0b1000_0100));
+    // the coin switch is let go after a few frames
+    for _ in 0..4 * 29781 {
+        cartridge.tick();
+    }
+    cartridge.vs_system_mut().unwrap().set_service_button(false);
+    assert_eq!(cartridge.read_inputs(0), Some(0b0001_0000));
+
+    // the coin counter counts the pulses on bit 0 of $4020
+    for value in [1, 1, 0, 1, 0] {
+        cartridge.cpu_store(0x4020, value);
+    }
+    assert_eq!(cartridge.vs_system().unwrap().coins_counted(), 2);
+    assert_eq!(
+        Cartridge::from_ines(&ines_image(1, 1, 0))
+            .unwrap()
+            .read_inputs(0),
+        None
+    );
+}
+
+#[test]
+fn game_genie_decoding() {
+    // Super Mario Bros. infinite lives
+    let code: GameGenieCode = "SXIOPO".parse().unwrap();
+    assert_eq!(code.address, 0x91D9);
+    assert_eq!(code.value, 0xAD);
+    assert_eq!(code.compare, None);
+
+    let code: GameGenieCode = "yeuzugaa".parse().unwrap();
+    assert_eq!(code.address, 0xACB3);
+    assert_eq!(code.value, 0x07);
+    assert_eq!(code.compare, Some(0x00));
+
+    assert_eq!(
+        "SXIOP".parse::<GameGenieCode>(),
+        Err(GameGenieError::InvalidLength)
+    );
+    assert_eq!(
+        "SXIOPOPOP".parse::<GameGenieCode>(),
+        Err(GameGenieError::InvalidLength)
+    );
+    assert_eq!(
+        "SXIOPB".parse::<GameGenieCode>(),
+        Err(GameGenieError::InvalidLetter('B'))
+    );
+}
+
+#[test]
+fn game_genie_patching() {
+    let mut cartridge = Cartridge::from_ines(&ines_image(2, 1, 0)).unwrap();
+    let replace = GameGenieCode {
+        address: 0x8010,
+        value: 0xEA,
+        compare: None,
+    };
+    let compare_hit = GameGenieCode {
+        address: 0x8020,
+        value: 0xEA,
+        compare: Some(0x20),
+    };
+    let compare_miss = GameGenieCode {
+        address: 0x8030,
+        value: 0xEA,
+        compare: Some(0x00),
+    };
+    cartridge.add_game_genie_code(replace);
+    cartridge.add_game_genie_code(compare_hit);
+    cartridge.add_game_genie_code(compare_miss);
+
+    assert_eq!(cartridge.cpu_load(0x8010), Some(0xEA));
+    assert_eq!(cartridge.cpu_load(0x8020), Some(0xEA));
+    assert_eq!(cartridge.cpu_load(0x8030), Some(0x30));
+    assert_eq!(cartridge.cpu_load(0x8011), Some(0x11));
+
+    assert!(cartridge.set_game_genie_code_enabled(replace, false));
+    assert_eq!(cartridge.cpu_load(0x8010), Some(0x10));
+    assert!(cartridge.set_game_genie_code_enabled(replace, true));
+    assert_eq!(cartridge.cpu_load(0x8010), Some(0xEA));
+
+    assert!(cartridge.remove_game_genie_code(replace));
+    assert!(!cartridge.remove_game_genie_code(replace));
+    assert_eq!(cartridge.cpu_load(0x8010), Some(0x10));
+    assert_eq!(cartridge.game_genie_codes().count(), 2);
+}
+
+#[test]
+fn ips_patch() {
+    let rom = ines_image(1, 0, 0);
+    let mut ips = b"PATCH".to_vec();
+    // 3 bytes at $10, a run of 5 $EA at $20, and 2 bytes past the end
+    ips.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x03, 1, 2, 3]);
+    ips.extend_from_slice(&[0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x05, 0xEA]);
+    ips.extend_from_slice(&[0x00, 0x40, 0x10, 0x00, 0x02, 4, 5]);
+    ips.extend_from_slice(b"EOF");
+
+    let patched = patch::apply(&rom, &ips).unwrap();
+    assert_eq!(patched.len(), 0x4012);
+    assert_eq!(patched[0x10..0x14], [1, 2, 3, 3]);
+    assert_eq!(
+        patched[0x1F..0x26],
+        [0x0F, 0xEA, 0xEA, 0xEA, 0xEA, 0xEA, 0x15]
+    );
+    assert_eq!(patched[0x4010..], [4, 5]);
+    assert!(Cartridge::from_ines(&patched).is_ok());
+
+    let mut truncating = ips.clone();
+    truncating.extend_from_slice(&[0x00, 0x40, 0x10]);
+    assert_eq!(patch::apply(&rom, &truncating).unwrap().len(), 0x4010);
+    assert_eq!(
+        patch::apply(&rom, &ips[..ips.len() - 4]),
+        Err(PatchError::Truncated)
+    );
+    assert_eq!(patch::apply(&rom, b"PACTH"), Err(PatchError::InvalidHeader));
+}
+
+fn bps_number(patch: &mut Vec<u8>, mut number: usize) {
+    loop {
+        let byte = (number & 0x7F) as u8;
+        number >>= 7;
+        if number == 0 {
+            patch.push(byte | 0x80);
+            return;
+        }
+        patch.push(byte);
+        number -= 1;
+    }
+}
+
+#[test]
+fn bps_patch() {
+    let rom = ines_image(1, 1, 0);
+    let mut expected = rom[..0x10].to_vec();
+    expected.extend_from_slice(b"ABCD");
+    expected.extend_from_slice(&rom[0x1000..0x1100]);
+    // an overlapping copy of the last 2 bytes
+    expected.extend_from_within(expected.len() - 2..);
+    expected.extend_from_within(expected.len() - 2..);
+    expected.extend_from_within(expected.len() - 4..);
+    let position = expected.len();
+    expected.extend_from_slice(&rom[position..]);
+
+    let mut bps = b"BPS1".to_vec();
+    bps_number(&mut bps, rom.len());
+    bps_number(&mut bps, expected.len());
+    bps_number(&mut bps, 3);
+    bps.extend_from_slice(b"xml");
+    // the header from the ROM
+    bps_number(&mut bps, (0x10 - 1) << 2);
+    bps_number(&mut bps, (4 - 1) << 2 | 1);
+    bps.extend_from_slice(b"ABCD");
+    // $100 bytes from $1000 of the ROM
+    bps_number(&mut bps, (0x100 - 1) << 2 | 2);
+    bps_number(&mut bps, 0x1000 << 1);
+    // back to what was just written
+    bps_number(&mut bps, (4 - 1) << 2 | 3);
+    bps_number(&mut bps, (0x10 + 4 + 0x100 - 2) << 1);
+    bps_number(&mut bps, (4 - 1) << 2 | 3);
+    bps_number(&mut bps, 2 << 1 | 1);
+    bps_number(&mut bps, (rom.len() - position - 1) << 2);
+    bps.extend_from_slice(&database::crc32(&rom).to_le_bytes());
+    bps.extend_from_slice(&database::crc32(&expected).to_le_bytes());
+    bps.extend_from_slice(&database::crc32(&bps).to_le_bytes());
+
+    assert_eq!(patch::apply(&rom, &bps).unwrap(), expected);
+
+    let other = ines_image(1, 0, 0);
+    assert_eq!(
+        patch::apply(&other, &bps),
+        Err(PatchError::SourceMismatch {
+            expected: database::crc32(&rom),
+            actual: database::crc32(&other)
+        })
+    );
+    let mut corrupt = bps.clone();
+    corrupt[20] ^= 1;
+    assert_eq!(patch::apply(&rom, &corrupt), Err(PatchError::Corrupt));
+
+    // a target size the actions don't write isn't allocated up front
+    let mut huge = b"BPS1".to_vec();
+    bps_number(&mut huge, rom.len());
+    bps_number(&mut huge, usize::MAX >> 8);
+    bps_number(&mut huge, 0);
+    bps_number(&mut huge, (0x10 - 1) << 2);
+    huge.extend_from_slice(&database::crc32(&rom).to_le_bytes());
+    huge.extend_from_slice(&database::crc32(&expected).to_le_bytes());
+    huge.extend_from_slice(&database::crc32(&huge).to_le_bytes());
+    assert_eq!(patch::apply(&rom, &huge), Err(PatchError::Truncated));
+}
+
+#[test]
+fn load_any_format() {
+    let load =
+        |bytes: &[u8]| Cartridge::from_bytes(bytes, None).map(|cartridge| cartridge.mapper());
+    assert_eq!(load(&ines_image(1, 1, 0)), Ok(0));
+    assert_eq!(load(&[0; 16]).unwrap_err(), LoadError::InvalidHeader);
+    assert_eq!(load(&fds_image()).unwrap_err(), LoadError::MissingBios);
+    let fds = Cartridge::from_bytes(&fds_image(), Some(&[0; 0x2000])).unwrap();
+    assert_eq!(fds.mapper(), 20);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn load_from_reader() {
+    let reader = std::io::Cursor::new(ines_image(1, 1, 0));
+    assert!(Cartridge::from_reader(reader, None).is_ok());
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn load_from_zip() {
+    use crate::zip::tests::archive;
+
+    let rom = ines_image(1, 1, 0);
+    let zipped = archive(&[("readme.txt", b"hi"), ("Game (U).NES", &rom)]);
+    assert_eq!(super::unzip_rom(&zipped).unwrap(), rom);
+    let cartridge = Cartridge::from_bytes(&zipped, None).unwrap();
+    assert_eq!(
+        cartridge.crc32(),
+        Cartridge::from_ines(&rom).unwrap().crc32()
+    );
+
+    assert_eq!(
+        Cartridge::from_bytes(&archive(&[("readme.txt", b"hi")]), None).unwrap_err(),
+        LoadError::ArchiveRoms(0)
+    );
+    let two = archive(&[("a.nes", &rom), ("b.nsf", &rom)]);
+    assert_eq!(
+        Cartridge::from_bytes(&two, None).unwrap_err(),
+        LoadError::ArchiveRoms(2)
+    );
+    // archives in archives aren't unpacked
+    let nested = archive(&[("game.nes", &zipped)]);
+    assert_eq!(
+        Cartridge::from_bytes(&nested, None).unwrap_err(),
+        LoadError::InvalidHeader
+    );
+}
@@ -0,0 +1,189 @@
+//! Parser for the UNIF ROM format
+//!
+//! UNIF files identify the cartridge by its board name instead of a mapper number,
+//! the format is described at https://www.nesdev.org/wiki/UNIF
+
+use alloc::string::String;
+
+use crate::clock::Region;
+
+use super::{BoardInfo, Cartridge, LoadError, Mirroring, CHR_BANK_SIZE, PRG_RAM_WINDOW_SIZE};
+
+const MAGIC: &[u8; 4] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+
+/// Mapper numbers of known boards, keyed by the board name without the `NES-`/`UNL-`/etc. prefix
+///
+/// Boards with the same mapper number only differ in details
+/// the mapper number doesn't capture either (e.g. the amount of RAM)
+const BOARDS: &[(&str, u16)] = &[
+    ("NROM", 0),
+    ("NROM-128", 0),
+    ("NROM-256", 0),
+    ("RROM", 0),
+    ("RROM-128", 0),
+    ("SAROM", 1),
+    ("SBROM", 1),
+    ("SCROM", 1),
+    ("SEROM", 1),
+    ("SGROM", 1),
+    ("SKROM", 1),
+    ("SLROM", 1),
+    ("SL1ROM", 1),
+    ("SNROM", 1),
+    ("SOROM", 1),
+    ("SUROM", 1),
+    ("SXROM", 1),
+    ("UNROM", 2),
+    ("UOROM", 2),
+    ("CNROM", 3),
+    ("TEROM", 4),
+    ("TFROM", 4),
+    ("TGROM", 4),
+    ("TKROM", 4),
+    ("TLROM", 4),
+    ("TL1ROM", 4),
+    ("TR1ROM", 4),
+    ("TSROM", 4),
+    ("TVROM", 4),
+    ("EKROM", 5),
+    ("ELROM", 5),
+    ("ETROM", 5),
+    ("EWROM", 5),
+    ("AMROM", 7),
+    ("ANROM", 7),
+    ("AOROM", 7),
+    ("PNROM", 9),
+    ("FJROM", 10),
+    ("FKROM", 10),
+    ("GNROM", 66),
+    ("MHROM", 66),
+    ("BNROM", 34),
+    ("CPROM", 13),
+];
+
+const BOARD_PREFIXES: &[&str] = &["NES-", "UNL-", "HVC-", "BTL-", "BMC-", "IREM-", "KONAMI-"];
+
+/// Find the iNES mapper number of a board by its UNIF name
+pub fn resolve_board(name: &str) -> Option<u16> {
+    let name = BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+
+    BOARDS
+        .iter()
+        .find(|(board, _)| board.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper)| mapper)
+}
+
+/// Iterator over the (id, data) chunks after the UNIF header
+struct Chunks<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<(&'a [u8], &'a [u8]), LoadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let Some((id, rest)) = self.bytes.split_at_checked(4) else {
+            return Some(Err(LoadError::Truncated));
+        };
+        let Some((length, rest)) = rest.split_at_checked(4) else {
+            return Some(Err(LoadError::Truncated));
+        };
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let Some((data, rest)) = rest.split_at_checked(length) else {
+            return Some(Err(LoadError::Truncated));
+        };
+
+        self.bytes = rest;
+        Some(Ok((id, data)))
+    }
+}
+
+impl Cartridge {
+    /// Load a cartridge from the contents of a UNIF (.unf, .unif) file
+    pub fn from_unif(bytes: &[u8]) -> Result<Self, LoadError> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(LoadError::InvalidHeader);
+        }
+
+        let mut board_name = None;
+        let mut battery = false;
+        let mut mirroring = Mirroring::Horizontal;
+        let mut region = None;
+        // PRG0-PRGF and CHR0-CHRF chunks, concatenated in order of their number
+        let mut prg_chunks: [&[u8]; 16] = Default::default();
+        let mut chr_chunks: [&[u8]; 16] = Default::default();
+
+        let chunks = Chunks {
+            bytes: &bytes[HEADER_SIZE..],
+        };
+        for chunk in chunks {
+            let (id, data) = chunk?;
+            match id {
+                b"MAPR" => {
+                    let name = data.split(|&b| b == 0).next().unwrap_or_default();
+                    board_name = Some(String::from_utf8_lossy(name).into_owned());
+                }
+                b"BATR" => battery = data.first().is_some_and(|&b| b != 0),
+                b"MIRR" => {
+                    mirroring = match data.first() {
+                        Some(1) => Mirroring::Vertical,
+                        Some(2) => Mirroring::SingleScreenLower,
+                        Some(3) => Mirroring::SingleScreenUpper,
+                        Some(4) => Mirroring::FourScreen,
+                        // 5 means the mapper controls mirroring
+                        _ => Mirroring::Horizontal,
+                    };
+                }
+                b"TVCI" => {
+                    region = match data.first() {
+                        Some(0) => Some(Region::Ntsc),
+                        Some(1) => Some(Region::Pal),
+                        // 2 means it works on both
+                        _ => None,
+                    };
+                }
+                [b'P', b'R', b'G', n] | [b'C', b'H', b'R', n] if n.is_ascii_hexdigit() => {
+                    let index = (*n as char).to_digit(16).unwrap() as usize;
+                    let chunks = if id[0] == b'P' {
+                        &mut prg_chunks
+                    } else {
+                        &mut chr_chunks
+                    };
+                    chunks[index] = data;
+                }
+                // other chunks are metadata we don't need
+                _ => {}
+            }
+        }
+
+        let Some(board_name) = board_name else {
+            return Err(LoadError::InvalidHeader);
+        };
+        let Some(mapper) = resolve_board(&board_name) else {
+            return Err(LoadError::UnknownBoard(board_name));
+        };
+
+        let board = BoardInfo {
+            mapper,
+            mirroring,
+            prg_ram_size: PRG_RAM_WINDOW_SIZE,
+            chr_ram_size: CHR_BANK_SIZE,
+            battery,
+            region,
+            vs_ppu: None,
+        };
+
+        Cartridge::new(
+            board,
+            prg_chunks.concat().into(),
+            chr_chunks.concat().into(),
+        )
+    }
+}
@@ -0,0 +1,184 @@
+//! VS System, the arcade version of the NES
+//!
+//! VS games run on a mainboard with 2KB of RAM at $6000-$7FFF, two coin slots, a service button
+//! and 8 DIP switches that set things like the difficulty and the price of a game.
+//! They're read through the bits of $4016 and $4017 the controller ports don't use,
+//! and writes to $4020 drive the coin counter.
+//! Each game was made for one model of RGB PPU, see [`Variant`](crate::ppu::Variant).
+//!
+//! The simplest boards are mapper 99, where bit 2 of $4016 selects the 8KB CHR bank,
+//! and with 40KB of PRG ROM also the bank at $8000-$9FFF.
+//!
+//! Details at https://www.nesdev.org/wiki/Vs._System and https://www.nesdev.org/wiki/INES_Mapper_099
+
+use crate::{ppu, state::impl_state};
+
+use super::{Cartridge, Hardware, CHR_BANK_SIZE};
+
+/// iNES mapper number of the VS System's own boards
+pub const VS_MAPPER: u16 = 99;
+/// Size of the RAM on the mainboard, mirrored through $6000-$7FFF
+pub const RAM_SIZE: usize = 0x800;
+/// Size of the PRG ROM banks switched by mapper 99
+const PRG_BANK_SIZE: usize = 0x2000;
+/// CPU cycles a coin holds its switch down for, about 4 frames
+const COIN_CYCLES: u32 = 4 * 29781;
+
+/// The PPU of a VS game from the PPU type in byte 13 of a NES 2.0 header
+pub fn nes2_ppu(ppu_type: u8) -> ppu::Variant {
+    match ppu_type {
+        2 => ppu::Variant::Ricoh2C04A,
+        3 => ppu::Variant::Ricoh2C04B,
+        4 => ppu::Variant::Ricoh2C04C,
+        5 => ppu::Variant::Ricoh2C04D,
+        8 => ppu::Variant::Ricoh2C05A,
+        9 => ppu::Variant::Ricoh2C05B,
+        10 => ppu::Variant::Ricoh2C05C,
+        11 => ppu::Variant::Ricoh2C05D,
+        12 => ppu::Variant::Ricoh2C05E,
+        // the RP2C03B/G and RC2C03B/C, and the reserved values
+        _ => ppu::Variant::Ricoh2C03,
+    }
+}
+
+/// One of the two coin slots of a VS System cabinet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoinSlot {
+    /// Coin 1, read in bit 5 of $4016
+    One,
+    /// Coin 2, read in bit 6 of $4016
+    Two,
+}
+
+/// The mainboard of the VS System and the bank switching of its cartridges
+#[derive(Debug, Clone, Default)]
+pub struct VsSystem {
+    /// Switch 1 in bit 0 up to switch 8 in bit 7, not part of the state
+    dip_switches: u8,
+    service_button: bool,
+    /// Cycles left until each coin slot's switch is let go, indexed by [`CoinSlot`]
+    coin_cycles: [u32; 2],
+    /// Bit 0 of the last write to $4020
+    coin_counter_line: bool,
+    /// Coins the counter has counted, see [`VsSystem::coins_counted`]
+    coins_counted: u32,
+    /// Bit 2 of the last write to $4016
+    bank_select: bool,
+}
+
+impl VsSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn dip_switches(&self) -> u8 {
+        self.dip_switches
+    }
+
+    /// Set the DIP switches, a set bit is a switch turned on
+    ///
+    /// Games usually only look at them after a reset.
+    pub fn set_dip_switches(&mut self, dip_switches: u8) {
+        self.dip_switches = dip_switches;
+    }
+
+    pub fn service_button(&self) -> bool {
+        self.service_button
+    }
+
+    /// Hold or let go of the service button, which gives a credit without a coin
+    pub fn set_service_button(&mut self, pressed: bool) {
+        self.service_button = pressed;
+    }
+
+    /// Drop a coin in one of the slots, its switch is held down for a few frames
+    pub fn insert_coin(&mut self, slot: CoinSlot) {
+        self.coin_cycles[slot as usize] = COIN_CYCLES;
+    }
+
+    /// Times the game pulsed the coin counter, which the operator reads to see how much the machine made
+    pub fn coins_counted(&self) -> u32 {
+        self.coins_counted
+    }
+
+    pub(super) fn tick(&mut self) {
+        for cycles in &mut self.coin_cycles {
+            *cycles = cycles.saturating_sub(1);
+        }
+    }
+
+    /// Bits 1-7 of reading port 1 or 2, indexed from 0
+    pub(super) fn read_inputs(&self, port: usize) -> u8 {
+        if port == 1 {
+            return self.dip_switches & 0xFC;
+        }
+        // bit 7 is 0 on the main CPU of the dual systems, which is the only one there is here
+        (self.service_button as u8) << 2
+            | (self.dip_switches & 0b11) << 3
+            | ((self.coin_cycles[0] > 0) as u8) << 5
+            | ((self.coin_cycles[1] > 0) as u8) << 6
+    }
+
+    pub(super) fn write_coin_counter(&mut self, value: u8) {
+        let line = value & 1 != 0;
+        if line && !self.coin_counter_line {
+            self.coins_counted += 1;
+        }
+        self.coin_counter_line = line;
+    }
+
+    /// Offset in the PRG ROM of a CPU address at $8000-$FFFF
+    pub(super) fn prg_offset(&self, address: u16, prg_size: usize) -> usize {
+        let offset = address as usize - 0x8000;
+        // only the 40KB games switch the first bank, with the 5th bank of the ROM
+        if prg_size > 0x8000 && offset < PRG_BANK_SIZE && self.bank_select {
+            return (offset + 4 * PRG_BANK_SIZE) % prg_size;
+        }
+        offset % prg_size
+    }
+
+    /// Offset in the CHR memory of a pattern table address
+    pub(super) fn chr_offset(&self, address: u16, chr_size: usize) -> usize {
+        (self.bank_select as usize * CHR_BANK_SIZE + address as usize) % chr_size
+    }
+}
+
+impl Cartridge {
+    /// The mainboard, if this is a VS System game
+    pub fn vs_system(&self) -> Option<&VsSystem> {
+        match &self.hardware {
+            Hardware::Vs(vs) => Some(vs),
+            _ => None,
+        }
+    }
+
+    /// See [`Cartridge::vs_system`], for the DIP switches, coins and the service button
+    pub fn vs_system_mut(&mut self) -> Option<&mut VsSystem> {
+        match &mut self.hardware {
+            Hardware::Vs(vs) => Some(vs),
+            _ => None,
+        }
+    }
+
+    /// Handle a write to $4016, where the VS System's cartridges see bits 0-2
+    pub fn write_outputs(&mut self, value: u8) {
+        if let Hardware::Vs(vs) = &mut self.hardware {
+            vs.bank_select = value & 0b100 != 0;
+        }
+    }
+
+    /// Bits 1-7 of reading $4016 or $4017, for the VS System, indexed from 0
+    ///
+    /// Returns `None` for other cartridges, where those bits come from the controller ports.
+    pub fn read_inputs(&self, port: usize) -> Option<u8> {
+        self.vs_system().map(|vs| vs.read_inputs(port))
+    }
+}
+
+impl_state!(VsSystem {
+    service_button,
+    coin_cycles,
+    coin_counter_line,
+    coins_counted,
+    bank_select,
+});
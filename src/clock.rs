@@ -0,0 +1,182 @@
+//! Master clock the CPU and PPU clocks are derived from
+//!
+//! The console has a single crystal oscillator, and the CPU and the PPU divide its frequency
+//! by different amounts depending on the region.
+//! Keeping time in master clock cycles keeps every component in sync,
+//! even when there isn't a whole number of PPU dots per CPU cycle, like the 3.2 on PAL consoles.
+//!
+//! Details at https://www.nesdev.org/wiki/Cycle_reference_chart
+
+use core::fmt::{Display, Formatter};
+
+use crate::{
+    ppu::DOTS_PER_SCANLINE,
+    state::{impl_state, State, StateError, StateReader, StateWriter},
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// Famiclone sold in Russia, with PAL frequencies but NTSC-like CPU and APU timing
+    Dendy,
+}
+
+impl Region {
+    /// Frequency of the master clock in Hz
+    pub fn master_clock_rate(self) -> u32 {
+        match self {
+            Region::Ntsc => 21_477_272,
+            Region::Pal | Region::Dendy => 26_601_712,
+        }
+    }
+
+    /// Master clock cycles per CPU cycle
+    pub fn cpu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Master clock cycles per PPU dot
+    pub fn ppu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    /// Frequency of the CPU clock in Hz, rounded down
+    pub fn cpu_clock_rate(self) -> u32 {
+        self.master_clock_rate() / self.cpu_divider()
+    }
+
+    /// Scanlines per frame, including the pre-render scanline
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// Scanline at the start of which the VBlank flag is set
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            // the Dendy waits 51 idle scanlines before VBlank, so that it comes at the same time after rendering as on NTSC
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Frames per second as a numerator and denominator, ignoring the dot skipped on odd frames
+    pub fn frame_rate(self) -> (u32, u32) {
+        let dots = DOTS_PER_SCANLINE as u32 * self.scanlines_per_frame() as u32;
+        (self.master_clock_rate(), self.ppu_divider() * dots)
+    }
+
+    /// Whether every other frame is a dot shorter when rendering is enabled
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Keeps track of how far along the CPU and the PPU are, in master clock cycles
+#[derive(Debug, Clone)]
+pub struct MasterClock {
+    region: Region,
+    /// Master clock cycles since power on, the CPU is always at this point
+    cycle: u64,
+    /// Master clock cycle the PPU has been run up to
+    ppu_cycle: u64,
+}
+
+impl MasterClock {
+    pub fn new(region: Region) -> Self {
+        Self {
+            region,
+            cycle: 0,
+            ppu_cycle: 0,
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Master clock cycles since power on
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Advance the clock by one CPU cycle, returns how many PPU dots fall into it
+    pub fn advance_cpu_cycle(&mut self) -> u32 {
+        self.cycle += self.region.cpu_divider() as u64;
+
+        let ppu_divider = self.region.ppu_divider() as u64;
+        let mut dots = 0;
+        while self.ppu_cycle + ppu_divider <= self.cycle {
+            self.ppu_cycle += ppu_divider;
+            dots += 1;
+        }
+        dots
+    }
+
+    /// CPU cycles it takes for the PPU to run the given number of dots, rounded up
+    pub fn cpu_cycles_for_dots(&self, dots: u64) -> u64 {
+        (dots * self.region.ppu_divider() as u64).div_ceil(self.region.cpu_divider() as u64)
+    }
+
+    /// CPU cycles that can run while the PPU runs fewer than the given number of dots, rounded down
+    pub fn cpu_cycles_before_dots(&self, dots: u64) -> u64 {
+        // depending on where the clock is, a cycle can have one dot more than the average
+        (dots.saturating_sub(2) * self.region.ppu_divider() as u64)
+            / self.region.cpu_divider() as u64
+    }
+}
+
+impl Default for MasterClock {
+    fn default() -> Self {
+        Self::new(Region::default())
+    }
+}
+
+impl State for Region {
+    fn save_state(&self, writer: &mut StateWriter) {
+        (*self as u8).save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut region = 0u8;
+        region.load_state(reader)?;
+        *self = match region {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Dendy,
+            _ => return Err(StateError::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+impl_state!(MasterClock {
+    region,
+    cycle,
+    ppu_cycle
+});
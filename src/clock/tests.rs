@@ -0,0 +1,37 @@
+use super::{MasterClock, Region};
+
+#[test]
+fn ntsc() {
+    let mut clock = MasterClock::new(Region::Ntsc);
+    for _ in 0..100 {
+        assert_eq!(clock.advance_cpu_cycle(), 3);
+    }
+    assert_eq!(clock.cycle(), 1200);
+    assert_eq!(Region::Ntsc.cpu_clock_rate(), 1_789_772);
+    let (frames, seconds) = Region::Ntsc.frame_rate();
+    assert_eq!(frames / seconds, 60);
+}
+
+#[test]
+fn pal() {
+    let mut clock = MasterClock::new(Region::Pal);
+    // 16 dots every 5 CPU cycles
+    let dots: Vec<_> = (0..10).map(|_| clock.advance_cpu_cycle()).collect();
+    assert_eq!(dots, [3, 3, 3, 3, 4, 3, 3, 3, 3, 4]);
+    assert_eq!(clock.cpu_cycles_for_dots(16), 5);
+    assert_eq!(clock.cpu_cycles_for_dots(17), 6);
+    // whatever the phase of the clock, 5 cycles never reach 18 dots
+    assert_eq!(clock.cpu_cycles_before_dots(18), 5);
+    assert_eq!(clock.cpu_cycles_before_dots(1), 0);
+}
+
+#[test]
+fn dendy() {
+    let mut clock = MasterClock::new(Region::Dendy);
+    // exactly 3 dots per CPU cycle like NTSC, at the PAL frequency
+    for _ in 0..100 {
+        assert_eq!(clock.advance_cpu_cycle(), 3);
+    }
+    assert_eq!(Region::Dendy.cpu_clock_rate(), 1_773_447);
+    assert_eq!(Region::Dendy.scanlines_per_frame(), 312);
+}
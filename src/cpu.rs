@@ -1,14 +1,19 @@
-use std::ops::ControlFlow;
+use core::ops::ControlFlow;
 
 use bitflags::bitflags;
-use dispatch::{dispatch_current_opcode, OpCode};
+use dispatch::dispatch_current_opcode;
 
-use crate::memory::MemoryMapping;
+#[cfg(feature = "console")]
+use crate::state::{impl_state_bits, State, StateError, StateReader, StateWriter};
+use crate::{logging, memory::Memory};
 
 mod dispatch;
+mod opcode;
 #[cfg(test)]
 mod tests;
 
+pub use opcode::{AddressingMode, OpCode};
+
 bitflags! {
     /// Status Flags used by the Processor Status register
     ///
@@ -22,13 +27,7 @@ bitflags! {
         const INTERRUPT_DISABLE = 1 << 2;
         const DECIMAL = 1 << 3;
         const BREAK = 1 << 4;
-        /// Flag that isn't actually there in the hardware
-        ///
-        /// The emulator uses this flag in some places where it needs to store persist some simple
-        /// boolean state between instruction cycles (i.e. absolute indexed addressing, where
-        /// we add an additional cycle when a page boundary is crossed)
-        ///
-        /// Always pushed to the stack as 1
+        /// Flag that isn't actually there in the hardware, always pushed to the stack as 1
         const IGNORED_FLAG = 1 << 5;
         const OVERFLOW = 1 << 6;
         const NEGATIVE = 1 << 7;
@@ -41,17 +40,90 @@ impl Default for StatusFlags {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Events that make the CPU stop what it's doing and jump to a handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Reset,
+    Nmi,
+    Irq,
+}
+
+impl Interrupt {
+    /// Address of the handler's address
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::Nmi => 0xFFFA,
+            Interrupt::Reset => 0xFFFC,
+            Interrupt::Irq => 0xFFFE,
+        }
+    }
+}
+
+/// Which chip of the 6502 family the core behaves like
+///
+/// The NES uses the Ricoh 2A03, an NMOS 6502 with decimal mode cut out.
+/// The other variants let the core run software for other 6502 machines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// The NES CPU, `ADC` and `SBC` ignore the decimal flag
+    #[default]
+    Ricoh2A03,
+    /// The original NMOS 6502, with decimal mode and the same unofficial opcodes as the 2A03
+    Nmos6502,
+    /// The CMOS 65C02, without Rockwell's and WDC's bit instructions, `WAI` and `STP`
+    ///
+    /// It adds `BRA`, `PHX`, `PHY`, `PLX`, `PLY`, `STZ`, `TRB`, `TSB`, `INC A`, `DEC A`,
+    /// the `(zp)` addressing mode and more modes for `BIT`, and `JMP (abs,X)`.
+    /// `JMP (abs)` no longer wraps the pointer within its page, read-modify-write instructions
+    /// read the address twice instead of writing it twice, interrupts clear the decimal flag,
+    /// and decimal mode sets the negative and zero flags from the result.
+    /// The unofficial opcodes of the NMOS chips are all `NOP`s.
+    ///
+    /// Cycle counts are the same as the NMOS chips, except for `JMP (abs)` and the new instructions.
+    /// The extra cycle of `ADC` and `SBC` in decimal mode and the shorter shifts with absolute X
+    /// addressing aren't emulated.
+    Cmos65C02,
+}
+
+impl Variant {
+    /// Whether `ADC` and `SBC` work on BCD numbers when the decimal flag is set
+    pub fn has_decimal_mode(self) -> bool {
+        self != Variant::Ricoh2A03
+    }
+}
+
+/// Values of the registers, to make a CPU in a known state with [`CpuState::with_registers`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Registers {
+    pub accumulator: u8,
+    pub x_index: u8,
+    pub y_index: u8,
+    pub program_counter: u16,
+    pub stack_ptr: u8,
+    pub flags: StatusFlags,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
 pub struct CpuState {
     /// The currently executed instruction
     current_opcode: OpCode,
 
+    /// Interrupt sequence being executed instead of an instruction
+    current_interrupt: Option<Interrupt>,
+
     /// Which cycle we're on within the current instruction
     current_cycle: u8,
 
     /// Used in various instructions that calculate the address to dereference
     effective_address: u16,
 
+    /// Value read in an earlier cycle, for read-modify-write instructions, branches and `JMP` indirect
+    data_latch: u8,
+
+    /// Whether adding the index to `effective_address` carried into the high byte,
+    /// which is only fixed up in the next cycle
+    page_crossed: bool,
+
     /// Accumulator register
     pub accumulator: u8,
 
@@ -69,6 +141,21 @@ pub struct CpuState {
 
     /// Processor Status
     pub flags: StatusFlags,
+
+    reset_pending: bool,
+    /// NMI is edge triggered, this is set on the falling edge of the NMI line
+    nmi_pending: bool,
+    /// Set instead of `nmi_pending` on an edge in the last cycle of an instruction,
+    /// which is too late for the poll before the next one
+    nmi_delayed: bool,
+    nmi_line: bool,
+    /// IRQ is level triggered, the CPU handles it as long as the line is held
+    irq_line: bool,
+    /// The I flag at the start of the last instruction, see [`CpuState::irq_inhibited`]
+    previous_interrupt_disable: bool,
+
+    /// Not part of the state, it's the chip itself
+    variant: Variant,
 }
 
 impl CpuState {
@@ -76,8 +163,191 @@ impl CpuState {
         Self::default()
     }
 
+    /// A CPU that behaves like another chip of the 6502 family than the NES one
+    pub fn with_variant(variant: Variant) -> Self {
+        Self {
+            variant,
+            ..Self::default()
+        }
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// A CPU about to run the instruction at `program_counter`,
+    /// with the stack pointer and the flags as the reset sequence leaves them
+    pub fn with_pc(program_counter: u16) -> Self {
+        Self::with_registers(Registers {
+            program_counter,
+            stack_ptr: 0xFD,
+            ..Registers::default()
+        })
+    }
+
+    /// A CPU that just went through the reset sequence, about to run the handler `memory` points to
+    pub fn from_reset_vector<M: Memory>(memory: &mut M) -> Self {
+        let vector = Interrupt::Reset.vector();
+        let program_counter = u16::from_le_bytes([memory.load(vector), memory.load(vector + 1)]);
+        Self::with_pc(program_counter)
+    }
+
+    /// A CPU about to run an instruction with the given registers, mostly for tests
+    pub fn with_registers(registers: Registers) -> Self {
+        let Registers {
+            accumulator,
+            x_index,
+            y_index,
+            program_counter,
+            stack_ptr,
+            flags,
+        } = registers;
+        Self {
+            accumulator,
+            x_index,
+            y_index,
+            program_counter,
+            stack_ptr,
+            flags,
+            previous_interrupt_disable: flags.contains(StatusFlags::INTERRUPT_DISABLE),
+            ..Self::default()
+        }
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            accumulator: self.accumulator,
+            x_index: self.x_index,
+            y_index: self.y_index,
+            program_counter: self.program_counter,
+            stack_ptr: self.stack_ptr,
+            flags: self.flags,
+        }
+    }
+
+    /// Pull the reset line, the reset sequence will run instead of the next instruction
+    pub fn reset(&mut self) {
+        self.reset_pending = true;
+    }
+
+    /// Set the state of the NMI line, `true` means an NMI is being requested
+    ///
+    /// The line is sampled at the end of the cycle that was just run. An edge stays pending
+    /// even if the line goes back before the NMI is handled, and one that comes in the last cycle
+    /// of an instruction is only handled after the next instruction.
+    ///
+    /// Details at https://www.nesdev.org/wiki/CPU_interrupts#Detailed_interrupt_behavior
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        self.sample_nmi_line(asserted, self.current_cycle == 0);
+    }
+
+    /// Set the state of the NMI line as it was sampled in an earlier cycle,
+    /// for when the CPU has already run ahead of the rest of the console
+    pub(crate) fn sample_nmi_line(&mut self, asserted: bool, in_last_cycle: bool) {
+        if asserted && !self.nmi_line {
+            if in_last_cycle {
+                self.nmi_delayed = true;
+            } else {
+                self.nmi_pending = true;
+            }
+        }
+        self.nmi_line = asserted;
+    }
+
+    /// Set the state of the IRQ line, `true` means an IRQ is being requested
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Which cycle we're on within the current instruction, 0 means the next opcode will be fetched
+    #[cfg(feature = "console")]
+    pub(crate) fn current_cycle(&self) -> u8 {
+        self.current_cycle
+    }
+
+    /// Whether an instruction or an interrupt sequence was started and isn't finished yet
+    pub fn instruction_in_progress(&self) -> bool {
+        self.current_cycle != 0
+    }
+
+    /// Cycles of the current instruction or interrupt sequence that were already run, 0 between instructions
+    pub fn cycles_into_instruction(&self) -> u8 {
+        self.current_cycle
+    }
+
+    /// Cycles left until the current instruction or interrupt sequence is finished, 0 between instructions
+    ///
+    /// Is `None` if the CPU is locked up by a `JAM`. The extra cycles of crossing a page or taking
+    /// a branch aren't known in advance, until then they're not counted. The counts are those of
+    /// the NMOS chips, so they can be off for the instructions the 65C02 changed.
+    pub fn remaining_cycles(&self) -> Option<u8> {
+        if !self.instruction_in_progress() {
+            return Some(0);
+        }
+        let cycles = match self.current_interrupt {
+            Some(_) => 7,
+            None => self.current_opcode.cycles()?,
+        };
+        // there's at least one cycle left when the extra cycles weren't counted
+        Some(cycles.saturating_sub(self.current_cycle).max(1))
+    }
+
+    /// Opcode of the instruction being run, or the one that just finished between instructions
+    #[cfg(feature = "console")]
+    pub(crate) fn current_opcode(&self) -> u8 {
+        self.current_opcode.into()
+    }
+
+    /// Interrupt sequence being run instead of an instruction
+    ///
+    /// Between instructions this is the one that just finished, if it wasn't an instruction.
+    pub fn current_interrupt(&self) -> Option<Interrupt> {
+        self.current_interrupt
+    }
+
+    /// Whether the I flag keeps an IRQ from being handled before the next instruction
+    ///
+    /// The CPU polls for interrupts before `CLI`, `SEI` and `PLP` change the flag in their last cycle,
+    /// so the change only counts after the next instruction: an IRQ can still come right after `SEI`,
+    /// and has to wait an instruction after `CLI`. `RTI` changes the flag earlier and counts right away.
+    ///
+    /// Details at https://www.nesdev.org/wiki/CPU_interrupts#Delayed_IRQ_response_after_CLI,_SEI,_and_PLP
+    fn irq_inhibited(&self) -> bool {
+        let delayed = self.current_interrupt.is_none()
+            && matches!(self.current_opcode, OpCode::Cli | OpCode::Sei | OpCode::Plp);
+        if delayed {
+            self.previous_interrupt_disable
+        } else {
+            self.flags.contains(StatusFlags::INTERRUPT_DISABLE)
+        }
+    }
+
+    /// Decide which interrupt, if any, should run instead of the next instruction
+    fn poll_interrupts(&mut self) -> Option<Interrupt> {
+        let irq_inhibited = self.irq_inhibited();
+        self.previous_interrupt_disable = self.flags.contains(StatusFlags::INTERRUPT_DISABLE);
+        let interrupt = if self.reset_pending {
+            self.reset_pending = false;
+            Some(Interrupt::Reset)
+        } else if self.nmi_pending {
+            // the edge detector only has one latch, so a delayed edge counts as the same NMI
+            self.nmi_pending = false;
+            self.nmi_delayed = false;
+            Some(Interrupt::Nmi)
+        } else if self.irq_line && !irq_inhibited {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
+        self.nmi_pending |= core::mem::take(&mut self.nmi_delayed);
+        if let Some(interrupt) = interrupt {
+            logging::trace!("{interrupt:?} at ${:04X}", self.program_counter);
+        }
+        interrupt
+    }
+
     /// Advances the CPU state one clock cycle forward
-    pub fn run_cycle(&mut self, memory: &mut MemoryMapping) {
+    pub fn run_cycle<M: Memory>(&mut self, memory: &mut M) {
         let instruction_status = dispatch_current_opcode(self, memory);
 
         match instruction_status {
@@ -91,18 +361,79 @@ impl CpuState {
     }
 }
 
-impl Default for CpuState {
-    fn default() -> Self {
-        CpuState {
-            current_opcode: OpCode::Unimplemented,
-            current_cycle: 0,
-            effective_address: 0,
-            accumulator: 0,
-            x_index: 0,
-            y_index: 0,
-            program_counter: 0,
-            stack_ptr: 0,
-            flags: StatusFlags::default(),
+#[cfg(feature = "console")]
+impl_state_bits!(StatusFlags);
+
+#[cfg(feature = "console")]
+impl State for CpuState {
+    fn save_state(&self, writer: &mut StateWriter) {
+        u8::from(self.current_opcode).save_state(writer);
+        let interrupt = match self.current_interrupt {
+            None => 0u8,
+            Some(Interrupt::Reset) => 1,
+            Some(Interrupt::Nmi) => 2,
+            Some(Interrupt::Irq) => 3,
+        };
+        interrupt.save_state(writer);
+        self.current_cycle.save_state(writer);
+        self.effective_address.save_state(writer);
+        self.accumulator.save_state(writer);
+        self.x_index.save_state(writer);
+        self.y_index.save_state(writer);
+        self.program_counter.save_state(writer);
+        self.stack_ptr.save_state(writer);
+        self.flags.save_state(writer);
+        self.reset_pending.save_state(writer);
+        self.nmi_pending.save_state(writer);
+        self.nmi_line.save_state(writer);
+        self.irq_line.save_state(writer);
+        self.data_latch.save_state(writer);
+        self.previous_interrupt_disable.save_state(writer);
+        self.nmi_delayed.save_state(writer);
+        self.page_crossed.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut opcode = 0u8;
+        opcode.load_state(reader)?;
+        self.current_opcode = OpCode::from(opcode);
+        let mut interrupt = 0u8;
+        interrupt.load_state(reader)?;
+        self.current_interrupt = match interrupt {
+            0 => None,
+            1 => Some(Interrupt::Reset),
+            2 => Some(Interrupt::Nmi),
+            3 => Some(Interrupt::Irq),
+            _ => return Err(StateError::InvalidData),
+        };
+        self.current_cycle.load_state(reader)?;
+        self.effective_address.load_state(reader)?;
+        self.accumulator.load_state(reader)?;
+        self.x_index.load_state(reader)?;
+        self.y_index.load_state(reader)?;
+        self.program_counter.load_state(reader)?;
+        self.stack_ptr.load_state(reader)?;
+        self.flags.load_state(reader)?;
+        self.reset_pending.load_state(reader)?;
+        self.nmi_pending.load_state(reader)?;
+        self.nmi_line.load_state(reader)?;
+        self.irq_line.load_state(reader)?;
+        self.data_latch.load_state(reader)?;
+        if reader.version() >= 5 {
+            self.previous_interrupt_disable.load_state(reader)?;
+        } else {
+            self.previous_interrupt_disable = self.flags.contains(StatusFlags::INTERRUPT_DISABLE);
+        }
+        if reader.version() >= 6 {
+            self.nmi_delayed.load_state(reader)?;
+        }
+        if reader.version() >= 7 {
+            self.page_crossed.load_state(reader)?;
+        } else {
+            // older versions kept it in the unused flag
+            self.page_crossed = self.flags.contains(StatusFlags::IGNORED_FLAG);
+            self.flags.remove(StatusFlags::IGNORED_FLAG);
         }
+        Ok(())
     }
 }
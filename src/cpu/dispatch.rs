@@ -1,43 +1,298 @@
-use super::CpuState;
-use crate::memory::MemoryMapping;
+use super::{CpuState, OpCode, StatusFlags, Variant};
+use crate::memory::Memory;
+use cmos::{dispatch_cmos_opcode, is_single_cycle_nop};
+use core::ops::ControlFlow;
 use helpers::fetch_from_pc;
-use num_enum::{FromPrimitive, IntoPrimitive};
-use std::ops::ControlFlow;
 
+mod cmos;
 pub(in crate::cpu) mod instructions;
 use instructions::*;
 
-#[derive(Debug, Clone, Copy, IntoPrimitive, FromPrimitive, Default)]
-#[non_exhaustive]
-#[repr(u8)]
-pub enum OpCode {
-    LdxImmediate = 0xA2,
-    LdxZeroPage = 0xA6,
-    LdxZeroPageY = 0xB6,
-    LdxAbs = 0xAE,
-    LdxAbsY = 0xBE,
-
-    #[default]
-    Unimplemented,
-}
-
-pub fn dispatch_current_opcode(
+pub fn dispatch_current_opcode<M: Memory>(
     cpu_state: &mut CpuState,
-    memory: &mut MemoryMapping,
+    memory: &mut M,
 ) -> ControlFlow<()> //
 {
     // First cycle is always fetching the opcode
     if cpu_state.current_cycle == 0 {
-        cpu_state.current_opcode = OpCode::from(fetch_from_pc(cpu_state, memory));
+        cpu_state.current_interrupt = cpu_state.poll_interrupts();
+        if cpu_state.current_interrupt.is_some() {
+            // the opcode is still fetched, but ignored, and the PC isn't incremented
+            let _ = memory.fetch(cpu_state.program_counter);
+        } else {
+            let opcode = fetch_from_pc(cpu_state, memory);
+            cpu_state.current_opcode = OpCode::from(opcode);
+            if cpu_state.variant == Variant::Cmos65C02 && is_single_cycle_nop(opcode) {
+                return ControlFlow::Break(());
+            }
+        }
         return ControlFlow::Continue(());
     }
+    if let Some(current_interrupt) = cpu_state.current_interrupt {
+        return interrupt(cpu_state, memory, current_interrupt);
+    }
+    if cpu_state.variant == Variant::Cmos65C02 {
+        if let Some(status) = dispatch_cmos_opcode(cpu_state, memory) {
+            return status;
+        }
+    }
     match cpu_state.current_opcode {
-        OpCode::LdxImmediate => ldx_immediate(cpu_state, memory),
-        OpCode::LdxZeroPage => ldx_zeropage(cpu_state, memory),
-        OpCode::LdxZeroPageY => ldx_zeropage_y(cpu_state, memory),
-        OpCode::LdxAbs => ldx_absolute(cpu_state, memory),
-        OpCode::LdxAbsY => ldx_absolute_y(cpu_state, memory),
+        OpCode::AdcIndirectX => read_indirect_x(cpu_state, memory, adc),
+        OpCode::AdcZeroPage => read_zeropage(cpu_state, memory, adc),
+        OpCode::AdcImmediate => read_immediate(cpu_state, memory, adc),
+        OpCode::AdcAbs => read_absolute(cpu_state, memory, adc),
+        OpCode::AdcIndirectY => read_indirect_y(cpu_state, memory, adc),
+        OpCode::AdcZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, adc),
+        OpCode::AdcAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, adc),
+        OpCode::AdcAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, adc),
+        OpCode::AndIndirectX => read_indirect_x(cpu_state, memory, and),
+        OpCode::AndZeroPage => read_zeropage(cpu_state, memory, and),
+        OpCode::AndImmediate => read_immediate(cpu_state, memory, and),
+        OpCode::AndAbs => read_absolute(cpu_state, memory, and),
+        OpCode::AndIndirectY => read_indirect_y(cpu_state, memory, and),
+        OpCode::AndZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, and),
+        OpCode::AndAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, and),
+        OpCode::AndAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, and),
+        OpCode::AslZeroPage => modify_zeropage(cpu_state, memory, asl),
+        OpCode::AslAccumulator => modify_accumulator(cpu_state, memory, asl),
+        OpCode::AslAbs => modify_absolute(cpu_state, memory, asl),
+        OpCode::AslZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, asl),
+        OpCode::AslAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, asl),
+        OpCode::Bcc => branch(cpu_state, memory, StatusFlags::CARRY, false),
+        OpCode::Bcs => branch(cpu_state, memory, StatusFlags::CARRY, true),
+        OpCode::Beq => branch(cpu_state, memory, StatusFlags::ZERO, true),
+        OpCode::BitZeroPage => read_zeropage(cpu_state, memory, bit),
+        OpCode::BitAbs => read_absolute(cpu_state, memory, bit),
+        OpCode::Bmi => branch(cpu_state, memory, StatusFlags::NEGATIVE, true),
+        OpCode::Bne => branch(cpu_state, memory, StatusFlags::ZERO, false),
+        OpCode::Bpl => branch(cpu_state, memory, StatusFlags::NEGATIVE, false),
+        OpCode::Brk => brk(cpu_state, memory),
+        OpCode::Bvc => branch(cpu_state, memory, StatusFlags::OVERFLOW, false),
+        OpCode::Bvs => branch(cpu_state, memory, StatusFlags::OVERFLOW, true),
+        OpCode::Clc => implied(cpu_state, memory, clc),
+        OpCode::Cld => implied(cpu_state, memory, cld),
+        OpCode::Cli => implied(cpu_state, memory, cli),
+        OpCode::Clv => implied(cpu_state, memory, clv),
+        OpCode::CmpIndirectX => read_indirect_x(cpu_state, memory, cmp),
+        OpCode::CmpZeroPage => read_zeropage(cpu_state, memory, cmp),
+        OpCode::CmpImmediate => read_immediate(cpu_state, memory, cmp),
+        OpCode::CmpAbs => read_absolute(cpu_state, memory, cmp),
+        OpCode::CmpIndirectY => read_indirect_y(cpu_state, memory, cmp),
+        OpCode::CmpZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, cmp),
+        OpCode::CmpAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, cmp),
+        OpCode::CmpAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, cmp),
+        OpCode::CpxImmediate => read_immediate(cpu_state, memory, cpx),
+        OpCode::CpxZeroPage => read_zeropage(cpu_state, memory, cpx),
+        OpCode::CpxAbs => read_absolute(cpu_state, memory, cpx),
+        OpCode::CpyImmediate => read_immediate(cpu_state, memory, cpy),
+        OpCode::CpyZeroPage => read_zeropage(cpu_state, memory, cpy),
+        OpCode::CpyAbs => read_absolute(cpu_state, memory, cpy),
+        OpCode::DecZeroPage => modify_zeropage(cpu_state, memory, dec),
+        OpCode::DecAbs => modify_absolute(cpu_state, memory, dec),
+        OpCode::DecZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, dec),
+        OpCode::DecAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, dec),
+        OpCode::Dex => implied(cpu_state, memory, dex),
+        OpCode::Dey => implied(cpu_state, memory, dey),
+        OpCode::EorIndirectX => read_indirect_x(cpu_state, memory, eor),
+        OpCode::EorZeroPage => read_zeropage(cpu_state, memory, eor),
+        OpCode::EorImmediate => read_immediate(cpu_state, memory, eor),
+        OpCode::EorAbs => read_absolute(cpu_state, memory, eor),
+        OpCode::EorIndirectY => read_indirect_y(cpu_state, memory, eor),
+        OpCode::EorZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, eor),
+        OpCode::EorAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, eor),
+        OpCode::EorAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, eor),
+        OpCode::IncZeroPage => modify_zeropage(cpu_state, memory, inc),
+        OpCode::IncAbs => modify_absolute(cpu_state, memory, inc),
+        OpCode::IncZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, inc),
+        OpCode::IncAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, inc),
+        OpCode::Inx => implied(cpu_state, memory, inx),
+        OpCode::Iny => implied(cpu_state, memory, iny),
+        OpCode::JmpAbs => jmp_absolute(cpu_state, memory),
+        OpCode::JmpIndirect => jmp_indirect(cpu_state, memory),
+        OpCode::Jsr => jsr(cpu_state, memory),
+        OpCode::LdaIndirectX => read_indirect_x(cpu_state, memory, lda),
+        OpCode::LdaZeroPage => read_zeropage(cpu_state, memory, lda),
+        OpCode::LdaImmediate => read_immediate(cpu_state, memory, lda),
+        OpCode::LdaAbs => read_absolute(cpu_state, memory, lda),
+        OpCode::LdaIndirectY => read_indirect_y(cpu_state, memory, lda),
+        OpCode::LdaZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, lda),
+        OpCode::LdaAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, lda),
+        OpCode::LdaAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, lda),
+        OpCode::LdxImmediate => read_immediate(cpu_state, memory, ldx),
+        OpCode::LdxZeroPage => read_zeropage(cpu_state, memory, ldx),
+        OpCode::LdxAbs => read_absolute(cpu_state, memory, ldx),
+        OpCode::LdxZeroPageY => read_zeropage_indexed(cpu_state, memory, get_y_index, ldx),
+        OpCode::LdxAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, ldx),
+        OpCode::LdyImmediate => read_immediate(cpu_state, memory, ldy),
+        OpCode::LdyZeroPage => read_zeropage(cpu_state, memory, ldy),
+        OpCode::LdyAbs => read_absolute(cpu_state, memory, ldy),
+        OpCode::LdyZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, ldy),
+        OpCode::LdyAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, ldy),
+        OpCode::LsrZeroPage => modify_zeropage(cpu_state, memory, lsr),
+        OpCode::LsrAccumulator => modify_accumulator(cpu_state, memory, lsr),
+        OpCode::LsrAbs => modify_absolute(cpu_state, memory, lsr),
+        OpCode::LsrZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, lsr),
+        OpCode::LsrAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, lsr),
+        OpCode::Nop => implied(cpu_state, memory, nop),
+        OpCode::OraIndirectX => read_indirect_x(cpu_state, memory, ora),
+        OpCode::OraZeroPage => read_zeropage(cpu_state, memory, ora),
+        OpCode::OraImmediate => read_immediate(cpu_state, memory, ora),
+        OpCode::OraAbs => read_absolute(cpu_state, memory, ora),
+        OpCode::OraIndirectY => read_indirect_y(cpu_state, memory, ora),
+        OpCode::OraZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, ora),
+        OpCode::OraAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, ora),
+        OpCode::OraAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, ora),
+        OpCode::Pha => pha(cpu_state, memory),
+        OpCode::Php => php(cpu_state, memory),
+        OpCode::Pla => pla(cpu_state, memory),
+        OpCode::Plp => plp(cpu_state, memory),
+        OpCode::RolZeroPage => modify_zeropage(cpu_state, memory, rol),
+        OpCode::RolAccumulator => modify_accumulator(cpu_state, memory, rol),
+        OpCode::RolAbs => modify_absolute(cpu_state, memory, rol),
+        OpCode::RolZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, rol),
+        OpCode::RolAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, rol),
+        OpCode::RorZeroPage => modify_zeropage(cpu_state, memory, ror),
+        OpCode::RorAccumulator => modify_accumulator(cpu_state, memory, ror),
+        OpCode::RorAbs => modify_absolute(cpu_state, memory, ror),
+        OpCode::RorZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, ror),
+        OpCode::RorAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, ror),
+        OpCode::Rti => rti(cpu_state, memory),
+        OpCode::Rts => rts(cpu_state, memory),
+        OpCode::SbcIndirectX => read_indirect_x(cpu_state, memory, sbc),
+        OpCode::SbcZeroPage => read_zeropage(cpu_state, memory, sbc),
+        OpCode::SbcImmediate => read_immediate(cpu_state, memory, sbc),
+        OpCode::SbcAbs => read_absolute(cpu_state, memory, sbc),
+        OpCode::SbcIndirectY => read_indirect_y(cpu_state, memory, sbc),
+        OpCode::SbcZeroPageX => read_zeropage_indexed(cpu_state, memory, get_x_index, sbc),
+        OpCode::SbcAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, sbc),
+        OpCode::SbcAbsX => read_absolute_indexed(cpu_state, memory, get_x_index, sbc),
+        OpCode::Sec => implied(cpu_state, memory, sec),
+        OpCode::Sed => implied(cpu_state, memory, sed),
+        OpCode::Sei => implied(cpu_state, memory, sei),
+        OpCode::StaIndirectX => write_indirect_x(cpu_state, memory, sta),
+        OpCode::StaZeroPage => write_zeropage(cpu_state, memory, sta),
+        OpCode::StaAbs => write_absolute(cpu_state, memory, sta),
+        OpCode::StaIndirectY => write_indirect_y(cpu_state, memory, sta),
+        OpCode::StaZeroPageX => write_zeropage_indexed(cpu_state, memory, get_x_index, sta),
+        OpCode::StaAbsY => write_absolute_indexed(cpu_state, memory, get_y_index, sta),
+        OpCode::StaAbsX => write_absolute_indexed(cpu_state, memory, get_x_index, sta),
+        OpCode::StxZeroPage => write_zeropage(cpu_state, memory, stx),
+        OpCode::StxAbs => write_absolute(cpu_state, memory, stx),
+        OpCode::StxZeroPageY => write_zeropage_indexed(cpu_state, memory, get_y_index, stx),
+        OpCode::StyZeroPage => write_zeropage(cpu_state, memory, sty),
+        OpCode::StyAbs => write_absolute(cpu_state, memory, sty),
+        OpCode::StyZeroPageX => write_zeropage_indexed(cpu_state, memory, get_x_index, sty),
+        OpCode::Tax => implied(cpu_state, memory, tax),
+        OpCode::Tay => implied(cpu_state, memory, tay),
+        OpCode::Tsx => implied(cpu_state, memory, tsx),
+        OpCode::Txa => implied(cpu_state, memory, txa),
+        OpCode::Txs => implied(cpu_state, memory, txs),
+        OpCode::Tya => implied(cpu_state, memory, tya),
 
-        _ => unimplemented!(),
+        // unofficial
+        OpCode::AlrImmediate => read_immediate(cpu_state, memory, alr),
+        OpCode::AncImmediate0B | OpCode::AncImmediate2B => read_immediate(cpu_state, memory, anc),
+        OpCode::ArrImmediate => read_immediate(cpu_state, memory, arr),
+        OpCode::DcpIndirectX => modify_indirect_x(cpu_state, memory, dcp),
+        OpCode::DcpZeroPage => modify_zeropage(cpu_state, memory, dcp),
+        OpCode::DcpAbs => modify_absolute(cpu_state, memory, dcp),
+        OpCode::DcpIndirectY => modify_indirect_y(cpu_state, memory, dcp),
+        OpCode::DcpZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, dcp),
+        OpCode::DcpAbsY => modify_absolute_indexed(cpu_state, memory, get_y_index, dcp),
+        OpCode::DcpAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, dcp),
+        OpCode::IsbIndirectX => modify_indirect_x(cpu_state, memory, isb),
+        OpCode::IsbZeroPage => modify_zeropage(cpu_state, memory, isb),
+        OpCode::IsbAbs => modify_absolute(cpu_state, memory, isb),
+        OpCode::IsbIndirectY => modify_indirect_y(cpu_state, memory, isb),
+        OpCode::IsbZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, isb),
+        OpCode::IsbAbsY => modify_absolute_indexed(cpu_state, memory, get_y_index, isb),
+        OpCode::IsbAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, isb),
+        OpCode::Jam02
+        | OpCode::Jam12
+        | OpCode::Jam22
+        | OpCode::Jam32
+        | OpCode::Jam42
+        | OpCode::Jam52
+        | OpCode::Jam62
+        | OpCode::Jam72
+        | OpCode::Jam92
+        | OpCode::JamB2
+        | OpCode::JamD2
+        | OpCode::JamF2 => jam(cpu_state, memory),
+        OpCode::LasAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, las),
+        OpCode::LaxIndirectX => read_indirect_x(cpu_state, memory, lax),
+        OpCode::LaxZeroPage => read_zeropage(cpu_state, memory, lax),
+        OpCode::LaxAbs => read_absolute(cpu_state, memory, lax),
+        OpCode::LaxIndirectY => read_indirect_y(cpu_state, memory, lax),
+        OpCode::LaxZeroPageY => read_zeropage_indexed(cpu_state, memory, get_y_index, lax),
+        OpCode::LaxAbsY => read_absolute_indexed(cpu_state, memory, get_y_index, lax),
+        OpCode::LxaImmediate => read_immediate(cpu_state, memory, lxa),
+        OpCode::NopZeroPage04 | OpCode::NopZeroPage44 | OpCode::NopZeroPage64 => {
+            read_zeropage(cpu_state, memory, nop_read)
+        }
+        OpCode::NopAbs0C => read_absolute(cpu_state, memory, nop_read),
+        OpCode::NopZeroPageX14
+        | OpCode::NopZeroPageX34
+        | OpCode::NopZeroPageX54
+        | OpCode::NopZeroPageX74
+        | OpCode::NopZeroPageXD4
+        | OpCode::NopZeroPageXF4 => read_zeropage_indexed(cpu_state, memory, get_x_index, nop_read),
+        OpCode::NopAbsX1C
+        | OpCode::NopAbsX3C
+        | OpCode::NopAbsX5C
+        | OpCode::NopAbsX7C
+        | OpCode::NopAbsXDC
+        | OpCode::NopAbsXFC => read_absolute_indexed(cpu_state, memory, get_x_index, nop_read),
+        OpCode::NopImmediate80
+        | OpCode::NopImmediate82
+        | OpCode::NopImmediate89
+        | OpCode::NopImmediateC2
+        | OpCode::NopImmediateE2 => read_immediate(cpu_state, memory, nop_read),
+        OpCode::Nop1A
+        | OpCode::Nop3A
+        | OpCode::Nop5A
+        | OpCode::Nop7A
+        | OpCode::NopDA
+        | OpCode::NopFA => implied(cpu_state, memory, nop),
+        OpCode::RlaIndirectX => modify_indirect_x(cpu_state, memory, rla),
+        OpCode::RlaZeroPage => modify_zeropage(cpu_state, memory, rla),
+        OpCode::RlaAbs => modify_absolute(cpu_state, memory, rla),
+        OpCode::RlaIndirectY => modify_indirect_y(cpu_state, memory, rla),
+        OpCode::RlaZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, rla),
+        OpCode::RlaAbsY => modify_absolute_indexed(cpu_state, memory, get_y_index, rla),
+        OpCode::RlaAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, rla),
+        OpCode::RraIndirectX => modify_indirect_x(cpu_state, memory, rra),
+        OpCode::RraZeroPage => modify_zeropage(cpu_state, memory, rra),
+        OpCode::RraAbs => modify_absolute(cpu_state, memory, rra),
+        OpCode::RraIndirectY => modify_indirect_y(cpu_state, memory, rra),
+        OpCode::RraZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, rra),
+        OpCode::RraAbsY => modify_absolute_indexed(cpu_state, memory, get_y_index, rra),
+        OpCode::RraAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, rra),
+        OpCode::SaxIndirectX => write_indirect_x(cpu_state, memory, sax),
+        OpCode::SaxZeroPage => write_zeropage(cpu_state, memory, sax),
+        OpCode::SaxAbs => write_absolute(cpu_state, memory, sax),
+        OpCode::SaxZeroPageY => write_zeropage_indexed(cpu_state, memory, get_y_index, sax),
+        OpCode::SbcImmediateEB => read_immediate(cpu_state, memory, sbc),
+        OpCode::SbxImmediate => read_immediate(cpu_state, memory, sbx),
+        OpCode::ShaIndirectY => sha_indirect_y(cpu_state, memory),
+        OpCode::ShaAbsY => sha_absolute_y(cpu_state, memory),
+        OpCode::ShxAbsY => shx(cpu_state, memory),
+        OpCode::ShyAbsX => shy(cpu_state, memory),
+        OpCode::SloIndirectX => modify_indirect_x(cpu_state, memory, slo),
+        OpCode::SloZeroPage => modify_zeropage(cpu_state, memory, slo),
+        OpCode::SloAbs => modify_absolute(cpu_state, memory, slo),
+        OpCode::SloIndirectY => modify_indirect_y(cpu_state, memory, slo),
+        OpCode::SloZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, slo),
+        OpCode::SloAbsY => modify_absolute_indexed(cpu_state, memory, get_y_index, slo),
+        OpCode::SloAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, slo),
+        OpCode::SreIndirectX => modify_indirect_x(cpu_state, memory, sre),
+        OpCode::SreZeroPage => modify_zeropage(cpu_state, memory, sre),
+        OpCode::SreAbs => modify_absolute(cpu_state, memory, sre),
+        OpCode::SreIndirectY => modify_indirect_y(cpu_state, memory, sre),
+        OpCode::SreZeroPageX => modify_zeropage_indexed(cpu_state, memory, get_x_index, sre),
+        OpCode::SreAbsY => modify_absolute_indexed(cpu_state, memory, get_y_index, sre),
+        OpCode::SreAbsX => modify_absolute_indexed(cpu_state, memory, get_x_index, sre),
+        OpCode::TasAbsY => tas(cpu_state, memory),
+        OpCode::XaaImmediate => read_immediate(cpu_state, memory, xaa),
     }
 }
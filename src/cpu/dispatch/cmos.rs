@@ -0,0 +1,65 @@
+//! Opcodes the 65C02 changed, dispatched before the ones it shares with the NMOS chips
+//!
+//! Most of the unofficial NMOS opcodes became new instructions,
+//! the rest are `NOP`s with the size and timing the 65C02 gives them.
+//!
+//! Details at http://www.6502.org/tutorials/65c02opcodes.html
+
+use core::ops::ControlFlow;
+
+use super::{instructions::*, CpuState, StatusFlags};
+use crate::memory::Memory;
+
+/// Opcodes in the columns `$x3`, `$x7`, `$xB` and `$xF`, which are all `NOP`s that take one cycle
+pub fn is_single_cycle_nop(opcode: u8) -> bool {
+    opcode & 0x03 == 0x03
+}
+
+/// Run a cycle of an opcode that the 65C02 doesn't share with the NMOS chips,
+/// or `None` if it's one they share
+pub fn dispatch_cmos_opcode<M: Memory>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+) -> Option<ControlFlow<()>> //
+{
+    let status = match u8::from(cpu_state.current_opcode) {
+        0x04 => modify_zeropage(cpu_state, memory, tsb),
+        0x0C => modify_absolute(cpu_state, memory, tsb),
+        0x12 => read_indirect(cpu_state, memory, ora),
+        0x14 => modify_zeropage(cpu_state, memory, trb),
+        0x1A => modify_accumulator(cpu_state, memory, inc),
+        0x1C => modify_absolute(cpu_state, memory, trb),
+        0x32 => read_indirect(cpu_state, memory, and),
+        0x34 => read_zeropage_indexed(cpu_state, memory, get_x_index, bit),
+        0x3A => modify_accumulator(cpu_state, memory, dec),
+        0x3C => read_absolute_indexed(cpu_state, memory, get_x_index, bit),
+        0x52 => read_indirect(cpu_state, memory, eor),
+        0x5A => phy(cpu_state, memory),
+        0x5C => nop_5c(cpu_state, memory),
+        0x64 => write_zeropage(cpu_state, memory, stz),
+        0x72 => read_indirect(cpu_state, memory, adc),
+        0x74 => write_zeropage_indexed(cpu_state, memory, get_x_index, stz),
+        0x7A => ply(cpu_state, memory),
+        0x7C => jmp_indirect_fixed(cpu_state, memory, cpu_state.x_index),
+        // branch always, every status contains the empty set of flags
+        0x80 => branch(cpu_state, memory, StatusFlags::empty(), true),
+        0x89 => read_immediate(cpu_state, memory, bit_immediate),
+        0x92 => write_indirect(cpu_state, memory, sta),
+        0x9C => write_absolute(cpu_state, memory, stz),
+        0x9E => write_absolute_indexed(cpu_state, memory, get_x_index, stz),
+        0xB2 => read_indirect(cpu_state, memory, lda),
+        0xD2 => read_indirect(cpu_state, memory, cmp),
+        0xDA => phx(cpu_state, memory),
+        0xF2 => read_indirect(cpu_state, memory, sbc),
+        0xFA => plx(cpu_state, memory),
+
+        0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xC2 | 0xE2 => {
+            read_immediate(cpu_state, memory, nop_read)
+        }
+        0x44 => read_zeropage(cpu_state, memory, nop_read),
+        0x54 | 0xD4 | 0xF4 => read_zeropage_indexed(cpu_state, memory, get_x_index, nop_read),
+        0xDC | 0xFC => read_absolute(cpu_state, memory, nop_read),
+        _ => return None,
+    };
+    Some(status)
+}
@@ -17,41 +17,762 @@
 //! Cycle 0 is always fetching the opcode, every match should start with cycle 1
 //! Last match arm should always return ControlFlow::Break(());
 
-use crate::{cpu::CpuState, memory::MemoryMapping};
-use std::ops::ControlFlow;
+use crate::{
+    cpu::{CpuState, Interrupt, StatusFlags, Variant},
+    logging,
+    memory::Memory,
+};
+use core::ops::ControlFlow;
 
+mod decimal;
 pub(in crate::cpu) mod helpers;
 mod templates;
 use helpers::*;
-use templates::*;
+pub use templates::*;
 
-fn get_y_index(cpu_state: &CpuState) -> u8 {
+pub fn get_x_index(cpu_state: &CpuState) -> u8 {
+    cpu_state.x_index
+}
+
+pub fn get_y_index(cpu_state: &CpuState) -> u8 {
     cpu_state.y_index
 }
 
-// LDX
+// Loads and stores
+
+pub fn lda(cpu_state: &mut CpuState, value: u8) {
+    set_register(&mut cpu_state.accumulator, value, &mut cpu_state.flags);
+}
 
-/// Closure passed into LDX templates
-fn ldx_common(cpu_state: &mut CpuState, value: u8) {
+pub fn ldx(cpu_state: &mut CpuState, value: u8) {
     set_register(&mut cpu_state.x_index, value, &mut cpu_state.flags);
 }
 
-pub fn ldx_immediate(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> ControlFlow<()> {
-    read_immediate(cpu_state, memory, ldx_common)
+pub fn ldy(cpu_state: &mut CpuState, value: u8) {
+    set_register(&mut cpu_state.y_index, value, &mut cpu_state.flags);
+}
+
+pub fn sta(cpu_state: &CpuState) -> u8 {
+    cpu_state.accumulator
+}
+
+pub fn stx(cpu_state: &CpuState) -> u8 {
+    cpu_state.x_index
+}
+
+pub fn sty(cpu_state: &CpuState) -> u8 {
+    cpu_state.y_index
+}
+
+// Arithmetic and logic
+
+pub fn adc(cpu_state: &mut CpuState, value: u8) {
+    if decimal::is_enabled(cpu_state) {
+        decimal::adc(cpu_state, value);
+    } else {
+        add(cpu_state, value);
+    }
+}
+
+pub fn sbc(cpu_state: &mut CpuState, value: u8) {
+    if decimal::is_enabled(cpu_state) {
+        decimal::sbc(cpu_state, value);
+    } else {
+        add(cpu_state, !value);
+    }
+}
+
+/// Binary addition with the carry, subtracting is adding the complement
+fn add(cpu_state: &mut CpuState, value: u8) {
+    let accumulator = cpu_state.accumulator;
+    let sum =
+        accumulator as u16 + value as u16 + cpu_state.flags.contains(StatusFlags::CARRY) as u16;
+    let result = sum as u8;
+    cpu_state.flags.set(StatusFlags::CARRY, sum > 0xFF);
+    // the sign of the result doesn't match the sign of either input
+    let overflow = (accumulator ^ result) & (value ^ result) & 0x80 != 0;
+    cpu_state.flags.set(StatusFlags::OVERFLOW, overflow);
+    set_register(&mut cpu_state.accumulator, result, &mut cpu_state.flags);
+}
+
+pub fn and(cpu_state: &mut CpuState, value: u8) {
+    lda(cpu_state, cpu_state.accumulator & value);
+}
+
+pub fn ora(cpu_state: &mut CpuState, value: u8) {
+    lda(cpu_state, cpu_state.accumulator | value);
+}
+
+pub fn eor(cpu_state: &mut CpuState, value: u8) {
+    lda(cpu_state, cpu_state.accumulator ^ value);
+}
+
+/// Set the flags like for subtracting `value` from `register`
+fn compare(cpu_state: &mut CpuState, register: u8, value: u8) {
+    cpu_state.flags.set(StatusFlags::CARRY, register >= value);
+    set_zero_negative(&mut cpu_state.flags, register.wrapping_sub(value));
+}
+
+pub fn cmp(cpu_state: &mut CpuState, value: u8) {
+    compare(cpu_state, cpu_state.accumulator, value);
+}
+
+pub fn cpx(cpu_state: &mut CpuState, value: u8) {
+    compare(cpu_state, cpu_state.x_index, value);
+}
+
+pub fn cpy(cpu_state: &mut CpuState, value: u8) {
+    compare(cpu_state, cpu_state.y_index, value);
+}
+
+pub fn bit(cpu_state: &mut CpuState, value: u8) {
+    let flags = &mut cpu_state.flags;
+    flags.set(StatusFlags::ZERO, cpu_state.accumulator & value == 0);
+    flags.set(StatusFlags::NEGATIVE, value & 0x80 != 0);
+    flags.set(StatusFlags::OVERFLOW, value & 0x40 != 0);
+}
+
+/// Reads that are thrown away
+pub fn nop_read(_: &mut CpuState, _: u8) {}
+
+// Read-modify-write
+
+pub fn asl(cpu_state: &mut CpuState, value: u8) -> u8 {
+    cpu_state.flags.set(StatusFlags::CARRY, value & 0x80 != 0);
+    let result = value << 1;
+    set_zero_negative(&mut cpu_state.flags, result);
+    result
+}
+
+pub fn lsr(cpu_state: &mut CpuState, value: u8) -> u8 {
+    cpu_state.flags.set(StatusFlags::CARRY, value & 1 != 0);
+    let result = value >> 1;
+    set_zero_negative(&mut cpu_state.flags, result);
+    result
+}
+
+pub fn rol(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let carry = cpu_state.flags.contains(StatusFlags::CARRY) as u8;
+    cpu_state.flags.set(StatusFlags::CARRY, value & 0x80 != 0);
+    let result = value << 1 | carry;
+    set_zero_negative(&mut cpu_state.flags, result);
+    result
+}
+
+pub fn ror(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let carry = cpu_state.flags.contains(StatusFlags::CARRY) as u8;
+    cpu_state.flags.set(StatusFlags::CARRY, value & 1 != 0);
+    let result = value >> 1 | carry << 7;
+    set_zero_negative(&mut cpu_state.flags, result);
+    result
+}
+
+pub fn inc(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    set_zero_negative(&mut cpu_state.flags, result);
+    result
+}
+
+pub fn dec(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    set_zero_negative(&mut cpu_state.flags, result);
+    result
+}
+
+// Registers
+
+pub fn tax(cpu_state: &mut CpuState) {
+    ldx(cpu_state, cpu_state.accumulator);
+}
+
+pub fn tay(cpu_state: &mut CpuState) {
+    ldy(cpu_state, cpu_state.accumulator);
+}
+
+pub fn txa(cpu_state: &mut CpuState) {
+    lda(cpu_state, cpu_state.x_index);
+}
+
+pub fn tya(cpu_state: &mut CpuState) {
+    lda(cpu_state, cpu_state.y_index);
+}
+
+pub fn tsx(cpu_state: &mut CpuState) {
+    ldx(cpu_state, cpu_state.stack_ptr);
+}
+
+/// The only transfer that doesn't set the flags
+pub fn txs(cpu_state: &mut CpuState) {
+    cpu_state.stack_ptr = cpu_state.x_index;
+}
+
+pub fn inx(cpu_state: &mut CpuState) {
+    cpu_state.x_index = inc(cpu_state, cpu_state.x_index);
+}
+
+pub fn iny(cpu_state: &mut CpuState) {
+    cpu_state.y_index = inc(cpu_state, cpu_state.y_index);
+}
+
+pub fn dex(cpu_state: &mut CpuState) {
+    cpu_state.x_index = dec(cpu_state, cpu_state.x_index);
+}
+
+pub fn dey(cpu_state: &mut CpuState) {
+    cpu_state.y_index = dec(cpu_state, cpu_state.y_index);
+}
+
+pub fn clc(cpu_state: &mut CpuState) {
+    cpu_state.flags.remove(StatusFlags::CARRY);
 }
 
-pub fn ldx_zeropage(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> ControlFlow<()> {
-    read_zeropage(cpu_state, memory, ldx_common)
+pub fn sec(cpu_state: &mut CpuState) {
+    cpu_state.flags.insert(StatusFlags::CARRY);
 }
 
-pub fn ldx_zeropage_y(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> ControlFlow<()> {
-    read_zeropage_indexed(cpu_state, memory, get_y_index, ldx_common)
+pub fn cli(cpu_state: &mut CpuState) {
+    cpu_state.flags.remove(StatusFlags::INTERRUPT_DISABLE);
 }
 
-pub fn ldx_absolute(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> ControlFlow<()> {
-    read_absolute(cpu_state, memory, ldx_common)
+pub fn sei(cpu_state: &mut CpuState) {
+    cpu_state.flags.insert(StatusFlags::INTERRUPT_DISABLE);
 }
 
-pub fn ldx_absolute_y(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> ControlFlow<()> {
-    read_absolute_indexed(cpu_state, memory, get_y_index, ldx_common)
+pub fn clv(cpu_state: &mut CpuState) {
+    cpu_state.flags.remove(StatusFlags::OVERFLOW);
+}
+
+pub fn cld(cpu_state: &mut CpuState) {
+    cpu_state.flags.remove(StatusFlags::DECIMAL);
+}
+
+pub fn sed(cpu_state: &mut CpuState) {
+    cpu_state.flags.insert(StatusFlags::DECIMAL);
+}
+
+pub fn nop(_: &mut CpuState) {}
+
+// Unofficial combinations of two instructions
+
+pub fn lax(cpu_state: &mut CpuState, value: u8) {
+    lda(cpu_state, value);
+    cpu_state.x_index = value;
+}
+
+pub fn sax(cpu_state: &CpuState) -> u8 {
+    cpu_state.accumulator & cpu_state.x_index
+}
+
+pub fn slo(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = asl(cpu_state, value);
+    ora(cpu_state, result);
+    result
+}
+
+pub fn rla(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = rol(cpu_state, value);
+    and(cpu_state, result);
+    result
+}
+
+pub fn sre(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = lsr(cpu_state, value);
+    eor(cpu_state, result);
+    result
+}
+
+pub fn rra(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = ror(cpu_state, value);
+    adc(cpu_state, result);
+    result
+}
+
+pub fn dcp(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = value.wrapping_sub(1);
+    cmp(cpu_state, result);
+    result
+}
+
+pub fn isb(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let result = value.wrapping_add(1);
+    sbc(cpu_state, result);
+    result
+}
+
+pub fn anc(cpu_state: &mut CpuState, value: u8) {
+    and(cpu_state, value);
+    let negative = cpu_state.flags.contains(StatusFlags::NEGATIVE);
+    cpu_state.flags.set(StatusFlags::CARRY, negative);
+}
+
+pub fn alr(cpu_state: &mut CpuState, value: u8) {
+    let value = cpu_state.accumulator & value;
+    cpu_state.accumulator = lsr(cpu_state, value);
+}
+
+pub fn arr(cpu_state: &mut CpuState, value: u8) {
+    let value = cpu_state.accumulator & value;
+    cpu_state.accumulator = ror(cpu_state, value);
+    // the carry and overflow come from the adder, which sees the value before rotating
+    let result = cpu_state.accumulator;
+    cpu_state.flags.set(StatusFlags::CARRY, result & 0x40 != 0);
+    cpu_state
+        .flags
+        .set(StatusFlags::OVERFLOW, (result ^ result << 1) & 0x40 != 0);
+}
+
+/// Magic constant of the unstable `XAA` and `LXA`, it varies between chips
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+pub fn xaa(cpu_state: &mut CpuState, value: u8) {
+    lda(
+        cpu_state,
+        (cpu_state.accumulator | UNSTABLE_MAGIC) & cpu_state.x_index & value,
+    );
+}
+
+pub fn lxa(cpu_state: &mut CpuState, value: u8) {
+    lax(cpu_state, (cpu_state.accumulator | UNSTABLE_MAGIC) & value);
+}
+
+pub fn sbx(cpu_state: &mut CpuState, value: u8) {
+    let register = cpu_state.accumulator & cpu_state.x_index;
+    compare(cpu_state, register, value);
+    cpu_state.x_index = register.wrapping_sub(value);
+}
+
+pub fn las(cpu_state: &mut CpuState, value: u8) {
+    let value = value & cpu_state.stack_ptr;
+    lax(cpu_state, value);
+    cpu_state.stack_ptr = value;
+}
+
+pub fn sha_absolute_y<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    write_unstable_absolute_indexed(cpu_state, memory, get_y_index, |cpu_state| {
+        cpu_state.accumulator & cpu_state.x_index
+    })
+}
+
+pub fn sha_indirect_y<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    write_unstable_indirect_y(cpu_state, memory, |cpu_state| {
+        cpu_state.accumulator & cpu_state.x_index
+    })
+}
+
+pub fn shx<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    write_unstable_absolute_indexed(cpu_state, memory, get_y_index, |cpu_state| {
+        cpu_state.x_index
+    })
+}
+
+pub fn shy<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    write_unstable_absolute_indexed(cpu_state, memory, get_x_index, |cpu_state| {
+        cpu_state.y_index
+    })
+}
+
+pub fn tas<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    write_unstable_absolute_indexed(cpu_state, memory, get_y_index, |cpu_state| {
+        cpu_state.stack_ptr = cpu_state.accumulator & cpu_state.x_index;
+        cpu_state.stack_ptr
+    })
+}
+
+/// Halts the CPU, only a reset gets it going again
+///
+/// The bus keeps reading $FFFF.
+pub fn jam<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => {
+            let _ = memory.fetch(cpu_state.program_counter);
+            logging::warn!(
+                "CPU jammed by opcode ${:02X} at ${:04X}",
+                cpu_state.current_opcode as u8,
+                cpu_state.program_counter.wrapping_sub(1)
+            );
+        }
+        _ if cpu_state.reset_pending => return ControlFlow::Break(()),
+        _ => {
+            let _ = memory.load(0xFFFF);
+            // stay on the same cycle forever
+            cpu_state.current_cycle = 1;
+        }
+    };
+
+    ControlFlow::Continue(())
+}
+
+// Jumps
+
+pub fn jmp_absolute<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            let high_byte = memory.fetch(cpu_state.program_counter) as u16;
+            cpu_state.program_counter = high_byte << 8 | cpu_state.effective_address;
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Jump to the address stored at the operand
+///
+/// The high byte of the pointer isn't incremented, the pointer wraps around within its page.
+/// The 65C02 fixed that, see [`jmp_indirect_fixed`].
+pub fn jmp_indirect<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    if cpu_state.variant == Variant::Cmos65C02 {
+        return jmp_indirect_fixed(cpu_state, memory, 0);
+    }
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.effective_address |= (fetch_from_pc(cpu_state, memory) as u16) << 8,
+        3 => cpu_state.data_latch = memory.load(cpu_state.effective_address),
+        4 => {
+            let pointer = cpu_state.effective_address;
+            let high_address = (pointer & 0xFF00) | (pointer as u8).wrapping_add(1) as u16;
+            let high_byte = memory.load(high_address);
+            cpu_state.program_counter = u16::from_le_bytes([cpu_state.data_latch, high_byte]);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// `JMP (abs)` of the 65C02 and `JMP (abs,X)`, the pointer is indexed by `index`
+///
+/// They take an extra cycle to carry into the high byte of the pointer.
+pub fn jmp_indirect_fixed<M: Memory>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    index: u8,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.effective_address |= (fetch_from_pc(cpu_state, memory) as u16) << 8,
+        3 => {
+            // dummy read of the last operand byte
+            let _ = memory.fetch(cpu_state.program_counter.wrapping_sub(1));
+            cpu_state.effective_address = cpu_state.effective_address.wrapping_add(index as u16);
+        }
+        4 => cpu_state.data_latch = memory.load(cpu_state.effective_address),
+        5 => {
+            let high_byte = memory.load(cpu_state.effective_address.wrapping_add(1));
+            cpu_state.program_counter = u16::from_le_bytes([cpu_state.data_latch, high_byte]);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+// Stack
+
+pub fn pha<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    push_register(cpu_state, memory, cpu_state.accumulator)
+}
+
+/// Push the flags, with B and the unused bit set
+pub fn php<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    let flags = cpu_state.flags | StatusFlags::BREAK | StatusFlags::IGNORED_FLAG;
+    push_register(cpu_state, memory, flags.bits())
+}
+
+fn push_register<M: Memory>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    value: u8,
+) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => {
+            // dummy read
+            let _ = memory.fetch(cpu_state.program_counter);
+        }
+        2 => {
+            push(cpu_state, memory, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn pla<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    pull_register(cpu_state, memory, lda)
+}
+
+pub fn plp<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    pull_register(cpu_state, memory, |cpu_state, value| {
+        let flags = StatusFlags::from_bits_retain(value);
+        cpu_state.flags = flags - StatusFlags::BREAK - StatusFlags::IGNORED_FLAG;
+    })
+}
+
+fn pull_register<M: Memory, F: FnOnce(&mut CpuState, u8)>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => {
+            // dummy read
+            let _ = memory.fetch(cpu_state.program_counter);
+        }
+        2 => {
+            let _ = pop(cpu_state, memory);
+        }
+        3 => {
+            let value = peek_stack(cpu_state, memory);
+            f(cpu_state, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+// 65C02
+
+pub fn stz(_: &CpuState) -> u8 {
+    0
+}
+
+/// Test and set bits, the zero flag is set like `BIT` does
+pub fn tsb(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let accumulator = cpu_state.accumulator;
+    cpu_state
+        .flags
+        .set(StatusFlags::ZERO, accumulator & value == 0);
+    value | accumulator
+}
+
+/// Test and reset bits, the zero flag is set like `BIT` does
+pub fn trb(cpu_state: &mut CpuState, value: u8) -> u8 {
+    let accumulator = cpu_state.accumulator;
+    cpu_state
+        .flags
+        .set(StatusFlags::ZERO, accumulator & value == 0);
+    value & !accumulator
+}
+
+/// `BIT #imm` only sets the zero flag, there are no bits 6 and 7 of memory to copy
+pub fn bit_immediate(cpu_state: &mut CpuState, value: u8) {
+    cpu_state
+        .flags
+        .set(StatusFlags::ZERO, cpu_state.accumulator & value == 0);
+}
+
+pub fn phx<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    push_register(cpu_state, memory, cpu_state.x_index)
+}
+
+pub fn phy<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    push_register(cpu_state, memory, cpu_state.y_index)
+}
+
+pub fn plx<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    pull_register(cpu_state, memory, ldx)
+}
+
+pub fn ply<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    pull_register(cpu_state, memory, ldy)
+}
+
+/// Opcode `$5C`, a `NOP` with an absolute operand that takes 8 cycles
+pub fn nop_5c<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.effective_address |= (fetch_from_pc(cpu_state, memory) as u16) << 8,
+        3..=6 => {
+            let _ = memory.load(cpu_state.effective_address);
+        }
+        7 => {
+            let _ = memory.load(cpu_state.effective_address);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+// Subroutines
+
+/// Jump to subroutine, pushes the address of its last byte
+pub fn jsr<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            // dummy read
+            let _ = peek_stack(cpu_state, memory);
+        }
+        3 => push(cpu_state, memory, (cpu_state.program_counter >> 8) as u8),
+        4 => push(cpu_state, memory, cpu_state.program_counter as u8),
+        5 => {
+            let high_byte = memory.fetch(cpu_state.program_counter) as u16;
+            cpu_state.program_counter = high_byte << 8 | cpu_state.effective_address;
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Return from subroutine, to the byte after the pushed address
+pub fn rts<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => {
+            // dummy read
+            let _ = memory.fetch(cpu_state.program_counter);
+        }
+        2 => {
+            let _ = pop(cpu_state, memory);
+        }
+        3 => cpu_state.effective_address = pop(cpu_state, memory) as u16,
+        4 => {
+            let high_byte = peek_stack(cpu_state, memory) as u16;
+            cpu_state.program_counter = high_byte << 8 | cpu_state.effective_address;
+        }
+        5 => {
+            let _ = fetch_from_pc(cpu_state, memory);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Return from interrupt, pulls the flags and the address pushed by the interrupt
+pub fn rti<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    match cpu_state.current_cycle {
+        1 => {
+            // dummy read
+            let _ = memory.fetch(cpu_state.program_counter);
+        }
+        2 => {
+            let _ = pop(cpu_state, memory);
+        }
+        3 => {
+            // B and the unused bit don't really exist, they're only there when pushed
+            let flags = StatusFlags::from_bits_retain(pop(cpu_state, memory));
+            cpu_state.flags = flags - StatusFlags::BREAK - StatusFlags::IGNORED_FLAG;
+        }
+        4 => cpu_state.effective_address = pop(cpu_state, memory) as u16,
+        5 => {
+            let high_byte = peek_stack(cpu_state, memory) as u16;
+            cpu_state.program_counter = high_byte << 8 | cpu_state.effective_address;
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+// Interrupts
+
+/// Software interrupt, goes through the IRQ vector with the B flag set in the pushed flags
+///
+/// The byte after the opcode is skipped, so the pushed address is 2 bytes after it
+pub fn brk<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> ControlFlow<()> {
+    if cpu_state.current_cycle == 1 {
+        // padding byte
+        let _ = fetch_from_pc(cpu_state, memory);
+        return ControlFlow::Continue(());
+    }
+    push_and_jump(
+        cpu_state,
+        memory,
+        Interrupt::Irq.vector(),
+        StatusFlags::BREAK,
+    )
+}
+
+/// Reset, NMI and IRQ sequence
+///
+/// Cycle 0 already did a dummy opcode fetch without incrementing the PC
+pub fn interrupt<M: Memory>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    interrupt: Interrupt,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => {
+            // dummy read
+            let _ = memory.fetch(cpu_state.program_counter);
+        }
+        2..=4 if interrupt == Interrupt::Reset => {
+            // reset goes through the motions of pushing, but the writes are turned into reads
+            let _ = memory.load(0x0100 | cpu_state.stack_ptr as u16);
+            cpu_state.stack_ptr = cpu_state.stack_ptr.wrapping_sub(1);
+        }
+        // B flag is clear when pushed by hardware interrupts
+        _ => return push_and_jump(cpu_state, memory, interrupt.vector(), StatusFlags::empty()),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Whether an NMI that comes before the flags are pushed makes the sequence go through the NMI vector
+///
+/// The flags were already pushed with the B flag as `BRK` or the IRQ left it, so the NMI handler
+/// runs as if it was called by them, and the `BRK` or IRQ is lost. The 65C02 doesn't do this for `BRK`.
+///
+/// Details at https://www.nesdev.org/wiki/CPU_interrupts#Interrupt_hijacking
+fn can_be_hijacked(cpu_state: &CpuState, vector: u16, break_flag: StatusFlags) -> bool {
+    let is_brk = break_flag.contains(StatusFlags::BREAK);
+    vector == Interrupt::Irq.vector() && !(is_brk && cpu_state.variant == Variant::Cmos65C02)
+}
+
+/// Cycles 2 to 6 of interrupts and BRK, push the PC and the flags and jump to the handler
+pub fn push_and_jump<M: Memory>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    vector: u16,
+    break_flag: StatusFlags,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        2 => push(cpu_state, memory, (cpu_state.program_counter >> 8) as u8),
+        3 => push(cpu_state, memory, cpu_state.program_counter as u8),
+        4 => {
+            let flags = (cpu_state.flags | StatusFlags::IGNORED_FLAG) - StatusFlags::BREAK;
+            push(cpu_state, memory, (flags | break_flag).bits());
+            if cpu_state.nmi_pending && can_be_hijacked(cpu_state, vector, break_flag) {
+                // the NMI takes over the rest of the sequence, which is dispatched as the NMI from now on
+                cpu_state.nmi_pending = false;
+                cpu_state.current_interrupt = Some(Interrupt::Nmi);
+            }
+        }
+        5 => {
+            cpu_state.effective_address = memory.load(vector) as u16;
+            cpu_state.flags.insert(StatusFlags::INTERRUPT_DISABLE);
+            if cpu_state.variant == Variant::Cmos65C02 {
+                cpu_state.flags.remove(StatusFlags::DECIMAL);
+            }
+        }
+        6 => {
+            let high_byte = memory.load(vector + 1) as u16;
+            cpu_state.program_counter = high_byte << 8 | cpu_state.effective_address;
+            // the sequence doesn't poll for interrupts, so an NMI too late to hijack it
+            // waits for the first instruction of the handler
+            cpu_state.nmi_delayed |= core::mem::take(&mut cpu_state.nmi_pending);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
 }
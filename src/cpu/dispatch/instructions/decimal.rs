@@ -0,0 +1,81 @@
+//! `ADC` and `SBC` in decimal mode, where each nibble of the operands is a decimal digit
+//!
+//! The NMOS chips adjust the digits after the binary adder, so only the carry comes out right,
+//! the negative and zero flags are those of the binary result, and the overflow flag is from
+//! halfway through the adjustment. The 65C02 sets the negative and zero flags from the result.
+//! Invalid BCD numbers give the same results as on the real chips.
+//!
+//! Details at http://www.6502.org/tutorials/decimal_mode.html
+
+use crate::cpu::{CpuState, StatusFlags, Variant};
+
+use super::{add, set_zero_negative};
+
+/// Whether `ADC` and `SBC` should use decimal mode
+pub fn is_enabled(cpu_state: &CpuState) -> bool {
+    cpu_state.flags.contains(StatusFlags::DECIMAL) && cpu_state.variant.has_decimal_mode()
+}
+
+pub fn adc(cpu_state: &mut CpuState, value: u8) {
+    let accumulator = cpu_state.accumulator;
+    let carry = cpu_state.flags.contains(StatusFlags::CARRY) as u8;
+
+    let mut low_digit = (accumulator & 0x0F) + (value & 0x0F) + carry;
+    if low_digit >= 0x0A {
+        low_digit = ((low_digit + 0x06) & 0x0F) + 0x10;
+    }
+    let sum = (accumulator & 0xF0) as u16 + (value & 0xF0) as u16 + low_digit as u16;
+    // the overflow is taken before the high digit is adjusted, as if the operands were signed
+    let signed = (accumulator & 0xF0) as i8 as i16 + (value & 0xF0) as i8 as i16 + low_digit as i16;
+    let sum = if sum >= 0xA0 { sum + 0x60 } else { sum };
+    let result = sum as u8;
+
+    let flags = &mut cpu_state.flags;
+    flags.set(StatusFlags::CARRY, sum > 0xFF);
+    flags.set(StatusFlags::OVERFLOW, !(-128..=127).contains(&signed));
+    if cpu_state.variant == Variant::Cmos65C02 {
+        set_zero_negative(flags, result);
+    } else {
+        let binary = accumulator.wrapping_add(value).wrapping_add(carry);
+        flags.set(StatusFlags::ZERO, binary == 0);
+        flags.set(StatusFlags::NEGATIVE, signed & 0x80 != 0);
+    }
+    cpu_state.accumulator = result;
+}
+
+pub fn sbc(cpu_state: &mut CpuState, value: u8) {
+    let accumulator = cpu_state.accumulator as i16;
+    let operand = value as i16;
+    let borrow = !cpu_state.flags.contains(StatusFlags::CARRY) as i16;
+
+    let low_digit = (accumulator & 0x0F) - (operand & 0x0F) - borrow;
+    let result = if cpu_state.variant == Variant::Cmos65C02 {
+        let mut difference = accumulator - operand - borrow;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        if low_digit < 0 {
+            difference -= 0x06;
+        }
+        difference
+    } else {
+        let low_digit = if low_digit < 0 {
+            ((low_digit - 0x06) & 0x0F) - 0x10
+        } else {
+            low_digit
+        };
+        let difference = (accumulator & 0xF0) - (operand & 0xF0) + low_digit;
+        if difference < 0 {
+            difference - 0x60
+        } else {
+            difference
+        }
+    } as u8;
+
+    // the carry and overflow are the same as in binary mode, and so are the other flags on NMOS
+    add(cpu_state, !value);
+    if cpu_state.variant == Variant::Cmos65C02 {
+        set_zero_negative(&mut cpu_state.flags, result);
+    }
+    cpu_state.accumulator = result;
+}
@@ -2,11 +2,11 @@
 
 use crate::{
     cpu::{CpuState, StatusFlags},
-    memory::MemoryMapping,
+    memory::Memory,
 };
 
-pub fn fetch_from_pc(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> u8 {
-    let value = memory.load(cpu_state.program_counter);
+pub fn fetch_from_pc<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> u8 {
+    let value = memory.fetch(cpu_state.program_counter);
     cpu_state.program_counter += 1;
 
     value
@@ -15,6 +15,31 @@ pub fn fetch_from_pc(cpu_state: &mut CpuState, memory: &mut MemoryMapping) -> u8
 /// Set a register to a value and also correctly set the Negative and Zero flags
 pub fn set_register(register: &mut u8, value: u8, flags: &mut StatusFlags) {
     *register = value;
+    set_zero_negative(flags, value);
+}
+
+/// Set the Negative and Zero flags for a result
+pub fn set_zero_negative(flags: &mut StatusFlags, value: u8) {
     flags.set(StatusFlags::NEGATIVE, (value as i8).is_negative());
     flags.set(StatusFlags::ZERO, value == 0);
 }
+
+/// Push a byte onto the stack
+pub fn push<M: Memory>(cpu_state: &mut CpuState, memory: &mut M, value: u8) {
+    memory.store(0x0100 | cpu_state.stack_ptr as u16, value);
+    cpu_state.stack_ptr = cpu_state.stack_ptr.wrapping_sub(1);
+}
+
+/// Read the top of the stack without moving the stack pointer
+pub fn peek_stack<M: Memory>(cpu_state: &CpuState, memory: &mut M) -> u8 {
+    memory.load(0x0100 | cpu_state.stack_ptr as u16)
+}
+
+/// Read the top of the stack and move the stack pointer to the next byte
+///
+/// Pulling takes two cycles, the first one only does this, the second one uses [`peek_stack`]
+pub fn pop<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) -> u8 {
+    let value = peek_stack(cpu_state, memory);
+    cpu_state.stack_ptr = cpu_state.stack_ptr.wrapping_add(1);
+    value
+}
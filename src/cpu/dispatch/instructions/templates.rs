@@ -5,18 +5,18 @@
 //! This module defines template instructions that implement the common functionality between instructions
 //! by taking a closure as an argument that performs the actual work after the addressing is done
 
-use std::ops::ControlFlow;
+use core::ops::ControlFlow;
 
 use crate::{
-    cpu::{CpuState, StatusFlags},
-    memory::MemoryMapping,
+    cpu::{CpuState, StatusFlags, Variant},
+    memory::Memory,
 };
 
 use super::fetch_from_pc;
 
-pub fn read_immediate<F: FnOnce(&mut CpuState, u8)>(
+pub fn read_immediate<M: Memory, F: FnOnce(&mut CpuState, u8)>(
     cpu_state: &mut CpuState,
-    memory: &mut MemoryMapping,
+    memory: &mut M,
     f: F,
 ) -> ControlFlow<()> //
 {
@@ -30,9 +30,9 @@ pub fn read_immediate<F: FnOnce(&mut CpuState, u8)>(
     }
 }
 
-pub fn read_zeropage<F: FnOnce(&mut CpuState, u8)>(
+pub fn read_zeropage<M: Memory, F: FnOnce(&mut CpuState, u8)>(
     cpu_state: &mut CpuState,
-    memory: &mut MemoryMapping,
+    memory: &mut M,
     f: F,
 ) -> ControlFlow<()> //
 {
@@ -52,13 +52,14 @@ pub fn read_zeropage<F: FnOnce(&mut CpuState, u8)>(
     ControlFlow::Continue(())
 }
 
-pub fn read_zeropage_indexed<F, I>(
+pub fn read_zeropage_indexed<M, F, I>(
     cpu_state: &mut CpuState,
-    memory: &mut MemoryMapping,
+    memory: &mut M,
     get_index: I,
     f: F,
 ) -> ControlFlow<()>
 where
+    M: Memory,
     F: FnOnce(&mut CpuState, u8),
     I: FnOnce(&CpuState) -> u8,
 {
@@ -85,9 +86,9 @@ where
     ControlFlow::Continue(())
 }
 
-pub fn read_absolute<F: FnOnce(&mut CpuState, u8)>(
+pub fn read_absolute<M: Memory, F: FnOnce(&mut CpuState, u8)>(
     cpu_state: &mut CpuState,
-    memory: &mut MemoryMapping,
+    memory: &mut M,
     f: F,
 ) -> ControlFlow<()> //
 {
@@ -109,13 +110,14 @@ pub fn read_absolute<F: FnOnce(&mut CpuState, u8)>(
     ControlFlow::Continue(())
 }
 
-pub fn read_absolute_indexed<F, I>(
+pub fn read_absolute_indexed<M, F, I>(
     cpu_state: &mut CpuState,
-    memory: &mut MemoryMapping,
+    memory: &mut M,
     get_index: I,
     f: F,
 ) -> ControlFlow<()>
 where
+    M: Memory,
     F: FnOnce(&mut CpuState, u8),
     I: FnOnce(&CpuState) -> u8,
 {
@@ -130,18 +132,14 @@ where
                 (cpu_state.effective_address as u8).overflowing_add(get_index(cpu_state));
 
             cpu_state.effective_address = (address_high_byte as u16) << 8 | address_low_byte as u16;
-            // store information about the carry.
             // the 6502 will first read from the page-wrapped address, and then,
-            // if the carry was set, will fix up the address and do a second read.
-            // Most importantly, we can't use the CARRY flag, since that
-            // is documented not to change, the real CPU uses the ALU output, we don't
-            // simulate an ALU, so we use the IGNORED_FLAG flag to persist a single bit of information
-            cpu_state.flags.set(StatusFlags::IGNORED_FLAG, carry);
+            // if the carry was set, will fix up the address and do a second read
+            cpu_state.page_crossed = carry;
         }
         3 => {
             let value = memory.load(cpu_state.effective_address);
 
-            if cpu_state.flags.contains(StatusFlags::IGNORED_FLAG) {
+            if cpu_state.page_crossed {
                 // oops, blown through a page, fix up the effective address
                 cpu_state.effective_address = cpu_state.effective_address.wrapping_add(1 << 8);
             } else {
@@ -160,3 +158,594 @@ where
 
     ControlFlow::Continue(())
 }
+
+/// Cycles 1 to 4 of the `(zp,X)` mode, which read the pointer from the indexed zero page address
+fn indirect_x_address<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            // dummy read of the pointer before it's indexed
+            let _ = memory.load(cpu_state.effective_address);
+            cpu_state.effective_address =
+                (cpu_state.effective_address as u8).wrapping_add(cpu_state.x_index) as u16;
+        }
+        3 => cpu_state.data_latch = memory.load(cpu_state.effective_address),
+        4 => {
+            // the pointer wraps around within the zero page
+            let high_address = (cpu_state.effective_address as u8).wrapping_add(1);
+            let high_byte = memory.load(high_address as u16);
+            cpu_state.effective_address = u16::from_le_bytes([cpu_state.data_latch, high_byte]);
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Cycles 1 to 3 of the `(zp),Y` mode, which index the pointer they read
+///
+/// The address is left without the carry into the high byte, see [`index_address`]
+fn indirect_y_address<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.data_latch = memory.load(cpu_state.effective_address),
+        3 => {
+            let high_address = (cpu_state.effective_address as u8).wrapping_add(1);
+            let high_byte = memory.load(high_address as u16);
+            index_address(
+                cpu_state,
+                cpu_state.data_latch,
+                high_byte,
+                cpu_state.y_index,
+            );
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Cycles 1 and 2 of the absolute indexed modes, which fetch the address
+///
+/// The address is left without the carry into the high byte, see [`index_address`]
+fn absolute_indexed_address<M: Memory>(cpu_state: &mut CpuState, memory: &mut M, index: u8) {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            let high_byte = fetch_from_pc(cpu_state, memory);
+            index_address(
+                cpu_state,
+                cpu_state.effective_address as u8,
+                high_byte,
+                index,
+            );
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Add the index to the low byte of an address, the carry into the high byte is done in the next cycle
+///
+/// The carry is kept in `page_crossed`, see [`read_absolute_indexed`]
+fn index_address(cpu_state: &mut CpuState, low_byte: u8, high_byte: u8, index: u8) {
+    let (low_byte, carry) = low_byte.overflowing_add(index);
+    cpu_state.effective_address = u16::from_le_bytes([low_byte, high_byte]);
+    cpu_state.page_crossed = carry;
+}
+
+/// Carry into the high byte of an address from [`index_address`]
+fn fix_address(cpu_state: &mut CpuState) {
+    if cpu_state.page_crossed {
+        cpu_state.effective_address = cpu_state.effective_address.wrapping_add(1 << 8);
+    }
+}
+
+pub fn read_indirect_x<M: Memory, F: FnOnce(&mut CpuState, u8)>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=4 => indirect_x_address(cpu_state, memory),
+        5 => {
+            let value = memory.load(cpu_state.effective_address);
+            f(cpu_state, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Cycles 1 to 3 of the `(zp)` mode of the 65C02, which reads the pointer from the zero page
+fn indirect_address<M: Memory>(cpu_state: &mut CpuState, memory: &mut M) {
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.data_latch = memory.load(cpu_state.effective_address),
+        3 => {
+            let high_address = (cpu_state.effective_address as u8).wrapping_add(1);
+            let high_byte = memory.load(high_address as u16);
+            cpu_state.effective_address = u16::from_le_bytes([cpu_state.data_latch, high_byte]);
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn read_indirect<M: Memory, F: FnOnce(&mut CpuState, u8)>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=3 => indirect_address(cpu_state, memory),
+        4 => {
+            let value = memory.load(cpu_state.effective_address);
+            f(cpu_state, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn read_indirect_y<M: Memory, F: FnOnce(&mut CpuState, u8)>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=3 => indirect_y_address(cpu_state, memory),
+        4 => {
+            let value = memory.load(cpu_state.effective_address);
+            if cpu_state.page_crossed {
+                fix_address(cpu_state);
+            } else {
+                f(cpu_state, value);
+                return ControlFlow::Break(());
+            }
+        }
+        5 => {
+            let value = memory.load(cpu_state.effective_address);
+            f(cpu_state, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_zeropage<M: Memory, F: FnOnce(&CpuState) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_zeropage_indexed<M, F, I>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    get_index: I,
+    f: F,
+) -> ControlFlow<()>
+where
+    M: Memory,
+    F: FnOnce(&CpuState) -> u8,
+    I: FnOnce(&CpuState) -> u8,
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            let _ = memory.load(cpu_state.effective_address);
+            cpu_state.effective_address =
+                (cpu_state.effective_address as u8).wrapping_add(get_index(cpu_state)) as u16;
+        }
+        3 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_absolute<M: Memory, F: FnOnce(&CpuState) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.effective_address |= (fetch_from_pc(cpu_state, memory) as u16) << 8,
+        3 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Writes always take the extra cycle, reading from the address before the carry is fixed
+pub fn write_absolute_indexed<M, F, I>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    get_index: I,
+    f: F,
+) -> ControlFlow<()>
+where
+    M: Memory,
+    F: FnOnce(&CpuState) -> u8,
+    I: FnOnce(&CpuState) -> u8,
+{
+    match cpu_state.current_cycle {
+        1..=2 => absolute_indexed_address(cpu_state, memory, get_index(cpu_state)),
+        3 => {
+            let _ = memory.load(cpu_state.effective_address);
+            fix_address(cpu_state);
+        }
+        4 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_indirect_x<M: Memory, F: FnOnce(&CpuState) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=4 => indirect_x_address(cpu_state, memory),
+        5 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_indirect<M: Memory, F: FnOnce(&CpuState) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=3 => indirect_address(cpu_state, memory),
+        4 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_indirect_y<M: Memory, F: FnOnce(&CpuState) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=3 => indirect_y_address(cpu_state, memory),
+        4 => {
+            let _ = memory.load(cpu_state.effective_address);
+            fix_address(cpu_state);
+        }
+        5 => {
+            memory.store(cpu_state.effective_address, f(cpu_state));
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Last three cycles of read-modify-write instructions, starting at `first_cycle`
+///
+/// The value is read, written back unchanged while the CPU modifies it,
+/// and then the modified value is written. The 65C02 reads it again instead of writing it back.
+/// Both writes go to the bus, mappers that ignore writes on consecutive cycles and
+/// the PPU's $2007, which moves its address on each access, depend on that.
+fn modify<M: Memory, F: FnOnce(&mut CpuState, u8) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    first_cycle: u8,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle - first_cycle {
+        0 => cpu_state.data_latch = memory.load(cpu_state.effective_address),
+        1 => {
+            if cpu_state.variant == Variant::Cmos65C02 {
+                let _ = memory.load(cpu_state.effective_address);
+            } else {
+                memory.store(cpu_state.effective_address, cpu_state.data_latch);
+            }
+            cpu_state.data_latch = f(cpu_state, cpu_state.data_latch);
+        }
+        2 => {
+            memory.store(cpu_state.effective_address, cpu_state.data_latch);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Read-modify-write instructions on the accumulator, which take 2 cycles like implied ones
+pub fn modify_accumulator<M: Memory, F: FnOnce(&mut CpuState, u8) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    implied(cpu_state, memory, |cpu_state| {
+        cpu_state.accumulator = f(cpu_state, cpu_state.accumulator);
+    })
+}
+
+pub fn modify_zeropage<M: Memory, F: FnOnce(&mut CpuState, u8) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        _ => return modify(cpu_state, memory, 2, f),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn modify_zeropage_indexed<M, F, I>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    get_index: I,
+    f: F,
+) -> ControlFlow<()>
+where
+    M: Memory,
+    F: FnOnce(&mut CpuState, u8) -> u8,
+    I: FnOnce(&CpuState) -> u8,
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => {
+            let _ = memory.load(cpu_state.effective_address);
+            cpu_state.effective_address =
+                (cpu_state.effective_address as u8).wrapping_add(get_index(cpu_state)) as u16;
+        }
+        _ => return modify(cpu_state, memory, 3, f),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn modify_absolute<M: Memory, F: FnOnce(&mut CpuState, u8) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => cpu_state.effective_address = fetch_from_pc(cpu_state, memory) as u16,
+        2 => cpu_state.effective_address |= (fetch_from_pc(cpu_state, memory) as u16) << 8,
+        _ => return modify(cpu_state, memory, 3, f),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn modify_absolute_indexed<M, F, I>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    get_index: I,
+    f: F,
+) -> ControlFlow<()>
+where
+    M: Memory,
+    F: FnOnce(&mut CpuState, u8) -> u8,
+    I: FnOnce(&CpuState) -> u8,
+{
+    match cpu_state.current_cycle {
+        1..=2 => absolute_indexed_address(cpu_state, memory, get_index(cpu_state)),
+        3 => {
+            let _ = memory.load(cpu_state.effective_address);
+            fix_address(cpu_state);
+        }
+        _ => return modify(cpu_state, memory, 4, f),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn modify_indirect_x<M: Memory, F: FnOnce(&mut CpuState, u8) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=4 => indirect_x_address(cpu_state, memory),
+        _ => return modify(cpu_state, memory, 5, f),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn modify_indirect_y<M: Memory, F: FnOnce(&mut CpuState, u8) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=3 => indirect_y_address(cpu_state, memory),
+        4 => {
+            let _ = memory.load(cpu_state.effective_address);
+            fix_address(cpu_state);
+        }
+        _ => return modify(cpu_state, memory, 5, f),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Instructions that only work on registers, the second cycle reads the next byte and ignores it
+pub fn implied<M: Memory, F: FnOnce(&mut CpuState)>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => {
+            let _ = memory.fetch(cpu_state.program_counter);
+            f(cpu_state);
+            ControlFlow::Break(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Branch if `flag` is `set`
+///
+/// Takes another cycle if the branch is taken, and another one if it goes to another page
+pub fn branch<M: Memory>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    flag: StatusFlags,
+    set: bool,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1 => {
+            let offset = fetch_from_pc(cpu_state, memory);
+            if cpu_state.flags.contains(flag) != set {
+                return ControlFlow::Break(());
+            }
+            cpu_state.data_latch = offset;
+        }
+        2 => {
+            let _ = memory.fetch(cpu_state.program_counter);
+            let target = cpu_state
+                .program_counter
+                .wrapping_add(cpu_state.data_latch as i8 as u16);
+            // the low byte is added first, the page is fixed in the next cycle
+            cpu_state.effective_address = target;
+            cpu_state.program_counter = (cpu_state.program_counter & 0xFF00) | (target & 0xFF);
+            if cpu_state.program_counter == target {
+                return ControlFlow::Break(());
+            }
+        }
+        3 => {
+            let _ = memory.fetch(cpu_state.program_counter);
+            cpu_state.program_counter = cpu_state.effective_address;
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+/// Store the value ANDed with the high byte of the base address plus one, for `SHA`, `SHX`, `SHY` and `TAS`
+///
+/// When the index carries into the high byte, the written value replaces the high byte of the address.
+fn store_unstable<M: Memory>(cpu_state: &CpuState, memory: &mut M, value: u8) {
+    let carry = cpu_state.page_crossed;
+    let [low_byte, high_byte] = cpu_state.effective_address.to_le_bytes();
+    // the address was already fixed if it carried
+    let value = value
+        & if carry {
+            high_byte
+        } else {
+            high_byte.wrapping_add(1)
+        };
+    let address = if carry {
+        u16::from_le_bytes([low_byte, value])
+    } else {
+        cpu_state.effective_address
+    };
+    memory.store(address, value);
+}
+
+pub fn write_unstable_absolute_indexed<M, F, I>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    get_index: I,
+    f: F,
+) -> ControlFlow<()>
+where
+    M: Memory,
+    F: FnOnce(&mut CpuState) -> u8,
+    I: FnOnce(&CpuState) -> u8,
+{
+    match cpu_state.current_cycle {
+        1..=2 => absolute_indexed_address(cpu_state, memory, get_index(cpu_state)),
+        3 => {
+            let _ = memory.load(cpu_state.effective_address);
+            fix_address(cpu_state);
+        }
+        4 => {
+            let value = f(cpu_state);
+            store_unstable(cpu_state, memory, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
+
+pub fn write_unstable_indirect_y<M: Memory, F: FnOnce(&mut CpuState) -> u8>(
+    cpu_state: &mut CpuState,
+    memory: &mut M,
+    f: F,
+) -> ControlFlow<()> //
+{
+    match cpu_state.current_cycle {
+        1..=3 => indirect_y_address(cpu_state, memory),
+        4 => {
+            let _ = memory.load(cpu_state.effective_address);
+            fix_address(cpu_state);
+        }
+        5 => {
+            let value = f(cpu_state);
+            store_unstable(cpu_state, memory, value);
+            return ControlFlow::Break(());
+        }
+        _ => unreachable!(),
+    };
+
+    ControlFlow::Continue(())
+}
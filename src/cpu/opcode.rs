@@ -0,0 +1,489 @@
+//! The opcodes and what the disassembler and the assembler need to know about them
+//!
+//! Mnemonics are the ones from the nesdev wiki, unofficial opcodes included.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::{Display, Formatter};
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+/// Every opcode, named after the instruction and its addressing mode
+///
+/// Implied and relative instructions have no mode in the name.
+/// Unofficial opcodes that do the same as another one are told apart by their value.
+/// The names are those of the NMOS chips, the 65C02 reuses the unofficial ones for its own instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, FromPrimitive, Default)]
+#[repr(u8)]
+pub enum OpCode {
+    #[default]
+    Brk = 0x00,
+    OraIndirectX = 0x01,
+    Jam02 = 0x02,
+    SloIndirectX = 0x03,
+    NopZeroPage04 = 0x04,
+    OraZeroPage = 0x05,
+    AslZeroPage = 0x06,
+    SloZeroPage = 0x07,
+    Php = 0x08,
+    OraImmediate = 0x09,
+    AslAccumulator = 0x0A,
+    AncImmediate0B = 0x0B,
+    NopAbs0C = 0x0C,
+    OraAbs = 0x0D,
+    AslAbs = 0x0E,
+    SloAbs = 0x0F,
+    Bpl = 0x10,
+    OraIndirectY = 0x11,
+    Jam12 = 0x12,
+    SloIndirectY = 0x13,
+    NopZeroPageX14 = 0x14,
+    OraZeroPageX = 0x15,
+    AslZeroPageX = 0x16,
+    SloZeroPageX = 0x17,
+    Clc = 0x18,
+    OraAbsY = 0x19,
+    Nop1A = 0x1A,
+    SloAbsY = 0x1B,
+    NopAbsX1C = 0x1C,
+    OraAbsX = 0x1D,
+    AslAbsX = 0x1E,
+    SloAbsX = 0x1F,
+    Jsr = 0x20,
+    AndIndirectX = 0x21,
+    Jam22 = 0x22,
+    RlaIndirectX = 0x23,
+    BitZeroPage = 0x24,
+    AndZeroPage = 0x25,
+    RolZeroPage = 0x26,
+    RlaZeroPage = 0x27,
+    Plp = 0x28,
+    AndImmediate = 0x29,
+    RolAccumulator = 0x2A,
+    AncImmediate2B = 0x2B,
+    BitAbs = 0x2C,
+    AndAbs = 0x2D,
+    RolAbs = 0x2E,
+    RlaAbs = 0x2F,
+    Bmi = 0x30,
+    AndIndirectY = 0x31,
+    Jam32 = 0x32,
+    RlaIndirectY = 0x33,
+    NopZeroPageX34 = 0x34,
+    AndZeroPageX = 0x35,
+    RolZeroPageX = 0x36,
+    RlaZeroPageX = 0x37,
+    Sec = 0x38,
+    AndAbsY = 0x39,
+    Nop3A = 0x3A,
+    RlaAbsY = 0x3B,
+    NopAbsX3C = 0x3C,
+    AndAbsX = 0x3D,
+    RolAbsX = 0x3E,
+    RlaAbsX = 0x3F,
+    Rti = 0x40,
+    EorIndirectX = 0x41,
+    Jam42 = 0x42,
+    SreIndirectX = 0x43,
+    NopZeroPage44 = 0x44,
+    EorZeroPage = 0x45,
+    LsrZeroPage = 0x46,
+    SreZeroPage = 0x47,
+    Pha = 0x48,
+    EorImmediate = 0x49,
+    LsrAccumulator = 0x4A,
+    AlrImmediate = 0x4B,
+    JmpAbs = 0x4C,
+    EorAbs = 0x4D,
+    LsrAbs = 0x4E,
+    SreAbs = 0x4F,
+    Bvc = 0x50,
+    EorIndirectY = 0x51,
+    Jam52 = 0x52,
+    SreIndirectY = 0x53,
+    NopZeroPageX54 = 0x54,
+    EorZeroPageX = 0x55,
+    LsrZeroPageX = 0x56,
+    SreZeroPageX = 0x57,
+    Cli = 0x58,
+    EorAbsY = 0x59,
+    Nop5A = 0x5A,
+    SreAbsY = 0x5B,
+    NopAbsX5C = 0x5C,
+    EorAbsX = 0x5D,
+    LsrAbsX = 0x5E,
+    SreAbsX = 0x5F,
+    Rts = 0x60,
+    AdcIndirectX = 0x61,
+    Jam62 = 0x62,
+    RraIndirectX = 0x63,
+    NopZeroPage64 = 0x64,
+    AdcZeroPage = 0x65,
+    RorZeroPage = 0x66,
+    RraZeroPage = 0x67,
+    Pla = 0x68,
+    AdcImmediate = 0x69,
+    RorAccumulator = 0x6A,
+    ArrImmediate = 0x6B,
+    JmpIndirect = 0x6C,
+    AdcAbs = 0x6D,
+    RorAbs = 0x6E,
+    RraAbs = 0x6F,
+    Bvs = 0x70,
+    AdcIndirectY = 0x71,
+    Jam72 = 0x72,
+    RraIndirectY = 0x73,
+    NopZeroPageX74 = 0x74,
+    AdcZeroPageX = 0x75,
+    RorZeroPageX = 0x76,
+    RraZeroPageX = 0x77,
+    Sei = 0x78,
+    AdcAbsY = 0x79,
+    Nop7A = 0x7A,
+    RraAbsY = 0x7B,
+    NopAbsX7C = 0x7C,
+    AdcAbsX = 0x7D,
+    RorAbsX = 0x7E,
+    RraAbsX = 0x7F,
+    NopImmediate80 = 0x80,
+    StaIndirectX = 0x81,
+    NopImmediate82 = 0x82,
+    SaxIndirectX = 0x83,
+    StyZeroPage = 0x84,
+    StaZeroPage = 0x85,
+    StxZeroPage = 0x86,
+    SaxZeroPage = 0x87,
+    Dey = 0x88,
+    NopImmediate89 = 0x89,
+    Txa = 0x8A,
+    XaaImmediate = 0x8B,
+    StyAbs = 0x8C,
+    StaAbs = 0x8D,
+    StxAbs = 0x8E,
+    SaxAbs = 0x8F,
+    Bcc = 0x90,
+    StaIndirectY = 0x91,
+    Jam92 = 0x92,
+    ShaIndirectY = 0x93,
+    StyZeroPageX = 0x94,
+    StaZeroPageX = 0x95,
+    StxZeroPageY = 0x96,
+    SaxZeroPageY = 0x97,
+    Tya = 0x98,
+    StaAbsY = 0x99,
+    Txs = 0x9A,
+    TasAbsY = 0x9B,
+    ShyAbsX = 0x9C,
+    StaAbsX = 0x9D,
+    ShxAbsY = 0x9E,
+    ShaAbsY = 0x9F,
+    LdyImmediate = 0xA0,
+    LdaIndirectX = 0xA1,
+    LdxImmediate = 0xA2,
+    LaxIndirectX = 0xA3,
+    LdyZeroPage = 0xA4,
+    LdaZeroPage = 0xA5,
+    LdxZeroPage = 0xA6,
+    LaxZeroPage = 0xA7,
+    Tay = 0xA8,
+    LdaImmediate = 0xA9,
+    Tax = 0xAA,
+    LxaImmediate = 0xAB,
+    LdyAbs = 0xAC,
+    LdaAbs = 0xAD,
+    LdxAbs = 0xAE,
+    LaxAbs = 0xAF,
+    Bcs = 0xB0,
+    LdaIndirectY = 0xB1,
+    JamB2 = 0xB2,
+    LaxIndirectY = 0xB3,
+    LdyZeroPageX = 0xB4,
+    LdaZeroPageX = 0xB5,
+    LdxZeroPageY = 0xB6,
+    LaxZeroPageY = 0xB7,
+    Clv = 0xB8,
+    LdaAbsY = 0xB9,
+    Tsx = 0xBA,
+    LasAbsY = 0xBB,
+    LdyAbsX = 0xBC,
+    LdaAbsX = 0xBD,
+    LdxAbsY = 0xBE,
+    LaxAbsY = 0xBF,
+    CpyImmediate = 0xC0,
+    CmpIndirectX = 0xC1,
+    NopImmediateC2 = 0xC2,
+    DcpIndirectX = 0xC3,
+    CpyZeroPage = 0xC4,
+    CmpZeroPage = 0xC5,
+    DecZeroPage = 0xC6,
+    DcpZeroPage = 0xC7,
+    Iny = 0xC8,
+    CmpImmediate = 0xC9,
+    Dex = 0xCA,
+    SbxImmediate = 0xCB,
+    CpyAbs = 0xCC,
+    CmpAbs = 0xCD,
+    DecAbs = 0xCE,
+    DcpAbs = 0xCF,
+    Bne = 0xD0,
+    CmpIndirectY = 0xD1,
+    JamD2 = 0xD2,
+    DcpIndirectY = 0xD3,
+    NopZeroPageXD4 = 0xD4,
+    CmpZeroPageX = 0xD5,
+    DecZeroPageX = 0xD6,
+    DcpZeroPageX = 0xD7,
+    Cld = 0xD8,
+    CmpAbsY = 0xD9,
+    NopDA = 0xDA,
+    DcpAbsY = 0xDB,
+    NopAbsXDC = 0xDC,
+    CmpAbsX = 0xDD,
+    DecAbsX = 0xDE,
+    DcpAbsX = 0xDF,
+    CpxImmediate = 0xE0,
+    SbcIndirectX = 0xE1,
+    NopImmediateE2 = 0xE2,
+    IsbIndirectX = 0xE3,
+    CpxZeroPage = 0xE4,
+    SbcZeroPage = 0xE5,
+    IncZeroPage = 0xE6,
+    IsbZeroPage = 0xE7,
+    Inx = 0xE8,
+    SbcImmediate = 0xE9,
+    Nop = 0xEA,
+    SbcImmediateEB = 0xEB,
+    CpxAbs = 0xEC,
+    SbcAbs = 0xED,
+    IncAbs = 0xEE,
+    IsbAbs = 0xEF,
+    Beq = 0xF0,
+    SbcIndirectY = 0xF1,
+    JamF2 = 0xF2,
+    IsbIndirectY = 0xF3,
+    NopZeroPageXF4 = 0xF4,
+    SbcZeroPageX = 0xF5,
+    IncZeroPageX = 0xF6,
+    IsbZeroPageX = 0xF7,
+    Sed = 0xF8,
+    SbcAbsY = 0xF9,
+    NopFA = 0xFA,
+    IsbAbsY = 0xFB,
+    NopAbsXFC = 0xFC,
+    SbcAbsX = 0xFD,
+    IncAbsX = 0xFE,
+    IsbAbsX = 0xFF,
+}
+
+/// How an instruction finds its operand
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddressingMode {
+    /// Number of bytes after the opcode
+    pub fn operand_size(self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether the operand points to a pointer to the address that's accessed
+    pub fn is_indirect(self) -> bool {
+        matches!(
+            self,
+            AddressingMode::Indirect | AddressingMode::IndirectX | AddressingMode::IndirectY
+        )
+    }
+
+    /// Text of an operand in this mode, like `($34),Y`
+    ///
+    /// `operand` is the bytes after the opcode, little endian, or where a branch goes in the relative mode.
+    pub fn format_operand(self, operand: u16) -> String {
+        self.format_operand_with(operand, |address, digits| format!("${address:0digits$X}"))
+    }
+
+    /// Text of an operand in this mode, with addresses formatted by `address`
+    /// from the address and the number of hex digits it's normally written with
+    pub fn format_operand_with(
+        self,
+        operand: u16,
+        mut address: impl FnMut(u16, usize) -> String,
+    ) -> String {
+        let byte = operand as u8;
+        match self {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${byte:02X}"),
+            AddressingMode::ZeroPage => address(byte as u16, 2),
+            AddressingMode::ZeroPageX => format!("{},X", address(byte as u16, 2)),
+            AddressingMode::ZeroPageY => format!("{},Y", address(byte as u16, 2)),
+            AddressingMode::Absolute | AddressingMode::Relative => address(operand, 4),
+            AddressingMode::AbsoluteX => format!("{},X", address(operand, 4)),
+            AddressingMode::AbsoluteY => format!("{},Y", address(operand, 4)),
+            AddressingMode::Indirect => format!("({})", address(operand, 4)),
+            AddressingMode::IndirectX => format!("({},X)", address(byte as u16, 2)),
+            AddressingMode::IndirectY => format!("({}),Y", address(byte as u16, 2)),
+        }
+    }
+}
+
+#[rustfmt::skip]
+const MNEMONICS: [&str; 256] = [
+    "BRK", "ORA", "JAM", "SLO", "NOP", "ORA", "ASL", "SLO", "PHP", "ORA", "ASL", "ANC", "NOP", "ORA", "ASL", "SLO", // 0_
+    "BPL", "ORA", "JAM", "SLO", "NOP", "ORA", "ASL", "SLO", "CLC", "ORA", "NOP", "SLO", "NOP", "ORA", "ASL", "SLO", // 1_
+    "JSR", "AND", "JAM", "RLA", "BIT", "AND", "ROL", "RLA", "PLP", "AND", "ROL", "ANC", "BIT", "AND", "ROL", "RLA", // 2_
+    "BMI", "AND", "JAM", "RLA", "NOP", "AND", "ROL", "RLA", "SEC", "AND", "NOP", "RLA", "NOP", "AND", "ROL", "RLA", // 3_
+    "RTI", "EOR", "JAM", "SRE", "NOP", "EOR", "LSR", "SRE", "PHA", "EOR", "LSR", "ALR", "JMP", "EOR", "LSR", "SRE", // 4_
+    "BVC", "EOR", "JAM", "SRE", "NOP", "EOR", "LSR", "SRE", "CLI", "EOR", "NOP", "SRE", "NOP", "EOR", "LSR", "SRE", // 5_
+    "RTS", "ADC", "JAM", "RRA", "NOP", "ADC", "ROR", "RRA", "PLA", "ADC", "ROR", "ARR", "JMP", "ADC", "ROR", "RRA", // 6_
+    "BVS", "ADC", "JAM", "RRA", "NOP", "ADC", "ROR", "RRA", "SEI", "ADC", "NOP", "RRA", "NOP", "ADC", "ROR", "RRA", // 7_
+    "NOP", "STA", "NOP", "SAX", "STY", "STA", "STX", "SAX", "DEY", "NOP", "TXA", "XAA", "STY", "STA", "STX", "SAX", // 8_
+    "BCC", "STA", "JAM", "SHA", "STY", "STA", "STX", "SAX", "TYA", "STA", "TXS", "TAS", "SHY", "STA", "SHX", "SHA", // 9_
+    "LDY", "LDA", "LDX", "LAX", "LDY", "LDA", "LDX", "LAX", "TAY", "LDA", "TAX", "LXA", "LDY", "LDA", "LDX", "LAX", // A_
+    "BCS", "LDA", "JAM", "LAX", "LDY", "LDA", "LDX", "LAX", "CLV", "LDA", "TSX", "LAS", "LDY", "LDA", "LDX", "LAX", // B_
+    "CPY", "CMP", "NOP", "DCP", "CPY", "CMP", "DEC", "DCP", "INY", "CMP", "DEX", "SBX", "CPY", "CMP", "DEC", "DCP", // C_
+    "BNE", "CMP", "JAM", "DCP", "NOP", "CMP", "DEC", "DCP", "CLD", "CMP", "NOP", "DCP", "NOP", "CMP", "DEC", "DCP", // D_
+    "CPX", "SBC", "NOP", "ISB", "CPX", "SBC", "INC", "ISB", "INX", "SBC", "NOP", "SBC", "CPX", "SBC", "INC", "ISB", // E_
+    "BEQ", "SBC", "JAM", "ISB", "NOP", "SBC", "INC", "ISB", "SED", "SBC", "NOP", "ISB", "NOP", "SBC", "INC", "ISB", // F_
+];
+
+#[rustfmt::skip]
+const MODES: [AddressingMode; 256] = {
+    const IMP: AddressingMode = AddressingMode::Implied;
+    const ACC: AddressingMode = AddressingMode::Accumulator;
+    const IMM: AddressingMode = AddressingMode::Immediate;
+    const ZPG: AddressingMode = AddressingMode::ZeroPage;
+    const ZPX: AddressingMode = AddressingMode::ZeroPageX;
+    const ZPY: AddressingMode = AddressingMode::ZeroPageY;
+    const ABS: AddressingMode = AddressingMode::Absolute;
+    const ABX: AddressingMode = AddressingMode::AbsoluteX;
+    const ABY: AddressingMode = AddressingMode::AbsoluteY;
+    const IND: AddressingMode = AddressingMode::Indirect;
+    const IZX: AddressingMode = AddressingMode::IndirectX;
+    const IZY: AddressingMode = AddressingMode::IndirectY;
+    const REL: AddressingMode = AddressingMode::Relative;
+    [
+        IMP, IZX, IMP, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, ACC, IMM, ABS, ABS, ABS, ABS, // 0_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPX, ZPX, IMP, ABY, IMP, ABY, ABX, ABX, ABX, ABX, // 1_
+        ABS, IZX, IMP, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, ACC, IMM, ABS, ABS, ABS, ABS, // 2_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPX, ZPX, IMP, ABY, IMP, ABY, ABX, ABX, ABX, ABX, // 3_
+        IMP, IZX, IMP, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, ACC, IMM, ABS, ABS, ABS, ABS, // 4_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPX, ZPX, IMP, ABY, IMP, ABY, ABX, ABX, ABX, ABX, // 5_
+        IMP, IZX, IMP, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, ACC, IMM, IND, ABS, ABS, ABS, // 6_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPX, ZPX, IMP, ABY, IMP, ABY, ABX, ABX, ABX, ABX, // 7_
+        IMM, IZX, IMM, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, IMP, IMM, ABS, ABS, ABS, ABS, // 8_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPY, ZPY, IMP, ABY, IMP, ABY, ABX, ABX, ABY, ABY, // 9_
+        IMM, IZX, IMM, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, IMP, IMM, ABS, ABS, ABS, ABS, // A_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPY, ZPY, IMP, ABY, IMP, ABY, ABX, ABX, ABY, ABY, // B_
+        IMM, IZX, IMM, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, IMP, IMM, ABS, ABS, ABS, ABS, // C_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPX, ZPX, IMP, ABY, IMP, ABY, ABX, ABX, ABX, ABX, // D_
+        IMM, IZX, IMM, IZX, ZPG, ZPG, ZPG, ZPG, IMP, IMM, IMP, IMM, ABS, ABS, ABS, ABS, // E_
+        REL, IZY, IMP, IZY, ZPX, ZPX, ZPX, ZPX, IMP, ABY, IMP, ABY, ABX, ABX, ABX, ABX, // F_
+    ]
+};
+
+/// Mnemonics that only exist as unofficial opcodes
+const UNOFFICIAL_MNEMONICS: [&str; 19] = [
+    "SLO", "RLA", "SRE", "RRA", "SAX", "LAX", "DCP", "ISB", "ANC", "ALR", "ARR", "XAA", "LXA",
+    "SBX", "SHA", "SHY", "SHX", "TAS", "LAS",
+];
+
+/// Cycles of each opcode on the NMOS chips, without crossing a page or taking a branch, 0 for `JAM`
+#[rustfmt::skip]
+const CYCLES: [u8; 256] = [
+    7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, // 0_
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 1_
+    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, // 2_
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 3_
+    6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, // 4_
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 5_
+    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, // 6_
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 7_
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // 8_
+    2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, // 9_
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // A_
+    2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, // B_
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // C_
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // D_
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // E_
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // F_
+];
+
+impl OpCode {
+    /// Three letter name of the instruction
+    ///
+    /// Opcodes that lock up the CPU are called `JAM`.
+    pub fn mnemonic(self) -> &'static str {
+        MNEMONICS[u8::from(self) as usize]
+    }
+
+    pub fn addressing_mode(self) -> AddressingMode {
+        MODES[u8::from(self) as usize]
+    }
+
+    /// Cycles the instruction takes on the NMOS chips, or `None` if it locks up the CPU
+    ///
+    /// Reads that cross a page and taken branches take one more cycle, branches to another page two more.
+    pub fn cycles(self) -> Option<u8> {
+        match CYCLES[u8::from(self) as usize] {
+            0 => None,
+            cycles => Some(cycles),
+        }
+    }
+
+    /// Whether the opcode is documented, as opposed to a side effect of how the CPU decodes them
+    pub fn is_official(self) -> bool {
+        let mnemonic = self.mnemonic();
+        match self {
+            OpCode::Nop => true,
+            OpCode::SbcImmediateEB => false,
+            _ => {
+                mnemonic != "NOP" && mnemonic != "JAM" && !UNOFFICIAL_MNEMONICS.contains(&mnemonic)
+            }
+        }
+    }
+
+    /// The opcode of an instruction in a mode, the official one if there are several
+    ///
+    /// `mnemonic` is in upper case.
+    pub fn from_mnemonic_and_mode(mnemonic: &str, mode: AddressingMode) -> Option<Self> {
+        let mut opcodes = (0..=0xFF)
+            .map(OpCode::from)
+            .filter(|opcode| opcode.mnemonic() == mnemonic && opcode.addressing_mode() == mode);
+        let first = opcodes.clone().next()?;
+        Some(opcodes.find(|opcode| opcode.is_official()).unwrap_or(first))
+    }
+}
+
+/// The mnemonic and the shape of the operand, like `LDA ($nn),Y`
+impl Display for OpCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let operand = match self.addressing_mode() {
+            AddressingMode::Implied => return write!(f, "{}", self.mnemonic()),
+            AddressingMode::Immediate => "#$nn".to_string(),
+            mode => mode.format_operand_with(0, |_, digits| format!("${:n<digits$}", "")),
+        };
+        write!(f, "{} {operand}", self.mnemonic())
+    }
+}
@@ -1,12 +1,104 @@
-use crate::memory::{ram::Ram, MemoryMapping};
+use crate::{disasm::asm::assemble, memory::Memory};
 
-use super::CpuState;
+use super::{AddressingMode, CpuState, Interrupt, OpCode, Registers, StatusFlags, Variant};
+
+mod single_step;
+
+/// A bus operation of the CPU, with the address and the value read or written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BusAccess {
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+/// Flat 64KB of RAM, so instructions can be tested without the rest of the console
+///
+/// Every access is logged, so tests can check the dummy reads and writes too.
+struct TestMemory {
+    buf: Box<[u8; 0x10000]>,
+    accesses: Vec<BusAccess>,
+}
+
+impl TestMemory {
+    fn new() -> Self {
+        Self {
+            buf: Box::new([0; 0x10000]),
+            accesses: Vec::new(),
+        }
+    }
+
+    /// Memory with a program at $8000, and a CPU ready to run it
+    fn with_program(program: &[u8]) -> (CpuState, Self) {
+        let mut memory = Self::new();
+        memory.buf[0x8000..0x8000 + program.len()].copy_from_slice(program);
+        let cpu_state = CpuState::with_registers(Registers {
+            program_counter: 0x8000,
+            stack_ptr: 0xFD,
+            flags: StatusFlags::empty(),
+            ..Registers::default()
+        });
+        (cpu_state, memory)
+    }
+
+    /// Memory with an assembled program, and a CPU ready to run it from $8000
+    fn assembled(source: &str) -> (CpuState, Self) {
+        let (cpu_state, mut memory) = Self::with_program(&[]);
+        assemble(source)
+            .unwrap()
+            .write(|address, value| memory.buf[address as usize] = value);
+        (cpu_state, memory)
+    }
+}
+
+/// Run an instruction, returns how many cycles it took
+fn run_instruction(cpu_state: &mut CpuState, memory: &mut TestMemory) -> usize {
+    cpu_state.run_cycle(memory);
+    let mut cycles = 1;
+    while cpu_state.current_cycle != 0 {
+        cpu_state.run_cycle(memory);
+        cycles += 1;
+    }
+    cycles
+}
+
+/// Run an instruction, returns the bus access of each of its cycles
+///
+/// # Panics
+/// If the CPU didn't access the bus exactly once in a cycle.
+fn trace_instruction(cpu_state: &mut CpuState, memory: &mut TestMemory) -> Vec<BusAccess> {
+    memory.accesses.clear();
+    loop {
+        let accesses = memory.accesses.len();
+        cpu_state.run_cycle(memory);
+        assert_eq!(
+            memory.accesses.len(),
+            accesses + 1,
+            "cycle {} should access the bus once",
+            accesses
+        );
+        if cpu_state.current_cycle == 0 {
+            return std::mem::take(&mut memory.accesses);
+        }
+    }
+}
+
+impl Memory for TestMemory {
+    fn load(&mut self, address: u16) -> u8 {
+        let value = self.buf[address as usize];
+        self.accesses.push(BusAccess::Read(address, value));
+        value
+    }
+
+    fn store(&mut self, address: u16, value: u8) {
+        self.buf[address as usize] = value;
+        self.accesses.push(BusAccess::Write(address, value));
+    }
+}
 
 #[test]
 fn ldx_test() {
-    let mut ram = Ram::new();
     let mut cpu_state = CpuState::new();
-    let mut memory = MemoryMapping { ram: &mut ram };
+    let mut memory = TestMemory::new();
     cpu_state.program_counter = 0;
 
     #[rustfmt::skip]
@@ -87,3 +179,910 @@ fn ldx_test() {
     // the current instruction must be finished
     assert_eq!(cpu_state.current_cycle, 0);
 }
+
+#[test]
+fn interrupts() {
+    let mut cpu_state = CpuState::new();
+    let mut memory = TestMemory::new();
+    memory.store(Interrupt::Reset.vector(), 0x00);
+    memory.store(Interrupt::Reset.vector() + 1, 0x80);
+    memory.store(Interrupt::Nmi.vector(), 0x00);
+    memory.store(Interrupt::Nmi.vector() + 1, 0x90);
+    memory.store(Interrupt::Irq.vector(), 0x00);
+    memory.store(Interrupt::Irq.vector() + 1, 0xA0);
+    // LDX #1 everywhere
+    for address in [0x8000, 0x9000, 0xA000] {
+        memory.store(address, 0xA2);
+        memory.store(address + 1, 0x01);
+    }
+
+    cpu_state.reset();
+    (0..7).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x8000);
+    assert!(cpu_state.flags.contains(StatusFlags::INTERRUPT_DISABLE));
+    let stack_pointer = cpu_state.stack_ptr;
+
+    // IRQs are ignored while the I flag is set
+    cpu_state.set_irq_line(true);
+    cpu_state.run_cycle(&mut memory);
+    // NMI is edge triggered
+    cpu_state.set_nmi_line(true);
+    cpu_state.run_cycle(&mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8002);
+
+    (0..7).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    assert_eq!(cpu_state.stack_ptr, stack_pointer.wrapping_sub(3));
+    assert_eq!(memory.load(0x0100 | stack_pointer as u16), 0x80);
+    assert_eq!(
+        memory.load(0x0100 | stack_pointer.wrapping_sub(1) as u16),
+        0x02
+    );
+    (0..2).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x9002);
+
+    cpu_state.flags.remove(StatusFlags::INTERRUPT_DISABLE);
+    (0..7).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0xA000);
+    // break flag is clear when pushed by an interrupt
+    let status = memory.load(0x0100 | cpu_state.stack_ptr.wrapping_add(1) as u16);
+    assert_eq!(status & StatusFlags::BREAK.bits(), 0);
+}
+
+#[test]
+fn delayed_irq() {
+    let source = "
+        CLI
+        NOP
+        SEI
+        LDA #0
+        PHA
+        PLP
+        NOP
+        ";
+    let (mut cpu_state, mut memory) = TestMemory::assembled(source);
+    memory.store(Interrupt::Irq.vector(), 0x00);
+    memory.store(Interrupt::Irq.vector() + 1, 0xA0);
+    // RTI
+    memory.store(0xA000, 0x40);
+    cpu_state.flags = StatusFlags::INTERRUPT_DISABLE;
+    cpu_state.set_irq_line(true);
+
+    // the instruction after CLI runs before the IRQ
+    run_instruction(&mut cpu_state, &mut memory);
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8002);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(cpu_state.program_counter, 0xA000);
+
+    // RTI clears the I flag right away, so the IRQ comes again
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8002);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(cpu_state.program_counter, 0xA000);
+    cpu_state.set_irq_line(false);
+    run_instruction(&mut cpu_state, &mut memory);
+
+    // the IRQ still comes right after SEI
+    run_instruction(&mut cpu_state, &mut memory);
+    assert!(cpu_state.flags.contains(StatusFlags::INTERRUPT_DISABLE));
+    cpu_state.set_irq_line(true);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    let return_low = memory.load(0x0100 | cpu_state.stack_ptr.wrapping_add(2) as u16);
+    assert_eq!(return_low, 0x03);
+    // the pushed status has I set, RTI keeps it set
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8003);
+
+    // PLP is delayed the same way as CLI
+    (0..3).for_each(|_| {
+        run_instruction(&mut cpu_state, &mut memory);
+    });
+    assert!(!cpu_state.flags.contains(StatusFlags::INTERRUPT_DISABLE));
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8008);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(cpu_state.program_counter, 0xA000);
+}
+
+#[test]
+fn nmi_timing() {
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[0xEA; 8]);
+    memory.store(Interrupt::Nmi.vector(), 0x00);
+    memory.store(Interrupt::Nmi.vector() + 1, 0x90);
+    // RTI
+    memory.store(0x9000, 0x40);
+
+    // an NMI before the last cycle is handled after the instruction
+    cpu_state.run_cycle(&mut memory);
+    cpu_state.set_nmi_line(true);
+    cpu_state.run_cycle(&mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8001);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    cpu_state.set_nmi_line(false);
+    run_instruction(&mut cpu_state, &mut memory);
+
+    // one in the last cycle is only handled after the next instruction
+    cpu_state.set_nmi_line(true);
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8002);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    run_instruction(&mut cpu_state, &mut memory);
+
+    // the edge is remembered even if the line goes back right away
+    cpu_state.set_nmi_line(false);
+    cpu_state.run_cycle(&mut memory);
+    cpu_state.set_nmi_line(true);
+    cpu_state.set_nmi_line(false);
+    cpu_state.run_cycle(&mut memory);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    run_instruction(&mut cpu_state, &mut memory);
+
+    // and it's only handled once
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8004);
+}
+
+#[test]
+fn interrupt_hijacking() {
+    let setup = |variant| {
+        // BRK, NOP
+        let (mut cpu_state, mut memory) = TestMemory::with_program(&[0x00, 0x00, 0xEA]);
+        cpu_state.variant = variant;
+        memory.store(Interrupt::Nmi.vector(), 0x00);
+        memory.store(Interrupt::Nmi.vector() + 1, 0x90);
+        memory.store(Interrupt::Irq.vector(), 0x00);
+        memory.store(Interrupt::Irq.vector() + 1, 0xA0);
+        // NOP in both handlers
+        memory.store(0x9000, 0xEA);
+        memory.store(0xA000, 0xEA);
+        (cpu_state, memory)
+    };
+    let pushed_flags = |cpu_state: &CpuState, memory: &TestMemory| {
+        memory.buf[0x0100 | cpu_state.stack_ptr.wrapping_add(1) as usize]
+    };
+
+    // an NMI before the flags are pushed takes over BRK, which still pushes the B flag
+    let (mut cpu_state, mut memory) = setup(Variant::Ricoh2A03);
+    (0..4).for_each(|_| cpu_state.run_cycle(&mut memory));
+    cpu_state.set_nmi_line(true);
+    (0..3).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    assert_eq!(cpu_state.current_interrupt(), Some(Interrupt::Nmi));
+    assert_ne!(
+        pushed_flags(&cpu_state, &memory) & StatusFlags::BREAK.bits(),
+        0
+    );
+    assert_eq!(memory.load(0x01FC), 0x02);
+    // and the NMI isn't handled again
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x9001);
+
+    // the same goes for IRQs
+    let (mut cpu_state, mut memory) = setup(Variant::Ricoh2A03);
+    cpu_state.program_counter = 0x8002;
+    cpu_state.set_irq_line(true);
+    (0..2).for_each(|_| cpu_state.run_cycle(&mut memory));
+    cpu_state.set_nmi_line(true);
+    (0..5).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    assert_eq!(
+        pushed_flags(&cpu_state, &memory) & StatusFlags::BREAK.bits(),
+        0
+    );
+
+    // an NMI that comes later waits for the first instruction of the handler
+    let (mut cpu_state, mut memory) = setup(Variant::Ricoh2A03);
+    (0..5).for_each(|_| cpu_state.run_cycle(&mut memory));
+    cpu_state.set_nmi_line(true);
+    (0..2).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0xA000);
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0xA001);
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x9000);
+
+    // the 65C02 finishes BRK first
+    let (mut cpu_state, mut memory) = setup(Variant::Cmos65C02);
+    (0..4).for_each(|_| cpu_state.run_cycle(&mut memory));
+    cpu_state.set_nmi_line(true);
+    (0..3).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0xA000);
+    run_instruction(&mut cpu_state, &mut memory);
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.program_counter, 0x9000);
+}
+
+#[test]
+fn constructors() {
+    let mut memory = TestMemory::new();
+    memory.store(Interrupt::Reset.vector(), 0x34);
+    memory.store(Interrupt::Reset.vector() + 1, 0x82);
+    let mut reset = CpuState::new();
+    reset.reset();
+    (0..7).for_each(|_| reset.run_cycle(&mut memory));
+
+    // the same as going through the reset sequence from power on
+    let cpu_state = CpuState::from_reset_vector(&mut memory);
+    assert_eq!(cpu_state.program_counter, 0x8234);
+    assert_eq!(cpu_state.stack_ptr, reset.stack_ptr);
+    assert_eq!(cpu_state.flags.bits(), reset.flags.bits());
+    assert_eq!(cpu_state.current_cycle, 0);
+    assert_eq!(CpuState::with_pc(0x8234).stack_ptr, reset.stack_ptr);
+
+    let registers = Registers {
+        accumulator: 1,
+        x_index: 2,
+        y_index: 3,
+        program_counter: 0x1234,
+        stack_ptr: 0x80,
+        flags: StatusFlags::CARRY | StatusFlags::ZERO,
+    };
+    let cpu_state = CpuState::with_registers(registers);
+    assert_eq!(cpu_state.accumulator, 1);
+    assert_eq!(cpu_state.x_index, 2);
+    assert_eq!(cpu_state.y_index, 3);
+    assert_eq!(cpu_state.program_counter, 0x1234);
+    assert_eq!(cpu_state.stack_ptr, 0x80);
+    assert_eq!(cpu_state.registers().flags.bits(), registers.flags.bits());
+}
+
+#[test]
+fn opcode_metadata() {
+    let opcode = OpCode::from(0xB1);
+    assert_eq!(opcode.mnemonic(), "LDA");
+    assert_eq!(opcode.addressing_mode(), AddressingMode::IndirectY);
+    assert!(opcode.is_official());
+    assert_eq!(opcode.to_string(), "LDA ($nn),Y");
+    assert_eq!(OpCode::from(0x0A).to_string(), "ASL A");
+    assert_eq!(OpCode::from(0x18).to_string(), "CLC");
+
+    assert!(!OpCode::from(0xA7).is_official());
+    assert!(!OpCode::from(0x1A).is_official());
+    assert!(!OpCode::from(0xEB).is_official());
+    assert!(!OpCode::from(0x02).is_official());
+
+    // the official opcode is picked over the unofficial ones that do the same
+    let lookup = OpCode::from_mnemonic_and_mode;
+    assert_eq!(lookup("NOP", AddressingMode::Implied), Some(OpCode::Nop));
+    assert_eq!(
+        lookup("SBC", AddressingMode::Immediate).map(u8::from),
+        Some(0xE9)
+    );
+    assert_eq!(
+        lookup("LAX", AddressingMode::ZeroPage).map(u8::from),
+        Some(0xA7)
+    );
+    assert_eq!(lookup("STA", AddressingMode::Immediate), None);
+    for value in 0..=0xFF {
+        let opcode = OpCode::from(value);
+        let found = lookup(opcode.mnemonic(), opcode.addressing_mode()).unwrap();
+        assert_eq!(found.addressing_mode(), opcode.addressing_mode());
+    }
+}
+
+#[test]
+fn addressing_modes() {
+    use AddressingMode::*;
+
+    let cases = [
+        (Implied, 0, ""),
+        (Accumulator, 0, "A"),
+        (Immediate, 0x12, "#$12"),
+        (ZeroPage, 0x34, "$34"),
+        (ZeroPageX, 0x34, "$34,X"),
+        (ZeroPageY, 0x34, "$34,Y"),
+        (Absolute, 0x1234, "$1234"),
+        (AbsoluteX, 0x1234, "$1234,X"),
+        (AbsoluteY, 0x1234, "$1234,Y"),
+        (Indirect, 0x1234, "($1234)"),
+        (IndirectX, 0x34, "($34,X)"),
+        (IndirectY, 0x34, "($34),Y"),
+        (Relative, 0x8010, "$8010"),
+    ];
+    for (mode, operand, text) in cases {
+        assert_eq!(mode.format_operand(operand), text, "{mode:?}");
+    }
+    assert_eq!(
+        IndirectY.format_operand_with(0x34, |address, _| format!("ptr+{address}")),
+        "(ptr+52),Y"
+    );
+    assert_eq!(Immediate.operand_size(), 1);
+    assert_eq!(Indirect.operand_size(), 2);
+    assert_eq!(Accumulator.operand_size(), 0);
+    assert!(IndirectX.is_indirect());
+    assert!(!AbsoluteX.is_indirect());
+}
+
+#[test]
+fn opcode_cycles() {
+    for value in 0..=0xFF {
+        let Some(cycles) = OpCode::from(value).cycles() else {
+            continue;
+        };
+        // the operand doesn't cross a page with the indexes at 0, and branches aren't taken
+        // with one of the two sets of flags
+        let taken = [StatusFlags::empty(), StatusFlags::all()].map(|flags| {
+            let (mut cpu_state, mut memory) = TestMemory::with_program(&[value, 0x10, 0x00]);
+            cpu_state.flags = flags;
+            run_instruction(&mut cpu_state, &mut memory)
+        });
+        let found = taken.into_iter().min().unwrap();
+        assert_eq!(found, cycles as usize, "opcode ${value:02X}");
+    }
+}
+
+#[test]
+fn instruction_progress() {
+    // LDA $1234, BEQ +$10
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[0xAD, 0x34, 0x12, 0xF0, 0x10]);
+    assert!(!cpu_state.instruction_in_progress());
+    assert_eq!(cpu_state.remaining_cycles(), Some(0));
+
+    cpu_state.run_cycle(&mut memory);
+    assert!(cpu_state.instruction_in_progress());
+    assert_eq!(cpu_state.cycles_into_instruction(), 1);
+    assert_eq!(cpu_state.remaining_cycles(), Some(3));
+    (0..3).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert!(!cpu_state.instruction_in_progress());
+
+    // the taken branch is only counted once it's known
+    (0..2).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.remaining_cycles(), Some(1));
+    cpu_state.set_nmi_line(true);
+    cpu_state.run_cycle(&mut memory);
+    assert!(!cpu_state.instruction_in_progress());
+
+    (0..2).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.current_interrupt(), Some(Interrupt::Nmi));
+    assert_eq!(cpu_state.remaining_cycles(), Some(5));
+
+    // JAM
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[0x02]);
+    cpu_state.run_cycle(&mut memory);
+    assert_eq!(cpu_state.remaining_cycles(), None);
+}
+
+#[test]
+fn subroutines() {
+    let mut cpu_state = CpuState::with_pc(0x8000);
+    let mut memory = TestMemory::new();
+    // JSR $9000
+    memory.store(0x8000, 0x20);
+    memory.store(0x8001, 0x00);
+    memory.store(0x8002, 0x90);
+    // RTS
+    memory.store(0x9000, 0x60);
+
+    (0..6).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x9000);
+    assert_eq!(cpu_state.stack_ptr, 0xFB);
+    // the address of the last byte of the JSR is pushed
+    assert_eq!(memory.load(0x01FD), 0x80);
+    assert_eq!(memory.load(0x01FC), 0x02);
+
+    (0..6).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x8003);
+    assert_eq!(cpu_state.stack_ptr, 0xFD);
+    assert_eq!(cpu_state.current_cycle, 0);
+}
+
+#[test]
+fn brk_and_rti() {
+    let mut cpu_state = CpuState::with_registers(Registers {
+        program_counter: 0x8000,
+        stack_ptr: 0xFD,
+        flags: StatusFlags::CARRY,
+        ..Registers::default()
+    });
+    let mut memory = TestMemory::new();
+    memory.store(Interrupt::Irq.vector(), 0x00);
+    memory.store(Interrupt::Irq.vector() + 1, 0xA0);
+    // BRK, padding byte
+    memory.store(0x8000, 0x00);
+    // RTI
+    memory.store(0xA000, 0x40);
+
+    (0..7).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0xA000);
+    assert!(cpu_state.flags.contains(StatusFlags::INTERRUPT_DISABLE));
+    // the padding byte is skipped and the B flag is pushed
+    assert_eq!(memory.load(0x01FC), 0x02);
+    let status = StatusFlags::from_bits_retain(memory.load(0x01FB));
+    assert!(status.contains(StatusFlags::BREAK | StatusFlags::CARRY));
+
+    (0..6).for_each(|_| cpu_state.run_cycle(&mut memory));
+    assert_eq!(cpu_state.program_counter, 0x8002);
+    assert_eq!(cpu_state.stack_ptr, 0xFD);
+    assert_eq!(cpu_state.flags.bits(), StatusFlags::CARRY.bits());
+}
+
+#[test]
+fn arithmetic() {
+    #[rustfmt::skip]
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[
+        // CLC, LDA #$7F, ADC #$01
+        0x18, 0xA9, 0x7F, 0x69, 0x01,
+        // ADC #$80
+        0x69, 0x80,
+        // SEC, SBC #$01
+        0x38, 0xE9, 0x01,
+        // CMP #$7F
+        0xC9, 0x7F,
+    ]);
+    (0..3).for_each(|_| {
+        run_instruction(&mut cpu_state, &mut memory);
+    });
+    // signed overflow into the sign bit
+    assert_eq!(cpu_state.accumulator, 0x80);
+    assert_eq!(
+        cpu_state.flags.bits(),
+        (StatusFlags::OVERFLOW | StatusFlags::NEGATIVE).bits()
+    );
+
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(cpu_state.accumulator, 0x00);
+    assert_eq!(
+        cpu_state.flags.bits(),
+        (StatusFlags::CARRY | StatusFlags::ZERO | StatusFlags::OVERFLOW).bits()
+    );
+
+    (0..2).for_each(|_| {
+        run_instruction(&mut cpu_state, &mut memory);
+    });
+    // borrowing clears the carry
+    assert_eq!(cpu_state.accumulator, 0xFF);
+    assert_eq!(cpu_state.flags.bits(), StatusFlags::NEGATIVE.bits());
+
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(
+        cpu_state.flags.bits(),
+        (StatusFlags::CARRY | StatusFlags::NEGATIVE).bits()
+    );
+}
+
+#[test]
+fn read_modify_write() {
+    // INC $10, ROL A, DEC $0FFF,X (X = 1)
+    let (mut cpu_state, mut memory) =
+        TestMemory::with_program(&[0xE6, 0x10, 0x2A, 0xDE, 0xFF, 0x0F]);
+    memory.store(0x0010, 0xFF);
+    memory.store(0x1000, 0x01);
+    cpu_state.x_index = 1;
+    cpu_state.accumulator = 0x80;
+
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 5);
+    assert_eq!(memory.load(0x0010), 0x00);
+    assert!(cpu_state.flags.contains(StatusFlags::ZERO));
+
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 2);
+    assert_eq!(cpu_state.accumulator, 0x00);
+    assert!(cpu_state.flags.contains(StatusFlags::CARRY));
+
+    // indexed read-modify-write always takes the extra cycle
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 7);
+    assert_eq!(memory.load(0x1000), 0x00);
+}
+
+#[test]
+fn branches() {
+    #[rustfmt::skip]
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[
+        // BEQ +2 (not taken), BNE +2 (taken)
+        0xF0, 0x02, 0xD0, 0x02,
+        // padding
+        0x00, 0x00,
+        // BNE -128 (taken, crosses a page)
+        0xD0, 0x80,
+    ]);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 2);
+    assert_eq!(cpu_state.program_counter, 0x8002);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 3);
+    assert_eq!(cpu_state.program_counter, 0x8006);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 4);
+    assert_eq!(cpu_state.program_counter, 0x7F88);
+}
+
+#[test]
+fn jumps_and_stack() {
+    #[rustfmt::skip]
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[
+        // PHP, PHA, LDA #0, PLA, PLP
+        0x08, 0x48, 0xA9, 0x00, 0x68, 0x28,
+        // JMP ($10FF)
+        0x6C, 0xFF, 0x10,
+    ]);
+    // the pointer's high byte comes from the start of its page
+    memory.store(0x10FF, 0x34);
+    memory.store(0x1000, 0x12);
+    memory.store(0x1100, 0x56);
+    cpu_state.accumulator = 0x42;
+    cpu_state.flags = StatusFlags::CARRY;
+
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 3);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 3);
+    let pushed = StatusFlags::CARRY | StatusFlags::BREAK | StatusFlags::IGNORED_FLAG;
+    assert_eq!(memory.load(0x01FD), pushed.bits());
+    assert_eq!(memory.load(0x01FC), 0x42);
+
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 4);
+    assert_eq!(cpu_state.accumulator, 0x42);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 4);
+    assert_eq!(cpu_state.flags.bits(), StatusFlags::CARRY.bits());
+    assert_eq!(cpu_state.stack_ptr, 0xFD);
+
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 5);
+    assert_eq!(cpu_state.program_counter, 0x1234);
+}
+
+#[test]
+fn bus_accesses() {
+    use BusAccess::{Read, Write};
+
+    #[rustfmt::skip]
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[
+        // INC $10, STA $10FF,X (X = 1), LDA ($20),Y (Y = 1)
+        0xE6, 0x10, 0x9D, 0xFF, 0x10, 0xB1, 0x20,
+        // BNE -128, across a page
+        0xD0, 0x80,
+    ]);
+    memory.buf[0x0010] = 0x41;
+    memory.buf[0x0020] = 0xFF;
+    memory.buf[0x0021] = 0x02;
+    memory.buf[0x0300] = 0x99;
+    cpu_state.x_index = 1;
+    cpu_state.y_index = 1;
+    cpu_state.accumulator = 0x55;
+
+    // the old value is written back while the new one is computed
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory),
+        [
+            Read(0x8000, 0xE6),
+            Read(0x8001, 0x10),
+            Read(0x0010, 0x41),
+            Write(0x0010, 0x41),
+            Write(0x0010, 0x42),
+        ]
+    );
+    // indexed stores always read the address before the carry is added
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory),
+        [
+            Read(0x8002, 0x9D),
+            Read(0x8003, 0xFF),
+            Read(0x8004, 0x10),
+            Read(0x1000, 0x00),
+            Write(0x1100, 0x55),
+        ]
+    );
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory),
+        [
+            Read(0x8005, 0xB1),
+            Read(0x8006, 0x20),
+            Read(0x0020, 0xFF),
+            Read(0x0021, 0x02),
+            Read(0x0200, 0x00),
+            Read(0x0300, 0x99),
+        ]
+    );
+    // the next opcode is read while the branch is taken
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory),
+        [
+            Read(0x8007, 0xD0),
+            Read(0x8008, 0x80),
+            Read(0x8009, 0x00),
+            Read(0x8089, 0x00),
+        ]
+    );
+    assert_eq!(cpu_state.program_counter, 0x7F89);
+}
+
+/// Every addressing mode of read-modify-write instructions writes the old value back before the new one
+///
+/// Mappers that ignore writes on consecutive cycles, and the PPU's $2007 which moves the address
+/// on every access, can tell.
+#[test]
+fn read_modify_write_double_write() {
+    use BusAccess::{Read, Write};
+
+    #[rustfmt::skip]
+    let programs: [(&[u8], u16); 8] = [
+        // ASL $10
+        (&[0x06, 0x10], 0x0010),
+        // ROL $10,X
+        (&[0x36, 0x10], 0x0011),
+        // LSR $1234
+        (&[0x4E, 0x34, 0x12], 0x1234),
+        // ROR $1234,X
+        (&[0x7E, 0x34, 0x12], 0x1235),
+        // DEC $12FF,X, across a page
+        (&[0xDE, 0xFF, 0x12], 0x1300),
+        // SLO $1234,Y
+        (&[0x1B, 0x34, 0x12], 0x1235),
+        // DCP ($20,X)
+        (&[0xC3, 0x20], 0x0404),
+        // ISB ($20),Y
+        (&[0xF3, 0x20], 0x0457),
+    ];
+    for (program, target) in programs {
+        let (mut cpu_state, mut memory) = TestMemory::with_program(program);
+        cpu_state.x_index = 1;
+        cpu_state.y_index = 1;
+        memory.buf[0x0020] = 0x56;
+        memory.buf[0x0021] = 0x04;
+        memory.buf[0x0022] = 0x04;
+        memory.buf[target as usize] = 0x81;
+
+        let accesses = trace_instruction(&mut cpu_state, &mut memory);
+        let new_value = memory.buf[target as usize];
+        assert_ne!(new_value, 0x81, "opcode ${:02X}", program[0]);
+        assert_eq!(
+            accesses[accesses.len() - 3..],
+            [
+                Read(target, 0x81),
+                Write(target, 0x81),
+                Write(target, new_value)
+            ],
+            "opcode ${:02X}",
+            program[0]
+        );
+    }
+}
+
+/// Indexed stores and read-modify-writes read the address before the carry into the high byte
+/// is added, whether or not there is one
+#[test]
+fn indexed_dummy_reads() {
+    use BusAccess::{Read, Write};
+
+    #[rustfmt::skip]
+    let (mut cpu_state, mut memory) = TestMemory::with_program(&[
+        // INC $20F2,X (X = $10), STA ($30),Y (Y = $10), ROL $2000,X
+        0xFE, 0xF2, 0x20, 0x91, 0x30, 0x3E, 0x00, 0x20,
+    ]);
+    memory.buf[0x0030] = 0xF8;
+    memory.buf[0x0031] = 0x20;
+    memory.buf[0x2002] = 0x11;
+    memory.buf[0x2102] = 0x22;
+    memory.buf[0x2108] = 0x33;
+    memory.buf[0x2010] = 0x44;
+    cpu_state.x_index = 0x10;
+    cpu_state.y_index = 0x10;
+    cpu_state.accumulator = 0x55;
+
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory)[3..],
+        [
+            Read(0x2002, 0x11),
+            Read(0x2102, 0x22),
+            Write(0x2102, 0x22),
+            Write(0x2102, 0x23),
+        ]
+    );
+    // the carry is kept out of the flags
+    assert!(cpu_state.page_crossed);
+    assert!(!cpu_state.flags.contains(StatusFlags::IGNORED_FLAG));
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory)[4..],
+        [Read(0x2008, 0x00), Write(0x2108, 0x55)]
+    );
+    // without a carry the same address is read twice
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory)[3..5],
+        [Read(0x2010, 0x44), Read(0x2010, 0x44)]
+    );
+}
+
+#[test]
+fn decimal_mode() {
+    use super::dispatch::instructions::{adc, sbc};
+
+    let run = |variant, operation: fn(&mut CpuState, u8), accumulator, value, carry| {
+        let mut cpu_state = CpuState::with_variant(variant);
+        cpu_state.flags = StatusFlags::DECIMAL;
+        cpu_state.flags.set(StatusFlags::CARRY, carry);
+        cpu_state.accumulator = accumulator;
+        operation(&mut cpu_state, value);
+        (
+            cpu_state.accumulator,
+            (cpu_state.flags - StatusFlags::DECIMAL).bits(),
+        )
+    };
+
+    // the NES CPU adds in binary even with the flag set
+    assert_eq!(run(Variant::Ricoh2A03, adc, 0x15, 0x27, false).0, 0x3C);
+    for variant in [Variant::Nmos6502, Variant::Cmos65C02] {
+        assert_eq!(run(variant, adc, 0x15, 0x27, false).0, 0x42);
+        assert_eq!(run(variant, adc, 0x58, 0x46, true).0, 0x05);
+        assert_eq!(run(variant, sbc, 0x42, 0x15, true).0, 0x27);
+        assert_eq!(
+            run(variant, sbc, 0x00, 0x01, true),
+            (0x99, StatusFlags::NEGATIVE.bits())
+        );
+        assert_eq!(run(variant, sbc, 0x21, 0x34, false).0, 0x86);
+    }
+
+    // the NMOS zero flag comes from the binary sum, $9A
+    assert_eq!(
+        run(Variant::Nmos6502, adc, 0x99, 0x01, false),
+        (0x00, (StatusFlags::CARRY | StatusFlags::NEGATIVE).bits())
+    );
+    assert_eq!(
+        run(Variant::Cmos65C02, adc, 0x99, 0x01, false),
+        (0x00, (StatusFlags::CARRY | StatusFlags::ZERO).bits())
+    );
+}
+
+#[test]
+fn cmos_65c02() {
+    #[rustfmt::skip]
+    let program = [
+        // STZ $10, BRA +1
+        0x64, 0x10, 0x80, 0x01,
+        // padding
+        0x00,
+        // PHX, PLY, INC A, NOP (1 cycle)
+        0xDA, 0x7A, 0x1A, 0x03,
+        // LDA ($20), TSB $30
+        0xB2, 0x20, 0x04, 0x30,
+        // JMP ($10FF)
+        0x6C, 0xFF, 0x10,
+    ];
+    let (cpu_state, mut memory) = TestMemory::with_program(&program);
+    let mut cpu_state = CpuState {
+        variant: Variant::Cmos65C02,
+        ..cpu_state
+    };
+    memory.store(0x0010, 0xAA);
+    memory.store(0x0020, 0x00);
+    memory.store(0x0021, 0x02);
+    memory.store(0x0200, 0x0F);
+    memory.store(0x0030, 0xF0);
+    memory.store(0x10FF, 0x34);
+    memory.store(0x1000, 0x12);
+    memory.store(0x1100, 0x56);
+    cpu_state.x_index = 0x42;
+
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 3);
+    assert_eq!(memory.load(0x0010), 0x00);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 3);
+    assert_eq!(cpu_state.program_counter, 0x8005);
+
+    run_instruction(&mut cpu_state, &mut memory);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 4);
+    assert_eq!(cpu_state.y_index, 0x42);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 2);
+    assert_eq!(cpu_state.accumulator, 0x01);
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 1);
+
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 5);
+    assert_eq!(cpu_state.accumulator, 0x0F);
+    // read-modify-write reads twice instead of writing twice
+    assert_eq!(
+        trace_instruction(&mut cpu_state, &mut memory)[2..],
+        [
+            BusAccess::Read(0x0030, 0xF0),
+            BusAccess::Read(0x0030, 0xF0),
+            BusAccess::Write(0x0030, 0xFF),
+        ]
+    );
+    assert!(cpu_state.flags.contains(StatusFlags::ZERO));
+
+    // the pointer carries into the next page
+    assert_eq!(run_instruction(&mut cpu_state, &mut memory), 6);
+    assert_eq!(cpu_state.program_counter, 0x5634);
+}
+
+/// Flags after adding `value` and the carry to `accumulator`, worked out the long way
+fn reference_adc(accumulator: u8, value: u8, carry: bool) -> (u8, StatusFlags) {
+    let unsigned = accumulator as u16 + value as u16 + carry as u16;
+    let signed = accumulator as i8 as i16 + value as i8 as i16 + carry as i16;
+    let result = unsigned as u8;
+    let mut flags = StatusFlags::empty();
+    flags.set(StatusFlags::CARRY, unsigned > 0xFF);
+    flags.set(StatusFlags::OVERFLOW, !(-128..=127).contains(&signed));
+    flags.set(StatusFlags::ZERO, result == 0);
+    flags.set(StatusFlags::NEGATIVE, result >= 0x80);
+    (result, flags)
+}
+
+/// Every accumulator, operand and carry, against the reference model
+#[test]
+fn alu_against_reference() {
+    use super::dispatch::instructions::{adc, cmp, sbc};
+
+    for accumulator in 0..=0xFF {
+        for value in 0..=0xFF {
+            for carry in [false, true] {
+                let mut cpu_state = CpuState::new();
+                cpu_state.flags = StatusFlags::empty();
+                cpu_state.flags.set(StatusFlags::CARRY, carry);
+                cpu_state.accumulator = accumulator;
+                let start = cpu_state;
+
+                adc(&mut cpu_state, value);
+                let (result, flags) = reference_adc(accumulator, value, carry);
+                let context = format!("ADC ${accumulator:02X} + ${value:02X} + {carry}");
+                assert_eq!(cpu_state.accumulator, result, "{context}");
+                assert_eq!(cpu_state.flags.bits(), flags.bits(), "{context}");
+
+                // subtracting is adding the complement, with the carry as "no borrow"
+                let mut cpu_state = start;
+                sbc(&mut cpu_state, value);
+                let difference = accumulator as i16 - value as i16 - !carry as i16;
+                let signed = accumulator as i8 as i16 - value as i8 as i16 - !carry as i16;
+                let context = format!("SBC ${accumulator:02X} - ${value:02X} - {}", !carry);
+                assert_eq!(cpu_state.accumulator, difference as u8, "{context}");
+                let flags = cpu_state.flags;
+                assert_eq!(
+                    flags.contains(StatusFlags::CARRY),
+                    difference >= 0,
+                    "{context}"
+                );
+                assert_eq!(
+                    flags.contains(StatusFlags::OVERFLOW),
+                    !(-128..=127).contains(&signed),
+                    "{context}"
+                );
+
+                // comparing doesn't use or change the overflow or the incoming carry
+                let mut cpu_state = start;
+                cpu_state.flags.insert(StatusFlags::OVERFLOW);
+                cmp(&mut cpu_state, value);
+                let context = format!("CMP ${accumulator:02X} ${value:02X}");
+                let flags = cpu_state.flags;
+                assert_eq!(cpu_state.accumulator, accumulator, "{context}");
+                assert!(flags.contains(StatusFlags::OVERFLOW), "{context}");
+                assert_eq!(
+                    flags.contains(StatusFlags::CARRY),
+                    accumulator >= value,
+                    "{context}"
+                );
+                assert_eq!(
+                    flags.contains(StatusFlags::ZERO),
+                    accumulator == value,
+                    "{context}"
+                );
+                assert_eq!(
+                    flags.contains(StatusFlags::NEGATIVE),
+                    accumulator.wrapping_sub(value) >= 0x80,
+                    "{context}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn assembled_program() {
+    let source = "
+        LDX #5
+        LDA #0
+loop:   JSR add3
+        DEX
+        BNE loop
+        STA $10
+done:   JMP done
+
+add3:   CLC
+        ADC #3
+        RTS
+        ";
+    let done = assemble(source).unwrap().label("done");
+    let (mut cpu_state, mut memory) = TestMemory::assembled(source);
+    let mut instructions = 0;
+    while cpu_state.program_counter != done && instructions < 100 {
+        run_instruction(&mut cpu_state, &mut memory);
+        instructions += 1;
+    }
+    assert_eq!(cpu_state.program_counter, done);
+    assert_eq!(memory.buf[0x0010], 15);
+    assert_eq!(cpu_state.stack_ptr, 0xFD);
+}
@@ -0,0 +1,372 @@
+//! Runs Tom Harte's [SingleStepTests](https://github.com/SingleStepTests/65x02) for the NES CPU
+//!
+//! The tests aren't part of the repository, they're about 10000 cases per opcode.
+//! Point `NESTY_SINGLE_STEP_TESTS` at the `nes6502/v1` directory and run
+//! `cargo test single_step -- --ignored`.
+//! Every case starts the CPU in an initial state, runs one instruction and compares
+//! the registers, the RAM and every bus access it made with the expected ones.
+
+use std::{fmt::Write, path::Path};
+
+use super::{BusAccess, CpuState, Registers, StatusFlags, TestMemory};
+
+/// Opcodes that halt the CPU, the tests expect them to keep going
+const JAM: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xB2, 0xD2, 0xF2,
+];
+
+/// B and the unused bit only exist when the flags are pushed
+const PUSHED_BITS: u8 = 0x30;
+
+/// Just enough JSON for the test files
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.position == parser.bytes.len() {
+            true => Ok(value),
+            false => Err(parser.error("trailing characters")),
+        }
+    }
+
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value)
+                .unwrap_or(&Json::Null),
+            _ => &Json::Null,
+        }
+    }
+
+    fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(values) => values,
+            _ => panic!("expected an array, found {self:?}"),
+        }
+    }
+
+    fn as_number(&self) -> u16 {
+        match *self {
+            Json::Number(number) => number as u16,
+            _ => panic!("expected a number, found {self:?}"),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Json::String(string) => string,
+            _ => panic!("expected a string, found {self:?}"),
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at byte {}", self.position)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position) == Some(&byte) {
+            true => {
+                self.position += 1;
+                Ok(())
+            }
+            false => Err(self.error(&format!("expected '{}'", byte as char))),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+        match self.bytes[self.position..].starts_with(keyword.as_bytes()) {
+            true => {
+                self.position += keyword.len();
+                Ok(value)
+            }
+            false => Err(self.error("unexpected character")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position) {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(_) => self.number(),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.position) == Some(&b'}') {
+            self.position += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(b':')?;
+            fields.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.bytes.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.bytes.get(self.position) == Some(&b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    /// Escapes other than `\"` and `\\` don't appear in the test files
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    let escaped = *self.bytes.get(self.position + 1).ok_or("unexpected end")?;
+                    string.push(escaped as char);
+                    self.position += 2;
+                }
+                Some(&byte) => {
+                    string.push(byte as char);
+                    self.position += 1;
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+}
+
+/// Runs a test case, returns what didn't match
+fn run_case(case: &Json) -> String {
+    let initial = case.get("initial");
+    let expected = case.get("final");
+    let mut memory = TestMemory::new();
+    for entry in initial.get("ram").as_array() {
+        let entry = entry.as_array();
+        memory.buf[entry[0].as_number() as usize] = entry[1].as_number() as u8;
+    }
+    let mut cpu_state = CpuState::with_registers(Registers {
+        accumulator: initial.get("a").as_number() as u8,
+        x_index: initial.get("x").as_number() as u8,
+        y_index: initial.get("y").as_number() as u8,
+        program_counter: initial.get("pc").as_number(),
+        stack_ptr: initial.get("s").as_number() as u8,
+        flags: StatusFlags::from_bits_retain(initial.get("p").as_number() as u8 & !PUSHED_BITS),
+    });
+
+    let cycles = case.get("cycles").as_array();
+    for _ in cycles {
+        cpu_state.run_cycle(&mut memory);
+    }
+
+    let mut errors = String::new();
+    if cpu_state.current_cycle != 0 {
+        errors += "the instruction took longer\n";
+    }
+    let mut check = |name: &str, found: u16, wanted: u16| {
+        if found != wanted {
+            let _ = writeln!(errors, "{name}: found ${found:02X}, expected ${wanted:02X}");
+        }
+    };
+    check(
+        "pc",
+        cpu_state.program_counter,
+        expected.get("pc").as_number(),
+    );
+    check(
+        "s",
+        cpu_state.stack_ptr as u16,
+        expected.get("s").as_number(),
+    );
+    check(
+        "a",
+        cpu_state.accumulator as u16,
+        expected.get("a").as_number(),
+    );
+    check("x", cpu_state.x_index as u16, expected.get("x").as_number());
+    check("y", cpu_state.y_index as u16, expected.get("y").as_number());
+    check(
+        "p",
+        (cpu_state.flags.bits() & !PUSHED_BITS) as u16,
+        expected.get("p").as_number() & !PUSHED_BITS as u16,
+    );
+    for entry in expected.get("ram").as_array() {
+        let entry = entry.as_array();
+        let address = entry[0].as_number();
+        let name = format!("${address:04X}");
+        check(
+            &name,
+            memory.buf[address as usize] as u16,
+            entry[1].as_number(),
+        );
+    }
+
+    let wanted: Vec<_> = cycles
+        .iter()
+        .map(|cycle| {
+            let cycle = cycle.as_array();
+            let (address, value) = (cycle[0].as_number(), cycle[1].as_number() as u8);
+            match cycle[2].as_str() {
+                "write" => BusAccess::Write(address, value),
+                _ => BusAccess::Read(address, value),
+            }
+        })
+        .collect();
+    if memory.accesses != wanted {
+        let _ = writeln!(
+            errors,
+            "bus: found {:X?}\n     expected {wanted:X?}",
+            memory.accesses
+        );
+    }
+    errors
+}
+
+#[test]
+#[ignore = "needs the SingleStepTests files, see the module docs"]
+fn single_step() {
+    let directory = std::env::var("NESTY_SINGLE_STEP_TESTS")
+        .expect("NESTY_SINGLE_STEP_TESTS should be the directory with the test files");
+    let mut failures = Vec::new();
+    for opcode in 0..=0xFFu8 {
+        if JAM.contains(&opcode) {
+            continue;
+        }
+        let path = Path::new(&directory).join(format!("{opcode:02x}.json"));
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let cases = Json::parse(&text).unwrap_or_else(|error| panic!("{path:?}: {error}"));
+        let mut failed = 0;
+        let mut first = None;
+        for case in cases.as_array() {
+            let errors = run_case(case);
+            if !errors.is_empty() {
+                failed += 1;
+                first.get_or_insert_with(|| format!("{}:\n{errors}", case.get("name").as_str()));
+            }
+        }
+        if let Some(first) = first {
+            failures.push(format!("${opcode:02X}: {failed} failed, first was {first}"));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn parse_json() {
+    let text = r#"{"name": "a9 \"x\"", "cycles": [[1, 2.5, "read"], []], "empty": {}, "ok": true}"#;
+    let json = Json::parse(text).unwrap();
+    assert_eq!(json.get("name").as_str(), "a9 \"x\"");
+    assert_eq!(
+        json.get("cycles").as_array()[0],
+        Json::Array(vec![
+            Json::Number(1.0),
+            Json::Number(2.5),
+            Json::String("read".to_string()),
+        ])
+    );
+    assert_eq!(json.get("empty"), &Json::Object(Vec::new()));
+    assert_eq!(json.get("ok"), &Json::Bool(true));
+    assert_eq!(json.get("missing"), &Json::Null);
+    assert!(Json::parse("[1, 2").is_err());
+}
+
+/// A case in the same shape as the test files, so the harness itself is tested without them
+#[test]
+fn run_sample_case() {
+    // LDA ($10),Y with a page cross
+    let case = r#"{
+        "name": "b1 10",
+        "initial": {"pc": 512, "s": 253, "a": 0, "x": 0, "y": 255, "p": 36,
+            "ram": [[512, 177], [513, 16], [16, 1], [17, 3], [1024, 128]]},
+        "final": {"pc": 514, "s": 253, "a": 128, "x": 0, "y": 255, "p": 164,
+            "ram": [[512, 177], [513, 16], [16, 1], [17, 3], [1024, 128]]},
+        "cycles": [[512, 177, "read"], [513, 16, "read"], [16, 1, "read"], [17, 3, "read"],
+            [768, 0, "read"], [1024, 128, "read"]]
+    }"#;
+    assert_eq!(run_case(&Json::parse(case).unwrap()), "");
+}
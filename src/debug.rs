@@ -0,0 +1,41 @@
+//! Tools for looking inside a running game
+//!
+//! The console stops at [breakpoints](breakpoints) set through [`Nes::breakpoints_mut`],
+//! and reports why with a [`StopReason`].
+//! On top of single instructions, it can step over and out of subroutines, see [`Nes::step_over`],
+//! and the subroutines it's in are tracked on a [`call_stack`].
+//! The [`cdl`] module logs which parts of the ROM were used as code and which as data,
+//! and the [`profiler`] counts the cycles spent on every instruction.
+//! Tools can also react to events as they happen through [`hooks`].
+//! Label files loaded into [`symbols`] name the addresses in the disassembly and the profile.
+//! [`Nes::trace_line`] writes a trace log in the format of the nestest log,
+//! and [`Nes::pattern_table`] draws the tiles of the pattern tables.
+//!
+//! [`Nes::breakpoints_mut`]: crate::nes::Nes::breakpoints_mut
+//! [`Nes::step_over`]: crate::nes::Nes::step_over
+//! [`Nes::trace_line`]: crate::nes::Nes::trace_line
+//! [`Nes::pattern_table`]: crate::nes::Nes::pattern_table
+
+pub mod breakpoints;
+pub mod call_stack;
+pub mod cdl;
+pub mod hooks;
+pub mod profiler;
+mod stepping;
+pub mod symbols;
+#[cfg(test)]
+mod tests;
+mod trace;
+pub mod viewer;
+
+use breakpoints::BreakpointId;
+
+/// Why the console stopped before finishing what it was asked to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The CPU is about to run an instruction with a breakpoint on it,
+    /// or just accessed memory with one
+    Breakpoint(BreakpointId),
+    /// A step didn't finish within two frames, the game is probably stuck in a loop
+    Timeout,
+}
@@ -0,0 +1,333 @@
+//! Breakpoints on the instructions the CPU runs and the memory it accesses
+//!
+//! An execute breakpoint stops [`Nes::run_frame`] and [`Nes::step_instruction`] right before the CPU
+//! fetches an instruction in its range, as long as its condition holds.
+//! Continuing runs that instruction without stopping again.
+//!
+//! Read and write breakpoints stop right after the cycle that did the access,
+//! which can be in the middle of an instruction, `step_instruction` finishes it first.
+//! Besides the CPU address space they can watch the PPU's, as accessed through $2007,
+//! and OAM, as accessed through $2004 and OAM DMA.
+//!
+//! The other ways of running the console ignore breakpoints.
+//!
+//! [`Nes::run_frame`]: crate::nes::Nes::run_frame
+//! [`Nes::step_instruction`]: crate::nes::Nes::step_instruction
+
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+use bitflags::bitflags;
+
+use crate::cpu::CpuState;
+
+/// Handle for removing or toggling a breakpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BreakpointId(u32);
+
+/// CPU register a [`Condition`] looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Accumulator,
+    X,
+    Y,
+    StackPointer,
+    Flags,
+}
+
+impl Register {
+    fn value(self, cpu: &CpuState) -> u8 {
+        match self {
+            Register::Accumulator => cpu.accumulator,
+            Register::X => cpu.x_index,
+            Register::Y => cpu.y_index,
+            Register::StackPointer => cpu.stack_ptr,
+            Register::Flags => cpu.flags.bits(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Compares a register to a value, e.g. `X >= $10`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+impl Condition {
+    pub fn new(register: Register, comparison: Comparison, value: u8) -> Self {
+        Self {
+            register,
+            comparison,
+            value,
+        }
+    }
+
+    pub fn holds(&self, cpu: &CpuState) -> bool {
+        let register = self.register.value(cpu);
+        match self.comparison {
+            Comparison::Equal => register == self.value,
+            Comparison::NotEqual => register != self.value,
+            Comparison::Less => register < self.value,
+            Comparison::LessOrEqual => register <= self.value,
+            Comparison::Greater => register > self.value,
+            Comparison::GreaterOrEqual => register >= self.value,
+        }
+    }
+}
+
+bitflags! {
+    /// Kinds of accesses a breakpoint stops on
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Access: u8 {
+        const READ = 1;
+        const WRITE = 1 << 1;
+        /// Fetching an instruction, only happens on the CPU bus
+        const EXECUTE = 1 << 2;
+    }
+}
+
+/// Address space a breakpoint watches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    Cpu,
+    /// PPU address space, $0000-$3FFF
+    Vram,
+    /// Sprite memory, $00-$FF
+    Oam,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub bus: Bus,
+    /// First address watched
+    pub start: u16,
+    /// Last address watched, inclusive
+    pub end: u16,
+    pub access: Access,
+    /// Only stop when this holds, hits are only counted when it does
+    ///
+    /// For read and write breakpoints it's checked at the end of the cycle.
+    pub condition: Option<Condition>,
+    /// Number of hits to let through before stopping
+    pub ignore_count: u32,
+}
+
+impl Breakpoint {
+    /// Stop every time the CPU gets to the instruction at `address`
+    pub fn new(address: u16) -> Self {
+        Self::on_access(Bus::Cpu, address..=address, Access::EXECUTE)
+    }
+
+    /// Stop on the given kinds of accesses to a range of addresses
+    pub fn on_access(bus: Bus, range: RangeInclusive<u16>, access: Access) -> Self {
+        Self {
+            bus,
+            start: *range.start(),
+            end: *range.end(),
+            access,
+            condition: None,
+            ignore_count: 0,
+        }
+    }
+
+    pub fn with_condition(self, condition: Condition) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    pub fn with_ignore_count(self, ignore_count: u32) -> Self {
+        Self {
+            ignore_count,
+            ..self
+        }
+    }
+
+    fn watches(&self, bus: Bus, address: u16, access: Access) -> bool {
+        self.bus == bus
+            && self.access.intersects(access)
+            && (self.start..=self.end).contains(&address)
+    }
+
+    fn watches_memory(&self) -> bool {
+        self.bus != Bus::Cpu || self.access.intersects(Access::READ | Access::WRITE)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    id: BreakpointId,
+    breakpoint: Breakpoint,
+    enabled: bool,
+    hits: u32,
+}
+
+impl Entry {
+    /// Count a hit if the condition holds, returns whether to stop
+    fn hit(&mut self, cpu: &CpuState) -> bool {
+        let breakpoint = &self.breakpoint;
+        if !breakpoint
+            .condition
+            .is_none_or(|condition| condition.holds(cpu))
+        {
+            return false;
+        }
+        self.hits += 1;
+        self.hits > breakpoint.ignore_count
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Entry>,
+    next_id: u32,
+    /// Lets the console skip the checks without looking at the list when nothing is enabled
+    execute_count: usize,
+    memory_count: usize,
+    /// Breakpoints whose memory accesses happened this cycle
+    pending: Vec<usize>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a breakpoint, it starts out enabled
+    pub fn add(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = BreakpointId(self.next_id);
+        self.next_id += 1;
+        self.breakpoints.push(Entry {
+            id,
+            breakpoint,
+            enabled: true,
+            hits: 0,
+        });
+        self.count_enabled();
+        id
+    }
+
+    pub fn remove(&mut self, id: BreakpointId) -> Option<Breakpoint> {
+        let index = self.breakpoints.iter().position(|entry| entry.id == id)?;
+        let entry = self.breakpoints.remove(index);
+        self.count_enabled();
+        Some(entry.breakpoint)
+    }
+
+    /// Returns `false` if there's no such breakpoint
+    pub fn set_enabled(&mut self, id: BreakpointId, enabled: bool) -> bool {
+        let Some(entry) = self.breakpoints.iter_mut().find(|entry| entry.id == id) else {
+            return false;
+        };
+        entry.enabled = enabled;
+        self.count_enabled();
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.count_enabled();
+    }
+
+    fn count_enabled(&mut self) {
+        let enabled = || self.breakpoints.iter().filter(|entry| entry.enabled);
+        self.execute_count = enabled()
+            .filter(|entry| entry.breakpoint.access.contains(Access::EXECUTE))
+            .count();
+        self.memory_count = enabled()
+            .filter(|entry| entry.breakpoint.watches_memory())
+            .count();
+    }
+
+    /// All breakpoints and whether they are enabled
+    pub fn iter(&self) -> impl Iterator<Item = (BreakpointId, Breakpoint, bool)> + '_ {
+        self.breakpoints
+            .iter()
+            .map(|entry| (entry.id, entry.breakpoint, entry.enabled))
+    }
+
+    /// How many times the breakpoint was hit, including the ignored hits
+    pub fn hits(&self, id: BreakpointId) -> Option<u32> {
+        let entry = self.breakpoints.iter().find(|entry| entry.id == id)?;
+        Some(entry.hits)
+    }
+
+    /// Forget the hits of all breakpoints
+    pub fn reset_hits(&mut self) {
+        self.breakpoints.iter_mut().for_each(|entry| entry.hits = 0);
+    }
+
+    /// Count the hits of the CPU getting to its program counter,
+    /// returning the first breakpoint that should stop it
+    #[inline]
+    pub(crate) fn check_execute(&mut self, cpu: &CpuState) -> Option<BreakpointId> {
+        if self.execute_count == 0 {
+            return None;
+        }
+
+        let mut stop = None;
+        for entry in &mut self.breakpoints {
+            if entry.enabled
+                && entry
+                    .breakpoint
+                    .watches(Bus::Cpu, cpu.program_counter, Access::EXECUTE)
+                && entry.hit(cpu)
+            {
+                stop = stop.or(Some(entry.id));
+            }
+        }
+        stop
+    }
+
+    /// Whether any breakpoint is enabled
+    #[inline]
+    pub(crate) fn is_active(&self) -> bool {
+        self.execute_count != 0 || self.memory_count != 0
+    }
+
+    /// Whether there are any read or write breakpoints, the buses don't report accesses otherwise
+    #[inline]
+    pub(crate) fn watches_memory(&self) -> bool {
+        self.memory_count != 0
+    }
+
+    /// Note an access for [`Breakpoints::check_accesses`]
+    pub(crate) fn record_access(&mut self, bus: Bus, address: u16, access: Access) {
+        for (index, entry) in self.breakpoints.iter().enumerate() {
+            if entry.enabled && entry.breakpoint.watches(bus, address, access) {
+                self.pending.push(index);
+            }
+        }
+    }
+
+    /// Count the hits of the accesses made during the last cycle,
+    /// returning the first breakpoint that should stop the console
+    #[inline]
+    pub(crate) fn check_accesses(&mut self, cpu: &CpuState) -> Option<BreakpointId> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let mut stop = None;
+        for index in self.pending.drain(..) {
+            let entry = &mut self.breakpoints[index];
+            if entry.hit(cpu) {
+                stop = stop.or(Some(entry.id));
+            }
+        }
+        stop
+    }
+}
@@ -0,0 +1,171 @@
+//! Call stack tracking
+//!
+//! Keeps a shadow copy of the subroutines and interrupt handlers the CPU is in,
+//! from the `JSR`s, `BRK`s and interrupts it runs.
+//! The real stack isn't trusted for this, games push and pull return addresses
+//! themselves and reset the stack pointer, so a call is only considered over once
+//! the stack pointer goes back above where it was before the call.
+//! The call stack is read with [`Nes::call_stack`](crate::nes::Nes::call_stack).
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
+
+use crate::cpu::{CpuState, Interrupt};
+
+const BRK: u8 = 0x00;
+const JSR: u8 = 0x20;
+
+/// How a stack frame was entered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    Subroutine,
+    Brk,
+    Interrupt(Interrupt),
+}
+
+/// A subroutine or interrupt handler that's running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackFrame {
+    pub kind: CallKind,
+    /// Start of the subroutine or handler
+    pub target: u16,
+    /// Address of the `JSR` or `BRK`, or of the instruction that was interrupted
+    pub call_site: u16,
+    /// Where the CPU continues once the call returns
+    pub return_address: u16,
+    /// Stack pointer from before the call
+    stack_ptr: u8,
+}
+
+/// Calls there's room for from the start, deeper ones make the stack grow
+const INITIAL_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    /// Outermost first
+    frames: Vec<StackFrame>,
+    /// Address of the instruction that's running
+    address: u16,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::with_capacity(INITIAL_CAPACITY),
+            address: 0,
+        }
+    }
+
+    /// The running calls, the outermost first
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    /// The innermost running call
+    pub fn current(&self) -> Option<&StackFrame> {
+        self.frames.last()
+    }
+
+    /// Number of running calls
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Forget all calls, for when the CPU state stops matching them
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Text with a line for every running call, the innermost first, like `#0 $8013 in init`
+    ///
+    /// `pc` is where the CPU is now and `label` names an address,
+    /// see [`Nes::label`](crate::nes::Nes::label).
+    pub fn backtrace<L: Display>(
+        &self,
+        pc: u16,
+        mut label: impl FnMut(u16) -> Option<L>,
+    ) -> String {
+        let mut name = |address| match label(address) {
+            Some(label) => label.to_string(),
+            None => format!("${address:04X}"),
+        };
+        let mut text = String::new();
+        let mut address = pc;
+        for (index, frame) in self.frames.iter().rev().enumerate() {
+            let kind = match frame.kind {
+                CallKind::Subroutine => String::new(),
+                CallKind::Brk => " (BRK)".to_string(),
+                CallKind::Interrupt(interrupt) => format!(" ({interrupt:?})"),
+            };
+            text += &format!("#{index} ${address:04X} in {}{kind}\n", name(frame.target));
+            address = frame.call_site;
+        }
+        // code outside of any call, which can only be seen after the stack was cleared
+        if self.frames.first().map(|frame| frame.kind)
+            != Some(CallKind::Interrupt(Interrupt::Reset))
+        {
+            text += &format!("#{} ${address:04X}\n", self.frames.len());
+        }
+        text
+    }
+
+    /// Called when the CPU is about to start the instruction or interrupt at `address`
+    pub(crate) fn start_instruction(&mut self, address: u16) {
+        self.address = address;
+    }
+
+    /// Called when the CPU finishes an instruction, updates the stack from what it did
+    ///
+    /// `opcode` is the instruction, it's ignored if the CPU ran an interrupt sequence instead.
+    pub(crate) fn finish_instruction(&mut self, cpu: &CpuState, opcode: u8) {
+        let stack_ptr = cpu.stack_ptr;
+        let pc = cpu.program_counter;
+        let frame = |kind, return_address, pushed: u8| StackFrame {
+            kind,
+            target: pc,
+            call_site: self.address,
+            return_address,
+            stack_ptr: stack_ptr.wrapping_add(pushed),
+        };
+        match (cpu.current_interrupt(), opcode) {
+            (Some(Interrupt::Reset), _) => {
+                let reset = frame(CallKind::Interrupt(Interrupt::Reset), self.address, 3);
+                self.frames.clear();
+                self.frames.push(reset);
+            }
+            (Some(interrupt), _) => {
+                let frame = frame(CallKind::Interrupt(interrupt), self.address, 3);
+                self.frames.push(frame);
+            }
+            (None, JSR) => {
+                let frame = frame(CallKind::Subroutine, self.address.wrapping_add(3), 2);
+                self.frames.push(frame);
+            }
+            (None, BRK) => {
+                // the byte after BRK is skipped
+                let frame = frame(CallKind::Brk, self.address.wrapping_add(2), 3);
+                self.frames.push(frame);
+            }
+            _ => {
+                // the reset handler never returns, it's only replaced by the next reset
+                let returned = |frame: &StackFrame| {
+                    frame.kind != CallKind::Interrupt(Interrupt::Reset)
+                        && stack_ptr.wrapping_sub(frame.stack_ptr) as i8 >= 0
+                };
+                while self.frames.last().is_some_and(returned) {
+                    self.frames.pop();
+                }
+            }
+        }
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
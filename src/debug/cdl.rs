@@ -0,0 +1,153 @@
+//! Code/Data Logger
+//!
+//! Records how every byte of PRG and CHR ROM was used, to tell code from data when
+//! disassembling or hacking a game.
+//! The log is exchanged as a `.cdl` file in the format of FCEUX and Mesen:
+//! one byte per PRG ROM byte, followed by one byte per CHR ROM byte.
+//!
+//! A log is started with [`Cartridge::start_code_data_log`](crate::cartridge::Cartridge::start_code_data_log).
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt::{Display, Formatter};
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// How a PRG ROM byte was used
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct PrgUsage: u8 {
+        /// Fetched as an opcode or operand
+        const CODE = 1;
+        /// Read by an instruction
+        const DATA = 1 << 1;
+        /// Jumped to through a pointer
+        const INDIRECT_CODE = 1 << 4;
+        /// Read through a pointer
+        const INDIRECT_DATA = 1 << 5;
+        /// Played as a DMC sample
+        const PCM = 1 << 6;
+    }
+}
+
+bitflags! {
+    /// How a CHR ROM byte was used
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct ChrUsage: u8 {
+        /// Fetched by the PPU while rendering
+        const DRAWN = 1;
+        /// Read by the CPU through $2007
+        const READ = 1 << 1;
+    }
+}
+
+/// Bits 2-3 of a PRG byte say which 8KB of $8000-$FFFF it was last accessed at
+const BANK_SHIFT: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CdlError {
+    /// The file doesn't cover exactly the cartridge's PRG and CHR ROM
+    WrongSize { expected: usize, found: usize },
+}
+
+impl Display for CdlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CdlError::WrongSize { expected, found } => write!(
+                f,
+                "code/data log is {found} bytes, the cartridge needs {expected}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for CdlError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    prg: Box<[u8]>,
+    chr: Box<[u8]>,
+}
+
+impl CodeDataLog {
+    /// Empty log for the given amounts of PRG and CHR ROM
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg: vec![0; prg_size].into_boxed_slice(),
+            chr: vec![0; chr_size].into_boxed_slice(),
+        }
+    }
+
+    /// Parse a `.cdl` file
+    pub fn from_bytes(bytes: &[u8], prg_size: usize, chr_size: usize) -> Result<Self, CdlError> {
+        if bytes.len() != prg_size + chr_size {
+            return Err(CdlError::WrongSize {
+                expected: prg_size + chr_size,
+                found: bytes.len(),
+            });
+        }
+        let (prg, chr) = bytes.split_at(prg_size);
+        Ok(Self {
+            prg: prg.into(),
+            chr: chr.into(),
+        })
+    }
+
+    /// Contents of a `.cdl` file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.prg[..], &self.chr[..]].concat()
+    }
+
+    pub fn prg_size(&self) -> usize {
+        self.prg.len()
+    }
+
+    pub fn chr_size(&self) -> usize {
+        self.chr.len()
+    }
+
+    /// How the byte at the given PRG ROM offset was used, empty if it wasn't
+    pub fn prg_usage(&self, offset: usize) -> PrgUsage {
+        PrgUsage::from_bits_truncate(self.prg.get(offset).copied().unwrap_or(0))
+    }
+
+    /// Which 8KB of $8000-$FFFF the byte was last accessed at, as an index from 0 to 3
+    pub fn prg_bank(&self, offset: usize) -> u8 {
+        self.prg
+            .get(offset)
+            .map_or(0, |byte| byte >> BANK_SHIFT & 0b11)
+    }
+
+    /// How the byte at the given CHR ROM offset was used, empty if it wasn't
+    pub fn chr_usage(&self, offset: usize) -> ChrUsage {
+        ChrUsage::from_bits_truncate(self.chr.get(offset).copied().unwrap_or(0))
+    }
+
+    /// Number of PRG ROM bytes used in any way
+    pub fn prg_used(&self) -> usize {
+        self.prg.iter().filter(|&&byte| byte != 0).count()
+    }
+
+    /// Number of CHR ROM bytes used in any way
+    pub fn chr_used(&self) -> usize {
+        self.chr.iter().filter(|&&byte| byte != 0).count()
+    }
+
+    /// Forget everything logged so far
+    pub fn clear(&mut self) {
+        self.prg.fill(0);
+        self.chr.fill(0);
+    }
+
+    pub(crate) fn log_prg(&mut self, offset: usize, address: u16, usage: PrgUsage) {
+        if let Some(byte) = self.prg.get_mut(offset) {
+            let bank = (address >> 13) as u8 & 0b11;
+            *byte = (*byte & !(0b11 << BANK_SHIFT)) | usage.bits() | bank << BANK_SHIFT;
+        }
+    }
+
+    pub(crate) fn log_chr(&mut self, offset: usize, usage: ChrUsage) {
+        if let Some(byte) = self.chr.get_mut(offset) {
+            *byte |= usage.bits();
+        }
+    }
+}
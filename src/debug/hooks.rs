@@ -0,0 +1,220 @@
+//! Callbacks on events in the console
+//!
+//! Hooks run the code of a tool right when something happens, instead of making it poll
+//! the console between frames. They're added through [`Nes::hooks_mut`] with the
+//! [`Trigger`] they wait for, and get the console and the [`Event`] that happened.
+//!
+//! Every way of running the console runs the hooks, they can't stop it though,
+//! that's what [breakpoints](super::breakpoints) are for.
+//! Hooks added by a hook while it runs only start with the next event,
+//! and a hook can't remove hooks while it runs.
+//!
+//! [`Nes::hooks_mut`]: crate::nes::Nes::hooks_mut
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    fmt::{Debug, Formatter},
+    ops::RangeInclusive,
+};
+
+use crate::{cpu::Interrupt, nes::Nes};
+
+/// Handle for removing a hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(u32);
+
+/// What a hook waits for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// The CPU is about to run an instruction in the range
+    Execute(RangeInclusive<u16>),
+    /// The CPU wrote to an address in the range
+    Write(RangeInclusive<u16>),
+    /// The CPU finished jumping to the handler of an interrupt
+    Interrupt(Interrupt),
+    /// The PPU finished a frame
+    FrameEnd,
+    /// The PPU started the scanline, numbered from 0 at the top of the picture
+    Scanline(u16),
+}
+
+/// What happened to run a hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Execute {
+        address: u16,
+    },
+    Write {
+        address: u16,
+        value: u8,
+    },
+    Interrupt(Interrupt),
+    FrameEnd {
+        /// Number of completed frames, see [`Ppu::frame`](crate::ppu::Ppu::frame)
+        frame: u64,
+    },
+    Scanline {
+        scanline: u16,
+    },
+}
+
+impl Trigger {
+    fn matches(&self, event: &Event) -> bool {
+        match (self, event) {
+            (Trigger::Execute(range), Event::Execute { address }) => range.contains(address),
+            (Trigger::Write(range), Event::Write { address, .. }) => range.contains(address),
+            (Trigger::Interrupt(trigger), Event::Interrupt(interrupt)) => trigger == interrupt,
+            (Trigger::FrameEnd, Event::FrameEnd { .. }) => true,
+            (Trigger::Scanline(trigger), Event::Scanline { scanline }) => trigger == scanline,
+            _ => false,
+        }
+    }
+}
+
+// `Send` so the console can be moved to another thread with its hooks
+type Callback = Box<dyn FnMut(&mut Nes, Event) + Send>;
+
+struct Hook {
+    id: HookId,
+    trigger: Trigger,
+    callback: Callback,
+}
+
+/// The hooks of a console, cloning it gives an empty registry
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    next_id: u32,
+    /// Events that happened since the hooks last ran
+    pending: Vec<Event>,
+    /// Number of hooks of each trigger that needs work on every access or cycle
+    execute_count: usize,
+    write_count: usize,
+    scanline_count: usize,
+    /// Scanline the PPU was on when it was last checked
+    scanline: u16,
+}
+
+impl Hooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `callback` every time `trigger` happens
+    pub fn add(
+        &mut self,
+        trigger: Trigger,
+        callback: impl FnMut(&mut Nes, Event) + Send + 'static,
+    ) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook {
+            id,
+            trigger,
+            callback: Box::new(callback),
+        });
+        self.count_triggers();
+        id
+    }
+
+    /// Returns whether there was a hook with the ID
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.count_triggers();
+        self.hooks.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+        self.pending.clear();
+        self.count_triggers();
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// Triggers of the hooks, with their IDs
+    pub fn iter(&self) -> impl Iterator<Item = (HookId, &Trigger)> + '_ {
+        self.hooks.iter().map(|hook| (hook.id, &hook.trigger))
+    }
+
+    fn count_triggers(&mut self) {
+        let count =
+            |f: fn(&Trigger) -> bool| self.hooks.iter().filter(|hook| f(&hook.trigger)).count();
+        self.execute_count = count(|trigger| matches!(trigger, Trigger::Execute(_)));
+        self.write_count = count(|trigger| matches!(trigger, Trigger::Write(_)));
+        self.scanline_count = count(|trigger| matches!(trigger, Trigger::Scanline(_)));
+    }
+
+    pub(crate) fn watches_execute(&self) -> bool {
+        self.execute_count != 0
+    }
+
+    pub(crate) fn watches_scanlines(&self) -> bool {
+        self.scanline_count != 0
+    }
+
+    /// Note an event for [`Hooks::run`], if any hook could be waiting for it
+    #[inline]
+    pub(crate) fn record(&mut self, event: Event) {
+        if !self.hooks.is_empty() {
+            self.pending.push(event);
+        }
+    }
+
+    /// Note a write by the CPU
+    #[inline]
+    pub(crate) fn record_write(&mut self, address: u16, value: u8) {
+        if self.write_count != 0 {
+            self.pending.push(Event::Write { address, value });
+        }
+    }
+
+    /// Note the scanline the PPU is on, it only counts as an event if it changed
+    pub(crate) fn record_scanline(&mut self, scanline: u16) {
+        if scanline != self.scanline {
+            self.scanline = scanline;
+            self.pending.push(Event::Scanline { scanline });
+        }
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Run the hooks waiting for the recorded events, in the order they happened
+    pub(crate) fn run(nes: &mut Nes) {
+        let events = core::mem::take(&mut nes.hooks_mut().pending);
+        // the hooks are taken out so that they can get the console
+        let mut hooks = core::mem::take(&mut nes.hooks_mut().hooks);
+        for event in events {
+            for hook in &mut hooks {
+                if hook.trigger.matches(&event) {
+                    (hook.callback)(nes, event);
+                }
+            }
+        }
+        let registry = nes.hooks_mut();
+        hooks.append(&mut registry.hooks);
+        registry.hooks = hooks;
+        registry.count_triggers();
+    }
+}
+
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,139 @@
+//! Cycle profiler
+//!
+//! Counts the CPU cycles spent on every instruction, and in every subroutine,
+//! so the hot spots of a game loop can be found.
+//! Cycles the CPU spends halted by DMA count towards the instruction it was halted on.
+//! A profile is started with [`Nes::start_profiler`](crate::nes::Nes::start_profiler).
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Display;
+
+/// Cycles spent at an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotSpot {
+    pub address: u16,
+    pub cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// Cycles per instruction address
+    cycles: Box<[u64]>,
+    /// Cycles per subroutine and interrupt handler, not counting the ones it calls
+    functions: BTreeMap<u16, u64>,
+    /// The instruction being run
+    address: u16,
+    /// The subroutine it's in
+    function: Option<u16>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            cycles: vec![0; 0x10000].into_boxed_slice(),
+            functions: BTreeMap::new(),
+            address: 0,
+            function: None,
+        }
+    }
+
+    /// Cycles spent on the instruction at `address`
+    pub fn cycles(&self, address: u16) -> u64 {
+        self.cycles[address as usize]
+    }
+
+    /// Cycles counted since the profiler was started
+    pub fn total_cycles(&self) -> u64 {
+        self.cycles.iter().sum()
+    }
+
+    /// Instructions that took any cycles, the most expensive first
+    pub fn hot_spots(&self) -> Vec<HotSpot> {
+        let mut hot_spots: Vec<_> = (0..=u16::MAX)
+            .zip(self.cycles.iter())
+            .filter(|&(_, &cycles)| cycles != 0)
+            .map(|(address, &cycles)| HotSpot { address, cycles })
+            .collect();
+        sort(&mut hot_spots);
+        hot_spots
+    }
+
+    /// Subroutines (by their `JSR` target) and interrupt handlers, the most expensive first
+    ///
+    /// Each one only counts its own cycles, not the ones of the subroutines it calls.
+    /// Code outside of any subroutine isn't counted.
+    pub fn functions(&self) -> Vec<HotSpot> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(&address, &cycles)| HotSpot { address, cycles })
+            .collect();
+        sort(&mut functions);
+        functions
+    }
+
+    /// Text table of the `limit` most expensive subroutines and instructions
+    ///
+    /// `label` names an address, see [`Nes::label`](crate::nes::Nes::label).
+    pub fn report<L: Display>(
+        &self,
+        limit: usize,
+        mut label: impl FnMut(u16) -> Option<L>,
+    ) -> String {
+        let total = self.total_cycles().max(1);
+        let mut report = String::new();
+        for (title, hot_spots) in [
+            ("Subroutines", self.functions()),
+            ("Instructions", self.hot_spots()),
+        ] {
+            report += &format!("{title}:\n");
+            for hot_spot in hot_spots.iter().take(limit) {
+                let name = match label(hot_spot.address) {
+                    Some(label) => label.to_string(),
+                    None => format!("${:04X}", hot_spot.address),
+                };
+                let share = hot_spot.cycles as f64 * 100.0 / total as f64;
+                report += &format!("{:>12} {share:>6.2}% {name}\n", hot_spot.cycles);
+            }
+        }
+        report
+    }
+
+    /// Forget everything counted so far
+    pub fn clear(&mut self) {
+        self.cycles.fill(0);
+        self.functions.clear();
+    }
+
+    /// Called when the CPU is about to start the instruction at `address`,
+    /// inside the subroutine or interrupt handler at `function`
+    pub(crate) fn start_instruction(&mut self, address: u16, function: Option<u16>) {
+        self.address = address;
+        self.function = function;
+    }
+
+    pub(crate) fn count_cycle(&mut self) {
+        self.cycles[self.address as usize] += 1;
+        if let Some(function) = self.function {
+            *self.functions.entry(function).or_default() += 1;
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Most cycles first, lowest address first on ties
+fn sort(hot_spots: &mut [HotSpot]) {
+    hot_spots.sort_by_key(|hot_spot| (core::cmp::Reverse(hot_spot.cycles), hot_spot.address));
+}
@@ -0,0 +1,70 @@
+//! Stepping through subroutines and interrupt handlers
+
+use super::StopReason;
+use crate::nes::Nes;
+
+impl Nes {
+    /// Run the next instruction, or the interrupt the CPU takes instead,
+    /// same as [`Nes::step_instruction`]
+    pub fn step_into(&mut self) -> Option<StopReason> {
+        self.step_instruction()
+    }
+
+    /// Run the next instruction, along with the whole subroutine if it's a `JSR` or `BRK`
+    ///
+    /// If the CPU takes an interrupt instead, its handler is run until it returns.
+    /// Stops early at breakpoints, continuing after that is up to the caller.
+    pub fn step_over(&mut self) -> Option<StopReason> {
+        self.cartridge()?;
+
+        let address = self.cpu().program_counter;
+        let stack_ptr = self.cpu().stack_ptr;
+        let instruction = self.disassemble(address);
+        if let Some(stop) = self.step_instruction() {
+            return Some(stop);
+        }
+
+        let return_address = if self.cpu().current_interrupt().is_some() {
+            // the instruction is still up next after the handler
+            address
+        } else {
+            match instruction.mnemonic() {
+                "JSR" => instruction.next_address(),
+                // the byte after BRK is skipped
+                "BRK" => address.wrapping_add(2),
+                _ => return None,
+            }
+        };
+        self.step_until(|nes| {
+            nes.cpu().program_counter == return_address && nes.cpu().stack_ptr == stack_ptr
+        })
+    }
+
+    /// Run until the current subroutine or interrupt handler returns
+    ///
+    /// Any subroutines it calls are run to the end, it's only done once the
+    /// [call stack](Nes::call_stack) is shallower than at the start.
+    /// The reset handler never returns, so stepping out of it times out.
+    pub fn step_out(&mut self) -> Option<StopReason> {
+        self.cartridge()?;
+
+        let depth = self.call_stack().depth();
+        self.step_until(|nes| nes.call_stack().depth() < depth)
+    }
+
+    /// Step through instructions until `done` holds, it's checked before every instruction
+    fn step_until(&mut self, mut done: impl FnMut(&Nes) -> bool) -> Option<StopReason> {
+        let frame = self.ppu().frame();
+        loop {
+            if done(self) {
+                return None;
+            }
+            if self.ppu().frame() >= frame + 2 {
+                return Some(StopReason::Timeout);
+            }
+            if let Some(stop) = self.step_instruction() {
+                return Some(stop);
+            }
+        }
+    }
+}
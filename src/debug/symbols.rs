@@ -0,0 +1,261 @@
+//! Labels for addresses
+//!
+//! Debug output shows addresses by name, like `reset_handler+3`, when a label file is loaded.
+//! Three formats are read:
+//! - Mesen `.mlb` files, with lines like `P:0010:reset_handler`
+//! - FCEUX `.nl` files, one for RAM and one per 16KB PRG ROM bank, with lines like `$C010#reset_handler#`
+//! - ca65 label files made with `ld65 -Ln`, with lines like `al 00C010 .reset_handler`
+//!
+//! The labels are kept in [`Nes::symbols_mut`](crate::nes::Nes::symbols_mut).
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+};
+use core::fmt::{Display, Formatter};
+
+/// How far past a label without a size addresses are still shown relative to it
+const MAX_OFFSET: usize = 0x100;
+
+/// Size of a PRG ROM bank in FCEUX's `.nl` files
+const NL_BANK_SIZE: usize = 0x4000;
+
+/// What a label is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    /// An address in the CPU address space, whatever is mapped there
+    Cpu(u16),
+    /// A byte of PRG ROM, wherever it's mapped
+    PrgRom(usize),
+}
+
+/// Which of FCEUX's `.nl` files is being loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NlFile {
+    /// `game.nes.ram.nl`, addresses in the CPU address space
+    Ram,
+    /// `game.nes.<bank>.nl`, addresses in a 16KB PRG ROM bank
+    Bank(usize),
+}
+
+/// A line of a label file couldn't be parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolError {
+    /// 1-based
+    pub line: usize,
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "invalid label on line {}", self.line)
+    }
+}
+
+impl core::error::Error for SymbolError {}
+
+/// A name for an address, shown as `name` or `name+offset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label<'a> {
+    pub name: &'a str,
+    pub offset: usize,
+}
+
+impl Display for Label<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.offset {
+            0 => write!(f, "{}", self.name),
+            offset => write!(f, "{}+{offset}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    /// Number of bytes the label covers, if the file said
+    size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    cpu: BTreeMap<usize, Entry>,
+    prg: BTreeMap<usize, Entry>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cpu.len() + self.prg.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.cpu.clear();
+        self.prg.clear();
+    }
+
+    /// Name a location, replacing the label that was there
+    ///
+    /// Addresses after it are shown relative to it up until the next label.
+    pub fn add(&mut self, location: Location, name: impl Into<String>) {
+        self.insert(location, name.into(), None);
+    }
+
+    /// Name a block of `size` bytes, like an array
+    pub fn add_range(&mut self, location: Location, size: usize, name: impl Into<String>) {
+        self.insert(location, name.into(), Some(size.max(1)));
+    }
+
+    /// Label of a location
+    ///
+    /// A location that's inside a labelled block, or shortly after a label,
+    /// is named relative to the start of it.
+    pub fn label(&self, location: Location) -> Option<Label<'_>> {
+        let (map, address) = match location {
+            Location::Cpu(address) => (&self.cpu, address as usize),
+            Location::PrgRom(offset) => (&self.prg, offset),
+        };
+        let (&start, entry) = map.range(..=address).next_back()?;
+        let offset = address - start;
+        (offset < entry.size.unwrap_or(MAX_OFFSET)).then_some(Label {
+            name: &entry.name,
+            offset,
+        })
+    }
+
+    /// Location of the label with the given name
+    pub fn find(&self, name: &str) -> Option<Location> {
+        let find = |map: &BTreeMap<usize, Entry>| {
+            map.iter()
+                .find(|(_, entry)| entry.name == name)
+                .map(|(&address, _)| address)
+        };
+        find(&self.prg)
+            .map(Location::PrgRom)
+            .or_else(|| find(&self.cpu).map(|address| Location::Cpu(address as u16)))
+    }
+
+    /// Add the labels from a Mesen `.mlb` file
+    ///
+    /// Lines look like `P:0010:name`, `R:0300-033F:name` or `P:0010:name:comment`.
+    /// `P` is PRG ROM, `R` is the internal RAM and `G` the registers;
+    /// `S` and `W` are taken to be the cartridge RAM at $6000.
+    /// Lines with just a comment and lines for other memory types are skipped.
+    pub fn load_mlb(&mut self, text: &str) -> Result<(), SymbolError> {
+        for (index, line) in text.lines().enumerate() {
+            let error = SymbolError { line: index + 1 };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(4, ':');
+            let (Some(kind), Some(range), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(error);
+            };
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (parse_hex(start), parse_hex(end)),
+                None => (parse_hex(range), parse_hex(range)),
+            };
+            let (Some(start), Some(end)) = (start, end) else {
+                return Err(error);
+            };
+            if end < start {
+                return Err(error);
+            }
+            let location = match kind {
+                "P" => Location::PrgRom(start),
+                "R" if end < 0x800 => Location::Cpu(start as u16),
+                "G" if end <= 0xFFFF => Location::Cpu(start as u16),
+                "S" | "W" if end < 0x2000 => Location::Cpu(0x6000 + start as u16),
+                "R" | "G" | "S" | "W" => return Err(error),
+                _ => continue,
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let size = range.contains('-').then_some(end - start + 1);
+            self.insert(location, name.to_string(), size);
+        }
+        Ok(())
+    }
+
+    /// Add the labels from an FCEUX `.nl` file
+    ///
+    /// Lines look like `$C010#name#comment`, or `$0300/40#name#` for a block of $40 bytes.
+    pub fn load_nl(&mut self, text: &str, file: NlFile) -> Result<(), SymbolError> {
+        for (index, line) in text.lines().enumerate() {
+            let error = SymbolError { line: index + 1 };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(3, '#');
+            let (Some(address), Some(name)) = (fields.next(), fields.next()) else {
+                return Err(error);
+            };
+            let address = address.strip_prefix('$').ok_or(error)?;
+            let (address, size) = match address.split_once('/') {
+                Some((address, size)) => (address, Some(parse_hex(size).ok_or(error)?)),
+                None => (address, None),
+            };
+            let address = parse_hex(address)
+                .filter(|&address| address <= 0xFFFF)
+                .ok_or(error)?;
+            let location = match file {
+                NlFile::Ram => Location::Cpu(address as u16),
+                NlFile::Bank(bank) => {
+                    Location::PrgRom(bank * NL_BANK_SIZE + address % NL_BANK_SIZE)
+                }
+            };
+            if !name.is_empty() {
+                self.insert(location, name.to_string(), size.map(|size| size.max(1)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the labels from a label file written by `ld65 -Ln`
+    ///
+    /// Lines look like `al 00C010 .name`. The addresses are in the CPU address space.
+    pub fn load_ca65(&mut self, text: &str) -> Result<(), SymbolError> {
+        for (index, line) in text.lines().enumerate() {
+            let error = SymbolError { line: index + 1 };
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (None, ..) => continue,
+                (Some("al"), Some(address), Some(name)) => {
+                    let address = parse_hex(address)
+                        .filter(|&address| address <= 0xFFFF)
+                        .ok_or(error)?;
+                    let name = name.strip_prefix('.').unwrap_or(name);
+                    // local labels from the cheap label syntax aren't useful on their own
+                    if !name.starts_with('@') {
+                        self.insert(Location::Cpu(address as u16), name.to_string(), None);
+                    }
+                }
+                _ => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, location: Location, name: String, size: Option<usize>) {
+        let (map, address) = match location {
+            Location::Cpu(address) => (&mut self.cpu, address as usize),
+            Location::PrgRom(offset) => (&mut self.prg, offset),
+        };
+        map.insert(address, Entry { name, size });
+    }
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text.trim(), 16).ok()
+}
@@ -0,0 +1,503 @@
+use std::sync::{Arc, Mutex};
+
+use super::{
+    breakpoints::{Access, Breakpoint, Bus, Comparison, Condition, Register},
+    call_stack::CallKind,
+    cdl::{CdlError, ChrUsage, CodeDataLog, PrgUsage},
+    hooks::{Event, Trigger},
+    profiler::HotSpot,
+    symbols::{Label, Location, NlFile, SymbolError, Symbols},
+    viewer::PATTERN_TABLE_SIZE,
+    StopReason,
+};
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    cpu::Interrupt,
+    disasm::asm::assemble,
+    nes::Nes,
+};
+
+/// Console running `program` followed by `LDX #$00` over and over
+fn nes(program: &[u8]) -> Nes {
+    let mut prg = [0xA2, 0x00].repeat(0x3FFE);
+    prg.resize(prg.len().max(program.len()), 0);
+    prg[..program.len()].copy_from_slice(program);
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&nrom_image(&prg, 1, 0)).unwrap());
+    nes.power_cycle();
+    nes
+}
+
+#[test]
+fn breakpoints() {
+    let mut nes = nes(&[0xA2, 0x55]);
+    let id = nes.breakpoints_mut().add(Breakpoint::new(0x8010));
+    assert_eq!(nes.run_frame().stop, Some(StopReason::Breakpoint(id)));
+    assert_eq!(nes.cpu().program_counter, 0x8010);
+    assert_eq!(nes.breakpoints().hits(id), Some(1));
+
+    // continuing runs the instruction under the breakpoint
+    let frame = nes.ppu().frame();
+    assert_eq!(nes.run_frame().stop, None);
+    assert_eq!(nes.ppu().frame(), frame + 1);
+
+    // stepping onto a breakpoint reports it
+    nes.cpu_mut().program_counter = 0x800E;
+    assert_eq!(nes.step_instruction(), Some(StopReason::Breakpoint(id)));
+    assert_eq!(nes.step_instruction(), None);
+    assert_eq!(nes.cpu().program_counter, 0x8012);
+
+    assert!(nes.breakpoints_mut().set_enabled(id, false));
+    nes.cpu_mut().program_counter = 0x8000;
+    assert_eq!(nes.run_frame().stop, None);
+    assert_eq!(
+        nes.breakpoints_mut().remove(id),
+        Some(Breakpoint::new(0x8010))
+    );
+}
+
+#[test]
+fn conditions_and_hit_counts() {
+    let mut nes = nes(&[0xA2, 0x55]);
+    let x_is = |value| Condition::new(Register::X, Comparison::Equal, value);
+    let never = nes
+        .breakpoints_mut()
+        .add(Breakpoint::new(0x8004).with_condition(x_is(0x66)));
+    let second = nes.breakpoints_mut().add(
+        Breakpoint::new(0x8002)
+            .with_condition(x_is(0x55))
+            .with_ignore_count(1),
+    );
+
+    // the first hit is let through
+    assert_eq!(nes.run_frame().stop, None);
+    assert_eq!(nes.breakpoints().hits(second), Some(1));
+
+    nes.step_instruction();
+    nes.cpu_mut().program_counter = 0x8000;
+    assert_eq!(nes.run_frame().stop, Some(StopReason::Breakpoint(second)));
+    assert_eq!(nes.cpu().x_index, 0x55);
+    assert_eq!(nes.breakpoints().hits(second), Some(2));
+    assert_eq!(nes.breakpoints().hits(never), Some(0));
+}
+
+#[test]
+fn memory_breakpoints() {
+    // LDX $0010, LDX $2007
+    let mut nes = nes(&[0xAE, 0x10, 0x00, 0xAE, 0x07, 0x20]);
+    let read = nes.breakpoints_mut().add(Breakpoint::on_access(
+        Bus::Cpu,
+        0x0010..=0x0010,
+        Access::READ,
+    ));
+    let vram = nes.breakpoints_mut().add(Breakpoint::on_access(
+        Bus::Vram,
+        0x0000..=0x1FFF,
+        Access::READ | Access::WRITE,
+    ));
+    assert_eq!(nes.run_frame().stop, Some(StopReason::Breakpoint(read)));
+    assert_eq!(nes.cpu().program_counter, 0x8003);
+    assert_eq!(nes.step_instruction(), Some(StopReason::Breakpoint(vram)));
+    assert_eq!(nes.cpu().program_counter, 0x8006);
+
+    // the NMI pushes the program counter
+    let stack = nes.breakpoints_mut().add(Breakpoint::on_access(
+        Bus::Cpu,
+        0x0100..=0x01FF,
+        Access::WRITE,
+    ));
+    nes.cpu_mut().set_nmi_line(true);
+    let stack_ptr = nes.cpu().stack_ptr;
+    assert_eq!(nes.run_frame().stop, Some(StopReason::Breakpoint(stack)));
+    assert_eq!(nes.cpu().stack_ptr, stack_ptr.wrapping_sub(1));
+    assert_eq!(nes.breakpoints().hits(read), Some(1));
+}
+
+#[test]
+fn stepping() {
+    #[rustfmt::skip]
+    let mut program = vec![
+        // JSR $8010
+        0x20, 0x10, 0x80,
+        // LDX #$01
+        0xA2, 0x01,
+    ];
+    program.resize(0x10, 0xA2);
+    // LDX #$02, JSR $8020, RTS
+    program.extend([0xA2, 0x02, 0x20, 0x20, 0x80, 0x60]);
+    program.resize(0x20, 0xA2);
+    // LDX #$03, RTS
+    program.extend([0xA2, 0x03, 0x60]);
+    let mut nes = nes(&program);
+    nes.step_instruction();
+    let stack_ptr = nes.cpu().stack_ptr;
+
+    assert_eq!(nes.step_over(), None);
+    assert_eq!(nes.cpu().program_counter, 0x8003);
+    assert_eq!(nes.cpu().x_index, 0x03);
+    assert_eq!(nes.cpu().stack_ptr, stack_ptr);
+    // not a subroutine, just one instruction
+    assert_eq!(nes.step_over(), None);
+    assert_eq!(nes.cpu().program_counter, 0x8005);
+
+    nes.cpu_mut().program_counter = 0x8000;
+    assert_eq!(nes.step_into(), None);
+    assert_eq!(nes.cpu().program_counter, 0x8010);
+    // the RTS of the inner subroutine doesn't count
+    assert_eq!(nes.step_out(), None);
+    assert_eq!(nes.cpu().program_counter, 0x8003);
+    assert_eq!(nes.cpu().stack_ptr, stack_ptr);
+
+    nes.cpu_mut().program_counter = 0x8000;
+    (0..3).for_each(|_| assert_eq!(nes.step_into(), None));
+    assert_eq!(nes.cpu().program_counter, 0x8020);
+    assert_eq!(nes.step_out(), None);
+    assert_eq!(nes.cpu().program_counter, 0x8015);
+
+    // breakpoints in the subroutine still stop it
+    nes.cpu_mut().program_counter = 0x8000;
+    let id = nes.breakpoints_mut().add(Breakpoint::new(0x8020));
+    assert_eq!(nes.step_over(), Some(StopReason::Breakpoint(id)));
+    assert_eq!(nes.cpu().program_counter, 0x8020);
+}
+
+#[test]
+fn call_stack() {
+    let mut program = [0xA2, 0x00].repeat(0x4000);
+    // JSR $8010
+    program[..3].copy_from_slice(&[0x20, 0x10, 0x80]);
+    // JSR $8020, RTS
+    program[0x10..0x14].copy_from_slice(&[0x20, 0x20, 0x80, 0x60]);
+    // BRK
+    program[0x20] = 0x00;
+    // RTI
+    program[0x30] = 0x40;
+    // reset and IRQ vectors
+    program[0x7FFC..].copy_from_slice(&[0x00, 0x80, 0x30, 0x80]);
+    let mut nes = nes(&program);
+    nes.symbols_mut()
+        .add_range(Location::Cpu(0x8010), 4, "init");
+
+    nes.step_instruction();
+    assert_eq!(nes.call_stack().depth(), 1);
+    nes.step_instruction();
+    let frame = *nes.call_stack().current().unwrap();
+    assert_eq!(frame.kind, CallKind::Subroutine);
+    assert_eq!(
+        (frame.target, frame.call_site, frame.return_address),
+        (0x8010, 0x8000, 0x8003)
+    );
+
+    (0..2).for_each(|_| assert_eq!(nes.step_instruction(), None));
+    let frame = *nes.call_stack().current().unwrap();
+    assert_eq!(frame.kind, CallKind::Brk);
+    assert_eq!(frame.return_address, 0x8022);
+    assert_eq!(
+        nes.backtrace(),
+        "#0 $8030 in $8030 (BRK)\n#1 $8020 in $8020\n#2 $8010 in init\n#3 $8000 in $8000 (Reset)\n"
+    );
+
+    nes.step_instruction();
+    assert_eq!(nes.cpu().program_counter, 0x8022);
+    assert_eq!(nes.call_stack().depth(), 3);
+    // throwing away the return address leaves the subroutine
+    nes.cpu_mut().stack_ptr += 2;
+    nes.step_instruction();
+    assert_eq!(nes.call_stack().depth(), 2);
+    assert_eq!(nes.call_stack().current().unwrap().target, 0x8010);
+
+    // interrupts are calls too
+    let frames = nes.call_stack().frames();
+    assert_eq!(frames[0].kind, CallKind::Interrupt(Interrupt::Reset));
+}
+
+#[test]
+fn hooks() {
+    // JSR $8010
+    let mut nes = nes(&[0x20, 0x10, 0x80]);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let log = |events: &Arc<Mutex<Vec<Event>>>| {
+        let events = events.clone();
+        move |_: &mut Nes, event| events.lock().unwrap().push(event)
+    };
+    let hooks = nes.hooks_mut();
+    hooks.add(Trigger::Interrupt(Interrupt::Reset), log(&events));
+    hooks.add(Trigger::Execute(0x8010..=0x8011), log(&events));
+    let stack = hooks.add(Trigger::Write(0x0100..=0x01FF), log(&events));
+    hooks.add(Trigger::FrameEnd, log(&events));
+    let scanlines = Arc::new(Mutex::new(Vec::new()));
+    let log_scanline = scanlines.clone();
+    hooks.add(Trigger::Scanline(100), move |nes, _| {
+        log_scanline
+            .lock()
+            .unwrap()
+            .push((nes.ppu().scanline(), nes.ppu().dot()));
+    });
+    assert_eq!(nes.hooks().len(), 5);
+
+    (0..3).for_each(|_| {
+        nes.step_instruction();
+    });
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            Event::Interrupt(Interrupt::Reset),
+            Event::Write {
+                address: 0x01FD,
+                value: 0x80
+            },
+            Event::Write {
+                address: 0x01FC,
+                value: 0x02
+            },
+            Event::Execute { address: 0x8010 },
+        ]
+    );
+
+    events.lock().unwrap().clear();
+    assert!(nes.hooks_mut().remove(stack));
+    assert!(!nes.hooks_mut().remove(stack));
+    nes.run_frame();
+    nes.run_frame();
+    assert_eq!(
+        *events.lock().unwrap(),
+        [Event::FrameEnd { frame: 1 }, Event::FrameEnd { frame: 2 }]
+    );
+    // the PPU is caught up for scanline hooks, they run within a CPU cycle of the start
+    assert_eq!(scanlines.lock().unwrap().len(), 2);
+    assert!(scanlines
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|&(scanline, dot)| scanline == 100 && dot < 3));
+}
+
+#[test]
+fn code_data_log() {
+    // LDX $C010, LDX $2007
+    let mut nes = nes(&[0xAE, 0x10, 0xC0, 0xAE, 0x07, 0x20]);
+    let cartridge = nes.cartridge_mut().unwrap();
+    cartridge.start_code_data_log(None).unwrap();
+    nes.step_instruction();
+    nes.step_instruction();
+    nes.step_instruction();
+
+    let log = nes.cartridge_mut().unwrap().stop_code_data_log().unwrap();
+    assert_eq!(log.prg_size(), 0x8000);
+    assert_eq!(log.chr_size(), 0x2000);
+    assert!((0..6).all(|offset| log.prg_usage(offset) == PrgUsage::CODE));
+    assert_eq!(log.prg_usage(0x4010), PrgUsage::DATA);
+    // $C000 is the third 8KB bank
+    assert_eq!(log.prg_bank(0x4010), 2);
+    // the reset vector
+    assert_eq!(log.prg_usage(0x7FFC), PrgUsage::DATA);
+    assert_eq!(log.prg_usage(0x7FFE), PrgUsage::empty());
+    assert_eq!(log.prg_used(), 9);
+    assert_eq!(log.chr_usage(0), ChrUsage::READ);
+    assert_eq!(log.chr_used(), 1);
+
+    let bytes = log.to_bytes();
+    assert_eq!(bytes.len(), 0xA000);
+    assert_eq!(bytes[0x4010], 0x0A);
+    assert_eq!(
+        CodeDataLog::from_bytes(&bytes, 0x8000, 0x2000).unwrap(),
+        log
+    );
+    assert_eq!(
+        CodeDataLog::from_bytes(&bytes, 0x4000, 0x2000),
+        Err(CdlError::WrongSize {
+            expected: 0x6000,
+            found: 0xA000
+        })
+    );
+    let cartridge = nes.cartridge_mut().unwrap();
+    assert!(cartridge
+        .start_code_data_log(Some(CodeDataLog::new(0x4000, 0)))
+        .is_err());
+    assert!(cartridge.start_code_data_log(Some(log)).is_ok());
+}
+
+#[test]
+fn profiler() {
+    let mut program = vec![0x20, 0x10, 0x80];
+    program.resize(0x10, 0xA2);
+    // LDX #$02, RTS
+    program.extend([0xA2, 0x02, 0x60]);
+    let mut nes = nes(&program);
+    nes.step_instruction();
+    nes.start_profiler();
+    (0..4).for_each(|_| {
+        nes.step_instruction();
+    });
+
+    let profiler = nes.stop_profiler().unwrap();
+    assert_eq!(profiler.total_cycles(), 16);
+    assert_eq!(profiler.cycles(0x8000), 6);
+    let hot_spot = |address, cycles| HotSpot { address, cycles };
+    assert_eq!(
+        profiler.hot_spots(),
+        [
+            hot_spot(0x8000, 6),
+            hot_spot(0x8012, 6),
+            hot_spot(0x8003, 2),
+            hot_spot(0x8010, 2)
+        ]
+    );
+    // the reset handler counts as well
+    assert_eq!(
+        profiler.functions(),
+        [hot_spot(0x8000, 8), hot_spot(0x8010, 8)]
+    );
+    assert!(nes.profiler().is_none());
+}
+
+#[test]
+fn symbols() {
+    let mut symbols = Symbols::new();
+    symbols
+        .load_mlb("P:0010:init:sets things up\nR:0300-033F:buffer\n\nG:2002:PPUSTATUS\n")
+        .unwrap();
+    symbols
+        .load_nl("$0000#temp#\n$C020#nmi#\n", NlFile::Bank(1))
+        .unwrap();
+    symbols.load_nl("$0010/2#pointer#\n", NlFile::Ram).unwrap();
+    symbols
+        .load_ca65("al 008000 .reset\nal 008002 .@loop\n")
+        .unwrap();
+    assert_eq!(symbols.len(), 7);
+
+    let label = |name, offset| Some(Label { name, offset });
+    assert_eq!(symbols.label(Location::PrgRom(0x13)), label("init", 3));
+    assert_eq!(symbols.label(Location::PrgRom(0x4020)), label("nmi", 0));
+    assert_eq!(symbols.label(Location::Cpu(0x033F)), label("buffer", 0x3F));
+    assert_eq!(symbols.label(Location::Cpu(0x0340)), None);
+    assert_eq!(symbols.label(Location::Cpu(0x0011)), label("pointer", 1));
+    assert_eq!(symbols.label(Location::Cpu(0x0012)), None);
+    assert_eq!(symbols.find("reset"), Some(Location::Cpu(0x8000)));
+    assert_eq!(
+        symbols.load_mlb("P:0010:init\nP:zz:bad"),
+        Err(SymbolError { line: 2 })
+    );
+    assert_eq!(symbols.load_ca65("al 8000"), Err(SymbolError { line: 1 }));
+
+    // JSR $8010, LDA $0305,X, BIT $2002
+    let mut nes = nes(&[0x20, 0x10, 0x80, 0xBD, 0x05, 0x03, 0x2C, 0x02, 0x20]);
+    *nes.symbols_mut() = symbols;
+    assert_eq!(nes.label(0x8013).unwrap().to_string(), "init+3");
+    assert_eq!(nes.disassemble_labeled(0x8000), "JSR init");
+    assert_eq!(nes.disassemble_labeled(0x8003), "LDA buffer+5,X");
+    assert_eq!(nes.disassemble_labeled(0x8006), "BIT PPUSTATUS");
+    assert_eq!(nes.disassemble_labeled(0x8010), "LDX #$00");
+
+    nes.step_instruction();
+    nes.start_profiler();
+    nes.step_instruction();
+    let report = nes
+        .profiler()
+        .unwrap()
+        .report(1, |address| nes.label(address));
+    assert_eq!(
+        report,
+        "Subroutines:\n           6 100.00% reset\nInstructions:\n           6 100.00% reset\n"
+    );
+}
+
+#[test]
+fn trace_lines() {
+    // LDA #$10, NOP $33
+    let mut nes = nes(&[0xA9, 0x10, 0x04, 0x33]);
+    nes.step_instruction();
+    assert_eq!(
+        nes.trace_line(),
+        "8000  A9 10     LDA #$10                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+    );
+    nes.step_instruction();
+    assert_eq!(
+        nes.trace_line(),
+        "8002  04 33    *NOP $33 = 00                    A:10 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9"
+    );
+}
+
+/// Runs nestest in automation mode, from $C000, and compares the trace with its log
+///
+/// Needs `nestest.nes` and `nestest.log` in the directory in `NESTY_NESTEST`.
+#[test]
+#[ignore = "needs nestest.nes and nestest.log"]
+fn nestest() {
+    let directory = std::path::PathBuf::from(
+        std::env::var("NESTY_NESTEST").expect("NESTY_NESTEST should be the directory with nestest"),
+    );
+    let rom = std::fs::read(directory.join("nestest.nes")).unwrap();
+    let log = std::fs::read_to_string(directory.join("nestest.log")).unwrap();
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&rom).unwrap());
+    nes.power_cycle();
+    nes.step_instruction();
+    nes.cpu_mut().program_counter = 0xC000;
+    let expected: Vec<_> = log.lines().map(str::trim_end).collect();
+    for (index, &line) in expected.iter().enumerate() {
+        let found = nes.trace_line();
+        if found != line {
+            let context = expected[index.saturating_sub(5)..index].join("\n");
+            panic!(
+                "trace differs on line {}:\n{context}\nexpected: {line}\nfound:    {found}",
+                index + 1
+            );
+        }
+        nes.step_instruction();
+    }
+    // the official and unofficial opcode tests leave their error codes here
+    assert_eq!((nes.peek(0x0002), nes.peek(0x0003)), (0, 0));
+}
+
+#[test]
+fn pattern_tables() {
+    assert!(Nes::new().pattern_table(0, 0).is_empty());
+
+    // CHR RAM, filled in by the program together with the palette
+    let program = assemble(
+        "
+                LDX #0
+        loop:   LDA writes,X
+                STA $2006
+                LDA writes+1,X
+                STA $2006
+                LDA writes+2,X
+                STA $2007
+                INX
+                INX
+                INX
+                CPX #15
+                BNE loop
+        end:    JMP end
+        ; address and value of every write
+        writes: .byte $00, $10, $FF     ; low plane of the first row of tile 1
+                .byte $00, $18, $0F     ; high plane
+                .byte $3F, $00, $0F     ; backdrop
+                .byte $3F, $05, $16     ; palette 1
+                .byte $3F, $07, $38
+        ",
+    )
+    .unwrap();
+    let image = nrom_image(&program.bytes(), 0, 0);
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    while nes.cpu().program_counter != program.label("end") {
+        nes.step_instruction();
+    }
+
+    let picture = nes.pattern_table(0, 1);
+    assert_eq!(picture.len(), PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE);
+    assert_eq!(
+        picture[8..16],
+        [0x16, 0x16, 0x16, 0x16, 0x38, 0x38, 0x38, 0x38]
+    );
+    // the rest of tile 1, and tile 0
+    assert!(picture[PATTERN_TABLE_SIZE + 8..PATTERN_TABLE_SIZE + 16]
+        .iter()
+        .chain(&picture[..8])
+        .all(|&pixel| pixel == 0x0F));
+}
@@ -0,0 +1,41 @@
+//! Trace logs in the format of the nestest log
+
+use alloc::{format, string::String, vec::Vec};
+
+use crate::{cpu::StatusFlags, nes::Nes};
+
+impl Nes {
+    /// Line of a trace log for the instruction the CPU is about to run
+    ///
+    /// Follows the format of the nestest log and the traces of Nintendulator, e.g.
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`.
+    /// Unofficial opcodes are marked with a `*` before the mnemonic.
+    /// The PPU position is only up to date between instructions, like after [`Nes::step_instruction`].
+    pub fn trace_line(&self) -> String {
+        let cpu = self.cpu();
+        let instruction = self.disassemble(cpu.program_counter);
+        let bytes = (0..instruction.size())
+            .map(|i| {
+                let byte = self.peek(instruction.address.wrapping_add(i as u16));
+                format!("{byte:02X}")
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let unofficial = if instruction.is_official() { ' ' } else { '*' };
+        let text = instruction.annotate(cpu, |address| self.peek(address));
+        // the unused bit always reads as set
+        let flags = (cpu.flags - StatusFlags::BREAK) | StatusFlags::IGNORED_FLAG;
+        format!(
+            "{:04X}  {bytes:<9}{unofficial}{text:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            instruction.address,
+            cpu.accumulator,
+            cpu.x_index,
+            cpu.y_index,
+            flags.bits(),
+            cpu.stack_ptr,
+            self.ppu().scanline(),
+            self.ppu().dot(),
+            self.cycle(),
+        )
+    }
+}
@@ -0,0 +1,49 @@
+//! Pictures of the graphics in the PPU's memory, for debuggers
+
+use alloc::{vec, vec::Vec};
+
+use crate::{nes::Nes, ppu::pattern::decode_row};
+
+/// Width and height of the picture of a pattern table, it's 16x16 tiles of 8x8 pixels
+pub const PATTERN_TABLE_SIZE: usize = 128;
+
+impl Nes {
+    /// Picture of the 256 tiles of pattern table 0 or 1, in rows of 16 tiles
+    ///
+    /// The tiles are colored with one of the 8 palettes of the PPU, 4-7 being the sprite ones.
+    /// Pixels are in the format of the framebuffer, see [`Ppu::framebuffer`](crate::ppu::Ppu::framebuffer).
+    /// Empty if there's no cartridge inserted.
+    pub fn pattern_table(&self, table: usize, palette: usize) -> Vec<u16> {
+        let Some(cartridge) = self.cartridge() else {
+            return Vec::new();
+        };
+        let ppu = self.ppu();
+        let colors: [u16; 4] = core::array::from_fn(|index| {
+            // color 0 is always the backdrop
+            let entry = if index == 0 {
+                0
+            } else {
+                palette % 8 * 4 + index
+            };
+            ppu.palette[entry] as u16 & 0x3F
+        });
+
+        let mut picture = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE];
+        for tile in 0..256 {
+            let address = (table as u16 & 1) << 12 | tile << 4;
+            let (tile_x, tile_y) = (tile as usize % 16 * 8, tile as usize / 16 * 8);
+            for row in 0..8 {
+                let low = cartridge.ppu_load(address + row);
+                let high = cartridge.ppu_load(address + row + 8);
+                let start = (tile_y + row as usize) * PATTERN_TABLE_SIZE + tile_x;
+                for (pixel, index) in picture[start..start + 8]
+                    .iter_mut()
+                    .zip(decode_row(low, high))
+                {
+                    *pixel = colors[index as usize];
+                }
+            }
+        }
+        picture
+    }
+}
@@ -0,0 +1,198 @@
+//! 6502 disassembler
+//!
+//! Decodes instructions into their mnemonic and operand, with the official opcodes
+//! as well as the unofficial ones, using the names from the nesdev wiki.
+//! [`Nes::disassemble`](crate::nes::Nes::disassemble) decodes straight from the console's memory.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::{Display, Formatter};
+
+use crate::cpu::{AddressingMode, CpuState, OpCode};
+
+#[cfg(test)]
+pub(crate) mod asm;
+#[cfg(test)]
+mod tests;
+
+/// A decoded instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    /// Where the opcode is
+    pub address: u16,
+    pub opcode: u8,
+    /// Bytes after the opcode, little endian, unused bytes are 0
+    pub operand: u16,
+}
+
+impl Instruction {
+    /// Decode the instruction at `address`, reading its bytes with `read`
+    pub fn decode(address: u16, mut read: impl FnMut(u16) -> u8) -> Self {
+        let opcode = read(address);
+        let mut operand = 0;
+        for i in 0..OpCode::from(opcode).addressing_mode().operand_size() {
+            let byte = read(address.wrapping_add(1 + i as u16));
+            operand |= (byte as u16) << (8 * i);
+        }
+        Self {
+            address,
+            opcode,
+            operand,
+        }
+    }
+
+    fn mode(&self) -> AddressingMode {
+        OpCode::from(self.opcode).addressing_mode()
+    }
+
+    /// Three letter name of the instruction, see [`OpCode::mnemonic`]
+    pub fn mnemonic(&self) -> &'static str {
+        OpCode::from(self.opcode).mnemonic()
+    }
+
+    /// See [`OpCode::is_official`]
+    pub fn is_official(&self) -> bool {
+        OpCode::from(self.opcode).is_official()
+    }
+
+    /// Whether the operand points to a pointer to the address that's accessed
+    pub fn is_indirect(&self) -> bool {
+        self.mode().is_indirect()
+    }
+
+    /// Number of bytes the instruction takes up, including the opcode
+    pub fn size(&self) -> u8 {
+        1 + self.mode().operand_size()
+    }
+
+    /// Address of the next instruction, if no jump is taken
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.size() as u16)
+    }
+
+    /// Where a branch, `JMP` or `JSR` goes, if it's known without the CPU state
+    pub fn target(&self) -> Option<u16> {
+        match (self.mode(), self.mnemonic()) {
+            (AddressingMode::Relative, _) => Some(
+                self.next_address()
+                    .wrapping_add(self.operand as u8 as i8 as u16),
+            ),
+            (AddressingMode::Absolute, "JMP" | "JSR") => Some(self.operand),
+            _ => None,
+        }
+    }
+
+    /// Text of the operand, like `($44),Y`
+    pub fn operand_text(&self) -> String {
+        self.mode().format_operand(self.formatted_operand())
+    }
+
+    /// The instruction with the addresses it uses replaced by their labels, like `JSR init+3`
+    ///
+    /// `label` names an address, see [`Nes::label`](crate::nes::Nes::label).
+    /// Addresses without a label are shown as usual.
+    pub fn labeled<L: Display>(&self, mut label: impl FnMut(u16) -> Option<L>) -> String {
+        let operand = self.format_operand(|address, digits| match label(address) {
+            Some(label) => label.to_string(),
+            None => format!("${address:0digits$X}"),
+        });
+        match self.mode() {
+            AddressingMode::Implied => self.mnemonic().to_string(),
+            _ => format!("{} {operand}", self.mnemonic()),
+        }
+    }
+
+    /// Text of the operand, with addresses formatted by `address`, see [`AddressingMode::format_operand_with`]
+    fn format_operand(&self, address: impl FnMut(u16, usize) -> String) -> String {
+        self.mode()
+            .format_operand_with(self.formatted_operand(), address)
+    }
+
+    /// The operand as it's shown, which is the target for branches
+    fn formatted_operand(&self) -> u16 {
+        match self.mode() {
+            AddressingMode::Relative => self.target().unwrap_or_default(),
+            _ => self.operand,
+        }
+    }
+
+    /// Address of the memory the instruction accesses, for the given CPU registers
+    ///
+    /// `read` is used for the pointers of the indirect modes.
+    /// For `JMP ($nnnn)` this is the address of the jump.
+    pub fn effective_address(
+        &self,
+        cpu: &CpuState,
+        mut read: impl FnMut(u16) -> u8,
+    ) -> Option<u16> {
+        let byte = self.operand as u8;
+        // pointers wrap around within their page
+        let mut pointer = |address: u16| {
+            let high = (address & 0xFF00) | (address as u8).wrapping_add(1) as u16;
+            u16::from_le_bytes([read(address), read(high)])
+        };
+        let address = match self.mode() {
+            AddressingMode::ZeroPage => byte as u16,
+            AddressingMode::ZeroPageX => byte.wrapping_add(cpu.x_index) as u16,
+            AddressingMode::ZeroPageY => byte.wrapping_add(cpu.y_index) as u16,
+            AddressingMode::Absolute => self.operand,
+            AddressingMode::AbsoluteX => self.operand.wrapping_add(cpu.x_index as u16),
+            AddressingMode::AbsoluteY => self.operand.wrapping_add(cpu.y_index as u16),
+            AddressingMode::Indirect => pointer(self.operand),
+            AddressingMode::IndirectX => pointer(byte.wrapping_add(cpu.x_index) as u16),
+            AddressingMode::IndirectY => pointer(byte as u16).wrapping_add(cpu.y_index as u16),
+            AddressingMode::Implied
+            | AddressingMode::Accumulator
+            | AddressingMode::Immediate
+            | AddressingMode::Relative => return None,
+        };
+        Some(address)
+    }
+
+    /// The instruction with the addresses and memory values it uses, for the given CPU registers
+    ///
+    /// Follows the format of the nestest log, e.g. `LDA ($89),Y = 0300 @ 0302 = 89`.
+    /// `read` shouldn't have side effects, see [`Nes::peek`](crate::nes::Nes::peek).
+    pub fn annotate(&self, cpu: &CpuState, mut read: impl FnMut(u16) -> u8) -> String {
+        let mut text = self.to_string();
+        let Some(address) = self.effective_address(cpu, &mut read) else {
+            return text;
+        };
+        let byte = self.operand as u8;
+        match self.mode() {
+            AddressingMode::Absolute if self.target().is_some() => return text,
+            AddressingMode::Indirect => {
+                text += &format!(" = {address:04X}");
+                return text;
+            }
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                text += &format!(" @ {address:02X}")
+            }
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                text += &format!(" @ {address:04X}")
+            }
+            AddressingMode::IndirectX => {
+                let pointer = byte.wrapping_add(cpu.x_index);
+                text += &format!(" @ {pointer:02X} = {address:04X}");
+            }
+            AddressingMode::IndirectY => {
+                let base = address.wrapping_sub(cpu.y_index as u16);
+                text += &format!(" = {base:04X} @ {address:04X}");
+            }
+            _ => {}
+        }
+        text += &format!(" = {:02X}", read(address));
+        text
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.mode() {
+            AddressingMode::Implied => write!(f, "{}", self.mnemonic()),
+            _ => write!(f, "{} {}", self.mnemonic(), self.operand_text()),
+        }
+    }
+}
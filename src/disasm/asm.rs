@@ -0,0 +1,339 @@
+//! A small assembler, so tests can be written as 6502 code instead of bytes
+//!
+//! Each line holds a label, an instruction or a directive, with comments after `;`:
+//!
+//! ```text
+//!         .org $8000
+//! reset:  LDX #$00
+//! loop:   INC $0200,X     ; addresses with 2 digits or less are on the zero page
+//!         BNE loop
+//!         JMP (vector)
+//! vector: .word reset, $1234
+//!         .byte $01, %10, 3, <vector, >vector
+//! ```
+//!
+//! Numbers are hex with `$`, binary with `%`, or decimal, and can be added to labels
+//! like `table+1`. `<` and `>` take the low and high byte. Labels are always absolute addresses,
+//! unless the instruction only takes a zero page one. Code starts at $8000 when there's no `.org`.
+//! The mnemonics are the ones of the disassembler, unofficial opcodes included.
+
+use alloc::collections::BTreeMap;
+use core::fmt::{Display, Formatter};
+
+use crate::cpu::{AddressingMode, OpCode};
+
+/// A line of the source couldn't be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl core::error::Error for AsmError {}
+
+/// Assembled code, in blocks of contiguous bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    /// Start address and bytes of each block, in the order of the source
+    pub blocks: Vec<(u16, Vec<u8>)>,
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Program {
+    /// Address of a label
+    ///
+    /// # Panics
+    /// If there's no such label.
+    pub fn label(&self, name: &str) -> u16 {
+        self.labels[name]
+    }
+
+    /// Call `store` with the address and value of every byte
+    pub fn write(&self, mut store: impl FnMut(u16, u8)) {
+        for (start, bytes) in &self.blocks {
+            for (i, &byte) in bytes.iter().enumerate() {
+                store(start.wrapping_add(i as u16), byte);
+            }
+        }
+    }
+
+    /// All bytes, assuming the blocks follow each other
+    pub fn bytes(&self) -> Vec<u8> {
+        self.blocks
+            .iter()
+            .flat_map(|(_, bytes)| bytes.iter().copied())
+            .collect()
+    }
+}
+
+/// Assemble `source`, see the [module docs](self) for the syntax
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    // the first pass finds the labels, the second one has all of them
+    let mut assembler = Assembler::default();
+    assembler.pass(source)?;
+    let labels = core::mem::take(&mut assembler.labels);
+    let mut assembler = Assembler {
+        labels,
+        resolve: true,
+        ..Default::default()
+    };
+    assembler.pass(source)?;
+    Ok(Program {
+        blocks: assembler.blocks,
+        labels: assembler.labels,
+    })
+}
+
+#[derive(Default)]
+struct Assembler {
+    labels: BTreeMap<String, u16>,
+    blocks: Vec<(u16, Vec<u8>)>,
+    address: u16,
+    /// Whether labels have to be known, in the second pass
+    resolve: bool,
+}
+
+/// An operand value, with whether it's written as a zero page address
+#[derive(Clone, Copy)]
+struct Value {
+    value: u16,
+    short: bool,
+}
+
+impl Assembler {
+    fn pass(&mut self, source: &str) -> Result<(), AsmError> {
+        self.address = 0x8000;
+        self.blocks = vec![(self.address, Vec::new())];
+        for (index, line) in source.lines().enumerate() {
+            self.line(line).map_err(|message| AsmError {
+                line: index + 1,
+                message,
+            })?;
+        }
+        self.blocks.retain(|(_, bytes)| !bytes.is_empty());
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), String> {
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(format!("invalid label `{label}`"));
+            }
+            if !self.resolve
+                && self
+                    .labels
+                    .insert(label.to_string(), self.address)
+                    .is_some()
+            {
+                return Err(format!("`{label}` is defined twice"));
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+        let (word, operand) = match line.split_once(char::is_whitespace) {
+            Some((word, operand)) => (word, operand.trim()),
+            None => (line, ""),
+        };
+        match word.to_ascii_lowercase().as_str() {
+            ".org" => {
+                self.address = self.expression(operand)?.value;
+                self.blocks.push((self.address, Vec::new()));
+            }
+            ".byte" => {
+                for value in operand.split(',') {
+                    let value = self.expression(value.trim())?.value;
+                    let byte =
+                        u8::try_from(value).map_err(|_| format!("${value:X} isn't a byte"))?;
+                    self.emit(&[byte]);
+                }
+            }
+            ".word" => {
+                for value in operand.split(',') {
+                    let value = self.expression(value.trim())?.value;
+                    self.emit(&value.to_le_bytes());
+                }
+            }
+            _ => self.instruction(&word.to_ascii_uppercase(), operand)?,
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, mnemonic: &str, operand: &str) -> Result<(), String> {
+        if !(0..=0xFF).any(|opcode| OpCode::from(opcode).mnemonic() == mnemonic) {
+            return Err(format!("unknown instruction `{mnemonic}`"));
+        }
+        let upper = operand.to_ascii_uppercase();
+        let (mode, value) = if operand.is_empty() {
+            let mode = match opcode(mnemonic, AddressingMode::Accumulator) {
+                Some(_) => AddressingMode::Accumulator,
+                None => AddressingMode::Implied,
+            };
+            (mode, None)
+        } else if upper == "A" {
+            (AddressingMode::Accumulator, None)
+        } else if let Some(value) = operand.strip_prefix('#') {
+            (AddressingMode::Immediate, Some(self.expression(value)?))
+        } else if let Some(pointer) = upper.strip_prefix('(') {
+            let (mode, inner) = if let Some(inner) = pointer.strip_suffix(",X)") {
+                (AddressingMode::IndirectX, inner)
+            } else if let Some(inner) = pointer.strip_suffix("),Y") {
+                (AddressingMode::IndirectY, inner)
+            } else if let Some(inner) = pointer.strip_suffix(')') {
+                (AddressingMode::Indirect, inner)
+            } else {
+                return Err(format!("invalid operand `{operand}`"));
+            };
+            // labels keep their case
+            (mode, Some(self.expression(&operand[1..=inner.len()])?))
+        } else {
+            let (modes, inner) = if let Some(inner) = upper.strip_suffix(",X") {
+                (
+                    [AddressingMode::ZeroPageX, AddressingMode::AbsoluteX],
+                    inner,
+                )
+            } else if let Some(inner) = upper.strip_suffix(",Y") {
+                (
+                    [AddressingMode::ZeroPageY, AddressingMode::AbsoluteY],
+                    inner,
+                )
+            } else {
+                (
+                    [AddressingMode::ZeroPage, AddressingMode::Absolute],
+                    upper.as_str(),
+                )
+            };
+            let value = self.expression(operand[..inner.len()].trim())?;
+            let mode = if opcode(mnemonic, AddressingMode::Relative).is_some() {
+                AddressingMode::Relative
+            } else {
+                let [short, long] = modes;
+                let short_exists = opcode(mnemonic, short).is_some();
+                let long_exists = opcode(mnemonic, long).is_some();
+                if short_exists && (value.short || !long_exists) {
+                    short
+                } else {
+                    long
+                }
+            };
+            (mode, Some(value))
+        };
+        let opcode = opcode(mnemonic, mode)
+            .ok_or_else(|| format!("`{mnemonic}` can't be used with `{operand}`"))?;
+
+        let value = value.map_or(0, |value| value.value);
+        let next = self.address.wrapping_add(1 + mode.operand_size() as u16);
+        let operand = match mode {
+            AddressingMode::Relative if self.resolve => {
+                let offset = value.wrapping_sub(next) as i16;
+                let offset = i8::try_from(offset)
+                    .map_err(|_| format!("${value:04X} is too far to branch to"))?;
+                offset as u16 & 0xFF
+            }
+            AddressingMode::Relative => 0,
+            _ if mode.operand_size() == 1 && value > 0xFF && self.resolve => {
+                return Err(format!("${value:X} doesn't fit in a byte"));
+            }
+            _ => value,
+        };
+        let bytes = operand.to_le_bytes();
+        self.emit(&[opcode]);
+        self.emit(&bytes[..mode.operand_size() as usize]);
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        if let Some((_, block)) = self.blocks.last_mut() {
+            block.extend_from_slice(bytes);
+        }
+        self.address = self.address.wrapping_add(bytes.len() as u16);
+    }
+
+    /// Terms added or subtracted, with an optional `<` or `>` in front
+    fn expression(&self, text: &str) -> Result<Value, String> {
+        let text = text.trim();
+        if let Some(rest) = text.strip_prefix('<') {
+            let value = self.expression(rest)?.value & 0xFF;
+            return Ok(Value { value, short: true });
+        }
+        if let Some(rest) = text.strip_prefix('>') {
+            let value = self.expression(rest)?.value >> 8;
+            return Ok(Value { value, short: true });
+        }
+        let mut total = 0u16;
+        let mut short = true;
+        let mut rest = text;
+        let mut negative = false;
+        loop {
+            let end = rest[1.min(rest.len())..]
+                .find(['+', '-'])
+                .map_or(rest.len(), |end| end + 1);
+            let term = self.term(rest[..end].trim())?;
+            total = match negative {
+                true => total.wrapping_sub(term.value),
+                false => total.wrapping_add(term.value),
+            };
+            short &= term.short;
+            if end == rest.len() {
+                return Ok(Value {
+                    value: total,
+                    short,
+                });
+            }
+            negative = rest.as_bytes()[end] == b'-';
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn term(&self, text: &str) -> Result<Value, String> {
+        let invalid = || format!("invalid number `{text}`");
+        let (digits, radix) = if let Some(hex) = text.strip_prefix('$') {
+            (hex, 16)
+        } else if let Some(binary) = text.strip_prefix('%') {
+            (binary, 2)
+        } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+            (text, 10)
+        } else if is_identifier(text) {
+            return match self.labels.get(text) {
+                Some(&value) => Ok(Value {
+                    value,
+                    short: false,
+                }),
+                None if !self.resolve => Ok(Value {
+                    value: 0,
+                    short: false,
+                }),
+                None => Err(format!("unknown label `{text}`")),
+            };
+        } else {
+            return Err(invalid());
+        };
+        let value = u16::from_str_radix(digits, radix).map_err(|_| invalid())?;
+        let short = match radix {
+            16 => digits.len() <= 2,
+            2 => digits.len() <= 8,
+            _ => value <= 0xFF,
+        };
+        Ok(Value { value, short })
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The opcode of an instruction in a mode, see [`OpCode::from_mnemonic_and_mode`]
+fn opcode(mnemonic: &str, mode: AddressingMode) -> Option<u8> {
+    OpCode::from_mnemonic_and_mode(mnemonic, mode).map(u8::from)
+}
@@ -0,0 +1,136 @@
+use super::{
+    asm::{assemble, AsmError},
+    Instruction,
+};
+use crate::cpu::CpuState;
+
+fn decode(bytes: &[u8]) -> Instruction {
+    Instruction::decode(0x8000, |address| {
+        bytes.get(address as usize - 0x8000).copied().unwrap_or(0)
+    })
+}
+
+#[test]
+fn decoding() {
+    let instruction = decode(&[0xBD, 0x34, 0x12]);
+    assert_eq!(instruction.to_string(), "LDA $1234,X");
+    assert_eq!(instruction.size(), 3);
+    assert_eq!(instruction.next_address(), 0x8003);
+    assert!(instruction.is_official());
+
+    assert_eq!(decode(&[0x0A]).to_string(), "ASL A");
+    assert_eq!(decode(&[0x60]).to_string(), "RTS");
+    assert_eq!(decode(&[0xA9, 0x07]).to_string(), "LDA #$07");
+    assert_eq!(decode(&[0xB1, 0x44]).to_string(), "LDA ($44),Y");
+    assert_eq!(decode(&[0x6C, 0xFF, 0x02]).to_string(), "JMP ($02FF)");
+
+    // backwards branch
+    let branch = decode(&[0xD0, 0xFE]);
+    assert_eq!(branch.to_string(), "BNE $8000");
+    assert_eq!(branch.target(), Some(0x8000));
+
+    // unofficial opcodes
+    assert_eq!(decode(&[0xA7, 0x10]).to_string(), "LAX $10");
+    assert!(!decode(&[0xA7, 0x10]).is_official());
+    assert!(!decode(&[0xEB, 0x10]).is_official());
+    assert!(!decode(&[0x1A]).is_official());
+    assert!(decode(&[0xEA]).is_official());
+    assert_eq!(decode(&[0x02]).mnemonic(), "JAM");
+}
+
+#[test]
+fn annotations() {
+    let mut memory = [0u8; 0x800];
+    memory[0x33] = 0xAA;
+    memory[0x80] = 0x00;
+    memory[0x81] = 0x02;
+    memory[0x202] = 0x5A;
+    memory[0x2FF] = 0x7E;
+    memory[0x200] = 0xDB;
+    let read = |address: u16| memory[address as usize % 0x800];
+
+    let mut cpu = CpuState::new();
+    cpu.x_index = 0x33;
+    cpu.y_index = 0x02;
+    let annotate = |bytes: &[u8]| decode(bytes).annotate(&cpu, read);
+    assert_eq!(annotate(&[0xA5, 0x33]), "LDA $33 = AA");
+    assert_eq!(annotate(&[0xB5, 0x00]), "LDA $00,X @ 33 = AA");
+    assert_eq!(annotate(&[0xBD, 0xCF, 0x01]), "LDA $01CF,X @ 0202 = 5A");
+    assert_eq!(annotate(&[0xA1, 0x4D]), "LDA ($4D,X) @ 80 = 0200 = DB");
+    assert_eq!(annotate(&[0xB1, 0x80]), "LDA ($80),Y = 0200 @ 0202 = 5A");
+    // the pointer wraps around in its page
+    assert_eq!(annotate(&[0x6C, 0xFF, 0x02]), "JMP ($02FF) = DB7E");
+    assert_eq!(annotate(&[0x4C, 0xF5, 0xC5]), "JMP $C5F5");
+    assert_eq!(annotate(&[0xE8]), "INX");
+}
+
+#[test]
+fn assembler() {
+    let program = assemble(
+        "
+        .org $8000
+reset:  LDX #$00            ; comment
+loop:   INC $0200,X
+        LDA $10,X
+        STX $10,Y
+        ASL
+        ROR A
+        BNE loop
+        LDA ($04),Y
+        JMP (vector)
+        .org $9000
+vector: .word reset, $1234
+ptr:    .byte <vector, >vector+1, %101, 10
+        lax table+1
+table:  nop
+        ",
+    )
+    .unwrap();
+    assert_eq!(program.label("loop"), 0x8002);
+    assert_eq!(program.label("table"), 0x900B);
+    assert_eq!(
+        program.blocks[0],
+        (
+            0x8000,
+            vec![
+                0xA2, 0x00, 0xFE, 0x00, 0x02, 0xB5, 0x10, 0x96, 0x10, 0x0A, 0x6A, 0xD0, 0xF5, 0xB1,
+                0x04, 0x6C, 0x00, 0x90,
+            ]
+        )
+    );
+    assert_eq!(
+        program.blocks[1],
+        (
+            0x9000,
+            vec![0x00, 0x80, 0x34, 0x12, 0x00, 0x90, 0x05, 0x0A, 0xAF, 0x0C, 0x90, 0xEA]
+        )
+    );
+
+    // disassembling gives back the source
+    let source = [
+        "LDA #$07",
+        "STA $1234,Y",
+        "LDA ($44,X)",
+        "JSR $8000",
+        "SBC #$01",
+        "JAM",
+    ];
+    for line in source {
+        let bytes = assemble(line).unwrap().bytes();
+        assert_eq!(decode(&bytes).to_string(), line);
+    }
+
+    let error = |source: &str| assemble(source).unwrap_err();
+    assert_eq!(
+        error("NOP\nFOO $10"),
+        AsmError {
+            line: 2,
+            message: "unknown instruction `FOO`".to_string()
+        }
+    );
+    assert_eq!(error("BNE far\n.org $9000\nfar:").line, 1);
+    assert_eq!(error("JMP nowhere").line, 1);
+    assert_eq!(error("LDA #$100").line, 1);
+    assert_eq!(error("a:\na:").line, 2);
+    assert_eq!(error("STA #$10").line, 1);
+}
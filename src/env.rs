@@ -0,0 +1,161 @@
+//! A reinforcement learning environment, in the style of Gym
+//!
+//! [`Environment`] runs the console a few frames per step with the buttons of the agent,
+//! and returns an [`Observation`] of the picture and of the RAM addresses the reward
+//! or the end of the episode is computed from, like the score or the lives.
+//! Episodes restart from a save state, by default the one taken when the environment was made.
+//!
+//! ```no_run
+//! # use nesty::{env::Environment, input::ButtonState, nes::Nes};
+//! # let nes = Nes::new();
+//! // the lives of Super Mario Bros.
+//! let mut env = Environment::new(nes, &[0x075A]).unwrap();
+//! env.set_frames_per_step(4);
+//! env.reset();
+//! loop {
+//!     let (observation, info) = env.step(ButtonState::RIGHT | ButtonState::A);
+//!     if observation.ram[0] == 0 || info.episode_frames > 10_000 {
+//!         break;
+//!     }
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{input::ButtonState, nes::Nes, ppu::palette, state::StateError};
+
+#[cfg(test)]
+mod tests;
+
+/// What the agent sees after a step
+#[derive(Debug, Clone, Copy)]
+pub struct Observation<'a> {
+    /// 256x240 pixels, see [`Ppu::framebuffer`](crate::ppu::Ppu::framebuffer) for their format
+    pub video: &'a [u16],
+    /// The value at each of the observed addresses, in order
+    pub ram: &'a [u8],
+}
+
+impl Observation<'_> {
+    /// Convert the picture to 8-bit RGB, 3 bytes per pixel
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.video
+            .iter()
+            .flat_map(|&pixel| palette::to_rgb(pixel))
+            .collect()
+    }
+}
+
+/// Details about a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    /// Frames run since the episode started
+    pub episode_frames: u64,
+    /// Frames of the step during which the game didn't read the controllers
+    pub lag_frames: u32,
+}
+
+#[derive(Debug)]
+pub struct Environment {
+    nes: Nes,
+    /// The save state episodes start from
+    start: Vec<u8>,
+    addresses: Vec<u16>,
+    /// Values at the addresses, updated after every step
+    ram: Vec<u8>,
+    frames_per_step: u32,
+    episode_frames: u64,
+}
+
+impl Environment {
+    /// Wrap a console with a cartridge, episodes start from its current state
+    ///
+    /// `addresses` are CPU addresses, read without side effects, so they can also be in
+    /// the cartridge's RAM. Fails if there's no cartridge inserted.
+    pub fn new(nes: Nes, addresses: &[u16]) -> Result<Self, StateError> {
+        let start = nes.save_state()?;
+        Ok(Self {
+            nes,
+            start,
+            addresses: addresses.to_vec(),
+            ram: Vec::with_capacity(addresses.len()),
+            frames_per_step: 1,
+            episode_frames: 0,
+        })
+    }
+
+    pub fn nes(&self) -> &Nes {
+        &self.nes
+    }
+
+    /// The console, to change its settings or the buttons of the other players
+    pub fn nes_mut(&mut self) -> &mut Nes {
+        &mut self.nes
+    }
+
+    pub fn frames_per_step(&self) -> u32 {
+        self.frames_per_step
+    }
+
+    /// Run `frames` frames with the same buttons for every step, 1 by default
+    ///
+    /// # Panics
+    /// If `frames` is 0
+    pub fn set_frames_per_step(&mut self, frames: u32) {
+        assert!(frames > 0, "a step has to run at least one frame");
+        self.frames_per_step = frames;
+    }
+
+    /// Start the next episodes from the current state of the console
+    pub fn set_start_here(&mut self) {
+        self.start = self
+            .nes
+            .save_state()
+            .expect("the cartridge can't be removed from the environment");
+    }
+
+    /// Start the next episodes from a state made by [`Nes::save_state`]
+    ///
+    /// The state is checked by loading it, which starts an episode.
+    pub fn set_start_state(&mut self, state: Vec<u8>) -> Result<(), StateError> {
+        self.nes.load_state(&state)?;
+        self.start = state;
+        self.episode_frames = 0;
+        Ok(())
+    }
+
+    /// Start a new episode
+    pub fn reset(&mut self) -> Observation<'_> {
+        self.nes
+            .load_state(&self.start)
+            .expect("the start state was made with the same cartridge");
+        self.episode_frames = 0;
+        self.observe()
+    }
+
+    /// Hold `buttons` on the first controller for a step
+    pub fn step(&mut self, buttons: ButtonState) -> (Observation<'_>, StepInfo) {
+        self.nes.set_player_buttons(0, buttons);
+        let mut lag_frames = 0;
+        for _ in 0..self.frames_per_step {
+            while self.nes.run_frame().stop.is_some() {}
+            lag_frames += self.nes.is_lag_frame() as u32;
+        }
+        self.episode_frames += self.frames_per_step as u64;
+        let info = StepInfo {
+            episode_frames: self.episode_frames,
+            lag_frames,
+        };
+        (self.observe(), info)
+    }
+
+    fn observe(&mut self) -> Observation<'_> {
+        self.ram.clear();
+        let values = self.addresses.iter().map(|&address| self.nes.peek(address));
+        self.ram.extend(values);
+        Observation {
+            video: self.nes.ppu().framebuffer(),
+            ram: &self.ram,
+        }
+    }
+}
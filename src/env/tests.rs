@@ -0,0 +1,68 @@
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    disasm::asm::assemble,
+    input::ButtonState,
+    nes::Nes,
+};
+
+use super::Environment;
+
+/// A console with a game that counts frames in $10 and stores the buttons of player 1 in $11
+fn nes() -> Nes {
+    let program = assemble(
+        "
+        reset:  LDA #$80        ; NMIs on
+                STA $2000
+        loop:   JMP loop
+        nmi:    INC $10
+                LDA #$01
+                STA $4016
+                LDA #$00
+                STA $4016
+                LDX #$08
+        read:   LDA $4016
+                LSR
+                ROR $11
+                DEX
+                BNE read
+                RTI
+                .org $FFFA
+                .word nmi, reset, reset
+        ",
+    )
+    .unwrap();
+    let mut prg = vec![0; 0x8000];
+    program.write(|address, value| prg[address as usize - 0x8000] = value);
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&nrom_image(&prg, 1, 0)).unwrap());
+    nes.power_cycle();
+    nes
+}
+
+#[test]
+fn episodes() {
+    assert!(Environment::new(Nes::new(), &[]).is_err());
+
+    let mut env = Environment::new(nes(), &[0x10, 0x11]).unwrap();
+    env.set_frames_per_step(3);
+    assert_eq!(env.reset().ram, [0, 0]);
+    let (observation, info) = env.step(ButtonState::START);
+    assert_eq!(observation.video.len(), 256 * 240);
+    assert_eq!(observation.to_rgb().len(), 256 * 240 * 3);
+    assert_eq!(observation.ram, [3, ButtonState::START.bits()]);
+    assert_eq!((info.episode_frames, info.lag_frames), (3, 0));
+    let (observation, info) = env.step(ButtonState::A);
+    assert_eq!(observation.ram, [6, ButtonState::A.bits()]);
+    assert_eq!(info.episode_frames, 6);
+
+    // the next episodes start from here
+    env.set_start_here();
+    env.step(ButtonState::empty());
+    assert_eq!(env.reset().ram, [6, ButtonState::A.bits()]);
+
+    let state = nes().save_state().unwrap();
+    env.set_start_state(state).unwrap();
+    env.step(ButtonState::B);
+    assert_eq!(env.reset().ram, [0, 0]);
+    assert!(env.set_start_state(vec![1, 2, 3]).is_err());
+}
@@ -0,0 +1,86 @@
+//! An error type for everything that can fail in the crate
+//!
+//! Each fallible part of the emulator has its own error type, with only the failures that can happen there.
+//! [`Error`] holds any of them, so an application loading games, states and movies can pass them all
+//! up with `?` and show them with [`Display`].
+
+use core::fmt::{Display, Formatter};
+
+use crate::{
+    cartridge::{game_genie::GameGenieError, patch::PatchError, LoadError},
+    clock::Region,
+    debug::cdl::CdlError,
+    movie::{Bk2Error, Fm2Error},
+    state::StateError,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A ROM image couldn't be loaded
+    Load(LoadError),
+    /// A save state couldn't be loaded
+    State(StateError),
+    /// A ROM patch couldn't be applied
+    Patch(PatchError),
+    /// A Game Genie code couldn't be decoded
+    GameGenie(GameGenieError),
+    /// A code/data log doesn't fit the cartridge
+    Cdl(CdlError),
+    /// An FCEUX movie couldn't be read
+    Fm2(Fm2Error),
+    /// A BizHawk movie couldn't be read
+    Bk2(Bk2Error),
+    /// Something was made for a console of another region, like a movie
+    RegionMismatch { expected: Region, found: Region },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Load(error) => write!(f, "{error}"),
+            Error::State(error) => write!(f, "{error}"),
+            Error::Patch(error) => write!(f, "{error}"),
+            Error::GameGenie(error) => write!(f, "{error}"),
+            Error::Cdl(error) => write!(f, "{error}"),
+            Error::Fm2(error) => write!(f, "{error}"),
+            Error::Bk2(error) => write!(f, "{error}"),
+            Error::RegionMismatch { expected, found } => {
+                write!(f, "made for a {expected} console, running as {found}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::Load(error) => Some(error),
+            Error::State(error) => Some(error),
+            Error::Patch(error) => Some(error),
+            Error::GameGenie(error) => Some(error),
+            Error::Cdl(error) => Some(error),
+            Error::Fm2(error) => Some(error),
+            Error::Bk2(error) => Some(error),
+            Error::RegionMismatch { .. } => None,
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($($variant:ident($type:ty)),* $(,)?) => {
+        $(impl From<$type> for Error {
+            fn from(error: $type) -> Self {
+                Error::$variant(error)
+            }
+        })*
+    };
+}
+impl_from!(
+    Load(LoadError),
+    State(StateError),
+    Patch(PatchError),
+    GameGenie(GameGenieError),
+    Cdl(CdlError),
+    Fm2(Fm2Error),
+    Bk2(Bk2Error),
+);
@@ -0,0 +1,254 @@
+//! Animated GIF clips of the picture
+//!
+//! [`GifRecorder`] encodes frames as they're recorded, every other frame at half the frame rate,
+//! since browsers slow down GIFs that go faster than 50 frames per second.
+//! The picture uses the 64 colors of the palette directly, so it's never dithered and compresses well.
+//! A frame with the emphasis bits set gets its own color table.
+
+use alloc::vec::Vec;
+
+use crate::{
+    clock::Region,
+    nes::Frame,
+    ppu::palette::{self, NTSC_PALETTE},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Frames of the console per frame of the GIF
+const FRAME_INTERVAL: u64 = 2;
+const MAX_CODE_LEN: u32 = 12;
+const MAX_CODES: usize = 1 << MAX_CODE_LEN;
+/// The most bytes in a sub-block of image data
+const MAX_BLOCK: usize = 255;
+const COLORS: usize = NTSC_PALETTE.len();
+const EMPHASIS_SHIFT: u16 = 6;
+
+/// Bits of the LZW codes, filled from the least significant bit of every byte
+#[derive(Debug, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u16, len: u32) {
+        self.bits |= (value as u32) << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.len > 0 {
+            self.bytes.push(self.bits as u8);
+            self.bits = 0;
+            self.len = 0;
+        }
+    }
+}
+
+/// Compresses the indices of a picture with the variable length code LZW of GIF
+#[derive(Debug, Default)]
+struct LzwEncoder {
+    /// For every code, the code of the string followed by a color, or 0 if there's none yet
+    table: Vec<u16>,
+    output: BitWriter,
+}
+
+impl LzwEncoder {
+    /// Encode `indices`, which are all below `1 << min_code_len`, into the output
+    fn encode(&mut self, indices: &[u8], min_code_len: u32) {
+        let colors = 1 << min_code_len;
+        let clear = colors as u16;
+        let end = clear + 1;
+        let mut code_len = min_code_len + 1;
+        let mut next = end + 1;
+        self.table.clear();
+        self.table.resize(next as usize * colors, 0);
+
+        self.output.write(clear, code_len);
+        let Some((&first, rest)) = indices.split_first() else {
+            self.output.write(end, code_len);
+            return;
+        };
+        let mut prefix = first as u16;
+        for &index in rest {
+            let entry = prefix as usize * colors + index as usize;
+            if self.table[entry] != 0 {
+                prefix = self.table[entry];
+                continue;
+            }
+            self.output.write(prefix, code_len);
+            self.table[entry] = next;
+            next += 1;
+            self.table.resize(next as usize * colors, 0);
+            if next as usize == MAX_CODES {
+                self.output.write(clear, code_len);
+                code_len = min_code_len + 1;
+                next = end + 1;
+                self.table.clear();
+                self.table.resize(next as usize * colors, 0);
+            } else if next > 1 << code_len {
+                code_len += 1;
+            }
+            prefix = index as u16;
+        }
+        self.output.write(prefix, code_len);
+        self.output.write(end, code_len);
+    }
+}
+
+/// Records a bounded clip of frames as an animated GIF
+#[derive(Debug)]
+pub struct GifRecorder {
+    /// Pixels of the picture per pixel of the GIF, in each direction
+    scale: usize,
+    width: usize,
+    height: usize,
+    /// Console frames per second as a fraction, for the delays
+    frame_rate: (u32, u32),
+    max_frames: usize,
+    frames: usize,
+    /// Console frames seen, only every `FRAME_INTERVAL`th is recorded
+    seen: u64,
+    gif: Vec<u8>,
+    /// Reused for every frame
+    indices: Vec<u8>,
+    lzw: LzwEncoder,
+}
+
+impl GifRecorder {
+    /// Start a clip of up to `max_frames` frames of the GIF, dividing the size of the picture by `scale`
+    ///
+    /// `region` sets the speed of the animation.
+    ///
+    /// # Panics
+    /// If `scale` is 0 or more than the height of the picture
+    pub fn new(region: Region, max_frames: usize, scale: usize) -> Self {
+        assert!(
+            (1..=Frame::HEIGHT).contains(&scale),
+            "invalid GIF scale {scale}"
+        );
+        let width = Frame::WIDTH / scale;
+        let height = Frame::HEIGHT / scale;
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&(width as u16).to_le_bytes());
+        gif.extend_from_slice(&(height as u16).to_le_bytes());
+        // a global color table of 64 colors with 8 bits per channel, no background or aspect ratio
+        gif.extend_from_slice(&[0xF5, 0, 0]);
+        gif.extend(NTSC_PALETTE.iter().flatten());
+        // loop forever
+        gif.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        Self {
+            scale,
+            width,
+            height,
+            frame_rate: region.frame_rate(),
+            max_frames,
+            frames: 0,
+            seen: 0,
+            gif,
+            indices: Vec::with_capacity(width * height),
+            lzw: LzwEncoder::default(),
+        }
+    }
+
+    /// Number of frames of the GIF recorded so far
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    /// The clip reached its maximum length and doesn't record anymore
+    pub fn is_full(&self) -> bool {
+        self.frames >= self.max_frames
+    }
+
+    /// Time since the start of the clip after `frames` frames of the GIF, in hundredths of a second
+    fn time(&self, frames: usize) -> u64 {
+        let (rate, seconds) = self.frame_rate;
+        let console_frames = frames as u64 * FRAME_INTERVAL;
+        (console_frames * 100 * seconds as u64 + rate as u64 / 2) / rate as u64
+    }
+
+    /// Add a frame to the clip, returns `false` once it's full
+    ///
+    /// Frames cut short by a breakpoint are skipped, only finished pictures are recorded.
+    pub fn record(&mut self, frame: &Frame) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        if frame.stop.is_some() {
+            return true;
+        }
+        let skipped = !self.seen.is_multiple_of(FRAME_INTERVAL);
+        self.seen += 1;
+        if skipped {
+            return true;
+        }
+
+        // the emphasis of the top left pixel is used for the whole frame
+        let emphasis = frame.video[0] >> EMPHASIS_SHIFT;
+        self.indices.clear();
+        let rows = frame.video.chunks_exact(Frame::WIDTH).step_by(self.scale);
+        for row in rows.take(self.height) {
+            let row = row.iter().step_by(self.scale).take(self.width);
+            self.indices
+                .extend(row.map(|&pixel| (pixel as usize % COLORS) as u8));
+        }
+
+        let delay = self.time(self.frames + 1) - self.time(self.frames);
+        // the delay, and no transparency
+        self.gif.extend_from_slice(&[0x21, 0xF9, 4, 0]);
+        self.gif.extend_from_slice(&(delay as u16).to_le_bytes());
+        self.gif.extend_from_slice(&[0, 0]);
+
+        self.gif.push(0x2C);
+        self.gif.extend_from_slice(&[0; 4]);
+        self.gif
+            .extend_from_slice(&(self.width as u16).to_le_bytes());
+        self.gif
+            .extend_from_slice(&(self.height as u16).to_le_bytes());
+        if emphasis == 0 {
+            self.gif.push(0);
+        } else {
+            // a local color table of 64 colors
+            self.gif.push(0x85);
+            for color in 0..COLORS as u16 {
+                self.gif
+                    .extend(palette::to_rgb(emphasis << EMPHASIS_SHIFT | color));
+            }
+        }
+
+        let min_code_len = COLORS.trailing_zeros();
+        self.gif.push(min_code_len as u8);
+        self.lzw.output.bytes.clear();
+        self.lzw.encode(&self.indices, min_code_len);
+        self.lzw.output.flush();
+        for block in self.lzw.output.bytes.chunks(MAX_BLOCK) {
+            self.gif.push(block.len() as u8);
+            self.gif.extend_from_slice(block);
+        }
+        self.gif.push(0);
+
+        self.frames += 1;
+        !self.is_full()
+    }
+
+    /// Stop recording, returning the GIF file
+    pub fn finish(mut self) -> Vec<u8> {
+        self.gif.push(0x3B);
+        self.gif
+    }
+}
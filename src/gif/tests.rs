@@ -0,0 +1,182 @@
+use crate::{clock::Region, nes::Frame, ppu::Overscan};
+
+use super::{palette, GifRecorder, LzwEncoder, COLORS, MAX_CODES, NTSC_PALETTE};
+
+/// Decompress the LZW codes of an image
+fn decode_lzw(bytes: &[u8], min_code_len: u32) -> Vec<u8> {
+    let clear = 1 << min_code_len;
+    let end = clear + 1;
+    let mut table: Vec<Vec<u8>> = (0..=end as u8).map(|index| vec![index]).collect();
+    let mut code_len = min_code_len + 1;
+    let mut previous: Option<Vec<u8>> = None;
+    let mut output = Vec::new();
+    let mut position = 0;
+    loop {
+        let code = (0..code_len).fold(0, |code, bit| {
+            let bit_index = position + bit as usize;
+            let bit = (bytes[bit_index / 8] >> (bit_index % 8)) & 1;
+            code | (bit as usize) << (bit_index - position)
+        });
+        position += code_len as usize;
+        if code == clear {
+            table.truncate(end + 1);
+            code_len = min_code_len + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return output;
+        }
+        let entry = match table.get(code) {
+            Some(entry) => entry.clone(),
+            None => {
+                let mut entry = previous.clone().unwrap();
+                entry.push(entry[0]);
+                entry
+            }
+        };
+        output.extend_from_slice(&entry);
+        if let Some(mut string) = previous.take() {
+            if table.len() < MAX_CODES {
+                string.push(entry[0]);
+                table.push(string);
+            }
+        }
+        if table.len() == 1 << code_len && code_len < 12 {
+            code_len += 1;
+        }
+        previous = Some(entry);
+    }
+}
+
+struct Image<'a> {
+    delay: u16,
+    local_colors: Option<&'a [u8]>,
+    indices: Vec<u8>,
+}
+
+fn images(gif: &[u8]) -> Vec<Image<'_>> {
+    assert_eq!(&gif[..6], b"GIF89a");
+    assert_eq!(gif.last(), Some(&0x3B));
+    let mut position = 13 + COLORS * 3;
+    let mut images = Vec::new();
+    let mut delay = 0;
+    let sub_blocks = |position: &mut usize| {
+        let mut data = Vec::new();
+        while gif[*position] != 0 {
+            let len = gif[*position] as usize;
+            data.extend_from_slice(&gif[*position + 1..*position + 1 + len]);
+            *position += 1 + len;
+        }
+        *position += 1;
+        data
+    };
+    loop {
+        match gif[position] {
+            0x21 => {
+                if gif[position + 1] == 0xF9 {
+                    delay = u16::from_le_bytes([gif[position + 4], gif[position + 5]]);
+                }
+                position += 2;
+                sub_blocks(&mut position);
+            }
+            0x2C => {
+                let packed = gif[position + 9];
+                position += 10;
+                let colors = match packed & 0x80 {
+                    0 => None,
+                    _ => {
+                        let table = &gif[position..position + COLORS * 3];
+                        position += COLORS * 3;
+                        Some(table)
+                    }
+                };
+                let min_code_len = gif[position] as u32;
+                position += 1;
+                let data = sub_blocks(&mut position);
+                images.push(Image {
+                    delay,
+                    local_colors: colors,
+                    indices: decode_lzw(&data, min_code_len),
+                });
+            }
+            0x3B => return images,
+            byte => panic!("unexpected block {byte:02X}"),
+        }
+    }
+}
+
+#[test]
+fn lzw() {
+    // long enough to fill the table and clear it a few times
+    let mut seed = 7u32;
+    let indices: Vec<u8> = (0..20_000)
+        .map(|index| match index % 5000 {
+            0..=999 => 3,
+            _ => {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8 % 64
+            }
+        })
+        .collect();
+    let mut lzw = LzwEncoder::default();
+    lzw.encode(&indices, 6);
+    lzw.output.flush();
+    assert_eq!(decode_lzw(&lzw.output.bytes, 6), indices);
+
+    let mut lzw = LzwEncoder::default();
+    lzw.encode(&[], 2);
+    lzw.output.flush();
+    assert!(decode_lzw(&lzw.output.bytes, 2).is_empty());
+}
+
+#[test]
+fn record() {
+    let picture: Vec<u16> = (0..Frame::WIDTH * Frame::HEIGHT)
+        .map(|pixel| (pixel / 7 % 64) as u16)
+        .collect();
+    let frame = Frame {
+        video: &picture,
+        audio: &[],
+        palette: &palette::Palette::NTSC,
+        overscan: Overscan::NONE,
+        stop: None,
+    };
+    let mut recorder = GifRecorder::new(Region::Ntsc, 3, 2);
+    assert!(recorder.is_empty());
+    // every other frame is recorded
+    for _ in 0..4 {
+        assert!(recorder.record(&frame));
+    }
+    assert_eq!(recorder.len(), 2);
+
+    let emphasized: Vec<u16> = picture.iter().map(|&pixel| pixel | 0b101 << 6).collect();
+    let frame = Frame {
+        video: &emphasized,
+        ..frame
+    };
+    assert!(!recorder.record(&frame));
+    assert!(recorder.is_full());
+    assert!(!recorder.record(&frame));
+
+    let gif = recorder.finish();
+    assert_eq!(gif[6..10], [128, 0, 120, 0]);
+    assert_eq!(gif[13..16], NTSC_PALETTE[0]);
+    let images = images(&gif);
+    assert_eq!(images.len(), 3);
+    // 30 frames per second, in hundredths of a second
+    let delays: Vec<u16> = images.iter().map(|image| image.delay).collect();
+    assert_eq!(delays, [3, 4, 3]);
+
+    let downscaled: Vec<u8> = picture
+        .chunks_exact(Frame::WIDTH)
+        .step_by(2)
+        .flat_map(|row| row.iter().step_by(2))
+        .map(|&pixel| pixel as u8)
+        .collect();
+    assert_eq!(images[0].local_colors, None);
+    assert_eq!(images[0].indices, downscaled);
+    let colors = images[2].local_colors.unwrap();
+    assert_eq!(colors[..3], palette::to_rgb(0b101 << 6));
+    assert_eq!(images[2].indices, downscaled);
+}
@@ -0,0 +1,291 @@
+//! Controller input
+//!
+//! Each of the two controller ports has a [`Device`] plugged into it.
+//! The CPU reads them one bit at a time through $4016 and $4017,
+//! and writing bit 0 of $4016 (the strobe) tells them to latch their state.
+//!
+//! Frontends can either set the state of the devices whenever they like,
+//! or give the console an [`InputProvider`] it asks for input right when the game strobes the controllers.
+
+use alloc::boxed::Box;
+use core::fmt::{Debug, Formatter};
+
+use bitflags::bitflags;
+
+use crate::{
+    ppu::Ppu,
+    state::{impl_state, impl_state_bits, State, StateError, StateReader, StateWriter},
+};
+
+mod controller;
+mod four_score;
+pub mod log;
+mod power_pad;
+#[cfg(test)]
+mod tests;
+mod turbo;
+mod vaus;
+mod zapper;
+
+pub use controller::Controller;
+pub use four_score::FourScore;
+pub use power_pad::PowerPad;
+pub use turbo::{Turbo, DEFAULT_TURBO_PERIOD};
+pub use vaus::Vaus;
+pub use zapper::Zapper;
+
+bitflags! {
+    /// Buttons of a standard controller, in the order the controller reports them
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct ButtonState: u8 {
+        const A = 1;
+        const B = 1 << 1;
+        const SELECT = 1 << 2;
+        const START = 1 << 3;
+        const UP = 1 << 4;
+        const DOWN = 1 << 5;
+        const LEFT = 1 << 6;
+        const RIGHT = 1 << 7;
+    }
+}
+
+impl_state_bits!(ButtonState);
+
+/// Something plugged into a controller port
+#[derive(Debug, Clone)]
+pub enum Device {
+    /// Nothing is connected, reads return 0
+    None,
+    Controller(Controller),
+    Zapper(Zapper),
+    /// One half of the Four Score, which needs to be connected to both ports
+    FourScore(FourScore),
+    /// The NES Arkanoid controller, which games expect in port 2
+    Vaus(Vaus),
+    /// Games expect the Power Pad in port 2
+    PowerPad(PowerPad),
+}
+
+impl Device {
+    /// Handle a write to $4016
+    pub fn write_strobe(&mut self, value: u8, ppu: &Ppu) {
+        match self {
+            Device::None | Device::Zapper(_) => {}
+            Device::Controller(controller) => controller.write_strobe(value, ppu.frame()),
+            Device::FourScore(four_score) => four_score.write_strobe(value),
+            Device::Vaus(vaus) => vaus.write_strobe(value),
+            Device::PowerPad(power_pad) => power_pad.write_strobe(value),
+        }
+    }
+
+    /// Read the port, devices that look at the picture need the PPU to be caught up
+    pub fn read(&mut self, ppu: &Ppu) -> u8 {
+        match self {
+            Device::None => 0,
+            Device::Controller(controller) => controller.read(ppu.frame()),
+            Device::Zapper(zapper) => zapper.read(ppu),
+            Device::FourScore(four_score) => four_score.read(),
+            Device::Vaus(vaus) => vaus.read(),
+            Device::PowerPad(power_pad) => power_pad.read(),
+        }
+    }
+
+    pub fn as_controller_mut(&mut self) -> Option<&mut Controller> {
+        match self {
+            Device::Controller(controller) => Some(controller),
+            _ => None,
+        }
+    }
+
+    /// The same device as it is when the console is turned on, keeping what the player is doing with it
+    pub(crate) fn power_cycle(&mut self) {
+        match self {
+            Device::None | Device::Zapper(_) => {}
+            Device::Controller(controller) => {
+                let mut new = Controller::new();
+                new.set_buttons(controller.buttons());
+                new.set_turbo_buttons(controller.turbo_buttons());
+                *new.turbo_mut() = *controller.turbo();
+                *controller = new;
+            }
+            Device::FourScore(four_score) => four_score.power_cycle(),
+            Device::Vaus(vaus) => vaus.power_cycle(),
+            Device::PowerPad(power_pad) => power_pad.power_cycle(),
+        }
+    }
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Device::Controller(Controller::new())
+    }
+}
+
+// only the state of the connected device is saved,
+// so the same devices have to be connected when the state is loaded
+impl State for Device {
+    fn save_state(&self, writer: &mut StateWriter) {
+        match self {
+            Device::None | Device::Zapper(_) => {}
+            Device::Controller(controller) => controller.save_state(writer),
+            Device::FourScore(four_score) => four_score.save_state(writer),
+            Device::Vaus(vaus) => vaus.save_state(writer),
+            Device::PowerPad(power_pad) => power_pad.save_state(writer),
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        match self {
+            Device::None | Device::Zapper(_) => Ok(()),
+            Device::Controller(controller) => controller.load_state(reader),
+            Device::FourScore(four_score) => four_score.load_state(reader),
+            Device::Vaus(vaus) => vaus.load_state(reader),
+            Device::PowerPad(power_pad) => power_pad.load_state(reader),
+        }
+    }
+}
+
+/// Something plugged into the Famicom's expansion port
+///
+/// Expansion devices are read through bits 1-4 of both $4016 and $4017,
+/// alongside whatever is in the controller ports.
+#[derive(Debug, Clone, Default)]
+pub enum Expansion {
+    #[default]
+    None,
+    /// The Famicom Arkanoid controller
+    Vaus(Vaus),
+}
+
+impl Expansion {
+    fn write_strobe(&mut self, value: u8) {
+        match self {
+            Expansion::None => {}
+            Expansion::Vaus(vaus) => vaus.write_strobe(value),
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        match self {
+            Expansion::None => 0,
+            Expansion::Vaus(vaus) => vaus.read_famicom(port),
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        match self {
+            Expansion::None => {}
+            Expansion::Vaus(vaus) => vaus.power_cycle(),
+        }
+    }
+}
+
+impl State for Expansion {
+    fn save_state(&self, writer: &mut StateWriter) {
+        match self {
+            Expansion::None => {}
+            Expansion::Vaus(vaus) => vaus.save_state(writer),
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        match self {
+            Expansion::None => Ok(()),
+            Expansion::Vaus(vaus) => vaus.load_state(reader),
+        }
+    }
+}
+
+/// Source of input the console polls when the game strobes the controllers
+///
+/// Implemented for closures taking the PPU frame number and the devices.
+/// Providers are `Send` so the console they're set on can be moved to another thread.
+pub trait InputProvider: Send {
+    /// Update the devices right before they latch their state
+    fn poll(&mut self, frame: u64, devices: &mut [Device; 2]);
+}
+
+impl<F: FnMut(u64, &mut [Device; 2]) + Send> InputProvider for F {
+    fn poll(&mut self, frame: u64, devices: &mut [Device; 2]) {
+        self(frame, devices)
+    }
+}
+
+/// Holds the input provider, cloning it gives an empty slot
+#[derive(Default)]
+struct ProviderSlot(Option<Box<dyn InputProvider>>);
+
+impl Clone for ProviderSlot {
+    fn clone(&self) -> Self {
+        Self(None)
+    }
+}
+
+impl Debug for ProviderSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "Some(InputProvider)"),
+            None => write!(f, "None"),
+        }
+    }
+}
+
+/// The two controller ports, as seen by the CPU at $4016 and $4017
+#[derive(Debug, Clone, Default)]
+pub struct ControllerPorts {
+    /// Devices in ports 1 and 2, both start out with a standard controller
+    pub devices: [Device; 2],
+    pub expansion: Expansion,
+    provider: ProviderSlot,
+    /// Whether the game read any of the ports since [`ControllerPorts::take_polled`]
+    polled: bool,
+}
+
+impl ControllerPorts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the provider polled on every strobe, returning the previous one
+    ///
+    /// Clones of the ports don't get the provider.
+    pub fn set_provider(
+        &mut self,
+        provider: Option<Box<dyn InputProvider>>,
+    ) -> Option<Box<dyn InputProvider>> {
+        core::mem::replace(&mut self.provider.0, provider)
+    }
+
+    /// Handle a write to $4016, the provider is polled when bit 0 is set
+    pub fn write_strobe(&mut self, value: u8, ppu: &Ppu) {
+        if value & 1 != 0 {
+            if let Some(provider) = &mut self.provider.0 {
+                provider.poll(ppu.frame(), &mut self.devices);
+            }
+        }
+        for device in &mut self.devices {
+            device.write_strobe(value, ppu);
+        }
+        self.expansion.write_strobe(value);
+    }
+
+    /// Read port 1 or 2, indexed from 0
+    pub fn read(&mut self, port: usize, ppu: &Ppu) -> u8 {
+        self.polled = true;
+        self.devices[port].read(ppu) | self.expansion.read(port)
+    }
+
+    /// Whether the game read the ports since the last call, resetting it
+    pub fn take_polled(&mut self) -> bool {
+        core::mem::take(&mut self.polled)
+    }
+
+    pub(crate) fn power_cycle(&mut self) {
+        self.devices.iter_mut().for_each(Device::power_cycle);
+        self.expansion.power_cycle();
+        self.polled = false;
+    }
+}
+
+// the provider and the poll flag belong to the frontend
+impl_state!(ControllerPorts { devices, expansion });
@@ -0,0 +1,87 @@
+//! Standard controller
+
+use super::{ButtonState, Turbo};
+use crate::state::impl_state;
+
+/// Standard controller
+///
+/// Writing 1 to bit 0 of $4016 (the strobe) makes the controller latch its buttons,
+/// after it goes back to 0 every read returns the next button in bit 0, in the [`ButtonState`] order.
+/// After all 8 buttons, reads return 1.
+/// While the strobe is held at 1 the buttons are latched continuously, so every read returns the A button.
+///
+/// Details at https://www.nesdev.org/wiki/Standard_controller
+#[derive(Debug, Clone, Default)]
+pub struct Controller {
+    /// Buttons currently held down, set by the frontend
+    buttons: ButtonState,
+    /// Buttons held down with autofire
+    turbo_buttons: ButtonState,
+    turbo: Turbo,
+    /// Latched buttons, shifted out one bit per read with 1s shifted in
+    shift_register: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buttons(&self) -> ButtonState {
+        self.buttons
+    }
+
+    /// Set the buttons held down, the game sees them the next time it strobes the controller
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        self.buttons = buttons;
+    }
+
+    pub fn turbo_buttons(&self) -> ButtonState {
+        self.turbo_buttons
+    }
+
+    /// Set the buttons held down with autofire, on top of the ones from [`Controller::set_buttons`]
+    pub fn set_turbo_buttons(&mut self, buttons: ButtonState) {
+        self.turbo_buttons = buttons;
+    }
+
+    pub fn turbo(&self) -> &Turbo {
+        &self.turbo
+    }
+
+    pub fn turbo_mut(&mut self) -> &mut Turbo {
+        &mut self.turbo
+    }
+
+    /// Buttons the game sees as pressed during the PPU frame
+    fn pressed(&self, frame: u64) -> ButtonState {
+        self.buttons | self.turbo.apply(self.turbo_buttons, frame)
+    }
+
+    /// Handle a write to $4016 during the given PPU frame, only bit 0 of the value matters
+    pub fn write_strobe(&mut self, value: u8, frame: u64) {
+        let strobe = value & 1 != 0;
+        // the buttons are reloaded for as long as the strobe is high, the last reload happens as it falls
+        if self.strobe || strobe {
+            self.shift_register = self.pressed(frame).bits();
+        }
+        self.strobe = strobe;
+    }
+
+    /// Read the next bit during the given PPU frame, returned in bit 0
+    pub fn read(&mut self, frame: u64) -> u8 {
+        if self.strobe {
+            return self.pressed(frame).bits() & 1;
+        }
+        let bit = self.shift_register & 1;
+        self.shift_register = self.shift_register >> 1 | 0x80;
+        bit
+    }
+}
+
+// the buttons and the autofire configuration come from the frontend, only the protocol state is saved
+impl_state!(Controller {
+    shift_register,
+    strobe
+});
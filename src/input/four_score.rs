@@ -0,0 +1,84 @@
+//! Four Score four player adapter
+//!
+//! Both controller ports go through the adapter, each one reading two controllers:
+//! port 1 has players 1 and 3, port 2 has players 2 and 4.
+//! After the 16 buttons each port sends a signature byte, so games can tell the adapter is there,
+//! then reads return 1.
+//!
+//! Details at https://www.nesdev.org/wiki/Four_Score
+
+use super::ButtonState;
+use crate::state::impl_state;
+
+/// Half of the Four Score, connected to one controller port
+#[derive(Debug, Clone)]
+pub struct FourScore {
+    /// Buttons of the two players on this port
+    buttons: [ButtonState; 2],
+    signature: u8,
+    /// Latched report, shifted out one bit per read with 1s shifted in
+    shift_register: u32,
+    strobe: bool,
+}
+
+impl FourScore {
+    /// The half connected to port 1 or 2, indexed from 0
+    ///
+    /// # Panics
+    /// If `port` isn't 0 or 1
+    pub fn new(port: usize) -> Self {
+        // 00010000 on port 1 and 00100000 on port 2, in the order they're read
+        let signature = match port {
+            0 => 0x08,
+            1 => 0x04,
+            _ => panic!("there are only 2 controller ports"),
+        };
+        Self {
+            buttons: [ButtonState::empty(); 2],
+            signature,
+            shift_register: 0,
+            strobe: false,
+        }
+    }
+
+    /// Buttons of the first or the second player on this port
+    pub fn buttons(&self, player: usize) -> ButtonState {
+        self.buttons[player]
+    }
+
+    pub fn set_buttons(&mut self, player: usize, buttons: ButtonState) {
+        self.buttons[player] = buttons;
+    }
+
+    /// Handle a write to $4016, only bit 0 matters
+    pub fn write_strobe(&mut self, value: u8) {
+        let strobe = value & 1 != 0;
+        if self.strobe || strobe {
+            self.shift_register = self.buttons[0].bits() as u32
+                | (self.buttons[1].bits() as u32) << 8
+                | (self.signature as u32) << 16;
+        }
+        self.strobe = strobe;
+    }
+
+    pub(super) fn power_cycle(&mut self) {
+        self.shift_register = 0;
+        self.strobe = false;
+    }
+
+    /// Read the next bit, returned in bit 0
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons[0].bits() & 1;
+        }
+        let bit = self.shift_register & 1;
+        self.shift_register = self.shift_register >> 1 | 1 << 23;
+        bit as u8
+    }
+}
+
+// the buttons come from the frontend, only the protocol state is saved
+impl_state!(FourScore {
+    shift_register,
+    strobe
+});
@@ -0,0 +1,160 @@
+//! Per-frame record of the input
+//!
+//! An [`InputLog`] keeps the buttons of every player and the console events for each frame,
+//! and nothing else, so it can be replayed on top of any starting state.
+//! Movie formats like FM2 build on it, see [`Movie`](crate::movie::Movie).
+
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+
+use super::ButtonState;
+use crate::nes::Nes;
+
+bitflags! {
+    /// Console events that happen at the start of a frame
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct ConsoleEvents: u8 {
+        const SOFT_RESET = 1;
+        const POWER = 1 << 1;
+        const FDS_INSERT = 1 << 2;
+        const FDS_SELECT = 1 << 3;
+        const VS_COIN = 1 << 4;
+    }
+}
+
+/// Bit of the first byte of an encoded frame that's set when there are events
+const EVENTS_BIT: u8 = 4;
+
+/// Input of a single frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct InputFrame {
+    pub events: ConsoleEvents,
+    /// Buttons of players 1 to 4, the last two are only used with a Four Score
+    pub buttons: [ButtonState; 4],
+}
+
+impl InputFrame {
+    /// The buttons the players are currently holding
+    pub fn capture(nes: &Nes) -> Self {
+        Self {
+            events: ConsoleEvents::empty(),
+            buttons: [0, 1, 2, 3].map(|player| nes.player_buttons(player)),
+        }
+    }
+
+    /// Append the frame in a compact format, for sending it over the network
+    ///
+    /// A first byte has a bit for each player holding buttons and bit 4 set if there are events,
+    /// followed by those players' buttons then the events, so most frames take 1 or 2 bytes.
+    pub fn encode(&self, output: &mut Vec<u8>) {
+        let players = self.buttons.iter().enumerate();
+        let mut present = players.fold(0, |present, (player, buttons)| {
+            present | (!buttons.is_empty() as u8) << player
+        });
+        present |= (!self.events.is_empty() as u8) << EVENTS_BIT;
+        output.push(present);
+        for buttons in self.buttons.iter().filter(|buttons| !buttons.is_empty()) {
+            output.push(buttons.bits());
+        }
+        if !self.events.is_empty() {
+            output.push(self.events.bits());
+        }
+    }
+
+    /// Read a frame written by [`InputFrame::encode`], with the number of bytes it took
+    ///
+    /// Returns `None` if the bytes end before the frame does or aren't a frame.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (&present, mut rest) = bytes.split_first()?;
+        if present >> (EVENTS_BIT + 1) != 0 {
+            return None;
+        }
+        let mut next = || {
+            let (&byte, tail) = rest.split_first()?;
+            rest = tail;
+            Some(byte)
+        };
+        let mut frame = Self::default();
+        for (player, buttons) in frame.buttons.iter_mut().enumerate() {
+            if present & 1 << player != 0 {
+                *buttons = ButtonState::from_bits_retain(next()?);
+            }
+        }
+        if present & 1 << EVENTS_BIT != 0 {
+            frame.events = ConsoleEvents::from_bits(next()?)?;
+        }
+        Some((frame, bytes.len() - rest.len()))
+    }
+
+    /// Apply the events and give the buttons to the players, before the frame is run
+    pub fn apply(&self, nes: &mut Nes) {
+        if self.events.contains(ConsoleEvents::POWER) {
+            nes.power_cycle();
+        } else if self.events.contains(ConsoleEvents::SOFT_RESET) {
+            nes.reset();
+        }
+        for (player, buttons) in self.buttons.into_iter().enumerate() {
+            nes.set_player_buttons(player, buttons);
+        }
+    }
+}
+
+/// Input of every frame, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputLog {
+    frames: Vec<InputFrame>,
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn frames(&self) -> &[InputFrame] {
+        &self.frames
+    }
+
+    pub fn frame(&self, index: usize) -> Option<&InputFrame> {
+        self.frames.get(index)
+    }
+
+    /// Append the input of the next frame
+    pub fn record(&mut self, frame: InputFrame) {
+        self.frames.push(frame);
+    }
+
+    /// Drop every frame starting with `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    /// Apply the input of the given frame and run it
+    ///
+    /// Returns `false` without running anything past the end of the log.
+    pub fn replay_frame(&self, index: usize, nes: &mut Nes) -> bool {
+        let Some(frame) = self.frames.get(index) else {
+            return false;
+        };
+        frame.apply(nes);
+        nes.run_frame();
+        true
+    }
+}
+
+impl FromIterator<InputFrame> for InputLog {
+    fn from_iter<T: IntoIterator<Item = InputFrame>>(iter: T) -> Self {
+        Self {
+            frames: iter.into_iter().collect(),
+        }
+    }
+}
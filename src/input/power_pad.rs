@@ -0,0 +1,101 @@
+//! Power Pad dance mat
+//!
+//! The mat has 12 buttons numbered like this on side B:
+//! ```text
+//!  1  2  3  4
+//!  5  6  7  8
+//!  9 10 11 12
+//! ```
+//! The strobe latches all of them into two shift registers, read through bits 3 and 4 of the port.
+//! Bit 3 sends 8 buttons and bit 4 the other 4, then both read 1.
+//!
+//! Details at https://www.nesdev.org/wiki/Power_Pad
+
+use crate::state::impl_state;
+
+/// Buttons sent through bit 3, in order
+const LOW_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+/// Buttons sent through bit 4, in order
+const HIGH_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+#[derive(Debug, Clone, Default)]
+pub struct PowerPad {
+    /// Bit `n - 1` is set when button `n` is pressed
+    buttons: u16,
+    /// Latched buttons for bit 3 and 4, shifted out one bit per read with 1s shifted in
+    shift_registers: [u8; 2],
+    strobe: bool,
+}
+
+impl PowerPad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bit `n - 1` is set when button `n` is pressed
+    pub fn buttons(&self) -> u16 {
+        self.buttons
+    }
+
+    pub fn set_buttons(&mut self, buttons: u16) {
+        self.buttons = buttons & 0x0FFF;
+    }
+
+    /// Press or release a button, numbered 1 to 12
+    ///
+    /// # Panics
+    /// If `number` isn't between 1 and 12
+    pub fn set_button(&mut self, number: u8, pressed: bool) {
+        assert!((1..=12).contains(&number), "no Power Pad button {number}");
+        let bit = 1 << (number - 1);
+        if pressed {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+    }
+
+    fn serialize(&self, order: &[u8]) -> u8 {
+        order
+            .iter()
+            .enumerate()
+            .filter(|&(_, &number)| self.buttons & 1 << (number - 1) != 0)
+            .fold(0, |bits, (i, _)| bits | 1 << i)
+    }
+
+    /// Handle a write to $4016, only bit 0 matters
+    pub fn write_strobe(&mut self, value: u8) {
+        let strobe = value & 1 != 0;
+        if self.strobe || strobe {
+            // the unused top bits of the second register read 1, like after the end
+            self.shift_registers = [
+                self.serialize(&LOW_ORDER),
+                self.serialize(&HIGH_ORDER) | 0xF0,
+            ];
+        }
+        self.strobe = strobe;
+    }
+
+    /// Read the next bit of both registers, in bits 3 and 4
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.write_strobe(1);
+        }
+        let bits = (self.shift_registers[0] & 1) << 3 | (self.shift_registers[1] & 1) << 4;
+        if !self.strobe {
+            self.shift_registers = self.shift_registers.map(|register| register >> 1 | 0x80);
+        }
+        bits
+    }
+
+    pub(super) fn power_cycle(&mut self) {
+        self.shift_registers = [0; 2];
+        self.strobe = false;
+    }
+}
+
+// the buttons come from the frontend, only the protocol state is saved
+impl_state!(PowerPad {
+    shift_registers,
+    strobe
+});
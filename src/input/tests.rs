@@ -0,0 +1,298 @@
+use super::{
+    log::{ConsoleEvents, InputFrame, InputLog},
+    ButtonState, Controller, ControllerPorts, Device, Expansion, FourScore, PowerPad, Turbo, Vaus,
+    Zapper,
+};
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    memory::{ram::Ram, PpuMemoryMapping},
+    nes::Nes,
+    ppu::Ppu,
+};
+
+fn read_all(controller: &mut Controller) -> Vec<u8> {
+    (0..10).map(|_| controller.read(0)).collect()
+}
+
+#[test]
+fn standard_controller() {
+    let mut controller = Controller::new();
+    controller.set_buttons(ButtonState::A | ButtonState::START | ButtonState::RIGHT);
+    controller.write_strobe(1, 0);
+    controller.write_strobe(0, 0);
+    // 1s after the 8 buttons
+    assert_eq!(read_all(&mut controller), [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+    // buttons are only seen after the next strobe
+    controller.set_buttons(ButtonState::B);
+    assert_eq!(controller.read(0), 1);
+    controller.write_strobe(1, 0);
+    controller.write_strobe(0, 0);
+    assert_eq!(read_all(&mut controller), [0, 1, 0, 0, 0, 0, 0, 0, 1, 1]);
+}
+
+#[test]
+fn strobe_held_high() {
+    let mut controller = Controller::new();
+    controller.write_strobe(1, 0);
+    assert_eq!(controller.read(0), 0);
+    // the buttons are reloaded continuously, so the A button can change between reads
+    controller.set_buttons(ButtonState::A | ButtonState::B);
+    assert_eq!(read_all(&mut controller), [1; 10]);
+    controller.set_buttons(ButtonState::B);
+    assert_eq!(controller.read(0), 0);
+
+    // the buttons at the moment the strobe goes low are the ones latched
+    controller.write_strobe(0, 0);
+    controller.set_buttons(ButtonState::empty());
+    assert_eq!(read_all(&mut controller), [0, 1, 0, 0, 0, 0, 0, 0, 1, 1]);
+}
+
+#[test]
+fn zapper() {
+    let mut cartridge = Cartridge::from_ines(&nrom_image(&[], 0, 1)).unwrap();
+    let mut vram = Ram::new();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    let mut ppu = Ppu::new();
+    let mut run_to = |ppu: &mut Ppu, scanline: u16| {
+        while ppu.scanline() != scanline {
+            ppu.tick(&mut memory);
+        }
+    };
+
+    let mut zapper = Zapper::new();
+    zapper.aim(100, 50);
+    // black screen
+    ppu.palette[0] = 0x0F;
+    run_to(&mut ppu, 60);
+    assert_eq!(zapper.read(&ppu), 0x08);
+    zapper.set_trigger(true);
+    assert_eq!(zapper.read(&ppu), 0x18);
+
+    // white screen, seen only after the beam passes the target and for a while after
+    ppu.palette[0] = 0x30;
+    run_to(&mut ppu, 0);
+    run_to(&mut ppu, 50);
+    assert_eq!(zapper.read(&ppu), 0x18);
+    run_to(&mut ppu, 51);
+    assert_eq!(zapper.read(&ppu), 0x10);
+    run_to(&mut ppu, 75);
+    assert_eq!(zapper.read(&ppu), 0x10);
+    run_to(&mut ppu, 80);
+    assert_eq!(zapper.read(&ppu), 0x18);
+
+    zapper.aim(-1, 50);
+    run_to(&mut ppu, 0);
+    run_to(&mut ppu, 51);
+    assert_eq!(zapper.read(&ppu), 0x18);
+}
+
+#[test]
+fn four_score() {
+    let mut ports = [FourScore::new(0), FourScore::new(1)];
+    ports[0].set_buttons(0, ButtonState::A);
+    ports[0].set_buttons(1, ButtonState::B);
+    ports[1].set_buttons(1, ButtonState::RIGHT);
+    for port in &mut ports {
+        port.write_strobe(1);
+        port.write_strobe(0);
+    }
+    let reads = ports.map(|mut port| (0..26).map(|_| port.read()).collect::<Vec<_>>());
+    #[rustfmt::skip]
+    assert_eq!(reads[0], [
+        1, 0, 0, 0, 0, 0, 0, 0,
+        0, 1, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 1, 0, 0, 0, 0,
+        1, 1,
+    ]);
+    #[rustfmt::skip]
+    assert_eq!(reads[1], [
+        0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 1,
+        0, 0, 1, 0, 0, 0, 0, 0,
+        1, 1,
+    ]);
+}
+
+#[test]
+fn turbo() {
+    let mut turbo = Turbo::new();
+    turbo.set_period(ButtonState::B, 3);
+    turbo.set_period(ButtonState::SELECT, 0);
+    let held = ButtonState::A | ButtonState::B | ButtonState::SELECT;
+    let frames: Vec<_> = (0..6).map(|frame| turbo.apply(held, frame)).collect();
+    assert_eq!(
+        frames,
+        [
+            held,
+            ButtonState::B | ButtonState::SELECT,
+            held,
+            ButtonState::SELECT,
+            ButtonState::A | ButtonState::SELECT,
+            ButtonState::SELECT,
+        ]
+    );
+    assert_eq!(turbo.period(ButtonState::A), 1);
+
+    let mut controller = Controller::new();
+    controller.set_buttons(ButtonState::START);
+    controller.set_turbo_buttons(ButtonState::A);
+    for frame in 0..4 {
+        controller.write_strobe(1, frame);
+        let a_pressed = frame % 2 == 0;
+        assert_eq!(controller.read(frame), a_pressed as u8);
+        controller.write_strobe(0, frame);
+        assert_eq!(read_all(&mut controller)[..4], [a_pressed as u8, 0, 0, 1]);
+    }
+}
+
+#[test]
+fn input_provider() {
+    let ppu = Ppu::new();
+    let mut ports = ControllerPorts::new();
+    let mut polls = 0;
+    ports.set_provider(Some(Box::new(move |frame, devices: &mut [Device; 2]| {
+        assert_eq!(frame, 0);
+        polls += 1;
+        let buttons = if polls == 1 {
+            ButtonState::A
+        } else {
+            ButtonState::B
+        };
+        devices[1].as_controller_mut().unwrap().set_buttons(buttons);
+    })));
+
+    assert!(!ports.take_polled());
+    ports.write_strobe(1, &ppu);
+    ports.write_strobe(0, &ppu);
+    assert_eq!((ports.read(1, &ppu), ports.read(1, &ppu)), (1, 0));
+    assert!(ports.take_polled());
+    assert!(!ports.take_polled());
+
+    ports.write_strobe(1, &ppu);
+    ports.write_strobe(0, &ppu);
+    assert_eq!((ports.read(1, &ppu), ports.read(1, &ppu)), (0, 1));
+    // clones don't take the provider along
+    let mut clone = ports.clone();
+    clone.write_strobe(1, &ppu);
+    clone.write_strobe(0, &ppu);
+    assert_eq!((clone.read(1, &ppu), clone.read(1, &ppu)), (0, 1));
+}
+
+#[test]
+fn vaus() {
+    let mut vaus = Vaus::new();
+    vaus.set_position(0b1010_0110);
+    vaus.set_fire(true);
+    vaus.write_strobe(1);
+    vaus.write_strobe(0);
+    // inverted, most significant bit first
+    let bits: Vec<_> = (0..8).map(|_| vaus.read()).collect();
+    assert_eq!(bits, [0x08, 0x18, 0x08, 0x18, 0x18, 0x08, 0x08, 0x18]);
+
+    let ppu = Ppu::new();
+    let mut ports = ControllerPorts::new();
+    ports.devices[1] = Device::None;
+    ports.expansion = Expansion::Vaus(vaus);
+    ports.write_strobe(1, &ppu);
+    ports.write_strobe(0, &ppu);
+    assert_eq!(ports.read(0, &ppu) & 0x02, 0x02);
+    let bits: Vec<_> = (0..8).map(|_| ports.read(1, &ppu)).collect();
+    assert_eq!(bits, [0, 2, 0, 2, 2, 0, 0, 2]);
+}
+
+#[test]
+fn power_pad() {
+    let mut power_pad = PowerPad::new();
+    for number in [1, 9, 12] {
+        power_pad.set_button(number, true);
+    }
+    power_pad.write_strobe(1);
+    power_pad.write_strobe(0);
+    let bits: Vec<_> = (0..10).map(|_| power_pad.read()).collect();
+    // bit 3 has 2, 1, 5, 9, 6, 10, 11, 7 and bit 4 has 4, 3, 12, 8
+    assert_eq!(
+        bits,
+        [0x00, 0x08, 0x10, 0x08, 0x10, 0x10, 0x10, 0x10, 0x18, 0x18]
+    );
+    assert_eq!(power_pad.buttons(), 0b1001_0000_0001);
+}
+
+#[test]
+fn input_log() {
+    let mut nes = Nes::new();
+    nes.connect_four_score();
+    nes.set_player_buttons(0, ButtonState::A);
+    nes.set_player_buttons(3, ButtonState::LEFT);
+
+    let mut log = InputLog::new();
+    log.record(InputFrame::capture(&nes));
+    log.record(InputFrame {
+        events: ConsoleEvents::SOFT_RESET,
+        buttons: [ButtonState::B; 4],
+    });
+    log.record(InputFrame::default());
+    assert_eq!(log.len(), 3);
+    assert_eq!(
+        log.frame(0).unwrap().buttons,
+        [
+            ButtonState::A,
+            ButtonState::empty(),
+            ButtonState::empty(),
+            ButtonState::LEFT
+        ]
+    );
+
+    log.truncate(2);
+    assert_eq!(log.len(), 2);
+    assert!(log.frame(2).is_none());
+
+    let mut nes = Nes::new();
+    nes.connect_four_score();
+    assert!(log.replay_frame(0, &mut nes));
+    assert_eq!(nes.player_buttons(0), ButtonState::A);
+    assert_eq!(nes.player_buttons(3), ButtonState::LEFT);
+    assert!(log.replay_frame(1, &mut nes));
+    assert!((0..4).all(|player| nes.player_buttons(player) == ButtonState::B));
+    assert!(!log.replay_frame(2, &mut nes));
+}
+
+#[test]
+fn encoded_input_frame() {
+    let frames = [
+        InputFrame::default(),
+        InputFrame {
+            events: ConsoleEvents::empty(),
+            buttons: [
+                ButtonState::A | ButtonState::RIGHT,
+                ButtonState::empty(),
+                ButtonState::empty(),
+                ButtonState::START,
+            ],
+        },
+        InputFrame {
+            events: ConsoleEvents::SOFT_RESET,
+            buttons: [ButtonState::all(); 4],
+        },
+    ];
+    let mut bytes = Vec::new();
+    for frame in &frames {
+        frame.encode(&mut bytes);
+    }
+    assert_eq!(bytes[..4], [0, 0b1001, 0x81, 0x08]);
+    assert_eq!(bytes.len(), 1 + 3 + 6);
+
+    let mut rest = &bytes[..];
+    for frame in &frames {
+        let (decoded, len) = InputFrame::decode(rest).unwrap();
+        assert_eq!(&decoded, frame);
+        rest = &rest[len..];
+    }
+    assert!(rest.is_empty());
+    assert_eq!(InputFrame::decode(&[]), None);
+    assert_eq!(InputFrame::decode(&[0b1]), None);
+    assert_eq!(InputFrame::decode(&[0x20]), None);
+}
@@ -0,0 +1,53 @@
+//! Autofire
+//!
+//! Buttons held with turbo are pressed and released repeatedly, switching every few frames.
+//! The phase comes from the PPU frame number instead of when the button was pressed,
+//! so the same input always produces the same presses, which keeps movies and replays in sync.
+
+use super::ButtonState;
+
+/// Frames between toggles used when nothing else is configured, 30 presses per second on NTSC
+pub const DEFAULT_TURBO_PERIOD: u8 = 1;
+
+/// Per button turbo speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Turbo {
+    /// Frames each button stays pressed, and then released, in the [`ButtonState`] bit order
+    periods: [u8; 8],
+}
+
+impl Turbo {
+    pub fn new() -> Self {
+        Self {
+            periods: [DEFAULT_TURBO_PERIOD; 8],
+        }
+    }
+
+    /// Frames between presses and releases of a single button
+    pub fn period(&self, button: ButtonState) -> u8 {
+        self.periods[button.bits().trailing_zeros() as usize % 8]
+    }
+
+    /// Set how many frames the buttons stay pressed and then released, 0 means they're held down without turbo
+    pub fn set_period(&mut self, buttons: ButtonState, frames: u8) {
+        for button in buttons.iter() {
+            self.periods[button.bits().trailing_zeros() as usize] = frames;
+        }
+    }
+
+    /// Which of the buttons held with turbo are pressed on the given frame
+    pub fn apply(&self, held: ButtonState, frame: u64) -> ButtonState {
+        held.iter()
+            .filter(|&button| match self.period(button) {
+                0 => true,
+                period => (frame / period as u64).is_multiple_of(2),
+            })
+            .collect()
+    }
+}
+
+impl Default for Turbo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,82 @@
+//! Arkanoid Vaus paddle controller
+//!
+//! The paddle's knob turns a potentiometer, whose 8-bit position is latched by the strobe
+//! and sent inverted, most significant bit first, along with the fire button.
+//! The NES version plugs into port 2 and uses bits 3 and 4 of $4017,
+//! the Famicom version plugs into the expansion port and uses bit 1 of both $4016 and $4017.
+//!
+//! Details at https://www.nesdev.org/wiki/Arkanoid_controller
+
+use crate::state::impl_state;
+
+#[derive(Debug, Clone, Default)]
+pub struct Vaus {
+    /// Potentiometer position, Arkanoid expects values roughly between $62 and $F2
+    position: u8,
+    fire: bool,
+    /// Latched position, shifted out one bit per read
+    shift_register: u8,
+    strobe: bool,
+}
+
+impl Vaus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position;
+    }
+
+    pub fn set_fire(&mut self, pressed: bool) {
+        self.fire = pressed;
+    }
+
+    /// Handle a write to $4016, only bit 0 matters
+    pub fn write_strobe(&mut self, value: u8) {
+        let strobe = value & 1 != 0;
+        if self.strobe || strobe {
+            self.shift_register = self.position;
+        }
+        self.strobe = strobe;
+    }
+
+    /// Next bit of the position, already inverted
+    fn next_bit(&mut self) -> u8 {
+        if self.strobe {
+            return !self.position >> 7;
+        }
+        let bit = self.shift_register >> 7;
+        self.shift_register <<= 1;
+        !bit & 1
+    }
+
+    /// Read the NES version from port 2, bit 3 is the fire button and bit 4 the position
+    pub fn read(&mut self) -> u8 {
+        self.next_bit() << 4 | (self.fire as u8) << 3
+    }
+
+    /// Read the Famicom version, bit 1 of $4016 (`port` 0) is the fire button
+    /// and bit 1 of $4017 (`port` 1) the position
+    pub fn read_famicom(&mut self, port: usize) -> u8 {
+        match port {
+            0 => (self.fire as u8) << 1,
+            _ => self.next_bit() << 1,
+        }
+    }
+
+    pub(super) fn power_cycle(&mut self) {
+        self.shift_register = 0;
+        self.strobe = false;
+    }
+}
+
+// the position and the button come from the frontend, only the protocol state is saved
+impl_state!(Vaus {
+    shift_register,
+    strobe
+});
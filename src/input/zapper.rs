@@ -0,0 +1,61 @@
+//! Zapper light gun
+//!
+//! The Zapper has a trigger and a photodiode that sees the spot on the screen it's aimed at.
+//! The photodiode only reacts to the beam drawing a bright pixel there,
+//! and its output stays on for a while afterwards, so games flash targets white for a frame
+//! and check the light sense bit while the picture is drawn.
+//!
+//! Details at https://www.nesdev.org/wiki/Zapper
+
+use crate::ppu::{palette, Ppu, FRAME_HEIGHT, FRAME_WIDTH};
+
+/// Scanlines the photodiode keeps reporting light after the bright pixel was drawn
+const LIGHT_SCANLINES: u16 = 26;
+/// Brightness (0-255) a pixel has to have to be seen
+const LIGHT_THRESHOLD: u32 = 128;
+
+#[derive(Debug, Clone, Default)]
+pub struct Zapper {
+    trigger: bool,
+    /// Pixel the Zapper is aimed at, `None` if it's pointed away from the screen
+    target: Option<(u16, u16)>,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// Aim at a pixel of the 256x240 picture, coordinates outside of it aim away from the screen
+    pub fn aim(&mut self, x: i32, y: i32) {
+        let on_screen =
+            (0..FRAME_WIDTH as i32).contains(&x) && (0..FRAME_HEIGHT as i32).contains(&y);
+        self.target = on_screen.then_some((x as u16, y as u16));
+    }
+
+    pub fn aim_away(&mut self) {
+        self.target = None;
+    }
+
+    /// Bit 3 is cleared when light is detected, bit 4 is set while the trigger is pulled
+    pub fn read(&self, ppu: &Ppu) -> u8 {
+        (!self.detects_light(ppu) as u8) << 3 | (self.trigger as u8) << 4
+    }
+
+    fn detects_light(&self, ppu: &Ppu) -> bool {
+        let Some((x, y)) = self.target else {
+            return false;
+        };
+        // dot 1 draws pixel 0
+        let drawn = ppu.scanline() > y || (ppu.scanline() == y && ppu.dot() > x + 1);
+        if !drawn || ppu.scanline() - y >= LIGHT_SCANLINES {
+            return false;
+        }
+        let [r, g, b] = palette::to_rgb(ppu.framebuffer()[y as usize * FRAME_WIDTH + x as usize]);
+        (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000 >= LIGHT_THRESHOLD
+    }
+}
@@ -1,2 +1,77 @@
+//! An NES emulator
+//!
+//! [`prelude`] has what's needed to run games, the most used types are also at the crate root.
+//! Fallible operations return the error type of their module, [`error::Error`] can hold any of them.
+//!
+//! The `std` feature is on by default, without it the crate is `no_std` and only needs `alloc`,
+//! which leaves out what needs threads.
+//! The `png` feature adds [`nes::Frame::to_png_bytes`] to take screenshots,
+//! the `zip` feature lets [`cartridge::Cartridge::from_bytes`] load ROMs from ZIP archives,
+//! the `compression` feature adds [`state::compression::compress`] and compresses rewind snapshots,
+//! and the `log` feature logs what the console does through the `log` crate, like loaded ROMs,
+//! interrupts, jammed CPUs and writes the emulated hardware ignores.
+//!
+//! The `console` feature is on by default and has everything past the 6502 core.
+//! Without it only [`cpu`], the [`memory::Memory`] trait it runs on and [`disasm`] are left,
+//! for emulating other machines built around a 6502.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "console")]
+pub mod apu;
+#[cfg(all(feature = "std", feature = "console"))]
+pub mod capture;
+#[cfg(feature = "console")]
+pub mod cartridge;
+#[cfg(feature = "console")]
+pub mod clock;
 pub mod cpu;
-pub mod memory;
\ No newline at end of file
+#[cfg(feature = "console")]
+pub mod debug;
+pub mod disasm;
+#[cfg(feature = "console")]
+pub mod env;
+#[cfg(feature = "console")]
+pub mod error;
+#[cfg(feature = "console")]
+pub mod gif;
+#[cfg(feature = "console")]
+pub mod input;
+mod logging;
+pub mod memory;
+#[cfg(feature = "console")]
+pub mod movie;
+#[cfg(feature = "console")]
+pub mod nes;
+#[cfg(feature = "console")]
+pub mod netplay;
+#[cfg(all(feature = "png", feature = "console"))]
+pub mod png;
+#[cfg(feature = "console")]
+pub mod ppu;
+#[cfg(feature = "console")]
+pub mod prelude;
+#[cfg(feature = "console")]
+pub mod rewind;
+#[cfg(feature = "console")]
+pub mod state;
+#[cfg(all(test, feature = "console"))]
+mod test_roms;
+// the worker thread can't be spawned in browsers
+#[cfg(all(
+    feature = "std",
+    feature = "console",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+pub mod video;
+#[cfg(feature = "console")]
+pub mod zip;
+
+#[cfg(feature = "console")]
+pub use cartridge::Cartridge;
+#[cfg(feature = "console")]
+pub use error::Error;
+#[cfg(feature = "console")]
+pub use nes::{Frame, Nes};
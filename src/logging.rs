@@ -0,0 +1,49 @@
+//! Logging macros that go to the `log` crate with the `log` feature, and compile to nothing without it
+//!
+//! The arguments are still type checked when the feature is off, so variables only used for logging
+//! don't cause warnings.
+
+macro_rules! warn_ {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = ::core::format_args!($($arg)*);
+    }};
+}
+// `warn` alone would be ambiguous with the lint attribute
+pub(crate) use warn_ as warn;
+
+#[cfg(feature = "console")]
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::info!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = ::core::format_args!($($arg)*);
+    }};
+}
+#[cfg(feature = "console")]
+pub(crate) use info;
+
+#[cfg(feature = "console")]
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = ::core::format_args!($($arg)*);
+    }};
+}
+#[cfg(feature = "console")]
+pub(crate) use debug;
+
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::trace!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        let _ = ::core::format_args!($($arg)*);
+    }};
+}
+pub(crate) use trace;
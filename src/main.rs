@@ -1,3 +1,3 @@
 fn main() {
     // println!("{:?}", ram);
-}
\ No newline at end of file
+}
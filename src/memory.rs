@@ -1,28 +1,27 @@
-use ram::Ram;
+#[cfg(feature = "console")]
+pub mod cheats;
+#[cfg(feature = "console")]
+mod mapping;
+#[cfg(feature = "console")]
 pub mod ram;
+#[cfg(feature = "console")]
+pub mod search;
+#[cfg(all(test, feature = "console"))]
+mod tests;
 
-/// Console's memory mapping.
-/// Allows the cpu to read and write to mapped addresses
-///
-/// This struct collects references to hardware that can be mapped to memory,
-/// such as IO registers, cartridge ROMs and regular RAM.
-#[derive(Debug)]
-pub struct MemoryMapping<'a> {
-    pub ram: &'a mut Ram,
-}
+#[cfg(feature = "console")]
+pub use mapping::{MemoryMapping, PpuMemoryMapping};
 
-impl MemoryMapping<'_> {
-    pub fn load(&mut self, address: u16) -> u8 {
-        match address {
-            0x0000..0x1000 => self.ram.load(address % 0x800),
-            _ => unimplemented!(),
-        }
-    }
+/// An address space the CPU can read and write
+pub trait Memory {
+    fn load(&mut self, address: u16) -> u8;
+    fn store(&mut self, address: u16, value: u8);
 
-    pub fn store(&mut self, address: u16, value: u8) {
-        match address {
-            0x0000..0x1000 => self.ram.store(address % 0x800, value),
-            _ => unimplemented!(),
-        };
+    /// Read a byte of an instruction
+    ///
+    /// The CPU uses this instead of [`Memory::load`] for every read at the program counter,
+    /// so the memory can tell code from data.
+    fn fetch(&mut self, address: u16) -> u8 {
+        self.load(address)
     }
 }
@@ -0,0 +1,103 @@
+//! Cheats applied to CPU memory reads
+//!
+//! A cheat replaces the value read from an address, either always ("freezing" the address)
+//! or only when the actual value matches a compare value.
+
+use alloc::vec::Vec;
+
+/// Handle for removing or toggling a cheat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CheatId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    /// Only replace the value if this is what's actually in memory
+    pub compare: Option<u8>,
+}
+
+impl Cheat {
+    /// Reads from `address` always return `value`
+    pub fn freeze(address: u16, value: u8) -> Self {
+        Self {
+            address,
+            value,
+            compare: None,
+        }
+    }
+
+    /// Reads from `address` return `value` if the actual value is `compare`
+    pub fn replace(address: u16, compare: u8, value: u8) -> Self {
+        Self {
+            address,
+            value,
+            compare: Some(compare),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<(CheatId, Cheat, bool)>,
+    next_id: u32,
+    /// Lets the bus skip the list without looking at it when nothing is enabled
+    enabled_count: usize,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a cheat, it starts out enabled
+    pub fn add(&mut self, cheat: Cheat) -> CheatId {
+        let id = CheatId(self.next_id);
+        self.next_id += 1;
+        self.cheats.push((id, cheat, true));
+        self.enabled_count += 1;
+        id
+    }
+
+    pub fn remove(&mut self, id: CheatId) -> Option<Cheat> {
+        let index = self.cheats.iter().position(|&(i, ..)| i == id)?;
+        let (_, cheat, enabled) = self.cheats.remove(index);
+        self.enabled_count -= enabled as usize;
+        Some(cheat)
+    }
+
+    /// Returns `false` if there's no such cheat
+    pub fn set_enabled(&mut self, id: CheatId, enabled: bool) -> bool {
+        let Some((_, _, e)) = self.cheats.iter_mut().find(|(i, ..)| *i == id) else {
+            return false;
+        };
+        self.enabled_count = self.enabled_count - *e as usize + enabled as usize;
+        *e = enabled;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.enabled_count = 0;
+    }
+
+    /// All cheats and whether they are enabled
+    pub fn iter(&self) -> impl Iterator<Item = (CheatId, Cheat, bool)> + '_ {
+        self.cheats.iter().copied()
+    }
+
+    /// Apply the cheats to a value read from memory
+    #[inline]
+    #[must_use]
+    pub fn patch(&self, address: u16, value: u8) -> u8 {
+        if self.enabled_count == 0 {
+            return value;
+        }
+
+        self.cheats
+            .iter()
+            .filter(|&&(_, cheat, enabled)| enabled && cheat.address == address)
+            .find(|(_, cheat, _)| cheat.compare.is_none_or(|compare| compare == value))
+            .map_or(value, |(_, cheat, _)| cheat.value)
+    }
+}
@@ -0,0 +1,237 @@
+use crate::{
+    apu::Apu,
+    cartridge::Cartridge,
+    debug::{
+        breakpoints::{Access, Breakpoints, Bus},
+        cdl::{ChrUsage, PrgUsage},
+        hooks::Hooks,
+    },
+    input::ControllerPorts,
+    logging,
+    ppu::Ppu,
+};
+
+use super::{cheats::Cheats, ram::Ram, Memory};
+
+/// Console's memory mapping.
+/// Allows the cpu to read and write to mapped addresses
+///
+/// This struct collects references to hardware that can be mapped to memory,
+/// such as IO registers, cartridge ROMs and regular RAM.
+#[derive(Debug)]
+pub struct MemoryMapping<'a> {
+    pub ram: &'a mut Ram,
+    pub cheats: &'a Cheats,
+    pub ppu: &'a mut Ppu,
+    /// Nametable memory, accessed by the CPU through the PPU registers
+    pub vram: &'a mut Ram,
+    pub apu: &'a mut Apu,
+    pub ports: &'a mut ControllerPorts,
+    /// Last value on the data bus, which is what reads from unmapped addresses see
+    pub open_bus: &'a mut u8,
+    pub cartridge: &'a mut Cartridge,
+    /// Accesses are reported to the read and write breakpoints
+    pub breakpoints: &'a mut Breakpoints,
+    /// Writes are reported to the hooks
+    pub hooks: &'a mut Hooks,
+}
+
+impl Memory for MemoryMapping<'_> {
+    #[inline]
+    fn load(&mut self, address: u16) -> u8 {
+        // most accesses are to the internal RAM, which is never PRG ROM
+        if address < 0x2000 {
+            return self.read_ram(address);
+        }
+        let value = self.read(address);
+        self.cartridge.log_prg(address, PrgUsage::DATA);
+        value
+    }
+
+    #[inline]
+    fn fetch(&mut self, address: u16) -> u8 {
+        if address < 0x2000 {
+            return self.read_ram(address);
+        }
+        let value = self.read(address);
+        self.cartridge.log_prg(address, PrgUsage::CODE);
+        value
+    }
+
+    #[inline]
+    fn store(&mut self, address: u16, value: u8) {
+        *self.open_bus = value;
+        self.hooks.record_write(address, value);
+        if self.breakpoints.watches_memory() {
+            self.breakpoints
+                .record_access(Bus::Cpu, address, Access::WRITE);
+        }
+        if address < 0x2000 {
+            self.ram.store(address & 0x7FF, value);
+            return;
+        }
+        self.store_slow(address, value);
+    }
+}
+
+impl MemoryMapping<'_> {
+    /// Read from the internal RAM or its mirrors
+    #[inline(always)]
+    fn read_ram(&mut self, address: u16) -> u8 {
+        let value = self.cheats.patch(address, self.ram.load(address & 0x7FF));
+        *self.open_bus = value;
+        if self.breakpoints.watches_memory() {
+            self.breakpoints
+                .record_access(Bus::Cpu, address, Access::READ);
+        }
+        value
+    }
+
+    /// Write to anything but the internal RAM
+    fn store_slow(&mut self, address: u16, value: u8) {
+        match address {
+            0x2000..0x4000 => {
+                self.catch_up_ppu();
+                if self.breakpoints.watches_memory() {
+                    self.record_ppu_access(address, Access::WRITE);
+                }
+                let mut ppu_memory = PpuMemoryMapping {
+                    vram: self.vram,
+                    cartridge: self.cartridge,
+                };
+                self.ppu.store_register(address, value, &mut ppu_memory);
+            }
+            0x4014 => self.ppu.request_oam_dma(value),
+            // the strobe goes to both ports, while writes to $4017 go to the APU frame counter
+            0x4016 => {
+                // autofire depends on the frame number
+                self.catch_up_ppu();
+                self.ports.write_strobe(value, self.ppu);
+                self.cartridge.write_outputs(value);
+            }
+            0x4000..0x4018 => self.apu.store_register(address, value),
+            0x4018..0x4020 => {
+                logging::debug!(
+                    "ignored write of ${value:02X} to CPU test register ${address:04X}"
+                );
+            }
+            _ => {
+                // the cartridge might change what the PPU sees
+                self.catch_up_ppu();
+                self.cartridge.cpu_store(address, value);
+            }
+        };
+    }
+
+    /// Read from anything but the internal RAM
+    fn read(&mut self, address: u16) -> u8 {
+        let value = match address {
+            0x2000..0x4000 => {
+                self.catch_up_ppu();
+                if self.breakpoints.watches_memory() {
+                    self.record_ppu_access(address, Access::READ);
+                }
+                let mut ppu_memory = PpuMemoryMapping {
+                    vram: self.vram,
+                    cartridge: self.cartridge,
+                };
+                self.ppu.load_register(address, &mut ppu_memory)
+            }
+            0x4015 => self.apu.load_status() | *self.open_bus & 0x20,
+            0x4016 | 0x4017 => {
+                // the Zapper looks at the picture
+                self.catch_up_ppu();
+                let index = address as usize - 0x4016;
+                let port = self.ports.read(index, self.ppu);
+                match self.cartridge.read_inputs(index) {
+                    // the VS System drives the whole bus, with the controllers only on bit 0
+                    Some(inputs) => port & 1 | inputs,
+                    // only the low 5 bits are connected to the ports,
+                    // usually the top ones are left over from the $40 of the address
+                    None => port & 0x1F | *self.open_bus & 0xE0,
+                }
+            }
+            // write-only registers
+            0x4000..0x4020 => *self.open_bus,
+            0x0000..0x2000 => unreachable!(),
+            _ => self.cartridge.cpu_load(address).unwrap_or(*self.open_bus),
+        };
+        let value = self.cheats.patch(address, value);
+        *self.open_bus = value;
+        if self.breakpoints.watches_memory() {
+            self.breakpoints
+                .record_access(Bus::Cpu, address, Access::READ);
+        }
+        value
+    }
+
+    /// Report accesses to VRAM and OAM made through the PPU registers
+    fn record_ppu_access(&mut self, address: u16, access: Access) {
+        match address & 7 {
+            4 => {
+                let oam_address = self.ppu.oam_address as u16;
+                self.breakpoints
+                    .record_access(Bus::Oam, oam_address, access);
+            }
+            7 => {
+                let vram_address = self.ppu.vram_address() & 0x3FFF;
+                self.breakpoints
+                    .record_access(Bus::Vram, vram_address, access);
+            }
+            _ => {}
+        }
+    }
+
+    fn catch_up_ppu(&mut self) {
+        let mut ppu_memory = PpuMemoryMapping {
+            vram: self.vram,
+            cartridge: self.cartridge,
+        };
+        self.ppu.catch_up(&mut ppu_memory);
+    }
+}
+
+/// Memory mapping of the PPU address space
+///
+/// Pattern tables are on the cartridge, nametables are in the console's VRAM,
+/// unless the cartridge decides otherwise.
+#[derive(Debug)]
+pub struct PpuMemoryMapping<'a> {
+    pub vram: &'a mut Ram,
+    pub cartridge: &'a mut Cartridge,
+}
+
+impl PpuMemoryMapping<'_> {
+    pub fn load(&mut self, address: u16) -> u8 {
+        match address & 0x3FFF {
+            address @ 0x0000..0x2000 => {
+                self.cartridge.log_chr(address, ChrUsage::DRAWN);
+                self.cartridge.ppu_load(address)
+            }
+            // palette RAM at $3F00-$3FFF is inside the PPU itself, the bus has the nametables under it
+            address => match self.cartridge.nametable_address(address) {
+                Some(vram_address) => self.vram.load(vram_address),
+                None => self.cartridge.ppu_load(address),
+            },
+        }
+    }
+
+    /// Same as [`PpuMemoryMapping::load`], for reads the CPU makes through $2007
+    pub fn read_data(&mut self, address: u16) -> u8 {
+        if address & 0x3FFF < 0x2000 {
+            self.cartridge.log_chr(address & 0x3FFF, ChrUsage::READ);
+            return self.cartridge.ppu_load(address & 0x3FFF);
+        }
+        self.load(address)
+    }
+
+    pub fn store(&mut self, address: u16, value: u8) {
+        match address & 0x3FFF {
+            address @ 0x0000..0x2000 => self.cartridge.ppu_store(address, value),
+            address => match self.cartridge.nametable_address(address) {
+                Some(vram_address) => self.vram.store(vram_address, value),
+                None => self.cartridge.ppu_store(address, value),
+            },
+        }
+    }
+}
@@ -1,10 +1,61 @@
-use std::{
+use alloc::boxed::Box;
+
+use crate::state::impl_state;
+use core::{
     fmt::{Debug, Formatter},
     ops::{Index, IndexMut},
 };
 
 const RAM_SIZE: usize = 2048;
 
+/// Contents of RAM right after power on
+///
+/// The console fills CPU RAM, nametable RAM, OAM and the palette with it.
+/// Real RAM chips come up with garbage that differs between consoles,
+/// and some games accidentally depend on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RamPattern {
+    #[default]
+    Zeros,
+    Ones,
+    /// Four bytes of $00 followed by four bytes of $FF, like FCEUX
+    Alternating,
+    /// A 256 byte page of $00 followed by a page of $FF
+    AlternatingPages,
+    /// Pseudo-random bytes generated from a seed, so they're the same on every run
+    Random(u64),
+}
+
+impl RamPattern {
+    /// Fill a memory with the pattern, starting from its first byte
+    pub fn fill(self, memory: &mut [u8]) {
+        match self {
+            RamPattern::Zeros => memory.fill(0x00),
+            RamPattern::Ones => memory.fill(0xFF),
+            RamPattern::Alternating => {
+                for (i, byte) in memory.iter_mut().enumerate() {
+                    *byte = if i & 4 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            RamPattern::AlternatingPages => {
+                for (i, byte) in memory.iter_mut().enumerate() {
+                    *byte = if i & 0x100 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            RamPattern::Random(seed) => {
+                // xorshift64, which gets stuck at 0
+                let mut state = seed.max(1);
+                for byte in memory.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *byte = (state >> 32) as u8;
+                }
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Ram {
     buf: Box<[u8; RAM_SIZE]>,
@@ -17,6 +68,13 @@ impl Ram {
         }
     }
 
+    /// RAM filled according to the pattern
+    pub fn with_pattern(pattern: RamPattern) -> Self {
+        let mut ram = Self::new();
+        pattern.fill(&mut ram.buf[..]);
+        ram
+    }
+
     #[must_use]
     pub fn load(&self, addr: u16) -> u8 {
         self[addr]
@@ -26,6 +84,14 @@ impl Ram {
     pub fn store(&mut self, addr: u16, value: u8) {
         self[addr] = value;
     }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[..]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[..]
+    }
 }
 
 impl Index<u16> for Ram {
@@ -42,7 +108,7 @@ impl IndexMut<u16> for Ram {
 }
 
 impl Debug for Ram {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let write_slice = |f: &mut Formatter, slice: &[u8]| {
             for (i, n) in slice.iter().copied().enumerate() {
                 write!(f, "{n:0>2X}{}", if i == slice.len() - 1 { "" } else { " " })?
@@ -71,3 +137,5 @@ impl Default for Ram {
         Self::new()
     }
 }
+
+impl_state!(Ram { buf });
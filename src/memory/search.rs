@@ -0,0 +1,169 @@
+//! Searching RAM for the bytes that hold a value, to find cheats
+//!
+//! A [`RamSearch`] starts with every byte of CPU RAM and PRG RAM as a candidate and a snapshot
+//! of their values. Every [`RamSearch::filter`] keeps the candidates whose value compares
+//! with the snapshot or with a given value, then takes a new snapshot, so that running the game
+//! between filters narrows the search down: "the lives went down", "it didn't change", "it's 3".
+
+use alloc::vec::Vec;
+
+use crate::nes::Nes;
+
+/// Size of the CPU RAM, the candidates after it are in PRG RAM
+const RAM_SIZE: usize = 0x800;
+
+/// Where a byte that's searched is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SearchAddress {
+    /// An address of CPU RAM, $0000-$07FF
+    Ram(u16),
+    /// An offset into the cartridge's PRG RAM, usually seen at $6000-$7FFF
+    PrgRam(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn matches(self, value: u8, other: u8) -> bool {
+        match self {
+            Comparison::Equal => value == other,
+            Comparison::NotEqual => value != other,
+            Comparison::Greater => value > other,
+            Comparison::GreaterOrEqual => value >= other,
+            Comparison::Less => value < other,
+            Comparison::LessOrEqual => value <= other,
+        }
+    }
+}
+
+/// What the current values are compared with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// The values in the snapshot, so [`Comparison::NotEqual`] keeps the bytes that changed
+    Previous,
+    Value(u8),
+}
+
+/// A candidate of a search
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchResult {
+    pub address: SearchAddress,
+    pub value: u8,
+    /// The value in the snapshot
+    pub previous: u8,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RamSearch {
+    /// Values of CPU RAM then PRG RAM at the last snapshot
+    snapshot: Vec<u8>,
+    /// Indices into the snapshot of the bytes that still match, in order
+    candidates: Vec<u32>,
+    /// The candidates before each filter, for undoing them
+    history: Vec<Vec<u32>>,
+}
+
+fn memory(nes: &Nes) -> Vec<u8> {
+    let mut memory = nes.ram().as_slice().to_vec();
+    if let Some(cartridge) = nes.cartridge() {
+        memory.extend_from_slice(cartridge.prg_ram());
+    }
+    memory
+}
+
+impl RamSearch {
+    /// Start a search with every byte as a candidate
+    pub fn new(nes: &Nes) -> Self {
+        let mut search = Self::default();
+        search.reset(nes);
+        search
+    }
+
+    /// Make every byte a candidate again and take a snapshot
+    pub fn reset(&mut self, nes: &Nes) {
+        self.snapshot = memory(nes);
+        self.candidates = (0..self.snapshot.len() as u32).collect();
+        self.history.clear();
+    }
+
+    /// Take a snapshot of the values without filtering
+    pub fn snapshot(&mut self, nes: &Nes) {
+        self.snapshot = memory(nes);
+    }
+
+    /// Keep the candidates whose value compares with `operand`, then take a snapshot
+    ///
+    /// Returns the number of candidates left.
+    pub fn filter(&mut self, nes: &Nes, comparison: Comparison, operand: Operand) -> usize {
+        let memory = memory(nes);
+        let kept = self
+            .candidates
+            .iter()
+            .copied()
+            .filter(|&index| {
+                let index = index as usize;
+                let (Some(&value), Some(&previous)) = (memory.get(index), self.snapshot.get(index))
+                else {
+                    // the cartridge changed
+                    return false;
+                };
+                let other = match operand {
+                    Operand::Previous => previous,
+                    Operand::Value(other) => other,
+                };
+                comparison.matches(value, other)
+            })
+            .collect();
+        self.history
+            .push(core::mem::replace(&mut self.candidates, kept));
+        self.snapshot = memory;
+        self.candidates.len()
+    }
+
+    /// Bring back the candidates from before the last filter, the snapshot stays the newest one
+    ///
+    /// Returns `false` if there was no filter to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.pop() {
+            Some(candidates) => {
+                self.candidates = candidates;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of candidates
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// The candidates with their current values, in address order, CPU RAM first
+    pub fn results<'a>(&'a self, nes: &Nes) -> impl Iterator<Item = SearchResult> + 'a {
+        let memory = memory(nes);
+        self.candidates.iter().filter_map(move |&index| {
+            let index = index as usize;
+            let address = match index.checked_sub(RAM_SIZE) {
+                None => SearchAddress::Ram(index as u16),
+                Some(offset) => SearchAddress::PrgRam(offset),
+            };
+            Some(SearchResult {
+                address,
+                value: *memory.get(index)?,
+                previous: self.snapshot[index],
+            })
+        })
+    }
+}
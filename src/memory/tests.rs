@@ -0,0 +1,192 @@
+use crate::{
+    apu::Apu,
+    cartridge::{tests::nrom_image, Cartridge},
+    debug::{breakpoints::Breakpoints, hooks::Hooks},
+    input::{ButtonState, ControllerPorts},
+    nes::Nes,
+    ppu::Ppu,
+};
+
+use super::{
+    cheats::{Cheat, Cheats},
+    ram::Ram,
+    search::{Comparison, Operand, RamSearch, SearchAddress, SearchResult},
+    Memory, MemoryMapping, PpuMemoryMapping,
+};
+
+/// PRG ROM filled with the low byte of the address, no CHR ROM
+fn nrom() -> Cartridge {
+    let prg: Vec<u8> = (0..0x8000).map(|i| i as u8).collect();
+    Cartridge::from_ines(&nrom_image(&prg, 0, 0)).unwrap()
+}
+
+#[test]
+fn cheats() {
+    let mut ram = Ram::new();
+    let mut cheats = Cheats::new();
+    ram.store(0x10, 0x01);
+    ram.store(0x20, 0x02);
+
+    let freeze = cheats.add(Cheat::freeze(0x10, 0x09));
+    let replace = cheats.add(Cheat::replace(0x20, 0x03, 0x63));
+
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut apu = Apu::new();
+    let mut ports = ControllerPorts::new();
+    let mut open_bus = 0;
+    let mut breakpoints = Breakpoints::new();
+    let mut hooks = Hooks::new();
+    let mut cartridge = nrom();
+    let mut memory = MemoryMapping {
+        ram: &mut ram,
+        cheats: &cheats,
+        ppu: &mut ppu,
+        vram: &mut vram,
+        apu: &mut apu,
+        ports: &mut ports,
+        open_bus: &mut open_bus,
+        breakpoints: &mut breakpoints,
+        hooks: &mut hooks,
+        cartridge: &mut cartridge,
+    };
+    assert_eq!(memory.load(0x10), 0x09);
+    // also applies to the RAM mirrors
+    assert_eq!(memory.load(0x810), 0x01);
+    // compare value doesn't match
+    assert_eq!(memory.load(0x20), 0x02);
+    memory.store(0x20, 0x03);
+    assert_eq!(memory.load(0x20), 0x63);
+
+    assert!(cheats.set_enabled(freeze, false));
+    assert_eq!(cheats.patch(0x10, 0x01), 0x01);
+    assert!(cheats.set_enabled(freeze, true));
+    assert_eq!(cheats.patch(0x10, 0x01), 0x09);
+
+    assert_eq!(
+        cheats.remove(replace),
+        Some(Cheat::replace(0x20, 0x03, 0x63))
+    );
+    assert_eq!(cheats.remove(replace), None);
+    assert!(!cheats.set_enabled(replace, true));
+    assert_eq!(cheats.patch(0x20, 0x03), 0x03);
+    assert_eq!(cheats.iter().count(), 1);
+}
+
+#[test]
+fn cpu_memory_map() {
+    let mut ram = Ram::new();
+    let cheats = Cheats::new();
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut apu = Apu::new();
+    let mut ports = ControllerPorts::new();
+    let mut open_bus = 0;
+    let mut breakpoints = Breakpoints::new();
+    let mut hooks = Hooks::new();
+    let mut cartridge = nrom();
+    let mut memory = MemoryMapping {
+        ram: &mut ram,
+        cheats: &cheats,
+        ppu: &mut ppu,
+        vram: &mut vram,
+        apu: &mut apu,
+        ports: &mut ports,
+        open_bus: &mut open_bus,
+        breakpoints: &mut breakpoints,
+        hooks: &mut hooks,
+        cartridge: &mut cartridge,
+    };
+
+    memory.store(0x1801, 0x42);
+    assert_eq!(memory.load(0x0001), 0x42);
+    assert_eq!(memory.load(0x8012), 0x12);
+    assert_eq!(memory.load(0xC012), 0x12);
+
+    // write to the nametable through PPUADDR/PPUDATA, mirrored every 8 bytes
+    memory.store(0x2006, 0x20);
+    memory.store(0x3FFE, 0x05);
+    memory.store(0x2007, 0x99);
+    memory.store(0x4014, 0x02);
+    assert_eq!(memory.ppu.take_oam_dma_request(), Some(0x02));
+
+    for (device, buttons) in memory
+        .ports
+        .devices
+        .iter_mut()
+        .zip([ButtonState::B, ButtonState::A])
+    {
+        device.as_controller_mut().unwrap().set_buttons(buttons);
+    }
+    memory.store(0x4016, 1);
+    memory.store(0x4016, 0);
+    assert_eq!(memory.load(0x4016), 0);
+    assert_eq!(memory.load(0x4016), 1);
+    assert_eq!(memory.load(0x4017), 1);
+    // $4017 writes go to the APU, not the controllers
+    memory.store(0x4017, 0x01);
+    assert_eq!(memory.load(0x4017), 0);
+    assert_eq!(memory.load(0x4016), 0);
+
+    // the top bits of the controller ports and unmapped addresses are open bus
+    assert_eq!(memory.load(0x8040), 0x40);
+    assert_eq!(memory.load(0x4016), 0x40);
+    assert_eq!(memory.load(0x80E5), 0xE5);
+    assert_eq!(memory.load(0x4018), 0xE5);
+    assert_eq!(memory.load(0x4015) & 0x20, 0x20);
+
+    let mut ppu_memory = PpuMemoryMapping {
+        vram: memory.vram,
+        cartridge: memory.cartridge,
+    };
+    assert_eq!(ppu_memory.load(0x2005), 0x99);
+    // horizontal mirroring
+    assert_eq!(ppu_memory.load(0x2405), 0x99);
+    assert_eq!(ppu_memory.load(0x2805), 0x00);
+}
+
+#[test]
+fn ram_search() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(nrom());
+    let mut search = RamSearch::new(&nes);
+    let prg_ram_size = nes.cartridge().unwrap().prg_ram().len();
+    assert_eq!(search.len(), 0x800 + prg_ram_size);
+
+    // the lives at $0020 go from 3 to 2, and the cartridge keeps a counter in PRG RAM
+    nes.ram_mut().store(0x20, 3);
+    nes.ram_mut().store(0x30, 3);
+    assert_eq!(search.filter(&nes, Comparison::Equal, Operand::Value(3)), 2);
+    nes.ram_mut().store(0x20, 2);
+    nes.ram_mut().store(0x30, 4);
+    assert_eq!(search.filter(&nes, Comparison::Less, Operand::Previous), 1);
+    let results: Vec<_> = search.results(&nes).collect();
+    assert_eq!(
+        results,
+        [SearchResult {
+            address: SearchAddress::Ram(0x20),
+            value: 2,
+            previous: 2,
+        }]
+    );
+
+    assert!(search.undo());
+    assert_eq!(search.len(), 2);
+    assert!(search.undo());
+    assert!(!search.undo());
+
+    search.reset(&nes);
+    nes.cartridge_mut().unwrap().prg_ram_mut()[5] = 1;
+    assert_eq!(
+        search.filter(&nes, Comparison::NotEqual, Operand::Previous),
+        1
+    );
+    let result = search.results(&nes).next().unwrap();
+    assert_eq!(
+        (result.address, result.value),
+        (SearchAddress::PrgRam(5), 1)
+    );
+    search.snapshot(&nes);
+    assert_eq!(search.filter(&nes, Comparison::Equal, Operand::Previous), 1);
+    assert!(!search.is_empty());
+}
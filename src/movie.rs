@@ -0,0 +1,119 @@
+//! Input movies
+//!
+//! A [`Movie`] is the controller input of every frame, plus console events like resets,
+//! starting either from power on or from a save state.
+//! Since the emulation is deterministic, playing it back reproduces the same run exactly.
+//!
+//! Movies can be exchanged with other emulators as FCEUX `.fm2` files, see [`Movie::from_fm2`],
+//! and BizHawk `.bk2` files can be imported with [`Movie::from_bk2`].
+
+use alloc::{string::String, vec::Vec};
+
+use crate::{
+    clock::Region,
+    error::Error,
+    input::log::{InputFrame, InputLog},
+    nes::Nes,
+    state::StateError,
+};
+
+mod bk2;
+mod fm2;
+#[cfg(test)]
+mod tests;
+
+pub use bk2::Bk2Error;
+pub use fm2::Fm2Error;
+
+/// Where playback of a movie starts from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MovieStart {
+    #[default]
+    PowerOn,
+    /// A state made by [`Nes::save_state`]
+    SaveState(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub start: MovieStart,
+    pub input: InputLog,
+
+    /// Times the recording was rewound and continued from an earlier point
+    pub rerecord_count: u32,
+    pub pal: bool,
+    pub rom_filename: String,
+    /// Checksum as written in the movie file, FCEUX uses a base64 encoded MD5 of the ROM
+    /// and BizHawk a hexadecimal SHA1
+    pub rom_checksum: String,
+    pub guid: String,
+    pub comments: Vec<String>,
+}
+
+impl Movie {
+    /// Start an empty movie that plays back from the console's current state
+    pub fn record_from(nes: &Nes) -> Result<Self, StateError> {
+        Ok(Self {
+            start: MovieStart::SaveState(nes.save_state()?),
+            pal: nes.region() == Region::Pal,
+            ..Self::default()
+        })
+    }
+
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        self.input.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Append the input of the next frame
+    pub fn record_frame(&mut self, frame: InputFrame) {
+        self.input.record(frame);
+    }
+
+    /// Drop every frame starting with `frame`, to continue recording from there
+    pub fn truncate(&mut self, frame: usize) {
+        if frame < self.input.len() {
+            self.input.truncate(frame);
+            self.rerecord_count += 1;
+        }
+    }
+
+    /// Put the console into the state the movie starts from
+    ///
+    /// A movie recorded on a PAL console can't be played back on an NTSC or Dendy one and the other way around,
+    /// the console is still started then, but returns [`Error::RegionMismatch`].
+    /// Forcing the right region with [`Nes::set_region`] and starting again fixes it.
+    pub fn start_playback(&self, nes: &mut Nes) -> Result<(), Error> {
+        match &self.start {
+            MovieStart::PowerOn => nes.power_cycle(),
+            MovieStart::SaveState(state) => nes.load_state(state)?,
+        }
+        if self.pal != (nes.region() == Region::Pal) {
+            return Err(Error::RegionMismatch {
+                expected: self.region(),
+                found: nes.region(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The region the movie was recorded in, FCEUX and BizHawk movies don't tell NTSC and Dendy apart
+    pub fn region(&self) -> Region {
+        if self.pal {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    /// Apply the events and input of the given frame and run it
+    ///
+    /// Returns `false` without running anything past the end of the movie.
+    pub fn play_frame(&self, index: usize, nes: &mut Nes) -> bool {
+        self.input.replay_frame(index, nes)
+    }
+}
@@ -0,0 +1,220 @@
+//! BizHawk movie files
+//!
+//! A `.bk2` is a ZIP archive, of which only two text files matter here.
+//! `Header.txt` has `key value` lines, and `Input Log.txt` has a `LogKey:` line naming the
+//! buttons, then a line per frame between `[Input]` and `[/Input]` like `|..|UDLRSsBA|........|`:
+//! the console buttons, then the buttons of each controller, with `.` for released buttons.
+//! Only NES movies with standard controllers that start from power on are supported.
+//!
+//! Details at https://tasvideos.org/Bizhawk/BK2Format
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Display, Formatter};
+
+use crate::{
+    input::{
+        log::{ConsoleEvents, InputFrame},
+        ButtonState,
+    },
+    zip::{self, ZipError, ZipFile},
+};
+
+use super::Movie;
+
+const HEADER: &str = "Header.txt";
+const INPUT_LOG: &str = "Input Log.txt";
+const COMMENTS: &str = "Comments.txt";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bk2Error {
+    /// The archive couldn't be read
+    Archive(ZipError),
+    /// A file the movie needs isn't in the archive, or isn't text
+    MissingFile(&'static str),
+    /// The movie was recorded for another console
+    UnsupportedPlatform(String),
+    /// Starting from a save state or SRAM, or devices other than standard controllers
+    UnsupportedFeature(String),
+    /// The line of the input log with this (1-based) number couldn't be parsed
+    InvalidLine(usize),
+}
+
+impl Display for Bk2Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Bk2Error::Archive(error) => write!(f, "invalid BK2 archive: {error}"),
+            Bk2Error::MissingFile(name) => write!(f, "missing BK2 file {name}"),
+            Bk2Error::UnsupportedPlatform(platform) => {
+                write!(f, "unsupported BK2 platform {platform}")
+            }
+            Bk2Error::UnsupportedFeature(feature) => {
+                write!(f, "unsupported BK2 feature: {feature}")
+            }
+            Bk2Error::InvalidLine(line) => write!(f, "invalid BK2 input log line {line}"),
+        }
+    }
+}
+
+impl core::error::Error for Bk2Error {}
+
+impl From<ZipError> for Bk2Error {
+    fn from(error: ZipError) -> Self {
+        Bk2Error::Archive(error)
+    }
+}
+
+/// What a button of the log key presses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Button {
+    Console(ConsoleEvents),
+    Player(usize, ButtonState),
+}
+
+impl Button {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "Reset" => return Some(Button::Console(ConsoleEvents::SOFT_RESET)),
+            "Power" => return Some(Button::Console(ConsoleEvents::POWER)),
+            _ => {}
+        }
+        let (player, button) = name.strip_prefix('P')?.split_once(' ')?;
+        let player = match player.parse() {
+            Ok(player @ 1..=4) => player - 1,
+            _ => return None,
+        };
+        let button = match button {
+            "Up" => ButtonState::UP,
+            "Down" => ButtonState::DOWN,
+            "Left" => ButtonState::LEFT,
+            "Right" => ButtonState::RIGHT,
+            "Start" => ButtonState::START,
+            "Select" => ButtonState::SELECT,
+            "B" => ButtonState::B,
+            "A" => ButtonState::A,
+            _ => return None,
+        };
+        Some(Button::Player(player, button))
+    }
+}
+
+impl Movie {
+    /// Read a BizHawk `.bk2` movie
+    ///
+    /// The ROM checksum is the SHA1 from the header, in hexadecimal.
+    pub fn from_bk2(archive: &[u8]) -> Result<Self, Bk2Error> {
+        let files = zip::files(archive)?;
+        let header = read_text(&files, HEADER)?.ok_or(Bk2Error::MissingFile(HEADER))?;
+        let input_log = read_text(&files, INPUT_LOG)?.ok_or(Bk2Error::MissingFile(INPUT_LOG))?;
+
+        let mut movie = Movie::default();
+        parse_header(&mut movie, &header)?;
+        parse_input_log(&mut movie, &input_log)?;
+        if let Some(comments) = read_text(&files, COMMENTS)? {
+            let comments = comments.lines().filter(|line| !line.trim().is_empty());
+            movie.comments.extend(comments.map(str::to_string));
+        }
+        Ok(movie)
+    }
+}
+
+fn read_text(files: &[ZipFile], name: &'static str) -> Result<Option<String>, Bk2Error> {
+    let Some(file) = files.iter().find(|file| file.name == name.as_bytes()) else {
+        return Ok(None);
+    };
+    String::from_utf8(file.read()?)
+        .map(Some)
+        .map_err(|_| Bk2Error::MissingFile(name))
+}
+
+fn parse_header(movie: &mut Movie, text: &str) -> Result<(), Bk2Error> {
+    for line in text.lines() {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+        let enabled = value == "1" || value.eq_ignore_ascii_case("true");
+        match key {
+            "Platform" if value != "NES" => {
+                return Err(Bk2Error::UnsupportedPlatform(value.to_string()))
+            }
+            // BizHawk doesn't count badly formatted numbers either
+            "rerecordCount" => movie.rerecord_count = value.parse().unwrap_or(0),
+            "PAL" => movie.pal = enabled,
+            "GameName" => movie.rom_filename = value.to_string(),
+            "SHA1" => movie.rom_checksum = value.to_string(),
+            "Author" if !value.is_empty() => movie.comments.push(format!("author {value}")),
+            "StartsFromSavestate" | "StartsFromSaveRam" if enabled => {
+                return Err(Bk2Error::UnsupportedFeature(key.to_string()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_input_log(movie: &mut Movie, text: &str) -> Result<(), Bk2Error> {
+    // the buttons of each field of a frame line
+    let mut fields: Vec<Vec<Button>> = Vec::new();
+    let mut in_input = false;
+    for (index, line) in text.lines().enumerate() {
+        let invalid = || Bk2Error::InvalidLine(index + 1);
+        let line = line.trim_end();
+        match line {
+            "[Input]" => in_input = true,
+            "[/Input]" => break,
+            _ if !in_input => {}
+            _ if line.starts_with("LogKey:") => {
+                fields = parse_log_key(&line["LogKey:".len()..])?;
+            }
+            _ if line.starts_with('|') => {
+                movie
+                    .input
+                    .record(parse_frame(line, &fields).ok_or_else(invalid)?);
+            }
+            "" => {}
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(())
+}
+
+/// Parse a log key like `#Reset|Power|#P1 Up|P1 Down|...|`, every `#` starts a field
+fn parse_log_key(key: &str) -> Result<Vec<Vec<Button>>, Bk2Error> {
+    key.split('#')
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            field
+                .split('|')
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    Button::parse(name)
+                        .ok_or_else(|| Bk2Error::UnsupportedFeature(name.to_string()))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn parse_frame(line: &str, fields: &[Vec<Button>]) -> Option<InputFrame> {
+    let line = line.strip_prefix('|')?.strip_suffix('|')?;
+    let mut frame = InputFrame::default();
+    let mut values = line.split('|');
+    for buttons in fields {
+        let value = values.next()?.as_bytes();
+        if value.len() != buttons.len() {
+            return None;
+        }
+        for (&c, &button) in value.iter().zip(buttons) {
+            if c == b'.' || c == b' ' {
+                continue;
+            }
+            match button {
+                Button::Console(events) => frame.events |= events,
+                Button::Player(player, buttons) => frame.buttons[player] |= buttons,
+            }
+        }
+    }
+    values.next().is_none().then_some(frame)
+}
@@ -0,0 +1,204 @@
+//! FCEUX movie files
+//!
+//! A text header of `key value` lines, followed by a line per frame like
+//! `|0|RLDUTSBA|........||`: the commands, then the buttons of each port, with `.` for released buttons.
+//! Only the text format with standard controllers is supported.
+//!
+//! FCEUX save state anchors can't be used, the `savestate` key holds a save state of this emulator.
+//!
+//! Details at https://fceux.com/web/help/fm2.html
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{Display, Formatter, Write};
+
+use crate::input::{
+    log::{ConsoleEvents, InputFrame},
+    ButtonState,
+};
+
+use super::{Movie, MovieStart};
+
+/// Button letters in the order they're written
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fm2Error {
+    /// The file doesn't start with a `version 3` line
+    UnsupportedVersion,
+    /// Binary input, the Four Score or devices other than standard controllers
+    UnsupportedFeature(String),
+    /// The line with this (1-based) number couldn't be parsed
+    InvalidLine(usize),
+}
+
+impl Display for Fm2Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Fm2Error::UnsupportedVersion => write!(f, "unsupported FM2 version"),
+            Fm2Error::UnsupportedFeature(feature) => {
+                write!(f, "unsupported FM2 feature: {feature}")
+            }
+            Fm2Error::InvalidLine(line) => write!(f, "invalid FM2 line {line}"),
+        }
+    }
+}
+
+impl core::error::Error for Fm2Error {}
+
+impl Movie {
+    /// Parse an FCEUX `.fm2` movie
+    pub fn from_fm2(text: &str) -> Result<Self, Fm2Error> {
+        let mut movie = Movie::default();
+        let mut lines = text.lines().enumerate();
+
+        match lines.next() {
+            Some((_, line)) if line.trim() == "version 3" => {}
+            _ => return Err(Fm2Error::UnsupportedVersion),
+        }
+
+        for (index, line) in lines {
+            let invalid = || Fm2Error::InvalidLine(index + 1);
+            if line.starts_with('|') {
+                movie.input.record(parse_frame(line).ok_or_else(invalid)?);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "rerecordCount" => movie.rerecord_count = value.parse().map_err(|_| invalid())?,
+                "palFlag" => movie.pal = value == "1",
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => movie.rom_checksum = value.to_string(),
+                "guid" => movie.guid = value.to_string(),
+                "comment" => movie.comments.push(value.to_string()),
+                "savestate" => {
+                    let state = value
+                        .strip_prefix("base64:")
+                        .and_then(decode_base64)
+                        .ok_or_else(invalid)?;
+                    movie.start = MovieStart::SaveState(state);
+                }
+                "binary" | "fourscore" if value != "0" => {
+                    return Err(Fm2Error::UnsupportedFeature(key.to_string()))
+                }
+                // 0 is nothing and 1 is a standard controller
+                "port0" | "port1" if value != "0" && value != "1" => {
+                    return Err(Fm2Error::UnsupportedFeature(format!("{key} {value}")))
+                }
+                _ => {}
+            }
+        }
+        Ok(movie)
+    }
+
+    /// Write the movie in the FCEUX `.fm2` format
+    pub fn to_fm2(&self) -> String {
+        let mut text = String::new();
+        let mut line = |key: &str, value: &dyn Display| {
+            let _ = writeln!(text, "{key} {value}");
+        };
+        line("version", &3);
+        line("emuVersion", &0);
+        line("rerecordCount", &self.rerecord_count);
+        line("palFlag", &(self.pal as u8));
+        line("romFilename", &self.rom_filename);
+        line("romChecksum", &self.rom_checksum);
+        line("guid", &self.guid);
+        line("fourscore", &0);
+        line("microphone", &0);
+        line("port0", &1);
+        line("port1", &1);
+        line("port2", &0);
+        line("FDS", &0);
+        line("NewPPU", &0);
+        for comment in &self.comments {
+            line("comment", comment);
+        }
+        if let MovieStart::SaveState(state) = &self.start {
+            line(
+                "savestate",
+                &format_args!("base64:{}", encode_base64(state)),
+            );
+        }
+        line("length", &self.input.len());
+
+        for frame in self.input.frames() {
+            let _ = write!(text, "|{}|", frame.events.bits());
+            for port in &frame.buttons[..2] {
+                for (i, &letter) in BUTTONS.iter().enumerate() {
+                    let pressed = port.bits() & (0x80 >> i) != 0;
+                    text.push(if pressed { letter as char } else { '.' });
+                }
+                text.push('|');
+            }
+            text.push_str("|\n");
+        }
+        text
+    }
+}
+
+fn parse_frame(line: &str) -> Option<InputFrame> {
+    let mut fields = line.strip_prefix('|')?.split('|');
+    let events = ConsoleEvents::from_bits_retain(fields.next()?.trim().parse().ok()?);
+    let mut buttons = [ButtonState::empty(); 4];
+    for port in &mut buttons[..2] {
+        let field = fields.next()?.as_bytes();
+        if field.is_empty() {
+            continue;
+        }
+        if field.len() != BUTTONS.len() {
+            return None;
+        }
+        let bits = field
+            .iter()
+            .enumerate()
+            .filter(|&(_, &c)| c != b'.' && c != b' ')
+            .fold(0u8, |bits, (i, _)| bits | 0x80 >> i);
+        *port = ButtonState::from_bits_retain(bits);
+    }
+    Some(InputFrame { events, buttons })
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(super) fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let block = chunk.iter().enumerate().fold(0u32, |block, (i, &byte)| {
+            block | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(block >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub(super) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim().trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut block = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE64_ALPHABET.iter().position(|&letter| letter == c)?;
+        block = block << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((block >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
@@ -0,0 +1,218 @@
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    clock::Region,
+    error::Error,
+    input::{
+        log::{ConsoleEvents, InputFrame},
+        ButtonState,
+    },
+    nes::Nes,
+};
+
+use crate::zip::tests::archive;
+
+use super::{fm2, Bk2Error, Fm2Error, Movie, MovieStart};
+
+const FM2: &str = "version 3
+emuVersion 22020
+rerecordCount 5
+palFlag 0
+romFilename Some Game
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 12345678-ABCD-EF01-2345-6789ABCDEF01
+fourscore 0
+port0 1
+port1 1
+port2 0
+comment author someone
+|0|........|........||
+|1|.......A|........||
+|0|R..UT...|......B.||
+";
+
+#[test]
+fn fm2_import() {
+    let movie = Movie::from_fm2(FM2).unwrap();
+    assert_eq!(movie.rerecord_count, 5);
+    assert_eq!(movie.rom_filename, "Some Game");
+    assert_eq!(movie.comments, ["author someone"]);
+    assert_eq!(movie.start, MovieStart::PowerOn);
+    assert_eq!(movie.len(), 3);
+    assert_eq!(
+        movie.input.frames()[1],
+        InputFrame {
+            events: ConsoleEvents::SOFT_RESET,
+            buttons: [
+                ButtonState::A,
+                ButtonState::empty(),
+                ButtonState::empty(),
+                ButtonState::empty()
+            ],
+        }
+    );
+    assert_eq!(
+        movie.input.frames()[2].buttons[..2],
+        [
+            ButtonState::RIGHT | ButtonState::UP | ButtonState::START,
+            ButtonState::B
+        ]
+    );
+}
+
+#[test]
+fn fm2_round_trip() {
+    let mut movie = Movie::from_fm2(FM2).unwrap();
+    movie.start = MovieStart::SaveState(vec![0, 1, 2, 3, 254, 255, 42]);
+    let text = movie.to_fm2();
+    assert_eq!(Movie::from_fm2(&text), Ok(movie));
+}
+
+#[test]
+fn fm2_errors() {
+    assert_eq!(
+        Movie::from_fm2("version 2\n"),
+        Err(Fm2Error::UnsupportedVersion)
+    );
+    assert_eq!(
+        Movie::from_fm2("version 3\nbinary 1\n"),
+        Err(Fm2Error::UnsupportedFeature("binary".to_string()))
+    );
+    assert_eq!(
+        Movie::from_fm2("version 3\n|0|....|........||\n"),
+        Err(Fm2Error::InvalidLine(2))
+    );
+}
+
+const BK2_HEADER: &str = "MovieVersion BizHawk v2.0.0
+Author someone
+emuVersion Version 2.9.1
+Platform NES
+GameName Some Game
+SHA1 EA343F4E445A9050D4B4FBAC2C77D0693B1D0922
+rerecordCount 12
+Core NesHawk
+";
+
+const BK2_INPUT_LOG: &str = "[Input]
+LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|
+|..|........|........|
+|r.|.......A|........|
+|..|U..RS...|......B.|
+[/Input]
+";
+
+#[test]
+fn bk2_import() {
+    let bk2 = archive(&[
+        ("Header.txt", BK2_HEADER.as_bytes()),
+        ("Comments.txt", b"a comment\n"),
+        ("Input Log.txt", BK2_INPUT_LOG.as_bytes()),
+    ]);
+    let movie = Movie::from_bk2(&bk2).unwrap();
+    assert_eq!(movie.rerecord_count, 12);
+    assert_eq!(movie.rom_filename, "Some Game");
+    assert_eq!(
+        movie.rom_checksum,
+        "EA343F4E445A9050D4B4FBAC2C77D0693B1D0922"
+    );
+    assert_eq!(movie.comments, ["author someone", "a comment"]);
+    assert!(!movie.pal);
+    assert_eq!(movie.len(), 3);
+    assert_eq!(
+        movie.input.frames()[1],
+        InputFrame {
+            events: ConsoleEvents::SOFT_RESET,
+            buttons: [
+                ButtonState::A,
+                ButtonState::empty(),
+                ButtonState::empty(),
+                ButtonState::empty()
+            ],
+        }
+    );
+    assert_eq!(
+        movie.input.frames()[2].buttons[..2],
+        [
+            ButtonState::UP | ButtonState::RIGHT | ButtonState::START,
+            ButtonState::B
+        ]
+    );
+}
+
+#[test]
+fn bk2_errors() {
+    let bk2 = |header: &str, input_log: &str| {
+        Movie::from_bk2(&archive(&[
+            ("Header.txt", header.as_bytes()),
+            ("Input Log.txt", input_log.as_bytes()),
+        ]))
+    };
+    assert_eq!(
+        Movie::from_bk2(&archive(&[("Header.txt", b"Platform NES\n")])),
+        Err(Bk2Error::MissingFile("Input Log.txt"))
+    );
+    assert_eq!(
+        bk2("Platform SNES\n", BK2_INPUT_LOG),
+        Err(Bk2Error::UnsupportedPlatform("SNES".to_string()))
+    );
+    assert_eq!(
+        bk2("Platform NES\nStartsFromSavestate True\n", BK2_INPUT_LOG),
+        Err(Bk2Error::UnsupportedFeature(
+            "StartsFromSavestate".to_string()
+        ))
+    );
+    assert_eq!(
+        bk2(
+            BK2_HEADER,
+            "[Input]\nLogKey:#Reset|Power|#P1 Fire|P1 Paddle|\n[/Input]\n"
+        ),
+        Err(Bk2Error::UnsupportedFeature("P1 Fire".to_string()))
+    );
+    assert_eq!(
+        bk2(BK2_HEADER, &BK2_INPUT_LOG.replace("|r.|", "|r|")),
+        Err(Bk2Error::InvalidLine(4))
+    );
+}
+
+#[test]
+fn base64() {
+    for bytes in [&b""[..], b"a", b"ab", b"abc", b"abcd", &[0xFF, 0x00, 0x80]] {
+        let encoded = fm2::encode_base64(bytes);
+        assert_eq!(encoded.len() % 4, 0);
+        assert_eq!(fm2::decode_base64(&encoded).as_deref(), Some(bytes));
+    }
+    assert_eq!(fm2::encode_base64(b"Man"), "TWFu");
+}
+
+#[test]
+fn truncate() {
+    let mut movie = Movie::default();
+    (0..10).for_each(|_| movie.record_frame(InputFrame::default()));
+    movie.truncate(20);
+    assert_eq!((movie.len(), movie.rerecord_count), (10, 0));
+    movie.truncate(4);
+    assert_eq!((movie.len(), movie.rerecord_count), (4, 1));
+}
+
+#[test]
+fn playback_region() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&nrom_image(&[], 0, 0)).unwrap());
+
+    let mut movie = Movie::from_fm2(FM2).unwrap();
+    assert_eq!(movie.start_playback(&mut nes), Ok(()));
+    movie.pal = true;
+    let error = movie.start_playback(&mut nes).unwrap_err();
+    assert_eq!(
+        error,
+        Error::RegionMismatch {
+            expected: Region::Pal,
+            found: Region::Ntsc
+        }
+    );
+    assert_eq!(error.to_string(), "made for a PAL console, running as NTSC");
+
+    nes.set_region(Some(Region::Pal));
+    assert_eq!(movie.start_playback(&mut nes), Ok(()));
+    assert!(Movie::record_from(&nes).unwrap().pal);
+}
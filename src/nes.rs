@@ -0,0 +1,1252 @@
+//! The whole console
+//!
+//! [`Nes`] owns all the hardware, connects it through the CPU and PPU buses,
+//! and clocks everything in lockstep: every CPU cycle is followed by the PPU dots
+//! that happen during it according to the [`MasterClock`].
+//!
+//! To save time the PPU is allowed to fall behind and is run in batches,
+//! it's only caught up when the CPU accesses it, or right before it changes the NMI line.
+//! All the public methods catch it up before returning, so this isn't observable from the outside.
+//!
+//! A console shares nothing with other consoles, there's no global state.
+//! [`Nes`] is `Send`, hooks and input providers have to be too, so consoles can run on worker threads,
+//! many of them in parallel. It isn't `Sync`, a console is run by one thread at a time.
+
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
+
+#[cfg(feature = "png")]
+use crate::png;
+use crate::{
+    apu::{recording::Recording, Apu},
+    cartridge::Cartridge,
+    clock::{MasterClock, Region},
+    cpu::CpuState,
+    debug::{
+        breakpoints::Breakpoints,
+        call_stack::CallStack,
+        cdl::PrgUsage,
+        hooks::{Event, Hooks},
+        profiler::Profiler,
+        symbols::{Label, Location, Symbols},
+        StopReason,
+    },
+    disasm::Instruction,
+    input::{
+        ButtonState, Controller, ControllerPorts, Device, Expansion, FourScore, InputProvider,
+    },
+    logging,
+    memory::{
+        cheats::Cheats,
+        ram::{Ram, RamPattern},
+        Memory, MemoryMapping, PpuMemoryMapping,
+    },
+    ppu::{
+        self, palette::Palette, Overscan, Ppu, PpuStatus, DOTS_PER_SCANLINE, FRAME_HEIGHT,
+        FRAME_WIDTH,
+    },
+    state::{compression, impl_state, State, StateError, StateReader, StateWriter},
+};
+
+mod builder;
+mod config;
+#[cfg(test)]
+mod tests;
+
+pub use builder::NesBuilder;
+pub use config::{EmulationConfig, MAX_OVERCLOCK};
+
+/// Copy of a page of CPU memory to OAM, triggered by writing to $4014
+///
+/// The CPU is halted while it happens.
+#[derive(Debug, Clone, Copy, Default)]
+struct OamDma {
+    page: u8,
+    /// Cycles to wait before the copy starts
+    wait: u8,
+    index: u16,
+    /// Byte read on the previous cycle, to be written on this one
+    value: Option<u8>,
+}
+
+/// Longest an instruction or interrupt sequence takes, in CPU cycles
+const MAX_INSTRUCTION_CYCLES: u32 = 8;
+
+/// How closely the CPU is kept in step with the rest of the console
+///
+/// Whatever it is set to, the console runs cycle by cycle while a debugging tool like
+/// a breakpoint, a hook or the profiler is active, and when stepping by cycles or scanlines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Accuracy {
+    /// The rest of the console is clocked after every CPU cycle, like on the real hardware
+    #[default]
+    Cycle,
+    /// The CPU runs whole instructions, then the rest of the console catches up with it
+    ///
+    /// This skips most of the work done between cycles, for when speed matters more than
+    /// matching the hardware, like when fast-forwarding or training agents.
+    /// The CPU still runs the same instructions with the same cycle counts, but:
+    /// - Registers see the PPU and APU as they were when the instruction started,
+    ///   so reads of PPUSTATUS and writes that change the picture can be up to 8 cycles early
+    /// - Interrupts, and DMC DMAs stealing cycles, are only noticed between instructions
+    /// - Mappers counting CPU cycles or bus accesses see them in bursts
+    ///
+    /// Most games run fine, games and test ROMs that rely on exact timing don't.
+    Instruction,
+}
+
+/// Output of one frame of emulation
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a> {
+    /// 256x240 pixels, see [`Ppu::framebuffer`] for their format
+    pub video: &'a [u16],
+    /// Mono samples at the APU's sample rate, see [`Apu::samples`]
+    pub audio: &'a [f32],
+    /// Colors the picture is converted to RGB with
+    pub palette: &'a Palette,
+    /// Edges cut off by [`Frame::to_rgb_cropped`], from the [`EmulationConfig`]
+    pub overscan: Overscan,
+    /// Set if the frame was cut short, the picture is only partly drawn then
+    pub stop: Option<StopReason>,
+}
+
+impl Frame<'_> {
+    pub const WIDTH: usize = FRAME_WIDTH;
+    pub const HEIGHT: usize = FRAME_HEIGHT;
+
+    /// Convert the picture to 8-bit RGB, 3 bytes per pixel
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.video
+            .iter()
+            .flat_map(|&pixel| self.palette.to_rgb(pixel))
+            .collect()
+    }
+
+    /// Convert the picture to 8-bit RGB without the pixels cut off by [`Frame::overscan`]
+    ///
+    /// It's [`Frame::cropped_size`] pixels large.
+    pub fn to_rgb_cropped(&self) -> Vec<u8> {
+        self.crop_rgb(self.overscan)
+    }
+
+    /// Width and height of the picture without the pixels cut off by `overscan`
+    pub fn cropped_size(overscan: Overscan) -> (usize, usize) {
+        (
+            Self::WIDTH.saturating_sub(overscan.left + overscan.right),
+            Self::HEIGHT.saturating_sub(overscan.top + overscan.bottom),
+        )
+    }
+
+    fn crop_rgb(&self, overscan: Overscan) -> Vec<u8> {
+        let (width, height) = Self::cropped_size(overscan);
+        self.video
+            .chunks_exact(Self::WIDTH)
+            .skip(overscan.top)
+            .take(height)
+            .flat_map(|row| &row[overscan.left..overscan.left + width])
+            .flat_map(|&pixel| self.palette.to_rgb(pixel))
+            .collect()
+    }
+
+    /// Convert the picture to 8-bit RGBA, 4 bytes per pixel
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = vec![0; self.video.len() * 4];
+        self.write_rgba(&mut rgba);
+        rgba
+    }
+
+    /// Convert the picture to 8-bit RGBA into a buffer, row by row, so a frontend can reuse its texture memory
+    ///
+    /// # Panics
+    /// If `output` isn't `WIDTH * HEIGHT * 4` bytes long
+    pub fn write_rgba(&self, output: &mut [u8]) {
+        assert_eq!(output.len(), Self::WIDTH * Self::HEIGHT * 4);
+        for (pixels, row) in self
+            .video
+            .chunks_exact(Self::WIDTH)
+            .zip(output.chunks_exact_mut(Self::WIDTH * 4))
+        {
+            self.palette.write_rgba_row(pixels, row);
+        }
+    }
+
+    /// Encode the picture as a PNG image, without the pixels cut off by `overscan`
+    ///
+    /// # Panics
+    /// If `overscan` cuts off the whole picture
+    #[cfg(feature = "png")]
+    pub fn to_png_bytes(&self, overscan: Overscan) -> Vec<u8> {
+        let (width, height) = Self::cropped_size(overscan);
+        png::encode_rgb(width, height, &self.crop_rgb(overscan))
+    }
+
+    /// Save the picture as a PNG image, see [`Frame::to_png_bytes`]
+    #[cfg(all(feature = "png", feature = "std"))]
+    pub fn save_png(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        overscan: Overscan,
+    ) -> std::io::Result<()> {
+        std::fs::write(path, self.to_png_bytes(overscan))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Nes {
+    cpu: CpuState,
+    ram: Ram,
+    vram: Ram,
+    ppu: Ppu,
+    apu: Apu,
+    ports: ControllerPorts,
+    /// Frame the lag detection is looking at
+    input_frame: u64,
+    /// The last frame ended without the game reading input
+    lag_frame: bool,
+    lag_frames: u64,
+    cartridge: Option<Cartridge>,
+    cheats: Cheats,
+    oam_dma: Option<OamDma>,
+    /// Last value on the CPU data bus
+    open_bus: u8,
+    clock: MasterClock,
+    config: EmulationConfig,
+    /// CPU cycles since power on
+    cycle: u64,
+    ram_pattern: RamPattern,
+    palette: Box<Palette>,
+    /// Region forced by the user instead of the one detected from the cartridge
+    region_override: Option<Region>,
+    /// Kept here so it survives power cycles, see [`Ppu::set_frame_skip`]
+    frame_skip: u32,
+    /// Kept here for the same reason, see [`Ppu::set_variant`]
+    ppu_variant: ppu::Variant,
+    breakpoints: Breakpoints,
+    /// The breakpoints were already checked for the instruction the CPU is about to fetch
+    breakpoints_checked: bool,
+    profiler: Option<Box<Profiler>>,
+    symbols: Symbols,
+    call_stack: CallStack,
+    hooks: Hooks,
+}
+
+impl Nes {
+    /// Create a powered off console with no cartridge
+    ///
+    /// [`Nes::builder`] configures the console and loads a game in one go.
+    pub fn new() -> Self {
+        Self {
+            cpu: CpuState::new(),
+            ram: Ram::new(),
+            vram: Ram::new(),
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            ports: ControllerPorts::new(),
+            input_frame: 0,
+            lag_frame: false,
+            lag_frames: 0,
+            cartridge: None,
+            cheats: Cheats::new(),
+            oam_dma: None,
+            open_bus: 0,
+            clock: MasterClock::default(),
+            config: EmulationConfig::default(),
+            cycle: 0,
+            ram_pattern: RamPattern::default(),
+            palette: Box::default(),
+            region_override: None,
+            frame_skip: 0,
+            ppu_variant: ppu::Variant::default(),
+            breakpoints: Breakpoints::new(),
+            breakpoints_checked: false,
+            profiler: None,
+            symbols: Symbols::new(),
+            call_stack: CallStack::new(),
+            hooks: Hooks::new(),
+        }
+    }
+
+    /// Insert a cartridge, returning the one that was inserted before
+    ///
+    /// The console has to be powered on again for the game to start
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) -> Option<Cartridge> {
+        logging::info!(
+            "inserted cartridge with CRC32 {:08X}, mapper {}",
+            cartridge.crc32(),
+            cartridge.mapper()
+        );
+        self.cartridge.replace(cartridge)
+    }
+
+    pub fn remove_cartridge(&mut self) -> Option<Cartridge> {
+        self.cartridge.take()
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    /// Turn the console off and on again, reinitializing all the hardware
+    ///
+    /// RAM, OAM and the palette are filled according to the [`RamPattern`], battery backed cartridge RAM is kept.
+    /// The console switches to the region forced with [`Nes::set_region`],
+    /// or the one the cartridge was made for, or NTSC if it's not known.
+    /// VS System games get the PPU they were made for, see [`Nes::set_ppu_variant`].
+    pub fn power_cycle(&mut self) {
+        let previous_variant = self.ppu.variant();
+        self.cpu = CpuState::new();
+        self.cpu.reset();
+        self.ram = Ram::with_pattern(self.ram_pattern);
+        self.vram = Ram::with_pattern(self.ram_pattern);
+        self.ppu = Ppu::with_pattern(self.ram_pattern);
+        let sample_rate = self.apu.sample_rate();
+        let recording = self.apu.stop_recording();
+        self.apu = Apu::new();
+        self.apu.set_sample_rate(sample_rate);
+        if let Some(recording) = recording {
+            self.apu.start_recording(recording);
+        }
+        self.ports.power_cycle();
+        self.input_frame = 0;
+        self.lag_frame = false;
+        self.lag_frames = 0;
+        self.oam_dma = None;
+        self.open_bus = 0;
+        self.call_stack.clear();
+        let region = self
+            .region_override
+            .or(self
+                .cartridge
+                .as_ref()
+                .and_then(|cartridge| cartridge.board().region))
+            .unwrap_or_default();
+        self.clock = MasterClock::new(region);
+        self.apply_region();
+        self.apply_config();
+        self.ppu.set_frame_skip(self.frame_skip);
+        self.apply_ppu_variant(previous_variant);
+        self.cycle = 0;
+    }
+
+    /// The region the console is currently running as
+    pub fn region(&self) -> Region {
+        self.clock.region()
+    }
+
+    /// Force a region, or go back to detecting it from the cartridge with `None`
+    ///
+    /// Takes effect at the next [`Nes::power_cycle`]
+    pub fn set_region(&mut self, region: Option<Region>) {
+        self.region_override = region;
+    }
+
+    pub fn accuracy(&self) -> Accuracy {
+        self.config.accuracy
+    }
+
+    /// Trade accuracy for speed, see [`Accuracy`], takes effect right away
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.config.accuracy = accuracy;
+    }
+
+    pub fn config(&self) -> &EmulationConfig {
+        &self.config
+    }
+
+    /// Change the options of the running console, see [`EmulationConfig`] for when each one takes effect
+    pub fn set_config(&mut self, config: EmulationConfig) {
+        self.config = config;
+        self.apply_config();
+    }
+
+    /// Hand the options of the [`EmulationConfig`] to the hardware they're for
+    fn apply_config(&mut self) {
+        self.ppu.set_sprite_limit(self.config.sprite_limit);
+        self.ppu
+            .set_extra_scanlines(self.config.extra_scanlines(self.region()));
+        self.apu.set_output_filter(self.config.audio_filter);
+        self.apu
+            .set_reduce_dmc_popping(self.config.reduce_dmc_popping);
+        if let Some(period) = self.config.turbo_period {
+            for controller in self
+                .ports
+                .devices
+                .iter_mut()
+                .filter_map(Device::as_controller_mut)
+            {
+                controller
+                    .turbo_mut()
+                    .set_period(ButtonState::all(), period);
+            }
+        }
+    }
+
+    /// Only draw one frame out of every `frame_skip + 1`, see [`Ppu::set_frame_skip`]
+    ///
+    /// The picture of [`Nes::run_frame`] stays the last drawn one on skipped frames.
+    pub fn set_frame_skip(&mut self, frame_skip: u32) {
+        self.frame_skip = frame_skip;
+        self.ppu.set_frame_skip(frame_skip);
+    }
+
+    pub fn ppu_variant(&self) -> ppu::Variant {
+        self.ppu_variant
+    }
+
+    /// Switch to another model of PPU and the colors it outputs, takes effect right away
+    ///
+    /// Replaces the palette, a different one can be set after with [`Nes::set_palette`].
+    /// VS System games get the PPU they were made for at the next [`Nes::power_cycle`] instead.
+    pub fn set_ppu_variant(&mut self, variant: ppu::Variant) {
+        self.catch_up_ppu();
+        self.ppu_variant = variant;
+        self.ppu.set_variant(variant);
+        self.set_palette(variant.palette());
+    }
+
+    /// Give the PPU the variant that was set, or the one the VS System game needs,
+    /// with its palette if that's not the variant it had before
+    fn apply_ppu_variant(&mut self, previous: ppu::Variant) {
+        let variant = self
+            .cartridge
+            .as_ref()
+            .and_then(|cartridge| cartridge.board().vs_ppu)
+            .unwrap_or(self.ppu_variant);
+        self.ppu.set_variant(variant);
+        if variant != previous {
+            self.set_palette(variant.palette());
+        }
+    }
+
+    /// Configure the PPU and APU timing for the clock's region
+    fn apply_region(&mut self) {
+        self.ppu.set_region(self.clock.region());
+        self.apu.set_region(self.clock.region());
+    }
+
+    /// Press the reset button
+    ///
+    /// RAM and most of the state is preserved, the CPU runs the reset sequence,
+    /// the APU is silenced and PPU registers are cleared as on real hardware.
+    pub fn reset(&mut self) {
+        self.catch_up_ppu();
+        self.cpu.reset();
+        self.ppu.reset();
+        self.apu.reset();
+        self.oam_dma = None;
+    }
+
+    pub fn ram_pattern(&self) -> RamPattern {
+        self.ram_pattern
+    }
+
+    /// Set the contents of RAM, OAM and the palette after the next [`Nes::power_cycle`]
+    pub fn set_ram_pattern(&mut self, pattern: RamPattern) {
+        self.ram_pattern = pattern;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Set the colors the pictures of [`Nes::run_frame`] are converted with
+    pub fn set_palette(&mut self, palette: Palette) {
+        *self.palette = palette;
+    }
+
+    pub fn cpu(&self) -> &CpuState {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CpuState {
+        &mut self.cpu
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// The device in controller port 1 or 2, indexed from 0
+    ///
+    /// Both ports start out with a standard controller.
+    ///
+    /// # Panics
+    /// If `port` isn't 0 or 1
+    pub fn port(&self, port: usize) -> &Device {
+        &self.ports.devices[port]
+    }
+
+    /// See [`Nes::port`]
+    pub fn port_mut(&mut self, port: usize) -> &mut Device {
+        &mut self.ports.devices[port]
+    }
+
+    /// Plug a device into a controller port, returning the one that was there before
+    ///
+    /// # Panics
+    /// If `port` isn't 0 or 1
+    pub fn connect(&mut self, port: usize, device: Device) -> Device {
+        let device = core::mem::replace(&mut self.ports.devices[port], device);
+        self.apply_config();
+        device
+    }
+
+    /// The device in the Famicom expansion port
+    pub fn expansion(&self) -> &Expansion {
+        &self.ports.expansion
+    }
+
+    pub fn expansion_mut(&mut self) -> &mut Expansion {
+        &mut self.ports.expansion
+    }
+
+    /// Connect a Four Score to both ports, for up to 4 players
+    pub fn connect_four_score(&mut self) {
+        self.ports.devices = [0, 1].map(|port| Device::FourScore(FourScore::new(port)));
+    }
+
+    /// Buttons of a player, indexed from 0, see [`Nes::set_player_buttons`]
+    ///
+    /// Empty if the player doesn't have a controller.
+    pub fn player_buttons(&self, player: usize) -> ButtonState {
+        match self.ports.devices.get(player % 2) {
+            Some(Device::Controller(controller)) if player < 2 => controller.buttons(),
+            Some(Device::FourScore(four_score)) if player < 4 => four_score.buttons(player / 2),
+            _ => ButtonState::empty(),
+        }
+    }
+
+    /// Set the buttons of a player, indexed from 0
+    ///
+    /// Players 1 and 2 use the controllers in the ports, 3 and 4 are only there with a Four Score.
+    /// Does nothing if the player doesn't have a controller.
+    pub fn set_player_buttons(&mut self, player: usize, buttons: ButtonState) {
+        match self.ports.devices.get_mut(player % 2) {
+            Some(Device::Controller(controller)) if player < 2 => controller.set_buttons(buttons),
+            Some(Device::FourScore(four_score)) if player < 4 => {
+                four_score.set_buttons(player / 2, buttons)
+            }
+            _ => {}
+        }
+    }
+
+    /// Poll the provider for input every time the game strobes the controllers, returning the previous one
+    ///
+    /// The provider isn't cloned along with the console.
+    pub fn set_input_provider(
+        &mut self,
+        provider: Option<Box<dyn InputProvider>>,
+    ) -> Option<Box<dyn InputProvider>> {
+        self.ports.set_provider(provider)
+    }
+
+    /// Whether the game didn't read the controllers during the last finished frame
+    pub fn is_lag_frame(&self) -> bool {
+        self.lag_frame
+    }
+
+    /// Lag frames since power on, not part of save states
+    pub fn lag_frames(&self) -> u64 {
+        self.lag_frames
+    }
+
+    /// The standard controller in the port, if that's what is connected there
+    ///
+    /// # Panics
+    /// If `port` isn't 0 or 1
+    pub fn controller_mut(&mut self, port: usize) -> Option<&mut Controller> {
+        self.ports.devices[port].as_controller_mut()
+    }
+
+    /// Set the rate at which audio is sampled, in Hz
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.apu.set_sample_rate(sample_rate);
+    }
+
+    /// Start capturing the audio into `recording`, it goes on through resets and power cycles
+    pub fn start_audio_recording(&mut self, recording: Recording) {
+        self.apu.start_recording(recording);
+    }
+
+    /// Stop capturing the audio, returning what was recorded
+    pub fn stop_audio_recording(&mut self) -> Option<Recording> {
+        self.apu.stop_recording()
+    }
+
+    pub fn audio_recording(&self) -> Option<&Recording> {
+        self.apu.recording()
+    }
+
+    /// The 2KB of CPU RAM
+    pub fn ram(&self) -> &Ram {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut Ram {
+        &mut self.ram
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Read a byte from the CPU address space without side effects, for debuggers
+    ///
+    /// Registers read as the last value on the data bus.
+    pub fn peek(&self, address: u16) -> u8 {
+        let value = match address {
+            0x0000..0x2000 => self.ram.load(address % 0x800),
+            0x2000..0x4020 => self.open_bus,
+            _ => self
+                .cartridge
+                .as_ref()
+                .and_then(|cartridge| cartridge.cpu_peek(address))
+                .unwrap_or(self.open_bus),
+        };
+        self.cheats.patch(address, value)
+    }
+
+    /// Decode the instruction at the given address
+    pub fn disassemble(&self, address: u16) -> Instruction {
+        Instruction::decode(address, |address| self.peek(address))
+    }
+
+    /// Decode the instruction at the given address, with the addresses it uses shown by label
+    pub fn disassemble_labeled(&self, address: u16) -> String {
+        self.disassemble(address)
+            .labeled(|address| self.label(address))
+    }
+
+    /// Label of a CPU address, from the labels of the PRG ROM mapped there or of the address itself
+    pub fn label(&self, address: u16) -> Option<Label<'_>> {
+        let prg_label = self
+            .cartridge
+            .as_ref()
+            .and_then(|cartridge| cartridge.prg_rom_offset(address))
+            .and_then(|offset| self.symbols.label(Location::PrgRom(offset)));
+        prg_label.or_else(|| self.symbols.label(Location::Cpu(address)))
+    }
+
+    /// Subroutines and interrupt handlers the CPU is in, tracked since power on
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Text with the subroutines and interrupt handlers the CPU is in, by label
+    pub fn backtrace(&self) -> String {
+        self.call_stack
+            .backtrace(self.cpu.program_counter, |address| self.label(address))
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    pub fn hooks_mut(&mut self) -> &mut Hooks {
+        &mut self.hooks
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut Symbols {
+        &mut self.symbols
+    }
+
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Start counting the cycles spent on each instruction, throwing away the last profile
+    pub fn start_profiler(&mut self) {
+        self.profiler = Some(Box::default());
+    }
+
+    /// Stop counting cycles, returning the profile
+    pub fn stop_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /// Check the execute breakpoints if the CPU is about to fetch an instruction
+    ///
+    /// Each instruction is only checked once, so running again after a stop continues past the breakpoint.
+    fn check_breakpoints(&mut self) -> Option<StopReason> {
+        if self.breakpoints_checked || self.oam_dma.is_some() || self.cpu.current_cycle() != 0 {
+            return None;
+        }
+        self.breakpoints_checked = true;
+        self.breakpoints
+            .check_execute(&self.cpu)
+            .map(StopReason::Breakpoint)
+    }
+
+    /// CPU cycles since power on
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Master clock cycles since power on
+    pub fn master_cycle(&self) -> u64 {
+        self.clock.cycle()
+    }
+
+    /// Advance the console by one CPU cycle
+    ///
+    /// Does nothing if there is no cartridge inserted
+    pub fn step_cycle(&mut self) {
+        let _ = self.run_cycle();
+        self.catch_up_ppu();
+    }
+
+    /// Run one CPU cycle, leaving the PPU behind unless it's about to do something the CPU would notice
+    ///
+    /// Returns the read or write breakpoint hit during the cycle
+    fn run_cycle(&mut self) -> Option<StopReason> {
+        self.run_cycle_with::<true>()
+    }
+
+    /// [`Nes::run_cycle`], without the work of the debugging tools when `OBSERVED` is false
+    ///
+    /// Turning them off is only correct while [`Nes::is_observed`] is false
+    #[inline(always)]
+    fn run_cycle_with<const OBSERVED: bool>(&mut self) -> Option<StopReason> {
+        let cartridge = self.cartridge.as_ref()?;
+        if self.oam_dma.is_none() && self.cpu.current_cycle() == 0 {
+            if OBSERVED && cartridge.code_data_log().is_some() {
+                self.log_indirect_access();
+            }
+            self.call_stack.start_instruction(self.cpu.program_counter);
+            if OBSERVED {
+                if let Some(profiler) = &mut self.profiler {
+                    let function = self.call_stack.current().map(|frame| frame.target);
+                    profiler.start_instruction(self.cpu.program_counter, function);
+                }
+                if self.hooks.watches_execute() {
+                    let address = self.cpu.program_counter;
+                    self.hooks.record(Event::Execute { address });
+                }
+                if self.hooks.has_pending() {
+                    Hooks::run(self);
+                }
+            }
+        }
+        if OBSERVED {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.count_cycle();
+            }
+        }
+
+        let Some(cartridge) = &mut self.cartridge else {
+            return None;
+        };
+
+        let mut memory = MemoryMapping {
+            ram: &mut self.ram,
+            cheats: &self.cheats,
+            ppu: &mut self.ppu,
+            vram: &mut self.vram,
+            apu: &mut self.apu,
+            ports: &mut self.ports,
+            open_bus: &mut self.open_bus,
+            cartridge,
+            breakpoints: &mut self.breakpoints,
+            hooks: &mut self.hooks,
+        };
+
+        match &mut self.oam_dma {
+            Some(dma) => {
+                if Self::oam_dma_cycle(dma, &mut memory) {
+                    self.oam_dma = None;
+                }
+            }
+            None => {
+                self.cpu.run_cycle(&mut memory);
+                self.breakpoints_checked = false;
+                if self.cpu.current_cycle() == 0 {
+                    self.call_stack
+                        .finish_instruction(&self.cpu, self.cpu.current_opcode());
+                    if let Some(interrupt) = self.cpu.current_interrupt() {
+                        self.hooks.record(Event::Interrupt(interrupt));
+                    }
+                }
+            }
+        }
+
+        self.start_oam_dma(self.cycle);
+        self.run_devices(1);
+        self.cpu.set_nmi_line(self.ppu.nmi());
+
+        if !OBSERVED {
+            return None;
+        }
+        if self.hooks.watches_scanlines() {
+            self.catch_up_ppu();
+            self.hooks.record_scanline(self.ppu.scanline());
+        }
+        if self.hooks.has_pending() {
+            Hooks::run(self);
+        }
+
+        self.breakpoints
+            .check_accesses(&self.cpu)
+            .map(StopReason::Breakpoint)
+    }
+
+    /// Start the OAM DMA if the CPU asked for one by writing on the given cycle
+    fn start_oam_dma(&mut self, cycle: u64) {
+        if let Some(page) = self.ppu.take_oam_dma_request() {
+            self.oam_dma = Some(OamDma {
+                page,
+                // one cycle to halt the CPU, and another one if the copy would start on a write cycle
+                wait: 1 + cycle.is_multiple_of(2) as u8,
+                index: 0,
+                value: None,
+            });
+        }
+    }
+
+    /// Run everything but the CPU through the given number of CPU cycles, and update the IRQ line
+    ///
+    /// The NMI line is left to the caller, since when it's sampled matters.
+    fn run_devices(&mut self, cycles: u32) {
+        let Some(cartridge) = &mut self.cartridge else {
+            return;
+        };
+
+        let mut dots = 0;
+        for _ in 0..cycles {
+            dots += self.clock.advance_cpu_cycle();
+        }
+        let mut ppu_memory = PpuMemoryMapping {
+            vram: &mut self.vram,
+            cartridge,
+        };
+        self.ppu.advance(dots, &mut ppu_memory);
+        // the APU waits out the extra scanlines of overclocking with the PPU
+        let apu_paused = self.ppu.is_in_extra_scanline();
+        for _ in 0..cycles {
+            if apu_paused {
+                cartridge.tick();
+                continue;
+            }
+            self.apu.tick();
+            if let Some(address) = self.apu.dmc_dma_request() {
+                let mut memory = MemoryMapping {
+                    ram: &mut self.ram,
+                    cheats: &self.cheats,
+                    ppu: &mut self.ppu,
+                    vram: &mut self.vram,
+                    apu: &mut self.apu,
+                    ports: &mut self.ports,
+                    open_bus: &mut self.open_bus,
+                    cartridge,
+                    breakpoints: &mut self.breakpoints,
+                    hooks: &mut self.hooks,
+                };
+                let value = memory.load(address);
+                self.apu.fill_dmc_buffer(value);
+                cartridge.log_prg(address, PrgUsage::PCM);
+            }
+            cartridge.tick();
+        }
+
+        if self.ppu.frame() != self.input_frame {
+            self.input_frame = self.ppu.frame();
+            self.lag_frame = !self.ports.take_polled();
+            self.lag_frames += self.lag_frame as u64;
+            self.hooks.record(Event::FrameEnd {
+                frame: self.input_frame,
+            });
+        }
+
+        self.cpu.set_irq_line(self.apu.irq() || cartridge.irq());
+        self.cycle += cycles as u64;
+    }
+
+    /// Run a whole instruction on the CPU before running the rest of the console through it,
+    /// see [`Accuracy::Instruction`]
+    ///
+    /// Has to start at the beginning of an instruction, without an OAM DMA going on.
+    fn run_instruction_fast(&mut self) {
+        let Some(cartridge) = &mut self.cartridge else {
+            return;
+        };
+        self.call_stack.start_instruction(self.cpu.program_counter);
+        let mut memory = MemoryMapping {
+            ram: &mut self.ram,
+            cheats: &self.cheats,
+            ppu: &mut self.ppu,
+            vram: &mut self.vram,
+            apu: &mut self.apu,
+            ports: &mut self.ports,
+            open_bus: &mut self.open_bus,
+            cartridge,
+            breakpoints: &mut self.breakpoints,
+            hooks: &mut self.hooks,
+        };
+        let mut cycles = 0;
+        // a jammed CPU never finishes its instruction
+        while cycles < MAX_INSTRUCTION_CYCLES {
+            self.cpu.run_cycle(&mut memory);
+            cycles += 1;
+            if self.cpu.current_cycle() == 0 {
+                break;
+            }
+        }
+        self.breakpoints_checked = false;
+        if self.cpu.current_cycle() == 0 {
+            self.call_stack
+                .finish_instruction(&self.cpu, self.cpu.current_opcode());
+        }
+        // the write to $4014 is the last cycle of the instruction
+        self.start_oam_dma(self.cycle + cycles as u64 - 1);
+        // the NMI line is sampled before the last cycle too, so an NMI that came in time isn't delayed
+        self.run_devices(cycles - 1);
+        self.cpu.sample_nmi_line(self.ppu.nmi(), false);
+        self.run_devices(1);
+        self.cpu.set_nmi_line(self.ppu.nmi());
+    }
+
+    /// Whether a debugging tool has to see every cycle, which rules out [`Nes::run_batch`]
+    fn is_observed(&self) -> bool {
+        !self.hooks.is_empty()
+            || self.profiler.is_some()
+            || self.breakpoints.is_active()
+            || self
+                .cartridge
+                .as_ref()
+                .is_some_and(|cartridge| cartridge.code_data_log().is_some())
+    }
+
+    /// CPU cycles that surely run before the PPU finishes the frame
+    ///
+    /// Is 0 close to the end, when the cycles have to be run one by one to stop right there.
+    fn cycles_until_frame_end(&self) -> u64 {
+        self.clock
+            .cpu_cycles_before_dots(self.ppu.dots_until_frame_end() as u64)
+    }
+
+    /// Run a batch of CPU cycles without checking for breakpoints or running hooks in between
+    ///
+    /// The batch only has to end for things the loop around it looks at, like the end of the frame.
+    /// Interrupts and DMAs are handled by the cycles themselves like they always are.
+    /// With [`Accuracy::Instruction`], whole instructions are run as long as they fit in the batch.
+    fn run_batch(&mut self, cycles: u64) {
+        debug_assert!(!self.is_observed());
+        let end = self.cycle + cycles;
+        while self.cycle < end {
+            if self.config.accuracy == Accuracy::Instruction
+                && self.oam_dma.is_none()
+                && self.cpu.current_cycle() == 0
+                && end - self.cycle >= MAX_INSTRUCTION_CYCLES as u64
+            {
+                self.run_instruction_fast();
+            } else {
+                self.run_cycle_with::<false>();
+            }
+        }
+    }
+
+    /// Note what the instruction the CPU is about to run accesses through a pointer in the code/data log
+    fn log_indirect_access(&mut self) {
+        let instruction = self.disassemble(self.cpu.program_counter);
+        if !instruction.is_indirect() {
+            return;
+        }
+        let Some(address) = instruction.effective_address(&self.cpu, |address| self.peek(address))
+        else {
+            return;
+        };
+        let usage = match instruction.mnemonic() {
+            "JMP" => PrgUsage::INDIRECT_CODE,
+            _ => PrgUsage::INDIRECT_DATA,
+        };
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.log_prg(address, usage);
+        }
+    }
+
+    /// Run the PPU up to the current CPU cycle
+    fn catch_up_ppu(&mut self) {
+        if let Some(cartridge) = &mut self.cartridge {
+            let mut ppu_memory = PpuMemoryMapping {
+                vram: &mut self.vram,
+                cartridge,
+            };
+            self.ppu.catch_up(&mut ppu_memory);
+        }
+    }
+
+    /// Advance the console until the current CPU instruction is finished
+    ///
+    /// The instruction is run even if there's a breakpoint on it.
+    /// Returns the first read or write breakpoint it hit,
+    /// or the execute breakpoint on the next instruction if there is one.
+    /// Does nothing if there is no cartridge inserted
+    pub fn step_instruction(&mut self) -> Option<StopReason> {
+        self.cartridge.as_ref()?;
+
+        let mut stop = self.run_cycle();
+        while self.cpu.current_cycle() != 0 || self.oam_dma.is_some() {
+            stop = stop.or(self.run_cycle());
+        }
+        self.catch_up_ppu();
+        stop.or_else(|| self.check_breakpoints())
+    }
+
+    /// Advance the console until the PPU finishes the current frame
+    ///
+    /// Returns the picture and the audio generated while running.
+    /// Stops early if a breakpoint is hit, running again finishes the frame.
+    /// Nothing is allocated, unless a debugging tool needs to, or the audio is more than
+    /// a frame's worth because of another way of running the console was used before.
+    /// [`Frame::write_rgba`] converts the picture without allocating either.
+    /// Without a cartridge nothing is run, the last picture and no audio is returned.
+    pub fn run_frame(&mut self) -> Frame<'_> {
+        self.apu.clear_samples();
+        let mut stop = None;
+        if self.cartridge.is_some() {
+            let frame = self.ppu.frame();
+            while self.ppu.frame() == frame {
+                let batch = self.cycles_until_frame_end();
+                if batch != 0 && !self.is_observed() {
+                    self.run_batch(batch);
+                    continue;
+                }
+                stop = self.check_breakpoints().or_else(|| self.run_cycle());
+                if stop.is_some() {
+                    break;
+                }
+            }
+            self.catch_up_ppu();
+        }
+
+        Frame {
+            video: self.ppu.framebuffer(),
+            audio: self.apu.samples(),
+            palette: &self.palette,
+            overscan: self.config.overscan,
+            stop,
+        }
+    }
+
+    /// Advance the console by the given number of CPU cycles
+    ///
+    /// Does nothing if there is no cartridge inserted
+    pub fn run_cycles(&mut self, cycles: u64) {
+        if self.cartridge.is_none() {
+            return;
+        }
+
+        if self.is_observed() {
+            for _ in 0..cycles {
+                self.run_cycle();
+            }
+        } else {
+            self.run_batch(cycles);
+        }
+        self.catch_up_ppu();
+    }
+
+    /// Advance the console until the PPU starts the next scanline
+    ///
+    /// Does nothing if there is no cartridge inserted
+    pub fn run_scanline(&mut self) {
+        if self.cartridge.is_none() {
+            return;
+        }
+
+        let scanline = self.ppu.scanline();
+        while self.ppu.scanline() == scanline {
+            self.step_cycle();
+        }
+    }
+
+    /// Advance the console until the PPU sets the VBlank flag
+    ///
+    /// Does nothing if there is no cartridge inserted
+    pub fn run_until_vblank(&mut self) {
+        if self.cartridge.is_none() {
+            return;
+        }
+
+        let mut vblank = self.ppu.status.contains(PpuStatus::VBLANK);
+        loop {
+            self.run_cycle();
+            let previous = vblank;
+            vblank = self.ppu.status.contains(PpuStatus::VBLANK);
+            if vblank && !previous {
+                self.catch_up_ppu();
+                return;
+            }
+        }
+    }
+
+    /// Advance the console until the PPU requests an NMI, the CPU will handle it with its next instruction
+    ///
+    /// Because the game might have NMIs disabled, this gives up after a frame's worth of cycles.
+    /// Returns whether an NMI was requested
+    pub fn run_until_nmi(&mut self) -> bool {
+        if self.cartridge.is_none() {
+            return false;
+        }
+
+        let start = self.cycle;
+        let mut nmi = self.ppu.nmi();
+        // ignoring the skipped dot of odd frames
+        let dots_per_frame = DOTS_PER_SCANLINE as u64 * self.ppu.scanlines_per_frame() as u64;
+        let cycles_per_frame = self.clock.cpu_cycles_for_dots(dots_per_frame);
+        while self.cycle - start < cycles_per_frame {
+            self.run_cycle();
+            let previous = nmi;
+            nmi = self.ppu.nmi();
+            if nmi && !previous {
+                self.catch_up_ppu();
+                return true;
+            }
+        }
+        self.catch_up_ppu();
+        false
+    }
+
+    /// Take a snapshot of the console, which can be restored with [`Nes::load_state`]
+    ///
+    /// Fails if there's no cartridge inserted
+    pub fn save_state(&self) -> Result<Vec<u8>, StateError> {
+        let cartridge = self.cartridge.as_ref().ok_or(StateError::NoCartridge)?;
+
+        let mut writer = StateWriter::new(cartridge.crc32());
+        writer.section(b"NES ", |writer| {
+            self.clock.save_state(writer);
+            self.cycle.save_state(writer);
+            self.oam_dma.save_state(writer);
+            self.open_bus.save_state(writer);
+        });
+        writer.section(b"CPU ", |writer| self.cpu.save_state(writer));
+        writer.section(b"RAM ", |writer| self.ram.save_state(writer));
+        writer.section(b"VRAM", |writer| self.vram.save_state(writer));
+        writer.section(b"PPU ", |writer| self.ppu.save_state(writer));
+        writer.section(b"APU ", |writer| self.apu.save_state(writer));
+        writer.section(b"CART", |writer| cartridge.save_state(writer));
+        writer.section(b"INPT", |writer| self.ports.save_state(writer));
+        Ok(writer.finish())
+    }
+
+    /// Restore a snapshot made by [`Nes::save_state`]
+    ///
+    /// The state has to be made with the same game as the inserted cartridge.
+    /// If loading fails, the console is left as it was.
+    /// Compressed states are decompressed first.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state = compression::decompress(state)?;
+        let crc32 = self
+            .cartridge
+            .as_ref()
+            .ok_or(StateError::NoCartridge)?
+            .crc32();
+        let (mut reader, found) = StateReader::new(&state)?;
+        if found != crc32 {
+            return Err(StateError::RomMismatch {
+                expected: crc32,
+                found,
+            });
+        }
+
+        let mut nes = self.clone();
+        reader.section(b"NES ", |reader| {
+            nes.clock.load_state(reader)?;
+            nes.cycle.load_state(reader)?;
+            nes.oam_dma.load_state(reader)?;
+            // open bus was added in version 4
+            if reader.version() >= 4 {
+                nes.open_bus.load_state(reader)?;
+            }
+            Ok(())
+        })?;
+        reader.section(b"CPU ", |reader| nes.cpu.load_state(reader))?;
+        reader.section(b"RAM ", |reader| nes.ram.load_state(reader))?;
+        reader.section(b"VRAM", |reader| nes.vram.load_state(reader))?;
+        reader.section(b"PPU ", |reader| nes.ppu.load_state(reader))?;
+        reader.section(b"APU ", |reader| nes.apu.load_state(reader))?;
+        let cartridge = nes.cartridge.as_mut().expect("checked above");
+        reader.section(b"CART", |reader| cartridge.load_state(reader))?;
+        nes.input_frame = nes.ppu.frame();
+        // controller ports were added in version 2
+        if reader.version() >= 2 {
+            reader.section(b"INPT", |reader| {
+                // the second controller was added in version 3
+                if reader.version() >= 3 {
+                    nes.ports.load_state(reader)
+                } else {
+                    nes.ports.devices[0].load_state(reader)
+                }
+            })?;
+        }
+        nes.apply_region();
+        // the stack the calls were tracked on was replaced
+        nes.call_stack.clear();
+
+        nes.set_input_provider(self.set_input_provider(None));
+        nes.hooks = core::mem::take(&mut self.hooks);
+        *self = nes;
+        logging::info!(
+            "loaded version {} save state at frame {}",
+            reader.version(),
+            self.ppu.frame()
+        );
+        Ok(())
+    }
+
+    /// Run a single cycle of an OAM DMA, returns `true` when the copy is finished
+    fn oam_dma_cycle(dma: &mut OamDma, memory: &mut MemoryMapping) -> bool {
+        if dma.wait > 0 {
+            dma.wait -= 1;
+            return false;
+        }
+
+        match dma.value.take() {
+            None => {
+                dma.value = Some(memory.load((dma.page as u16) << 8 | dma.index));
+                false
+            }
+            Some(value) => {
+                memory.store(0x2004, value);
+                dma.index += 1;
+                dma.index == 0x100
+            }
+        }
+    }
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_state!(OamDma {
+    page,
+    wait,
+    index,
+    value
+});
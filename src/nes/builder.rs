@@ -0,0 +1,110 @@
+use crate::{
+    cartridge::{Cartridge, LoadError},
+    clock::Region,
+    memory::ram::RamPattern,
+    ppu::{self, palette::Palette},
+};
+
+use super::{Accuracy, EmulationConfig, Nes};
+
+/// Configuration of a console, made by [`Nes::builder`]
+///
+/// Every setting is optional and left as [`Nes::new`] has it if not set.
+/// Settings added in the future get a default the same way, so code using the builder keeps working.
+#[derive(Debug, Clone, Default)]
+pub struct NesBuilder {
+    region: Option<Region>,
+    config: EmulationConfig,
+    ram_pattern: RamPattern,
+    ppu_variant: ppu::Variant,
+    palette: Option<Palette>,
+    sample_rate: Option<u32>,
+    frame_skip: u32,
+}
+
+impl NesBuilder {
+    /// Force a region instead of detecting it from the cartridge, see [`Nes::set_region`]
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// See [`Nes::set_accuracy`]
+    pub fn accuracy(mut self, accuracy: Accuracy) -> Self {
+        self.config.accuracy = accuracy;
+        self
+    }
+
+    /// See [`Nes::set_config`], replaces the accuracy set before
+    pub fn config(mut self, config: EmulationConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Contents of RAM at power on, see [`Nes::set_ram_pattern`]
+    pub fn ram_pattern(mut self, pattern: RamPattern) -> Self {
+        self.ram_pattern = pattern;
+        self
+    }
+
+    /// See [`Nes::set_ppu_variant`], the palette set with [`NesBuilder::palette`] replaces the variant's
+    pub fn ppu_variant(mut self, variant: ppu::Variant) -> Self {
+        self.ppu_variant = variant;
+        self
+    }
+
+    /// See [`Nes::set_palette`]
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+
+    /// Rate at which audio is sampled, in Hz
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// See [`Nes::set_frame_skip`]
+    pub fn frame_skip(mut self, frame_skip: u32) -> Self {
+        self.frame_skip = frame_skip;
+        self
+    }
+
+    /// Make a powered off console with no cartridge
+    pub fn build(self) -> Nes {
+        let mut nes = Nes::new();
+        nes.set_region(self.region);
+        nes.set_config(self.config);
+        nes.set_ram_pattern(self.ram_pattern);
+        nes.set_ppu_variant(self.ppu_variant);
+        if let Some(palette) = self.palette {
+            nes.set_palette(palette);
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            nes.set_sample_rate(sample_rate);
+        }
+        nes.set_frame_skip(self.frame_skip);
+        nes
+    }
+
+    /// Make a console with `cartridge` inserted, powered on
+    pub fn build_with(self, cartridge: Cartridge) -> Nes {
+        let mut nes = self.build();
+        nes.insert_cartridge(cartridge);
+        nes.power_cycle();
+        nes
+    }
+
+    /// Load a ROM image with [`Cartridge::from_bytes`] and make a console running it
+    pub fn load(self, rom: &[u8]) -> Result<Nes, LoadError> {
+        Ok(self.build_with(Cartridge::from_bytes(rom, None)?))
+    }
+}
+
+impl Nes {
+    /// Start configuring a console, see [`NesBuilder`]
+    pub fn builder() -> NesBuilder {
+        NesBuilder::default()
+    }
+}
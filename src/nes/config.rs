@@ -0,0 +1,59 @@
+use crate::{clock::Region, ppu::Overscan};
+
+use super::Accuracy;
+
+/// Options that can be changed while the console is running, set with [`Nes::set_config`](super::Nes::set_config)
+///
+/// None of them are part of save states, each one is picked up at a defined point:
+/// - `sprite_limit` at the next scanline
+/// - `overscan` when a [`Frame`](super::Frame) is converted
+/// - `audio_filter` at the next sample, `reduce_dmc_popping` at the next write to $4011
+/// - `turbo_period` right away, and on the controllers connected later
+/// - `accuracy` at the next instruction
+/// - `overclock` right away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmulationConfig {
+    /// Draw at most 8 sprites per scanline, see [`Ppu::set_sprite_limit`](crate::ppu::Ppu::set_sprite_limit)
+    pub sprite_limit: bool,
+    /// Pixels cut off by [`Frame::to_rgb_cropped`](super::Frame::to_rgb_cropped)
+    pub overscan: Overscan,
+    /// See [`Apu::set_output_filter`](crate::apu::Apu::set_output_filter)
+    pub audio_filter: bool,
+    /// See [`Apu::set_reduce_dmc_popping`](crate::apu::Apu::set_reduce_dmc_popping)
+    pub reduce_dmc_popping: bool,
+    /// Turbo period of every button of the standard controllers, replacing the one set on each
+    /// with [`Turbo::set_period`](crate::input::Turbo::set_period)
+    pub turbo_period: Option<u8>,
+    pub accuracy: Accuracy,
+    /// Extra CPU time per frame in percent, given as idle scanlines after the NMI
+    ///
+    /// Slowdown in games that run out of time goes away, code timed against rendering is unaffected.
+    /// The APU is paused through the extra scanlines so the sound doesn't change.
+    /// Capped at [`MAX_OVERCLOCK`], see [`Ppu::set_extra_scanlines`](crate::ppu::Ppu::set_extra_scanlines)
+    pub overclock: u16,
+}
+
+/// Highest `overclock` percentage of [`EmulationConfig`], higher ones are treated as this
+pub const MAX_OVERCLOCK: u16 = 1000;
+
+impl EmulationConfig {
+    /// Idle scanlines added to each frame for the `overclock` percentage
+    pub fn extra_scanlines(&self, region: Region) -> u16 {
+        let overclock = self.overclock.min(MAX_OVERCLOCK) as u32;
+        (region.scanlines_per_frame() as u32 * overclock / 100) as u16
+    }
+}
+
+impl Default for EmulationConfig {
+    fn default() -> Self {
+        Self {
+            sprite_limit: true,
+            overscan: Overscan::NONE,
+            audio_filter: false,
+            reduce_dmc_popping: false,
+            turbo_period: None,
+            accuracy: Accuracy::default(),
+            overclock: 0,
+        }
+    }
+}
@@ -0,0 +1,579 @@
+use crate::{
+    apu::DEFAULT_SAMPLE_RATE,
+    cartridge::{tests::nrom_image, vs_system::CoinSlot, Cartridge},
+    clock::Region,
+    disasm::asm::assemble,
+    input::{ButtonState, Controller, Device},
+    memory::ram::RamPattern,
+    ppu::{palette::Palette, Overscan, PpuCtrl, PpuStatus, Variant, VBLANK_SCANLINE},
+};
+
+use super::{Accuracy, EmulationConfig, Frame, Nes, MAX_OVERCLOCK};
+
+mod allocations;
+
+/// NROM cartridge with `program` at $8000, and the reset vector pointing to it
+fn cartridge(program: &[u8]) -> Cartridge {
+    Cartridge::from_ines(&nrom_image(program, 1, 0)).unwrap()
+}
+
+#[test]
+fn power_on() {
+    let mut nes = Nes::new();
+    // no cartridge, nothing happens
+    nes.step_cycle();
+    assert_eq!(nes.cycle(), 0);
+
+    // LDX #$42, LDX $0010
+    nes.insert_cartridge(cartridge(&[0xA2, 0x42, 0xAE, 0x10, 0x00]));
+    nes.power_cycle();
+    nes.ram_mut().store(0x10, 0x24);
+
+    // reset sequence
+    nes.step_instruction();
+    assert_eq!(nes.cycle(), 7);
+    assert_eq!(nes.cpu().program_counter, 0x8000);
+
+    nes.step_instruction();
+    assert_eq!(nes.cpu().x_index, 0x42);
+    nes.step_instruction();
+    assert_eq!(nes.cpu().x_index, 0x24);
+    assert_eq!(nes.cycle(), 7 + 2 + 4);
+    assert_eq!(nes.ppu().dot(), 3 * 13);
+}
+
+#[test]
+fn missing_chr_memory() {
+    // NES 2.0 header without CHR ROM or CHR RAM, and a program that turns on rendering
+    // LDA #$18, STA $2001, JMP $8005
+    let mut image = nrom_image(&[0xA9, 0x18, 0x8D, 0x01, 0x20, 0x4C, 0x05, 0x80], 0, 0);
+    image[7] = 0x08;
+    let cartridge = Cartridge::from_ines(&image).unwrap();
+    assert!(cartridge.has_chr_ram());
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    nes.power_cycle();
+    nes.run_frame();
+    assert_eq!(nes.ppu().frame(), 1);
+}
+
+#[test]
+fn run_frame() {
+    let mut nes = Nes::new();
+    assert!(nes.run_frame().audio.is_empty());
+
+    // LDX #$A2 all the way, enough for more than a frame
+    nes.insert_cartridge(cartridge(&[0xA2; 0x7FF0]));
+    nes.power_cycle();
+    let frame = nes.run_frame();
+    assert_eq!(frame.video.len(), Frame::WIDTH * Frame::HEIGHT);
+    assert_eq!(frame.to_rgb().len(), Frame::WIDTH * Frame::HEIGHT * 3);
+    assert_eq!(frame.to_rgba().len(), Frame::WIDTH * Frame::HEIGHT * 4);
+    #[cfg(feature = "png")]
+    {
+        // the size in the header
+        let size = |png: &[u8]| (png[16..20].to_vec(), png[20..24].to_vec());
+        let png = frame.to_png_bytes(crate::png::Overscan::NONE);
+        assert_eq!(size(&png), (vec![0, 0, 1, 0], vec![0, 0, 0, 240]));
+        let png = frame.to_png_bytes(crate::png::Overscan::NTSC);
+        assert_eq!(size(&png), (vec![0, 0, 1, 0], vec![0, 0, 0, 224]));
+    }
+    // roughly a 60th of a second
+    let expected_samples = DEFAULT_SAMPLE_RATE as usize / 60;
+    assert!(frame.audio.len().abs_diff(expected_samples) <= 2);
+
+    assert_eq!(nes.ppu().frame(), 1);
+    // 262 scanlines of 341 dots, a CPU cycle is 3 dots, so the frame ends in the middle of a cycle
+    assert_eq!((nes.ppu().scanline(), nes.ppu().dot()), (0, 1));
+    assert_eq!(nes.cycle(), (262 * 341_u64).div_ceil(3));
+}
+
+#[test]
+fn partial_stepping() {
+    let mut nes = Nes::new();
+    nes.run_cycles(10);
+    assert_eq!(nes.cycle(), 0);
+
+    nes.insert_cartridge(cartridge(&[0xA2; 0x7FF0]));
+    nes.power_cycle();
+    nes.run_cycles(10);
+    assert_eq!(nes.cycle(), 10);
+
+    nes.run_scanline();
+    assert_eq!(nes.ppu().scanline(), 1);
+    assert!(nes.ppu().dot() < 3);
+
+    // NMIs are disabled
+    assert!(!nes.run_until_nmi());
+    assert_eq!(nes.ppu().scanline(), 1);
+
+    // keep the CPU from running out of instructions
+    nes.cpu.program_counter = 0x8000;
+    nes.run_until_vblank();
+    assert_eq!(nes.ppu().scanline(), VBLANK_SCANLINE);
+    assert!(nes.ppu().status.contains(PpuStatus::VBLANK));
+    assert!(nes.ppu().dot() < 4);
+
+    nes.ppu.ctrl.insert(PpuCtrl::NMI_ENABLE);
+    nes.ppu.status.remove(PpuStatus::VBLANK);
+    nes.cpu.program_counter = 0x8000;
+    assert!(nes.run_until_nmi());
+    assert_eq!(
+        (nes.ppu().frame(), nes.ppu().scanline()),
+        (2, VBLANK_SCANLINE)
+    );
+}
+
+#[test]
+fn reset_and_power_cycle() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge(&[0xA2; 0x7FF0]));
+    nes.set_ram_pattern(RamPattern::Alternating);
+    nes.power_cycle();
+    assert_eq!(nes.ram().load(0x03), 0x00);
+    assert_eq!(nes.ram().load(0x04), 0xFF);
+
+    nes.ram_mut().store(0x10, 0x42);
+    nes.run_cycles(100);
+    nes.reset();
+    // the current instruction is finished first, then the reset sequence runs
+    nes.step_instruction();
+    nes.step_instruction();
+    assert_eq!(nes.cpu().program_counter, 0x8000);
+    assert_eq!(nes.ram().load(0x10), 0x42);
+    // the cycle count keeps going, only a power cycle resets it
+    assert!(nes.cycle() > 100);
+
+    nes.set_ram_pattern(RamPattern::Random(1));
+    nes.power_cycle();
+    assert_eq!(nes.cycle(), 0);
+    let random = nes.ram().clone();
+    nes.power_cycle();
+    assert_eq!(format!("{:?}", nes.ram()), format!("{random:?}"));
+    assert_ne!(random.load(0x10), random.load(0x11));
+    // OAM and the palette are filled too, with 6 bit colors
+    let oam = nes.ppu().oam;
+    assert_ne!(oam, [0; 256]);
+    assert!(nes.ppu().palette.iter().all(|&color| color < 0x40));
+    nes.power_cycle();
+    assert_eq!(nes.ppu().oam, oam);
+
+    nes.set_ram_pattern(RamPattern::AlternatingPages);
+    nes.power_cycle();
+    assert_eq!(nes.ram().load(0x0FF), 0x00);
+    assert_eq!(nes.ram().load(0x100), 0xFF);
+    assert_eq!(nes.ppu().oam, [0; 256]);
+    assert_eq!(nes.ppu().palette, [0; 32]);
+}
+
+/// The dummy write of a read-modify-write instruction reaches the PPU, which moves its address
+#[test]
+fn read_modify_write_ppu_data() {
+    #[rustfmt::skip]
+    let mut nes = Nes::builder().build_with(cartridge(&[
+        // LDA #$20, STA $2006, LDA #$00, STA $2006
+        0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+        // LDA $2007 to fill the read buffer, INC $2007
+        0xAD, 0x07, 0x20, 0xEE, 0x07, 0x20,
+    ]));
+    nes.vram.store(0x000, 0x41);
+    // the reset sequence, then the program
+    for _ in 0..7 {
+        nes.step_instruction();
+    }
+
+    // the value read from $2000 is written to $2002, and the incremented one to $2003
+    assert_eq!(nes.vram.load(0x002), 0x41);
+    assert_eq!(nes.vram.load(0x003), 0x42);
+    assert_eq!(nes.ppu().vram_address(), 0x2004);
+}
+
+/// The dummy read of an indexed store at the address without the carry can hit a register
+#[test]
+fn indexed_store_dummy_read() {
+    #[rustfmt::skip]
+    let mut nes = Nes::builder().build_with(cartridge(&[
+        // LDX #$10, STA $3FF2,X
+        0xA2, 0x10, 0x9D, 0xF2, 0x3F,
+    ]));
+    nes.step_instruction();
+    nes.step_instruction();
+    nes.ppu.status.insert(PpuStatus::VBLANK);
+
+    // reads $3F02, a mirror of PPUSTATUS, before writing to $4002
+    nes.step_instruction();
+    assert!(!nes.ppu().status.contains(PpuStatus::VBLANK));
+}
+
+#[test]
+fn region() {
+    let mut nes = Nes::new();
+    let mut image = nrom_image(&[0xA2; 0x7FF0], 1, 0);
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    assert_eq!(nes.region(), Region::Ntsc);
+
+    // PAL bit of the iNES header
+    image[9] = 1;
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    assert_eq!(nes.region(), Region::Pal);
+    assert_eq!(nes.ppu().region(), Region::Pal);
+    for _ in 0..312 {
+        // the program isn't long enough for a whole frame
+        if nes.cpu().program_counter > 0xF000 {
+            nes.cpu_mut().program_counter = 0x8000;
+        }
+        nes.run_scanline();
+    }
+    assert_eq!((nes.ppu().frame(), nes.ppu().scanline()), (1, 0));
+
+    nes.set_region(Some(Region::Dendy));
+    assert_eq!(nes.region(), Region::Pal);
+    nes.power_cycle();
+    assert_eq!(nes.region(), Region::Dendy);
+    assert_eq!(nes.apu().region(), Region::Dendy);
+
+    let state = nes.save_state().unwrap();
+    nes.set_region(None);
+    nes.power_cycle();
+    assert_eq!(nes.region(), Region::Pal);
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.ppu().region(), Region::Dendy);
+}
+
+#[test]
+fn vs_system() {
+    let mut nes = Nes::new();
+    nes.set_ppu_variant(Variant::Ricoh2C05A);
+    assert_eq!(nes.palette(), &Palette::RGB);
+    // LDA $4016, LDX $4017
+    let mut image = nrom_image(&[0xAD, 0x16, 0x40, 0xAE, 0x17, 0x40], 1, 0);
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    assert_eq!(nes.ppu().variant(), Variant::Ricoh2C05A);
+
+    // NES 2.0 VS System header for an RP2C04-0004
+    image[7] = 0x09;
+    image[13] = 0x05;
+    nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+    nes.power_cycle();
+    assert_eq!(nes.ppu().variant(), Variant::Ricoh2C04D);
+    assert_eq!(nes.palette(), &Palette::RGB_2C04_0004);
+    let vs = nes.cartridge_mut().unwrap().vs_system_mut().unwrap();
+    vs.set_dip_switches(0xFF);
+    vs.insert_coin(CoinSlot::One);
+    for _ in 0..3 {
+        nes.step_instruction();
+    }
+    // bit 7 of $4016 is always 0, and bit 1 of both
+    assert_eq!(nes.cpu().accumulator, 0b0011_1000);
+    assert_eq!(nes.cpu().x_index, 0b1111_1100);
+
+    nes.set_ppu_variant(Variant::Ricoh2C02);
+    nes.power_cycle();
+    assert_eq!(nes.ppu().variant(), Variant::Ricoh2C04D);
+    nes.insert_cartridge(cartridge(&[]));
+    nes.power_cycle();
+    assert_eq!(nes.ppu().variant(), Variant::Ricoh2C02);
+    assert_eq!(nes.palette(), &Palette::NTSC);
+}
+
+#[test]
+fn lag_frames() {
+    // LDX #$A2 never reads the controllers
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge(&[0xA2; 0x7FF0]));
+    nes.power_cycle();
+    nes.run_frame();
+    assert!(nes.is_lag_frame());
+    assert_eq!(nes.lag_frames(), 1);
+
+    // LDX $4016 all the way
+    let program: Vec<_> = [0xAE, 0x16, 0x40].repeat(0x2A00);
+    nes.insert_cartridge(cartridge(&program));
+    nes.power_cycle();
+    nes.run_frame();
+    // don't leave the CPU in the middle of an instruction
+    nes.step_instruction();
+    nes.cpu_mut().program_counter = 0x8000;
+    nes.run_frame();
+    assert!(!nes.is_lag_frame());
+    assert_eq!(nes.lag_frames(), 0);
+}
+
+/// Same as the `execution` fuzz target, with a few fixed seeds
+#[test]
+fn random_programs() {
+    let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+    let mut random = || {
+        // xorshift64
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as u8
+    };
+    for _ in 0..8 {
+        let mut program = Vec::new();
+        // writes to the PPU and APU registers
+        for _ in 0..32 {
+            let register = random() as u16;
+            let address = match register & 1 {
+                0 => 0x2000 + register % 8,
+                _ => 0x4000 + register % 0x18,
+            };
+            program.extend([0xA9, random(), 0x8D, address as u8, (address >> 8) as u8]);
+        }
+        program.extend((0..0x7F00).map(|_| random()));
+        let mut image = nrom_image(&program, 1, 0);
+        // random vectors, apart from the reset one
+        image[16 + 0x7FFA] = random();
+        image[16 + 0x7FFB] = random();
+        image[16 + 0x7FFE] = random();
+        image[16 + 0x7FFF] = random();
+
+        let mut nes = Nes::new();
+        nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+        nes.power_cycle();
+        for _ in 0..5 {
+            nes.run_frame();
+        }
+        let state = nes.save_state().unwrap();
+        nes.load_state(&state).unwrap();
+    }
+}
+
+/// Running in batches when no debugging tool is active has to give the same results as running cycle by cycle
+#[test]
+fn batched_execution() {
+    let program = assemble(
+        "
+        reset:  LDA #$80        ; NMIs on
+                STA $2000
+                LDA #$1E
+                STA $2001
+        loop:   INC $10
+                LDA $10
+                STA $4000       ; some APU activity
+                BNE loop
+                INC $11
+                JMP loop
+        nmi:    INC $12
+                LDA #$02        ; OAM DMA
+                STA $4014
+                LDA $12
+                STA $2005
+                STA $2005
+                RTI
+                .org $FFFA
+                .word nmi, reset, reset
+        ",
+    )
+    .unwrap();
+    let mut prg = vec![0; 0x8000];
+    program.write(|address, value| prg[address as usize - 0x8000] = value);
+    for region in [Region::Ntsc, Region::Pal] {
+        let image = nrom_image(&prg, 1, 0);
+        let consoles: Vec<_> = [false, true]
+            .into_iter()
+            .map(|observed| {
+                let mut nes = Nes::new();
+                nes.set_region(Some(region));
+                nes.insert_cartridge(Cartridge::from_ines(&image).unwrap());
+                nes.power_cycle();
+                if observed {
+                    // the profiler makes every cycle run on its own
+                    nes.start_profiler();
+                }
+                for _ in 0..10 {
+                    nes.run_frame();
+                }
+                nes.run_cycles(1000);
+                nes
+            })
+            .collect();
+        let [batched, observed] = &consoles[..] else {
+            unreachable!()
+        };
+        assert_eq!(batched.cycle(), observed.cycle());
+        assert_eq!(batched.lag_frames(), observed.lag_frames());
+        assert_eq!(
+            batched.save_state().unwrap(),
+            observed.save_state().unwrap()
+        );
+    }
+}
+
+#[test]
+fn instruction_accuracy() {
+    // count the NMIs and the frames the main loop saw
+    let program = assemble(
+        "
+        reset:  LDA #$80
+                STA $2000
+        wait:   BIT $2002
+                BPL wait
+                INC $11
+                JMP wait
+        nmi:    INC $10
+                RTI
+                .org $FFFA
+                .word nmi, reset, reset
+        ",
+    )
+    .unwrap();
+    let mut prg = vec![0; 0x8000];
+    program.write(|address, value| prg[address as usize - 0x8000] = value);
+    let run = |accuracy| {
+        let mut nes = Nes::new();
+        nes.set_accuracy(accuracy);
+        nes.insert_cartridge(cartridge(&prg));
+        nes.power_cycle();
+        for _ in 0..10 {
+            nes.run_frame();
+        }
+        nes
+    };
+    let accurate = run(Accuracy::Cycle);
+    let fast = run(Accuracy::Instruction);
+    assert_eq!(fast.accuracy(), Accuracy::Instruction);
+    // frames still end on the same dot
+    assert_eq!(fast.cycle(), accurate.cycle());
+    assert_eq!(fast.ppu().frame(), 10);
+    assert_eq!(fast.ram().load(0x10), accurate.ram().load(0x10));
+    assert_eq!(fast.ram().load(0x10), 10);
+    assert_eq!(fast.ram().load(0x11), accurate.ram().load(0x11));
+}
+
+#[test]
+fn builder() {
+    let palette = Palette::new(&[[1, 2, 3]; 64]);
+    let mut nes = Nes::builder()
+        .region(Region::Pal)
+        .accuracy(Accuracy::Instruction)
+        .ram_pattern(RamPattern::Ones)
+        .palette(palette.clone())
+        .sample_rate(48000)
+        .load(&nrom_image(&[], 1, 0))
+        .unwrap();
+    assert_eq!(nes.region(), Region::Pal);
+    assert_eq!(nes.accuracy(), Accuracy::Instruction);
+    assert_eq!(nes.ram().load(0x0123), 0xFF);
+    assert_eq!(nes.apu().sample_rate(), 48000);
+    assert_eq!(nes.run_frame().to_rgb()[..3], [1, 2, 3]);
+    assert_eq!(*nes.palette(), palette);
+    assert!(Nes::builder().load(b"nope").is_err());
+
+    let nes = Nes::builder().build();
+    assert!(nes.cartridge().is_none());
+    assert_eq!(nes.apu().sample_rate(), DEFAULT_SAMPLE_RATE);
+}
+
+#[test]
+fn config() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge(&[]));
+    nes.power_cycle();
+    assert_eq!(*nes.config(), EmulationConfig::default());
+
+    let config = EmulationConfig {
+        sprite_limit: false,
+        overscan: Overscan::NTSC,
+        audio_filter: true,
+        reduce_dmc_popping: true,
+        turbo_period: Some(3),
+        accuracy: Accuracy::Instruction,
+        overclock: 0,
+    };
+    nes.set_config(config);
+    assert_eq!(nes.accuracy(), Accuracy::Instruction);
+    assert!(!nes.ppu().sprite_limit());
+    assert!(nes.apu().has_output_filter());
+    assert!(nes.apu().reduce_dmc_popping());
+    let controller = nes.controller_mut(0).unwrap();
+    assert_eq!(controller.turbo().period(ButtonState::A), 3);
+
+    let frame = nes.run_frame();
+    let (width, height) = Frame::cropped_size(frame.overscan);
+    assert_eq!((width, height), (256, 224));
+    assert_eq!(frame.to_rgb_cropped().len(), width * height * 3);
+
+    // the options survive power cycles and new controllers
+    nes.power_cycle();
+    nes.connect(1, Device::Controller(Controller::new()));
+    assert!(!nes.ppu().sprite_limit());
+    assert!(nes.apu().has_output_filter());
+    let controller = nes.controller_mut(1).unwrap();
+    assert_eq!(controller.turbo().period(ButtonState::B), 3);
+}
+
+#[test]
+fn overclock() {
+    let mut nes = Nes::builder()
+        .config(EmulationConfig {
+            overclock: 50,
+            ..EmulationConfig::default()
+        })
+        .build_with(cartridge(&[0xA2; 0x7FF0]));
+    assert_eq!(nes.ppu().extra_scanlines(), 131);
+
+    // the frame is longer, but the audio is still a 60th of a second
+    nes.run_frame();
+    let frame = nes.run_frame();
+    let expected_samples = DEFAULT_SAMPLE_RATE as usize / 60;
+    assert!(frame.audio.len().abs_diff(expected_samples) <= 2);
+    let start = nes.cycle();
+    nes.run_frame();
+    assert_eq!(nes.cycle() - start, (262 + 131) * 341 / 3);
+
+    // VBlank lasts through the extra scanlines
+    nes.run_until_vblank();
+    nes.run_scanline();
+    assert!(nes.ppu().is_in_extra_scanline());
+    for _ in 0..131 {
+        assert!(nes.ppu().status.contains(PpuStatus::VBLANK));
+        nes.run_scanline();
+    }
+    assert!(!nes.ppu().is_in_extra_scanline());
+
+    // huge percentages are capped
+    let config = EmulationConfig {
+        overclock: u16::MAX,
+        ..Default::default()
+    };
+    assert_eq!(
+        config.extra_scanlines(Region::Pal),
+        312 * (MAX_OVERCLOCK / 100)
+    );
+    nes.set_config(config);
+    nes.run_frame();
+    assert_eq!(
+        nes.ppu().scanlines_per_frame(),
+        262 * (1 + MAX_OVERCLOCK / 100)
+    );
+
+    nes.set_config(EmulationConfig::default());
+    assert_eq!(nes.ppu().scanlines_per_frame(), 262);
+}
+
+#[test]
+fn threads() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Nes>();
+
+    let consoles: Vec<_> = (0..4u8)
+        .map(|value| {
+            std::thread::spawn(move || {
+                // LDA #value, STA $00, JMP $8004
+                let mut nes = Nes::new();
+                nes.insert_cartridge(cartridge(&[0xA9, value, 0x85, 0x00, 0x4C, 0x04, 0x80]));
+                nes.power_cycle();
+                nes.run_frame();
+                nes
+            })
+        })
+        .collect();
+    for (value, console) in consoles.into_iter().enumerate() {
+        assert_eq!(console.join().unwrap().ram().load(0x0000), value as u8);
+    }
+}
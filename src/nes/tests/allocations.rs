@@ -0,0 +1,100 @@
+//! Counts the heap allocations of the emulation, the steady state shouldn't make any
+//!
+//! The counting allocator is the global one of the whole test binary,
+//! it only counts on the thread that asked for it so other tests don't get in the way.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    hint::black_box,
+};
+
+use crate::nes::{Frame, Nes};
+
+use super::cartridge;
+
+struct CountingAllocator;
+
+thread_local! {
+    /// Allocations made on this thread while counting, `None` when not counting
+    static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn count() {
+    // the thread local can be gone while a thread is shutting down
+    let _ = ALLOCATIONS.try_with(|allocations| {
+        allocations.set(allocations.get().map(|count| count + 1));
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Number of allocations `f` made on this thread
+fn allocations(f: impl FnOnce()) -> usize {
+    ALLOCATIONS.with(|allocations| allocations.set(Some(0)));
+    f();
+    ALLOCATIONS.with(|allocations| allocations.take().unwrap_or_default())
+}
+
+#[test]
+fn counting() {
+    assert_eq!(allocations(|| drop(black_box(vec![0u8; 16]))), 1);
+    assert_eq!(allocations(|| ()), 0);
+}
+
+#[test]
+fn steady_state() {
+    // rendering on, an NMI handler, and some sound
+    #[rustfmt::skip]
+    let mut program = vec![
+        0xA9, 0x1E, 0x8D, 0x01, 0x20, // LDA #$1E, STA $2001
+        0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80, STA $2000
+        0xA9, 0x0F, 0x8D, 0x15, 0x40, // LDA #$0F, STA $4015
+        0xA9, 0xBF, 0x8D, 0x00, 0x40, // LDA #$BF, STA $4000
+        0xA9, 0x10, 0x8D, 0x03, 0x40, // LDA #$10, STA $4003
+        0x4C, 0x19, 0x80,             // loop: JMP loop
+    ];
+    program.resize(0x7FF9, 0xEA);
+    // RTI for the NMI handler
+    program.extend([0x40, 0xF9, 0xFF]);
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge(&program));
+    nes.set_sample_rate(48_000);
+    nes.power_cycle();
+    // kept through the power cycle, the buffer was made for it
+    assert_eq!(nes.apu().sample_rate(), 48_000);
+
+    let count = allocations(|| {
+        for _ in 0..60 {
+            let frame = nes.run_frame();
+            assert!(!frame.audio.is_empty());
+        }
+    });
+    assert_eq!(count, 0);
+
+    let mut rgba = vec![0; Frame::WIDTH * Frame::HEIGHT * 4];
+    let count = allocations(|| nes.run_frame().write_rgba(&mut rgba));
+    assert_eq!(count, 0);
+}
@@ -0,0 +1,130 @@
+//! Deterministic lockstep execution, the base of rollback netplay
+//!
+//! The emulation only depends on the state of the console and the input,
+//! so two consoles started the same way and given the same input every frame stay in sync.
+//! [`Lockstep`] makes sure of the parts that could differ: it powers the console on
+//! from a known state, only takes input as an [`InputFrame`] before every frame,
+//! which the game sees when it strobes the controllers like it would any other input,
+//! and runs whole frames even if a breakpoint is hit.
+//! The input goes over the network in the compact format of [`InputFrame::encode`].
+//!
+//! Rollback is [`Lockstep::save`] every frame, and [`Lockstep::load`] when the input of
+//! a remote player turns out to be different from the predicted one, before running
+//! the frames again with the right input.
+//! [`FrameChecksum`]s can be exchanged to notice desyncs.
+//!
+//! Settings that aren't part of the save state have to be the same on both sides:
+//! the [`RamPattern`](crate::memory::ram::RamPattern), the [`Accuracy`](crate::nes::Accuracy),
+//! the forced region, the Game Genie codes and the devices plugged into the ports.
+
+use alloc::vec::Vec;
+
+use crate::{cartridge::database::crc32, input::log::InputFrame, nes::Nes, state::StateError};
+
+#[cfg(test)]
+mod tests;
+
+/// The checksum of the state of the console after a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameChecksum {
+    /// Frames run since the session started
+    pub frame: u64,
+    /// CRC32 of the save state
+    pub crc32: u32,
+}
+
+impl FrameChecksum {
+    pub const SIZE: usize = 12;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.frame.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let (frame, crc32) = bytes.split_at(8);
+        Self {
+            frame: u64::from_le_bytes(frame.try_into().unwrap()),
+            crc32: u32::from_le_bytes(crc32.try_into().unwrap()),
+        }
+    }
+}
+
+/// A console run frame by frame from input only
+#[derive(Debug)]
+pub struct Lockstep {
+    nes: Nes,
+    frame: u64,
+}
+
+impl Lockstep {
+    /// Start a session with the cartridge in `nes`, powering it on
+    ///
+    /// The input provider is removed, since it could poll input from outside of the session.
+    /// Fails if there's no cartridge inserted.
+    pub fn new(mut nes: Nes) -> Result<Self, StateError> {
+        if nes.cartridge().is_none() {
+            return Err(StateError::NoCartridge);
+        }
+        nes.set_input_provider(None);
+        nes.power_cycle();
+        Ok(Self { nes, frame: 0 })
+    }
+
+    pub fn nes(&self) -> &Nes {
+        &self.nes
+    }
+
+    /// Give the console back, to leave the session
+    pub fn into_nes(self) -> Nes {
+        self.nes
+    }
+
+    /// Frames run since the session started
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Apply the input and run a whole frame
+    pub fn advance(&mut self, input: &InputFrame) {
+        input.apply(&mut self.nes);
+        while self.nes.run_frame().stop.is_some() {}
+        self.frame += 1;
+    }
+
+    /// The checksum of the console as it is now
+    ///
+    /// It costs about as much as [`Lockstep::save`], so it's usually only done every few frames
+    pub fn checksum(&self) -> FrameChecksum {
+        FrameChecksum {
+            frame: self.frame,
+            crc32: crc32(&self.save_state()),
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        // the session can't start without a cartridge, and it can't be removed from it
+        self.nes
+            .save_state()
+            .expect("the cartridge is always inserted")
+    }
+
+    /// A snapshot to roll back to, with the frame number
+    pub fn save(&self) -> Vec<u8> {
+        let mut snapshot = self.frame.to_le_bytes().to_vec();
+        snapshot.extend(self.save_state());
+        snapshot
+    }
+
+    /// Roll back to a snapshot made by [`Lockstep::save`]
+    pub fn load(&mut self, snapshot: &[u8]) -> Result<(), StateError> {
+        let (frame, state) = snapshot
+            .split_first_chunk::<8>()
+            .ok_or(StateError::Truncated)?;
+        self.nes.load_state(state)?;
+        self.frame = u64::from_le_bytes(*frame);
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    disasm::asm::assemble,
+    input::{
+        log::{ConsoleEvents, InputFrame},
+        ButtonState,
+    },
+    nes::Nes,
+    state::StateError,
+};
+
+use super::{FrameChecksum, Lockstep};
+
+/// A session with a game that sums up the buttons of player 1 in $10-$11
+fn session() -> Lockstep {
+    let program = assemble(
+        "
+        reset:  LDA #$01
+                STA $4016
+                LDA #$00
+                STA $4016
+                LDX #$08
+        read:   LDA $4016
+                LSR
+                ROL $12
+                DEX
+                BNE read
+                LDA $12
+                CLC
+                ADC $10
+                STA $10
+                BCC reset
+                INC $11
+                JMP reset
+                .org $FFFA
+                .word reset, reset, reset
+        ",
+    )
+    .unwrap();
+    let mut prg = vec![0; 0x8000];
+    program.write(|address, value| prg[address as usize - 0x8000] = value);
+    let mut nes = Nes::new();
+    nes.insert_cartridge(Cartridge::from_ines(&nrom_image(&prg, 1, 0)).unwrap());
+    Lockstep::new(nes).unwrap()
+}
+
+fn input(frame: u64) -> InputFrame {
+    InputFrame {
+        events: ConsoleEvents::empty(),
+        buttons: [
+            ButtonState::from_bits_retain(frame as u8),
+            ButtonState::empty(),
+            ButtonState::empty(),
+            ButtonState::empty(),
+        ],
+    }
+}
+
+#[test]
+fn in_sync() {
+    assert_eq!(
+        Lockstep::new(Nes::new()).unwrap_err(),
+        StateError::NoCartridge
+    );
+
+    let mut local = session();
+    let mut remote = session();
+    // whatever the console did before, a session starts from power on
+    remote.nes.run_frame();
+    let mut remote = Lockstep::new(remote.into_nes()).unwrap();
+    assert_eq!(local.checksum(), remote.checksum());
+    for frame in 0..10 {
+        local.advance(&input(frame));
+        remote.advance(&input(frame));
+        assert_eq!(local.checksum(), remote.checksum());
+    }
+    assert_eq!(local.frame(), 10);
+
+    remote.advance(&input(1));
+    local.advance(&input(2));
+    assert_ne!(local.checksum(), remote.checksum());
+}
+
+#[test]
+fn rollback() {
+    let mut lockstep = session();
+    lockstep.advance(&input(1));
+    let snapshot = lockstep.save();
+    for frame in 0..5 {
+        lockstep.advance(&input(frame));
+    }
+    let checksum = lockstep.checksum();
+    assert_eq!(checksum.frame, 6);
+
+    // a wrong prediction, then running again with the right input
+    lockstep.load(&snapshot).unwrap();
+    assert_eq!(lockstep.frame(), 1);
+    lockstep.advance(&input(7));
+    lockstep.load(&snapshot).unwrap();
+    for frame in 0..5 {
+        lockstep.advance(&input(frame));
+    }
+    assert_eq!(lockstep.checksum(), checksum);
+
+    assert_eq!(lockstep.load(&snapshot[..4]), Err(StateError::Truncated));
+    assert_eq!(FrameChecksum::from_bytes(checksum.to_bytes()), checksum);
+}
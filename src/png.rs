@@ -0,0 +1,236 @@
+//! PNG encoding of screenshots, with the `png` feature
+//!
+//! The encoder only writes what's needed for the picture of a frame: 8-bit RGB,
+//! compressed with the fixed Huffman codes of deflate and a small LZ77 matcher.
+//! That's enough to compress the flat areas most games draw, without any dependencies.
+
+use alloc::{vec, vec::Vec};
+
+use crate::cartridge::database::crc32_update;
+pub use crate::ppu::Overscan;
+
+#[cfg(test)]
+mod tests;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Encode an 8-bit RGB picture, 3 bytes per pixel row by row, as a PNG image
+///
+/// # Panics
+/// If `rgb` isn't `width * height * 3` bytes long, or the picture is empty
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3);
+    assert!(width > 0 && height > 0, "a PNG image can't be empty");
+
+    // every row starts with the filter it uses, 0 is none
+    let mut raw = Vec::with_capacity(rgb.len() + height);
+    for row in rgb.chunks_exact(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filtering beyond the per row one, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32_update(crc32_update(0, kind), data);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (a, b) = bytes.iter().fold((1, 0), |(a, b), &byte| {
+        let a = (a + byte as u32) % MODULUS;
+        (a, (b + a) % MODULUS)
+    });
+    (b << 16) | a
+}
+
+/// Bits of a deflate stream, filled from the least significant bit of every byte
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, len: u32) {
+        self.bits |= value << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Huffman codes are stored from their most significant bit
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW: usize = 32 * 1024;
+const HASH_BITS: u32 = 15;
+/// Earlier positions with the same hash looked at to find a match
+const MAX_CHAIN: usize = 32;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Write a literal byte, a length or the end of the block with the fixed literal/length codes
+fn write_symbol(output: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => output.write_code(0x30 + symbol, 8),
+        144..=255 => output.write_code(0x190 + symbol - 144, 9),
+        256..=279 => output.write_code(symbol - 256, 7),
+        _ => output.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(output: &mut BitWriter, len: usize, distance: usize) {
+    let code = LENGTH_BASES.partition_point(|&base| base as usize <= len) - 1;
+    write_symbol(output, 257 + code as u32);
+    let extra = (len - LENGTH_BASES[code] as usize) as u32;
+    output.write(extra, LENGTH_EXTRA_BITS[code] as u32);
+
+    let code = DISTANCE_BASES.partition_point(|&base| base as usize <= distance) - 1;
+    output.write_code(code as u32, 5);
+    let extra = (distance - DISTANCE_BASES[code] as usize) as u32;
+    output.write(extra, DISTANCE_EXTRA_BITS[code] as u32);
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Finds earlier repetitions of the bytes at a position
+struct Matcher<'a> {
+    bytes: &'a [u8],
+    /// The last position with a hash
+    head: Vec<usize>,
+    /// For every position, the previous one with the same hash
+    previous: Vec<usize>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            head: vec![usize::MAX; 1 << HASH_BITS],
+            previous: vec![usize::MAX; bytes.len()],
+        }
+    }
+
+    fn insert(&mut self, position: usize) {
+        if position + MIN_MATCH <= self.bytes.len() {
+            let hash = hash(&self.bytes[position..]);
+            self.previous[position] = self.head[hash];
+            self.head[hash] = position;
+        }
+    }
+
+    /// The length and distance of the longest match found, the length is 0 if there's none
+    fn find(&self, position: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if position + MIN_MATCH > self.bytes.len() {
+            return best;
+        }
+        let max_len = MAX_MATCH.min(self.bytes.len() - position);
+        let mut candidate = self.head[hash(&self.bytes[position..])];
+        for _ in 0..MAX_CHAIN {
+            if candidate == usize::MAX || position - candidate > WINDOW {
+                break;
+            }
+            let len = self.bytes[candidate..]
+                .iter()
+                .zip(&self.bytes[position..position + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.0 {
+                best = (len, position - candidate);
+                if len == max_len {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate];
+        }
+        best
+    }
+}
+
+/// Compress `bytes` into a single deflate block with the fixed codes
+fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut output = BitWriter {
+        bytes: Vec::with_capacity(bytes.len() / 4),
+        bits: 0,
+        len: 0,
+    };
+    // last block, fixed codes
+    output.write(1, 1);
+    output.write(1, 2);
+
+    let mut matcher = Matcher::new(bytes);
+    let mut position = 0;
+    while position < bytes.len() {
+        let (len, distance) = matcher.find(position);
+        if len >= MIN_MATCH {
+            write_match(&mut output, len, distance);
+            for position in position..position + len {
+                matcher.insert(position);
+            }
+            position += len;
+        } else {
+            write_symbol(&mut output, bytes[position] as u32);
+            matcher.insert(position);
+            position += 1;
+        }
+    }
+    write_symbol(&mut output, 256);
+    output.finish()
+}
+
+/// Wrap a deflate stream of `bytes` in the zlib format PNG uses
+fn zlib(bytes: &[u8]) -> Vec<u8> {
+    // deflate with a 32KB window, no dictionary, default compression
+    let mut output = vec![0x78, 0x9C];
+    output.extend(deflate(bytes));
+    output.extend_from_slice(&adler32(bytes).to_be_bytes());
+    output
+}
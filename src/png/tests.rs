@@ -0,0 +1,128 @@
+use super::*;
+
+/// Reads the bits of a deflate stream
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u32 {
+        let bit = (self.bytes[self.position / 8] >> (self.position % 8)) & 1;
+        self.position += 1;
+        bit as u32
+    }
+
+    fn bits(&mut self, len: u8) -> usize {
+        (0..len).fold(0, |value, shift| value | (self.bit() as usize) << shift)
+    }
+
+    fn code(&mut self, len: u32) -> u32 {
+        (0..len).fold(0, |code, _| (code << 1) | self.bit())
+    }
+
+    fn symbol(&mut self) -> u32 {
+        let code = self.code(7);
+        if code <= 0x17 {
+            return code + 256;
+        }
+        let code = (code << 1) | self.bit();
+        match code {
+            0x30..=0xBF => code - 0x30,
+            0xC0..=0xC7 => code - 0xC0 + 280,
+            _ => ((code << 1) | self.bit()) - 0x190 + 144,
+        }
+    }
+}
+
+/// Decompress a single block with the fixed codes, which is all the encoder writes
+fn inflate(bytes: &[u8]) -> Vec<u8> {
+    let mut input = BitReader { bytes, position: 0 };
+    assert_eq!((input.bits(1), input.bits(2)), (1, 1));
+    let mut output: Vec<u8> = Vec::new();
+    loop {
+        let symbol = input.symbol() as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return output,
+            _ => {
+                let code = symbol - 257;
+                let len = LENGTH_BASES[code] as usize + input.bits(LENGTH_EXTRA_BITS[code]);
+                let code = input.code(5) as usize;
+                let distance =
+                    DISTANCE_BASES[code] as usize + input.bits(DISTANCE_EXTRA_BITS[code]);
+                for _ in 0..len {
+                    output.push(output[output.len() - distance]);
+                }
+            }
+        }
+    }
+}
+
+/// The type and data of every chunk, checking their CRCs
+fn chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    assert_eq!(png[..8], SIGNATURE);
+    let mut chunks = Vec::new();
+    let mut rest = &png[8..];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+        let data = &rest[8..8 + len];
+        let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+        assert_eq!(crc, crc32_update(crc32_update(0, &kind), data));
+        chunks.push((kind, data));
+        rest = &rest[12 + len..];
+    }
+    chunks
+}
+
+fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+    let chunks = chunks(png);
+    let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+    assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+    let header = chunks[0].1;
+    assert_eq!(header[8..], [8, 2, 0, 0, 0]);
+    let width = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+
+    let zlib = chunks[1].1;
+    assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+    let raw = inflate(&zlib[2..zlib.len() - 4]);
+    assert_eq!(zlib[zlib.len() - 4..], adler32(&raw).to_be_bytes());
+
+    let mut rgb = Vec::new();
+    for row in raw.chunks_exact(width as usize * 3 + 1) {
+        assert_eq!(row[0], 0);
+        rgb.extend_from_slice(&row[1..]);
+    }
+    (width, height, rgb)
+}
+
+#[test]
+fn round_trip() {
+    // flat areas, a gradient and noise, to use literals and both short and long matches
+    let (width, height) = (300, 20);
+    let mut seed = 1u32;
+    let rgb: Vec<u8> = (0..width * height * 3)
+        .map(|index| match index / (width * 3) {
+            0..=7 => 0x0F,
+            8..=11 => index as u8,
+            _ => {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            }
+        })
+        .collect();
+    let png = encode_rgb(width, height, &rgb);
+    assert_eq!(decode(&png), (width as u32, height as u32, rgb.clone()));
+    assert!(png.len() < rgb.len());
+
+    let (_, _, pixel) = decode(&encode_rgb(1, 1, &[1, 2, 3]));
+    assert_eq!(pixel, [1, 2, 3]);
+}
+
+#[test]
+fn adler() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
@@ -0,0 +1,681 @@
+//! Picture Processing Unit
+//!
+//! The PPU runs at 3 times the CPU clock, with each tick being one dot (pixel) of the output.
+//! A frame is 262 scanlines of 341 dots, where scanlines 0-239 are visible,
+//! VBlank starts on scanline 241 and 261 is the pre-render scanline.
+//! PAL and Dendy consoles have 312 scanlines per frame instead, see [`Region`].
+//!
+//! The CPU talks to the PPU through 8 registers at $2000-$2007, mirrored up to $3FFF,
+//! the PPU accesses pattern tables and nametables through the [`PpuMemoryMapping`].
+//!
+//! Details at https://www.nesdev.org/wiki/PPU_registers and https://www.nesdev.org/wiki/PPU_rendering
+
+use alloc::boxed::Box;
+
+use bitflags::bitflags;
+use render::{BackgroundShifters, ScanlineSprite};
+
+use crate::{
+    clock::Region,
+    memory::{ram::RamPattern, PpuMemoryMapping},
+    ppu::palette::Palette,
+    state::{impl_state, impl_state_bits},
+};
+
+pub mod palette;
+pub mod pattern;
+mod render;
+#[cfg(test)]
+mod tests;
+
+pub const FRAME_WIDTH: usize = 256;
+pub const FRAME_HEIGHT: usize = 240;
+
+/// Pixels cut off at the edges of the picture
+///
+/// TVs don't show the whole picture, and games often leave garbage in the parts they hide,
+/// like the scrolling artifacts at the top and bottom.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Overscan {
+    /// The whole picture
+    pub const NONE: Self = Self {
+        top: 0,
+        bottom: 0,
+        left: 0,
+        right: 0,
+    };
+
+    /// The 8 lines at the top and bottom that most NTSC TVs hide
+    pub const NTSC: Self = Self {
+        top: 8,
+        bottom: 8,
+        left: 0,
+        right: 0,
+    };
+}
+
+/// The model of PPU in the console
+///
+/// Home consoles have a 2C02, or a 2C07 with PAL timing (see [`Region`]). The arcade machines
+/// have RGB PPUs, which output colors from a fixed palette where emphasis turns a channel all the way up.
+/// The 2C04s each have the colors in their own scrambled order.
+///
+/// Details at https://www.nesdev.org/wiki/PPU_palettes#2C03_and_2C05
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Variant {
+    #[default]
+    Ricoh2C02,
+    /// RP2C03B and RC2C03B/C, used in the PlayChoice-10 and some VS System games
+    Ricoh2C03,
+    /// RP2C04-0001
+    Ricoh2C04A,
+    /// RP2C04-0002
+    Ricoh2C04B,
+    /// RP2C04-0003
+    Ricoh2C04C,
+    /// RP2C04-0004
+    Ricoh2C04D,
+    /// RC2C05-01
+    Ricoh2C05A,
+    /// RC2C05-02
+    Ricoh2C05B,
+    /// RC2C05-03
+    Ricoh2C05C,
+    /// RC2C05-04
+    Ricoh2C05D,
+    /// RC2C05-05
+    Ricoh2C05E,
+}
+
+impl Variant {
+    /// Whether the PPU outputs RGB instead of a video signal
+    pub fn is_rgb(self) -> bool {
+        self != Self::Ricoh2C02
+    }
+
+    /// The colors the PPU outputs
+    pub fn palette(self) -> Palette {
+        match self {
+            Self::Ricoh2C02 => Palette::NTSC,
+            Self::Ricoh2C04A => Palette::RGB_2C04_0001,
+            Self::Ricoh2C04B => Palette::RGB_2C04_0002,
+            Self::Ricoh2C04C => Palette::RGB_2C04_0003,
+            Self::Ricoh2C04D => Palette::RGB_2C04_0004,
+            _ => Palette::RGB,
+        }
+    }
+
+    /// Whether PPUCTRL and PPUMASK are at each other's addresses, as on the 2C05s
+    pub fn swaps_ctrl_and_mask(self) -> bool {
+        matches!(
+            self,
+            Self::Ricoh2C05A
+                | Self::Ricoh2C05B
+                | Self::Ricoh2C05C
+                | Self::Ricoh2C05D
+                | Self::Ricoh2C05E
+        )
+    }
+
+    /// Value in the low bits of PPUSTATUS that games check to find out which 2C05 they run on,
+    /// `None` if the bits are left over from the last access like on the other PPUs
+    ///
+    /// The RC2C05-02's ID also sets the sprite overflow bit.
+    pub fn status_id(self) -> Option<u8> {
+        match self {
+            Self::Ricoh2C05A => Some(0x1B),
+            Self::Ricoh2C05B => Some(0x3D),
+            Self::Ricoh2C05C => Some(0x1C),
+            Self::Ricoh2C05D => Some(0x1B),
+            Self::Ricoh2C05E => Some(0x00),
+            _ => None,
+        }
+    }
+}
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+/// Scanlines per frame on NTSC, see [`Region::scanlines_per_frame`] for the others
+pub const SCANLINES_PER_FRAME: u16 = 262;
+/// VBlank scanline on NTSC, see [`Region::vblank_scanline`] for the others
+pub const VBLANK_SCANLINE: u16 = 241;
+
+bitflags! {
+    /// PPUCTRL ($2000)
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct PpuCtrl: u8 {
+        const NAMETABLE_X = 1;
+        const NAMETABLE_Y = 1 << 1;
+        /// Add 32 to the VRAM address after a PPUDATA access instead of 1
+        const INCREMENT_32 = 1 << 2;
+        const SPRITE_PATTERN_TABLE = 1 << 3;
+        const BACKGROUND_PATTERN_TABLE = 1 << 4;
+        const TALL_SPRITES = 1 << 5;
+        const PPU_SLAVE = 1 << 6;
+        /// Generate an NMI at the start of VBlank
+        const NMI_ENABLE = 1 << 7;
+    }
+
+    /// PPUMASK ($2001)
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct PpuMask: u8 {
+        const GREYSCALE = 1;
+        const SHOW_BACKGROUND_LEFT = 1 << 1;
+        const SHOW_SPRITES_LEFT = 1 << 2;
+        const SHOW_BACKGROUND = 1 << 3;
+        const SHOW_SPRITES = 1 << 4;
+        const EMPHASIZE_RED = 1 << 5;
+        const EMPHASIZE_GREEN = 1 << 6;
+        const EMPHASIZE_BLUE = 1 << 7;
+    }
+
+    /// PPUSTATUS ($2002)
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct PpuStatus: u8 {
+        const SPRITE_OVERFLOW = 1 << 5;
+        const SPRITE_ZERO_HIT = 1 << 6;
+        const VBLANK = 1 << 7;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ppu {
+    pub ctrl: PpuCtrl,
+    pub mask: PpuMask,
+    pub status: PpuStatus,
+
+    /// Object Attribute Memory, holds 64 sprites of 4 bytes each
+    pub oam: [u8; 256],
+    pub oam_address: u8,
+    pub palette: [u8; 32],
+
+    /// Current VRAM address, also used as the scroll position while rendering
+    vram_address: u16,
+    /// Temporary VRAM address, the top left corner of the screen
+    temp_address: u16,
+    fine_x: u8,
+    /// Shared first/second write toggle of PPUSCROLL and PPUADDR
+    write_toggle: bool,
+    /// PPUDATA reads are delayed by one read
+    read_buffer: u8,
+    /// Value of the last write to any register, reads of write-only registers return it
+    io_latch: u8,
+
+    /// Page written to OAMDMA ($4014) that hasn't been copied yet
+    oam_dma_page: Option<u8>,
+
+    background: BackgroundShifters,
+    sprites: [ScanlineSprite; 8],
+    sprite_count: usize,
+    /// Sprites after the first 8 on the scanline when the limit is off, not part of the state
+    extra_sprites: Box<[ScanlineSprite; 56]>,
+    extra_sprite_count: usize,
+    /// One pixel per dot of the visible scanlines, see [`Ppu::framebuffer`]
+    framebuffer: Box<[u16; FRAME_WIDTH * FRAME_HEIGHT]>,
+
+    /// Dots the PPU is behind the rest of the console, run in a batch by [`Ppu::catch_up`]
+    pending_dots: u32,
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    /// Not part of the state, the console sets it from its clock
+    region: Region,
+    /// Frames skipped between drawn ones, see [`Ppu::set_frame_skip`], not part of the state either
+    frame_skip: u32,
+    /// See [`Ppu::set_sprite_limit`], not part of the state either
+    sprite_limit: bool,
+    /// See [`Ppu::set_extra_scanlines`], not part of the state either
+    extra_scanlines: u16,
+    /// See [`Ppu::set_variant`], not part of the state either
+    variant: Variant,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A PPU with OAM and the palette filled according to the pattern, like they are at power on
+    ///
+    /// Palette entries only have 6 bits, the top 2 bits of the pattern are dropped.
+    pub fn with_pattern(pattern: RamPattern) -> Self {
+        let mut ppu = Self::new();
+        pattern.fill(&mut ppu.oam);
+        pattern.fill(&mut ppu.palette);
+        for color in &mut ppu.palette {
+            *color &= 0x3F;
+        }
+        ppu
+    }
+
+    /// Apply the effects of the reset button
+    ///
+    /// Unlike the power-on state, OAM, the palette and the VRAM address are left alone
+    pub fn reset(&mut self) {
+        self.ctrl = PpuCtrl::empty();
+        self.mask = PpuMask::empty();
+        self.write_toggle = false;
+        self.read_buffer = 0;
+        self.fine_x = 0;
+        self.temp_address = 0;
+        self.scanline = 0;
+        self.dot = 0;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Switch to the frame timing of another region
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.scanline = self.scanline.min(self.pre_render_scanline());
+    }
+
+    pub fn frame_skip(&self) -> u32 {
+        self.frame_skip
+    }
+
+    /// Only draw one frame out of every `frame_skip + 1`, for fast-forwarding and running without a screen
+    ///
+    /// Skipped frames are rendered as usual, with the same sprite 0 hits, flags and timing,
+    /// only the pixels aren't written, so the framebuffer keeps the last drawn picture.
+    /// The last frame of every group is the one drawn, frames are counted by [`Ppu::frame`].
+    pub fn set_frame_skip(&mut self, frame_skip: u32) {
+        self.frame_skip = frame_skip;
+    }
+
+    pub fn sprite_limit(&self) -> bool {
+        self.sprite_limit
+    }
+
+    /// Draw at most 8 sprites per scanline like the hardware, or all of them
+    ///
+    /// Games flicker sprites to show more than 8 on a scanline, without the limit they're all drawn
+    /// and the flicker goes away. The sprite overflow flag is set either way.
+    /// Takes effect from the next scanline.
+    pub fn set_sprite_limit(&mut self, sprite_limit: bool) {
+        self.sprite_limit = sprite_limit;
+    }
+
+    pub fn extra_scanlines(&self) -> u16 {
+        self.extra_scanlines
+    }
+
+    /// Add idle scanlines right after the one VBlank starts on, to overclock the CPU
+    ///
+    /// The CPU keeps running while the PPU waits through them, so games get more time
+    /// in VBlank without anything changing for code that times itself against rendering.
+    /// See [`Ppu::is_in_extra_scanline`], takes effect right away.
+    /// Capped so that a PAL frame with them still has fewer than 65536 scanlines.
+    pub fn set_extra_scanlines(&mut self, extra_scanlines: u16) {
+        let max = u16::MAX - Region::Pal.scanlines_per_frame();
+        self.extra_scanlines = extra_scanlines.min(max);
+        self.scanline = self.scanline.min(self.pre_render_scanline());
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Switch to another model of PPU, the colors it outputs are set on the console separately
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
+    /// Scanlines per frame, including the extra ones
+    pub fn scanlines_per_frame(&self) -> u16 {
+        self.region.scanlines_per_frame() + self.extra_scanlines
+    }
+
+    /// Whether the PPU is in one of the extra scanlines, counting the dots postponed by [`Ppu::advance`]
+    pub fn is_in_extra_scanline(&self) -> bool {
+        let position =
+            self.scanline as u32 * DOTS_PER_SCANLINE as u32 + self.dot as u32 + self.pending_dots;
+        let scanline = position / DOTS_PER_SCANLINE as u32;
+        let vblank_scanline = self.region.vblank_scanline() as u32;
+        scanline > vblank_scanline && scanline <= vblank_scanline + self.extra_scanlines as u32
+    }
+
+    /// Whether the pixels of the current frame are written to the framebuffer
+    fn is_drawing(&self) -> bool {
+        self.frame % (self.frame_skip as u64 + 1) == self.frame_skip as u64
+    }
+
+    /// The last scanline of the frame, which prepares for rendering the next one
+    fn pre_render_scanline(&self) -> u16 {
+        self.scanlines_per_frame() - 1
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// Number of completed frames
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The picture, row by row
+    ///
+    /// The low 6 bits of each pixel are the color, bits 6-8 are the emphasis bits of PPUMASK,
+    /// [`palette::to_rgb`] converts them to RGB.
+    /// Pixels of the current frame are written as it's rendered,
+    /// so the whole picture is only complete at the end of the frame.
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer[..]
+    }
+
+    pub fn vram_address(&self) -> u16 {
+        self.vram_address
+    }
+
+    /// Whether the PPU is pulling the NMI line low
+    pub fn nmi(&self) -> bool {
+        self.ctrl.contains(PpuCtrl::NMI_ENABLE) && self.status.contains(PpuStatus::VBLANK)
+    }
+
+    pub fn is_rendering_enabled(&self) -> bool {
+        self.mask
+            .intersects(PpuMask::SHOW_BACKGROUND | PpuMask::SHOW_SPRITES)
+    }
+
+    /// Whether rendering is enabled on a visible or the pre-render scanline, when OAM is used for sprites
+    ///
+    /// Details at https://www.nesdev.org/wiki/PPU_registers#OAMDATA
+    fn is_rendering(&self) -> bool {
+        self.is_rendering_enabled()
+            && (self.scanline < FRAME_HEIGHT as u16 || self.scanline == self.pre_render_scanline())
+    }
+
+    /// Whether the sprites found for the next scanline are being cleared, at the start of visible scanlines
+    fn is_clearing_secondary_oam(&self) -> bool {
+        self.is_rendering() && self.scanline < FRAME_HEIGHT as u16 && matches!(self.dot, 1..=64)
+    }
+
+    /// Take the page that was written to OAMDMA, so that it can be copied to OAM
+    pub fn take_oam_dma_request(&mut self) -> Option<u8> {
+        self.oam_dma_page.take()
+    }
+
+    /// Handle a write to OAMDMA ($4014)
+    pub fn request_oam_dma(&mut self, page: u8) {
+        self.oam_dma_page = Some(page);
+    }
+
+    fn increment_vram_address(&mut self) {
+        let increment = if self.ctrl.contains(PpuCtrl::INCREMENT_32) {
+            32
+        } else {
+            1
+        };
+        self.vram_address = self.vram_address.wrapping_add(increment) & 0x7FFF;
+    }
+
+    fn palette_index(address: u16) -> usize {
+        let index = address as usize & 0x1F;
+        // sprite palettes' first colors mirror the background ones
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1C => index - 0x10,
+            _ => index,
+        }
+    }
+
+    /// Read a byte from the PPU address space, including the palette
+    pub fn load(&self, address: u16, memory: &mut PpuMemoryMapping) -> u8 {
+        match address & 0x3FFF {
+            0x3F00..=0x3FFF => self.palette[Self::palette_index(address)],
+            address => memory.load(address),
+        }
+    }
+
+    /// Write a byte to the PPU address space, including the palette
+    pub fn store(&mut self, address: u16, value: u8, memory: &mut PpuMemoryMapping) {
+        match address & 0x3FFF {
+            0x3F00..=0x3FFF => self.palette[Self::palette_index(address)] = value & 0x3F,
+            address => memory.store(address, value),
+        }
+    }
+
+    /// Read the register at $2000-$2007, `address` is taken modulo 8
+    pub fn load_register(&mut self, address: u16, memory: &mut PpuMemoryMapping) -> u8 {
+        match address & 7 {
+            2 => {
+                let low_bits = self.variant.status_id().unwrap_or(self.io_latch & 0x1F);
+                let value = self.status.bits() | low_bits;
+                self.status.remove(PpuStatus::VBLANK);
+                self.write_toggle = false;
+                self.io_latch = value;
+            }
+            4 => {
+                let value = self.oam[self.oam_address as usize];
+                self.io_latch = if self.is_clearing_secondary_oam() {
+                    // the evaluation reads $FF while it clears the sprites of the next scanline
+                    0xFF
+                } else if self.oam_address & 3 == 2 {
+                    // bits 2 to 4 of the attributes don't exist
+                    value & 0xE3
+                } else {
+                    value
+                };
+            }
+            7 => {
+                let address = self.vram_address & 0x3FFF;
+                let value = if address >= 0x3F00 {
+                    // palette reads aren't buffered, but the buffer gets the nametable "under" the palette
+                    self.read_buffer = memory.load(address);
+                    self.load(address, memory) | (self.io_latch & 0xC0)
+                } else {
+                    let value = self.read_buffer;
+                    self.read_buffer = memory.read_data(address);
+                    value
+                };
+                self.increment_vram_address();
+                self.io_latch = value;
+            }
+            // the rest are write-only
+            _ => {}
+        }
+        self.io_latch
+    }
+
+    /// Write to the register at $2000-$2007, `address` is taken modulo 8
+    pub fn store_register(&mut self, address: u16, value: u8, memory: &mut PpuMemoryMapping) {
+        self.io_latch = value;
+        let register = match address & 7 {
+            0 | 1 if self.variant.swaps_ctrl_and_mask() => address & 7 ^ 1,
+            register => register,
+        };
+        match register {
+            0 => {
+                self.ctrl = PpuCtrl::from_bits_retain(value);
+                self.temp_address = (self.temp_address & !0x0C00) | ((value as u16 & 0b11) << 10);
+            }
+            1 => self.mask = PpuMask::from_bits_retain(value),
+            3 => self.oam_address = value,
+            4 => {
+                if self.is_rendering() {
+                    // OAM is busy, the write is lost and only the sprite number of the address goes up
+                    self.oam_address = self.oam_address.wrapping_add(4);
+                } else {
+                    self.oam[self.oam_address as usize] = value;
+                    self.oam_address = self.oam_address.wrapping_add(1);
+                }
+            }
+            5 => {
+                if !self.write_toggle {
+                    self.temp_address = (self.temp_address & !0x001F) | (value as u16 >> 3);
+                    self.fine_x = value & 0b111;
+                } else {
+                    self.temp_address = (self.temp_address & !0x73E0)
+                        | ((value as u16 & 0b111) << 12)
+                        | ((value as u16 & 0xF8) << 2);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            6 => {
+                if !self.write_toggle {
+                    self.temp_address = (self.temp_address & 0x00FF) | ((value as u16 & 0x3F) << 8);
+                } else {
+                    self.temp_address = (self.temp_address & 0xFF00) | value as u16;
+                    self.vram_address = self.temp_address;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            7 => {
+                self.store(self.vram_address, value, memory);
+                self.increment_vram_address();
+            }
+            // PPUSTATUS is read-only
+            _ => {}
+        }
+    }
+
+    /// Advance the PPU by the given number of dots, possibly later
+    ///
+    /// The dots are only run right away when the NMI output or the frame number would change during them,
+    /// otherwise they're postponed until [`Ppu::catch_up`] is called.
+    pub fn advance(&mut self, dots: u32, memory: &mut PpuMemoryMapping) {
+        self.pending_dots += dots;
+        if self.pending_dots >= self.dots_until_event() {
+            self.catch_up(memory);
+        }
+    }
+
+    /// Run all the dots postponed by [`Ppu::advance`]
+    ///
+    /// Has to be called before the PPU's state is accessed, or something the PPU reads is changed.
+    pub fn catch_up(&mut self, memory: &mut PpuMemoryMapping) {
+        for _ in 0..self.pending_dots {
+            self.tick(memory);
+        }
+        self.pending_dots = 0;
+    }
+
+    /// Lower bound of the dots until VBlank starts or ends, or the frame ends
+    fn dots_until_event(&self) -> u32 {
+        let position = self.scanline as u32 * DOTS_PER_SCANLINE as u32 + self.dot as u32;
+        let dots_per_frame = self.scanlines_per_frame() as u32 * DOTS_PER_SCANLINE as u32;
+        [
+            self.region.vblank_scanline() as u32 * DOTS_PER_SCANLINE as u32 + 1,
+            self.pre_render_scanline() as u32 * DOTS_PER_SCANLINE as u32 + 1,
+            // odd frames can be one dot shorter
+            dots_per_frame - 1,
+        ]
+        .into_iter()
+        .filter(|&event| event > position)
+        .map(|event| event - position)
+        .min()
+        .unwrap_or(1)
+    }
+
+    /// Lower bound of the dots until the frame number changes, counting the postponed ones as run
+    pub(crate) fn dots_until_frame_end(&self) -> u32 {
+        let position =
+            self.scanline as u32 * DOTS_PER_SCANLINE as u32 + self.dot as u32 + self.pending_dots;
+        let dots_per_frame = self.scanlines_per_frame() as u32 * DOTS_PER_SCANLINE as u32;
+        // odd frames can be one dot shorter
+        (dots_per_frame - 1).saturating_sub(position)
+    }
+
+    /// Advance the PPU by one dot
+    pub fn tick(&mut self, memory: &mut PpuMemoryMapping) {
+        self.dot += 1;
+        // the pre-render scanline is one dot shorter on odd frames when rendering
+        if self.region.skips_odd_frame_dot()
+            && self.scanline == self.pre_render_scanline()
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.frame % 2 == 1
+            && self.is_rendering_enabled()
+        {
+            self.dot = DOTS_PER_SCANLINE;
+        }
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.scanlines_per_frame() {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+
+        if self.dot == 1 {
+            if self.scanline == self.region.vblank_scanline() {
+                self.status.insert(PpuStatus::VBLANK);
+            } else if self.scanline == self.pre_render_scanline() {
+                self.status = PpuStatus::empty();
+            }
+        }
+        self.render_dot(memory);
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self {
+            ctrl: PpuCtrl::empty(),
+            mask: PpuMask::empty(),
+            status: PpuStatus::empty(),
+            oam: [0; 256],
+            oam_address: 0,
+            palette: [0; 32],
+            vram_address: 0,
+            temp_address: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+            io_latch: 0,
+            background: BackgroundShifters::default(),
+            sprites: [ScanlineSprite::default(); 8],
+            sprite_count: 0,
+            extra_sprites: Box::new([ScanlineSprite::default(); 56]),
+            extra_sprite_count: 0,
+            sprite_limit: true,
+            framebuffer: Box::new([0; FRAME_WIDTH * FRAME_HEIGHT]),
+            oam_dma_page: None,
+            pending_dots: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            region: Region::default(),
+            frame_skip: 0,
+            extra_scanlines: 0,
+            variant: Variant::default(),
+        }
+    }
+}
+
+impl_state_bits!(PpuCtrl, PpuMask, PpuStatus);
+// the framebuffer isn't part of the state, it's redrawn by the next frame
+impl_state!(Ppu {
+    ctrl,
+    mask,
+    status,
+    oam,
+    oam_address,
+    palette,
+    vram_address,
+    temp_address,
+    fine_x,
+    write_toggle,
+    read_buffer,
+    io_latch,
+    oam_dma_page,
+    background,
+    sprites,
+    sprite_count,
+    pending_dots,
+    scanline,
+    dot,
+    frame,
+});
@@ -0,0 +1,278 @@
+//! Conversion of the PPU's output to RGB colors
+//!
+//! The 2C02 generates an NTSC signal directly instead of RGB, so there's no single correct palette,
+//! [`NTSC_PALETTE`] is a commonly used approximation.
+//! The RGB PPUs of the arcade machines do output RGB, from the fixed [`RGB_PALETTE`],
+//! or one of the [`RGB_2C04_PALETTES`] that have the colors in a different order.
+//! Other palettes, like ones loaded from `.pal` files, can be used through [`Palette`].
+//!
+//! Details at https://www.nesdev.org/wiki/PPU_palettes
+
+use core::fmt::{Debug, Formatter};
+
+/// RGB values of the 64 colors
+#[rustfmt::skip]
+pub const NTSC_PALETTE: [[u8; 3]; 64] = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136], [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0], [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228], [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40], [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236], [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108], [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236], [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180], [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+/// Levels of the RGB PPUs' colors, one octal digit of 0-7 for each of red, green and blue
+#[rustfmt::skip]
+const RGB_LEVELS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/// Levels of the colors of the 2C04s, in the order of each of RP2C04-0001 to RP2C04-0004
+///
+/// All four have the same 64 colors in different orders, most of them the 2C03's.
+#[rustfmt::skip]
+const RGB_2C04_LEVELS: [[u16; 64]; 4] = [
+    // RP2C04-0001
+    [
+        0o755, 0o637, 0o700, 0o447, 0o044, 0o120, 0o222, 0o704, 0o777, 0o333, 0o750, 0o503, 0o403, 0o660, 0o320, 0o777,
+        0o357, 0o653, 0o310, 0o360, 0o467, 0o657, 0o764, 0o027, 0o760, 0o276, 0o000, 0o200, 0o666, 0o444, 0o707, 0o014,
+        0o003, 0o567, 0o757, 0o070, 0o077, 0o022, 0o053, 0o507, 0o000, 0o420, 0o747, 0o510, 0o407, 0o006, 0o740, 0o000,
+        0o000, 0o140, 0o555, 0o031, 0o572, 0o326, 0o770, 0o630, 0o020, 0o036, 0o040, 0o111, 0o773, 0o737, 0o430, 0o473,
+    ],
+    // RP2C04-0002
+    [
+        0o000, 0o750, 0o430, 0o572, 0o473, 0o737, 0o044, 0o567, 0o700, 0o407, 0o773, 0o747, 0o777, 0o637, 0o467, 0o040,
+        0o020, 0o357, 0o510, 0o666, 0o053, 0o360, 0o200, 0o447, 0o222, 0o707, 0o003, 0o276, 0o657, 0o320, 0o000, 0o326,
+        0o403, 0o764, 0o740, 0o757, 0o036, 0o310, 0o555, 0o006, 0o507, 0o760, 0o333, 0o120, 0o027, 0o000, 0o660, 0o777,
+        0o653, 0o111, 0o070, 0o630, 0o022, 0o014, 0o704, 0o140, 0o000, 0o077, 0o420, 0o770, 0o755, 0o503, 0o031, 0o444,
+    ],
+    // RP2C04-0003
+    [
+        0o507, 0o737, 0o473, 0o555, 0o040, 0o777, 0o567, 0o120, 0o014, 0o000, 0o764, 0o320, 0o704, 0o666, 0o653, 0o467,
+        0o447, 0o044, 0o503, 0o027, 0o140, 0o430, 0o630, 0o053, 0o333, 0o326, 0o000, 0o006, 0o700, 0o510, 0o747, 0o755,
+        0o637, 0o020, 0o003, 0o770, 0o111, 0o750, 0o740, 0o777, 0o360, 0o403, 0o357, 0o707, 0o036, 0o444, 0o000, 0o310,
+        0o077, 0o200, 0o572, 0o757, 0o420, 0o070, 0o660, 0o222, 0o031, 0o000, 0o657, 0o773, 0o407, 0o276, 0o760, 0o022,
+    ],
+    // RP2C04-0004
+    [
+        0o430, 0o326, 0o044, 0o660, 0o000, 0o755, 0o014, 0o630, 0o555, 0o310, 0o070, 0o003, 0o764, 0o770, 0o040, 0o572,
+        0o737, 0o200, 0o027, 0o747, 0o000, 0o222, 0o510, 0o740, 0o653, 0o053, 0o447, 0o140, 0o403, 0o000, 0o473, 0o357,
+        0o503, 0o031, 0o420, 0o006, 0o407, 0o507, 0o333, 0o704, 0o022, 0o666, 0o036, 0o020, 0o111, 0o773, 0o444, 0o707,
+        0o757, 0o777, 0o320, 0o700, 0o760, 0o276, 0o777, 0o467, 0o000, 0o750, 0o637, 0o567, 0o360, 0o657, 0o077, 0o120,
+    ],
+];
+
+/// Scale the lowest octal digit of `levels` to the full range of a channel
+const fn scale_level(levels: u16) -> u8 {
+    ((levels & 0o7) * 255 / 7) as u8
+}
+
+/// RGB values of colors given as levels like [`RGB_LEVELS`]
+const fn rgb_colors(levels: &[u16; 64]) -> [[u8; 3]; 64] {
+    let mut colors = [[0; 3]; 64];
+    let mut index = 0;
+    while index < colors.len() {
+        let levels = levels[index];
+        colors[index] = [
+            scale_level(levels >> 6),
+            scale_level(levels >> 3),
+            scale_level(levels),
+        ];
+        index += 1;
+    }
+    colors
+}
+
+/// RGB values of the 64 colors of the 2C03 and 2C05, see [`Variant`](super::Variant)
+pub const RGB_PALETTE: [[u8; 3]; 64] = rgb_colors(&RGB_LEVELS);
+
+/// RGB values of the 64 colors of the RP2C04-0001 to RP2C04-0004, see [`Variant`](super::Variant)
+pub const RGB_2C04_PALETTES: [[[u8; 3]; 64]; 4] = [
+    rgb_colors(&RGB_2C04_LEVELS[0]),
+    rgb_colors(&RGB_2C04_LEVELS[1]),
+    rgb_colors(&RGB_2C04_LEVELS[2]),
+    rgb_colors(&RGB_2C04_LEVELS[3]),
+];
+
+/// Emphasized color channels keep their brightness while the others are dimmed by this factor
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// RGBA colors of all the pixel values of the framebuffer, the emphasized ones included
+///
+/// Alpha is always 255. Converting the pixels through this table is a lot faster than applying the emphasis to each.
+pub static RGBA_TABLE: [[u8; 4]; 512] = rgba_table(&NTSC_PALETTE);
+
+const fn rgba_table(colors: &[[u8; 3]; 64]) -> [[u8; 4]; 512] {
+    let mut table = [[0; 4]; 512];
+    let mut pixel = 0;
+    while pixel < table.len() {
+        let [r, g, b] = colors[pixel & 0x3F];
+        let mut rgba = [r, g, b, 0xFF];
+        let emphasis = pixel >> 6;
+        let mut channel = 0;
+        while channel < 3 {
+            if emphasis != 0 && emphasis & (1 << channel) == 0 {
+                rgba[channel] = (rgba[channel] as f32 * EMPHASIS_ATTENUATION) as u8;
+            }
+            channel += 1;
+        }
+        table[pixel] = rgba;
+        pixel += 1;
+    }
+    table
+}
+
+/// RGBA colors of all the pixel values for the RGB PPUs, where emphasis turns a channel all the way up
+const fn rgb_emphasis_table(colors: &[[u8; 3]; 64]) -> [[u8; 4]; 512] {
+    let mut table = [[0; 4]; 512];
+    let mut pixel = 0;
+    while pixel < table.len() {
+        let [r, g, b] = colors[pixel & 0x3F];
+        let mut rgba = [r, g, b, 0xFF];
+        let emphasis = pixel >> 6;
+        let mut channel = 0;
+        while channel < 3 {
+            if emphasis & (1 << channel) != 0 {
+                rgba[channel] = 0xFF;
+            }
+            channel += 1;
+        }
+        table[pixel] = rgba;
+        pixel += 1;
+    }
+    table
+}
+
+/// Convert a pixel of the framebuffer to RGBA
+///
+/// The low 6 bits are the color and bits 6-8 are the red, green and blue emphasis bits of PPUMASK
+pub fn to_rgba(pixel: u16) -> [u8; 4] {
+    RGBA_TABLE[pixel as usize & 0x1FF]
+}
+
+/// Convert a pixel of the framebuffer to RGB, see [`to_rgba`]
+pub fn to_rgb(pixel: u16) -> [u8; 3] {
+    let [r, g, b, _] = to_rgba(pixel);
+    [r, g, b]
+}
+
+/// Convert a row of pixels to RGBA, 4 bytes per pixel
+///
+/// # Panics
+/// If `output` isn't 4 times as long as `pixels`
+pub fn write_rgba_row(pixels: &[u16], output: &mut [u8]) {
+    assert_eq!(output.len(), pixels.len() * 4);
+    for (rgba, &pixel) in output.chunks_exact_mut(4).zip(pixels) {
+        rgba.copy_from_slice(&to_rgba(pixel));
+    }
+}
+
+/// Colors to convert the pixels of the framebuffer with
+///
+/// The emphasized colors are derived from the 64 base colors, unless the palette lists them too.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Palette {
+    rgba: [[u8; 4]; 512],
+}
+
+impl Palette {
+    /// The palette of [`to_rgba`], made of [`NTSC_PALETTE`]
+    pub const NTSC: Palette = Palette::new(&NTSC_PALETTE);
+
+    /// The palette of the RGB PPUs, made of [`RGB_PALETTE`] with their emphasis
+    pub const RGB: Palette = Palette {
+        rgba: rgb_emphasis_table(&RGB_PALETTE),
+    };
+
+    /// The palette of the RP2C04-0001, made of [`RGB_2C04_PALETTES`] with the emphasis of [`Palette::RGB`]
+    pub const RGB_2C04_0001: Palette = Palette {
+        rgba: rgb_emphasis_table(&RGB_2C04_PALETTES[0]),
+    };
+
+    /// The palette of the RP2C04-0002, see [`Palette::RGB_2C04_0001`]
+    pub const RGB_2C04_0002: Palette = Palette {
+        rgba: rgb_emphasis_table(&RGB_2C04_PALETTES[1]),
+    };
+
+    /// The palette of the RP2C04-0003, see [`Palette::RGB_2C04_0001`]
+    pub const RGB_2C04_0003: Palette = Palette {
+        rgba: rgb_emphasis_table(&RGB_2C04_PALETTES[2]),
+    };
+
+    /// The palette of the RP2C04-0004, see [`Palette::RGB_2C04_0001`]
+    pub const RGB_2C04_0004: Palette = Palette {
+        rgba: rgb_emphasis_table(&RGB_2C04_PALETTES[3]),
+    };
+
+    /// Make a palette from RGB values of the 64 colors
+    pub const fn new(colors: &[[u8; 3]; 64]) -> Self {
+        Self {
+            rgba: rgba_table(colors),
+        }
+    }
+
+    /// Load the contents of a `.pal` file
+    ///
+    /// It's either the 64 colors, or all 512 with the 8 combinations of emphasis bits one after another,
+    /// 3 bytes of RGB each. Returns `None` if the data is neither size.
+    pub fn from_pal(data: &[u8]) -> Option<Self> {
+        let rgb = |color: &[u8]| [color[0], color[1], color[2]];
+        match data.len() {
+            192 => {
+                let mut colors = [[0; 3]; 64];
+                for (color, bytes) in colors.iter_mut().zip(data.chunks_exact(3)) {
+                    *color = rgb(bytes);
+                }
+                Some(Self::new(&colors))
+            }
+            1536 => {
+                let mut rgba = [[0; 4]; 512];
+                for (rgba, bytes) in rgba.iter_mut().zip(data.chunks_exact(3)) {
+                    let [r, g, b] = rgb(bytes);
+                    *rgba = [r, g, b, 0xFF];
+                }
+                Some(Self { rgba })
+            }
+            _ => None,
+        }
+    }
+
+    /// Convert a pixel of the framebuffer to RGBA, like [`to_rgba`]
+    pub fn to_rgba(&self, pixel: u16) -> [u8; 4] {
+        self.rgba[pixel as usize & 0x1FF]
+    }
+
+    /// Convert a pixel of the framebuffer to RGB, like [`to_rgb`]
+    pub fn to_rgb(&self, pixel: u16) -> [u8; 3] {
+        let [r, g, b, _] = self.to_rgba(pixel);
+        [r, g, b]
+    }
+
+    /// Convert a row of pixels to RGBA, like [`write_rgba_row`]
+    ///
+    /// # Panics
+    /// If `output` isn't 4 times as long as `pixels`
+    pub fn write_rgba_row(&self, pixels: &[u16], output: &mut [u8]) {
+        assert_eq!(output.len(), pixels.len() * 4);
+        for (rgba, &pixel) in output.chunks_exact_mut(4).zip(pixels) {
+            rgba.copy_from_slice(&self.to_rgba(pixel));
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::NTSC
+    }
+}
+
+impl Debug for Palette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Palette").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,36 @@
+//! Decoding of CHR patterns
+//!
+//! A row of a tile is stored in 2 bitplanes, a byte with the low bits of its 8 pixels
+//! and one with the high bits, the leftmost pixel is in bit 7 of both.
+//! On 64-bit targets the row is decoded all at once, spreading each plane over the bytes of a `u64`,
+//! elsewhere the pixels are decoded one by one.
+
+/// Bit `n` of every byte of a `u64` set to bit `n` of `plane`, already shifted down to bit 0
+fn spread(plane: u8) -> u64 {
+    const BYTES: u64 = 0x0101_0101_0101_0101;
+    // copy the plane into every byte and keep a different bit in each
+    let bits = (plane as u64 * BYTES) & 0x8040_2010_0804_0201;
+    // each byte is now 0 or a power of 2, adding $7F sets bit 7 for the powers without carrying out
+    ((bits + 0x7F7F_7F7F_7F7F_7F7F) >> 7) & BYTES
+}
+
+pub(super) fn decode_row_wide(low: u8, high: u8) -> [u8; 8] {
+    // bit 7 went into the most significant byte, which is the first in big endian
+    (spread(low) | spread(high) << 1).to_be_bytes()
+}
+
+pub(super) fn decode_row_scalar(low: u8, high: u8) -> [u8; 8] {
+    core::array::from_fn(|column| {
+        let bit = 7 - column;
+        (low >> bit) & 1 | ((high >> bit) & 1) << 1
+    })
+}
+
+/// Color indices 0-3 of the pixels of a tile row, from left to right
+pub fn decode_row(low: u8, high: u8) -> [u8; 8] {
+    if cfg!(target_pointer_width = "64") {
+        decode_row_wide(low, high)
+    } else {
+        decode_row_scalar(low, high)
+    }
+}
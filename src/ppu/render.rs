@@ -0,0 +1,357 @@
+//! Background and sprite rendering
+//!
+//! Background tiles are fetched into shift registers 2 tiles ahead, following the PPU's memory access pattern,
+//! so mid-scanline scroll and pattern changes show up at the right pixel.
+//! Sprites for the next scanline are evaluated and fetched all at once at dot 257,
+//! starting at the sprite OAMADDR points to then instead of at dot 65.
+//!
+//! Details at https://www.nesdev.org/wiki/PPU_rendering and https://www.nesdev.org/wiki/PPU_sprite_evaluation
+
+use crate::{memory::PpuMemoryMapping, state::impl_state};
+
+use super::{Ppu, PpuCtrl, PpuMask, PpuStatus, Variant, FRAME_HEIGHT, FRAME_WIDTH};
+
+const MAX_SPRITES_PER_SCANLINE: usize = 8;
+
+const SPRITE_PALETTE: u8 = 0b11;
+const SPRITE_BEHIND_BACKGROUND: u8 = 1 << 5;
+const SPRITE_FLIP_HORIZONTAL: u8 = 1 << 6;
+const SPRITE_FLIP_VERTICAL: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct BackgroundShifters {
+    next_tile: u8,
+    next_palette: u8,
+    next_pattern_low: u8,
+    next_pattern_high: u8,
+
+    pattern_low: u16,
+    pattern_high: u16,
+    /// Palette bits are expanded to 8 bits per tile so they shift along with the patterns
+    palette_low: u16,
+    palette_high: u16,
+}
+
+impl BackgroundShifters {
+    fn shift(&mut self) {
+        self.pattern_low <<= 1;
+        self.pattern_high <<= 1;
+        self.palette_low <<= 1;
+        self.palette_high <<= 1;
+    }
+
+    /// Move the fetched tile into the low byte of the shift registers
+    fn reload(&mut self) {
+        let expand = |bit: u8| if bit != 0 { 0xFF } else { 0x00 };
+        self.pattern_low = (self.pattern_low & 0xFF00) | self.next_pattern_low as u16;
+        self.pattern_high = (self.pattern_high & 0xFF00) | self.next_pattern_high as u16;
+        self.palette_low = (self.palette_low & 0xFF00) | expand(self.next_palette & 1);
+        self.palette_high = (self.palette_high & 0xFF00) | expand(self.next_palette & 2);
+    }
+
+    /// Background pixel and palette at the given fine X scroll
+    fn pixel(&self, fine_x: u8) -> (u8, u8) {
+        let bit = 15 - fine_x;
+        let pixel =
+            (self.pattern_low >> bit) as u8 & 1 | ((self.pattern_high >> bit) as u8 & 1) << 1;
+        let palette =
+            (self.palette_low >> bit) as u8 & 1 | ((self.palette_high >> bit) as u8 & 1) << 1;
+        (pixel, palette)
+    }
+}
+
+/// Sprite selected for the current scanline
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct ScanlineSprite {
+    x: u8,
+    attributes: u8,
+    /// Pattern row, already flipped, the leftmost pixel is the high bit
+    pattern_low: u8,
+    pattern_high: u8,
+    is_sprite_zero: bool,
+}
+
+impl ScanlineSprite {
+    fn pixel(&self, x: u16) -> u8 {
+        let Some(column) = x.checked_sub(self.x as u16).filter(|&column| column < 8) else {
+            return 0;
+        };
+        let bit = 7 - column;
+        (self.pattern_low >> bit) & 1 | ((self.pattern_high >> bit) & 1) << 1
+    }
+}
+
+impl Ppu {
+    /// Do the rendering work of the current dot
+    pub(super) fn render_dot(&mut self, memory: &mut PpuMemoryMapping) {
+        let visible = self.scanline < FRAME_HEIGHT as u16;
+        if !visible && self.scanline != self.pre_render_scanline() {
+            return;
+        }
+        if !self.is_rendering_enabled() {
+            if visible && matches!(self.dot, 1..=256) {
+                self.output_pixel();
+            }
+            return;
+        }
+
+        let dot = self.dot;
+        if dot == 1 && !visible && self.oam_address >= 8 && self.variant == Variant::Ricoh2C02 {
+            // the 2C02G copies the row of OAM that OAMADDR points to over the first 2 sprites,
+            // it's not known to happen on the other PPUs
+            let row = (self.oam_address & 0xF8) as usize;
+            self.oam.copy_within(row..row + 8, 0);
+        }
+        if matches!(dot, 2..=257 | 322..=337) {
+            self.background.shift();
+            if dot % 8 == 1 {
+                self.background.reload();
+            }
+        }
+        if visible && matches!(dot, 1..=256) {
+            self.output_pixel();
+        }
+
+        if matches!(dot, 1..=256 | 321..=336) {
+            self.fetch_background(memory);
+        }
+        match dot {
+            256 => self.increment_y(),
+            257 => {
+                // copy the horizontal scroll
+                self.vram_address = (self.vram_address & !0x041F) | (self.temp_address & 0x041F);
+                if visible {
+                    self.evaluate_sprites(memory);
+                } else {
+                    self.sprite_count = 0;
+                    self.extra_sprite_count = 0;
+                }
+            }
+            // copy the vertical scroll
+            280..=304 if !visible => {
+                self.vram_address = (self.vram_address & !0x7BE0) | (self.temp_address & 0x7BE0);
+            }
+            _ => {}
+        }
+        // the sprite fetches leave OAMADDR at 0
+        if matches!(dot, 257..=320) {
+            self.oam_address = 0;
+        }
+    }
+
+    /// Each tile takes 8 dots, 2 for each fetch
+    fn fetch_background(&mut self, memory: &mut PpuMemoryMapping) {
+        let v = self.vram_address;
+        match (self.dot - 1) % 8 {
+            0 => self.background.next_tile = memory.load(0x2000 | (v & 0x0FFF)),
+            2 => {
+                let address = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                let shift = ((v >> 4) & 0b100) | (v & 0b10);
+                self.background.next_palette = (memory.load(address) >> shift) & 0b11;
+            }
+            4 => self.background.next_pattern_low = memory.load(self.background_pattern_address()),
+            6 => {
+                self.background.next_pattern_high =
+                    memory.load(self.background_pattern_address() + 8)
+            }
+            7 => self.increment_coarse_x(),
+            _ => {}
+        }
+    }
+
+    fn background_pattern_address(&self) -> u16 {
+        let table = if self.ctrl.contains(PpuCtrl::BACKGROUND_PATTERN_TABLE) {
+            0x1000
+        } else {
+            0x0000
+        };
+        let fine_y = self.vram_address >> 12;
+        table | (self.background.next_tile as u16) << 4 | fine_y
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.vram_address & 0x001F == 31 {
+            // wrap into the next horizontal nametable
+            self.vram_address &= !0x001F;
+            self.vram_address ^= 0x0400;
+        } else {
+            self.vram_address += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.vram_address & 0x7000 != 0x7000 {
+            self.vram_address += 0x1000;
+            return;
+        }
+        self.vram_address &= !0x7000;
+        let mut coarse_y = (self.vram_address & 0x03E0) >> 5;
+        match coarse_y {
+            // the last row of tiles, wrap into the next vertical nametable
+            29 => {
+                coarse_y = 0;
+                self.vram_address ^= 0x0800;
+            }
+            // rows 30 and 31 are the attribute table, wrap without switching nametables
+            31 => coarse_y = 0,
+            _ => coarse_y += 1,
+        }
+        self.vram_address = (self.vram_address & !0x03E0) | (coarse_y << 5);
+    }
+
+    /// Select the sprites for the next scanline and fetch their patterns
+    fn evaluate_sprites(&mut self, memory: &mut PpuMemoryMapping) {
+        let height = if self.ctrl.contains(PpuCtrl::TALL_SPRITES) {
+            16
+        } else {
+            8
+        };
+        self.sprite_count = 0;
+        self.extra_sprite_count = 0;
+
+        // the evaluation stops at the end of OAM, sprites before OAMADDR are skipped
+        // and the first one evaluated counts as sprite 0
+        let start = self.oam_address as usize;
+        for (index, base) in (start..self.oam.len()).step_by(4).enumerate() {
+            // a misaligned OAMADDR mixes up the bytes of neighbouring sprites
+            let [y, tile, attributes, x] = [0, 1, 2, 3].map(|i| self.oam[(base + i) % 256]);
+            let row = self.scanline.wrapping_sub(y as u16);
+            if row >= height {
+                continue;
+            }
+            if self.sprite_count == MAX_SPRITES_PER_SCANLINE {
+                self.status.insert(PpuStatus::SPRITE_OVERFLOW);
+                if self.sprite_limit {
+                    break;
+                }
+            }
+
+            let row = if attributes & SPRITE_FLIP_VERTICAL != 0 {
+                height - 1 - row
+            } else {
+                row
+            };
+            let address = if height == 16 {
+                let table = (tile as u16 & 1) << 12;
+                let tile = (tile & 0xFE) as u16 + (row >> 3);
+                table | tile << 4 | (row & 0b111)
+            } else {
+                let table = if self.ctrl.contains(PpuCtrl::SPRITE_PATTERN_TABLE) {
+                    0x1000
+                } else {
+                    0x0000
+                };
+                table | (tile as u16) << 4 | row
+            };
+            let mut pattern_low = memory.load(address);
+            let mut pattern_high = memory.load(address + 8);
+            if attributes & SPRITE_FLIP_HORIZONTAL != 0 {
+                pattern_low = pattern_low.reverse_bits();
+                pattern_high = pattern_high.reverse_bits();
+            }
+
+            let sprite = ScanlineSprite {
+                x,
+                attributes,
+                pattern_low,
+                pattern_high,
+                is_sprite_zero: index == 0,
+            };
+            if self.sprite_count < MAX_SPRITES_PER_SCANLINE {
+                self.sprites[self.sprite_count] = sprite;
+                self.sprite_count += 1;
+            } else {
+                self.extra_sprites[self.extra_sprite_count] = sprite;
+                self.extra_sprite_count += 1;
+            }
+        }
+    }
+
+    /// Combine the background and sprite pixels at the current dot and write the result into the framebuffer
+    ///
+    /// On skipped frames only the sprite 0 hit is checked.
+    fn output_pixel(&mut self) {
+        let x = self.dot - 1;
+        if !self.is_drawing() {
+            // sprite 0 is always the first one if it's on the scanline
+            if self.is_rendering_enabled()
+                && self.sprite_count != 0
+                && self.sprites[0].is_sprite_zero
+            {
+                self.rendered_palette_address(x);
+            }
+            return;
+        }
+        let palette_address = if self.is_rendering_enabled() {
+            self.rendered_palette_address(x)
+        } else if self.vram_address & 0x3F00 == 0x3F00 {
+            // the backdrop color is replaced with the palette entry the VRAM address points to
+            self.vram_address
+        } else {
+            0x3F00
+        };
+
+        let mut color = self.palette[Self::palette_index(palette_address)];
+        if self.mask.contains(PpuMask::GREYSCALE) {
+            color &= 0x30;
+        }
+        let emphasis = (self.mask.bits() >> 5) as u16;
+        self.framebuffer[self.scanline as usize * FRAME_WIDTH + x as usize] =
+            color as u16 | emphasis << 6;
+    }
+
+    fn rendered_palette_address(&mut self, x: u16) -> u16 {
+        let show_background = self.mask.contains(PpuMask::SHOW_BACKGROUND)
+            && (x >= 8 || self.mask.contains(PpuMask::SHOW_BACKGROUND_LEFT));
+        let show_sprites = self.mask.contains(PpuMask::SHOW_SPRITES)
+            && (x >= 8 || self.mask.contains(PpuMask::SHOW_SPRITES_LEFT));
+
+        let (background_pixel, background_palette) = if show_background {
+            self.background.pixel(self.fine_x)
+        } else {
+            (0, 0)
+        };
+        let sprite = self.sprites[..self.sprite_count]
+            .iter()
+            .chain(&self.extra_sprites[..self.extra_sprite_count])
+            .filter(|_| show_sprites)
+            .map(|sprite| (sprite, sprite.pixel(x)))
+            .find(|&(_, pixel)| pixel != 0);
+
+        if let Some((sprite, _)) = sprite {
+            if sprite.is_sprite_zero && background_pixel != 0 && x != 255 {
+                self.status.insert(PpuStatus::SPRITE_ZERO_HIT);
+            }
+        }
+
+        match sprite {
+            Some((sprite, pixel))
+                if background_pixel == 0 || sprite.attributes & SPRITE_BEHIND_BACKGROUND == 0 =>
+            {
+                0x3F10 | ((sprite.attributes & SPRITE_PALETTE) as u16) << 2 | pixel as u16
+            }
+            _ if background_pixel != 0 => {
+                0x3F00 | (background_palette as u16) << 2 | background_pixel as u16
+            }
+            _ => 0x3F00,
+        }
+    }
+}
+
+impl_state!(BackgroundShifters {
+    next_tile,
+    next_palette,
+    next_pattern_low,
+    next_pattern_high,
+    pattern_low,
+    pattern_high,
+    palette_low,
+    palette_high,
+});
+impl_state!(ScanlineSprite {
+    x,
+    attributes,
+    pattern_low,
+    pattern_high,
+    is_sprite_zero,
+});
@@ -0,0 +1,471 @@
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    clock::Region,
+    memory::{ram::Ram, PpuMemoryMapping},
+};
+
+use super::{
+    palette, pattern, Ppu, PpuCtrl, PpuMask, PpuStatus, Variant, DOTS_PER_SCANLINE,
+    SCANLINES_PER_FRAME, VBLANK_SCANLINE,
+};
+
+/// NROM cartridge with 8KB of CHR RAM
+fn cartridge() -> Cartridge {
+    Cartridge::from_ines(&nrom_image(&[], 0, 1)).unwrap()
+}
+
+#[test]
+fn ppudata() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+
+    ppu.store_register(0x2006, 0x21, &mut memory);
+    ppu.store_register(0x2006, 0x00, &mut memory);
+    for value in 1..=3 {
+        ppu.store_register(0x2007, value, &mut memory);
+    }
+    assert_eq!(ppu.vram_address(), 0x2103);
+
+    ppu.store_register(0x2006, 0x21, &mut memory);
+    ppu.store_register(0x2006, 0x00, &mut memory);
+    // the first read returns the stale buffer
+    ppu.load_register(0x2007, &mut memory);
+    assert_eq!(ppu.load_register(0x2007, &mut memory), 1);
+    assert_eq!(ppu.load_register(0x2007, &mut memory), 2);
+
+    ppu.store_register(0x2000, PpuCtrl::INCREMENT_32.bits(), &mut memory);
+    ppu.store_register(0x2006, 0x3F, &mut memory);
+    ppu.store_register(0x2006, 0x10, &mut memory);
+    ppu.store_register(0x2007, 0x2C, &mut memory);
+    assert_eq!(ppu.vram_address(), 0x3F30);
+    // $3F10 mirrors $3F00
+    assert_eq!(ppu.palette[0], 0x2C);
+    memory.store(0x2F00, 0x07);
+    ppu.store_register(0x2006, 0x3F, &mut memory);
+    ppu.store_register(0x2006, 0x00, &mut memory);
+    // palette reads aren't buffered
+    assert_eq!(ppu.load_register(0x2007, &mut memory), 0x2C);
+    // but the buffer gets the nametable under the palette
+    ppu.store_register(0x2006, 0x20, &mut memory);
+    ppu.store_register(0x2006, 0x00, &mut memory);
+    assert_eq!(ppu.load_register(0x2007, &mut memory), 0x07);
+}
+
+#[test]
+fn vblank() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    ppu.store_register(0x2000, PpuCtrl::NMI_ENABLE.bits(), &mut memory);
+
+    while ppu.scanline() != VBLANK_SCANLINE || ppu.dot() != 0 {
+        ppu.tick(&mut memory);
+    }
+    assert!(!ppu.nmi());
+    ppu.tick(&mut memory);
+    assert!(ppu.status.contains(PpuStatus::VBLANK));
+    assert!(ppu.nmi());
+
+    // reading PPUSTATUS clears the flag
+    assert_eq!(ppu.load_register(0x2002, &mut memory) & 0x80, 0x80);
+    assert!(!ppu.nmi());
+    assert_eq!(ppu.load_register(0x2002, &mut memory) & 0x80, 0);
+
+    while ppu.frame() == 0 {
+        ppu.tick(&mut memory);
+    }
+    assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+
+    // rendering is disabled, so there's no odd frame skip
+    let mut dots = 0u32;
+    while ppu.frame() == 1 {
+        ppu.tick(&mut memory);
+        dots += 1;
+    }
+    assert_eq!(dots, DOTS_PER_SCANLINE as u32 * SCANLINES_PER_FRAME as u32);
+}
+
+#[test]
+fn regions() {
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+
+    for region in [Region::Pal, Region::Dendy] {
+        let mut ppu = Ppu::new();
+        ppu.set_region(region);
+        // rendering is enabled, but only NTSC skips a dot on odd frames
+        ppu.mask = PpuMask::SHOW_BACKGROUND;
+        let mut vblank_scanline = None;
+        let mut dots = 0u32;
+        while ppu.frame() < 2 {
+            ppu.tick(&mut memory);
+            dots += 1;
+            if ppu.status.contains(PpuStatus::VBLANK) && vblank_scanline.is_none() {
+                vblank_scanline = Some(ppu.scanline());
+            }
+        }
+        assert_eq!(dots, 2 * DOTS_PER_SCANLINE as u32 * 312);
+        assert_eq!(vblank_scanline, Some(region.vblank_scanline()));
+    }
+}
+
+#[test]
+fn rendering() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+
+    // tile 1 is solid color 1, tile 2 is solid color 3
+    for row in 0..8 {
+        memory.store(0x0010 + row, 0xFF);
+        memory.store(0x0020 + row, 0xFF);
+        memory.store(0x0028 + row, 0xFF);
+    }
+    // the top left tile is 1, everything else is 0
+    memory.store(0x2000, 0x01);
+    // the 2nd attribute quadrant uses palette 1
+    memory.store(0x23C0, 0b0000_0100);
+    ppu.palette[0x00] = 0x0F;
+    ppu.palette[0x01] = 0x11;
+    ppu.palette[0x05] = 0x15;
+    ppu.palette[0x13] = 0x23;
+    // sprite 0 is tile 2 at (8, 1), sprite 1 is tile 1 at (16, 0)
+    ppu.oam[..8].copy_from_slice(&[0, 0x02, 0x00, 8, 0xFF, 0x01, 0x00, 16]);
+    ppu.oam[8..].fill(0xFF);
+
+    ppu.store_register(0x2001, 0x1E, &mut memory);
+    // the first frame starts on the visible scanlines, without the scroll being set up on the pre-render scanline
+    while ppu.frame() < 2 {
+        ppu.tick(&mut memory);
+        if (ppu.scanline(), ppu.dot()) == (1, 10) {
+            // sprite 0 only overlaps the transparent background
+            assert!(!ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT));
+        }
+    }
+    let framebuffer = ppu.framebuffer();
+
+    assert_eq!(framebuffer[0], 0x11);
+    assert_eq!(framebuffer[7 * 256 + 7], 0x11);
+    assert_eq!(framebuffer[8], 0x0F);
+    // sprites are drawn one scanline lower
+    assert_eq!(framebuffer[8 + 256], 0x23);
+    assert_eq!(framebuffer[8 + 256 * 9], 0x0F);
+    // sprite 1 is hidden
+    assert_eq!(framebuffer[16 + 256], 0x0F);
+    // tile 0 with palette 1
+    assert_eq!(framebuffer[16 * 256 + 32], 0x0F);
+
+    // greyscale and emphasis
+    ppu.store_register(0x2001, 0x3F, &mut memory);
+    while ppu.frame() < 3 {
+        ppu.tick(&mut memory);
+    }
+    assert_eq!(ppu.framebuffer()[0], 0x10 | 0b001 << 6);
+}
+
+#[test]
+fn sprite_zero_hit() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    for row in 0..8 {
+        memory.store(0x0010 + row, 0xFF);
+    }
+    // fill the nametable with tile 1
+    for address in 0x2000..0x23C0 {
+        memory.store(address, 0x01);
+    }
+    ppu.oam.fill(0xFF);
+    ppu.oam[..4].copy_from_slice(&[100, 0x01, 0x00, 50]);
+    ppu.store_register(0x2001, 0x18, &mut memory);
+
+    while ppu.frame() < 1 {
+        ppu.tick(&mut memory);
+    }
+    // the sprite is drawn on the next scanline, x = 50 is output on dot 51
+    while (ppu.scanline(), ppu.dot()) != (101, 50) {
+        ppu.tick(&mut memory);
+        assert!(!ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT));
+    }
+    ppu.tick(&mut memory);
+    assert!(ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT));
+}
+
+#[test]
+fn oam_access_while_rendering() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    let run_to = |ppu: &mut Ppu, memory: &mut PpuMemoryMapping, scanline, dot| {
+        while (ppu.scanline(), ppu.dot()) != (scanline, dot) {
+            ppu.tick(memory);
+        }
+    };
+    for (index, value) in ppu.oam.iter_mut().enumerate() {
+        *value = index as u8;
+    }
+    ppu.oam_address = 2;
+    assert_eq!(ppu.load_register(0x2004, &mut memory), 0x02 & 0xE3);
+
+    ppu.mask = PpuMask::SHOW_BACKGROUND;
+    run_to(&mut ppu, &mut memory, 10, 30);
+    ppu.oam_address = 0x11;
+    // sprites are being cleared for the next scanline
+    assert_eq!(ppu.load_register(0x2004, &mut memory), 0xFF);
+    run_to(&mut ppu, &mut memory, 10, 100);
+    assert_eq!(ppu.load_register(0x2004, &mut memory), 0x11);
+    // writes are ignored, and only the sprite number goes up
+    ppu.store_register(0x2004, 0xAA, &mut memory);
+    assert_eq!(ppu.oam[0x11], 0x11);
+    assert_eq!(ppu.oam_address, 0x15);
+    // the sprite fetches clear OAMADDR
+    run_to(&mut ppu, &mut memory, 10, 300);
+    assert_eq!(ppu.oam_address, 0);
+
+    // writes go through during VBlank
+    run_to(&mut ppu, &mut memory, VBLANK_SCANLINE, 10);
+    ppu.store_register(0x2004, 0xAA, &mut memory);
+    assert_eq!((ppu.oam[0], ppu.oam_address), (0xAA, 1));
+
+    // OAMADDR at 8 or more at the start of rendering copies its row of OAM over the first one
+    ppu.oam_address = 0x23;
+    run_to(&mut ppu, &mut memory, SCANLINES_PER_FRAME - 1, 2);
+    assert_eq!(
+        ppu.oam[..8],
+        [0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27]
+    );
+    // only on the 2C02
+    ppu.set_variant(Variant::Ricoh2C03);
+    run_to(&mut ppu, &mut memory, VBLANK_SCANLINE, 10);
+    ppu.oam_address = 0x43;
+    run_to(&mut ppu, &mut memory, SCANLINES_PER_FRAME - 1, 2);
+    assert_eq!(
+        ppu.oam[..8],
+        [0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27]
+    );
+}
+
+#[test]
+fn sprite_limit() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    for row in 0..8 {
+        memory.store(0x0010 + row, 0xFF);
+    }
+    ppu.palette[0x11] = 0x16;
+    // 10 sprites of tile 1 side by side on scanline 50
+    ppu.oam.fill(0xFF);
+    for (index, sprite) in ppu.oam.chunks_exact_mut(4).take(10).enumerate() {
+        sprite.copy_from_slice(&[50, 0x01, 0x00, index as u8 * 8]);
+    }
+    ppu.store_register(0x2001, 0x14, &mut memory);
+
+    let mut draw = |ppu: &mut Ppu| {
+        let frame = ppu.frame();
+        while ppu.frame() < frame + 2 {
+            ppu.tick(&mut memory);
+        }
+        let row = 51 * 256;
+        (0..10)
+            .map(|sprite| ppu.framebuffer()[row + sprite * 8])
+            .collect::<Vec<_>>()
+    };
+    assert!(ppu.sprite_limit());
+    assert_eq!(draw(&mut ppu), [&[0x16; 8][..], &[0; 2]].concat());
+
+    ppu.set_sprite_limit(false);
+    assert_eq!(draw(&mut ppu), [0x16; 10]);
+}
+
+#[test]
+fn frame_skip() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    for row in 0..8 {
+        memory.store(0x0010 + row, 0xFF);
+    }
+    for address in 0x2000..0x23C0 {
+        memory.store(address, 0x01);
+    }
+    ppu.palette.fill(0x16);
+    ppu.oam.fill(0xFF);
+    ppu.oam[..4].copy_from_slice(&[100, 0x01, 0x00, 50]);
+    ppu.store_register(0x2001, 0x18, &mut memory);
+    // frames 0 and 1 are skipped, 2 is drawn
+    ppu.set_frame_skip(2);
+
+    while ppu.frame() < 1 {
+        ppu.tick(&mut memory);
+    }
+    while ppu.scanline() != 102 {
+        ppu.tick(&mut memory);
+    }
+    assert!(ppu.status.contains(PpuStatus::SPRITE_ZERO_HIT));
+    while ppu.frame() < 2 {
+        ppu.tick(&mut memory);
+    }
+    assert!(ppu.framebuffer().iter().all(|&pixel| pixel == 0));
+
+    while ppu.frame() < 3 {
+        ppu.tick(&mut memory);
+    }
+    assert!(ppu.framebuffer().iter().all(|&pixel| pixel == 0x16));
+}
+
+#[test]
+fn catch_up() {
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+
+    ppu.advance(1000, &mut memory);
+    assert_eq!((ppu.scanline(), ppu.dot()), (0, 0));
+    ppu.catch_up(&mut memory);
+    assert_eq!((ppu.scanline(), ppu.dot()), (2, 318));
+
+    // VBlank starting is never postponed
+    while ppu.scanline() < VBLANK_SCANLINE {
+        ppu.advance(3, &mut memory);
+    }
+    assert!(ppu.status.contains(PpuStatus::VBLANK));
+    assert_eq!(ppu.scanline(), VBLANK_SCANLINE);
+    assert!(ppu.dot() < 4);
+}
+
+#[test]
+fn colors() {
+    assert_eq!(palette::to_rgba(0x21), [76, 154, 236, 255]);
+    // red emphasis dims green and blue
+    assert_eq!(palette::to_rgb(0x21 | 0b001 << 6), [76, 125, 192]);
+    // emphasizing every channel dims none of them
+    assert_eq!(palette::to_rgb(0x30 | 0b111 << 6), [236, 238, 236]);
+    // bits above the emphasis ones are ignored
+    assert_eq!(palette::to_rgb(0x8021), palette::to_rgb(0x21));
+
+    let pixels: Vec<u16> = (0..512).collect();
+    let mut rgba = vec![0; 512 * 4];
+    palette::write_rgba_row(&pixels, &mut rgba);
+    for (&pixel, color) in pixels.iter().zip(rgba.chunks_exact(4)) {
+        assert_eq!(color, palette::RGBA_TABLE[pixel as usize]);
+    }
+}
+
+#[test]
+fn custom_palette() {
+    assert_eq!(palette::Palette::NTSC.to_rgba(0x21), palette::to_rgba(0x21));
+    assert!(palette::Palette::from_pal(&[0; 100]).is_none());
+
+    let colors: Vec<u8> = (0..64).flat_map(|color| [color, 0, 200]).collect();
+    let custom = palette::Palette::from_pal(&colors).unwrap();
+    assert_eq!(custom.to_rgb(0x21), [0x21, 0, 200]);
+    // emphasis is applied to the base colors
+    assert_eq!(custom.to_rgb(0x21 | 0b010 << 6), [26, 0, 163]);
+
+    let colors: Vec<u8> = (0..512u16)
+        .flat_map(|pixel| [pixel as u8, (pixel >> 8) as u8, 7])
+        .collect();
+    let full = palette::Palette::from_pal(&colors).unwrap();
+    assert_eq!(full.to_rgba(0x1FF), [0xFF, 1, 7, 255]);
+}
+
+#[test]
+fn rgb_variants() {
+    let rgb = Variant::Ricoh2C03.palette();
+    assert_eq!(rgb.to_rgba(0x21), [109, 182, 255, 255]);
+    // emphasis turns a channel all the way up instead of dimming the others
+    assert_eq!(rgb.to_rgb(0x21 | 0b001 << 6), [255, 182, 255]);
+    assert_eq!(rgb.to_rgb(0x0F | 0b110 << 6), [0, 255, 255]);
+    assert_eq!(
+        Variant::Ricoh2C02.palette().to_rgb(0x21),
+        palette::to_rgb(0x21)
+    );
+    // the 2C04s have the colors in their own orders
+    assert_eq!(Variant::Ricoh2C04A.palette().to_rgb(0x00), [255, 182, 182]);
+    assert_eq!(Variant::Ricoh2C04A.palette().to_rgb(0x08), [255, 255, 255]);
+    assert_eq!(Variant::Ricoh2C04B.palette().to_rgb(0x00), [0, 0, 0]);
+    assert_eq!(Variant::Ricoh2C04B.palette().to_rgb(0x01), [255, 182, 0]);
+    assert_eq!(Variant::Ricoh2C04C.palette().to_rgb(0x00), [182, 0, 255]);
+    assert_eq!(Variant::Ricoh2C04C.palette().to_rgb(0x09), [0, 0, 0]);
+    assert_eq!(Variant::Ricoh2C04D.palette().to_rgb(0x00), [145, 109, 0]);
+    assert_eq!(Variant::Ricoh2C04D.palette().to_rgb(0x3F), [36, 72, 0]);
+    assert_eq!(
+        Variant::Ricoh2C04D.palette().to_rgb(0x3F | 0b100 << 6),
+        [36, 72, 255]
+    );
+
+    let mut ppu = Ppu::new();
+    let mut vram = Ram::new();
+    let mut cartridge = cartridge();
+    let mut memory = PpuMemoryMapping {
+        vram: &mut vram,
+        cartridge: &mut cartridge,
+    };
+    ppu.store_register(0x2005, 0x1F, &mut memory);
+    assert_eq!(ppu.load_register(0x2002, &mut memory), 0x1F);
+
+    // the 2C05s have PPUCTRL and PPUMASK swapped, and an ID in PPUSTATUS
+    ppu.set_variant(Variant::Ricoh2C05C);
+    ppu.store_register(0x2000, 0x1E, &mut memory);
+    ppu.store_register(0x2009, 0x80, &mut memory);
+    assert_eq!(ppu.mask, PpuMask::from_bits_retain(0x1E));
+    assert_eq!(ppu.ctrl, PpuCtrl::NMI_ENABLE);
+    assert_eq!(ppu.load_register(0x2002, &mut memory), 0x1C);
+    ppu.set_variant(Variant::Ricoh2C05E);
+    assert_eq!(ppu.load_register(0x2002, &mut memory), 0x00);
+}
+
+#[test]
+fn pattern_rows() {
+    assert_eq!(
+        pattern::decode_row(0b1010_0001, 0b1100_0011),
+        [3, 2, 1, 0, 0, 0, 2, 3]
+    );
+    for low in 0..=0xFF {
+        for high in 0..=0xFF {
+            assert_eq!(
+                pattern::decode_row_wide(low, high),
+                pattern::decode_row_scalar(low, high),
+                "{low:08b} {high:08b}"
+            );
+        }
+    }
+}
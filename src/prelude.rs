@@ -0,0 +1,19 @@
+//! The types most frontends need, to be glob imported with `use nesty::prelude::*`
+//!
+//! This is the stable part of the API: loading games, running the console, feeding it input
+//! and getting pictures, sound and save states out of it.
+//! The hardware modules ([`cpu`](crate::cpu), [`ppu`](crate::ppu), [`apu`](crate::apu),
+//! [`memory`](crate::memory)) are public for debuggers and tools, but follow the emulator's
+//! internals and change along with them.
+
+pub use crate::{
+    cartridge::{Cartridge, LoadError},
+    clock::Region,
+    error::Error,
+    input::{ButtonState, Device, Expansion, InputProvider},
+    movie::Movie,
+    nes::{Accuracy, EmulationConfig, Frame, Nes, NesBuilder},
+    ppu::{palette::Palette, Overscan},
+    rewind::Rewind,
+    state::StateError,
+};
@@ -0,0 +1,238 @@
+//! Rewinding the emulation
+//!
+//! [`Rewind`] keeps a bounded history of save states, taken every few frames.
+//! Only the newest state is kept whole, every older one is stored as the difference from the state after it,
+//! which is mostly zeros and compresses well with a simple run length encoding.
+//! With the `compression` feature the encoded differences are compressed further
+//! with [`compression`](crate::state::compression).
+//!
+//! Going back to a frame between two snapshots loads the older one and runs the emulation forward.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::nes::Nes;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    /// PPU frame number the state was taken at
+    frame: u64,
+    /// Encoded difference from the next newer state
+    delta: Vec<u8>,
+    /// Size of the delta before it was compressed
+    #[cfg(feature = "compression")]
+    delta_size: usize,
+}
+
+impl Snapshot {
+    fn new(frame: u64, delta: Vec<u8>) -> Self {
+        #[cfg(feature = "compression")]
+        return Self {
+            frame,
+            delta_size: delta.len(),
+            delta: crate::state::compression::compress_block(&delta),
+        };
+        #[cfg(not(feature = "compression"))]
+        Self { frame, delta }
+    }
+
+    /// Turn the next newer state into this one
+    fn apply(&self, state: &mut [u8]) {
+        #[cfg(feature = "compression")]
+        {
+            let delta = crate::state::compression::decompress_block(&self.delta, self.delta_size)
+                .expect("rewind snapshots are compressed by the rewind");
+            apply_delta(state, &delta);
+        }
+        #[cfg(not(feature = "compression"))]
+        apply_delta(state, &self.delta);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    /// Maximum number of states, including the newest one
+    capacity: usize,
+    /// Frames between states
+    interval: u64,
+    snapshots: VecDeque<Snapshot>,
+    /// The newest state, whole, and the frame it was taken at
+    newest: Option<(u64, Vec<u8>)>,
+}
+
+impl Rewind {
+    /// Keep up to `capacity` states, one every `interval` frames
+    ///
+    /// # Panics
+    /// If `capacity` or `interval` is 0
+    pub fn new(capacity: usize, interval: u64) -> Self {
+        assert!(capacity > 0, "rewind capacity must be at least 1");
+        assert!(interval > 0, "rewind interval must be at least 1");
+        Self {
+            capacity,
+            interval,
+            snapshots: VecDeque::new(),
+            newest: None,
+        }
+    }
+
+    /// Number of states kept
+    pub fn len(&self) -> usize {
+        self.snapshots.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.newest = None;
+    }
+
+    /// Bytes taken by the stored states
+    pub fn memory_usage(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, |(_, state)| state.len());
+        newest
+            + self
+                .snapshots
+                .iter()
+                .map(|snapshot| snapshot.delta.len())
+                .sum::<usize>()
+    }
+
+    /// Oldest frame the console can be rewound to
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.snapshots
+            .front()
+            .map(|snapshot| snapshot.frame)
+            .or(self.newest.as_ref().map(|&(frame, _)| frame))
+    }
+
+    /// Record the console's state if enough frames have passed since the last one, meant to be called every frame
+    pub fn capture(&mut self, nes: &Nes) {
+        let frame = nes.ppu().frame();
+        if let Some((newest_frame, _)) = &self.newest {
+            // the console went back in time some other way, for example a loaded save state
+            if frame < *newest_frame {
+                self.clear();
+            } else if frame - newest_frame < self.interval {
+                return;
+            }
+        }
+        let Ok(state) = nes.save_state() else {
+            return;
+        };
+
+        if let Some((newest_frame, newest_state)) = self.newest.take() {
+            // states of a different game can't be diffed
+            if newest_state.len() == state.len() {
+                self.snapshots.push_back(Snapshot::new(
+                    newest_frame,
+                    encode_delta(&state, &newest_state),
+                ));
+            } else {
+                self.snapshots.clear();
+            }
+        }
+        self.newest = Some((frame, state));
+
+        while self.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Take the console back by up to `frames` frames, returns how many frames it actually went back
+    ///
+    /// States newer than the frame the console ends up on are dropped.
+    pub fn rewind(&mut self, nes: &mut Nes, frames: u64) -> u64 {
+        let current = nes.ppu().frame();
+        let Some(oldest) = self.oldest_frame() else {
+            return 0;
+        };
+        let target = current.saturating_sub(frames).max(oldest);
+
+        let Some((mut frame, mut state)) = self.newest.take() else {
+            return 0;
+        };
+        while frame > target {
+            let Some(snapshot) = self.snapshots.pop_back() else {
+                break;
+            };
+            snapshot.apply(&mut state);
+            frame = snapshot.frame;
+        }
+
+        if nes.load_state(&state).is_err() {
+            self.clear();
+            return 0;
+        }
+        self.newest = Some((frame, state));
+
+        while nes.ppu().frame() < target {
+            nes.run_frame();
+        }
+        current - nes.ppu().frame()
+    }
+}
+
+/// Encode the bytes that differ between `from` and `to`, which must be the same length
+///
+/// The XOR of the two is stored as pairs of a run of zeros and a run of literal bytes,
+/// with both lengths as LEB128 varints.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut xor = from.iter().zip(to).map(|(a, b)| a ^ b).peekable();
+
+    while xor.peek().is_some() {
+        let mut zeros = 0;
+        while xor.next_if_eq(&0).is_some() {
+            zeros += 1;
+        }
+        let mut literals = Vec::new();
+        while let Some(byte) = xor.next_if(|&byte| byte != 0) {
+            literals.push(byte);
+        }
+        write_varint(&mut encoded, zeros);
+        write_varint(&mut encoded, literals.len());
+        encoded.extend(literals);
+    }
+    encoded
+}
+
+/// Reverse of [`encode_delta`], turns `state` into the other state in place
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut delta = delta.iter().copied();
+    let mut position = 0;
+    while let Some(zeros) = read_varint(&mut delta) {
+        position += zeros;
+        let literals = read_varint(&mut delta).unwrap_or(0);
+        for byte in state[position..position + literals].iter_mut() {
+            *byte ^= delta.next().unwrap_or(0);
+        }
+        position += literals;
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
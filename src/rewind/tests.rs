@@ -0,0 +1,78 @@
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    nes::Nes,
+};
+
+use super::{apply_delta, encode_delta, Rewind};
+
+/// NROM cartridge full of `LDX #$A2`
+fn cartridge() -> Cartridge {
+    Cartridge::from_ines(&nrom_image(&[0xA2; 0x7FFC], 0, 0)).unwrap()
+}
+
+/// Run a frame and mark it in RAM, the program counter is moved back so the CPU never runs out of instructions
+fn run_frame(nes: &mut Nes) {
+    nes.run_frame();
+    nes.cpu_mut().program_counter = 0x8000;
+    let frame = nes.ppu().frame() as u8;
+    nes.ram_mut().store(0x0000, frame);
+}
+
+#[test]
+fn delta() {
+    let from = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let to = [1, 2, 0, 4, 5, 6, 0, 0, 9, 11];
+    let delta = encode_delta(&from, &to);
+    let mut state = from;
+    apply_delta(&mut state, &delta);
+    assert_eq!(state, to);
+
+    let zeros = vec![0; 1000];
+    assert!(encode_delta(&zeros, &zeros).len() < 4);
+}
+
+#[test]
+fn rewind() {
+    let mut nes = Nes::new();
+    let mut rewind = Rewind::new(4, 1);
+    assert_eq!(rewind.rewind(&mut nes, 1), 0);
+
+    nes.insert_cartridge(cartridge());
+    nes.power_cycle();
+    for _ in 0..10 {
+        run_frame(&mut nes);
+        rewind.capture(&nes);
+    }
+    assert_eq!(rewind.len(), 4);
+    assert_eq!(rewind.oldest_frame(), Some(7));
+
+    assert_eq!(rewind.rewind(&mut nes, 2), 2);
+    assert_eq!(nes.ppu().frame(), 8);
+    assert_eq!(nes.ram().load(0x0000), 8);
+    assert_eq!(rewind.len(), 2);
+
+    // can't go back past the oldest state
+    assert_eq!(rewind.rewind(&mut nes, 5), 1);
+    assert_eq!(nes.ram().load(0x0000), 7);
+    assert_eq!(rewind.len(), 1);
+}
+
+#[test]
+fn interval() {
+    let mut nes = Nes::new();
+    let mut rewind = Rewind::new(10, 3);
+    nes.insert_cartridge(cartridge());
+    nes.power_cycle();
+    for _ in 0..10 {
+        run_frame(&mut nes);
+        rewind.capture(&nes);
+    }
+    // frames 1, 4, 7 and 10
+    assert_eq!(rewind.len(), 4);
+
+    // going back to frame 8 loads frame 7 and runs one frame
+    assert_eq!(rewind.rewind(&mut nes, 2), 2);
+    assert_eq!(nes.ppu().frame(), 8);
+    assert_eq!(nes.ram().load(0x0000), 7);
+    assert_eq!(rewind.len(), 3);
+}
@@ -0,0 +1,309 @@
+//! Save states
+//!
+//! A save state is a binary snapshot of everything in the console that can change while it runs,
+//! made by [`Nes::save_state`](crate::nes::Nes::save_state).
+//! It starts with the `NSTY` magic, the format version and the CRC32 of the ROM it was made with,
+//! followed by a section for every component, each one tagged and prefixed with its length.
+//! All numbers are little endian.
+//!
+//! ROM contents and user settings like cheats aren't part of the state.
+//! [`diff::diff`] compares two states section by section.
+//! States can be shrunk with [`compression`], compressed states load like any other.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt::{Display, Formatter};
+
+pub mod compression;
+pub mod diff;
+#[cfg(test)]
+mod tests;
+
+/// Version of the save state format written by this version of the emulator
+///
+/// Bumped whenever the layout changes, states from newer versions are rejected
+pub const FORMAT_VERSION: u16 = 7;
+
+const MAGIC: &[u8; 4] = b"NSTY";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The data isn't a save state
+    InvalidFormat,
+    /// The state was made by a newer version of the emulator
+    UnsupportedVersion(u16),
+    /// The state was made with a different game, `expected` is the CRC32 of the inserted one
+    RomMismatch { expected: u32, found: u32 },
+    /// There's no cartridge to load the state into
+    NoCartridge,
+    /// The data ended before the whole state was read
+    Truncated,
+    /// Some value doesn't fit in the console, like a memory of the wrong size
+    InvalidData,
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StateError::InvalidFormat => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state is for a different ROM (CRC32 {found:08X}, expected {expected:08X})"
+            ),
+            StateError::NoCartridge => write!(f, "no cartridge inserted"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::InvalidData => write!(f, "save state contains invalid data"),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+/// Something that can be written into a save state and restored from it
+pub(crate) trait State {
+    fn save_state(&self, writer: &mut StateWriter);
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Implement [`State`] for a struct by saving and loading the listed fields in order
+macro_rules! impl_state {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        impl $crate::state::State for $type {
+            fn save_state(&self, writer: &mut $crate::state::StateWriter) {
+                $($crate::state::State::save_state(&self.$field, writer);)*
+            }
+
+            fn load_state(
+                &mut self,
+                reader: &mut $crate::state::StateReader,
+            ) -> Result<(), $crate::state::StateError> {
+                $($crate::state::State::load_state(&mut self.$field, reader)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use impl_state;
+
+/// Implement [`State`] for bitflags types by saving their bits
+macro_rules! impl_state_bits {
+    ($($type:ty),* $(,)?) => {
+        $(impl $crate::state::State for $type {
+            fn save_state(&self, writer: &mut $crate::state::StateWriter) {
+                $crate::state::State::save_state(&self.bits(), writer);
+            }
+
+            fn load_state(
+                &mut self,
+                reader: &mut $crate::state::StateReader,
+            ) -> Result<(), $crate::state::StateError> {
+                let mut bits = self.bits();
+                $crate::state::State::load_state(&mut bits, reader)?;
+                *self = Self::from_bits_retain(bits);
+                Ok(())
+            }
+        })*
+    };
+}
+pub(crate) use impl_state_bits;
+
+#[derive(Debug, Default)]
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Start a state with the header
+    pub(crate) fn new(rom_crc32: u32) -> Self {
+        let mut writer = Self::default();
+        writer.write(MAGIC);
+        writer.write(&FORMAT_VERSION.to_le_bytes());
+        writer.write(&rom_crc32.to_le_bytes());
+        writer
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Write a tagged section containing whatever `f` writes
+    pub(crate) fn section(&mut self, tag: &[u8; 4], f: impl FnOnce(&mut Self)) {
+        self.write(tag);
+        let length_offset = self.buf.len();
+        self.write(&[0; 4]);
+        f(self);
+        let length = (self.buf.len() - length_offset - 4) as u32;
+        self.buf[length_offset..length_offset + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    version: u16,
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header, returns the reader and the ROM's CRC32
+    pub(crate) fn new(data: &'a [u8]) -> Result<(Self, u32), StateError> {
+        let data = data.strip_prefix(MAGIC).ok_or(StateError::InvalidFormat)?;
+        let mut reader = Self { data, version: 0 };
+
+        let mut version = 0u16;
+        version.load_state(&mut reader)?;
+        if version > FORMAT_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        reader.version = version;
+
+        let mut crc32 = 0u32;
+        crc32.load_state(&mut reader)?;
+        Ok((reader, crc32))
+    }
+
+    /// Format version of the state being read
+    pub(crate) fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub(crate) fn read(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < length {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Read a section written by [`StateWriter::section`], the tag must match
+    pub(crate) fn section(
+        &mut self,
+        tag: &[u8; 4],
+        f: impl FnOnce(&mut StateReader<'a>) -> Result<(), StateError>,
+    ) -> Result<(), StateError> {
+        if self.read(4)? != tag {
+            return Err(StateError::InvalidFormat);
+        }
+        let mut length = 0u32;
+        length.load_state(self)?;
+        let mut section = StateReader {
+            data: self.read(length as usize)?,
+            version: self.version,
+        };
+        f(&mut section)
+    }
+}
+
+macro_rules! impl_state_int {
+    ($($type:ty),*) => {
+        $(impl State for $type {
+            fn save_state(&self, writer: &mut StateWriter) {
+                writer.write(&self.to_le_bytes());
+            }
+
+            fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+                let bytes = reader.read(size_of::<$type>())?;
+                *self = <$type>::from_le_bytes(bytes.try_into().unwrap());
+                Ok(())
+            }
+        })*
+    };
+}
+impl_state_int!(u8, u16, u32, u64, f32);
+
+impl State for usize {
+    fn save_state(&self, writer: &mut StateWriter) {
+        (*self as u64).save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u64;
+        value.load_state(reader)?;
+        *self = value.try_into().map_err(|_| StateError::InvalidData)?;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save_state(&self, writer: &mut StateWriter) {
+        (*self as u8).save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut value = 0u8;
+        value.load_state(reader)?;
+        *self = match value {
+            0 => false,
+            1 => true,
+            _ => return Err(StateError::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.iter().for_each(|value| value.save_state(writer));
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.iter_mut()
+            .try_for_each(|value| value.load_state(reader))
+    }
+}
+
+impl<T: State + ?Sized> State for Box<T> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        (**self).save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        (**self).load_state(reader)
+    }
+}
+
+/// Memories are prefixed with their size, which has to match when loading
+impl State for [u8] {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.len().save_state(writer);
+        writer.write(self);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut length = 0usize;
+        length.load_state(reader)?;
+        if length != self.len() {
+            return Err(StateError::InvalidData);
+        }
+        self.copy_from_slice(reader.read(length)?);
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.is_some().save_state(writer);
+        if let Some(value) = self {
+            value.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut is_some = false;
+        is_some.load_state(reader)?;
+        *self = if is_some {
+            let mut value = self.take().unwrap_or_default();
+            value.load_state(reader)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Compressed save states
+//!
+//! States are mostly zeros and repeated bytes, so they shrink to a fraction of their size
+//! with a fast LZ77 compressor, written in the LZ4 block format.
+//! A compressed state is the `NSTZ` magic, the size of the state as a 32-bit number,
+//! and the block. [`Nes::load_state`](crate::nes::Nes::load_state) and
+//! [`diff`](super::diff::diff) take compressed states as they are.
+//!
+//! With the `compression` feature, [`Rewind`](crate::rewind::Rewind) also compresses its snapshots.
+//!
+//! The block format is described at https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+
+use alloc::{borrow::Cow, vec::Vec};
+
+use super::StateError;
+
+const MAGIC: &[u8; 4] = b"NSTZ";
+const HEADER_SIZE: usize = 8;
+
+const MIN_MATCH: usize = 4;
+#[cfg(feature = "compression")]
+const MAX_OFFSET: usize = 0xFFFF;
+/// The last bytes of a block are always literals
+#[cfg(feature = "compression")]
+const LAST_LITERALS: usize = 5;
+/// No match starts in the last bytes of a block
+#[cfg(feature = "compression")]
+const MATCH_LIMIT: usize = 12;
+#[cfg(feature = "compression")]
+const HASH_BITS: u32 = 12;
+/// A nibble of the token with this value is followed by more bytes of the length
+const LONG_LENGTH: usize = 15;
+
+/// Compress a state made by [`Nes::save_state`](crate::nes::Nes::save_state)
+#[cfg(feature = "compression")]
+pub fn compress(state: &[u8]) -> Vec<u8> {
+    let mut compressed = MAGIC.to_vec();
+    compressed.extend_from_slice(&(state.len() as u32).to_le_bytes());
+    compressed.extend(compress_block(state));
+    compressed
+}
+
+/// Whether the data starts like a compressed state
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Decompress a state made by [`compress`], states that aren't compressed are returned as they are
+pub fn decompress(data: &[u8]) -> Result<Cow<'_, [u8]>, StateError> {
+    if !is_compressed(data) {
+        return Ok(Cow::Borrowed(data));
+    }
+    let size = data
+        .get(MAGIC.len()..HEADER_SIZE)
+        .ok_or(StateError::Truncated)?;
+    let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+    let state = decompress_block(&data[HEADER_SIZE..], size).ok_or(StateError::InvalidData)?;
+    if state.len() != size {
+        return Err(StateError::InvalidData);
+    }
+    Ok(Cow::Owned(state))
+}
+
+#[cfg(feature = "compression")]
+fn write_length(block: &mut Vec<u8>, mut len: usize) {
+    while len >= 0xFF {
+        block.push(0xFF);
+        len -= 0xFF;
+    }
+    block.push(len as u8);
+}
+
+/// Write literals followed by a match at an offset back with a length, the last sequence has none
+#[cfg(feature = "compression")]
+fn write_sequence(block: &mut Vec<u8>, literals: &[u8], copy: Option<(usize, usize)>) {
+    let match_len = copy.map_or(0, |(_, len)| len - MIN_MATCH);
+    block.push((literals.len().min(LONG_LENGTH) << 4 | match_len.min(LONG_LENGTH)) as u8);
+    if literals.len() >= LONG_LENGTH {
+        write_length(block, literals.len() - LONG_LENGTH);
+    }
+    block.extend_from_slice(literals);
+    if let Some((offset, _)) = copy {
+        block.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= LONG_LENGTH {
+            write_length(block, match_len - LONG_LENGTH);
+        }
+    }
+}
+
+/// Compress data into an LZ4 block, finding matches with a hash table of the last position
+/// of every 4 bytes
+#[cfg(feature = "compression")]
+pub(crate) fn compress_block(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() / 4);
+    // positions plus one, 0 is empty
+    let mut table = alloc::vec![0usize; 1 << HASH_BITS];
+    let read =
+        |position: usize| u32::from_le_bytes(data[position..position + 4].try_into().unwrap());
+    let match_end = data.len().saturating_sub(LAST_LITERALS);
+
+    let mut literals = 0;
+    let mut position = 0;
+    while position + MATCH_LIMIT < data.len() {
+        let bytes = read(position);
+        let hash = (bytes.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+        let candidate = core::mem::replace(&mut table[hash], position + 1);
+        let found = candidate
+            .checked_sub(1)
+            .filter(|&start| position - start <= MAX_OFFSET && read(start) == bytes);
+        let Some(start) = found else {
+            position += 1;
+            continue;
+        };
+
+        let mut len = MIN_MATCH;
+        while position + len < match_end && data[start + len] == data[position + len] {
+            len += 1;
+        }
+        write_sequence(
+            &mut block,
+            &data[literals..position],
+            Some((position - start, len)),
+        );
+        position += len;
+        literals = position;
+    }
+    write_sequence(&mut block, &data[literals..], None);
+    block
+}
+
+fn read_length(block: &mut impl Iterator<Item = u8>, mut len: usize) -> Option<usize> {
+    loop {
+        let byte = block.next()?;
+        len += byte as usize;
+        if byte != 0xFF {
+            return Some(len);
+        }
+    }
+}
+
+/// Decompress an LZ4 block, `size` is the expected size of the data
+pub(crate) fn decompress_block(block: &[u8], size: usize) -> Option<Vec<u8>> {
+    // a byte of the block gives at most 255 bytes of data, anything bigger is corrupt
+    if size > block.len().saturating_mul(255) {
+        return None;
+    }
+    let mut data = Vec::with_capacity(size);
+    let mut block = block.iter().copied();
+    while let Some(token) = block.next() {
+        let mut literals = (token >> 4) as usize;
+        if literals == LONG_LENGTH {
+            literals = read_length(&mut block, literals)?;
+        }
+        for _ in 0..literals {
+            data.push(block.next()?);
+        }
+        // the last sequence ends after its literals
+        let Some(low) = block.next() else {
+            break;
+        };
+        let offset = u16::from_le_bytes([low, block.next()?]) as usize;
+        let start = data.len().checked_sub(offset).filter(|_| offset != 0)?;
+        let mut len = (token & 0xF) as usize;
+        if len == LONG_LENGTH {
+            len = read_length(&mut block, len)?;
+        }
+        if data.len() + len + MIN_MATCH > size {
+            return None;
+        }
+        // the copy can overlap what it's writing
+        for index in start..start + len + MIN_MATCH {
+            data.push(data[index]);
+        }
+    }
+    Some(data)
+}
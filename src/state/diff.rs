@@ -0,0 +1,179 @@
+//! Comparing save states, to find where two runs that should be the same went apart
+//!
+//! When a movie or a netplay session desyncs, diffing the states saved on both sides
+//! on the first frame their checksums differ shows which component went wrong
+//! and which bytes of it: the RAM addresses, the VRAM offsets, the registers of the CPU,
+//! the PPU, the APU or the cartridge hardware.
+
+use alloc::vec::Vec;
+use core::{
+    fmt::{Display, Formatter},
+    ops::Range,
+};
+
+use super::{compression::decompress, StateError, StateReader};
+
+/// A component of the console in a save state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    /// The clock, the cycle count, OAM DMA and the open bus
+    Console,
+    Cpu,
+    /// The 2KB of CPU RAM, the offsets are the addresses
+    Ram,
+    /// The 2KB of nametable RAM, which of its halves are at $2000 depends on the mirroring
+    Vram,
+    Ppu,
+    Apu,
+    /// PRG RAM after its 8 byte size, then CHR RAM and the state of the board
+    Cartridge,
+    /// The devices plugged into the controller ports
+    Input,
+    /// A section this version doesn't know, with its tag
+    Other([u8; 4]),
+}
+
+impl Section {
+    fn from_tag(tag: [u8; 4]) -> Self {
+        match &tag {
+            b"NES " => Section::Console,
+            b"CPU " => Section::Cpu,
+            b"RAM " => Section::Ram,
+            b"VRAM" => Section::Vram,
+            b"PPU " => Section::Ppu,
+            b"APU " => Section::Apu,
+            b"CART" => Section::Cartridge,
+            b"INPT" => Section::Input,
+            _ => Section::Other(tag),
+        }
+    }
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Section::Console => write!(f, "console"),
+            Section::Cpu => write!(f, "CPU"),
+            Section::Ram => write!(f, "RAM"),
+            Section::Vram => write!(f, "VRAM"),
+            Section::Ppu => write!(f, "PPU"),
+            Section::Apu => write!(f, "APU"),
+            Section::Cartridge => write!(f, "cartridge"),
+            Section::Input => write!(f, "input"),
+            Section::Other(tag) => write!(f, "{}", tag.escape_ascii()),
+        }
+    }
+}
+
+/// The bytes of a section that differ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionDiff {
+    pub section: Section,
+    /// Runs of bytes that differ, as offsets into the section, in order
+    ///
+    /// A section that's longer in one state differs from the end of the shorter one,
+    /// and a section that's missing from a state differs entirely.
+    pub ranges: Vec<Range<usize>>,
+}
+
+/// The differences between two save states, see [`diff`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Only the sections that differ, in the order of the first state
+    pub sections: Vec<SectionDiff>,
+}
+
+impl StateDiff {
+    /// The states are the same, apart from their format version
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// The differing ranges of a section, empty if it's the same in both states
+    pub fn section(&self, section: Section) -> &[Range<usize>] {
+        self.sections
+            .iter()
+            .find(|diff| diff.section == section)
+            .map_or(&[], |diff| &diff.ranges)
+    }
+}
+
+/// A line per section, like `RAM: $0010-$0012 $0300`
+impl Display for StateDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for diff in &self.sections {
+            write!(f, "{}:", diff.section)?;
+            for range in &diff.ranges {
+                match range.len() {
+                    1 => write!(f, " ${:04X}", range.start)?,
+                    _ => write!(f, " ${:04X}-${:04X}", range.start, range.end - 1)?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// The tag and the contents of a section
+type RawSection<'a> = ([u8; 4], &'a [u8]);
+
+/// The CRC32 of the ROM and the sections of a state, after checking its header
+fn sections(state: &[u8]) -> Result<(u32, Vec<RawSection<'_>>), StateError> {
+    let (mut reader, crc32) = StateReader::new(state)?;
+    let mut sections = Vec::new();
+    while !reader.data.is_empty() {
+        let tag = reader.read(4)?.try_into().unwrap();
+        let length = u32::from_le_bytes(reader.read(4)?.try_into().unwrap());
+        sections.push((tag, reader.read(length as usize)?));
+    }
+    Ok((crc32, sections))
+}
+
+/// Runs of different bytes, past the end of the shorter slice everything differs
+fn differing_ranges(a: &[u8], b: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let len = a.len().max(b.len());
+    for offset in (0..len).filter(|&offset| a.get(offset) != b.get(offset)) {
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    ranges
+}
+
+/// Compare two states made by [`Nes::save_state`](crate::nes::Nes::save_state)
+/// with the same game
+///
+/// States of different format versions can be compared, but sections that changed layout
+/// between them will show up as different.
+pub fn diff(a: &[u8], b: &[u8]) -> Result<StateDiff, StateError> {
+    let (a, b) = (decompress(a)?, decompress(b)?);
+    let (expected, a) = sections(&a)?;
+    let (found, mut b) = sections(&b)?;
+    if expected != found {
+        return Err(StateError::RomMismatch { expected, found });
+    }
+
+    let mut diff = StateDiff::default();
+    let mut push = |tag, a, b| {
+        let ranges = differing_ranges(a, b);
+        if !ranges.is_empty() {
+            let section = Section::from_tag(tag);
+            diff.sections.push(SectionDiff { section, ranges });
+        }
+    };
+    for (tag, a) in a {
+        let b = match b.iter().position(|&(other, _)| other == tag) {
+            Some(index) => b.remove(index).1,
+            None => &[],
+        };
+        push(tag, a, b);
+    }
+    // sections only in the second state
+    for (tag, b) in b {
+        push(tag, &[], b);
+    }
+    Ok(diff)
+}
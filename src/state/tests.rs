@@ -0,0 +1,153 @@
+use core::ops::Range;
+
+use crate::{
+    cartridge::{tests::nrom_image, Cartridge},
+    nes::Nes,
+};
+
+use super::{
+    diff::{diff, Section},
+    StateError, FORMAT_VERSION,
+};
+
+/// NROM cartridge with PRG ROM full of `prg_fill`, and CHR RAM
+fn cartridge(prg_fill: u8) -> Cartridge {
+    Cartridge::from_ines(&nrom_image(&[prg_fill; 0x7FFC], 0, 0)).unwrap()
+}
+
+#[test]
+fn round_trip() {
+    let mut nes = Nes::new();
+    assert_eq!(nes.save_state(), Err(StateError::NoCartridge));
+    nes.insert_cartridge(cartridge(0xA2));
+    nes.power_cycle();
+    nes.run_cycles(1000);
+    nes.ram_mut().store(0x0123, 0x45);
+
+    let state = nes.save_state().unwrap();
+    nes.run_cycles(1000);
+    nes.ram_mut().store(0x0123, 0x67);
+    let expected = (nes.cycle(), nes.ppu().dot(), nes.cpu().program_counter);
+
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.cycle(), 1000);
+    assert_eq!(nes.ram().load(0x0123), 0x45);
+    nes.run_cycles(1000);
+    assert_eq!(
+        (nes.cycle(), nes.ppu().dot(), nes.cpu().program_counter),
+        expected
+    );
+    // states are deterministic
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.save_state().unwrap(), state);
+}
+
+#[test]
+fn errors() {
+    let mut nes = Nes::new();
+    assert_eq!(nes.load_state(b""), Err(StateError::NoCartridge));
+    nes.insert_cartridge(cartridge(0xA2));
+    nes.power_cycle();
+    let state = nes.save_state().unwrap();
+    nes.run_cycles(10);
+
+    assert_eq!(nes.load_state(b"NOPE"), Err(StateError::InvalidFormat));
+    assert_eq!(
+        nes.load_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+
+    let mut newer = state.clone();
+    newer[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        nes.load_state(&newer),
+        Err(StateError::UnsupportedVersion(FORMAT_VERSION + 1))
+    );
+
+    let mut other = Nes::new();
+    other.insert_cartridge(cartridge(0xA0));
+    other.power_cycle();
+    assert!(matches!(
+        other.load_state(&state),
+        Err(StateError::RomMismatch { .. })
+    ));
+    // failed loads leave the console alone
+    assert_eq!(nes.cycle(), 10);
+}
+
+#[test]
+fn state_diff() {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge(0xEA));
+    nes.power_cycle();
+    let state = nes.save_state().unwrap();
+    assert!(diff(&state, &state).unwrap().is_empty());
+
+    nes.ram_mut().store(0x0010, 1);
+    nes.ram_mut().store(0x0011, 2);
+    nes.ram_mut().store(0x0300, 3);
+    nes.cartridge_mut().unwrap().prg_ram_mut()[5] = 4;
+    let changed = nes.save_state().unwrap();
+    let state_diff = diff(&state, &changed).unwrap();
+    assert_eq!(state_diff.section(Section::Ram), [0x10..0x12, 0x300..0x301]);
+    // after the size of the PRG RAM
+    assert_eq!(
+        state_diff.section(Section::Cartridge),
+        [Range { start: 13, end: 14 }]
+    );
+    assert_eq!(state_diff.section(Section::Cpu), []);
+    assert_eq!(
+        state_diff.to_string(),
+        "RAM: $0010-$0011 $0300\ncartridge: $000D\n"
+    );
+
+    nes.run_cycles(100);
+    let later = diff(&state, &nes.save_state().unwrap()).unwrap();
+    assert!(!later.section(Section::Console).is_empty());
+    assert!(!later.section(Section::Cpu).is_empty());
+
+    let mut other = Nes::new();
+    other.insert_cartridge(cartridge(0xA2));
+    assert!(matches!(
+        diff(&state, &other.save_state().unwrap()),
+        Err(StateError::RomMismatch { .. })
+    ));
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compression() {
+    use super::compression::{compress, decompress, is_compressed};
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge(0xA2));
+    nes.power_cycle();
+    nes.run_cycles(1000);
+    let state = nes.save_state().unwrap();
+    let compressed = compress(&state);
+    assert!(is_compressed(&compressed));
+    assert!(compressed.len() * 10 < state.len());
+    assert_eq!(decompress(&compressed).unwrap(), state);
+    // uncompressed states pass through
+    assert_eq!(decompress(&state).unwrap(), state);
+
+    nes.run_cycles(1000);
+    nes.load_state(&compressed).unwrap();
+    assert_eq!(nes.cycle(), 1000);
+    assert!(diff(&state, &compressed).unwrap().is_empty());
+
+    assert_eq!(decompress(&compressed[..6]), Err(StateError::Truncated));
+    assert_eq!(
+        decompress(&compressed[..compressed.len() - 1]),
+        Err(StateError::InvalidData)
+    );
+    // a size the data can't have isn't allocated
+    let mut huge = compressed.clone();
+    huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(decompress(&huge), Err(StateError::InvalidData));
+
+    // short inputs and long runs
+    for data in [&b""[..], b"abc", &[7; 1000], b"abcabcabcabcabcabcabcabc"] {
+        assert_eq!(decompress(&compress(data)).unwrap(), data);
+    }
+}
@@ -0,0 +1,337 @@
+//! Accuracy tests with blargg's test ROMs
+//!
+//! The ROMs aren't part of the repository. Point `NESTY_TEST_ROMS` at a checkout of
+//! <https://github.com/christopherpow/nes-test-roms> and run `cargo test test_roms -- --ignored`.
+//!
+//! The ROMs report through the cartridge RAM: $6001-$6003 hold `DE B0 61` once the rest is valid,
+//! $6000 is $80 while the test is running, $81 when it wants the console to be reset,
+//! and the result code otherwise, 0 meaning it passed. $6004 has the text it printed.
+//!
+//! ROMs that only show their results on screen are checked against golden frames instead,
+//! the CRC32 of the picture after running them for some frames. Every ROM listed in
+//! `test_roms/golden_frames.txt` is checked by `cargo test golden_frames -- --ignored`,
+//! a mismatching frame is saved as a PPM image in `target/golden_frames` to be looked at.
+//! New ROMs go in the file with `?` for the CRC, the test then saves their picture
+//! and prints the line to replace it with once the picture shows a pass.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cartridge::{database::crc32, tests::nrom_image, Cartridge},
+    nes::{Frame, Nes},
+};
+
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+
+/// Frames to wait before pressing reset, the ROMs ask for at least 100ms
+const RESET_DELAY: u32 = 10;
+/// Longest a ROM gets to finish, in frames
+const TIMEOUT: u32 = 60 * 60;
+
+/// Result of a test ROM, if it finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Outcome {
+    code: u8,
+    text: String,
+}
+
+/// Runs a test ROM until it reports a result
+fn run(rom: &[u8]) -> Result<Outcome, String> {
+    let cartridge = Cartridge::from_ines(rom).map_err(|error| error.to_string())?;
+
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    nes.power_cycle();
+    let mut reset_in = None;
+    for _ in 0..TIMEOUT {
+        nes.run_frame();
+        if read_signature(&nes) != SIGNATURE {
+            continue;
+        }
+        match (nes.peek(0x6000), reset_in) {
+            (RUNNING, _) => {}
+            (NEEDS_RESET, None) => reset_in = Some(RESET_DELAY),
+            (NEEDS_RESET, Some(0)) => {
+                nes.reset();
+                reset_in = None;
+            }
+            (NEEDS_RESET, Some(frames)) => reset_in = Some(frames - 1),
+            (code, _) => {
+                return Ok(Outcome {
+                    code,
+                    text: read_text(&nes),
+                })
+            }
+        }
+    }
+    Err(format!(
+        "no result after {TIMEOUT} frames:\n{}",
+        read_text(&nes)
+    ))
+}
+
+fn read_signature(nes: &Nes) -> [u8; 3] {
+    [0x6001, 0x6002, 0x6003].map(|address| nes.peek(address))
+}
+
+/// The zero terminated text at $6004
+fn read_text(nes: &Nes) -> String {
+    (0x6004..0x8000)
+        .map(|address| nes.peek(address))
+        .take_while(|&byte| byte != 0)
+        .map(char::from)
+        .collect()
+}
+
+/// Reads the ROM at `path` in the checkout
+fn read_rom(path: &str) -> Vec<u8> {
+    let directory = std::env::var("NESTY_TEST_ROMS")
+        .expect("NESTY_TEST_ROMS should be the directory with the test ROMs");
+    std::fs::read(PathBuf::from(directory).join(path))
+        .unwrap_or_else(|error| panic!("{path}: {error}"))
+}
+
+/// Runs the ROM at `path` in the checkout and panics with its text unless it passed
+fn check(path: &str) {
+    match run(&read_rom(path)) {
+        Ok(Outcome { code: 0, .. }) => {}
+        Ok(Outcome { code, text }) => panic!("{path} failed with code {code}:\n{text}"),
+        Err(error) => panic!("{path}: {error}"),
+    }
+}
+
+macro_rules! test_roms {
+    ($($name:ident => $path:literal,)*) => {
+        $(
+            #[test]
+            #[ignore = "needs the test ROMs, see the module docs"]
+            fn $name() {
+                check($path);
+            }
+        )*
+    };
+}
+
+test_roms! {
+    cpu_basics => "instr_test-v5/rom_singles/01-basics.nes",
+    cpu_implied => "instr_test-v5/rom_singles/02-implied.nes",
+    cpu_immediate => "instr_test-v5/rom_singles/03-immediate.nes",
+    cpu_zero_page => "instr_test-v5/rom_singles/04-zero_page.nes",
+    cpu_zp_xy => "instr_test-v5/rom_singles/05-zp_xy.nes",
+    cpu_absolute => "instr_test-v5/rom_singles/06-absolute.nes",
+    cpu_abs_xy => "instr_test-v5/rom_singles/07-abs_xy.nes",
+    cpu_ind_x => "instr_test-v5/rom_singles/08-ind_x.nes",
+    cpu_ind_y => "instr_test-v5/rom_singles/09-ind_y.nes",
+    cpu_branches => "instr_test-v5/rom_singles/10-branches.nes",
+    cpu_stack => "instr_test-v5/rom_singles/11-stack.nes",
+    cpu_jmp_jsr => "instr_test-v5/rom_singles/12-jmp_jsr.nes",
+    cpu_rts => "instr_test-v5/rom_singles/13-rts.nes",
+    cpu_rti => "instr_test-v5/rom_singles/14-rti.nes",
+    cpu_brk => "instr_test-v5/rom_singles/15-brk.nes",
+    cpu_special => "instr_test-v5/rom_singles/16-special.nes",
+    cpu_abs_x_wrap => "instr_misc/rom_singles/01-abs_x_wrap.nes",
+    cpu_branch_wrap => "instr_misc/rom_singles/02-branch_wrap.nes",
+    cpu_dummy_reads => "instr_misc/rom_singles/03-dummy_reads.nes",
+    cpu_dummy_reads_apu => "instr_misc/rom_singles/04-dummy_reads_apu.nes",
+    cpu_instr_timing => "instr_timing/rom_singles/1-instr_timing.nes",
+    cpu_branch_timing => "instr_timing/rom_singles/2-branch_timing.nes",
+    cpu_cli_latency => "cpu_interrupts_v2/rom_singles/1-cli_latency.nes",
+    cpu_nmi_and_brk => "cpu_interrupts_v2/rom_singles/2-nmi_and_brk.nes",
+    cpu_nmi_and_irq => "cpu_interrupts_v2/rom_singles/3-nmi_and_irq.nes",
+    cpu_irq_and_dma => "cpu_interrupts_v2/rom_singles/4-irq_and_dma.nes",
+    cpu_branch_delays_irq => "cpu_interrupts_v2/rom_singles/5-branch_delays_irq.nes",
+    cpu_reset_registers => "cpu_reset/registers.nes",
+    cpu_reset_ram => "cpu_reset/ram_after_reset.nes",
+
+    ppu_open_bus => "ppu_open_bus/ppu_open_bus.nes",
+    ppu_oam_read => "oam_read/oam_read.nes",
+    ppu_oam_stress => "oam_stress/oam_stress.nes",
+
+    apu_len_ctr => "apu_test/rom_singles/1-len_ctr.nes",
+    apu_len_table => "apu_test/rom_singles/2-len_table.nes",
+    apu_irq_flag => "apu_test/rom_singles/3-irq_flag.nes",
+    apu_jitter => "apu_test/rom_singles/4-jitter.nes",
+    apu_len_timing => "apu_test/rom_singles/5-len_timing.nes",
+    apu_irq_flag_timing => "apu_test/rom_singles/6-irq_flag_timing.nes",
+    apu_dmc_basics => "apu_test/rom_singles/7-dmc_basics.nes",
+    apu_dmc_rates => "apu_test/rom_singles/8-dmc_rates.nes",
+}
+
+// VBlank and NMI timing, the first thing to break when the PPU or the CPU timing changes
+test_roms! {
+    ppu_vbl_basics => "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+    ppu_vbl_set_time => "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes",
+    ppu_vbl_clear_time => "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes",
+    ppu_nmi_control => "ppu_vbl_nmi/rom_singles/04-nmi_control.nes",
+    ppu_nmi_timing => "ppu_vbl_nmi/rom_singles/05-nmi_timing.nes",
+    ppu_suppression => "ppu_vbl_nmi/rom_singles/06-suppression.nes",
+    ppu_nmi_on_timing => "ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes",
+    ppu_nmi_off_timing => "ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes",
+    ppu_even_odd_frames => "ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes",
+    ppu_even_odd_timing => "ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes",
+    ppu_vbl_nmi => "ppu_vbl_nmi/ppu_vbl_nmi.nes",
+}
+
+/// Runs a ROM for some frames, returns the picture as RGB
+fn render(rom: &[u8], frames: u32) -> Result<Vec<u8>, String> {
+    let cartridge = Cartridge::from_ines(rom).map_err(|error| error.to_string())?;
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cartridge);
+    nes.power_cycle();
+    for _ in 1..frames {
+        nes.run_frame();
+    }
+    Ok(nes.run_frame().to_rgb())
+}
+
+/// A ROM of the golden file, with the CRC32 of its picture unless it wasn't recorded yet
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GoldenFrame<'a> {
+    path: &'a str,
+    frames: u32,
+    crc: Option<u32>,
+}
+
+/// Reads the golden file, its lines look like `path frames crc`, `#` starts a comment
+fn golden_frames(golden: &str) -> Result<Vec<GoldenFrame<'_>>, String> {
+    golden
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [path, frames, crc] = fields[..] else {
+                return Err(format!("expected `path frames crc`: {line}"));
+            };
+            let frames = frames
+                .parse()
+                .map_err(|_| format!("bad frame count: {line}"))?;
+            let crc = match crc {
+                "?" => None,
+                crc => Some(u32::from_str_radix(crc, 16).map_err(|_| format!("bad CRC: {line}"))?),
+            };
+            Ok(GoldenFrame { path, frames, crc })
+        })
+        .collect()
+}
+
+/// Save an RGB picture as a binary PPM image
+fn save_ppm(path: &Path, rgb: &[u8]) -> std::io::Result<()> {
+    let mut image = format!("P6\n{} {}\n255\n", Frame::WIDTH, Frame::HEIGHT).into_bytes();
+    image.extend_from_slice(rgb);
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    std::fs::write(path, image)
+}
+
+/// Runs the ROM of a golden frame and compares its picture, returns what's wrong if it doesn't match
+fn check_frame(golden: &GoldenFrame) -> Result<(), String> {
+    let GoldenFrame { path, frames, crc } = *golden;
+    let rgb = render(&read_rom(path), frames).map_err(|error| format!("{path}: {error}"))?;
+    let found = crc32(&rgb);
+    if crc == Some(found) {
+        return Ok(());
+    }
+    let image = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target/golden_frames")
+        .join(path.replace('/', "_"))
+        .with_extension("ppm");
+    save_ppm(&image, &rgb).map_err(|error| format!("{image:?}: {error}"))?;
+    Err(match crc {
+        Some(crc) => {
+            format!("{path} after {frames} frames: expected {crc:08X}, found {found:08X}, see {image:?}")
+        }
+        None => format!("{path} has no CRC yet, found {path} {frames} {found:08X}, see {image:?}"),
+    })
+}
+
+#[test]
+#[ignore = "needs the test ROMs, see the module docs"]
+fn golden_frames_match() {
+    let golden = include_str!("test_roms/golden_frames.txt");
+    let failures: Vec<_> = golden_frames(golden)
+        .unwrap()
+        .iter()
+        .filter_map(|golden| check_frame(golden).err())
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// The protocol itself, with a ROM that writes a result right away
+#[test]
+fn result_protocol() {
+    #[rustfmt::skip]
+    let program = [
+        // LDA #$80, STA $6000
+        0xA9, 0x80, 0x8D, 0x00, 0x60,
+        // signature
+        0xA9, 0xDE, 0x8D, 0x01, 0x60,
+        0xA9, 0xB0, 0x8D, 0x02, 0x60,
+        0xA9, 0x61, 0x8D, 0x03, 0x60,
+        // "ok" and the result
+        0xA9, b'o', 0x8D, 0x04, 0x60,
+        0xA9, b'k', 0x8D, 0x05, 0x60,
+        0xA9, 0x03, 0x8D, 0x00, 0x60,
+        // JMP to itself
+        0x4C, 0x23, 0x80,
+    ];
+    let outcome = run(&nrom_image(&program, 1, 0));
+    assert_eq!(
+        outcome,
+        Ok(Outcome {
+            code: 3,
+            text: "ok".to_string(),
+        })
+    );
+}
+
+#[test]
+fn golden_file() {
+    let golden = "# comment\nsome/rom.nes 60 0000BEEF # note\n\nsome/rom.nes 120 ?\n";
+    assert_eq!(
+        golden_frames(golden),
+        Ok(vec![
+            GoldenFrame {
+                path: "some/rom.nes",
+                frames: 60,
+                crc: Some(0xBEEF),
+            },
+            GoldenFrame {
+                path: "some/rom.nes",
+                frames: 120,
+                crc: None,
+            },
+        ])
+    );
+    assert!(golden_frames("some/rom.nes 60").is_err());
+    assert!(golden_frames("some/rom.nes sixty 0000BEEF").is_err());
+    assert!(golden_frames("some/rom.nes 60 BEEFY").is_err());
+    // the real one has to parse too
+    assert!(golden_frames(include_str!("test_roms/golden_frames.txt")).is_ok());
+}
+
+#[test]
+fn rendered_frames() {
+    // set the backdrop color, with rendering off it fills the picture
+    // LDA #$3F, STA $2006, LDA #$00, STA $2006, LDA #$21, STA $2007
+    // and point the VRAM address away from the palette: LDA #$00, STA $2006, STA $2006
+    // loop: JMP loop
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+        0xA9, 0x21, 0x8D, 0x07, 0x20,
+        0xA9, 0x00, 0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20,
+        0x4C, 0x17, 0x80,
+    ];
+    let image = nrom_image(&program, 1, 0);
+
+    let rgb = render(&image, 3).unwrap();
+    assert_eq!(rgb.len(), Frame::WIDTH * Frame::HEIGHT * 3);
+    let backdrop = crate::ppu::palette::to_rgb(0x21);
+    assert!(rgb.chunks(3).all(|pixel| pixel == backdrop));
+    // the same ROM always renders the same
+    assert_eq!(crc32(&render(&image, 3).unwrap()), crc32(&rgb));
+}
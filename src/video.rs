@@ -0,0 +1,231 @@
+//! Turning the picture into something a screen can show, on another thread
+//!
+//! [`VideoPipeline`] runs a filter, like the conversion to RGBA or an NTSC filter, on a worker thread,
+//! so a slow filter doesn't take time away from the emulation.
+//! The emulation thread hands it every frame with [`VideoPipeline::submit`], which copies the picture
+//! into one of two buffers and returns right away, and the frontend reads the newest filtered frame
+//! with [`VideoPipeline::with_latest`]. When the worker falls behind, the picture waiting for it
+//! is replaced by the newer one instead of making the emulation wait.
+
+use std::{
+    fmt::{Debug, Formatter},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+};
+
+use crate::{nes::Frame, ppu::palette};
+
+#[cfg(test)]
+mod tests;
+
+/// Turns a picture in the format of the framebuffer into the output, which it can resize
+pub type Filter = Box<dyn FnMut(&[u16], &mut Vec<u8>) + Send>;
+
+/// The filter that converts to RGBA with the palette, 4 bytes per pixel
+pub fn rgba_filter() -> Filter {
+    Box::new(|pixels, output| {
+        output.resize(pixels.len() * 4, 0);
+        palette::write_rgba_row(pixels, output);
+    })
+}
+
+#[derive(Debug, Default)]
+struct Output {
+    pixels: Vec<u8>,
+    /// Number of frames filtered so far
+    count: u64,
+}
+
+/// Pictures on their way to the worker
+#[derive(Default)]
+struct Input {
+    /// The newest submitted picture the worker didn't take yet
+    pending: Option<Vec<u16>>,
+    /// Buffer to copy the next picture into, the worker gives it back after filtering it
+    free: Option<Vec<u16>>,
+    /// Whether the worker is running the filter
+    busy: bool,
+    /// Set when the pipeline is shutting down
+    closed: bool,
+    /// Set when the worker stopped, which only happens early if the filter panicked
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    input: Mutex<Input>,
+    /// Signalled whenever `input` changes
+    changed: Condvar,
+    output: Mutex<Output>,
+}
+
+impl Shared {
+    fn input(&self) -> MutexGuard<'_, Input> {
+        self.input.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// Marks the worker as stopped when it returns or the filter panics
+struct Stopped<'a>(&'a Shared);
+
+impl Drop for Stopped<'_> {
+    fn drop(&mut self) {
+        self.0.input().stopped = true;
+        self.0.changed.notify_all();
+    }
+}
+
+pub struct VideoPipeline {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+    dropped: u64,
+}
+
+impl VideoPipeline {
+    /// Start a worker thread running `filter` on the submitted frames
+    pub fn new(mut filter: Filter) -> Self {
+        let shared = Arc::new(Shared {
+            input: Mutex::new(Input {
+                free: Some(vec![0; Frame::WIDTH * Frame::HEIGHT]),
+                ..Input::default()
+            }),
+            ..Shared::default()
+        });
+
+        let worker_shared = Arc::clone(&shared);
+        let worker = std::thread::spawn(move || {
+            let shared = &*worker_shared;
+            let _stopped = Stopped(shared);
+            // filtered into the back buffer, which is swapped with the shared one
+            let mut back = Vec::new();
+            loop {
+                let mut input = shared.input();
+                let pixels = loop {
+                    if input.closed {
+                        return;
+                    }
+                    if let Some(pixels) = input.pending.take() {
+                        break pixels;
+                    }
+                    input = shared
+                        .changed
+                        .wait(input)
+                        .unwrap_or_else(|error| error.into_inner());
+                };
+                input.busy = true;
+                drop(input);
+
+                filter(&pixels, &mut back);
+                let mut output = shared
+                    .output
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner());
+                std::mem::swap(&mut output.pixels, &mut back);
+                output.count += 1;
+                drop(output);
+
+                let mut input = shared.input();
+                input.busy = false;
+                input.free = Some(pixels);
+                drop(input);
+                shared.changed.notify_all();
+            }
+        });
+
+        Self {
+            shared,
+            worker: Some(worker),
+            dropped: 0,
+        }
+    }
+
+    /// Send a picture to the worker
+    ///
+    /// If the worker didn't take the previous picture yet, the new one replaces it,
+    /// so the worker always filters the newest picture and the emulation never waits for it.
+    ///
+    /// # Panics
+    /// If the picture isn't `WIDTH * HEIGHT` pixels, or the filter panicked
+    pub fn submit(&mut self, video: &[u16]) {
+        assert_eq!(video.len(), Frame::WIDTH * Frame::HEIGHT);
+        let mut input = self.shared.input();
+        if input.stopped {
+            panic!("the video filter panicked");
+        }
+        if let Some(pending) = &mut input.pending {
+            pending.copy_from_slice(video);
+            self.dropped += 1;
+            return;
+        }
+        // the worker holds at most one buffer, the other one is free when nothing is pending
+        let mut buffer = input
+            .free
+            .take()
+            .unwrap_or_else(|| vec![0; Frame::WIDTH * Frame::HEIGHT]);
+        buffer.copy_from_slice(video);
+        input.pending = Some(buffer);
+        drop(input);
+        self.shared.changed.notify_all();
+    }
+
+    /// Call `f` with the newest filtered frame and the number of frames filtered so far
+    ///
+    /// The frame is empty until the first one is filtered.
+    /// The worker waits for `f` to return before it can show the next frame.
+    pub fn with_latest<T>(&self, f: impl FnOnce(&[u8], u64) -> T) -> T {
+        let output = self
+            .shared
+            .output
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        f(&output.pixels, output.count)
+    }
+
+    /// Frames that [`VideoPipeline::submit`] replaced before the worker got to them
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Wait until the worker filtered every submitted frame
+    ///
+    /// # Panics
+    /// If the filter panicked
+    pub fn flush(&mut self) {
+        let mut input = self.shared.input();
+        while input.pending.is_some() || input.busy {
+            if input.stopped {
+                panic!("the video filter panicked");
+            }
+            input = self
+                .shared
+                .changed
+                .wait(input)
+                .unwrap_or_else(|error| error.into_inner());
+        }
+    }
+}
+
+impl Default for VideoPipeline {
+    fn default() -> Self {
+        Self::new(rgba_filter())
+    }
+}
+
+impl Debug for VideoPipeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VideoPipeline")
+            .field("pending", &self.shared.input().pending.is_some())
+            .field("dropped", &self.dropped)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for VideoPipeline {
+    fn drop(&mut self) {
+        self.shared.input().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::sync::mpsc;
+
+use crate::{nes::Frame, ppu::palette};
+
+use super::{Filter, VideoPipeline};
+
+fn picture(color: u16) -> Vec<u16> {
+    vec![color; Frame::WIDTH * Frame::HEIGHT]
+}
+
+#[test]
+fn rgba() {
+    let mut pipeline = VideoPipeline::default();
+    pipeline.with_latest(|pixels, count| {
+        assert!(pixels.is_empty());
+        assert_eq!(count, 0);
+    });
+
+    for color in [0x21, 0x16] {
+        pipeline.submit(&picture(color));
+        pipeline.flush();
+    }
+    pipeline.with_latest(|pixels, count| {
+        assert_eq!(count, 2);
+        assert_eq!(pixels.len(), Frame::WIDTH * Frame::HEIGHT * 4);
+        assert!(pixels
+            .chunks_exact(4)
+            .all(|pixel| pixel == palette::to_rgba(0x16)));
+    });
+    assert_eq!(pipeline.dropped(), 0);
+}
+
+#[test]
+fn slow_filter() {
+    // the filter tells the test it started, then waits for it to let it go on
+    let (started, filtering) = mpsc::channel::<()>();
+    let (go, wait) = mpsc::channel::<()>();
+    let filter: Filter = Box::new(move |pixels, output| {
+        let _ = started.send(());
+        let _ = wait.recv();
+        output.clear();
+        output.push(pixels[0] as u8);
+    });
+    let mut pipeline = VideoPipeline::new(filter);
+    pipeline.submit(&picture(1));
+    filtering.recv().unwrap();
+    pipeline.submit(&picture(2));
+    // the worker is busy, the newest picture replaces the one waiting for it
+    pipeline.submit(&picture(3));
+    assert_eq!(pipeline.dropped(), 1);
+
+    go.send(()).unwrap();
+    go.send(()).unwrap();
+    pipeline.flush();
+    pipeline.with_latest(|pixels, count| {
+        assert_eq!(pixels, [3]);
+        assert_eq!(count, 2);
+    });
+    pipeline.submit(&picture(4));
+    go.send(()).unwrap();
+    pipeline.flush();
+    pipeline.with_latest(|pixels, count| {
+        assert_eq!(pixels, [4]);
+        assert_eq!(count, 3);
+    });
+    assert_eq!(pipeline.dropped(), 1);
+}
+
+#[test]
+#[should_panic = "the video filter panicked"]
+fn filter_panic() {
+    let mut pipeline = VideoPipeline::new(Box::new(|_, _| panic!("broken filter")));
+    pipeline.submit(&picture(0));
+    pipeline.flush();
+}
@@ -0,0 +1,358 @@
+//! Reading ZIP archives, for the file formats that are stored in one
+//!
+//! Only what's needed to read files out of an archive: the central directory,
+//! stored and deflated files, and their CRC32. Archives spanning several files,
+//! encryption and ZIP64 aren't supported.
+//!
+//! Details at https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+use alloc::{vec, vec::Vec};
+use core::fmt::{Display, Formatter};
+
+use crate::cartridge::database::crc32;
+
+#[cfg(test)]
+pub(crate) mod tests;
+
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const END_OF_DIRECTORY_SIZE: usize = 22;
+const DIRECTORY_ENTRY: u32 = 0x0201_4B50;
+const DIRECTORY_ENTRY_SIZE: usize = 46;
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const LOCAL_HEADER_SIZE: usize = 30;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZipError {
+    /// The data isn't a ZIP archive, or it's cut short
+    InvalidArchive,
+    /// A file is compressed with something other than deflate
+    UnsupportedCompression(u16),
+    /// A file doesn't decompress, or not to the data it had
+    CorruptFile,
+}
+
+impl Display for ZipError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ZipError::InvalidArchive => write!(f, "invalid ZIP archive"),
+            ZipError::UnsupportedCompression(method) => {
+                write!(f, "unsupported ZIP compression method {method}")
+            }
+            ZipError::CorruptFile => write!(f, "corrupt file in the ZIP archive"),
+        }
+    }
+}
+
+impl core::error::Error for ZipError {}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, ZipError> {
+    let bytes = bytes
+        .get(offset..offset + 2)
+        .ok_or(ZipError::InvalidArchive)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, ZipError> {
+    let bytes = bytes
+        .get(offset..offset + 4)
+        .ok_or(ZipError::InvalidArchive)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// A file in an archive
+#[derive(Debug, Clone, Copy)]
+pub struct ZipFile<'a> {
+    /// Path in the archive, with `/` between directories
+    pub name: &'a [u8],
+    method: u16,
+    crc32: u32,
+    size: usize,
+    compressed: &'a [u8],
+}
+
+impl ZipFile<'_> {
+    /// Decompress the file and check its CRC32
+    pub fn read(&self) -> Result<Vec<u8>, ZipError> {
+        let data = match self.method {
+            STORED => self.compressed.to_vec(),
+            DEFLATED => inflate(self.compressed, self.size)?,
+            method => return Err(ZipError::UnsupportedCompression(method)),
+        };
+        if data.len() != self.size || crc32(&data) != self.crc32 {
+            return Err(ZipError::CorruptFile);
+        }
+        Ok(data)
+    }
+}
+
+/// Whether the data starts like a ZIP archive
+#[cfg(feature = "zip")]
+pub fn is_zip(data: &[u8]) -> bool {
+    u32_at(data, 0) == Ok(LOCAL_HEADER)
+}
+
+/// The files in an archive, in the order of its directory, without directories
+pub fn files(archive: &[u8]) -> Result<Vec<ZipFile<'_>>, ZipError> {
+    // the end of the directory is followed by a comment of up to 64KB
+    let end = (0..archive.len().saturating_sub(END_OF_DIRECTORY_SIZE - 1))
+        .rev()
+        .take(0x10000)
+        .find(|&offset| u32_at(archive, offset) == Ok(END_OF_DIRECTORY))
+        .ok_or(ZipError::InvalidArchive)?;
+    let count = u16_at(archive, end + 10)?;
+    let mut offset = u32_at(archive, end + 16)? as usize;
+
+    let mut files = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        if u32_at(archive, offset)? != DIRECTORY_ENTRY {
+            return Err(ZipError::InvalidArchive);
+        }
+        let name_len = u16_at(archive, offset + 28)? as usize;
+        let extra_len = u16_at(archive, offset + 30)? as usize;
+        let comment_len = u16_at(archive, offset + 32)? as usize;
+        let name_start = offset + DIRECTORY_ENTRY_SIZE;
+        let name = archive
+            .get(name_start..name_start + name_len)
+            .ok_or(ZipError::InvalidArchive)?;
+        let compressed_size = u32_at(archive, offset + 20)? as usize;
+
+        // the local header can have a different extra field than the directory
+        let header = u32_at(archive, offset + 42)? as usize;
+        if u32_at(archive, header)? != LOCAL_HEADER {
+            return Err(ZipError::InvalidArchive);
+        }
+        let data = header
+            + LOCAL_HEADER_SIZE
+            + u16_at(archive, header + 26)? as usize
+            + u16_at(archive, header + 28)? as usize;
+        let compressed = archive
+            .get(data..data + compressed_size)
+            .ok_or(ZipError::InvalidArchive)?;
+
+        if !name.ends_with(b"/") {
+            files.push(ZipFile {
+                name,
+                method: u16_at(archive, offset + 10)?,
+                crc32: u32_at(archive, offset + 16)?,
+                size: u32_at(archive, offset + 24)? as usize,
+                compressed,
+            });
+        }
+        offset = name_start + name_len + extra_len + comment_len;
+    }
+    Ok(files)
+}
+
+/// Bits of a deflate stream, read from the least significant bit of every byte
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Position in bits
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, len: u32) -> Result<u32, ZipError> {
+        let mut value = 0;
+        for bit in 0..len {
+            let byte = self
+                .bytes
+                .get(self.position / 8)
+                .ok_or(ZipError::CorruptFile)?;
+            value |= ((byte >> (self.position % 8)) as u32 & 1) << bit;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// Skip to the start of the next byte and read `len` bytes from there
+    fn bytes(&mut self, len: usize) -> Result<&[u8], ZipError> {
+        let start = self.position.div_ceil(8);
+        let bytes = self
+            .bytes
+            .get(start..start + len)
+            .ok_or(ZipError::CorruptFile)?;
+        self.position = (start + len) * 8;
+        Ok(bytes)
+    }
+}
+
+const MAX_CODE_LEN: usize = 15;
+
+/// A canonical Huffman code, decoded a bit at a time
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; MAX_CODE_LEN + 1],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// The code with the given code length for each symbol, 0 for symbols that aren't used
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; MAX_CODE_LEN + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, input: &mut BitReader) -> Result<u16, ZipError> {
+        // codes of a length are consecutive, and follow the shorter ones shifted left
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= input.bits(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or(ZipError::CorruptFile);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(ZipError::CorruptFile)
+    }
+}
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code lengths of the code length code are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const END_OF_BLOCK: u16 = 256;
+
+/// The codes of a block compressed with the fixed codes
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [8; 288];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Read the codes of a block compressed with its own codes
+fn dynamic_codes(input: &mut BitReader) -> Result<(Huffman, Huffman), ZipError> {
+    let literals = input.bits(5)? as usize + 257;
+    let distances = input.bits(5)? as usize + 1;
+    let code_lengths = input.bits(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[symbol] = input.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+
+    let mut lengths = vec![0; literals + distances];
+    let mut index = 0;
+    while index < lengths.len() {
+        let (len, repeat) = match code_length_code.decode(input)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..index].last().ok_or(ZipError::CorruptFile)?;
+                (previous, 3 + input.bits(2)? as usize)
+            }
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize),
+        };
+        lengths
+            .get_mut(index..index + repeat)
+            .ok_or(ZipError::CorruptFile)?
+            .fill(len);
+        index += repeat;
+    }
+    let (literals, distances) = lengths.split_at(literals);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+/// Decompress a deflate stream, `size` is the expected size of the output
+///
+/// The size comes from the archive, so it's only trusted as a limit, a stream that goes past it
+/// is corrupt.
+pub fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>, ZipError> {
+    let mut input = BitReader {
+        bytes: data,
+        position: 0,
+    };
+    let mut output = Vec::with_capacity(size.min(data.len().saturating_mul(4)));
+    loop {
+        let last = input.bits(1)? == 1;
+        let (literals, distances) = match input.bits(2)? {
+            0 => {
+                let header = input.bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(ZipError::CorruptFile);
+                }
+                output.extend_from_slice(input.bytes(len as usize)?);
+                if output.len() > size {
+                    return Err(ZipError::CorruptFile);
+                }
+                if last {
+                    return Ok(output);
+                }
+                continue;
+            }
+            1 => fixed_codes(),
+            2 => dynamic_codes(&mut input)?,
+            _ => return Err(ZipError::CorruptFile),
+        };
+
+        loop {
+            let symbol = literals.decode(&mut input)?;
+            if symbol < END_OF_BLOCK {
+                if output.len() == size {
+                    return Err(ZipError::CorruptFile);
+                }
+                output.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                break;
+            }
+            let code = symbol as usize - 257;
+            let len = *LENGTH_BASES.get(code).ok_or(ZipError::CorruptFile)? as usize
+                + input.bits(LENGTH_EXTRA_BITS[code] as u32)? as usize;
+            let code = distances.decode(&mut input)? as usize;
+            let distance = *DISTANCE_BASES.get(code).ok_or(ZipError::CorruptFile)? as usize
+                + input.bits(DISTANCE_EXTRA_BITS[code] as u32)? as usize;
+            let start = output
+                .len()
+                .checked_sub(distance)
+                .ok_or(ZipError::CorruptFile)?;
+            if output.len() + len > size {
+                return Err(ZipError::CorruptFile);
+            }
+            // the copy can overlap what it's writing
+            for index in start..start + len {
+                output.push(output[index]);
+            }
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
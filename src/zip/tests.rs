@@ -0,0 +1,119 @@
+use alloc::vec::Vec;
+
+use crate::cartridge::database::crc32;
+
+use super::{files, inflate, ZipError};
+
+/// An archive of stored files, for testing what reads them
+pub(crate) fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for &(name, data) in files {
+        let mut header = Vec::new();
+        // version, flags, method, time and date
+        header.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        header.extend_from_slice(&crc32(data).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // no extra field
+        header.extend_from_slice(&[0, 0]);
+
+        directory.extend_from_slice(&0x0201_4B50u32.to_le_bytes());
+        directory.extend_from_slice(&[20, 0]);
+        directory.extend_from_slice(&header);
+        // no comment, disk, attributes
+        directory.extend_from_slice(&[0; 10]);
+        directory.extend_from_slice(&(archive.len() as u32).to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+
+        archive.extend_from_slice(&0x0403_4B50u32.to_le_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(data);
+    }
+    let offset = archive.len() as u32;
+    archive.extend_from_slice(&0x0605_4B50u32.to_le_bytes());
+    archive.extend_from_slice(&[0; 4]);
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&offset.to_le_bytes());
+    archive.extend_from_slice(&[0, 0]);
+    archive.splice(offset as usize..offset as usize, directory);
+    archive
+}
+
+#[test]
+fn stored_files() {
+    let archive = archive(&[("a.txt", b"hello"), ("dir/b.nes", b"NES\x1A")]);
+    let read = files(&archive).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!(read[0].name, b"a.txt");
+    assert_eq!(read[0].read().unwrap(), b"hello");
+    assert_eq!(read[1].name, b"dir/b.nes");
+    assert_eq!(read[1].read().unwrap(), b"NES\x1A");
+
+    let mut corrupt = archive.clone();
+    let data = corrupt
+        .windows(5)
+        .position(|window| window == b"hello")
+        .unwrap();
+    corrupt[data] = b'j';
+    assert_eq!(
+        files(&corrupt).unwrap()[0].read(),
+        Err(ZipError::CorruptFile)
+    );
+
+    assert_eq!(files(b"not a zip").unwrap_err(), ZipError::InvalidArchive);
+    assert_eq!(
+        files(&archive[..archive.len() - 1]).unwrap_err(),
+        ZipError::InvalidArchive
+    );
+}
+
+/// 1958 bytes of words picked by an LCG, compressed by zlib with its own codes
+const DYNAMIC: &[u8] = &[
+    0x8D, 0x95, 0xC1, 0x0E, 0x83, 0x30, 0x0C, 0x43, 0x7F, 0x85, 0x5F, 0x43, 0x1A, 0x07, 0xA4, 0x31,
+    0x24, 0xC6, 0x65, 0xFD, 0xFA, 0x49, 0xC0, 0x21, 0xC5, 0xCF, 0x09, 0x97, 0x22, 0x5A, 0x12, 0x27,
+    0xAE, 0x63, 0xDA, 0xF4, 0x7E, 0x8D, 0x43, 0x3B, 0xD6, 0x65, 0xDC, 0xE6, 0x75, 0x58, 0xA6, 0x7D,
+    0x5B, 0xE7, 0x97, 0x3C, 0xAF, 0x6F, 0xAE, 0xB7, 0xCF, 0xF4, 0xDD, 0x7F, 0x18, 0x77, 0x9E, 0x9C,
+    0xEB, 0x2D, 0x23, 0xBC, 0xB5, 0x80, 0xDF, 0x23, 0xE4, 0xF8, 0x31, 0x5A, 0x11, 0xB3, 0x7C, 0x5D,
+    0xCD, 0xC7, 0x1A, 0xE3, 0xB1, 0x6E, 0x8A, 0xEC, 0xF6, 0xE2, 0x49, 0x97, 0x07, 0xBE, 0xA2, 0x3E,
+    0x9E, 0x30, 0x4F, 0xFC, 0xC7, 0x68, 0xC5, 0x88, 0xEC, 0xE8, 0x8E, 0xC6, 0x52, 0x9F, 0xD4, 0x4D,
+    0x5F, 0x7B, 0xCC, 0xA6, 0x99, 0x6F, 0x91, 0x80, 0xA6, 0xF7, 0x46, 0x75, 0x54, 0x27, 0x65, 0x4E,
+    0xD1, 0x8B, 0x72, 0xA1, 0x4C, 0x79, 0x8E, 0xBC, 0x9A, 0x94, 0x69, 0xC5, 0xE8, 0x59, 0x49, 0x66,
+    0xA5, 0xE0, 0xCB, 0x4C, 0x16, 0xDE, 0x93, 0xBB, 0xA1, 0x54, 0x81, 0x96, 0x61, 0xD7, 0x65, 0xAB,
+    0xFD, 0xA4, 0xD4, 0x95, 0xE3, 0x2E, 0xD5, 0x5F, 0xE2, 0x12, 0xD0, 0x33, 0xE9, 0xD1, 0x66, 0xF3,
+    0x2E, 0xF7, 0x6C, 0x1A, 0x7D, 0x5F, 0xAC, 0x29, 0xF5, 0x56, 0x60, 0x04, 0xEB, 0xA0, 0xBE, 0xAB,
+    0x1A, 0x50, 0xDD, 0xC8, 0x37, 0xF8, 0x64, 0x52, 0x39, 0xAA, 0xD6, 0xAA, 0xBB, 0xF4, 0x38, 0xAF,
+    0x48, 0xEB, 0x33, 0x95, 0x53, 0x13, 0x9A, 0x73, 0x0E, 0x66, 0xC6, 0xFF, 0x05, 0xFD, 0x9C, 0xE8,
+    0xD4, 0xD5, 0xCE, 0xF2, 0x07,
+];
+
+#[test]
+fn inflate_blocks() {
+    let words: [&[u8]; 4] = [b"nesty ", b"mario ", b"zelda ", b"metroid "];
+    let mut x: u32 = 1;
+    let mut expected = Vec::new();
+    for _ in 0..300 {
+        x = (x.wrapping_mul(1_103_515_245).wrapping_add(12345)) & 0x7FFF_FFFF;
+        expected.extend_from_slice(words[(x >> 16) as usize % 4]);
+    }
+    assert_eq!(inflate(DYNAMIC, expected.len()).unwrap(), expected);
+    // more output than the archive says is an error, not an allocation
+    assert_eq!(
+        inflate(DYNAMIC, expected.len() - 1),
+        Err(ZipError::CorruptFile)
+    );
+
+    // the fixed codes, with a copy overlapping its output
+    let fixed = [0x4B, 0x4C, 0x4A, 0x4E, 0x44, 0x45, 0x00];
+    assert_eq!(inflate(&fixed, 18).unwrap(), b"abcabcabcabcabcabc");
+
+    // a stored block that isn't the last one, then an empty fixed block
+    let stored = [0x00, 0x03, 0x00, 0xFC, 0xFF, b'N', b'E', b'S', 0x03, 0x00];
+    assert_eq!(inflate(&stored, 3).unwrap(), b"NES");
+
+    assert_eq!(inflate(&DYNAMIC[..100], 0), Err(ZipError::CorruptFile));
+}
@@ -0,0 +1,152 @@
+//! The console for browser frontends, through [wasm-bindgen](https://rustwasm.github.io/docs/wasm-bindgen/)
+//!
+//! Build with `wasm-pack build --target web` in this directory, which gives a `pkg` directory
+//! with the module and its JavaScript glue. A frontend runs a frame on every animation frame:
+//!
+//! ```js
+//! import init, { Emulator } from "./pkg/nesty_wasm.js";
+//!
+//! await init();
+//! const emulator = new Emulator(audioContext.sampleRate);
+//! emulator.load_rom(new Uint8Array(await file.arrayBuffer()));
+//! function frame() {
+//!     emulator.set_buttons(0, buttons);
+//!     emulator.run_frame();
+//!     context.putImageData(new ImageData(emulator.framebuffer(), 256, 240), 0, 0);
+//!     queueAudio(emulator.audio());
+//!     requestAnimationFrame(frame);
+//! }
+//! ```
+//!
+//! Everything runs on the calling thread and nothing touches the filesystem,
+//! ROMs and save states are passed around as bytes.
+
+use nesty::{
+    cartridge::{Cartridge, LoadError},
+    input::ButtonState,
+    nes::{Frame, Nes},
+    ppu::palette,
+};
+use wasm_bindgen::{prelude::*, Clamped};
+
+/// Width of the picture in pixels
+#[wasm_bindgen]
+pub fn width() -> usize {
+    Frame::WIDTH
+}
+
+/// Height of the picture in pixels
+#[wasm_bindgen]
+pub fn height() -> usize {
+    Frame::HEIGHT
+}
+
+#[wasm_bindgen]
+pub struct Emulator {
+    nes: Nes,
+    /// The picture of the last frame in RGBA
+    rgba: Vec<u8>,
+    /// The samples of the last frame, -1.0-1.0 like Web Audio wants them
+    audio: Vec<f32>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// A console without a cartridge, making audio at `sample_rate`
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: u32) -> Emulator {
+        let mut nes = Nes::new();
+        nes.set_sample_rate(sample_rate);
+        Emulator {
+            nes,
+            rgba: vec![0; Frame::WIDTH * Frame::HEIGHT * 4],
+            audio: Vec::new(),
+        }
+    }
+
+    /// Insert an iNES, UNIF or NSF file and power the console on
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        let cartridge = [
+            Cartridge::from_ines,
+            Cartridge::from_unif,
+            Cartridge::from_nsf,
+        ]
+        .into_iter()
+        .map(|load| load(rom))
+        .find(|cartridge| !matches!(cartridge, Err(LoadError::InvalidHeader)))
+        .unwrap_or(Err(LoadError::InvalidHeader))?;
+        self.nes.insert_cartridge(cartridge);
+        self.nes.power_cycle();
+        Ok(())
+    }
+
+    pub fn unload_rom(&mut self) {
+        self.nes.remove_cartridge();
+    }
+
+    /// Run until the end of the frame, then the picture and audio are ready
+    pub fn run_frame(&mut self) {
+        let frame = self.nes.run_frame();
+        palette::write_rgba_row(frame.video, &mut self.rgba);
+        self.audio.clear();
+        self.audio
+            .extend(frame.audio.iter().map(|&sample| sample * 2.0 - 1.0));
+    }
+
+    /// The picture of the last frame in RGBA, ready for an `ImageData`
+    pub fn framebuffer(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.rgba.clone())
+    }
+
+    /// Where the picture is in the module's memory, to read it without a copy
+    ///
+    /// The pointer changes when the memory grows, so it has to be used right away.
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.rgba.as_ptr()
+    }
+
+    /// The mono samples of the last frame
+    pub fn audio(&self) -> Vec<f32> {
+        self.audio.clone()
+    }
+
+    /// Set the buttons a player holds, in the bits of [`ButtonState`]:
+    /// A, B, Select, Start, Up, Down, Left and Right from the lowest
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        self.nes
+            .set_player_buttons(player, ButtonState::from_bits_truncate(buttons));
+    }
+
+    pub fn reset(&mut self) {
+        self.nes.reset();
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.nes.power_cycle();
+    }
+
+    pub fn save_state(&self) -> Result<Vec<u8>, JsError> {
+        Ok(self.nes.save_state()?)
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsError> {
+        Ok(self.nes.load_state(state)?)
+    }
+
+    /// The battery backed RAM of the cartridge, empty if it has none
+    pub fn save_ram(&self) -> Vec<u8> {
+        match self.nes.cartridge() {
+            Some(cartridge) if cartridge.board().battery => cartridge.prg_ram().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Restore the battery backed RAM, what doesn't fit is ignored
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        if let Some(cartridge) = self.nes.cartridge_mut() {
+            let prg_ram = cartridge.prg_ram_mut();
+            let len = prg_ram.len().min(data.len());
+            prg_ram[..len].copy_from_slice(&data[..len]);
+        }
+    }
+}